@@ -1,45 +1,158 @@
 //! Wallet Manager implementation with BDK
-use std::cell::RefCell;
-use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use bdk::bitcoin::bip32::ExtendedPrivKey;
 use bdk::bitcoin::consensus::serialize;
-use bdk::bitcoin::{Amount, ScriptBuf};
+use bdk::bitcoin::hashes::{sha256, Hash};
+use bdk::bitcoin::psbt::PartiallySignedTransaction as BdkPsbt;
+use bdk::bitcoin::secp256k1::Secp256k1 as BdkSecp256k1;
+use bdk::bitcoin::{
+    Block as BdkBlock, OutPoint as BdkOutPoint, ScriptBuf, Transaction as BdkTransaction,
+};
+use bdk::descriptor::IntoWalletDescriptor;
 use bdk::keys::bip39::{Language, Mnemonic, WordCount};
 use bdk::keys::GeneratableKey;
 use bdk::keys::{DerivableKey, ExtendedKey, GeneratedKey};
+use bdk::miniscript::Descriptor;
 use bdk::template::Bip84;
 use bdk::wallet::{ChangeSet, Update};
 use bdk::{FeeRate, KeychainKind, SignOptions, Wallet};
+use bdk_chain::{BlockId, ChainPosition, ConfirmationTime};
 use bdk_esplora::EsploraExt;
 use bdk_file_store::Store;
+use serde::{Deserialize, Serialize};
 
+use lampo_common::bitcoin;
+use lampo_common::bitcoin::bip32::Fingerprint;
 use lampo_common::bitcoin::consensus::deserialize;
 use lampo_common::bitcoin::hashes::hex::ToHex;
+use lampo_common::bitcoin::psbt::PartiallySignedTransaction;
 use lampo_common::bitcoin::{PrivateKey, Script, Transaction};
-use lampo_common::conf::{LampoConf, Network};
+use lampo_common::conf::{public_esplora_url, LampoConf, Network, DEFAULT_SCAN_CONCURRENCY};
 use lampo_common::error;
-use lampo_common::keys::LampoKeys;
-use lampo_common::model::response::{NewAddress, Utxo};
-use lampo_common::wallet::WalletManager;
+use lampo_common::json;
+use lampo_common::keys::{KeysScheme, LampoKeys};
+use lampo_common::ldk::events::bump_transaction;
+use lampo_common::model::response::{NewAddress, StoreSize, TxDetail, Utxo};
+use lampo_common::model::Sat;
+use lampo_common::sync::RwLockExt;
+use lampo_common::wallet::{
+    check_fee_rate, min_fee_rate, sat_per_vb_to_sat_per_kw, sort_history, SyncReport, WalletError,
+    WalletManager, DEFAULT_MIN_FEE_RATE_SAT_VB, P2WPKH_SATISFACTION_WEIGHT,
+};
 
+/// Wallet manager backed by a bdk wallet.
+///
+/// Durability: every operation that changes the wallet (the derivation
+/// of an address, the change reserved by a new transaction, or a sync)
+/// commits its changes to the store before it returns, while holding
+/// the write lock, so a derived index is never lost on an unclean
+/// shutdown. Inside a `WalletBatch` the commits are deferred until the
+/// batch ends, so the changes made by the batch can be lost if the
+/// node crashes before.
 pub struct BDKWalletManager {
-    pub wallet: RefCell<Mutex<Wallet<Store<'static, ChangeSet>>>>,
-    pub keymanager: Arc<LampoKeys>,
+    /// The read only operations take a read lock, so they can
+    /// run in parallel, while the one that derive new addresses
+    /// or apply a chain update take the write lock.
+    wallet: Arc<RwLock<Wallet<Store<'static, ChangeSet>>>>,
+    keymanager: Arc<LampoKeys>,
     pub network: Network,
+    /// Number of parallel requests made to esplora during the scan.
+    pub scan_concurrency: usize,
+    /// The esplora apis used by the sync, in order of preference,
+    /// they can be replaced at runtime by `reload`.
+    pub esplora_urls: Arc<RwLock<Vec<String>>>,
+    /// The esplora used when `esplora_urls` is empty, a custom signet
+    /// has none.
+    default_esplora_url: Option<&'static str>,
+    /// Minimum fee rate in sat/kW of the transactions built.
+    pub min_fee_rate: u32,
+    /// The path of the file where the wallet state is stored.
+    pub store_path: PathBuf,
+    /// The SOCKS5 proxy used to reach esplora.
+    pub proxy: Option<SocketAddr>,
+    /// The BIP 84 account of the wallet.
+    pub account_index: u32,
+    /// The wallet is synced with the blocks matched by the compact
+    /// block filters of the backend, so there is no esplora to sync.
+    pub block_sync: bool,
+    /// Number of `WalletBatch` open, the changes are committed when
+    /// the last one ends.
+    batches: Arc<AtomicUsize>,
+    /// The height of the chain seen by the last sync, `0` when it is
+    /// not known yet.
+    tip_height: Arc<AtomicU32>,
 }
 
-// SAFETY: It is safe to do because the `LampoWalletManager`
-// is not send and sync due the RefCell, but we use the Mutex
-// inside, so we are safe to share across threads.
-unsafe impl Send for BDKWalletManager {}
-unsafe impl Sync for BDKWalletManager {}
+/// Defer the commits of the wallet until it is dropped, so a bulk
+/// operation writes the store only once.
+pub struct WalletBatch<'a> {
+    manager: &'a BDKWalletManager,
+}
+
+impl Drop for WalletBatch<'_> {
+    fn drop(&mut self) {
+        if self.manager.batches.fetch_sub(1, Ordering::SeqCst) == 1 {
+            if let Err(err) = self.manager.flush() {
+                log::error!(target: "wallet", "impossible commit the wallet batch: {err}");
+            }
+        }
+    }
+}
+
+/// The magic bytes at the beginning of the wallet store.
+const STORE_MAGIC: &[u8] = b"lampo";
+/// The version of the backup format.
+const BACKUP_VERSION: u32 = 1;
+
+/// The state of the wallet exported by `BDKWalletManager::export_backup`.
+///
+/// The backup contains only the on chain state (the chain checkpoints,
+/// the transactions and the derived addresses), the keys still come
+/// from the seed.
+#[derive(Serialize, Deserialize)]
+struct WalletBackup {
+    version: u32,
+    network: String,
+    changeset: ChangeSet,
+}
+
+/// The first hardened index, the account index must be below it.
+const HARDENED_INDEX: u32 = 1 << 31;
+
+/// Number of unused addresses after that the scan stops.
+const SCAN_STOP_GAP: usize = 50;
+
+/// Seconds after that a request to esplora fails, so the sync
+/// moves to the next esplora.
+const ESPLORA_TIMEOUT_SECS: u64 = 30;
+
+/// The tag of the hash of the descriptor keys used as ldk seed.
+const DESCRIPTOR_SEED_TAG: &[u8] = b"lampo/descriptor/ldk-seed";
+
+fn bdk_network(network: Network) -> bdk::bitcoin::Network {
+    match network.to_string().as_str() {
+        "bitcoin" => bdk::bitcoin::Network::Bitcoin,
+        "testnet" => bdk::bitcoin::Network::Testnet,
+        "signet" => bdk::bitcoin::Network::Signet,
+        "regtest" => bdk::bitcoin::Network::Regtest,
+        _ => unreachable!(),
+    }
+}
 
 impl BDKWalletManager {
     /// from mnemonic_words build or bkd::Wallet or return an bdk::Error
+    ///
+    /// The wallet uses the BIP 84 `account_index`, so the funds of
+    /// each account are derived from `m/84h/<coin>h/<account>h`.
     fn build_wallet(
         conf: Arc<LampoConf>,
         mnemonic_words: &str,
+        account_index: u32,
     ) -> Result<(Wallet<Store<'static, ChangeSet>>, LampoKeys), bdk::Error> {
         // Parse a mnemonic
         let mnemonic =
@@ -58,16 +171,23 @@ impl BDKWalletManager {
             "wrong convertion to a private key".to_string(),
         ))?;
 
-        let db = Store::<ChangeSet>::new_from_path(
-            "lampo".as_bytes(),
-            format!("{}/onchain", conf.path()),
-        )
-        .map_err(|err| bdk::Error::Generic(format!("{err}")))?;
-        let ldk_kesy = LampoKeys::new(xprv.private_key.secret_bytes());
-        // Create a BDK wallet structure using BIP 84 descriptor ("m/84h/1h/0h/0" and "m/84h/1h/0h/1")
+        let store_path = Self::conf_store_path(&conf, account_index);
+        let db = Store::<ChangeSet>::new_from_path(STORE_MAGIC, store_path)
+            .map_err(|err| bdk::Error::Generic(format!("{err}")))?;
+        let scheme = KeysScheme::load_or_init(&conf.path(), conf.keys_scheme)
+            .map_err(|err| bdk::Error::Generic(format!("{err}")))?;
+        let ldk_kesy = LampoKeys::from_master(&xprv.encode(), scheme)
+            .map_err(|err| bdk::Error::Generic(format!("{err}")))?;
+        // Create a BDK wallet structure using BIP 84 descriptor ("m/84h/1h/<account>h/0"
+        // and "m/84h/1h/<account>h/1"), that is the same of the `Bip84` template for the
+        // account 0.
+        let coin = match network {
+            bdk::bitcoin::Network::Bitcoin => 0,
+            _ => 1,
+        };
         let wallet = Wallet::new(
-            Bip84(xprv, KeychainKind::External),
-            Some(Bip84(xprv, KeychainKind::Internal)),
+            &format!("wpkh({xprv}/84'/{coin}'/{account_index}'/0/*)"),
+            Some(&format!("wpkh({xprv}/84'/{coin}'/{account_index}'/1/*)")),
             db,
             network,
         )
@@ -77,19 +197,100 @@ impl BDKWalletManager {
         Ok((wallet, ldk_kesy))
     }
 
-    #[cfg(debug_assertions)]
+    /// The seed of the ldk keys of a wallet built from a descriptor.
+    ///
+    /// A descriptor has no single seed, so the seed is the tagged hash
+    /// of all the private keys inside the `external` descriptor. The
+    /// same descriptor restores the same node id and channel keys,
+    /// while changing its keys gives a new node.
+    fn descriptor_ldk_seed(external: &str, network: Network) -> error::Result<[u8; 32]> {
+        let secp = BdkSecp256k1::new();
+        let (_, keymap) = external
+            .into_wallet_descriptor(&secp, bdk_network(network))
+            .map_err(|err| error::anyhow!("invalid descriptor: {err}"))?;
+        if keymap.is_empty() {
+            error::bail!(
+                "the descriptor has no private keys, they are needed to sign and to derive the node keys"
+            );
+        }
+        let mut secrets = keymap
+            .values()
+            .map(|secret| secret.to_string())
+            .collect::<Vec<_>>();
+        secrets.sort();
+        let mut engine = DESCRIPTOR_SEED_TAG.to_vec();
+        for secret in secrets {
+            engine.extend_from_slice(secret.as_bytes());
+        }
+        Ok(sha256::Hash::hash(&engine).to_byte_array())
+    }
+
+    /// The fingerprints of the master keys of the `external` descriptor.
+    fn descriptor_fingerprints(
+        external: &str,
+        network: Network,
+    ) -> error::Result<Vec<Fingerprint>> {
+        let secp = BdkSecp256k1::new();
+        let (_, keymap) = external
+            .into_wallet_descriptor(&secp, bdk_network(network))
+            .map_err(|err| error::anyhow!("invalid descriptor: {err}"))?;
+        let mut fingerprints = keymap
+            .keys()
+            .map(|key| Fingerprint::from(key.master_fingerprint().to_bytes()))
+            .collect::<Vec<_>>();
+        fingerprints.sort();
+        fingerprints.dedup();
+        Ok(fingerprints)
+    }
+
+    /// The path of the wallet store inside the lampo directory, each
+    /// account has its own store.
+    fn conf_store_path(conf: &LampoConf, account_index: u32) -> PathBuf {
+        match account_index {
+            0 => PathBuf::from(format!("{}/onchain", conf.path())),
+            account => PathBuf::from(format!("{}/onchain-account-{account}", conf.path())),
+        }
+    }
+
+    /// Return a new directory inside the temporary directory of the
+    /// system, so each wallet built from a private key has its own
+    /// store and the wallets do not collide when used in parallel.
+    #[cfg(any(debug_assertions, test, feature = "test-utils"))]
+    fn temp_store_path() -> PathBuf {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos())
+            .unwrap_or_default();
+        std::env::temp_dir()
+            .join(format!(
+                "lampo-wallet-{}-{}-{nanos}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::SeqCst)
+            ))
+            .join("onchain")
+    }
+
+    #[cfg(any(debug_assertions, test, feature = "test-utils"))]
     fn build_from_private_key(
         xprv: PrivateKey,
         channel_keys: Option<String>,
+        db_path: &Path,
     ) -> Result<(Wallet<Store<'static, ChangeSet>>, LampoKeys), bdk::Error> {
-        let ldk_keys = if channel_keys.is_some() {
-            LampoKeys::with_channel_keys(xprv.inner.secret_bytes(), channel_keys.unwrap())
-        } else {
-            LampoKeys::new(xprv.inner.secret_bytes())
+        let ldk_keys = match channel_keys {
+            Some(channel_keys) => {
+                LampoKeys::with_channel_keys(xprv.inner.secret_bytes(), channel_keys)
+                    .map_err(|err| bdk::Error::Generic(format!("{err}")))?
+            }
+            None => LampoKeys::new(xprv.inner.secret_bytes()),
         };
 
-        // FIXME: Get a tmp path
-        let db = Store::new_from_path("lampo".as_bytes(), "/tmp/onchain")
+        if let Some(parent) = db_path.parent() {
+            std::fs::create_dir_all(parent).map_err(|err| bdk::Error::Generic(format!("{err}")))?;
+        }
+        let db = Store::new_from_path(STORE_MAGIC, db_path)
             .map_err(|err| bdk::Error::Generic(format!("{err}")))?;
         let network = match xprv.network.to_string().as_str() {
             "bitcoin" => bdk::bitcoin::Network::Bitcoin,
@@ -106,6 +307,486 @@ impl BDKWalletManager {
     }
 }
 
+impl BDKWalletManager {
+    /// Build a deterministic wallet with its own temporary store,
+    /// so every test gets an isolated wallet.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn new_in_memory(network: Network) -> error::Result<Self> {
+        Self::in_memory_at(network, Self::temp_store_path())
+    }
+
+    /// Build a deterministic wallet like `new_in_memory`, with the
+    /// state imported from the `backup`.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn from_backup_in_memory(network: Network, backup: &[u8]) -> error::Result<Self> {
+        let store_path = Self::temp_store_path();
+        Self::write_backup(&store_path, network, backup)?;
+        Self::in_memory_at(network, store_path)
+    }
+
+    #[cfg(any(test, feature = "test-utils"))]
+    fn in_memory_at(network: Network, store_path: PathBuf) -> error::Result<Self> {
+        use lampo_common::secp256k1::SecretKey;
+
+        // SAFETY: the key is a valid secret key.
+        let key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let (wallet, keymanager) = BDKWalletManager::build_from_private_key(
+            PrivateKey::new(key, network),
+            None,
+            &store_path,
+        )?;
+        Ok(Self {
+            wallet: Arc::new(RwLock::new(wallet)),
+            keymanager: Arc::new(keymanager),
+            network,
+            scan_concurrency: DEFAULT_SCAN_CONCURRENCY,
+            esplora_urls: Arc::new(RwLock::new(Vec::new())),
+            default_esplora_url: public_esplora_url(network),
+            min_fee_rate: sat_per_vb_to_sat_per_kw(DEFAULT_MIN_FEE_RATE_SAT_VB),
+            store_path,
+            proxy: None,
+            account_index: 0,
+            block_sync: false,
+            batches: Arc::new(AtomicUsize::new(0)),
+            tip_height: Arc::new(AtomicU32::new(0)),
+        })
+    }
+
+    /// Restore the wallet of another BIP 84 account of the seed, the
+    /// account has its own store, so its addresses and its balance
+    /// are independent from the other accounts.
+    pub fn restore_account(
+        conf: Arc<LampoConf>,
+        mnemonic_words: &str,
+        account_index: u32,
+    ) -> error::Result<Self> {
+        if account_index >= HARDENED_INDEX {
+            error::bail!("invalid account index `{account_index}`");
+        }
+        let (wallet, keymanager) =
+            BDKWalletManager::build_wallet(conf.clone(), mnemonic_words, account_index)?;
+        Ok(Self {
+            wallet: Arc::new(RwLock::new(wallet)),
+            keymanager: Arc::new(keymanager),
+            network: conf.network,
+            scan_concurrency: conf.scan_concurrency,
+            esplora_urls: Arc::new(RwLock::new(conf.esplora_urls.clone())),
+            default_esplora_url: conf.default_esplora_url(),
+            min_fee_rate: min_fee_rate(&conf),
+            store_path: Self::conf_store_path(&conf, account_index),
+            proxy: conf.proxy,
+            account_index,
+            block_sync: conf.node == "bip157",
+            batches: Arc::new(AtomicUsize::new(0)),
+            tip_height: Arc::new(AtomicU32::new(0)),
+        })
+    }
+
+    /// Build the wallet from the `external` and `internal` (change)
+    /// descriptors, with their private keys, so any policy supported
+    /// by miniscript (e.g. multisig or timelocks) can back the on
+    /// chain wallet.
+    ///
+    /// The wallet has its own store, and the ldk keys are derived
+    /// from the keys of the `external` descriptor, see
+    /// `descriptor_ldk_seed`. The change goes to `external` when
+    /// `internal` is not given.
+    pub fn from_descriptor(
+        conf: Arc<LampoConf>,
+        external: &str,
+        internal: Option<&str>,
+    ) -> error::Result<Self> {
+        let seed = Self::descriptor_ldk_seed(external, conf.network)?;
+        let fingerprints = Self::descriptor_fingerprints(external, conf.network)?;
+        let store_path = PathBuf::from(format!("{}/onchain-descriptor", conf.path()));
+        if let Some(parent) = store_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let db = Store::<ChangeSet>::new_from_path(STORE_MAGIC, &store_path)
+            .map_err(|err| error::anyhow!("impossible open the wallet store: {err}"))?;
+        let wallet = Wallet::new(external, internal, db, bdk_network(conf.network))
+            .map_err(|err| error::anyhow!("impossible build the wallet: {err}"))?;
+        Ok(Self {
+            wallet: Arc::new(RwLock::new(wallet)),
+            keymanager: Arc::new(LampoKeys::new(seed).with_fingerprints(fingerprints)),
+            network: conf.network,
+            scan_concurrency: conf.scan_concurrency,
+            esplora_urls: Arc::new(RwLock::new(conf.esplora_urls.clone())),
+            default_esplora_url: conf.default_esplora_url(),
+            min_fee_rate: min_fee_rate(&conf),
+            store_path,
+            proxy: conf.proxy,
+            account_index: 0,
+            block_sync: conf.node == "bip157",
+            batches: Arc::new(AtomicUsize::new(0)),
+            tip_height: Arc::new(AtomicU32::new(0)),
+        })
+    }
+
+    /// Export the on chain state of the wallet, so a new instance of
+    /// the node can import it without scanning the chain again.
+    pub fn export_backup(&self) -> error::Result<Vec<u8>> {
+        // hold the write lock, so nothing is written in the store
+        // while we read it.
+        let mut wallet = self.write_wallet();
+        wallet.commit()?;
+        let changeset = Self::read_changeset(&self.store_path)?;
+        let backup = WalletBackup {
+            version: BACKUP_VERSION,
+            network: self.network.to_string(),
+            changeset,
+        };
+        Ok(json::to_vec(&backup)?)
+    }
+
+    /// Restore the wallet from the seed and from a backup made with
+    /// `export_backup`.
+    ///
+    /// The backup is imported in a fresh store, so it fails if the
+    /// wallet of the node already exists.
+    pub fn import_backup(
+        conf: Arc<LampoConf>,
+        mnemonic_words: &str,
+        backup: &[u8],
+    ) -> error::Result<Self> {
+        let store_path = Self::conf_store_path(&conf, conf.account_index);
+        Self::write_backup(&store_path, conf.network, backup)?;
+        Self::restore(conf, mnemonic_words)
+    }
+
+    /// Write the state of the `backup` inside a new store at `path`.
+    fn write_backup(path: &Path, network: Network, backup: &[u8]) -> error::Result<()> {
+        let backup: WalletBackup = json::from_slice(backup)
+            .map_err(|err| error::anyhow!("invalid wallet backup: {err}"))?;
+        if backup.version != BACKUP_VERSION {
+            error::bail!("wallet backup version `{}` not supported", backup.version);
+        }
+        if backup.network != network.to_string() {
+            error::bail!(
+                "the wallet backup is for `{}`, but the node is running on `{network}`",
+                backup.network
+            );
+        }
+        if path.exists() {
+            error::bail!(
+                "the wallet store `{}` already exists, the backup must be imported in a new node",
+                path.display()
+            );
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut store = Store::<ChangeSet>::new_from_path(STORE_MAGIC, path)
+            .map_err(|err| error::anyhow!("impossible create the wallet store: {err}"))?;
+        store
+            .append_changeset(&backup.changeset)
+            .map_err(|err| error::anyhow!("impossible write the wallet store: {err}"))?;
+        log::info!(target: "wallet", "wallet backup imported in `{}`", path.display());
+        Ok(())
+    }
+
+    /// The state of the wallet inside the store at `path`, that is
+    /// the sum of all the changesets appended to it.
+    fn read_changeset(path: &Path) -> error::Result<ChangeSet> {
+        let mut store = Store::<ChangeSet>::new_from_path(STORE_MAGIC, path)
+            .map_err(|err| error::anyhow!("impossible open the wallet store: {err}"))?;
+        let changeset = store
+            .aggregate_changesets()
+            .map_err(|err| error::anyhow!("impossible read the wallet store: {err}"))?
+            .unwrap_or_default();
+        Ok(changeset)
+    }
+
+    /// Rewrite the store with the sum of its changesets, so the changes
+    /// appended over time (e.g. an index for every address) take only
+    /// the space of the state they give.
+    ///
+    /// The write lock is held while the store is rewritten, that takes
+    /// the time of writing the state once, and the wallet is loaded
+    /// from the new store before it replaces the old one.
+    pub fn compact_store(&self) -> error::Result<StoreSize> {
+        if self.batches.load(Ordering::SeqCst) > 0 {
+            error::bail!("a batch of changes of the wallet is in progress, try again later");
+        }
+        let mut wallet = self.write_wallet();
+        wallet.commit()?;
+        let bytes_before = std::fs::metadata(&self.store_path)?.len();
+        let changeset = Self::read_changeset(&self.store_path)?;
+
+        let compact_path = self.store_path.with_extension("compact");
+        // the leftover of a compaction that did not end
+        let _ = std::fs::remove_file(&compact_path);
+        let mut store = Store::<ChangeSet>::new_from_path(STORE_MAGIC, &compact_path)
+            .map_err(|err| error::anyhow!("impossible create the compacted store: {err}"))?;
+        store
+            .append_changeset(&changeset)
+            .map_err(|err| error::anyhow!("impossible write the compacted store: {err}"))?;
+        drop(store);
+        std::fs::File::open(&compact_path)?.sync_all()?;
+        // the old store is kept if the new one has not the same state
+        if json::to_value(Self::read_changeset(&compact_path)?)? != json::to_value(&changeset)? {
+            let _ = std::fs::remove_file(&compact_path);
+            error::bail!("the compacted store has not the state of the wallet");
+        }
+
+        let compacted = Self::reload(&wallet, &compact_path)?;
+        std::fs::rename(&compact_path, &self.store_path)?;
+        *wallet = compacted;
+        let bytes_after = std::fs::metadata(&self.store_path)?.len();
+        log::info!(target: "wallet", "wallet store compacted from {bytes_before} to {bytes_after} bytes");
+        Ok(StoreSize {
+            name: "wallet".to_owned(),
+            path: self.store_path.display().to_string(),
+            bytes_before,
+            bytes_after,
+        })
+    }
+
+    /// Build a wallet with the descriptors (and the keys) of `wallet`,
+    /// with the state of the store at `path`.
+    fn reload(
+        wallet: &Wallet<Store<'static, ChangeSet>>,
+        path: &Path,
+    ) -> error::Result<Wallet<Store<'static, ChangeSet>>> {
+        let secp = BdkSecp256k1::new();
+        let descriptor = |keychain| {
+            let keymap = wallet.get_signers(keychain).as_key_map(&secp);
+            wallet
+                .public_descriptor(keychain)
+                .map(|descriptor| descriptor.to_string_with_secret(&keymap))
+        };
+        let Some(external) = descriptor(KeychainKind::External) else {
+            error::bail!("the wallet has no descriptor");
+        };
+        let internal = descriptor(KeychainKind::Internal);
+        let db = Store::<ChangeSet>::new_from_path(STORE_MAGIC, path)
+            .map_err(|err| error::anyhow!("impossible open the wallet store: {err}"))?;
+        Wallet::new(external.as_str(), internal.as_deref(), db, wallet.network())
+            .map_err(|err| error::anyhow!("impossible load the compacted wallet: {err}"))
+    }
+
+    /// Take the read lock of the wallet.
+    ///
+    /// If a thread panicked while holding the lock, the lock is
+    /// poisoned but the wallet is still consistent, because bdk
+    /// stages the changes and they are committed only at the end
+    /// of an operation, so we recover the guard instead of taking
+    /// down the whole node.
+    fn read_wallet(&self) -> RwLockReadGuard<'_, Wallet<Store<'static, ChangeSet>>> {
+        self.wallet.read_or_recover()
+    }
+
+    /// Take the write lock of the wallet, see `read_wallet` for the
+    /// handling of a poisoned lock.
+    fn write_wallet(&self) -> RwLockWriteGuard<'_, Wallet<Store<'static, ChangeSet>>> {
+        self.wallet.write_or_recover()
+    }
+
+    /// The esplora apis used by the sync, the public instance of the
+    /// network when none is configured.
+    fn esplora_urls(&self) -> error::Result<Vec<String>> {
+        let urls = self.esplora_urls.read_or_recover().clone();
+        if !urls.is_empty() {
+            return Ok(urls);
+        }
+        let Some(url) = self.default_esplora_url else {
+            error::bail!(
+                "there is no public esplora for the network `{}`, set an `esplora-url`",
+                self.network
+            );
+        };
+        Ok(vec![url.to_owned()])
+    }
+
+    fn esplora_client(
+        url: &str,
+        proxy: Option<SocketAddr>,
+    ) -> error::Result<bdk_esplora::esplora_client::BlockingClient> {
+        let mut builder =
+            bdk_esplora::esplora_client::Builder::new(url).timeout(ESPLORA_TIMEOUT_SECS);
+        if let Some(proxy) = proxy {
+            // `socks5h` let the proxy resolve the name of the server
+            builder = builder.proxy(&format!("socks5h://{proxy}"));
+        }
+        Ok(builder.build_blocking()?)
+    }
+
+    /// The height of the chain given by the first esplora that answers.
+    fn esplora_height(&self) -> error::Result<u32> {
+        let mut errors = Vec::new();
+        for url in self.esplora_urls()? {
+            let height =
+                Self::esplora_client(&url, self.proxy).and_then(|client| Ok(client.get_height()?));
+            match height {
+                Ok(height) => {
+                    self.tip_height.store(height, Ordering::SeqCst);
+                    return Ok(height);
+                }
+                Err(err) => errors.push(format!("`{url}`: {err}")),
+            }
+        }
+        error::bail!(
+            "impossible get the height from any esplora: {}",
+            errors.join(", ")
+        )
+    }
+
+    /// The file where the birthday of the wallet is stored, next
+    /// to the wallet store.
+    fn birthday_path(&self) -> PathBuf {
+        self.store_path.with_extension("birthday")
+    }
+
+    /// The height of the chain seen by the last sync, without asking
+    /// it to the backend. `None` until the wallet is synced.
+    pub fn tip_height(&self) -> Option<u32> {
+        Some(self.tip_height.load(Ordering::SeqCst)).filter(|height| *height > 0)
+    }
+
+    /// Scan the chain with the esplora at `url`, and return the
+    /// update of the wallet with the height of the chain.
+    fn scan(
+        wallet: &Wallet<Store<'static, ChangeSet>>,
+        url: &str,
+        proxy: Option<SocketAddr>,
+        concurrency: usize,
+    ) -> error::Result<(Update, u32)> {
+        let client = Self::esplora_client(url, proxy)?;
+        let checkpoints = wallet.latest_checkpoint();
+        let (update_graph, last_active_indices) = client.scan_txs_with_keychains(
+            wallet.spks_of_all_keychains(),
+            None,
+            None,
+            SCAN_STOP_GAP,
+            concurrency,
+        )?;
+        let missing_heights = wallet.tx_graph().missing_heights(wallet.local_chain());
+        let chain_update = client.update_local_chain(checkpoints, missing_heights)?;
+        let update = Update {
+            last_active_indices,
+            graph: update_graph,
+            chain: Some(chain_update),
+        };
+        // the chain update ends at the tip of esplora, so the height
+        // is asked only when there is no update.
+        let height = match &update.chain {
+            Some(chain) => chain.tip.height(),
+            None => client.get_height()?,
+        };
+        Ok((update, height))
+    }
+
+    /// Start a batch of changes, see `WalletBatch`.
+    pub fn batch(&self) -> WalletBatch<'_> {
+        self.batches.fetch_add(1, Ordering::SeqCst);
+        WalletBatch { manager: self }
+    }
+
+    /// Commit the changes staged in the wallet, unless a batch is open.
+    fn commit(&self, wallet: &mut Wallet<Store<'static, ChangeSet>>) -> error::Result<()> {
+        if self.batches.load(Ordering::SeqCst) == 0 {
+            wallet.commit()?;
+        }
+        Ok(())
+    }
+
+    /// Derive `count` new addresses, and commit them all at once.
+    pub fn get_addresses(&self, count: usize) -> error::Result<Vec<NewAddress>> {
+        let _batch = self.batch();
+        let mut wallet = self.write_wallet();
+        let addresses = (0..count)
+            .map(|_| NewAddress {
+                address: wallet
+                    .get_address(bdk::wallet::AddressIndex::New)
+                    .address
+                    .to_string(),
+            })
+            .collect();
+        // the batch commits when it is dropped, after the lock
+        drop(wallet);
+        Ok(addresses)
+    }
+
+    /// Sign and finalize the psbt with the wallet keys, and return
+    /// the transaction ready to be broadcasted.
+    fn sign_and_extract(
+        wallet: &Wallet<Store<'static, ChangeSet>>,
+        mut psbt: BdkPsbt,
+    ) -> error::Result<Transaction> {
+        if !wallet.sign(&mut psbt, SignOptions::default())? {
+            error::bail!("wallet not able to sing the psbt {psbt}");
+        }
+        if !wallet.finalize_psbt(&mut psbt, SignOptions::default())? {
+            error::bail!("wallet impossible finalize the psbt: {psbt}");
+        };
+        let tx: Transaction = deserialize(&serialize(&psbt.extract_tx()))?;
+        Ok(tx)
+    }
+}
+
+/// The state of the wallet before a sync, to report what the sync changed.
+struct WalletSnapshot {
+    /// The transactions of the wallet, `true` when confirmed.
+    txs: HashMap<String, bool>,
+    tip: BlockId,
+}
+
+impl WalletSnapshot {
+    fn take(wallet: &Wallet<Store<'static, ChangeSet>>) -> Self {
+        let txs = wallet
+            .transactions()
+            .map(|tx| {
+                let confirmed = matches!(tx.chain_position, ChainPosition::Confirmed(_));
+                (tx.tx_node.tx.txid().to_hex(), confirmed)
+            })
+            .collect();
+        Self {
+            txs,
+            tip: wallet.latest_checkpoint().block_id(),
+        }
+    }
+
+    /// What changed from the snapshot to the current state of the `wallet`.
+    fn report(&self, wallet: &Wallet<Store<'static, ChangeSet>>) -> SyncReport {
+        let mut report = SyncReport::default();
+        for tx in wallet.transactions() {
+            let txid = tx.tx_node.tx.txid().to_hex();
+            let confirmed = matches!(tx.chain_position, ChainPosition::Confirmed(_));
+            match self.txs.get(&txid) {
+                None => report.new_txs.push(txid.clone()),
+                Some(true) => continue,
+                Some(false) => {}
+            }
+            if confirmed {
+                report.confirmed_txs.push(txid);
+            }
+        }
+        report.new_txs.sort();
+        report.confirmed_txs.sort();
+
+        let tip = wallet.latest_checkpoint();
+        if tip.block_id() != self.tip {
+            report.new_tip_height = Some(tip.height());
+        }
+        // the block of the old tip was replaced by another one
+        report.reorged = tip
+            .iter()
+            .take_while(|checkpoint| checkpoint.height() >= self.tip.height)
+            .any(|checkpoint| {
+                checkpoint.height() == self.tip.height && checkpoint.hash() != self.tip.hash
+            });
+        report
+    }
+}
+
+/// Confirmations of an output when the wallet tip is at `tip`.
+fn confirmations(confirmation_time: &ConfirmationTime, tip: u32) -> u32 {
+    match confirmation_time {
+        ConfirmationTime::Confirmed { height, .. } => tip.saturating_sub(*height) + 1,
+        ConfirmationTime::Unconfirmed { .. } => 0,
+    }
+}
+
 impl WalletManager for BDKWalletManager {
     fn new(conf: Arc<LampoConf>) -> error::Result<(Self, String)> {
         // Generate fresh mnemonic
@@ -115,24 +796,29 @@ impl WalletManager for BDKWalletManager {
         // Convert mnemonic to string
         let mnemonic_words = mnemonic.to_string();
         log::info!("mnemonic words `{mnemonic_words}`");
-        let (wallet, keymanager) = BDKWalletManager::build_wallet(conf.clone(), &mnemonic_words)?;
-        Ok((
-            Self {
-                wallet: RefCell::new(Mutex::new(wallet)),
-                keymanager: Arc::new(keymanager),
-                network: conf.network,
-            },
-            mnemonic_words,
-        ))
+        let account_index = conf.account_index;
+        let wallet = Self::restore_account(conf, &mnemonic_words, account_index)?;
+        // a new wallet has no transactions before the tip, with the
+        // compact block filters the birthday is set by the caller that
+        // knows the tip of the backend.
+        if !wallet.block_sync {
+            match wallet.esplora_height() {
+                Ok(height) => wallet.set_birthday(height)?,
+                Err(err) => {
+                    log::warn!(target: "wallet", "impossible set the birthday of the wallet: {err}")
+                }
+            }
+        }
+        Ok((wallet, mnemonic_words))
     }
 
     fn restore(conf: Arc<LampoConf>, mnemonic_words: &str) -> error::Result<Self> {
-        let (wallet, keymanager) = BDKWalletManager::build_wallet(conf.clone(), mnemonic_words)?;
-        Ok(Self {
-            wallet: RefCell::new(Mutex::new(wallet)),
-            keymanager: Arc::new(keymanager),
-            network: conf.network,
-        })
+        let account_index = conf.account_index;
+        let wallet = Self::restore_account(conf.clone(), mnemonic_words, account_index)?;
+        if let Some(height) = conf.wallet_birthday_height {
+            wallet.set_birthday(height)?;
+        }
+        Ok(wallet)
     }
 
     fn ldk_keys(&self) -> Arc<LampoKeys> {
@@ -140,114 +826,349 @@ impl WalletManager for BDKWalletManager {
     }
 
     fn get_onchain_address(&self) -> error::Result<NewAddress> {
-        let address = self
-            .wallet
-            .borrow_mut()
-            .lock()
-            .unwrap()
-            .get_address(bdk::wallet::AddressIndex::New);
+        let mut wallet = self.write_wallet();
+        let address = wallet.get_address(bdk::wallet::AddressIndex::New);
+        self.commit(&mut wallet)?;
         Ok(NewAddress {
             address: address.address.to_string(),
         })
     }
 
-    fn get_onchain_balance(&self) -> error::Result<u64> {
+    fn get_onchain_balance(&self) -> error::Result<Sat> {
         self.sync()?;
-        let balance = self.wallet.borrow().lock().unwrap().get_balance();
-        Ok(balance.confirmed)
+        let balance = self.read_wallet().get_balance();
+        Ok(Sat::from_sat(balance.confirmed))
+    }
+
+    fn pending_incoming_balance(&self) -> error::Result<Sat> {
+        self.sync()?;
+        let balance = self.read_wallet().get_balance();
+        let pending = Sat::from_sat(balance.trusted_pending)
+            .checked_add(Sat::from_sat(balance.untrusted_pending))?;
+        Ok(pending)
     }
 
     fn create_transaction(
         &self,
         script: Script,
-        amount: u64,
+        amount: Sat,
         fee_rate: u32,
     ) -> error::Result<Transaction> {
+        check_fee_rate(fee_rate, self.min_fee_rate)?;
         self.sync()?;
-        let wallet = self.wallet.borrow_mut();
-        let mut wallet = wallet.lock().unwrap();
+        let mut wallet = self.write_wallet();
         let mut tx = wallet.build_tx();
-        tx.add_recipient(ScriptBuf::from_bytes(script.into_bytes()), amount)
-            .fee_rate(FeeRate::from_sat_per_kvb(fee_rate as f32))
+        tx.add_recipient(ScriptBuf::from_bytes(script.into_bytes()), amount.sat())
+            .fee_rate(bdk_fee_rate(fee_rate))
             .enable_rbf();
-        let mut psbt = tx.finish()?;
-        if !wallet.sign(&mut psbt, SignOptions::default())? {
-            error::bail!("wallet not able to sing the psbt {psbt}");
+        let psbt = tx.finish()?;
+        // the change address is reserved by the transaction
+        self.commit(&mut wallet)?;
+        Self::sign_and_extract(&wallet, psbt)
+    }
+
+    fn create_funding_transaction(
+        &self,
+        script: Script,
+        amount: Sat,
+        fee_rate: u32,
+        min_confirmations: u32,
+    ) -> error::Result<Transaction> {
+        check_fee_rate(fee_rate, self.min_fee_rate)?;
+        self.sync()?;
+        let mut wallet = self.write_wallet();
+        let tip = wallet.latest_checkpoint().height();
+        let unspendable = wallet
+            .list_unspent()
+            .filter(|utxo| confirmations(&utxo.confirmation_time, tip) < min_confirmations)
+            .map(|utxo| utxo.outpoint)
+            .collect::<Vec<_>>();
+        let mut tx = wallet.build_tx();
+        tx.add_recipient(ScriptBuf::from_bytes(script.into_bytes()), amount.sat())
+            .unspendable(unspendable)
+            .fee_rate(bdk_fee_rate(fee_rate))
+            .enable_rbf();
+        let psbt = tx.finish().map_err(|err| match err {
+            bdk::Error::InsufficientFunds { needed, available } => WalletError::InsufficientFunds {
+                needed_sat: needed,
+                available_sat: available,
+                min_confirmations,
+            }
+            .into(),
+            err => error::anyhow!("{err}"),
+        })?;
+        self.commit(&mut wallet)?;
+        Self::sign_and_extract(&wallet, psbt)
+    }
+
+    fn estimate_fee(&self, script: Script, amount: Sat, fee_rate: u32) -> error::Result<Sat> {
+        check_fee_rate(fee_rate, self.min_fee_rate)?;
+        let mut wallet = self.write_wallet();
+        let mut tx = wallet.build_tx();
+        tx.add_recipient(ScriptBuf::from_bytes(script.into_bytes()), amount.sat())
+            .fee_rate(bdk_fee_rate(fee_rate))
+            .enable_rbf();
+        // the psbt is dropped, so the change is not committed
+        let psbt = tx.finish()?;
+        let fee = wallet
+            .calculate_fee(&psbt.unsigned_tx)
+            .map_err(|err| error::anyhow!("impossible calculate the fee: {err:?}"))?;
+        Ok(Sat::from_sat(fee))
+    }
+
+    fn create_transaction_from_utxos(
+        &self,
+        utxos: Vec<bitcoin::OutPoint>,
+        script: Script,
+        amount: Sat,
+        fee_rate: u32,
+    ) -> error::Result<Transaction> {
+        if utxos.is_empty() {
+            error::bail!("no utxos specified for the transaction");
         }
-        if !wallet.finalize_psbt(&mut psbt, SignOptions::default())? {
-            error::bail!("wallet impossible finalize the psbt: {psbt}");
-        };
-        let tx: Transaction = deserialize(&serialize(&psbt.extract_tx()))?;
-        Ok(tx)
+        check_fee_rate(fee_rate, self.min_fee_rate)?;
+        self.sync()?;
+        let mut wallet = self.write_wallet();
+        let mut outpoints = Vec::with_capacity(utxos.len());
+        for utxo in utxos {
+            let outpoint: BdkOutPoint = deserialize(&serialize(&utxo))?;
+            // `add_utxos` accept also an output that is already spent
+            // by a transaction of the wallet, so we check it here.
+            match wallet.get_utxo(outpoint) {
+                Some(local) if !local.is_spent => outpoints.push(outpoint),
+                Some(_) => error::bail!("utxo `{utxo}` is already spent or reserved"),
+                None => error::bail!("utxo `{utxo}` not found in the wallet"),
+            }
+        }
+        let mut tx = wallet.build_tx();
+        tx.add_utxos(&outpoints)?
+            .manually_selected_only()
+            .add_recipient(ScriptBuf::from_bytes(script.into_bytes()), amount.sat())
+            .fee_rate(bdk_fee_rate(fee_rate))
+            .enable_rbf();
+        let psbt = tx.finish().map_err(|err| match err {
+            bdk::Error::InsufficientFunds { needed, available } => error::anyhow!(
+                "the selected utxos do not cover the amount and the fee: needed {needed} sats, available {available} sats"
+            ),
+            err => error::anyhow!("{err}"),
+        })?;
+        self.commit(&mut wallet)?;
+        Self::sign_and_extract(&wallet, psbt)
+    }
+
+    fn list_transaction_history(&self) -> error::Result<Vec<TxDetail>> {
+        self.sync()?;
+        let wallet = self.read_wallet();
+        let mut history = Vec::new();
+        for canonical_tx in wallet.transactions() {
+            let tx: &BdkTransaction = &canonical_tx.tx_node.tx;
+            let (sent, received) = wallet.sent_and_received(tx);
+            let (confirmation_height, timestamp) = match canonical_tx.chain_position {
+                ChainPosition::Confirmed(anchor) => (
+                    Some(anchor.confirmation_height),
+                    Some(anchor.confirmation_time),
+                ),
+                // zero if the transaction was never seen in the mempool
+                ChainPosition::Unconfirmed(last_seen) => {
+                    (None, (last_seen > 0).then_some(last_seen))
+                }
+            };
+            history.push(TxDetail {
+                txid: tx.txid().to_hex(),
+                net_amount_sat: received as i64 - sent as i64,
+                fee_sat: wallet.calculate_fee(tx).ok(),
+                confirmation_height,
+                timestamp,
+            });
+        }
+        sort_history(&mut history);
+        Ok(history)
     }
 
-    fn list_transactions(&self) -> error::Result<Vec<Utxo>> {
+    fn list_unspent(&self) -> error::Result<Vec<Utxo>> {
         self.sync()?;
-        let wallet = self.wallet.borrow();
-        let wallet = wallet.lock().unwrap();
+        self.cached_unspent()
+    }
+
+    fn cached_unspent(&self) -> error::Result<Vec<Utxo>> {
+        let wallet = self.read_wallet();
+        let tip = wallet.latest_checkpoint().height();
+        let network = bdk_network(self.network);
         let txs = wallet
             .list_unspent()
-            .map(|tx| Utxo {
-                txid: tx.outpoint.txid.to_hex(),
-                vout: tx.outpoint.vout,
-                reserved: tx.is_spent,
-                confirmed: 0,
-                amount_msat: Amount::from_btc(tx.txout.value as f64).unwrap().to_sat() * 1000_u64,
+            .map(|tx| {
+                Ok(Utxo {
+                    txid: tx.outpoint.txid.to_hex(),
+                    vout: tx.outpoint.vout,
+                    reserved: tx.is_spent,
+                    confirmed: confirmations(&tx.confirmation_time, tip),
+                    amount_msat: Sat::from_sat(tx.txout.value).to_msat()?,
+                    address: bdk::bitcoin::Address::from_script(&tx.txout.script_pubkey, network)
+                        .ok()
+                        .map(|address| address.to_string()),
+                })
             })
-            .collect::<Vec<_>>();
+            .collect::<error::Result<Vec<_>>>()?;
         Ok(txs)
     }
 
-    fn sync(&self) -> error::Result<()> {
-        // Scanning the chain...
-        let esplora_url = match self.network {
-            Network::Bitcoin => "https://mempool.space/api",
-            Network::Testnet => "https://mempool.space/testnet/api",
-            _ => {
-                error::bail!("network `{:?}` not supported", self.network);
-            }
+    fn synced_height(&self) -> error::Result<Option<u32>> {
+        // the wallet starts from the genesis checkpoint
+        let height = self.read_wallet().latest_checkpoint().height();
+        Ok(Some(height).filter(|height| *height > 0))
+    }
+
+    fn get_birthday(&self) -> error::Result<Option<u32>> {
+        let birthday = match std::fs::read_to_string(self.birthday_path()) {
+            Ok(birthday) => birthday,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => error::bail!("impossible read the wallet birthday: {err}"),
         };
-        let wallet = self.wallet.borrow();
-        let mut wallet = wallet.lock().unwrap();
-        let client = bdk_esplora::esplora_client::Builder::new(esplora_url).build_blocking()?;
-        let checkpoints = wallet.latest_checkpoint();
-        let spks = wallet
-            .spks_of_all_keychains()
-            .into_iter()
-            .map(|(k, spks)| {
-                let mut first = true;
-                (
-                    k,
-                    spks.inspect(move |(_spk_i, _)| {
-                        if first {
-                            first = false;
-                        }
-                    }),
-                )
-            })
+        let height = birthday
+            .trim()
+            .parse()
+            .map_err(|err| error::anyhow!("invalid wallet birthday `{birthday}`: {err}"))?;
+        Ok(Some(height))
+    }
+
+    fn set_birthday(&self, height: u32) -> error::Result<()> {
+        std::fs::write(self.birthday_path(), height.to_string())
+            .map_err(|err| error::anyhow!("impossible store the wallet birthday: {err}"))?;
+        log::info!(target: "wallet", "wallet birthday at height {height}");
+        Ok(())
+    }
+
+    fn sync(&self) -> error::Result<SyncReport> {
+        if self.block_sync {
+            // the blocks are applied when the backend matches them
+            return Ok(SyncReport::default());
+        }
+        let urls = self.esplora_urls()?;
+        let mut wallet = self.write_wallet();
+        let mut errors = Vec::new();
+        for url in &urls {
+            log::info!(target: "wallet", "bdk start to sync with `{url}`");
+            match Self::scan(&wallet, url, self.proxy, self.scan_concurrency) {
+                Ok((update, height)) => {
+                    let snapshot = WalletSnapshot::take(&wallet);
+                    wallet.apply_update(update)?;
+                    self.commit(&mut wallet)?;
+                    self.tip_height.store(height, Ordering::SeqCst);
+                    log::info!(target: "wallet", "bdk in sync at height {height} with `{url}`");
+                    return Ok(snapshot.report(&wallet));
+                }
+                Err(err) => {
+                    log::warn!(target: "wallet", "impossible sync with `{url}`: {err}");
+                    errors.push(format!("`{url}`: {err}"));
+                }
+            }
+        }
+        error::bail!(
+            "impossible sync the wallet with any esplora: {}",
+            errors.join(", ")
+        )
+    }
+
+    fn watched_scripts(&self) -> error::Result<Vec<bitcoin::ScriptBuf>> {
+        let wallet = self.read_wallet();
+        let scripts = wallet
+            .spk_index()
+            .inner()
+            .all_spks()
+            .values()
+            .map(|script| bitcoin::ScriptBuf::from_bytes(script.to_bytes()))
             .collect();
-        log::info!("bdk stert to sync");
+        Ok(scripts)
+    }
 
-        let (update_graph, last_active_indices) =
-            client.scan_txs_with_keychains(spks, None, None, 50, 2)?;
-        let missing_heights = wallet.tx_graph().missing_heights(wallet.local_chain());
-        let chain_update = client.update_local_chain(checkpoints, missing_heights)?;
-        let update = Update {
-            last_active_indices,
-            graph: update_graph,
-            chain: Some(chain_update),
-        };
+    fn apply_block(&self, block: &bitcoin::Block, height: u32) -> error::Result<SyncReport> {
+        let block: BdkBlock =
+            bdk::bitcoin::consensus::deserialize(&bitcoin::consensus::serialize(block))?;
+        let mut wallet = self.write_wallet();
+        let snapshot = WalletSnapshot::take(&wallet);
+        wallet
+            .apply_block(&block, height)
+            .map_err(|err| {
+                error::anyhow!("impossible apply the block at height {height}: {err}")
+            })?;
+        self.commit(&mut wallet)?;
+        self.tip_height.fetch_max(height, Ordering::SeqCst);
+        log::debug!(target: "wallet", "block `{}` at height {height} applied", block.block_hash());
+        Ok(snapshot.report(&wallet))
+    }
 
-        wallet.apply_update(update)?;
-        wallet.commit()?;
-        log::info!(
-            "bdk in sync at height {}!",
-            client
-                .get_height()
-                .map_err(|err| bdk::Error::Generic(format!("{err}")))?
-        );
+    fn flush(&self) -> error::Result<()> {
+        self.write_wallet().commit()?;
         Ok(())
     }
+
+    fn set_esplora_urls(&self, urls: Vec<String>) -> error::Result<()> {
+        *self.esplora_urls.write_or_recover() = urls;
+        Ok(())
+    }
+
+    fn compact(&self) -> error::Result<Vec<StoreSize>> {
+        Ok(vec![self.compact_store()?])
+    }
+
+    fn list_confirmed_utxos(&self) -> error::Result<Vec<bump_transaction::Utxo>> {
+        let wallet = self.read_wallet();
+        let mut utxos = Vec::new();
+        for utxo in wallet
+            .list_unspent()
+            .filter(|utxo| !utxo.is_spent && utxo.confirmation_time.is_confirmed())
+        {
+            let outpoint: bitcoin::OutPoint = deserialize(&serialize(&utxo.outpoint))?;
+            let output: bitcoin::TxOut = deserialize(&serialize(&utxo.txout))?;
+            let satisfaction_weight = match wallet.get_descriptor_for_keychain(utxo.keychain) {
+                Descriptor::Wpkh(_) => P2WPKH_SATISFACTION_WEIGHT,
+                // a wallet built from a custom descriptor
+                descriptor => descriptor
+                    .max_weight_to_satisfy()
+                    .map_err(|err| error::anyhow!("impossible satisfy the descriptor: {err}"))?
+                    as u64,
+            };
+            utxos.push(bump_transaction::Utxo {
+                outpoint,
+                output,
+                satisfaction_weight,
+            });
+        }
+        Ok(utxos)
+    }
+
+    fn get_change_script(&self) -> error::Result<bitcoin::ScriptBuf> {
+        let mut wallet = self.write_wallet();
+        let address = wallet.get_internal_address(bdk::wallet::AddressIndex::New);
+        self.commit(&mut wallet)?;
+        Ok(bitcoin::ScriptBuf::from_bytes(
+            address.script_pubkey().to_bytes(),
+        ))
+    }
+
+    fn sign_psbt(&self, psbt: PartiallySignedTransaction) -> error::Result<Transaction> {
+        let mut psbt = BdkPsbt::deserialize(&psbt.serialize())?;
+        let wallet = self.read_wallet();
+        // ldk fill the `witness_utxo` of our inputs, and the inputs that
+        // we do not own are signed by ldk later.
+        let options = SignOptions {
+            trust_witness_utxo: true,
+            ..Default::default()
+        };
+        wallet.sign(&mut psbt, options)?;
+        let tx: Transaction = deserialize(&serialize(&psbt.extract_tx()))?;
+        Ok(tx)
+    }
+}
+
+/// Convert the fee rate in sat/kW of the `WalletManager` to the one
+/// used by bdk, in sat/vB.
+///
+/// The fractional rates are kept (e.g. 125 sat/kW is 0.5 sat/vB), and
+/// bdk rounds the fee of the transaction up to the next sat, so the
+/// transaction never pays less than the requested rate.
+fn bdk_fee_rate(fee_rate: u32) -> FeeRate {
+    FeeRate::from_sat_per_vb(fee_rate as f32 / 250.0)
 }
 
 #[cfg(debug_assertions)]
@@ -255,13 +1176,25 @@ impl TryFrom<(PrivateKey, Option<String>)> for BDKWalletManager {
     type Error = bdk::Error;
 
     fn try_from(value: (PrivateKey, Option<String>)) -> Result<Self, Self::Error> {
-        let (wallet, keymanager) = BDKWalletManager::build_from_private_key(value.0, value.1)?;
+        let store_path = Self::temp_store_path();
+        let (wallet, keymanager) =
+            BDKWalletManager::build_from_private_key(value.0, value.1, &store_path)?;
         Ok(Self {
-            wallet: RefCell::new(Mutex::new(wallet)),
+            wallet: Arc::new(RwLock::new(wallet)),
             keymanager: Arc::new(keymanager),
             // This should be possible only during integration testing
             // FIXME: fix the sync method in bdk, the esplora client will crash!
             network: Network::Regtest,
+            scan_concurrency: DEFAULT_SCAN_CONCURRENCY,
+            esplora_urls: Arc::new(RwLock::new(Vec::new())),
+            default_esplora_url: None,
+            min_fee_rate: sat_per_vb_to_sat_per_kw(DEFAULT_MIN_FEE_RATE_SAT_VB),
+            store_path,
+            proxy: None,
+            account_index: 0,
+            block_sync: false,
+            batches: Arc::new(AtomicUsize::new(0)),
+            tip_height: Arc::new(AtomicU32::new(0)),
         })
     }
 }
@@ -270,11 +1203,17 @@ impl TryFrom<(PrivateKey, Option<String>)> for BDKWalletManager {
 mod tests {
     use std::str::FromStr;
 
+    use std::sync::Arc;
+
     use lampo_common::bitcoin;
     use lampo_common::bitcoin::PrivateKey;
+    use lampo_common::conf::LampoConf;
+    use lampo_common::json;
+    use lampo_common::model::Sat;
     use lampo_common::secp256k1::SecretKey;
 
-    use super::{BDKWalletManager, WalletManager};
+    use super::{confirmations, BDKWalletManager, ConfirmationTime, WalletManager};
+    use lampo_common::wallet::{sat_per_vb_to_sat_per_kw, SyncReport};
 
     #[test]
     fn from_private_key() {
@@ -288,4 +1227,416 @@ mod tests {
         let wallet = wallet.unwrap();
         assert!(wallet.get_onchain_address().is_ok());
     }
+
+    #[test]
+    fn in_memory_wallets_are_isolated() {
+        let first = BDKWalletManager::new_in_memory(bitcoin::Network::Regtest).unwrap();
+        let second = BDKWalletManager::new_in_memory(bitcoin::Network::Regtest).unwrap();
+        // the wallets are deterministic, but each one has its own store
+        assert_eq!(
+            first.get_onchain_address().unwrap().address,
+            second.get_onchain_address().unwrap().address
+        );
+        assert_ne!(
+            first.get_onchain_address().unwrap().address,
+            first.get_onchain_address().unwrap().address
+        );
+    }
+
+    #[test]
+    fn recover_from_poisoned_lock() {
+        let wallet = BDKWalletManager::new_in_memory(bitcoin::Network::Regtest).unwrap();
+        let inner = wallet.wallet.clone();
+        let result = std::thread::spawn(move || {
+            let _guard = inner.write().unwrap();
+            panic!("poison the wallet lock");
+        })
+        .join();
+        assert!(result.is_err());
+        assert!(wallet.wallet.is_poisoned());
+        assert!(wallet.get_onchain_address().is_ok());
+        assert!(wallet.list_confirmed_utxos().is_ok());
+    }
+
+    #[test]
+    fn export_and_import_backup() {
+        let wallet = BDKWalletManager::new_in_memory(bitcoin::Network::Regtest).unwrap();
+        let _ = wallet.get_onchain_address().unwrap();
+        let _ = wallet.get_onchain_address().unwrap();
+        let backup = wallet.export_backup().unwrap();
+
+        // the derived addresses are restored, so the next address
+        // is the same for both wallets.
+        let restored =
+            BDKWalletManager::from_backup_in_memory(bitcoin::Network::Regtest, &backup).unwrap();
+        assert_eq!(
+            restored.get_onchain_address().unwrap().address,
+            wallet.get_onchain_address().unwrap().address
+        );
+
+        let other_network =
+            BDKWalletManager::from_backup_in_memory(bitcoin::Network::Testnet, &backup);
+        assert!(other_network.is_err());
+        assert!(BDKWalletManager::from_backup_in_memory(bitcoin::Network::Regtest, b"{}").is_err());
+    }
+
+    #[test]
+    fn compact_keeps_the_state() {
+        let wallet = BDKWalletManager::new_in_memory(bitcoin::Network::Regtest).unwrap();
+        // every address appends the index revealed to the store
+        let addresses = (0..200)
+            .map(|_| wallet.get_onchain_address().unwrap().address)
+            .collect::<Vec<_>>();
+        let before: json::Value = json::from_slice(&wallet.export_backup().unwrap()).unwrap();
+
+        let size = wallet.compact().unwrap().remove(0);
+        assert!(size.bytes_after < size.bytes_before, "{size:?}");
+        assert_eq!(
+            size.bytes_after,
+            std::fs::metadata(&wallet.store_path).unwrap().len()
+        );
+        let after: json::Value = json::from_slice(&wallet.export_backup().unwrap()).unwrap();
+        assert_eq!(before, after);
+
+        // the wallet goes on from the new store
+        let next = wallet.get_onchain_address().unwrap().address;
+        assert!(!addresses.contains(&next));
+        let restored = BDKWalletManager::from_backup_in_memory(
+            bitcoin::Network::Regtest,
+            &wallet.export_backup().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            restored.get_onchain_address().unwrap().address,
+            wallet.get_onchain_address().unwrap().address
+        );
+    }
+
+    #[test]
+    fn batch_of_addresses_is_committed() {
+        let wallet = BDKWalletManager::new_in_memory(bitcoin::Network::Regtest).unwrap();
+        let addresses = wallet.get_addresses(3).unwrap();
+        assert_eq!(addresses.len(), 3);
+        assert_ne!(addresses[0].address, addresses[2].address);
+        {
+            let _batch = wallet.batch();
+            let _ = wallet.get_onchain_address().unwrap();
+        }
+        wallet.flush().unwrap();
+
+        let restored = BDKWalletManager::from_backup_in_memory(
+            bitcoin::Network::Regtest,
+            &wallet.export_backup().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            restored.get_onchain_address().unwrap().address,
+            wallet.get_onchain_address().unwrap().address
+        );
+    }
+
+    #[test]
+    fn sync_tries_all_the_esplora() {
+        let wallet = BDKWalletManager::new_in_memory(bitcoin::Network::Regtest).unwrap();
+        // nothing listens on these ports
+        wallet
+            .set_esplora_urls(vec![
+                "http://127.0.0.1:1".to_owned(),
+                "http://127.0.0.1:2".to_owned(),
+            ])
+            .unwrap();
+        let err = wallet.sync().unwrap_err().to_string();
+        assert!(err.contains("http://127.0.0.1:1"), "{err}");
+        assert!(err.contains("http://127.0.0.1:2"), "{err}");
+    }
+
+    #[test]
+    fn scan_requests_stay_within_the_concurrency() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        // an esplora that has no transactions, and counts the
+        // requests that it is serving at the same time
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        {
+            let in_flight = in_flight.clone();
+            let max_in_flight = max_in_flight.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(mut stream) = stream else {
+                        continue;
+                    };
+                    let in_flight = in_flight.clone();
+                    let max_in_flight = max_in_flight.clone();
+                    std::thread::spawn(move || {
+                        let mut reader = BufReader::new(stream.try_clone().unwrap());
+                        let mut request = String::new();
+                        let _ = reader.read_line(&mut request);
+                        let mut header = String::new();
+                        while reader.read_line(&mut header).is_ok_and(|read| read > 2) {
+                            header.clear();
+                        }
+                        let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_in_flight.fetch_max(current, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(20));
+                        in_flight.fetch_sub(1, Ordering::SeqCst);
+                        let (status, body) = if request.contains("/scripthash/") {
+                            ("200 OK", "[]")
+                        } else {
+                            ("404 Not Found", "")
+                        };
+                        let _ = write!(
+                            stream,
+                            "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                            body.len()
+                        );
+                    });
+                }
+            });
+        }
+
+        let mut wallet = BDKWalletManager::new_in_memory(bitcoin::Network::Regtest).unwrap();
+        wallet.scan_concurrency = 3;
+        wallet.set_esplora_urls(vec![url]).unwrap();
+        // the fake esplora does not serve the blocks, so the sync
+        // fails after the scan of the scripts
+        assert!(wallet.sync().is_err());
+        let max_in_flight = max_in_flight.load(Ordering::SeqCst);
+        assert!(
+            max_in_flight <= 3,
+            "{max_in_flight} requests at the same time"
+        );
+        assert!(max_in_flight > 1, "the scan is not parallel");
+    }
+
+    #[test]
+    fn accounts_are_independent() {
+        let mut conf = LampoConf::default();
+        conf.network = bitcoin::Network::Regtest;
+        // SAFETY: the store path has always a parent.
+        conf.root_path = BDKWalletManager::temp_store_path()
+            .parent()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        std::fs::create_dir_all(conf.path()).unwrap();
+        let conf = Arc::new(conf);
+        let mnemonic = format!("{} about", ["abandon"; 11].join(" "));
+
+        let account0 = BDKWalletManager::restore(conf.clone(), &mnemonic).unwrap();
+        let account1 = BDKWalletManager::restore_account(conf.clone(), &mnemonic, 1).unwrap();
+        assert_eq!(account1.account_index, 1);
+        assert_ne!(account0.store_path, account1.store_path);
+        assert_ne!(
+            account0.get_onchain_address().unwrap().address,
+            account1.get_onchain_address().unwrap().address
+        );
+        assert!(BDKWalletManager::restore_account(conf, &mnemonic, 1 << 31).is_err());
+    }
+
+    #[test]
+    fn build_from_descriptor() {
+        let mut conf = LampoConf::default();
+        conf.network = bitcoin::Network::Regtest;
+        // SAFETY: the store path has always a parent.
+        conf.root_path = BDKWalletManager::temp_store_path()
+            .parent()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        let conf = Arc::new(conf);
+        let first = "tprv8ZgxMBicQKsPd3krDUsBAmtnRsK3rb8u5yi1zhQgMhF1tR8MW7xfE4rnrbbsrbPR52e7rKapu6ztw1jXveJSCGHEriUGZV7mCe88duLp5pj";
+        let second = "tprv8ZgxMBicQKsPeDgjzdC36fs6bMjGApWDNLR9erAXMs5skhMv36j9MV5ecvfavji5khqjWaWSFhN3YcCUUdiKH6isR4Pwy3U5y5egddBr16m";
+        let external = format!("wsh(multi(1,{first}/0/*,{second}/0/*))");
+        let internal = format!("wsh(multi(1,{first}/1/*,{second}/1/*))");
+
+        let wallet =
+            BDKWalletManager::from_descriptor(conf.clone(), &external, Some(&internal)).unwrap();
+        assert!(wallet.get_onchain_address().is_ok());
+        assert!(wallet.get_change_script().unwrap().is_v0_p2wsh());
+
+        // the ldk keys depend only on the keys of the descriptor
+        let network = bitcoin::Network::Regtest;
+        let seed = BDKWalletManager::descriptor_ldk_seed(&external, network).unwrap();
+        assert_eq!(
+            seed,
+            BDKWalletManager::descriptor_ldk_seed(&external, network).unwrap()
+        );
+        let single = format!("wpkh({first}/0/*)");
+        assert_ne!(
+            seed,
+            BDKWalletManager::descriptor_ldk_seed(&single, network).unwrap()
+        );
+        let watch_only =
+            "wpkh(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)";
+        assert!(BDKWalletManager::descriptor_ldk_seed(watch_only, network).is_err());
+    }
+
+    #[test]
+    fn store_the_birthday() {
+        let wallet = BDKWalletManager::new_in_memory(bitcoin::Network::Regtest).unwrap();
+        assert_eq!(wallet.get_birthday().unwrap(), None);
+        wallet.set_birthday(800_000).unwrap();
+        assert_eq!(wallet.get_birthday().unwrap(), Some(800_000));
+    }
+
+    /// Fund the wallet with `count` outputs of `value` sats confirmed
+    /// in the block at height 1.
+    fn fund_with_outputs(wallet: &BDKWalletManager, count: usize, value: u64) -> SyncReport {
+        use lampo_common::bitcoin::absolute::LockTime;
+        use lampo_common::bitcoin::block::{Header, Version};
+        use lampo_common::bitcoin::blockdata::constants::genesis_block;
+        use lampo_common::bitcoin::hashes::Hash;
+        use lampo_common::bitcoin::{
+            Address, Block, CompactTarget, OutPoint, Sequence, Transaction, TxIn, TxMerkleNode,
+            TxOut, Txid, Witness,
+        };
+
+        let output = TxOut {
+            value,
+            script_pubkey: Address::from_str(&wallet.get_onchain_address().unwrap().address)
+                .unwrap()
+                .assume_checked()
+                .script_pubkey(),
+        };
+        let coinbase = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![],
+        };
+        let funding = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array([1; 32]), 0),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+                ..Default::default()
+            }],
+            output: vec![output; count],
+        };
+        let block = Block {
+            header: Header {
+                version: Version::from_consensus(1),
+                prev_blockhash: genesis_block(bitcoin::Network::Regtest).block_hash(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 0,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce: 0,
+            },
+            txdata: vec![coinbase, funding],
+        };
+        wallet.apply_block(&block, 1).unwrap()
+    }
+
+    #[test]
+    fn report_the_applied_block() {
+        let wallet = BDKWalletManager::new_in_memory(bitcoin::Network::Regtest).unwrap();
+        assert_eq!(wallet.tip_height(), None);
+        let report = fund_with_outputs(&wallet, 2, 10_000);
+        assert_eq!(wallet.tip_height(), Some(1));
+        // only the funding transaction pays the wallet
+        assert_eq!(report.new_txs.len(), 1);
+        assert_eq!(report.confirmed_txs, report.new_txs);
+        assert_eq!(report.new_tip_height, Some(1));
+        assert!(!report.reorged);
+
+        // the same block changes nothing
+        let report = fund_with_outputs(&wallet, 2, 10_000);
+        assert!(report.is_empty(), "{report:?}");
+    }
+
+    #[test]
+    fn half_sat_per_vb_on_a_large_transaction() {
+        let mut wallet = BDKWalletManager::new_in_memory(bitcoin::Network::Regtest).unwrap();
+        wallet.min_fee_rate = sat_per_vb_to_sat_per_kw(0.5);
+        fund_with_outputs(&wallet, 100, 10_000);
+        let script = wallet.get_change_script().unwrap();
+
+        // ~90 inputs, so the fee is some thousands of sats
+        let half = wallet
+            .estimate_fee(
+                script.clone(),
+                Sat::from_sat(900_000),
+                sat_per_vb_to_sat_per_kw(0.5),
+            )
+            .unwrap()
+            .sat();
+        let one = wallet
+            .estimate_fee(
+                script.clone(),
+                Sat::from_sat(900_000),
+                sat_per_vb_to_sat_per_kw(1.0),
+            )
+            .unwrap()
+            .sat();
+        assert!(one > 5_000, "{one}");
+        // the fee is rounded up, so it is never below the rate
+        assert!(half * 2 >= one, "{half} {one}");
+        assert!(half * 2 <= one + 2, "{half} {one}");
+
+        // the rates below the minimum of the wallet are refused
+        assert!(wallet
+            .estimate_fee(
+                script,
+                Sat::from_sat(900_000),
+                sat_per_vb_to_sat_per_kw(0.25),
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn confirmations_of_the_outputs() {
+        let confirmed = ConfirmationTime::Confirmed {
+            height: 100,
+            time: 0,
+        };
+        assert_eq!(confirmations(&confirmed, 100), 1);
+        assert_eq!(confirmations(&confirmed, 105), 6);
+        let unconfirmed = ConfirmationTime::Unconfirmed { last_seen: 0 };
+        assert_eq!(confirmations(&unconfirmed, 100), 0);
+    }
+
+    #[test]
+    fn signet_addresses_and_esplora() {
+        let wallet = BDKWalletManager::new_in_memory(bitcoin::Network::Signet).unwrap();
+        let address = wallet.get_onchain_address().unwrap().address;
+        assert!(address.starts_with("tb1"), "{address}");
+        bitcoin::Address::from_str(&address)
+            .unwrap()
+            .require_network(bitcoin::Network::Signet)
+            .unwrap();
+        assert_eq!(
+            wallet.esplora_urls().unwrap(),
+            vec!["https://mempool.space/signet/api".to_owned()]
+        );
+
+        // a custom signet has no public esplora
+        let mut conf = LampoConf::default();
+        conf.network = bitcoin::Network::Signet;
+        conf.signet_challenge = Some(
+            bitcoin::ScriptBuf::from_hex(
+                "512102f7561d208dd9ae99bf497273e16f389bdbd6c4742ddb8e6b216e64fa2928ad8f51ae",
+            )
+            .unwrap(),
+        );
+        let wallet = BDKWalletManager {
+            default_esplora_url: conf.default_esplora_url(),
+            ..wallet
+        };
+        let err = wallet.esplora_urls().unwrap_err().to_string();
+        assert!(err.contains("esplora-url"), "{err}");
+    }
+
+    #[test]
+    fn wallet_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<BDKWalletManager>();
+    }
 }
@@ -0,0 +1,431 @@
+//! Compact block filters (BIP 157/158) backend implementation for Lampo.
+//!
+//! The backend follows the best chain with a light client, and matches
+//! the filters of each block with the scripts watched (the ones of the
+//! wallet and the ones registered by ldk). Only the blocks that match
+//! are downloaded, so we do not need a full node and we do not tell
+//! our scripts to a server.
+use std::collections::HashSet;
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use nakamoto_client::traits::Handle;
+use nakamoto_client::{Client, Config, Event as ClientEvent, Network as ClientNetwork};
+use nakamoto_common::bitcoin::consensus::encode::deserialize as client_deserialize;
+use nakamoto_common::bitcoin::consensus::encode::serialize as client_serialize;
+use nakamoto_common::bitcoin::Script as ClientScript;
+use nakamoto_net_poll::{Reactor, Waker};
+
+use lampo_common::backend::{deserialize, serialize};
+use lampo_common::backend::{AsyncBlockSourceResult, Backend, BackendKind, BlockHeaderData};
+use lampo_common::backend::BlockSourceError;
+use lampo_common::backend::{Block, BlockData, BlockHash, BroadcastError, TxResult, WatchedOutput};
+use lampo_common::bitcoin::absolute::Height;
+use lampo_common::bitcoin::consensus::{Decodable, Encodable};
+use lampo_common::bitcoin::{OutPoint, Script, ScriptBuf, Transaction, TxOut, Txid};
+use lampo_common::conf::{LampoConf, Network};
+use lampo_common::error;
+use lampo_common::event::onchain::OnChainEvent;
+use lampo_common::event::Event;
+use lampo_common::handler::Handler;
+use lampo_common::sync::MutexExt;
+
+/// Time waited for a block requested to the peers.
+const BLOCK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The fee rate in sat/kW used on regtest, that is the minimum
+/// accepted by ldk.
+const REGTEST_FEE_RATE: u32 = 253;
+
+/// Convert a type of the bitcoin crate used by the light client into
+/// the one used by lampo, they can be different versions.
+fn from_client<T: nakamoto_common::bitcoin::consensus::Encodable, U: Decodable>(
+    value: &T,
+) -> error::Result<U> {
+    Ok(deserialize(&client_serialize(value))?)
+}
+
+/// Convert a type of the bitcoin crate used by lampo into the one
+/// used by the light client.
+fn into_client<T: Encodable, U: nakamoto_common::bitcoin::consensus::Decodable>(
+    value: &T,
+) -> error::Result<U> {
+    Ok(client_deserialize(&serialize(value))?)
+}
+
+pub struct Bip157 {
+    client: nakamoto_client::Handle<Waker>,
+    peers: Vec<SocketAddr>,
+    network: Network,
+    handler: Mutex<Option<Arc<dyn Handler>>>,
+    /// The scripts given to the light client to match the filters.
+    scripts: Mutex<HashSet<ScriptBuf>>,
+    /// The transactions reported as confirmed when they are inside
+    /// a block matched, ours or registered by ldk.
+    txids: Mutex<HashSet<Txid>>,
+    /// The outputs registered by ldk, the transactions that spend
+    /// them are reported as confirmed.
+    outpoints: Mutex<HashSet<OutPoint>>,
+    /// The height of the last `NewBestBlock` emitted.
+    best_height: Mutex<Option<u32>>,
+}
+
+impl Bip157 {
+    /// Start the light client, that connects to the `bip157-peer`
+    /// of the configuration or to the peers of the dns seeds.
+    pub fn new(conf: &LampoConf) -> error::Result<Self> {
+        let network = match conf.network {
+            Network::Bitcoin => ClientNetwork::Mainnet,
+            Network::Testnet => ClientNetwork::Testnet,
+            Network::Signet if conf.is_custom_signet() => {
+                error::bail!("the bip157 backend supports only the public signet")
+            }
+            Network::Signet => ClientNetwork::Signet,
+            Network::Regtest => ClientNetwork::Regtest,
+            network => error::bail!("network `{network}` not supported by the bip157 backend"),
+        };
+        let peers = conf
+            .bip157_peers
+            .iter()
+            .map(|peer| SocketAddr::from_str(peer))
+            .collect::<Result<Vec<_>, _>>()?;
+        if peers.is_empty() && conf.network == Network::Regtest {
+            error::bail!("the bip157 backend needs a `bip157-peer` on regtest");
+        }
+        let root = PathBuf::from(format!("{}/bip157", conf.path()));
+        std::fs::create_dir_all(&root)?;
+        let config = Config {
+            network,
+            connect: peers.clone(),
+            root,
+            ..Config::default()
+        };
+
+        let client = Client::<Reactor<TcpStream>>::new()?;
+        let handle = client.handle();
+        // the light client runs until the node is stopped
+        let _worker = std::thread::spawn(move || {
+            if let Err(err) = client.run(config) {
+                log::error!(target: "bip157", "the light client stopped: {err}");
+            }
+        });
+        log::info!(target: "bip157", "light client started with the peers {:?}", peers);
+        Ok(Self {
+            client: handle,
+            peers,
+            network: conf.network,
+            handler: Mutex::new(None),
+            scripts: Mutex::new(HashSet::new()),
+            txids: Mutex::new(HashSet::new()),
+            outpoints: Mutex::new(HashSet::new()),
+            best_height: Mutex::new(None),
+        })
+    }
+
+    fn handler(&self) -> error::Result<Arc<dyn Handler>> {
+        self.handler
+            .lock_or_recover()
+            .clone()
+            .ok_or(error::anyhow!("handler is not set"))
+    }
+
+    /// Download the block with `hash` from the peers.
+    fn fetch_block(&self, hash: &BlockHash) -> error::Result<Block> {
+        // subscribe before the request, so the block is not lost
+        let blocks = self.client.blocks();
+        self.client.request_block(&into_client(hash)?)?;
+        let deadline = Instant::now() + BLOCK_TIMEOUT;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let Ok((block, _)) = blocks.recv_timeout(timeout) else {
+                error::bail!("the peers did not send the block `{hash}`");
+            };
+            let block: Block = from_client(&block)?;
+            if block.block_hash() == *hash {
+                return Ok(block);
+            }
+        }
+    }
+
+    /// Tell lampo that the block at `height` is the new tip, the
+    /// heights already notified (e.g. during a rescan) are skipped.
+    fn connect_block(&self, handler: &Arc<dyn Handler>, height: u32) -> error::Result<()> {
+        let mut best_height = self.best_height.lock_or_recover();
+        if best_height.is_some_and(|best_height| best_height >= height) {
+            return Ok(());
+        }
+        let header = self
+            .client
+            .get_block_by_height(height as u64)?
+            .ok_or(error::anyhow!("no block at height {height}"))?;
+        log::trace!(target: "bip157", "new best block `{}` at height {height}", header.block_hash());
+        handler.emit(Event::OnChain(OnChainEvent::NewBestBlock((
+            from_client(&header)?,
+            Height::from_consensus(height)?,
+        ))));
+        *best_height = Some(height);
+        Ok(())
+    }
+
+    /// Download a block that matched the filters, report the
+    /// transactions watched by ldk and give the block to the wallet.
+    fn process_matched(
+        &self,
+        handler: &Arc<dyn Handler>,
+        hash: BlockHash,
+        height: u32,
+    ) -> error::Result<()> {
+        log::debug!(target: "bip157", "block `{hash}` at height {height} matched the filters");
+        let block = self.fetch_block(&hash)?;
+        let height = Height::from_consensus(height)?;
+        {
+            let txids = self.txids.lock_or_recover();
+            let outpoints = self.outpoints.lock_or_recover();
+            for (idx, tx) in block.txdata.iter().enumerate() {
+                let spends_watched = tx
+                    .input
+                    .iter()
+                    .any(|input| outpoints.contains(&input.previous_output));
+                if txids.contains(&tx.txid()) || spends_watched {
+                    handler.emit(Event::OnChain(OnChainEvent::ConfirmedTransaction((
+                        tx.clone(),
+                        idx as u32,
+                        block.header,
+                        height,
+                    ))));
+                }
+            }
+        }
+        handler.emit(Event::OnChain(OnChainEvent::BlockMatched((block, height))));
+        self.connect_block(handler, height.to_consensus_u32())
+    }
+}
+
+impl Backend for Bip157 {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Bip157
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        if self.peers.is_empty() {
+            return None;
+        }
+        let peers = self
+            .peers
+            .iter()
+            .map(|peer| peer.to_string())
+            .collect::<Vec<_>>();
+        Some(peers.join(","))
+    }
+
+    fn fee_rate_estimation(&self, _blocks: u64) -> error::Result<u32> {
+        if self.network == Network::Regtest {
+            return Ok(REGTEST_FEE_RATE);
+        }
+        error::bail!(
+            "the peers do not estimate the fees, set `fee-estimates-url` to estimate them"
+        )
+    }
+
+    fn minimum_mempool_fee(&self) -> error::Result<u32> {
+        // the peers do not tell us their mempool, so we use the
+        // default min relay fee.
+        Ok(REGTEST_FEE_RATE)
+    }
+
+    fn brodcast_tx(&self, tx: &Transaction) -> Result<(), BroadcastError> {
+        // the peers do not tell us why a transaction is rejected, so
+        // we can only report that it was not submitted.
+        let result = into_client(tx).and_then(|client_tx| {
+            self.client
+                .submit_transaction(client_tx)
+                .map_err(|err| error::anyhow!("{err}"))
+        });
+        if let Err(err) = result {
+            log::error!(target: "bip157", "broadcast of the transaction `{}` fails: {err}", tx.txid());
+            return Err(BroadcastError::Rejected(err.to_string()));
+        }
+        log::info!(target: "bip157", "transaction `{}` broadcasted", tx.txid());
+        // watch the outputs, so we know when it is confirmed
+        self.txids.lock_or_recover().insert(tx.txid());
+        let scripts = tx
+            .output
+            .iter()
+            .map(|output| output.script_pubkey.clone())
+            .collect();
+        self.watch_scripts(scripts, None);
+        if let Ok(handler) = self.handler() {
+            handler.emit(Event::OnChain(OnChainEvent::SendRawTransaction(tx.clone())));
+        }
+        Ok(())
+    }
+
+    fn is_lightway(&self) -> bool {
+        true
+    }
+
+    fn notify_new_blocks(&self) -> bool {
+        true
+    }
+
+    fn filter_blocks(&self) -> bool {
+        true
+    }
+
+    fn watch_scripts(&self, scripts: Vec<ScriptBuf>, from_height: Option<u32>) {
+        let mut watched = self.scripts.lock_or_recover();
+        let new = scripts
+            .into_iter()
+            .filter(|script| watched.insert(script.clone()))
+            .collect::<Vec<_>>();
+        let result = match from_height {
+            Some(from) => watched
+                .iter()
+                .map(into_client)
+                .collect::<error::Result<Vec<ClientScript>>>()
+                .and_then(|scripts| {
+                    log::info!(target: "bip157", "scanning the filters from height {from}");
+                    self.client
+                        .rescan((from as u64).., scripts.into_iter())
+                        .map_err(|err| error::anyhow!("{err}"))
+                }),
+            None if new.is_empty() => return,
+            None => new
+                .iter()
+                .map(into_client)
+                .collect::<error::Result<Vec<ClientScript>>>()
+                .and_then(|scripts| {
+                    self.client
+                        .watch(scripts.into_iter())
+                        .map_err(|err| error::anyhow!("{err}"))
+                }),
+        };
+        if let Err(err) = result {
+            log::error!(target: "bip157", "impossible watch the scripts: {err}");
+        }
+    }
+
+    fn watch_utxo(&self, txid: &Txid, script: &Script) {
+        self.txids.lock_or_recover().insert(*txid);
+        self.watch_scripts(vec![script.to_owned()], None);
+    }
+
+    fn register_output(&self, output: WatchedOutput) -> Option<(usize, Transaction)> {
+        // the filters contain the scripts spent, so the spending
+        // transaction is found in the block that confirms it.
+        self.outpoints
+            .lock_or_recover()
+            .insert(output.outpoint.into_bitcoin_outpoint());
+        self.watch_scripts(vec![output.script_pubkey], None);
+        None
+    }
+
+    /// The headers store of the client does not keep the chainwork,
+    /// so the header is never given, lampo does not poll the chain
+    /// through this backend.
+    fn get_header<'a>(
+        &'a self,
+        header_hash: &'a BlockHash,
+        _height_hint: Option<u32>,
+    ) -> AsyncBlockSourceResult<'a, BlockHeaderData> {
+        Box::pin(async move {
+            Err(BlockSourceError::persistent(format!(
+                "the header `{header_hash}` is not served by the bip157 backend"
+            )))
+        })
+    }
+
+    fn get_block<'a>(&'a self, header_hash: &'a BlockHash) -> error::Result<BlockData> {
+        Ok(BlockData::FullBlock(self.fetch_block(header_hash)?))
+    }
+
+    fn get_best_block(&self) -> error::Result<(BlockHash, Option<u32>)> {
+        let tip = self.client.get_tip()?;
+        let hash: BlockHash = from_client(&tip.blk_header.block_hash())?;
+        Ok((hash, Some(tip.height as u32)))
+    }
+
+    fn get_block_hash_at(&self, height: u32) -> error::Result<BlockHash> {
+        let header = self
+            .client
+            .get_block_by_height(height as u64)?
+            .ok_or(error::anyhow!("no block at height {height}"))?;
+        from_client(&header.block_hash())
+    }
+
+    /// The peers do not tell us if the output is spent, so an output
+    /// that exists is always returned.
+    fn get_output_by_scid(
+        &self,
+        height: u32,
+        tx_index: u32,
+        vout: u16,
+    ) -> error::Result<Option<TxOut>> {
+        let hash = self.get_block_hash_at(height)?;
+        let block = self.fetch_block(&hash)?;
+        let output = block
+            .txdata
+            .get(tx_index as usize)
+            .and_then(|tx| tx.output.get(vout as usize))
+            .cloned();
+        Ok(output)
+    }
+
+    fn get_utxo_by_txid(&self, txid: &Txid, _: &Script) -> error::Result<TxResult> {
+        error::bail!("the bip157 backend can not look for the transaction `{txid}`")
+    }
+
+    fn set_handler(&self, handler: Arc<dyn Handler>) {
+        *self.handler.lock_or_recover() = Some(handler);
+    }
+
+    fn manage_transactions(&self, txs: &mut Vec<Txid>) -> error::Result<()> {
+        self.txids.lock_or_recover().extend(txs.drain(..));
+        Ok(())
+    }
+
+    fn listen(self: Arc<Self>) -> error::Result<JoinHandle<()>> {
+        let handler = self.handler()?;
+        let events = self.client.events();
+        log::info!(target: "bip157", "Starting to follow the compact block filters ...");
+        Ok(std::thread::spawn(move || {
+            while let Ok(event) = events.recv() {
+                // the tip is notified when its filter is processed, so
+                // ldk never sees a transaction confirmed in a block
+                // above the best one.
+                let result = match event {
+                    ClientEvent::FilterProcessed {
+                        height,
+                        matched: false,
+                        ..
+                    } => self.connect_block(&handler, height as u32),
+                    ClientEvent::BlockMatched { hash, height, .. } => from_client(&hash)
+                        .and_then(|hash| self.process_matched(&handler, hash, height as u32)),
+                    ClientEvent::BlockDisconnected { hash, height, .. } => {
+                        log::warn!(target: "bip157", "block `{hash}` at height {height} disconnected");
+                        Ok(())
+                    }
+                    _ => Ok(()),
+                };
+                if let Err(err) = result {
+                    log::error!(target: "bip157", "{err}");
+                }
+            }
+            log::warn!(target: "bip157", "the light client stopped, no more blocks");
+        }))
+    }
+
+    fn get_transaction(&self, txid: &Txid) -> error::Result<TxResult> {
+        error::bail!("the bip157 backend can not look for the transaction `{txid}`")
+    }
+
+    /// The transactions are reported as confirmed when they are found
+    /// inside a block that matched the filters.
+    fn process_transactions(&self) -> error::Result<()> {
+        Ok(())
+    }
+}
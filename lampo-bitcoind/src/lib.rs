@@ -13,10 +13,10 @@ use bitcoincore_rpc::Client;
 use bitcoincore_rpc::RpcApi;
 
 use lampo_common::backend::{deserialize, serialize};
-use lampo_common::backend::{Backend, TxResult};
+use lampo_common::backend::{Backend, BroadcastError, TxResult};
 use lampo_common::backend::{Block, BlockData, BlockHash};
 use lampo_common::bitcoin::absolute::Height;
-use lampo_common::bitcoin::{Transaction, Txid};
+use lampo_common::bitcoin::{Transaction, TxOut, Txid};
 use lampo_common::error;
 use lampo_common::event::onchain::OnChainEvent;
 use lampo_common::event::Event;
@@ -25,6 +25,7 @@ use lampo_common::json;
 
 pub struct BitcoinCore {
     inner: Client,
+    url: String,
     handler: RefCell<Option<Arc<dyn Handler>>>,
     ours_txs: Mutex<RefCell<Vec<Txid>>>,
     others_txs: Mutex<RefCell<Vec<(Txid, ScriptBuf)>>>,
@@ -67,6 +68,7 @@ impl BitcoinCore {
         // FIXME: grab some information from the blockchain, eg. Network
         Ok(Self {
             inner: client,
+            url: url.to_owned(),
             handler: RefCell::new(None),
             ours_txs: Mutex::new(RefCell::new(Vec::new())),
             others_txs: Mutex::new(RefCell::new(Vec::new())),
@@ -110,6 +112,71 @@ impl BitcoinCore {
         Ok(block_hash)
     }
 
+    /// Build the error from the `reason` given by bitcoind when it
+    /// rejects `tx`, `None` when the transaction is already in the
+    /// mempool or in the chain.
+    fn broadcast_error(&self, tx: &Transaction, reason: &str) -> Option<BroadcastError> {
+        const KNOWN: [&str; 4] = [
+            "txn-already-in-mempool",
+            "txn-already-known",
+            "already in block chain",
+            "already in utxo set",
+        ];
+        if KNOWN.iter().any(|known| reason.contains(known)) {
+            return None;
+        }
+        let err = if reason.contains("min relay fee not met")
+            || reason.contains("mempool min fee not met")
+        {
+            match self.minimum_mempool_fee() {
+                Ok(min_fee_rate) => BroadcastError::FeeTooLow {
+                    fee_rate: None,
+                    min_fee_rate,
+                },
+                Err(_) => BroadcastError::Rejected(reason.to_owned()),
+            }
+        } else if reason.contains("txn-mempool-conflict")
+            || reason.contains("rejecting replacement")
+        {
+            BroadcastError::Conflict {
+                txid: self.find_conflict(tx),
+            }
+        } else if reason.contains("missingorspent")
+            || reason.to_lowercase().contains("missing inputs")
+        {
+            BroadcastError::MissingInputs
+        } else {
+            BroadcastError::Rejected(reason.to_owned())
+        };
+        Some(err)
+    }
+
+    /// Find the transaction in the mempool that spends one of the
+    /// inputs of `tx`.
+    fn find_conflict(&self, tx: &Transaction) -> Option<Txid> {
+        let outputs = tx
+            .input
+            .iter()
+            .map(|input| {
+                json::json!({
+                    "txid": input.previous_output.txid.to_string(),
+                    "vout": input.previous_output.vout,
+                })
+            })
+            .collect::<Vec<_>>();
+        let spending: Vec<json::Value> = self
+            .inner
+            .call("gettxspendingprevout", &[outputs.into()])
+            .map_err(|err| {
+                log::debug!(target: "bitcoind", "impossible find the conflicting transaction: {err}");
+            })
+            .ok()?;
+        spending
+            .iter()
+            .filter_map(|output| output.get("spendingtxid")?.as_str())
+            .find_map(|txid| Txid::from_str(txid).ok())
+    }
+
     pub fn find_tx_in_block(&self, block: &Block) -> error::Result<()> {
         log::debug!(target: "bitcoin", "looking the tx inside the new block");
         let utxos = self.others_txs.lock().unwrap();
@@ -149,28 +216,44 @@ impl Backend for BitcoinCore {
         lampo_common::backend::BackendKind::Core
     }
 
-    fn brodcast_tx(&self, tx: &lampo_common::backend::Transaction) {
-        // FIXME: check the result.
+    fn endpoint(&self) -> Option<String> {
+        Some(self.url.clone())
+    }
+
+    fn brodcast_tx(
+        &self,
+        tx: &lampo_common::backend::Transaction,
+    ) -> Result<(), BroadcastError> {
         let result: bitcoincore_rpc::Result<json::Value> = self.inner.call(
             "sendrawtransaction",
             &[lampo_common::bitcoin::consensus::encode::serialize_hex(&tx).into()],
         );
         log::info!(target: "bitcoind", "broadcast transaction return {:?}", result);
-        if result.is_ok() {
-            self.ours_txs.lock().unwrap().borrow_mut().push(tx.txid());
-            self.others_txs
-                .lock()
-                .unwrap()
-                .borrow_mut()
-                .retain(|(txid, _)| txid.to_string() == tx.txid().to_string());
-            let handler = self.handler.borrow();
-            let Some(handler) = handler.as_ref() else {
-                return;
+        if let Err(err) = result {
+            let reason = match err {
+                bitcoincore_rpc::Error::JsonRpc(bitcoincore_rpc::jsonrpc::Error::Rpc(err)) => {
+                    err.message
+                }
+                err => err.to_string(),
             };
-            handler.emit(Event::OnChain(OnChainEvent::SendRawTransaction(tx.clone())));
-        } else {
-            log::error!(target: "bitcoind", "broadcast transaction return {:?}", result);
+            if let Some(err) = self.broadcast_error(tx, &reason) {
+                log::error!(target: "bitcoind", "broadcast of the transaction `{}` fails: {err}", tx.txid());
+                return Err(err);
+            }
+            log::info!(target: "bitcoind", "transaction `{}` already known: {reason}", tx.txid());
         }
+        self.ours_txs.lock().unwrap().borrow_mut().push(tx.txid());
+        self.others_txs
+            .lock()
+            .unwrap()
+            .borrow_mut()
+            .retain(|(txid, _)| txid.to_string() == tx.txid().to_string());
+        let handler = self.handler.borrow();
+        let Some(handler) = handler.as_ref() else {
+            return Ok(());
+        };
+        handler.emit(Event::OnChain(OnChainEvent::SendRawTransaction(tx.clone())));
+        Ok(())
     }
 
     /// Returning the fee rate estimation in sats.
@@ -225,8 +308,12 @@ impl Backend for BitcoinCore {
 
         let fee: MinimumMempoolFee = self.inner.call("getmempoolinfo", &[])?;
         // FIXME: adds the trait for conversion from and to BTC
+        //
+        // bitcoin core returns BTC/kvB, and ldk wants sat/kW, so
+        // we multiply by 10^8 for the sats and divide by 4 for
+        // the weight.
         let fee = fee.mempoolminfee;
-        Ok((fee * 10000 as f32) as u32)
+        Ok((fee * 25_000_000.0).round() as u32)
     }
 
     fn get_best_block(&self) -> error::Result<(lampo_common::backend::BlockHash, Option<u32>)> {
@@ -238,6 +325,10 @@ impl Backend for BitcoinCore {
         Ok((hash, Some(block.blocks as u32)))
     }
 
+    fn get_block_hash_at(&self, height: u32) -> error::Result<BlockHash> {
+        self.get_block_hash(height as u64)
+    }
+
     fn get_block(
         &self,
         header_hash: &lampo_common::backend::BlockHash,
@@ -262,18 +353,38 @@ impl Backend for BitcoinCore {
         unimplemented!("`get_header` is called only for lightway nodes");
     }
 
-    fn get_utxo(
+    fn get_output_by_scid(
         &self,
-        _block: &lampo_common::backend::BlockHash,
-        _idx: u64,
-    ) -> lampo_common::backend::UtxoResult {
-        unimplemented!()
+        height: u32,
+        tx_index: u32,
+        vout: u16,
+    ) -> error::Result<Option<TxOut>> {
+        let hash = self.get_block_hash(height as u64)?;
+        let BlockData::FullBlock(block) = self.get_block(&hash)? else {
+            unreachable!()
+        };
+        let Some(tx) = block.txdata.get(tx_index as usize) else {
+            return Ok(None);
+        };
+        let Some(output) = tx.output.get(vout as usize) else {
+            return Ok(None);
+        };
+        // `gettxout` returns null when the output is spent
+        let unspent: Option<GetTxOutResult> = self.inner.call(
+            "gettxout",
+            &[tx.txid().to_string().into(), vout.into(), false.into()],
+        )?;
+        Ok(unspent.map(|_| output.clone()))
     }
 
     fn is_lightway(&self) -> bool {
         false
     }
 
+    fn notify_new_blocks(&self) -> bool {
+        true
+    }
+
     fn register_output(
         &self,
         output: lampo_common::backend::WatchedOutput,
@@ -436,7 +547,27 @@ impl Backend for BitcoinCore {
                     continue;
                 };
 
-                if !self.others_txs.lock().unwrap().borrow().is_empty() {
+                // a reorg can replace our tip with a chain that is not longer,
+                // lampod walks back to the fork point when it sees the new tip.
+                let reorg = (height as u64) <= *self.best_height.borrow()
+                    && self
+                        .last_bloch_hash
+                        .borrow()
+                        .is_some_and(|hash| hash != block_hash);
+                if reorg {
+                    log::warn!(target: "bitcoind", "the tip changed to `{block_hash}` at height `{height}`, reorg detected");
+                    *self.best_height.borrow_mut() = height.into();
+                    *self.last_bloch_hash.borrow_mut() = Some(block_hash);
+                    if let Ok(BlockData::FullBlock(block)) = self.get_block(&block_hash) {
+                        handler.emit(Event::OnChain(OnChainEvent::NewBestBlock((
+                            block.header,
+                            // SAFETY: the height should be always a valid u32
+                            Height::from_consensus(height).unwrap(),
+                        ))));
+                        handler.emit(Event::OnChain(OnChainEvent::NewBlock(block.clone())));
+                        let _ = self.find_tx_in_block(&block);
+                    }
+                } else if !self.others_txs.lock().unwrap().borrow().is_empty() {
                     let start: u64 = self.best_height.borrow().clone().into();
                     let end: u64 = height.into();
                     log::trace!(target: "bitcoind", "Scan blocks in range [{start}..{end}]");
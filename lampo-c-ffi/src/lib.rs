@@ -219,9 +219,12 @@ pub extern "C" fn lampo_last_errror() -> *const libc::c_char {
 #[no_mangle]
 pub extern "C" fn add_jsonrpc_on_unixsocket(lampod: *mut LampoDaemon) -> i64 {
     use lampo_jsonrpc::JSONRPCv2;
+    use lampod::jsonrpc::auth::authenticate;
     use lampod::jsonrpc::inventory::get_info;
+    use lampod::jsonrpc::metrics::observe;
     use lampod::jsonrpc::open_channel::json_open_channel;
     use lampod::jsonrpc::peer_control::json_connect;
+    use lampod::jsonrpc::plugins::add_plugin_methods;
     use lampod::jsonrpc::CommandHandler;
 
     let Some(lampod) = as_rust!(lampod) else {
@@ -232,9 +235,12 @@ pub extern "C" fn add_jsonrpc_on_unixsocket(lampod: *mut LampoDaemon) -> i64 {
     let Ok(server) = JSONRPCv2::new(lampod.clone(), &socket_path) else {
         return -2;
     };
+    server.handler().set_auth(authenticate(lampod.rpc_auth()));
+    server.handler().set_observer(observe(lampod.metrics()));
     server.add_rpc("getinfo", get_info).unwrap();
     server.add_rpc("connect", json_connect).unwrap();
     server.add_rpc("fundchannel", json_open_channel).unwrap();
+    add_plugin_methods(&server.handler(), &lampod.plugins());
     let rpc_handler = server.handler();
     let Ok(lampo_handler) = CommandHandler::new(lampod.conf()) else {
         return -2;
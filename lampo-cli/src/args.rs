@@ -2,14 +2,22 @@ use std::collections::HashMap;
 
 use radicle_term as term;
 
+use lampo_common::amount;
 use lampo_common::error;
 use lampo_common::json;
 
 #[derive(Debug)]
 pub struct LampoCliArgs {
     pub socket: String,
+    /// The token of the request, without it the master token
+    /// next to the socket is used.
+    pub token: Option<String>,
     pub method: String,
     pub args: HashMap<String, json::Value>,
+    /// Print the JSON returned by the node instead of the tables.
+    pub json: bool,
+    /// Show the amounts of the tables in msat.
+    pub msat: bool,
 }
 
 struct Help {
@@ -28,11 +36,17 @@ Usage
 
     lampod-cli [<option> ...] <method> [arg=value]
 
+    The amounts can be written with their unit, e.g. `--amount_msat 0.01btc`,
+    `--amount 100k sats` or `--amount_msat 25000msat`.
+
 Options
 
     -d | --data-dir     Specify lampo data directory (used to get socket path)
     -n | --network      Set the network for lampo (default: testnet)
     -s | --socket       Specify Unix Socket patch of the lampod node directely
+    -t | --token        Specify the token of the request (default: the master token of the node)
+    --json              Print the JSON returned by the node (default: tables for the lists)
+    --msat              Show the amounts of the tables in msat (default: sats, or btc)
     -h | --help         Print help
 "#,
 };
@@ -43,8 +57,11 @@ pub fn parse_args() -> Result<LampoCliArgs, lexopt::Error> {
     let mut data_dir: Option<String> = None;
     let mut network: Option<String> = None;
     let mut socket: Option<String> = None;
+    let mut token: Option<String> = None;
     let mut method: Option<String> = None;
     let mut args = HashMap::<String, json::Value>::new();
+    let mut json = false;
+    let mut msat = false;
 
     let mut parser = lexopt::Parser::from_env();
     while let Some(arg) = parser.next()? {
@@ -61,6 +78,12 @@ pub fn parse_args() -> Result<LampoCliArgs, lexopt::Error> {
                 let val: String = parser.value()?.parse()?;
                 socket = Some(val);
             }
+            Short('t') | Long("token") => {
+                let val: String = parser.value()?.parse()?;
+                token = Some(val);
+            }
+            Long("json") => json = true,
+            Long("msat") => msat = true,
             Long("help") => {
                 let _ = print_help();
                 std::process::exit(0);
@@ -76,16 +99,9 @@ pub fn parse_args() -> Result<LampoCliArgs, lexopt::Error> {
                     Long(val) => {
                         let key = val.to_string();
                         let val: String = parser.value()?.parse()?;
-                        if let Ok(val) = val.parse::<u64>() {
-                            let val = json::json!(val);
-                            args.insert(key.clone(), val);
-                        } else if let Ok(val) = val.parse::<bool>() {
-                            let val = json::json!(val);
-                            args.insert(key.clone(), val);
-                        } else {
-                            let val = json::json!(val);
-                            args.insert(key, val);
-                        }
+                        // the method is checked above.
+                        let val = parse_value(method.as_ref().unwrap(), &key, val)?;
+                        args.insert(key, val);
                     }
                     _ => return Err(arg.unexpected()),
                 }
@@ -125,6 +141,7 @@ pub fn parse_args() -> Result<LampoCliArgs, lexopt::Error> {
         socket: socket.ok_or_else(|| lexopt::Error::MissingValue {
             option: Some("Socket path need to be specified".to_owned()),
         })?,
+        token,
         method: method.ok_or_else(|| lexopt::Error::MissingValue {
             option: Some(
                 "Too few params, a method need to be specified. Try run `lampo-cli --help`"
@@ -132,9 +149,49 @@ pub fn parse_args() -> Result<LampoCliArgs, lexopt::Error> {
             ),
         })?,
         args,
+        json,
+        msat,
     })
 }
 
+/// The msat in a unit of the amount `key` of `method`, `None` if
+/// `key` is not an amount.
+fn amount_unit(method: &str, key: &str) -> Option<u64> {
+    if key.ends_with("msat") {
+        Some(1)
+    } else if key.ends_with("_sat") || key == "satoshis" {
+        Some(amount::MSAT_PER_SAT)
+    } else if key == "amount" {
+        // the channel methods take the amount in sats, the
+        // payments in msat.
+        match method {
+            "fundchannel" | "estimateopencost" => Some(amount::MSAT_PER_SAT),
+            _ => Some(1),
+        }
+    } else {
+        None
+    }
+}
+
+fn parse_value(method: &str, key: &str, val: String) -> Result<json::Value, lexopt::Error> {
+    if let Ok(val) = val.parse::<u64>() {
+        return Ok(json::json!(val));
+    }
+    if let Ok(val) = val.parse::<bool>() {
+        return Ok(json::json!(val));
+    }
+    let Some(unit_msat) = amount_unit(method, key) else {
+        return Ok(json::json!(val));
+    };
+    let msat = amount::parse_msat(&val).map_err(|err| lexopt::Error::Custom(err.into()))?;
+    if msat % unit_msat != 0 {
+        return Err(lexopt::Error::Custom(
+            format!("`{key}` is in sats, `{val}` is not a whole number of sats").into(),
+        ));
+    }
+    Ok(json::json!(msat / unit_msat))
+}
+
 // Print helps
 pub fn print_help() -> error::Result<()> {
     println!(
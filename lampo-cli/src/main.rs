@@ -1,15 +1,19 @@
 mod args;
+mod table;
 
+use std::path::Path;
 use std::process::exit;
 
 use radicle_term as term;
 
 use lampo_client::errors::Error;
 use lampo_client::UnixClient;
+use lampo_common::auth::TOKEN_FILE;
 use lampo_common::error;
 use lampo_common::json;
 
 use crate::args::LampoCliArgs;
+use crate::table::Unit;
 
 fn main() -> error::Result<()> {
     let args = match args::parse_args() {
@@ -19,23 +23,66 @@ fn main() -> error::Result<()> {
             exit(1);
         }
     };
+    let method = args.method.clone();
+    let (json, unit) = match (args.json, args.msat) {
+        (true, _) => (true, Unit::Human),
+        (false, true) => (false, Unit::Msat),
+        (false, false) => (false, Unit::Human),
+    };
     let resp = run(args);
     match resp {
         Ok(resp) => {
-            term::print(json::to_string_pretty(&resp)?);
+            let tables = if json {
+                None
+            } else {
+                table::render(&method, &resp, unit)?
+            };
+            match tables {
+                Some(tables) => term::print(tables.trim_end()),
+                None => term::print(json::to_string_pretty(&resp)?),
+            }
         }
-        Err(Error::Rpc(rpc)) => {
+        Err(Error::Rpc(rpc)) if json => {
             term::print(json::to_string_pretty(&rpc)?);
+            exit(1);
+        }
+        Err(Error::Rpc(rpc)) => {
+            let category = rpc
+                .data
+                .as_ref()
+                .and_then(|data| data.get("category"))
+                .and_then(|category| category.as_str())
+                .unwrap_or("rpc");
+            term::error(format!("error {} ({category}): {}", rpc.code, rpc.message));
+            if let Some(hint) = error::hint(rpc.code) {
+                term::hint(hint);
+            }
+            exit(1);
         }
         Err(err) => {
             term::error(format!("{err}"));
+            exit(1);
         }
     }
     Ok(())
 }
 
-fn run(args: LampoCliArgs) -> Result<json::Value, lampo_client::errors::Error> {
+fn run(mut args: LampoCliArgs) -> Result<json::Value, lampo_client::errors::Error> {
     let client = UnixClient::new(&args.socket).unwrap();
+    if let Some(token) = token(&args) {
+        args.args.insert("token".to_owned(), json::json!(token));
+    }
     let resp = client.call(&args.method, args.args)?;
     Ok(resp)
 }
+
+/// The token given by the user, or the master token written by
+/// the node next to its socket.
+fn token(args: &LampoCliArgs) -> Option<String> {
+    if let Some(ref token) = args.token {
+        return Some(token.clone());
+    }
+    let path = Path::new(&args.socket).parent()?.join(TOKEN_FILE);
+    let token = std::fs::read_to_string(path).ok()?;
+    Some(token.trim().to_owned())
+}
@@ -0,0 +1,329 @@
+//! Tables for the human output of the lists of the node.
+use lampo_common::amount::format_msat;
+use lampo_common::error;
+use lampo_common::json;
+use lampo_common::model::request::ChannelIdentifier;
+use lampo_common::model::response::{
+    Channel, Channels, ListConfigs, ListFunds, PayStatus, Pays, Peers,
+};
+
+/// How the amounts are shown.
+#[derive(Clone, Copy, Debug)]
+pub enum Unit {
+    /// In sats, or in btc from one bitcoin on.
+    Human,
+    /// The msat as they are returned by the node.
+    Msat,
+}
+
+impl Unit {
+    fn amount(&self, msat: u64) -> String {
+        match self {
+            Self::Human => format_msat(msat),
+            Self::Msat => format!("{msat} msat"),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Align {
+    Left,
+    Right,
+}
+
+/// A table where every column is as wide as its widest cell.
+struct Table {
+    columns: Vec<(&'static str, Align)>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    fn new(columns: &[(&'static str, Align)]) -> Self {
+        Self {
+            columns: columns.to_vec(),
+            rows: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, row: Vec<String>) {
+        debug_assert_eq!(row.len(), self.columns.len());
+        self.rows.push(row);
+    }
+
+    fn render(&self) -> String {
+        let header = self
+            .columns
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .collect::<Vec<_>>();
+        let widths = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(index, (name, _))| {
+                self.rows
+                    .iter()
+                    .map(|row| row[index].chars().count())
+                    .fold(name.len(), usize::max)
+            })
+            .collect::<Vec<_>>();
+
+        let mut out = String::new();
+        for row in std::iter::once(&header).chain(self.rows.iter()) {
+            let line = row
+                .iter()
+                .zip(&self.columns)
+                .zip(&widths)
+                .map(|((cell, (_, align)), width)| match align {
+                    Align::Left => format!("{cell:<width$}"),
+                    Align::Right => format!("{cell:>width$}"),
+                })
+                .collect::<Vec<_>>()
+                .join("  ");
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Render the response of `method` as tables, `None` if the method
+/// has not a human output and the JSON must be printed.
+pub fn render(method: &str, resp: &json::Value, unit: Unit) -> error::Result<Option<String>> {
+    let out = match method {
+        "channels" | "listchannels" => channels(&json::from_value(resp.clone())?, unit),
+        "listpeers" => peers(&json::from_value(resp.clone())?),
+        "listfunds" => funds(&json::from_value(resp.clone())?, unit)?,
+        "listconfigs" => configs(&json::from_value(resp.clone())?),
+        "listpays" => pays(&json::from_value(resp.clone())?, unit),
+        _ => return Ok(None),
+    };
+    Ok(Some(out))
+}
+
+fn scid(scid: Option<u64>) -> String {
+    scid.map(|scid| ChannelIdentifier::ShortChannelId(scid).to_string())
+        .unwrap_or_else(|| "-".to_owned())
+}
+
+fn channel_state(channel: &Channel) -> &'static str {
+    if channel.pending_close.is_some() {
+        "closing"
+    } else if !channel.ready {
+        "pending"
+    } else if channel.usable {
+        "active"
+    } else {
+        "inactive"
+    }
+}
+
+fn channels(channels: &Channels, unit: Unit) -> String {
+    let mut table = Table::new(&[
+        ("SCID", Align::Left),
+        ("PEER", Align::Left),
+        ("STATE", Align::Left),
+        ("CAPACITY", Align::Right),
+        ("SEND", Align::Right),
+        ("RECEIVE", Align::Right),
+    ]);
+    for channel in &channels.channels {
+        table.push(vec![
+            scid(channel.short_channel_id),
+            channel
+                .peer_alias
+                .clone()
+                .unwrap_or_else(|| channel.peer_id.clone()),
+            channel_state(channel).to_owned(),
+            unit.amount(channel.amount_msat),
+            unit.amount(channel.available_balance_for_send_msat),
+            unit.amount(channel.available_balance_for_recv_msat),
+        ]);
+    }
+    table.render()
+}
+
+fn peers(peers: &Peers) -> String {
+    let mut table = Table::new(&[
+        ("NODE ID", Align::Left),
+        ("ALIAS", Align::Left),
+        ("ADDRESS", Align::Left),
+        ("DIRECTION", Align::Left),
+        ("CONNECTED", Align::Left),
+        ("CHANNELS", Align::Right),
+    ]);
+    for peer in &peers.peers {
+        table.push(vec![
+            peer.node_id.clone(),
+            peer.alias.clone().unwrap_or_else(|| "-".to_owned()),
+            peer.address.clone().unwrap_or_else(|| "-".to_owned()),
+            if peer.inbound { "in" } else { "out" }.to_owned(),
+            if peer.connected { "yes" } else { "no" }.to_owned(),
+            peer.channels.to_string(),
+        ]);
+    }
+    table.render()
+}
+
+fn funds(funds: &ListFunds, unit: Unit) -> error::Result<String> {
+    let mut outputs = Table::new(&[
+        ("OUTPUT", Align::Left),
+        ("AMOUNT", Align::Right),
+        ("CONFIRMATIONS", Align::Right),
+        ("RESERVED", Align::Left),
+    ]);
+    for utxo in &funds.outputs {
+        outputs.push(vec![
+            format!("{}:{}", utxo.txid, utxo.vout),
+            unit.amount(utxo.amount_msat.msat()),
+            utxo.confirmed.to_string(),
+            if utxo.reserved { "yes" } else { "no" }.to_owned(),
+        ]);
+    }
+    let mut channels = Table::new(&[
+        ("SCID", Align::Left),
+        ("PEER", Align::Left),
+        ("STATE", Align::Left),
+        ("OURS", Align::Right),
+        ("CAPACITY", Align::Right),
+    ]);
+    for channel in &funds.channels {
+        channels.push(vec![
+            scid(channel.short_channel_id),
+            channel.peer_id.clone(),
+            channel.state.clone(),
+            unit.amount(channel.our_amount_msat.msat()),
+            unit.amount(channel.amount_msat.msat()),
+        ]);
+    }
+    Ok(format!(
+        "{}\n{}\non-chain: {}\nchannels: {}\n",
+        outputs.render(),
+        channels.render(),
+        unit.amount(funds.total_onchain_sat.to_msat()?.msat()),
+        unit.amount(funds.total_channel_sat.to_msat()?.msat()),
+    ))
+}
+
+fn configs(configs: &ListConfigs) -> String {
+    let mut table = Table::new(&[
+        ("OPTION", Align::Left),
+        ("VALUE", Align::Left),
+        ("SOURCE", Align::Left),
+    ]);
+    for config in &configs.configs {
+        let value = if config.values.is_empty() {
+            "-".to_owned()
+        } else {
+            config.values.join(", ")
+        };
+        table.push(vec![config.name.clone(), value, config.source.clone()]);
+    }
+    table.render()
+}
+
+fn pays(pays: &Pays, unit: Unit) -> String {
+    let mut table = Table::new(&[
+        ("PAYMENT HASH", Align::Left),
+        ("STATUS", Align::Left),
+        ("AMOUNT", Align::Right),
+        ("FEE", Align::Right),
+    ]);
+    for pay in &pays.pays {
+        let status = match pay.status {
+            PayStatus::Pending => "pending",
+            PayStatus::Complete => "complete",
+            PayStatus::Failed => "failed",
+        };
+        let fee = pay
+            .amount_sent_msat
+            .zip(pay.amount_msat)
+            .map(|(sent, amount)| unit.amount(sent.saturating_sub(amount)));
+        table.push(vec![
+            pay.payment_hash.clone().unwrap_or_else(|| "-".to_owned()),
+            status.to_owned(),
+            pay.amount_msat
+                .map(|amount| unit.amount(amount))
+                .unwrap_or_else(|| "-".to_owned()),
+            fee.unwrap_or_else(|| "-".to_owned()),
+        ]);
+    }
+    table.render()
+}
+
+#[cfg(test)]
+mod tests {
+    use lampo_common::json;
+
+    use super::{render, Unit};
+
+    /// Render the response inside `golden/<name>.json` and compare
+    /// it with `golden/<name>.txt`.
+    fn golden(method: &str, resp: &str, unit: Unit, expected: &str) {
+        let resp: json::Value = json::from_str(resp).unwrap();
+        let out = render(method, &resp, unit).unwrap().unwrap();
+        assert_eq!(out, expected, "\n{out}");
+    }
+
+    #[test]
+    fn render_channels() {
+        golden(
+            "channels",
+            include_str!("../tests/golden/channels.json"),
+            Unit::Human,
+            include_str!("../tests/golden/channels.txt"),
+        );
+        golden(
+            "channels",
+            include_str!("../tests/golden/channels.json"),
+            Unit::Msat,
+            include_str!("../tests/golden/channels_msat.txt"),
+        );
+    }
+
+    #[test]
+    fn render_peers() {
+        golden(
+            "listpeers",
+            include_str!("../tests/golden/listpeers.json"),
+            Unit::Human,
+            include_str!("../tests/golden/listpeers.txt"),
+        );
+    }
+
+    #[test]
+    fn render_funds() {
+        golden(
+            "listfunds",
+            include_str!("../tests/golden/listfunds.json"),
+            Unit::Human,
+            include_str!("../tests/golden/listfunds.txt"),
+        );
+    }
+
+    #[test]
+    fn render_configs() {
+        golden(
+            "listconfigs",
+            include_str!("../tests/golden/listconfigs.json"),
+            Unit::Human,
+            include_str!("../tests/golden/listconfigs.txt"),
+        );
+    }
+
+    #[test]
+    fn render_pays() {
+        golden(
+            "listpays",
+            include_str!("../tests/golden/listpays.json"),
+            Unit::Human,
+            include_str!("../tests/golden/listpays.txt"),
+        );
+    }
+
+    #[test]
+    fn other_methods_are_json() {
+        let resp = json::json!({ "node_id": "02aa" });
+        assert!(render("getinfo", &resp, Unit::Human).unwrap().is_none());
+    }
+}
@@ -0,0 +1,132 @@
+//! Amounts written by the user.
+//!
+//! Lampo counts in millisatoshis, but the user writes the amounts in
+//! the unit that is more natural for them, e.g. `0.01btc`, `100k sats`
+//! or `25000msat`.
+use crate::error::LampoError;
+
+pub const MSAT_PER_SAT: u64 = 1_000;
+pub const SAT_PER_BTC: u64 = 100_000_000;
+pub const MSAT_PER_BTC: u64 = MSAT_PER_SAT * SAT_PER_BTC;
+
+/// The units accepted by `parse_msat`, with their value in msat. The
+/// longer suffixes come first, so `msat` is not read as `sat`.
+const UNITS: [(&str, u64); 6] = [
+    ("msats", 1),
+    ("msat", 1),
+    ("sats", MSAT_PER_SAT),
+    ("sat", MSAT_PER_SAT),
+    ("btc", MSAT_PER_BTC),
+    ("bitcoin", MSAT_PER_BTC),
+];
+
+/// Parse an amount with its unit into millisatoshis.
+///
+/// The unit is one of `btc`, `sat` (or `sats`) and `msat`, and it is
+/// mandatory because a bare number is ambiguous. The number can have
+/// decimals and a `k` suffix for the thousands, but the amount must be
+/// a whole number of millisatoshis.
+pub fn parse_msat(amount: &str) -> Result<u64, LampoError> {
+    let invalid = |reason: &str| LampoError::InvalidParams(format!("amount `{amount}` {reason}"));
+
+    let lowercase = amount.trim().to_lowercase();
+    let Some((number, unit_msat)) = UNITS.iter().find_map(|(unit, msat)| {
+        lowercase
+            .strip_suffix(unit)
+            .map(|number| (number.trim_end(), *msat))
+    }) else {
+        return Err(invalid("without a unit, use `btc`, `sat` or `msat`"));
+    };
+    let (number, multiplier) = match number.strip_suffix('k') {
+        Some(number) => (number, 1_000),
+        None => (number, 1),
+    };
+
+    let (integer, decimals) = number.split_once('.').unwrap_or((number, ""));
+    let digits = format!("{integer}{decimals}");
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid("is not a number"));
+    }
+    // the number is `digits / 10^decimals`, kept as an integer to not
+    // lose precision with the floats.
+    let digits: u128 = digits.parse().map_err(|_| invalid("is too big"))?;
+    let scale = 10u128
+        .checked_pow(decimals.len() as u32)
+        .ok_or_else(|| invalid("has too many decimals"))?;
+    let msat = digits
+        .checked_mul(unit_msat as u128 * multiplier)
+        .ok_or_else(|| invalid("is too big"))?;
+    if msat % scale != 0 {
+        return Err(invalid("is smaller than a millisatoshi"));
+    }
+    u64::try_from(msat / scale).map_err(|_| invalid("is too big"))
+}
+
+/// Format millisatoshis for a human, in btc from one bitcoin on and
+/// in sats below it.
+pub fn format_msat(msat: u64) -> String {
+    if msat >= MSAT_PER_BTC {
+        let sat = msat / MSAT_PER_SAT;
+        let btc = format_decimal(sat / SAT_PER_BTC, sat % SAT_PER_BTC, 8);
+        return format!("{btc} btc");
+    }
+    let sat = format_decimal(msat / MSAT_PER_SAT, msat % MSAT_PER_SAT, 3);
+    format!("{sat} sat")
+}
+
+/// `integer.fraction`, without the zeros at the end of the fraction.
+fn format_decimal(integer: u64, fraction: u64, width: usize) -> String {
+    if fraction == 0 {
+        return integer.to_string();
+    }
+    let fraction = format!("{fraction:0width$}");
+    format!("{integer}.{}", fraction.trim_end_matches('0'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_msat, parse_msat};
+
+    #[test]
+    fn parse_amounts() {
+        assert_eq!(parse_msat("0.01btc").unwrap(), 1_000_000_000);
+        assert_eq!(parse_msat("1 BTC").unwrap(), 100_000_000_000);
+        assert_eq!(parse_msat("100k sats").unwrap(), 100_000_000);
+        assert_eq!(parse_msat("100ksat").unwrap(), 100_000_000);
+        assert_eq!(parse_msat("25000msat").unwrap(), 25_000);
+        assert_eq!(parse_msat("1.5k msat").unwrap(), 1_500);
+        assert_eq!(parse_msat("0.001 sat").unwrap(), 1);
+        assert_eq!(parse_msat(".5sat").unwrap(), 500);
+    }
+
+    #[test]
+    fn reject_invalid_amounts() {
+        for amount in [
+            "100",
+            "100k",
+            "btc",
+            "1.2.3btc",
+            "-1sat",
+            "1e3sat",
+            "0.0001sat",
+        ] {
+            assert!(parse_msat(amount).is_err(), "{amount}");
+        }
+        assert!(parse_msat("21000000000btc").is_err());
+        let err = parse_msat("100").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid params: amount `100` without a unit, use `btc`, `sat` or `msat`"
+        );
+    }
+
+    #[test]
+    fn format_amounts() {
+        assert_eq!(format_msat(0), "0 sat");
+        assert_eq!(format_msat(25_000), "25 sat");
+        assert_eq!(format_msat(25_500), "25.5 sat");
+        assert_eq!(format_msat(99_999_999_999), "99999999.999 sat");
+        assert_eq!(format_msat(100_000_000_000), "1 btc");
+        assert_eq!(format_msat(150_000_001_000), "1.50000001 btc");
+    }
+}
@@ -0,0 +1,378 @@
+//! Authentication of the JSON RPC 2.0 interface.
+//!
+//! Every request of a client carries a `token` with the format
+//! `<id>:<scope>:<mac>`, where the mac is the HMAC-SHA256 of
+//! `<id>:<scope>` with the secret of the node. So a token is
+//! verified without storing it, the node only keeps the ids of
+//! the revoked tokens.
+//!
+//! The master token has the id `0` and the `admin` scope, it is
+//! written inside the lampo directory at the first start.
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::{Hash, HashEngine};
+use serde::{Deserialize, Serialize};
+
+use crate::error;
+use crate::error::LampoError;
+use crate::json;
+use crate::sync::MutexExt;
+
+/// File with the secret used to sign the tokens.
+pub const SECRET_FILE: &str = "rpc_secret";
+/// File with the master token, read by `lampo-cli`.
+pub const TOKEN_FILE: &str = "rpc_token";
+/// File with the state of the minted tokens.
+const STATE_FILE: &str = "rpc_tokens.json";
+
+/// The id of the master token.
+const MASTER_ID: u64 = 0;
+
+/// Methods allowed by a `readonly` token.
+pub const READ_ONLY_METHODS: [&str; 32] = [
+    "getinfo",
+    "listpeers",
+    "listnodes",
+    "listgossipchannels",
+    "channels",
+    "getchannel",
+    "listhtlcs",
+    "autofees-status",
+    "funds",
+    "listfunds",
+    "listtransactions",
+    "isinvoicepaid",
+    "listinvoices",
+    "listpays",
+    "decode",
+    "checkmessage",
+    "fees",
+    "feerates",
+    "getchaininfo",
+    "pendingbumps",
+    "pendingbroadcasts",
+    "waitevent",
+    "listwebhookdeliveries",
+    "getmetrics",
+    "health",
+    "listplugins",
+    "listdatastore",
+    "listconfigs",
+    "listtowers",
+    "towerstatus",
+    "towerinfo",
+    "listtowerclients",
+];
+
+/// Methods allowed by an `invoice` token, on top of the read only ones.
+const INVOICE_METHODS: [&str; 7] = [
+    "invoice",
+    "holdinvoice",
+    "offer",
+    "getbip21uri",
+    "unifiedreceive",
+    "datastore",
+    "deldatastore",
+];
+
+/// Methods allowed by a `nowithdraw` token, on top of the invoice ones.
+/// The funds that they move stay inside the node, `close` only
+/// without a `destination`.
+const NO_WITHDRAW_METHODS: [&str; 10] = [
+    "connect",
+    "disconnect",
+    "ping",
+    "newaddr",
+    "estimateopencost",
+    "fundchannel",
+    "close",
+    "settleinvoice",
+    "cancelinvoice",
+    "cancelpayment",
+];
+
+/// What a token is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// Every method.
+    Admin,
+    /// Only the methods that do not change the node.
+    ReadOnly,
+    /// The read only methods and the creation of invoices.
+    Invoice,
+    /// The invoice methods and the ones that keep the funds inside the
+    /// node, every other method is refused.
+    NoWithdraw,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Admin => "admin",
+            Self::ReadOnly => "readonly",
+            Self::Invoice => "invoice",
+            Self::NoWithdraw => "nowithdraw",
+        }
+    }
+
+    /// Whether the scope allows to call `method` with `params`, the
+    /// unknown methods (e.g. the ones of the plugins) are allowed only
+    /// to the `admin` scope.
+    pub fn allows(&self, method: &str, params: &json::Value) -> bool {
+        match self {
+            Self::Admin => true,
+            Self::ReadOnly => READ_ONLY_METHODS.contains(&method),
+            Self::Invoice => {
+                READ_ONLY_METHODS.contains(&method) || INVOICE_METHODS.contains(&method)
+            }
+            Self::NoWithdraw => {
+                // a close to an address outside the wallet is a withdraw
+                let destination = params
+                    .get("destination")
+                    .map_or(false, |dest| !dest.is_null());
+                if method == "close" && destination {
+                    return false;
+                }
+                READ_ONLY_METHODS.contains(&method)
+                    || INVOICE_METHODS.contains(&method)
+                    || NO_WITHDRAW_METHODS.contains(&method)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Self::Admin),
+            "readonly" => Ok(Self::ReadOnly),
+            "invoice" => Ok(Self::Invoice),
+            "nowithdraw" => Ok(Self::NoWithdraw),
+            _ => error::bail!(
+                "unknown scope `{s}`, expected one of `admin`, `readonly`, `invoice` or `nowithdraw`"
+            ),
+        }
+    }
+}
+
+/// The tokens minted and revoked by the node.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AuthState {
+    next_id: u64,
+    revoked: BTreeSet<u64>,
+}
+
+/// The authenticator of the JSON RPC 2.0 requests.
+pub struct RpcAuth {
+    secret: [u8; 32],
+    state_path: PathBuf,
+    state: Mutex<AuthState>,
+}
+
+impl RpcAuth {
+    /// Load the secret from `path`, or create it with `entropy` at the
+    /// first start, and write the master token.
+    pub fn load_or_create(path: &str, entropy: [u8; 32]) -> error::Result<Self> {
+        let path = Path::new(path);
+        let secret_path = path.join(SECRET_FILE);
+        let secret = if secret_path.exists() {
+            let secret = fs::read_to_string(&secret_path)?;
+            let secret = hex::decode(secret.trim())
+                .map_err(|err| error::anyhow!("malformed `{SECRET_FILE}`: {err}"))?;
+            <[u8; 32]>::try_from(secret.as_slice())
+                .map_err(|_| error::anyhow!("malformed `{SECRET_FILE}`: expected 32 bytes"))?
+        } else {
+            write_private(&secret_path, &hex::encode(entropy))?;
+            entropy
+        };
+
+        let state_path = path.join(STATE_FILE);
+        let state = if state_path.exists() {
+            json::from_str(&fs::read_to_string(&state_path)?)?
+        } else {
+            AuthState {
+                next_id: MASTER_ID + 1,
+                ..Default::default()
+            }
+        };
+        let auth = Self {
+            secret,
+            state_path,
+            state: Mutex::new(state),
+        };
+        write_private(&path.join(TOKEN_FILE), &auth.master_token())?;
+        Ok(auth)
+    }
+
+    pub fn master_token(&self) -> String {
+        self.sign(MASTER_ID, Scope::Admin)
+    }
+
+    /// Mint a new token with the `scope`.
+    pub fn mint(&self, scope: Scope) -> error::Result<(u64, String)> {
+        let mut state = self.state.lock_or_recover();
+        let id = state.next_id;
+        state.next_id += 1;
+        self.store(&state)?;
+        Ok((id, self.sign(id, scope)))
+    }
+
+    /// Revoke the token with the `id`, the master token can not be revoked.
+    pub fn revoke(&self, id: u64) -> error::Result<()> {
+        if id == MASTER_ID {
+            return Err(LampoError::InvalidParams(
+                "the master token can not be revoked".to_owned(),
+            )
+            .into());
+        }
+        let mut state = self.state.lock_or_recover();
+        if id >= state.next_id {
+            return Err(LampoError::InvalidParams(format!("token `{id}` was never minted")).into());
+        }
+        state.revoked.insert(id);
+        self.store(&state)
+    }
+
+    /// Verify that the `token` allows to call the `method` with `params`.
+    pub fn verify(
+        &self,
+        token: &str,
+        method: &str,
+        params: &json::Value,
+    ) -> Result<Scope, LampoError> {
+        let invalid = || LampoError::Unauthorized("invalid token".to_owned());
+        let mut parts = token.splitn(3, ':');
+        let (Some(id), Some(scope), Some(mac)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid());
+        };
+        let id = u64::from_str(id).map_err(|_| invalid())?;
+        let scope = Scope::from_str(scope).map_err(|_| invalid())?;
+        let mac = hex::decode(mac).map_err(|_| invalid())?;
+        if !constant_time_eq(&mac, &self.mac(id, scope)) {
+            return Err(invalid());
+        }
+        if self.state.lock_or_recover().revoked.contains(&id) {
+            return Err(LampoError::Unauthorized(format!("token `{id}` is revoked")));
+        }
+        if !scope.allows(method, params) {
+            return Err(LampoError::PermissionDenied {
+                method: method.to_owned(),
+                scope: scope.to_string(),
+            });
+        }
+        Ok(scope)
+    }
+
+    fn mac(&self, id: u64, scope: Scope) -> [u8; 32] {
+        let mut engine = HmacEngine::<sha256::Hash>::new(&self.secret);
+        engine.input(format!("{id}:{scope}").as_bytes());
+        Hmac::<sha256::Hash>::from_engine(engine).to_byte_array()
+    }
+
+    fn sign(&self, id: u64, scope: Scope) -> String {
+        format!("{id}:{scope}:{}", hex::encode(self.mac(id, scope)))
+    }
+
+    fn store(&self, state: &AuthState) -> error::Result<()> {
+        write_private(&self.state_path, &json::to_string(state)?)
+    }
+}
+
+/// Write the `content` inside a file readable only by the user.
+fn write_private(path: &Path, content: &str) -> error::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(content.as_bytes())?;
+    Ok(())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_only_token() {
+        let path = std::env::temp_dir().join(format!("lampo-auth-{}", std::process::id()));
+        fs::create_dir_all(&path).unwrap();
+        let auth = RpcAuth::load_or_create(path.to_str().unwrap(), [7; 32]).unwrap();
+
+        let none = json::json!({});
+        let (id, token) = auth.mint(Scope::ReadOnly).unwrap();
+        assert_eq!(auth.verify(&token, "getinfo", &none), Ok(Scope::ReadOnly));
+        assert_eq!(
+            auth.verify(&token, "pay", &none).unwrap_err().code(),
+            error::PERMISSION_DENIED
+        );
+
+        let forged = token.replace("readonly", "admin");
+        assert_eq!(
+            auth.verify(&forged, "pay", &none).unwrap_err().code(),
+            error::UNAUTHORIZED
+        );
+
+        auth.revoke(id).unwrap();
+        assert_eq!(
+            auth.verify(&token, "getinfo", &none).unwrap_err().code(),
+            error::UNAUTHORIZED
+        );
+        assert!(auth.verify(&auth.master_token(), "stop", &none).is_ok());
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn no_withdraw_token() {
+        let path = std::env::temp_dir().join(format!("lampo-auth-nw-{}", std::process::id()));
+        fs::create_dir_all(&path).unwrap();
+        let auth = RpcAuth::load_or_create(path.to_str().unwrap(), [7; 32]).unwrap();
+        let (_, token) = auth.mint(Scope::NoWithdraw).unwrap();
+        let denied = |method: &str, params: json::Value| {
+            let err = auth.verify(&token, method, &params).err();
+            err.map(|err| err.code()) == Some(error::PERMISSION_DENIED)
+        };
+
+        let close = json::json!({ "node_id": "02aa", "destination": null });
+        assert_eq!(auth.verify(&token, "close", &close), Ok(Scope::NoWithdraw));
+        let close = json::json!({ "node_id": "02aa", "destination": "bcrt1qxyz" });
+        assert!(denied("close", close));
+        for method in [
+            "withdraw",
+            "pay",
+            "keysend",
+            "setchannel",
+            "sendonionmessage",
+            "stop",
+        ] {
+            assert!(denied(method, json::json!({})), "`{method}` is allowed");
+        }
+        // the methods registered by a plugin
+        assert!(denied("plugin-method", json::json!({})));
+        assert!(auth.verify(&token, "invoice", &json::json!({})).is_ok());
+        assert!(auth.verify(&token, "getinfo", &json::json!({})).is_ok());
+        fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,233 @@
+//! Policy of the automatic fees of the channels.
+//!
+//! The proportional fee of a channel follows its liquidity: a channel
+//! almost drained on our side gets expensive, so less payments are
+//! routed through it, while a balanced one gets cheap. A channel that
+//! forwarded since the last evaluation is used at its fee, so it does
+//! not get cheaper.
+//!
+//! Every change of the fee is a channel update gossiped to the whole
+//! network, so the fee moves toward its target by at most
+//! `max_change_ppm` at every evaluation, and the changes smaller than
+//! `hysteresis_ppm` are skipped.
+use std::fmt;
+
+use crate::conf::LampoConf;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AutofeesPolicy {
+    pub min_ppm: u32,
+    pub max_ppm: u32,
+    pub max_change_ppm: u32,
+    pub hysteresis_ppm: u32,
+}
+
+/// Why the fee of a channel is changed or kept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeeReason {
+    /// The fee moves toward the target of the liquidity.
+    Liquidity,
+    /// The fee was outside of the bounds, e.g. set by hand.
+    OutOfBounds,
+    /// The channel forwarded at its fee, so it is not lowered.
+    Forwarding,
+    /// The change is smaller than `hysteresis_ppm`.
+    Hysteresis,
+    /// The fee is already the target.
+    OnTarget,
+}
+
+impl fmt::Display for FeeReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            Self::Liquidity => "liquidity",
+            Self::OutOfBounds => "out_of_bounds",
+            Self::Forwarding => "forwarding",
+            Self::Hysteresis => "hysteresis",
+            Self::OnTarget => "on_target",
+        };
+        write!(f, "{reason}")
+    }
+}
+
+/// What the policy decided for a channel.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeeDecision {
+    /// The fee asked by the liquidity of the channel.
+    pub target_ppm: u32,
+    /// The fee to set, `None` when the fee is kept.
+    pub new_ppm: Option<u32>,
+    pub reason: FeeReason,
+}
+
+impl AutofeesPolicy {
+    /// The policy configured by the user, `None` when `autofees` is
+    /// not enabled.
+    pub fn new(conf: &LampoConf) -> Option<Self> {
+        conf.autofees.then(|| Self {
+            min_ppm: conf.autofees_min_ppm,
+            max_ppm: conf.autofees_max_ppm,
+            max_change_ppm: conf.autofees_max_change_ppm,
+            hysteresis_ppm: conf.autofees_hysteresis_ppm,
+        })
+    }
+
+    /// The fee of a channel with `outbound_ratio` (from 0 to 1) of its
+    /// capacity on our side.
+    ///
+    /// A channel with at least half of the capacity on our side has
+    /// the minimum fee, below half the fee grows linearly up to the
+    /// maximum of a drained channel.
+    pub fn target_ppm(&self, outbound_ratio: f64) -> u32 {
+        let drained = (1.0 - outbound_ratio.clamp(0.0, 1.0) * 2.0).max(0.0);
+        let range = (self.max_ppm - self.min_ppm) as f64;
+        self.min_ppm + (range * drained).round() as u32
+    }
+
+    /// Decide the fee of a channel with the fee `current_ppm`, where
+    /// `forwarded_msat` were forwarded since the last evaluation.
+    pub fn decide(
+        &self,
+        current_ppm: u32,
+        outbound_ratio: f64,
+        forwarded_msat: u64,
+    ) -> FeeDecision {
+        let target_ppm = self.target_ppm(outbound_ratio);
+        let in_bounds = (self.min_ppm..=self.max_ppm).contains(&current_ppm);
+        let (goal, mut reason) = if forwarded_msat > 0 && target_ppm < current_ppm {
+            (current_ppm, FeeReason::Forwarding)
+        } else {
+            (target_ppm, FeeReason::Liquidity)
+        };
+        let step = if goal > current_ppm {
+            current_ppm.saturating_add(self.max_change_ppm).min(goal)
+        } else {
+            current_ppm.saturating_sub(self.max_change_ppm).max(goal)
+        };
+        // the bounds win over the change per evaluation
+        let new_ppm = step.clamp(self.min_ppm, self.max_ppm);
+        if !in_bounds {
+            reason = FeeReason::OutOfBounds;
+        }
+        let new_ppm = if new_ppm == current_ppm {
+            if reason == FeeReason::Liquidity {
+                reason = FeeReason::OnTarget;
+            }
+            None
+        } else if in_bounds && new_ppm.abs_diff(current_ppm) < self.hysteresis_ppm {
+            reason = FeeReason::Hysteresis;
+            None
+        } else {
+            Some(new_ppm)
+        };
+        FeeDecision {
+            target_ppm,
+            new_ppm,
+            reason,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> AutofeesPolicy {
+        AutofeesPolicy {
+            min_ppm: 10,
+            max_ppm: 1_010,
+            max_change_ppm: 100,
+            hysteresis_ppm: 20,
+        }
+    }
+
+    #[test]
+    fn target_follows_the_liquidity() {
+        let policy = policy();
+        assert_eq!(policy.target_ppm(0.0), 1_010);
+        assert_eq!(policy.target_ppm(0.25), 510);
+        assert_eq!(policy.target_ppm(0.5), 10);
+        assert_eq!(policy.target_ppm(0.9), 10);
+        // the ratio is clamped
+        assert_eq!(policy.target_ppm(-1.0), 1_010);
+        assert_eq!(policy.target_ppm(2.0), 10);
+    }
+
+    #[test]
+    fn targets_respect_the_bounds() {
+        let policy = policy();
+        for current_ppm in [0, 5, 10, 500, 1_010, 5_000, u32::MAX] {
+            for outbound_ratio in [0.0, 0.1, 0.3, 0.5, 1.0] {
+                for forwarded_msat in [0, 1_000] {
+                    let decision = policy.decide(current_ppm, outbound_ratio, forwarded_msat);
+                    assert!(
+                        (policy.min_ppm..=policy.max_ppm).contains(&decision.target_ppm),
+                        "{decision:?}"
+                    );
+                    if let Some(new_ppm) = decision.new_ppm {
+                        assert!(
+                            (policy.min_ppm..=policy.max_ppm).contains(&new_ppm),
+                            "{decision:?}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fee_moves_by_the_max_change() {
+        let policy = policy();
+        // a drained channel
+        let decision = policy.decide(10, 0.0, 0);
+        assert_eq!(decision.target_ppm, 1_010);
+        assert_eq!(decision.new_ppm, Some(110));
+        assert_eq!(decision.reason, FeeReason::Liquidity);
+        // a balanced channel
+        let decision = policy.decide(1_010, 0.5, 0);
+        assert_eq!(decision.new_ppm, Some(910));
+        // the last step stops on the target
+        let decision = policy.decide(950, 0.0, 0);
+        assert_eq!(decision.new_ppm, Some(1_010));
+    }
+
+    #[test]
+    fn small_changes_are_skipped() {
+        let policy = policy();
+        // the target is 510, 15ppm away
+        let decision = policy.decide(495, 0.25, 0);
+        assert_eq!(decision.target_ppm, 510);
+        assert_eq!(decision.new_ppm, None);
+        assert_eq!(decision.reason, FeeReason::Hysteresis);
+        let decision = policy.decide(490, 0.25, 0);
+        assert_eq!(decision.new_ppm, Some(510));
+        let decision = policy.decide(510, 0.25, 0);
+        assert_eq!(decision.new_ppm, None);
+        assert_eq!(decision.reason, FeeReason::OnTarget);
+    }
+
+    #[test]
+    fn fee_outside_the_bounds_goes_back() {
+        let policy = policy();
+        // the hysteresis and the max change do not hold it outside
+        let decision = policy.decide(5, 0.5, 0);
+        assert_eq!(decision.new_ppm, Some(10));
+        assert_eq!(decision.reason, FeeReason::OutOfBounds);
+        let decision = policy.decide(5_000, 0.0, 0);
+        assert_eq!(decision.new_ppm, Some(1_010));
+        assert_eq!(decision.reason, FeeReason::OutOfBounds);
+    }
+
+    #[test]
+    fn forwarding_channel_is_not_lowered() {
+        let policy = policy();
+        let decision = policy.decide(700, 0.5, 1_000);
+        assert_eq!(decision.target_ppm, 10);
+        assert_eq!(decision.new_ppm, None);
+        assert_eq!(decision.reason, FeeReason::Forwarding);
+        // but it is raised when it gets drained
+        let decision = policy.decide(700, 0.0, 1_000);
+        assert_eq!(decision.new_ppm, Some(800));
+        assert_eq!(decision.reason, FeeReason::Liquidity);
+    }
+}
@@ -8,11 +8,11 @@ use bitcoin::absolute::Height;
 use bitcoin::block::Header as BlockHeader;
 
 pub use bitcoin::consensus::{deserialize, serialize};
-pub use bitcoin::{Block, BlockHash, Script, Transaction, Txid};
+pub use bitcoin::{Block, BlockHash, Script, ScriptBuf, Transaction, TxOut, Txid};
 pub use lightning::chain::WatchedOutput;
 pub use lightning::routing::utxo::UtxoResult;
 pub use lightning_block_sync::{
-    AsyncBlockSourceResult, BlockData, BlockHeaderData, BlockSourceResult,
+    AsyncBlockSourceResult, BlockData, BlockHeaderData, BlockSourceError, BlockSourceResult,
 };
 use serde::{Deserialize, Serialize};
 
@@ -26,26 +26,115 @@ pub enum TxResult {
     Discarded,
 }
 
+/// Error returned by the backend when a transaction is rejected,
+/// built from the reason given by the node.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BroadcastError {
+    /// The fee rate (in sat/kW) is below the minimum fee rate of the
+    /// mempool, the fee rate is known only when lampo can compute it.
+    FeeTooLow {
+        fee_rate: Option<u32>,
+        min_fee_rate: u32,
+    },
+    /// The inputs are unknown or already spent by a confirmed
+    /// transaction.
+    MissingInputs,
+    /// An input is already spent by a transaction in the mempool,
+    /// that is `txid` when the backend is able to find it.
+    Conflict { txid: Option<Txid> },
+    /// Any other reason given by the backend.
+    Rejected(String),
+}
+
+impl std::fmt::Display for BroadcastError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FeeTooLow {
+                fee_rate: Some(fee_rate),
+                min_fee_rate,
+            } => write!(
+                f,
+                "fee rate of `{fee_rate}` sat/kW is below the mempool minimum of `{min_fee_rate}` sat/kW"
+            ),
+            Self::FeeTooLow {
+                fee_rate: None,
+                min_fee_rate,
+            } => write!(
+                f,
+                "fee rate is below the mempool minimum of `{min_fee_rate}` sat/kW"
+            ),
+            Self::MissingInputs => write!(f, "the inputs are missing or already spent"),
+            Self::Conflict { txid: Some(txid) } => {
+                write!(f, "the transaction conflicts with `{txid}` in the mempool")
+            }
+            Self::Conflict { txid: None } => {
+                write!(f, "the transaction conflicts with a transaction in the mempool")
+            }
+            Self::Rejected(reason) => write!(f, "the transaction is rejected: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for BroadcastError {}
+
 /// Backend kind supported by the lampo
 pub enum BackendKind {
     Core,
     Nakamoto,
+    Bip157,
+}
+
+impl std::fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackendKind::Core => write!(f, "core"),
+            BackendKind::Nakamoto => write!(f, "nakamoto"),
+            BackendKind::Bip157 => write!(f, "bip157"),
+        }
+    }
 }
 
 /// Bakend Trait specification
-pub trait Backend {
+pub trait Backend: Send + Sync {
     /// Return the kind of backend
     fn kind(&self) -> BackendKind;
 
+    /// The url of the server used by the backend, if any.
+    fn endpoint(&self) -> Option<String> {
+        None
+    }
+
     /// Fetch feerate give a number of blocks
     fn fee_rate_estimation(&self, blocks: u64) -> error::Result<u32>;
 
     fn minimum_mempool_fee(&self) -> error::Result<u32>;
 
-    fn brodcast_tx(&self, tx: &Transaction);
+    /// Broadcast the transaction, a transaction already in the
+    /// mempool or in the chain is not an error.
+    fn brodcast_tx(&self, tx: &Transaction) -> Result<(), BroadcastError>;
 
     fn is_lightway(&self) -> bool;
 
+    /// Return true if the backend emits an `OnChainEvent::NewBestBlock`
+    /// as soon as it sees a new block, so the wallet can be synced
+    /// on the notification instead of polling.
+    fn notify_new_blocks(&self) -> bool {
+        false
+    }
+
+    /// Return true if the backend matches the compact block filters
+    /// (BIP 157/158) with the scripts watched, and emits an
+    /// `OnChainEvent::BlockMatched` for the blocks that match, so the
+    /// wallet is synced with these blocks instead of a server.
+    fn filter_blocks(&self) -> bool {
+        false
+    }
+
+    /// Watch the `scripts` inside the compact block filters, when
+    /// `from_height` is given the blocks from that height are scanned
+    /// again with all the scripts watched.
+    fn watch_scripts(&self, _scripts: Vec<ScriptBuf>, _from_height: Option<u32>) {}
+
     /// You must follow this step if: you are not providing full blocks to LDK, i.e. if you're using BIP 157/158 or Electrum as your chain backend
     ///
     /// What it's used for: if you are not providing full blocks, LDK uses this object to tell you what transactions and outputs to watch for on-chain.
@@ -66,7 +155,18 @@ pub trait Backend {
 
     fn get_best_block(&self) -> error::Result<(BlockHash, Option<u32>)>;
 
-    fn get_utxo(&self, block: &BlockHash, idx: u64) -> UtxoResult;
+    /// Return the hash of the block at `height` in the best chain.
+    fn get_block_hash_at(&self, height: u32) -> error::Result<BlockHash>;
+
+    /// Return the output at `vout` of the transaction at `tx_index`
+    /// inside the block at `height`, that is the output of a short
+    /// channel id. `None` when it does not exist or it is spent.
+    fn get_output_by_scid(
+        &self,
+        height: u32,
+        tx_index: u32,
+        vout: u16,
+    ) -> error::Result<Option<TxOut>>;
 
     fn get_utxo_by_txid(&self, txid: &Txid, script: &Script) -> error::Result<TxResult>;
 
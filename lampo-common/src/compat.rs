@@ -0,0 +1,539 @@
+//! Compat layer with the JSON RPC of Core Lightning.
+//!
+//! The scripts written against Core Lightning call `invoice`, `pay`,
+//! `newaddr`, `getinfo`, `listfunds` and `fundchannel` with its params
+//! and read its fields. With `compat-cln` lampo serves these methods
+//! by translating the params into the ones of the native method, and
+//! the response of the native method into the fields of Core Lightning.
+//!
+//! The params of Core Lightning that lampo does not have are ignored,
+//! and the fields that lampo does not know are not in the response.
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use bitcoin::consensus::encode::serialize_hex;
+use bitcoin::hashes::Hash;
+use bitcoin::{Address, AddressType};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::amount::{parse_msat, MSAT_PER_SAT};
+use crate::error;
+use crate::error::LampoError;
+use crate::json;
+use crate::model::request::{ChannelIdentifier, GenerateInvoice, OpenChannel, Pay};
+use crate::model::response::{self, PaymentState};
+use crate::model::{GetInfo, Msat};
+
+/// The methods of Core Lightning served by the compat layer, they
+/// have the same name of the native method.
+pub const CLN_METHODS: [&str; 6] = [
+    "invoice",
+    "pay",
+    "newaddr",
+    "getinfo",
+    "listfunds",
+    "fundchannel",
+];
+
+/// The methods of Core Lightning that the compat layer does not
+/// serve, with the native method to use instead.
+pub const UNSUPPORTED_CLN_METHODS: [(&str, Option<&str>); 13] = [
+    ("delinvoice", Some("cancelinvoice")),
+    ("waitinvoice", Some("waitevent")),
+    ("waitanyinvoice", Some("waitevent")),
+    ("decodepay", Some("decode")),
+    ("fetchinvoice", Some("pay")),
+    ("sendpay", Some("pay")),
+    ("waitsendpay", Some("waitevent")),
+    ("listsendpays", Some("listpays")),
+    ("listpeerchannels", Some("channels")),
+    ("listchannels", Some("listgossipchannels")),
+    ("multifundchannel", Some("fundchannel")),
+    ("withdraw", None),
+    ("listforwards", None),
+];
+
+/// The error of a method of Core Lightning that is not served.
+pub fn unsupported(method: &str) -> LampoError {
+    let alternative = UNSUPPORTED_CLN_METHODS
+        .iter()
+        .find(|(name, _)| *name == method)
+        .and_then(|(_, alternative)| alternative.map(str::to_owned));
+    LampoError::CompatNotImplemented {
+        method: method.to_owned(),
+        alternative,
+    }
+}
+
+/// An amount of Core Lightning, a number or a string that can have
+/// a unit, e.g. `1000msat`, or that is `any`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Amount {
+    Number(u64),
+    Text(String),
+}
+
+impl Amount {
+    /// The amount in msat, `unit_msat` is the unit of the amount
+    /// without a unit. `None` when the amount is `any`.
+    fn to_msat(&self, unit_msat: u64) -> Result<Option<u64>, LampoError> {
+        match self {
+            Self::Number(amount) => amount
+                .checked_mul(unit_msat)
+                .map(Some)
+                .ok_or_else(|| LampoError::InvalidParams(format!("amount `{amount}` is too big"))),
+            Self::Text(amount) if amount == "any" => Ok(None),
+            Self::Text(amount) => match u64::from_str(amount) {
+                Ok(amount) => Self::Number(amount).to_msat(unit_msat),
+                Err(_) => parse_msat(amount).map(Some),
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct InvoiceParams {
+    #[serde(alias = "msatoshi")]
+    amount_msat: Amount,
+    /// A string or a number, as Core Lightning accepts.
+    label: json::Value,
+    description: String,
+    expiry: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct PayParams {
+    bolt11: String,
+    #[serde(alias = "msatoshi")]
+    amount_msat: Option<Amount>,
+    retry_for: Option<u64>,
+    maxdelay: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct NewAddrParams {
+    addresstype: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FundChannelParams {
+    /// The node id, or `node_id@host:port`.
+    id: String,
+    amount: Amount,
+    announce: Option<bool>,
+}
+
+fn params<T: DeserializeOwned>(method: &str, params: &json::Value) -> error::Result<T> {
+    if params.is_array() {
+        return Err(LampoError::InvalidParams(format!(
+            "the params of `{method}` must be passed by name"
+        ))
+        .into());
+    }
+    let params = json::from_value(params.clone())
+        .map_err(|err| LampoError::InvalidParams(format!("{err}")))?;
+    Ok(params)
+}
+
+/// Translate the params of the method of Core Lightning into the
+/// params of the native method.
+pub fn to_native_params(method: &str, request: &json::Value) -> error::Result<json::Value> {
+    let native = match method {
+        "invoice" => {
+            let request: InvoiceParams = params(method, request)?;
+            let expiring_in = request
+                .expiry
+                .map(u32::try_from)
+                .transpose()
+                .map_err(|_| LampoError::InvalidParams("`expiry` is too big".to_owned()))?;
+            let label = match request.label {
+                json::Value::String(label) => label,
+                json::Value::Number(label) => label.to_string(),
+                _ => {
+                    return Err(LampoError::InvalidParams(
+                        "`label` must be a string or a number".to_owned(),
+                    )
+                    .into())
+                }
+            };
+            json::to_value(GenerateInvoice {
+                amount_msat: request.amount_msat.to_msat(1)?,
+                description: request.description,
+                expiring_in,
+                expiry_unix: None,
+                no_expiry: false,
+                label: Some(label),
+                fallback: None,
+                metadata: None,
+            })?
+        }
+        "pay" => {
+            let request: PayParams = params(method, request)?;
+            let amount = match request.amount_msat {
+                Some(amount) => amount.to_msat(1)?.map(Msat::from_msat),
+                None => None,
+            };
+            json::to_value(Pay {
+                invoice_str: request.bolt11,
+                amount,
+                custom_tlvs: Vec::new(),
+                timeout_secs: request.retry_for,
+                max_total_cltv_expiry_delta: request.maxdelay,
+            })?
+        }
+        "newaddr" => {
+            let request: NewAddrParams = params(method, request)?;
+            address_type(&request)?;
+            json::json!({})
+        }
+        "getinfo" | "listfunds" => json::json!({}),
+        "fundchannel" => {
+            let request: FundChannelParams = params(method, request)?;
+            let amount_msat = request.amount.to_msat(MSAT_PER_SAT)?.ok_or_else(|| {
+                LampoError::InvalidParams("the amount of a channel can not be `any`".to_owned())
+            })?;
+            if amount_msat % MSAT_PER_SAT != 0 {
+                return Err(LampoError::InvalidParams(
+                    "the amount of a channel must be a whole number of sats".to_owned(),
+                )
+                .into());
+            }
+            let (node_id, addr, port) = match request.id.split_once('@') {
+                Some((node_id, host)) => {
+                    let (addr, port) = host
+                        .rsplit_once(':')
+                        .and_then(|(addr, port)| Some((addr, u64::from_str(port).ok()?)))
+                        .ok_or_else(|| {
+                            LampoError::InvalidParams(format!(
+                                "invalid address `{host}`, it must be `host:port`"
+                            ))
+                        })?;
+                    (node_id.to_owned(), Some(addr.to_owned()), Some(port))
+                }
+                None => (request.id, None, None),
+            };
+            json::to_value(OpenChannel {
+                node_id,
+                addr,
+                port,
+                amount: amount_msat / MSAT_PER_SAT,
+                public: request.announce.unwrap_or(true),
+            })?
+        }
+        _ => return Err(unsupported(method).into()),
+    };
+    Ok(native)
+}
+
+/// The address type asked to `newaddr`, `bech32` by default.
+fn address_type(request: &NewAddrParams) -> Result<&str, LampoError> {
+    let kind = request.addresstype.as_deref().unwrap_or("bech32");
+    if !["bech32", "p2tr", "all"].contains(&kind) {
+        return Err(LampoError::InvalidParams(format!(
+            "invalid `addresstype` `{kind}`, it must be `bech32`, `p2tr` or `all`"
+        )));
+    }
+    Ok(kind)
+}
+
+/// Translate the response of the native method into the response
+/// of the method of Core Lightning called with `request`.
+pub fn to_cln_response(
+    method: &str,
+    request: &json::Value,
+    resp: json::Value,
+) -> error::Result<json::Value> {
+    let cln = match method {
+        "invoice" => {
+            let invoice: response::Invoice = json::from_value(resp)?;
+            json::json!({
+                "bolt11": invoice.bolt11,
+                "payment_hash": invoice.payment_hash,
+                "payment_secret": invoice.payment_secret,
+                "expires_at": invoice.expiry_unix,
+            })
+        }
+        "pay" => {
+            let pay: response::PayResult = json::from_value(resp)?;
+            let status = match pay.state {
+                PaymentState::Success => "complete",
+                PaymentState::Pending => "pending",
+                PaymentState::Faulure => "failed",
+            };
+            let mut cln = json::json!({ "status": status, "parts": 1 });
+            if let Some(payment_hash) = pay.payment_hash {
+                cln["payment_hash"] = json::json!(payment_hash);
+            }
+            // the fee of the last hop is the amount delivered
+            if let Some(last) = pay.path.last() {
+                let sent = pay.path.iter().map(|hop| hop.hop_fee_msat).sum::<u64>();
+                cln["destination"] = json::json!(last.node_id);
+                cln["amount_msat"] = json::json!(last.hop_fee_msat);
+                cln["amount_sent_msat"] = json::json!(sent);
+            }
+            cln
+        }
+        "newaddr" => {
+            let request: NewAddrParams = params(method, request)?;
+            let requested = address_type(&request)?;
+            let address: response::NewAddress = json::from_value(resp)?;
+            let kind = match Address::from_str(&address.address)?
+                .assume_checked()
+                .address_type()
+            {
+                Some(AddressType::P2tr) => "p2tr",
+                Some(AddressType::P2wpkh | AddressType::P2wsh) => "bech32",
+                _ => error::bail!("the address `{}` is not a segwit address", address.address),
+            };
+            if requested != "all" && requested != kind {
+                return Err(LampoError::InvalidParams(format!(
+                    "the wallet gives only `{kind}` addresses"
+                ))
+                .into());
+            }
+            json::json!({ kind: address.address })
+        }
+        "getinfo" => {
+            let info: GetInfo = json::from_value(resp)?;
+            let addresses = |addresses: &[response::NetworkInfo]| {
+                addresses
+                    .iter()
+                    .map(|addr| {
+                        json::json!({
+                            "type": network_type(&addr.address),
+                            "address": addr.address,
+                            "port": addr.port,
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            };
+            let mut cln = json::json!({
+                "id": info.node_id,
+                "alias": info.alias,
+                "color": info.color,
+                "num_peers": info.peers,
+                "num_pending_channels": info.channels_state.pending,
+                "num_active_channels": info.channels_state.active,
+                "num_inactive_channels": info.channels_state.inactive,
+                "address": addresses(&info.address),
+                "binding": addresses(&info.binding),
+                "version": info.version,
+                "blockheight": info.blockheight,
+                "network": info.chain,
+                "lightning-dir": info.lampo_dir,
+            });
+            if !info.synced_to_chain {
+                cln["warning_lightningd_sync"] =
+                    json::json!("Still loading latest blocks from bitcoind.");
+            }
+            cln
+        }
+        "listfunds" => {
+            let funds: response::ListFunds = json::from_value(resp)?;
+            let outputs = funds
+                .outputs
+                .iter()
+                .map(|utxo| {
+                    let mut output = json::json!({
+                        "txid": utxo.txid,
+                        "output": utxo.vout,
+                        "amount_msat": utxo.amount_msat,
+                        "status": if utxo.confirmed > 0 { "confirmed" } else { "unconfirmed" },
+                        "reserved": utxo.reserved,
+                    });
+                    if let Some(address) = &utxo.address {
+                        output["address"] = json::json!(address);
+                    }
+                    output
+                })
+                .collect::<Vec<_>>();
+            let channels = funds
+                .channels
+                .iter()
+                .map(|channel| {
+                    let mut cln = json::json!({
+                        "peer_id": channel.peer_id,
+                        "channel_id": channel.channel_id,
+                        "state": if channel.state == "pending" {
+                            "CHANNELD_AWAITING_LOCKIN"
+                        } else {
+                            "CHANNELD_NORMAL"
+                        },
+                        "our_amount_msat": channel.our_amount_msat,
+                        "amount_msat": channel.amount_msat,
+                    });
+                    // an inactive channel is ready, but its peer is offline
+                    match channel.state.as_str() {
+                        "active" => cln["connected"] = json::json!(true),
+                        "inactive" => cln["connected"] = json::json!(false),
+                        _ => {}
+                    }
+                    if let Some(scid) = channel.short_channel_id {
+                        cln["short_channel_id"] =
+                            json::json!(ChannelIdentifier::ShortChannelId(scid).to_string());
+                    }
+                    cln
+                })
+                .collect::<Vec<_>>();
+            json::json!({ "outputs": outputs, "channels": channels })
+        }
+        "fundchannel" => {
+            let open: response::OpenChannel = json::from_value(resp)?;
+            let Some(tx) = open.tx else {
+                error::bail!("the funding transaction of the channel is not known");
+            };
+            let txid = tx.txid();
+            let mut cln = json::json!({ "tx": serialize_hex(&tx), "txid": txid.to_string() });
+            if let Some(outnum) = tx.output.iter().position(|out| out.value == open.amount) {
+                // the channel id of a v1 channel is the funding txid
+                // xor the funding output
+                let mut channel_id = txid.to_byte_array();
+                channel_id[30] ^= (outnum >> 8) as u8;
+                channel_id[31] ^= outnum as u8;
+                cln["outnum"] = json::json!(outnum);
+                cln["channel_id"] = json::json!(hex::encode(channel_id));
+            }
+            cln
+        }
+        _ => return Err(unsupported(method).into()),
+    };
+    Ok(cln)
+}
+
+/// The type of an address inside `getinfo`.
+fn network_type(address: &str) -> &'static str {
+    match IpAddr::from_str(address) {
+        Ok(IpAddr::V4(_)) => "ipv4",
+        Ok(IpAddr::V6(_)) => "ipv6",
+        Err(_) if address.ends_with(".onion") => "torv3",
+        Err(_) => "dns",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_cln_response, to_native_params, unsupported};
+    use crate::bitcoin::consensus::encode::deserialize;
+    use crate::bitcoin::Transaction;
+    use crate::error::LampoError;
+    use crate::json;
+
+    /// Check that `ours` has the same values of `cln` and return
+    /// the fields of `cln` that are not inside `ours`.
+    fn compare(ours: &json::Value, cln: &json::Value, path: &str, missing: &mut Vec<String>) {
+        match (ours, cln) {
+            (json::Value::Object(ours), json::Value::Object(cln)) => {
+                for key in ours.keys() {
+                    assert!(cln.contains_key(key), "`{path}{key}` is not a field of cln");
+                }
+                for (key, value) in cln {
+                    match ours.get(key) {
+                        Some(ours) => compare(ours, value, &format!("{path}{key}."), missing),
+                        None => missing.push(format!("{path}{key}")),
+                    }
+                }
+            }
+            (json::Value::Array(ours), json::Value::Array(cln)) => {
+                assert_eq!(ours.len(), cln.len(), "`{path}` has a different length");
+                for (ours, cln) in ours.iter().zip(cln) {
+                    compare(ours, cln, &format!("{path}[]."), missing);
+                }
+            }
+            _ => assert_eq!(ours, cln, "`{path}` has a different value"),
+        }
+    }
+
+    /// Run the request recorded in `cln/<name>.json` against the
+    /// compat layer, the native method is replaced by the native
+    /// request and response inside the fixture.
+    fn contract(fixture: &str) {
+        let fixture: json::Value = json::from_str(fixture).unwrap();
+        let method = fixture["method"].as_str().unwrap();
+        let request = &fixture["request"];
+        let native = to_native_params(method, request).unwrap();
+        assert_eq!(native, fixture["native_request"], "{method}");
+
+        let mut native_response = fixture["native_response"].clone();
+        if method == "fundchannel" {
+            // the recorded transaction in the shape of the native response
+            let tx = hex::decode(fixture["response"]["tx"].as_str().unwrap()).unwrap();
+            let tx: Transaction = deserialize(&tx).unwrap();
+            native_response["tx"] = json::to_value(tx).unwrap();
+        }
+        let response = to_cln_response(method, request, native_response).unwrap();
+        let mut missing = Vec::new();
+        compare(&response, &fixture["response"], "", &mut missing);
+        missing.sort();
+        missing.dedup();
+        let expected: Vec<String> = json::from_value(fixture["missing"].clone()).unwrap();
+        assert_eq!(missing, expected, "{method}");
+    }
+
+    #[test]
+    fn cln_invoice() {
+        contract(include_str!("../tests/cln/invoice.json"));
+        contract(include_str!("../tests/cln/invoice_msatoshi.json"));
+    }
+
+    #[test]
+    fn cln_pay() {
+        contract(include_str!("../tests/cln/pay.json"));
+    }
+
+    #[test]
+    fn cln_newaddr() {
+        contract(include_str!("../tests/cln/newaddr.json"));
+        let err = to_native_params("newaddr", &json::json!({ "addresstype": "p2sh" }));
+        assert!(err.is_err());
+        let resp = json::json!({ "address": "bcrt1qmc6mpt4l7u35xkrrtgndu5wdz8ergye4x0m7rr" });
+        let err = to_cln_response("newaddr", &json::json!({ "addresstype": "p2tr" }), resp);
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn cln_getinfo() {
+        contract(include_str!("../tests/cln/getinfo.json"));
+    }
+
+    #[test]
+    fn cln_listfunds() {
+        contract(include_str!("../tests/cln/listfunds.json"));
+    }
+
+    #[test]
+    fn cln_fundchannel() {
+        contract(include_str!("../tests/cln/fundchannel.json"));
+    }
+
+    #[test]
+    fn cln_invalid_params() {
+        let positional = json::json!([10000, "label", "description"]);
+        assert!(to_native_params("invoice", &positional).is_err());
+        let without_unit = json::json!({ "amount_msat": "10k", "label": "a", "description": "b" });
+        assert!(to_native_params("invoice", &without_unit).is_err());
+        let any = json::json!({ "id": "02aa", "amount": "any" });
+        assert!(to_native_params("fundchannel", &any).is_err());
+    }
+
+    #[test]
+    fn cln_unsupported_methods() {
+        let err = unsupported("delinvoice");
+        assert_eq!(
+            err,
+            LampoError::CompatNotImplemented {
+                method: "delinvoice".to_owned(),
+                alternative: Some("cancelinvoice".to_owned()),
+            }
+        );
+        assert_eq!(err.data()["alternative"], "cancelinvoice");
+        let err = to_native_params("withdraw", &json::json!({})).unwrap_err();
+        let err = LampoError::find(&err).unwrap();
+        assert_eq!(err.code(), crate::error::NOT_IMPLEMENTED);
+        assert_eq!(
+            err.to_string(),
+            "`withdraw` is not implemented in the Core Lightning compat layer, and lampo has no alternative"
+        );
+    }
+}
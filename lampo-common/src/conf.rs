@@ -1,30 +1,323 @@
+pub mod check;
+pub mod layers;
+
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
 use std::str::FromStr;
 
-use clightningrpc_conf::{CLNConf, SyncCLNConf};
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::ScriptBuf;
 
 pub use bitcoin::Network;
-pub use lightning::util::config::UserConfig;
+pub use lightning::ln::msgs::SocketAddress;
+pub use lightning::util::config::{MaxDustHTLCExposure, UserConfig};
+
+use crate::conf::layers::{redact, ConfLayers, ConfOption, ConfSource, ConfValues};
+use crate::keys::KeysScheme;
+use crate::logger;
+use crate::types::NodeId;
+use crate::wallet::DEFAULT_MIN_FEE_RATE_SAT_VB;
 
 #[derive(Clone, Debug)]
 pub struct LampoConf {
-    pub inner: Option<CLNConf>,
+    /// The values of the options inside each layer, see [`layers`].
+    pub layers: ConfLayers,
     pub network: Network,
     pub ldk_conf: UserConfig,
     pub port: u64,
     pub root_path: String,
     /// The backend implementation
     pub node: String,
+    /// The wallet implementation, see [`crate::wallet::WalletFactory`],
+    /// `None` for the default wallet of the backend.
+    pub wallet: Option<String>,
     pub core_url: Option<String>,
     pub core_user: Option<String>,
     pub core_pass: Option<String>,
     pub private_key: Option<String>,
+    /// The channel secrets forced with `dev-force-channel-secrets`, see
+    /// [`crate::keys::LampoKeys::with_channel_keys`] for the format.
     pub channels_keys: Option<String>,
     pub log_file: Option<String>,
     pub log_level: String,
+    /// The targets logged with a level different from `log_level`,
+    /// from `log-level-target=<target>=<level>`.
+    pub log_targets: Vec<(String, String)>,
+    /// The alias of the node inside the network graph.
     pub alias: Option<String>,
-    pub announce_addr: Option<String>,
+    /// The color of the node inside the network graph.
+    pub rgb_color: [u8; 3],
+    /// The addresses announced inside the network graph.
+    pub announce_addr: Vec<SocketAddress>,
+    /// Seconds between two node announcements.
+    pub announce_interval_secs: u64,
+    /// The addresses where lampo listens for the p2p connections,
+    /// see `LampoConf::bind_addrs` for the default.
+    pub bind_addr: Vec<SocketAddr>,
+    /// The SOCKS5 proxy (e.g. Tor) used for the outbound connections
+    /// to the onion addresses, and by the wallet.
+    pub proxy: Option<SocketAddr>,
+    /// Connect to the peers only through the proxy, also when they
+    /// have a clearnet address.
+    pub tor_only: bool,
+    /// The Tor control port, when set lampo creates an ephemeral
+    /// hidden service and announces its onion address.
+    pub tor_control: Option<SocketAddr>,
+    /// The password to authenticate on the Tor control port.
+    pub tor_password: Option<String>,
+    /// Maximum number of inbound peers without channels with us, the
+    /// oldest of them is disconnected when a new one connects.
+    pub max_inbound_peers: Option<usize>,
+    /// Peers that are disconnected as soon as they connect.
+    pub peer_denylist: Vec<NodeId>,
+    /// When not empty, only these peers can connect with us.
+    pub peer_allowlist: Vec<NodeId>,
+    /// Minimum size of a channel (inbound and outbound) in satoshis.
+    pub min_channel_size_sat: Option<u64>,
+    /// Maximum size of a channel (inbound and outbound) in satoshis.
+    pub max_channel_size_sat: Option<u64>,
+    /// Maximum number of channels that a single peer can open with us.
+    pub max_inbound_channels_per_peer: Option<usize>,
+    /// Maximum number of channels that are not ready yet.
+    pub max_pending_channels: Option<usize>,
+    /// Maximum dust HTLC exposure for each channel in msat.
+    pub max_dust_htlc_exposure_msat: Option<u64>,
+    /// Negotiate channels with anchor outputs, so the fee of the
+    /// commitment transaction can be bumped after a force close.
+    pub anchor_channels: bool,
+    /// Peers that we trust to open zero confirmation channels with us.
+    pub zero_conf_peers: Vec<NodeId>,
+    /// Seconds that a cooperative close waits for an offline
+    /// peer before the user should escalate to a force close.
+    pub pending_close_deadline_secs: u64,
+    /// Force close the channel when the deadline of a pending
+    /// cooperative close is passed.
+    pub force_close_after_deadline: bool,
+    /// Number of parallel requests made to the esplora backend
+    /// while scanning the wallet.
+    pub scan_concurrency: usize,
+    /// The esplora apis used to sync the wallet, tried in order
+    /// until one of them replies. When it is empty the public
+    /// instance of the network is used.
+    pub esplora_urls: Vec<String>,
+    /// The peers serving the compact block filters used by the
+    /// `bip157` backend, as `ip:port`. When it is empty the peers
+    /// are found with the dns seeds of the network.
+    pub bip157_peers: Vec<String>,
+    /// Reconnect automatically to the peers we have channels with.
+    pub auto_reconnect: bool,
+    /// Disconnect a peer that does not answer our pings for these
+    /// seconds, the peers are never disconnected when it is not set.
+    pub ping_disconnect_secs: Option<u64>,
+    /// The url of the rapid gossip sync server, the p2p gossip is
+    /// used when it is not set.
+    pub rgs_url: Option<String>,
+    /// Seconds between two downloads of the rapid gossip snapshot.
+    pub rgs_refresh_interval_secs: u64,
+    /// Check that the funding output of the channels announced by
+    /// the gossip exists, it can be disabled on constrained devices.
+    pub gossip_utxo_lookup: bool,
+    /// Sync the wallet when the backend notifies a new block, instead
+    /// of polling it, if the backend supports the notifications.
+    pub event_driven_sync: bool,
+    /// Seconds between two syncs of the wallet when the backend
+    /// does not notify the new blocks.
+    pub wallet_sync_interval_secs: u64,
+    /// Minimum fee rate in sat/vB of the transactions built by
+    /// the wallet, it can be below 1 sat/vB (e.g. `0.5`) when the
+    /// relay policy of the network allows it.
+    pub min_fee_rate_sat_vb: f32,
+    /// The BIP 84 account used by the on chain wallet.
+    pub account_index: u32,
+    /// Confirmations that an output of the wallet needs before it
+    /// can fund a channel, so a reorg can not take away the inputs.
+    pub funding_min_confirmations: u32,
+    /// The birthday given to a restored wallet, the blocks before
+    /// it are not scanned for the transactions of the wallet.
+    pub wallet_birthday_height: Option<u32>,
+    /// Seconds that a payment is retried before it is abandoned.
+    pub pay_timeout_secs: u64,
+    /// The esplora api used to estimate the fees, the backend
+    /// is used when it is not set.
+    pub fee_estimates_url: Option<String>,
+    /// Seconds between two refreshes of the fee estimations.
+    pub fee_refresh_interval_secs: u64,
+    /// The fee rates in sat/kW given to ldk are never below this.
+    pub min_fee_rate_sat_kw: u32,
+    /// The fee rates in sat/kW given to ldk are never above this.
+    pub max_fee_rate_sat_kw: Option<u32>,
+    /// Seconds that the shutdown can take before the process is
+    /// stopped anyway.
+    pub shutdown_timeout_secs: u64,
+    /// The ldk events journaled and waiting for the handler, ldk
+    /// waits to give the next events while the queue is full.
+    pub event_queue_size: usize,
+    /// The node is not ready when its chain is more than these
+    /// blocks behind the tip of the backend.
+    pub ready_max_blocks_behind: u32,
+    /// The node is not ready when the wallet was not synced for
+    /// these seconds, unless it is already at the tip.
+    pub ready_wallet_staleness_secs: u64,
+    /// The address of the HTTP gateway, disabled when `None`.
+    pub http_bind: Option<SocketAddr>,
+    /// The PEM certificate chain of the HTTP gateway, without it
+    /// the gateway is served over plain HTTP.
+    pub http_tls_cert: Option<String>,
+    /// The PEM private key of `http_tls_cert`.
+    pub http_tls_key: Option<String>,
+    /// The origins allowed by CORS to call the HTTP gateway, `*`
+    /// allows any origin.
+    pub http_cors_origins: Vec<String>,
+    /// Maximum size in bytes of the body of an HTTP request.
+    pub http_max_body_bytes: usize,
+    /// The urls notified of the events of the node.
+    pub webhooks: Vec<WebhookConf>,
+    /// Maximum number of attempts to deliver an event to a webhook.
+    pub webhook_max_attempts: u32,
+    /// The directory with the executables started as plugins.
+    pub plugin_dir: Option<String>,
+    /// Serve the methods of Core Lightning with their params and
+    /// their responses, for the scripts written against it.
+    pub compat_cln: bool,
+    /// Serve the `dev-*` methods, they break the node on purpose and
+    /// are only for testing.
+    pub developer: bool,
+    /// A directory, on another filesystem, where every channel
+    /// monitor is written too.
+    pub monitor_backup_dir: Option<String>,
+    /// The versioned storage where the channel manager, the channel
+    /// monitors and the invoices are sent after they are written.
+    pub remote_storage_url: Option<String>,
+    /// The bearer token of `remote_storage_url`.
+    pub remote_storage_token: Option<String>,
+    /// The seconds that the remote storage can lag behind, then the
+    /// writes of the channel state fail.
+    pub remote_storage_max_lag_secs: u64,
+    /// Restore a datadir without channels from the remote storage.
+    pub remote_storage_restore: bool,
+    /// How the keys of ldk are derived from the mnemonic of a new
+    /// node, a node keeps the scheme it was created with.
+    pub keys_scheme: Option<KeysScheme>,
+    /// The challenge of a custom signet (e.g. mutinynet), the public
+    /// signet when it is `None`.
+    pub signet_challenge: Option<ScriptBuf>,
+    /// The watchtowers that receive the justice transactions of the
+    /// channels, as `<tower id>@<host>[:<port>]`.
+    pub watchtowers: Vec<String>,
+    /// The address where the node serves as a watchtower for the
+    /// other nodes, disabled when `None`.
+    pub tower_bind: Option<SocketAddr>,
+    /// The appointments that a client of the tower can store with
+    /// every registration.
+    pub tower_max_appointments: u32,
+    /// The blocks that a registration with the tower lasts.
+    pub tower_subscription_blocks: u32,
+    /// Adjust the fees of the channels to their liquidity, see
+    /// `AutofeesPolicy`.
+    pub autofees: bool,
+    /// The seconds between two evaluations of the fees.
+    pub autofees_interval_secs: u64,
+    /// The bounds of the proportional fee set by `autofees`.
+    pub autofees_min_ppm: u32,
+    pub autofees_max_ppm: u32,
+    /// How much the fee of a channel can change at every evaluation,
+    /// so the network is not flooded by the channel updates.
+    pub autofees_max_change_ppm: u32,
+    /// The changes of the fee smaller than this are skipped.
+    pub autofees_hysteresis_ppm: u32,
+}
+
+/// An url that receives the events of the node with a POST request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WebhookConf {
+    pub url: String,
+    /// The secret used to sign the payloads with HMAC-SHA256.
+    pub secret: String,
+    /// The kinds of the events sent (e.g. `payment_received`), all
+    /// the events when empty.
+    pub events: Vec<String>,
+}
+
+/// The port of lampo when the configuration does not set it.
+pub const DEFAULT_PORT: u64 = 19735;
+
+/// Maximum length in bytes of the node alias.
+pub const MAX_ALIAS_LEN: usize = 32;
+
+/// The minimum fee rate in sat/kW accepted by ldk.
+pub const MIN_FEE_RATE_SAT_KW: u32 = 253;
+
+/// Default maximum size in bytes of the body of an HTTP request.
+pub const DEFAULT_HTTP_MAX_BODY_BYTES: usize = 1 << 20;
+
+/// Default maximum number of attempts to deliver an event to a webhook.
+pub const DEFAULT_WEBHOOK_MAX_ATTEMPTS: u32 = 5;
+
+/// Default number of seconds that the remote storage can lag behind
+/// before the writes fail.
+pub const DEFAULT_REMOTE_STORAGE_MAX_LAG_SECS: u64 = 600;
+
+/// Default number of appointments of a registration with the tower.
+pub const DEFAULT_TOWER_MAX_APPOINTMENTS: u32 = 10_000;
+
+/// Default number of blocks of a registration with the tower, about
+/// a month.
+pub const DEFAULT_TOWER_SUBSCRIPTION_BLOCKS: u32 = 4320;
+
+/// Default number of seconds between two evaluations of `autofees`.
+pub const DEFAULT_AUTOFEES_INTERVAL_SECS: u64 = 3600;
+
+/// Default bounds of the proportional fee set by `autofees`.
+pub const DEFAULT_AUTOFEES_MIN_PPM: u32 = 1;
+pub const DEFAULT_AUTOFEES_MAX_PPM: u32 = 2000;
+
+/// Default change of the fee at every evaluation of `autofees`.
+pub const DEFAULT_AUTOFEES_MAX_CHANGE_PPM: u32 = 100;
+
+/// Default smallest change of the fee made by `autofees`.
+pub const DEFAULT_AUTOFEES_HYSTERESIS_PPM: u32 = 10;
+
+/// Default number of parallel requests made to esplora during the scan.
+pub const DEFAULT_SCAN_CONCURRENCY: usize = 2;
+/// The options that `reload` applies to the running node, the
+/// others need a restart.
+pub const RELOADABLE_OPTIONS: [&str; 7] = [
+    "log-level",
+    "log-level-target",
+    "min-fee-rate-sat-kw",
+    "max-fee-rate-sat-kw",
+    "fee-estimates-url",
+    "esplora-url",
+    "webhook",
+];
+
+/// The challenge of the public signet, see BIP 325.
+pub const SIGNET_CHALLENGE: &str = "512103ad5e0edad18cb1f0fc0d28a3d4f1f3e445640337489abb10404f2d1e086be430210359ef5021964fe22d6f8e05b2463c9540ce96883fe3b278760f048f5189f2e6c452ae";
+
+/// The public esplora instance of the `network`, for signet the one
+/// of the public signet.
+pub fn public_esplora_url(network: Network) -> Option<&'static str> {
+    match network {
+        Network::Bitcoin => Some("https://mempool.space/api"),
+        Network::Testnet => Some("https://mempool.space/testnet/api"),
+        Network::Signet => Some("https://mempool.space/signet/api"),
+        _ => None,
+    }
+}
+
+/// The magic of the p2p messages of the signet with the `challenge`,
+/// that is the start of the double sha256 of the challenge as in BIP 325.
+pub fn signet_magic(challenge: &ScriptBuf) -> [u8; 4] {
+    let hash = sha256d::Hash::hash(&bitcoin::consensus::serialize(challenge));
+    let mut magic = [0; 4];
+    magic.copy_from_slice(&hash[..4]);
+    magic
 }
 
+/// Public esplora instances rate limit the clients that make too
+/// many requests in parallel, so we do not allow to go over this.
+pub const MAX_SCAN_CONCURRENCY: usize = 16;
+
 impl LampoConf {
     // Create a new LampoConf with default values (This is used if the user doesn't specify a path)
     pub fn default() -> Self {
@@ -35,23 +328,135 @@ impl LampoConf {
         let path = path.to_str().unwrap();
         let lampo_home = format!("{}/.lampo", path);
         Self {
-            inner: None,
+            layers: ConfLayers::default(),
             // default network is testnet
             network: Network::Testnet,
-            ldk_conf: UserConfig::default(),
-            // default port is 19735 for testnet
-            port: 19735,
+            ldk_conf: Self::default_ldk_conf(),
+            port: DEFAULT_PORT,
             root_path: lampo_home,
             node: "nakamoto".to_owned(),
+            wallet: None,
             core_url: None,
             core_user: None,
             core_pass: None,
             private_key: None,
             channels_keys: None,
             log_level: "info".to_string(),
+            log_targets: Vec::new(),
             log_file: None,
             alias: None,
-            announce_addr: None,
+            rgb_color: [0; 3],
+            announce_addr: Vec::new(),
+            // one hour
+            announce_interval_secs: 3600,
+            bind_addr: Vec::new(),
+            proxy: None,
+            tor_only: false,
+            tor_control: None,
+            tor_password: None,
+            max_inbound_peers: None,
+            peer_denylist: Vec::new(),
+            peer_allowlist: Vec::new(),
+            min_channel_size_sat: None,
+            max_channel_size_sat: None,
+            max_inbound_channels_per_peer: None,
+            max_pending_channels: None,
+            max_dust_htlc_exposure_msat: None,
+            anchor_channels: false,
+            zero_conf_peers: Vec::new(),
+            // one day
+            pending_close_deadline_secs: 86400,
+            force_close_after_deadline: false,
+            scan_concurrency: DEFAULT_SCAN_CONCURRENCY,
+            esplora_urls: Vec::new(),
+            bip157_peers: Vec::new(),
+            auto_reconnect: true,
+            ping_disconnect_secs: None,
+            rgs_url: None,
+            // one hour
+            rgs_refresh_interval_secs: 3600,
+            gossip_utxo_lookup: true,
+            event_driven_sync: true,
+            wallet_sync_interval_secs: 60,
+            min_fee_rate_sat_vb: DEFAULT_MIN_FEE_RATE_SAT_VB,
+            account_index: 0,
+            funding_min_confirmations: 1,
+            wallet_birthday_height: None,
+            pay_timeout_secs: 60,
+            fee_estimates_url: None,
+            fee_refresh_interval_secs: 60,
+            min_fee_rate_sat_kw: MIN_FEE_RATE_SAT_KW,
+            max_fee_rate_sat_kw: None,
+            shutdown_timeout_secs: 30,
+            event_queue_size: 256,
+            ready_max_blocks_behind: 2,
+            ready_wallet_staleness_secs: 600,
+            http_bind: None,
+            http_tls_cert: None,
+            http_tls_key: None,
+            http_cors_origins: Vec::new(),
+            http_max_body_bytes: DEFAULT_HTTP_MAX_BODY_BYTES,
+            webhooks: Vec::new(),
+            webhook_max_attempts: DEFAULT_WEBHOOK_MAX_ATTEMPTS,
+            plugin_dir: None,
+            compat_cln: false,
+            developer: false,
+            monitor_backup_dir: None,
+            remote_storage_url: None,
+            remote_storage_token: None,
+            remote_storage_max_lag_secs: DEFAULT_REMOTE_STORAGE_MAX_LAG_SECS,
+            remote_storage_restore: false,
+            keys_scheme: None,
+            signet_challenge: None,
+            watchtowers: Vec::new(),
+            tower_bind: None,
+            tower_max_appointments: DEFAULT_TOWER_MAX_APPOINTMENTS,
+            tower_subscription_blocks: DEFAULT_TOWER_SUBSCRIPTION_BLOCKS,
+            autofees: false,
+            autofees_interval_secs: DEFAULT_AUTOFEES_INTERVAL_SECS,
+            autofees_min_ppm: DEFAULT_AUTOFEES_MIN_PPM,
+            autofees_max_ppm: DEFAULT_AUTOFEES_MAX_PPM,
+            autofees_max_change_ppm: DEFAULT_AUTOFEES_MAX_CHANGE_PPM,
+            autofees_hysteresis_ppm: DEFAULT_AUTOFEES_HYSTERESIS_PPM,
+        }
+    }
+
+    /// The ldk configuration used by lampo before applying
+    /// the user options.
+    fn default_ldk_conf() -> UserConfig {
+        let mut conf = UserConfig::default();
+        // Use the scid alias in the route hints of our private channels,
+        // this is also needed for the zero conf channels that do not
+        // have a real scid yet.
+        conf.channel_handshake_config.negotiate_scid_privacy = true;
+        conf
+    }
+
+    /// Apply the channel limits configured by the user to the
+    /// ldk configuration.
+    pub fn apply_channel_limits(&mut self) {
+        if let Some(min) = self.min_channel_size_sat {
+            self.ldk_conf.channel_handshake_limits.min_funding_satoshis = min;
+        }
+        if let Some(max) = self.max_channel_size_sat {
+            self.ldk_conf.channel_handshake_limits.max_funding_satoshis = max;
+        }
+        if let Some(exposure) = self.max_dust_htlc_exposure_msat {
+            self.ldk_conf.channel_config.max_dust_htlc_exposure =
+                MaxDustHTLCExposure::FixedLimitMsat(exposure);
+        }
+        // The inbound channels limits are enforced by lampo
+        // so we need to look at the channel before accepting it.
+        if self.need_channel_acceptor() {
+            self.ldk_conf.manually_accept_inbound_channels = true;
+        }
+        // ldk refuses inbound anchor channels if they are not
+        // accepted manually.
+        if self.anchor_channels {
+            self.ldk_conf
+                .channel_handshake_config
+                .negotiate_anchors_zero_fee_htlc_tx = true;
+            self.ldk_conf.manually_accept_inbound_channels = true;
         }
     }
 
@@ -106,31 +511,101 @@ impl LampoConf {
         root
     }
 
+    /// The configuration of the node inside `path`, with the
+    /// `network` and the `port` that override the configuration file.
     pub fn new(
         path: Option<String>,
         network: Option<Network>,
         port: Option<u64>,
     ) -> Result<Self, anyhow::Error> {
-        let mut conf = Self::default();
-        conf.network = network.unwrap_or(conf.network);
-        conf.port = port.unwrap_or(conf.port);
-        conf.root_path = path.clone().unwrap_or(conf.root_path);
-        Self::prepare_directories(&conf.root_path, Some(conf.network))?;
-        let input_path = path;
-        let path = Self::normalize_root_dir(&conf.root_path, conf.network);
-        conf.root_path = path.clone();
+        let root_path = path.unwrap_or_else(|| Self::default().root_path);
+        let mut builder = LampoConfBuilder::new(&root_path);
+        if let Some(network) = network {
+            builder = builder.cli("network", &network.to_string())?;
+        }
+        if let Some(port) = port {
+            builder = builder.cli("port", &port.to_string())?;
+        }
+        builder.build()
+    }
 
-        let lampo_file = format!("{}/lampo.conf", conf.path());
+    /// Read again the configuration file, the values given by the
+    /// environment and by the command line are kept.
+    pub fn reloaded(&self) -> Result<Self, anyhow::Error> {
+        let mut layers = self.layers.clone();
+        let path = format!("{}/lampo.conf", self.path());
+        if !Path::new(&path).exists() {
+            anyhow::bail!("Configuration file not found at `{path}`");
+        }
+        layers.load_file(&path)?;
+        Self::from_layers(&self.path(), layers)
+    }
+}
 
-        if std::fs::File::open(lampo_file.clone()).is_ok() {
-            let mut conf = Self::try_from(conf.path())?;
-            conf.network = network.unwrap_or(conf.network);
-            conf.port = port.unwrap_or(conf.port);
-            conf.root_path = input_path.unwrap_or(conf.root_path);
-            return Ok(conf);
+/// Build the configuration from its layers: the defaults, the
+/// `lampo.conf` file, the environment and the command line.
+pub struct LampoConfBuilder {
+    root_path: String,
+    layers: ConfLayers,
+}
+
+impl LampoConfBuilder {
+    /// A builder of the configuration inside the directory `root_path`.
+    pub fn new(root_path: &str) -> Self {
+        Self {
+            root_path: root_path.to_owned(),
+            layers: ConfLayers::default(),
         }
+    }
+
+    /// Take the options from the environment variables `vars`, see
+    /// [`layers::env_var`] for their names.
+    pub fn env<I>(mut self, vars: I) -> Result<Self, anyhow::Error>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        self.layers.load_env(vars)?;
+        Ok(self)
+    }
 
-        Ok(conf)
+    /// Set the option `key` from the command line, the values of an
+    /// option given more times are all kept.
+    pub fn cli(mut self, key: &str, value: &str) -> Result<Self, anyhow::Error> {
+        check::check_option(&format!("--{key}"), key, value)?;
+        self.layers.push(ConfSource::Cli, key, value);
+        Ok(self)
+    }
+
+    /// The network of the node, it tells the directory with the
+    /// configuration file so the file can not change it.
+    pub fn network(&self) -> Result<Network, anyhow::Error> {
+        let network = self
+            .layers
+            .get_conf("network")
+            .map(|network| Network::from_str(&network))
+            .transpose()?;
+        Ok(network.unwrap_or(Network::Testnet))
+    }
+
+    /// Read the configuration file of the network, if there is one,
+    /// and build the configuration.
+    pub fn build(mut self) -> Result<LampoConf, anyhow::Error> {
+        let network = self.network()?;
+        LampoConf::prepare_directories(&self.root_path, Some(network))?;
+        let root_path = LampoConf::normalize_root_dir(&self.root_path, network);
+        let path = format!("{root_path}/{network}");
+        let lampo_file = format!("{path}/lampo.conf");
+        if Path::new(&lampo_file).exists() {
+            self.layers.load_file(&lampo_file)?;
+            if let Some(other) = self.layers.get_confs_in(ConfSource::File, "network").pop() {
+                if Network::from_str(&other)? != network {
+                    anyhow::bail!(
+                        "`{lampo_file}` sets `network={other}`, but it is the file of `{network}`"
+                    );
+                }
+            }
+        }
+        LampoConf::from_layers(&path, self.layers)
     }
 }
 
@@ -143,53 +618,46 @@ impl TryFrom<String> for LampoConf {
         let path = path.replace("//", "/");
 
         // If lampo.conf doesn't exist, return the default configuration
-        if !std::path::Path::new(&path).exists() {
+        if !Path::new(&path).exists() {
             anyhow::bail!("Configuration file not found at `{path}`");
         }
-
-        let mut conf = CLNConf::new(path, false);
-        conf.parse()
-            .map_err(|err| anyhow::anyhow!("{}", err.cause))?;
-
-        let Some(network) = conf
-            .get_conf("network")
-            .map_err(|err| anyhow::anyhow!("{err}"))?
-        else {
+        let mut layers = ConfLayers::default();
+        layers.load_file(&path)?;
+        if layers.get_conf("network").is_none() {
             anyhow::bail!("Network inside the configuration file missed");
-        };
+        }
+        Self::from_layers(&value, layers)
+    }
+}
 
-        let Some(port) = conf
-            .get_conf("port")
-            .map_err(|err| anyhow::anyhow!("{err}"))?
-        else {
-            anyhow::bail!("Port need to be specified inside the file");
-        };
+impl LampoConf {
+    /// Parse the options inside the `conf` layers, `value` is the
+    /// directory of the network of the node.
+    fn from_layers(value: &str, conf: ConfLayers) -> Result<Self, anyhow::Error> {
+        let network = conf
+            .get_conf("network")
+            .map(|network| Network::from_str(&network))
+            .transpose()?
+            .unwrap_or(Network::Testnet);
+        let port = parse_conf(&conf, "port")?.unwrap_or(DEFAULT_PORT);
 
-        let node = conf
-            .get_conf("backend")
-            .map_err(|err| anyhow::anyhow!("{err}"))?
-            .unwrap_or("nakamoto".to_owned());
+        let node = conf.get_conf("backend").unwrap_or("nakamoto".to_owned());
         // Strip the value of whitespace
         let node = node.to_trimmed();
+        let wallet = conf.get_conf("wallet").map(|wallet| wallet.to_trimmed());
 
         let mut core_url = None;
         let mut core_user = None;
         let mut core_pass = None;
         if node == "core" {
-            core_url = conf
-                .get_conf("core-url")
-                .map_err(|err| anyhow::anyhow!("{err}"))?;
+            core_url = conf.get_conf("core-url");
             // If the value isn't none, strip the value of whitespace
             core_url = core_url.map(|url| url.to_trimmed());
 
-            core_user = conf
-                .get_conf("core-user")
-                .map_err(|err| anyhow::anyhow!("{err}"))?;
+            core_user = conf.get_conf("core-user");
             core_user = core_user.map(|user| user.to_trimmed());
 
-            core_pass = conf
-                .get_conf("core-pass")
-                .map_err(|err| anyhow::anyhow!("{err}"))?;
+            core_pass = conf.get_conf("core-pass");
             core_pass = core_pass.map(|pass| pass.to_trimmed());
         }
         // Dev options
@@ -200,33 +668,316 @@ impl TryFrom<String> for LampoConf {
 
         #[cfg(debug_assertions)]
         {
-            private_key = conf
-                .get_conf("dev-private-key")
-                .map_err(|err| anyhow::anyhow!("{err}"))?;
+            private_key = conf.get_conf("dev-private-key");
 
-            channels_keys = conf
-                .get_conf("dev-force-channel-secrets")
-                .map_err(|err| anyhow::anyhow!("{err}"))?;
+            channels_keys = conf.get_conf("dev-force-channel-secrets");
         }
 
-        let network = Network::from_str(&network)?;
-        let root_path = Self::normalize_root_dir(&value, network);
-        let log_level = conf.get_conf("log-level");
-        let level = match log_level {
-            Ok(Some(level)) => level,
-            _ => "info".to_string(),
-        };
-        let log_file = conf.get_conf("log-file").unwrap_or_else(|_| None);
-        let alias = conf.get_conf("alias").unwrap_or(None);
-        let announce_addr = conf.get_conf("announce-addr").unwrap_or_else(|_| None);
+        let root_path = Self::normalize_root_dir(value, network);
+        let level = conf
+            .get_conf("log-level")
+            .unwrap_or_else(|| "info".to_string());
+        let log_file = conf.get_conf("log-file");
+        let log_targets = conf
+            .get_confs("log-level-target")
+            .iter()
+            .flat_map(|targets| targets.split(','))
+            .map(|target| parse_log_target(target.trim()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let alias: Option<String> = conf.get_conf("alias");
+        if let Some(ref alias) = alias {
+            if alias.len() > MAX_ALIAS_LEN {
+                anyhow::bail!("the alias `{alias}` is longer than {MAX_ALIAS_LEN} bytes");
+            }
+        }
+        let rgb_color = conf
+            .get_conf("rgb-color")
+            .map(|color| parse_rgb_color(&color.to_trimmed()))
+            .transpose()?
+            .unwrap_or_default();
+        let announce_addr = conf
+            .get_confs("announce-addr")
+            .iter()
+            .flat_map(|addrs| addrs.split(','))
+            .map(|addr| parse_announce_addr(addr.trim(), port))
+            .collect::<Result<Vec<_>, _>>()?;
+        let announce_interval_secs =
+            parse_conf(&conf, "announce-interval-secs")?.unwrap_or(3600);
+        let bind_addr = conf
+            .get_confs("bind-addr")
+            .iter()
+            .flat_map(|addrs| addrs.split(','))
+            .map(|addr| parse_bind_addr(addr.trim(), port))
+            .collect::<Result<Vec<_>, _>>()?;
+        let proxy = parse_conf(&conf, "proxy")?;
+        let tor_only = parse_conf(&conf, "tor-only")?.unwrap_or(false);
+        if tor_only && proxy.is_none() {
+            anyhow::bail!("`tor-only` needs a `proxy` to connect to the peers");
+        }
+        let tor_control = parse_conf(&conf, "tor-control")?;
+        let tor_password = conf
+            .get_conf("tor-password")
+            .map(|password| password.to_trimmed());
+
+        let max_inbound_peers = parse_conf(&conf, "max-inbound-peers")?;
+        let peer_denylist = parse_node_ids(&conf, "peer-denylist")?;
+        let peer_allowlist = parse_node_ids(&conf, "peer-allowlist")?;
+
+        let min_channel_size_sat = parse_conf(&conf, "min-channel-size-sat")?;
+        let max_channel_size_sat = parse_conf(&conf, "max-channel-size-sat")?;
+        let max_inbound_channels_per_peer = parse_conf(&conf, "max-inbound-channels-per-peer")?;
+        let max_pending_channels = parse_conf(&conf, "max-pending-channels")?;
+        let max_dust_htlc_exposure_msat = parse_conf(&conf, "max-dust-htlc-exposure-msat")?;
+        let anchor_channels = parse_conf(&conf, "anchor-channels")?.unwrap_or(false);
+        let zero_conf_peers = conf
+            .get_conf("trusted-zero-conf-peers")
+            .map(|peers| {
+                peers
+                    .split(',')
+                    .map(|peer| NodeId::from_str(peer.trim()))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?
+            .unwrap_or_default();
+        let pending_close_deadline_secs =
+            parse_conf(&conf, "pending-close-deadline-secs")?.unwrap_or(86400);
+        let force_close_after_deadline =
+            parse_conf(&conf, "force-close-after-deadline")?.unwrap_or(false);
+        let scan_concurrency =
+            parse_conf(&conf, "scan-concurrency")?.unwrap_or(DEFAULT_SCAN_CONCURRENCY);
+        if scan_concurrency == 0 || scan_concurrency > MAX_SCAN_CONCURRENCY {
+            anyhow::bail!(
+                "invalid value for `scan-concurrency`: `{scan_concurrency}`, it must be between 1 and {MAX_SCAN_CONCURRENCY}"
+            );
+        }
+
+        let esplora_urls = conf
+            .get_confs("esplora-url")
+            .iter()
+            .flat_map(|urls| urls.split(','))
+            .map(|url| {
+                let url = url.trim().trim_end_matches('/');
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    anyhow::bail!(
+                        "invalid value for `esplora-url`: `{url}`, it must be an http url"
+                    );
+                }
+                Ok(url.to_owned())
+            })
+            .collect::<Result<Vec<_>, _>>()?;
 
-        Ok(Self {
-            inner: Some(conf),
+        let bip157_peers = conf
+            .get_confs("bip157-peer")
+            .iter()
+            .flat_map(|peers| peers.split(','))
+            .map(|peer| {
+                let peer = peer.trim();
+                if SocketAddr::from_str(peer).is_err() {
+                    anyhow::bail!(
+                        "invalid value for `bip157-peer`: `{peer}`, it must be an `ip:port`"
+                    );
+                }
+                Ok(peer.to_owned())
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let auto_reconnect = parse_conf(&conf, "auto-reconnect")?.unwrap_or(true);
+        let ping_disconnect_secs: Option<u64> = parse_conf(&conf, "ping-disconnect-secs")?;
+        if ping_disconnect_secs == Some(0) {
+            anyhow::bail!("invalid value for `ping-disconnect-secs`, it must be greater than 0");
+        }
+        let rgs_url = conf.get_conf("rgs-url").map(|url| url.to_trimmed());
+        let rgs_refresh_interval_secs =
+            parse_conf(&conf, "rgs-refresh-interval-secs")?.unwrap_or(3600);
+        let gossip_utxo_lookup = parse_conf(&conf, "gossip-utxo-lookup")?.unwrap_or(true);
+        let event_driven_sync = parse_conf(&conf, "event-driven-sync")?.unwrap_or(true);
+        let wallet_sync_interval_secs =
+            parse_conf(&conf, "wallet-sync-interval-secs")?.unwrap_or(60);
+        if wallet_sync_interval_secs == 0 {
+            anyhow::bail!(
+                "invalid value for `wallet-sync-interval-secs`, it must be greater than 0"
+            );
+        }
+        let min_fee_rate_sat_vb: f32 =
+            parse_conf(&conf, "min-fee-rate-sat-vb")?.unwrap_or(DEFAULT_MIN_FEE_RATE_SAT_VB);
+        if !min_fee_rate_sat_vb.is_finite() || min_fee_rate_sat_vb <= 0.0 {
+            anyhow::bail!("invalid value for `min-fee-rate-sat-vb`, it must be greater than 0");
+        }
+        let account_index: u32 = parse_conf(&conf, "account-index")?.unwrap_or(0);
+        if account_index >= 1 << 31 {
+            anyhow::bail!("invalid value for `account-index`, it must be below 2^31");
+        }
+        // zero allows to fund the channels with the unconfirmed outputs
+        let funding_min_confirmations =
+            parse_conf(&conf, "funding-min-confirmations")?.unwrap_or(1);
+        let wallet_birthday_height = parse_conf(&conf, "wallet-birthday-height")?;
+        let pay_timeout_secs = parse_conf(&conf, "pay-timeout-secs")?.unwrap_or(60);
+        if pay_timeout_secs == 0 {
+            anyhow::bail!("invalid value for `pay-timeout-secs`, it must be greater than 0");
+        }
+        let fee_estimates_url = conf
+            .get_conf("fee-estimates-url")
+            .map(|url| url.to_trimmed());
+        let fee_refresh_interval_secs =
+            parse_conf(&conf, "fee-refresh-interval-secs")?.unwrap_or(60);
+        if fee_refresh_interval_secs == 0 {
+            anyhow::bail!(
+                "invalid value for `fee-refresh-interval-secs`, it must be greater than 0"
+            );
+        }
+        let min_fee_rate_sat_kw =
+            parse_conf(&conf, "min-fee-rate-sat-kw")?.unwrap_or(MIN_FEE_RATE_SAT_KW);
+        if min_fee_rate_sat_kw < MIN_FEE_RATE_SAT_KW {
+            anyhow::bail!(
+                "invalid value for `min-fee-rate-sat-kw`, it must be at least {MIN_FEE_RATE_SAT_KW}"
+            );
+        }
+        let max_fee_rate_sat_kw: Option<u32> = parse_conf(&conf, "max-fee-rate-sat-kw")?;
+        if max_fee_rate_sat_kw.is_some_and(|max| max < min_fee_rate_sat_kw) {
+            anyhow::bail!(
+                "invalid value for `max-fee-rate-sat-kw`, it must be at least `min-fee-rate-sat-kw`"
+            );
+        }
+        let shutdown_timeout_secs = parse_conf(&conf, "shutdown-timeout-secs")?.unwrap_or(30);
+        if shutdown_timeout_secs == 0 {
+            anyhow::bail!("invalid value for `shutdown-timeout-secs`, it must be greater than 0");
+        }
+        let event_queue_size = parse_conf(&conf, "event-queue-size")?.unwrap_or(256);
+        if event_queue_size == 0 {
+            anyhow::bail!("invalid value for `event-queue-size`, it must be greater than 0");
+        }
+        let ready_max_blocks_behind = parse_conf(&conf, "ready-max-blocks-behind")?.unwrap_or(2);
+        let ready_wallet_staleness_secs =
+            parse_conf(&conf, "ready-wallet-staleness-secs")?.unwrap_or(600);
+        if ready_wallet_staleness_secs == 0 {
+            anyhow::bail!(
+                "invalid value for `ready-wallet-staleness-secs`, it must be greater than 0"
+            );
+        }
+        let http_bind = parse_conf(&conf, "http-bind")?;
+        let http_tls_cert = conf.get_conf("http-tls-cert").map(|path| path.to_trimmed());
+        let http_tls_key = conf.get_conf("http-tls-key").map(|path| path.to_trimmed());
+        if http_tls_cert.is_some() != http_tls_key.is_some() {
+            anyhow::bail!("`http-tls-cert` and `http-tls-key` must be set together");
+        }
+        if http_tls_cert.is_some() && http_bind.is_none() {
+            anyhow::bail!("`http-tls-cert` needs the gateway enabled with `http-bind`");
+        }
+        let http_cors_origins = conf
+            .get_confs("http-cors-origin")
+            .iter()
+            .flat_map(|origins| origins.split(','))
+            .map(|origin| origin.trim().trim_end_matches('/').to_owned())
+            .filter(|origin| !origin.is_empty())
+            .collect();
+        let http_max_body_bytes =
+            parse_conf(&conf, "http-max-body-bytes")?.unwrap_or(DEFAULT_HTTP_MAX_BODY_BYTES);
+        if http_max_body_bytes == 0 {
+            anyhow::bail!("invalid value for `http-max-body-bytes`, it must be greater than 0");
+        }
+        let webhooks = conf
+            .get_confs("webhook")
+            .iter()
+            .map(|webhook| parse_webhook(webhook))
+            .collect::<Result<Vec<_>, _>>()?;
+        let webhook_max_attempts =
+            parse_conf(&conf, "webhook-max-attempts")?.unwrap_or(DEFAULT_WEBHOOK_MAX_ATTEMPTS);
+        if webhook_max_attempts == 0 {
+            anyhow::bail!("invalid value for `webhook-max-attempts`, it must be greater than 0");
+        }
+        let plugin_dir = conf.get_conf("plugin-dir").map(|path| path.to_trimmed());
+        let compat_cln = parse_conf(&conf, "compat-cln")?.unwrap_or(false);
+        let developer = parse_conf(&conf, "developer")?.unwrap_or(false);
+        let monitor_backup_dir = conf
+            .get_conf("monitor-backup-dir")
+            .map(|path| path.to_trimmed());
+        let remote_storage_url = conf
+            .get_conf("remote-storage-url")
+            .map(|url| url.to_trimmed());
+        let remote_storage_token = conf
+            .get_conf("remote-storage-token")
+            .map(|token| token.to_trimmed());
+        let remote_storage_max_lag_secs = parse_conf(&conf, "remote-storage-max-lag-secs")?
+            .unwrap_or(DEFAULT_REMOTE_STORAGE_MAX_LAG_SECS);
+        let remote_storage_restore = parse_conf(&conf, "remote-storage-restore")?.unwrap_or(false);
+        let keys_scheme = conf
+            .get_conf("keys-scheme")
+            .map(|scheme| KeysScheme::from_str(&scheme.to_trimmed()))
+            .transpose()?;
+        let signet_challenge = conf
+            .get_conf("signet-challenge")
+            .map(|challenge| ScriptBuf::from_hex(&challenge.to_trimmed()))
+            .transpose()
+            .map_err(|err| anyhow::anyhow!("invalid value for `signet-challenge`: {err}"))?;
+        if signet_challenge.is_some() && network != Network::Signet {
+            anyhow::bail!("`signet-challenge` is valid only with `network=signet`");
+        }
+        let watchtowers = conf
+            .get_confs("watchtower")
+            .iter()
+            .map(|tower| {
+                let tower = tower.trim();
+                let valid = tower
+                    .split_once('@')
+                    .is_some_and(|(id, host)| NodeId::from_str(id).is_ok() && !host.is_empty());
+                if !valid {
+                    anyhow::bail!(
+                        "invalid value for `watchtower`: `{tower}`, it must be `<tower id>@<host>[:<port>]`"
+                    );
+                }
+                Ok(tower.to_owned())
+            })
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+        let tower_bind = parse_conf(&conf, "tower-bind")?;
+        let tower_max_appointments =
+            parse_conf(&conf, "tower-max-appointments")?.unwrap_or(DEFAULT_TOWER_MAX_APPOINTMENTS);
+        if tower_max_appointments == 0 {
+            anyhow::bail!("invalid value for `tower-max-appointments`, it must be greater than 0");
+        }
+        let tower_subscription_blocks = parse_conf(&conf, "tower-subscription-blocks")?
+            .unwrap_or(DEFAULT_TOWER_SUBSCRIPTION_BLOCKS);
+        if tower_subscription_blocks == 0 {
+            anyhow::bail!(
+                "invalid value for `tower-subscription-blocks`, it must be greater than 0"
+            );
+        }
+        let autofees = parse_conf(&conf, "autofees")?.unwrap_or(false);
+        let autofees_interval_secs =
+            parse_conf(&conf, "autofees-interval-secs")?.unwrap_or(DEFAULT_AUTOFEES_INTERVAL_SECS);
+        if autofees_interval_secs == 0 {
+            anyhow::bail!("invalid value for `autofees-interval-secs`, it must be greater than 0");
+        }
+        let autofees_min_ppm =
+            parse_conf(&conf, "autofees-min-ppm")?.unwrap_or(DEFAULT_AUTOFEES_MIN_PPM);
+        let autofees_max_ppm =
+            parse_conf(&conf, "autofees-max-ppm")?.unwrap_or(DEFAULT_AUTOFEES_MAX_PPM);
+        if autofees_min_ppm > autofees_max_ppm {
+            anyhow::bail!(
+                "`autofees-min-ppm` ({autofees_min_ppm}) is greater than `autofees-max-ppm` ({autofees_max_ppm})"
+            );
+        }
+        let autofees_max_change_ppm = parse_conf(&conf, "autofees-max-change-ppm")?
+            .unwrap_or(DEFAULT_AUTOFEES_MAX_CHANGE_PPM);
+        if autofees_max_change_ppm == 0 {
+            anyhow::bail!("invalid value for `autofees-max-change-ppm`, it must be greater than 0");
+        }
+        let autofees_hysteresis_ppm = parse_conf(&conf, "autofees-hysteresis-ppm")?
+            .unwrap_or(DEFAULT_AUTOFEES_HYSTERESIS_PPM);
+        // otherwise the fee would never change
+        if autofees_hysteresis_ppm > autofees_max_change_ppm {
+            anyhow::bail!(
+                "`autofees-hysteresis-ppm` ({autofees_hysteresis_ppm}) is greater than `autofees-max-change-ppm` ({autofees_max_change_ppm})"
+            );
+        }
+
+        let mut lampo_conf = Self {
+            layers: conf,
             root_path,
             network,
-            ldk_conf: UserConfig::default(),
-            port: u64::from_str(&port)?,
+            ldk_conf: Self::default_ldk_conf(),
+            port,
             node,
+            wallet,
             core_url,
             core_user,
             core_pass,
@@ -234,9 +985,331 @@ impl TryFrom<String> for LampoConf {
             channels_keys,
             log_file,
             log_level: level,
+            log_targets,
             alias,
+            rgb_color,
             announce_addr,
+            announce_interval_secs,
+            bind_addr,
+            proxy,
+            tor_only,
+            tor_control,
+            tor_password,
+            max_inbound_peers,
+            peer_denylist,
+            peer_allowlist,
+            min_channel_size_sat,
+            max_channel_size_sat,
+            max_inbound_channels_per_peer,
+            max_pending_channels,
+            max_dust_htlc_exposure_msat,
+            anchor_channels,
+            zero_conf_peers,
+            pending_close_deadline_secs,
+            force_close_after_deadline,
+            scan_concurrency,
+            esplora_urls,
+            bip157_peers,
+            auto_reconnect,
+            ping_disconnect_secs,
+            rgs_url,
+            rgs_refresh_interval_secs,
+            gossip_utxo_lookup,
+            event_driven_sync,
+            wallet_sync_interval_secs,
+            min_fee_rate_sat_vb,
+            account_index,
+            funding_min_confirmations,
+            wallet_birthday_height,
+            pay_timeout_secs,
+            fee_estimates_url,
+            fee_refresh_interval_secs,
+            min_fee_rate_sat_kw,
+            max_fee_rate_sat_kw,
+            shutdown_timeout_secs,
+            event_queue_size,
+            ready_max_blocks_behind,
+            ready_wallet_staleness_secs,
+            http_bind,
+            http_tls_cert,
+            http_tls_key,
+            http_cors_origins,
+            http_max_body_bytes,
+            webhooks,
+            webhook_max_attempts,
+            plugin_dir,
+            compat_cln,
+            developer,
+            monitor_backup_dir,
+            remote_storage_url,
+            remote_storage_token,
+            remote_storage_max_lag_secs,
+            remote_storage_restore,
+            keys_scheme,
+            signet_challenge,
+            watchtowers,
+            tower_bind,
+            tower_max_appointments,
+            tower_subscription_blocks,
+            autofees,
+            autofees_interval_secs,
+            autofees_min_ppm,
+            autofees_max_ppm,
+            autofees_max_change_ppm,
+            autofees_hysteresis_ppm,
+        };
+        lampo_conf.apply_channel_limits();
+        Ok(lampo_conf)
+    }
+}
+
+// Parse the color of the node, in hex as `rrggbb`.
+fn parse_rgb_color(color: &str) -> Result<[u8; 3], anyhow::Error> {
+    let color = color.trim_start_matches('#');
+    let invalid = || anyhow::anyhow!("invalid value for `rgb-color`: `{color}`");
+    if color.len() != 6 || !color.is_ascii() {
+        return Err(invalid());
+    }
+    let mut rgb = [0u8; 3];
+    for (i, byte) in rgb.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&color[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(rgb)
+}
+
+// Parse an address to announce, an ip without port uses the
+// port where lampo is listening.
+fn parse_announce_addr(addr: &str, port: u64) -> Result<SocketAddress, anyhow::Error> {
+    if let Ok(ip) = IpAddr::from_str(addr) {
+        let port = u16::try_from(port)?;
+        return Ok(SocketAddress::from(SocketAddr::new(ip, port)));
+    }
+    SocketAddress::from_str(addr)
+        .map_err(|err| anyhow::anyhow!("invalid value for `announce-addr`: `{addr}`: {err}"))
+}
+
+// Parse an address where to listen, an ip without port uses the
+// port configured with the `port` option.
+fn parse_bind_addr(addr: &str, port: u64) -> Result<SocketAddr, anyhow::Error> {
+    if let Ok(ip) = IpAddr::from_str(addr) {
+        let port = u16::try_from(port)?;
+        return Ok(SocketAddr::new(ip, port));
+    }
+    SocketAddr::from_str(addr)
+        .map_err(|err| anyhow::anyhow!("invalid value for `bind-addr`: `{addr}`: {err}"))
+}
+
+// Parse a webhook, as `<url> <secret> [<kind>,<kind>...]`.
+fn parse_webhook(webhook: &str) -> Result<WebhookConf, anyhow::Error> {
+    let mut parts = webhook.split_whitespace();
+    let (Some(url), Some(secret)) = (parts.next(), parts.next()) else {
+        anyhow::bail!(
+            "invalid value for `webhook`: `{webhook}`, it must be `<url> <secret> [<kind>,...]`"
+        );
+    };
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        anyhow::bail!("invalid value for `webhook`: `{url}`, it must be an http url");
+    }
+    let events = parts
+        .flat_map(|kinds| kinds.split(','))
+        .filter(|kind| !kind.is_empty())
+        .map(|kind| kind.to_owned())
+        .collect();
+    Ok(WebhookConf {
+        url: url.to_owned(),
+        secret: secret.to_owned(),
+        events,
+    })
+}
+
+fn parse_log_target(target: &str) -> Result<(String, String), anyhow::Error> {
+    let Some((target, level)) = target.split_once('=') else {
+        anyhow::bail!(
+            "invalid value for `log-level-target`: `{target}`, it must be `<target>=<level>`"
+        );
+    };
+    logger::parse_level(level)?;
+    Ok((target.trim().to_owned(), level.trim().to_lowercase()))
+}
+
+// Parse a list of node ids, the option can be repeated and
+// can contain more node ids separated by a comma.
+fn parse_node_ids(conf: &ConfLayers, key: &str) -> Result<Vec<NodeId>, anyhow::Error> {
+    conf.get_confs(key)
+        .iter()
+        .flat_map(|node_ids| node_ids.split(','))
+        .map(|node_id| {
+            NodeId::from_str(node_id.trim())
+                .map_err(|err| anyhow::anyhow!("invalid value for `{key}`: `{node_id}`: {err}"))
         })
+        .collect()
+}
+
+// Parse an optional value of the configuration.
+fn parse_conf<T>(conf: &ConfLayers, key: &str) -> Result<Option<T>, anyhow::Error>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let Some(value) = conf.get_conf(key) else {
+        return Ok(None);
+    };
+    let value = T::from_str(&value.to_trimmed())
+        .map_err(|err| anyhow::anyhow!("invalid value for `{key}`: {err}"))?;
+    Ok(Some(value))
+}
+
+/// Call the macro `$callback` with `$args` and the list of the fields
+/// of the configuration with the name of their option.
+macro_rules! with_options {
+    ($callback:ident!($($args:tt)*)) => {
+        $callback!($($args)* {
+            network => "network",
+            port => "port",
+            node => "backend",
+            wallet => "wallet",
+            core_url => "core-url",
+            core_user => "core-user",
+            core_pass => "core-pass",
+            private_key => "dev-private-key",
+            channels_keys => "dev-force-channel-secrets",
+            log_file => "log-file",
+            log_level => "log-level",
+            log_targets => "log-level-target",
+            alias => "alias",
+            rgb_color => "rgb-color",
+            announce_addr => "announce-addr",
+            announce_interval_secs => "announce-interval-secs",
+            bind_addr => "bind-addr",
+            proxy => "proxy",
+            tor_only => "tor-only",
+            tor_control => "tor-control",
+            tor_password => "tor-password",
+            max_inbound_peers => "max-inbound-peers",
+            peer_denylist => "peer-denylist",
+            peer_allowlist => "peer-allowlist",
+            min_channel_size_sat => "min-channel-size-sat",
+            max_channel_size_sat => "max-channel-size-sat",
+            max_inbound_channels_per_peer => "max-inbound-channels-per-peer",
+            max_pending_channels => "max-pending-channels",
+            max_dust_htlc_exposure_msat => "max-dust-htlc-exposure-msat",
+            anchor_channels => "anchor-channels",
+            zero_conf_peers => "trusted-zero-conf-peers",
+            pending_close_deadline_secs => "pending-close-deadline-secs",
+            force_close_after_deadline => "force-close-after-deadline",
+            scan_concurrency => "scan-concurrency",
+            esplora_urls => "esplora-url",
+            bip157_peers => "bip157-peer",
+            auto_reconnect => "auto-reconnect",
+            ping_disconnect_secs => "ping-disconnect-secs",
+            rgs_url => "rgs-url",
+            rgs_refresh_interval_secs => "rgs-refresh-interval-secs",
+            gossip_utxo_lookup => "gossip-utxo-lookup",
+            event_driven_sync => "event-driven-sync",
+            wallet_sync_interval_secs => "wallet-sync-interval-secs",
+            min_fee_rate_sat_vb => "min-fee-rate-sat-vb",
+            account_index => "account-index",
+            funding_min_confirmations => "funding-min-confirmations",
+            wallet_birthday_height => "wallet-birthday-height",
+            pay_timeout_secs => "pay-timeout-secs",
+            fee_estimates_url => "fee-estimates-url",
+            fee_refresh_interval_secs => "fee-refresh-interval-secs",
+            min_fee_rate_sat_kw => "min-fee-rate-sat-kw",
+            max_fee_rate_sat_kw => "max-fee-rate-sat-kw",
+            shutdown_timeout_secs => "shutdown-timeout-secs",
+            event_queue_size => "event-queue-size",
+            ready_max_blocks_behind => "ready-max-blocks-behind",
+            ready_wallet_staleness_secs => "ready-wallet-staleness-secs",
+            http_bind => "http-bind",
+            http_tls_cert => "http-tls-cert",
+            http_tls_key => "http-tls-key",
+            http_cors_origins => "http-cors-origin",
+            http_max_body_bytes => "http-max-body-bytes",
+            webhooks => "webhook",
+            webhook_max_attempts => "webhook-max-attempts",
+            plugin_dir => "plugin-dir",
+            compat_cln => "compat-cln",
+            developer => "developer",
+            monitor_backup_dir => "monitor-backup-dir",
+            remote_storage_url => "remote-storage-url",
+            remote_storage_token => "remote-storage-token",
+            remote_storage_max_lag_secs => "remote-storage-max-lag-secs",
+            remote_storage_restore => "remote-storage-restore",
+            keys_scheme => "keys-scheme",
+            signet_challenge => "signet-challenge",
+            watchtowers => "watchtower",
+            tower_bind => "tower-bind",
+            tower_max_appointments => "tower-max-appointments",
+            tower_subscription_blocks => "tower-subscription-blocks",
+            autofees => "autofees",
+            autofees_interval_secs => "autofees-interval-secs",
+            autofees_min_ppm => "autofees-min-ppm",
+            autofees_max_ppm => "autofees-max-ppm",
+            autofees_max_change_ppm => "autofees-max-change-ppm",
+            autofees_hysteresis_ppm => "autofees-hysteresis-ppm",
+        })
+    };
+}
+
+/// Push the name of the options whose field differs between
+/// `$old` and `$new`.
+macro_rules! changed_options {
+    ($old:expr, $new:expr, $changed:expr, { $($field:ident => $key:literal),* $(,)? }) => {
+        $(
+            if $old.$field != $new.$field {
+                $changed.push($key);
+            }
+        )*
+    };
+}
+
+/// Push the values of the options of `$conf`, with the layer where
+/// they come from.
+macro_rules! option_values {
+    ($conf:expr, $options:expr, { $($field:ident => $key:literal),* $(,)? }) => {
+        $(
+            $options.push(ConfOption {
+                key: $key,
+                values: $conf
+                    .$field
+                    .conf_values()
+                    .iter()
+                    .map(|value| redact($key, value))
+                    .collect(),
+                source: $conf.layers.source($key),
+            });
+        )*
+    };
+}
+
+impl LampoConf {
+    /// The options of `other` with a value different from ours.
+    pub fn changed_options(&self, other: &LampoConf) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        with_options!(changed_options!(self, other, changed,));
+        changed
+    }
+
+    /// The effective value of every option with the layer where it
+    /// comes from, the secrets are redacted.
+    pub fn list_options(&self) -> Vec<ConfOption> {
+        let mut options = Vec::new();
+        with_options!(option_values!(self, options,));
+        options
+    }
+
+    /// Take the values of the `RELOADABLE_OPTIONS` from `other`.
+    pub fn apply_reloadable(&mut self, other: &LampoConf) {
+        self.log_level = other.log_level.clone();
+        self.log_targets = other.log_targets.clone();
+        self.min_fee_rate_sat_kw = other.min_fee_rate_sat_kw;
+        self.max_fee_rate_sat_kw = other.max_fee_rate_sat_kw;
+        self.fee_estimates_url = other.fee_estimates_url.clone();
+        self.esplora_urls = other.esplora_urls.clone();
+        self.webhooks = other.webhooks.clone();
+        for key in RELOADABLE_OPTIONS {
+            self.layers.copy_option(&other.layers, key);
+        }
     }
 }
 
@@ -245,23 +1318,61 @@ impl LampoConf {
         format!("{}/{}", self.root_path, self.network)
     }
 
-    pub fn get_values(&self, key: &str) -> Option<Vec<String>> {
-        match self.inner {
-            Some(ref conf) => Some(conf.get_confs(key)),
-            None => None,
+    /// Return true if the node runs on a signet that is not the
+    /// public one.
+    pub fn is_custom_signet(&self) -> bool {
+        self.network == Network::Signet
+            && self
+                .signet_challenge
+                .as_ref()
+                .is_some_and(|challenge| challenge.to_hex_string() != SIGNET_CHALLENGE)
+    }
+
+    /// The magic of the p2p messages of the network.
+    pub fn network_magic(&self) -> [u8; 4] {
+        match &self.signet_challenge {
+            Some(challenge) if self.network == Network::Signet => signet_magic(challenge),
+            _ => self.network.magic().to_bytes(),
         }
     }
 
-    pub fn get_value(&self, key: &str) -> Result<Option<String>, anyhow::Error> {
-        let conf = self
-            .inner
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("Lampo configuration was not loaded"))?;
+    /// The public esplora instance of the network, a custom signet
+    /// has none.
+    pub fn default_esplora_url(&self) -> Option<&'static str> {
+        if self.is_custom_signet() {
+            return None;
+        }
+        public_esplora_url(self.network)
+    }
 
-        let Some(value) = conf.get_conf(key).map_err(|err| anyhow::anyhow!("{err}"))? else {
-            return Ok(None);
-        };
-        Ok(Some(value))
+    /// The values of the option `key`, from the layer with the
+    /// highest precedence that sets it.
+    pub fn get_values(&self, key: &str) -> Vec<String> {
+        self.layers.get_confs(key)
+    }
+
+    pub fn get_value(&self, key: &str) -> Option<String> {
+        self.layers.get_conf(key)
+    }
+
+    /// The addresses where lampo listens for the p2p connections.
+    ///
+    /// When `bind-addr` is not configured lampo listens on the first
+    /// ip that it announces, or on localhost, on the `port` option.
+    pub fn bind_addrs(&self) -> Result<Vec<SocketAddr>, anyhow::Error> {
+        if !self.bind_addr.is_empty() {
+            return Ok(self.bind_addr.clone());
+        }
+        let ip = self
+            .announce_addr
+            .iter()
+            .find_map(|addr| match addr {
+                SocketAddress::TcpIpV4 { addr, .. } => Some(IpAddr::from(*addr)),
+                SocketAddress::TcpIpV6 { addr, .. } => Some(IpAddr::from(*addr)),
+                _ => None,
+            })
+            .unwrap_or(IpAddr::from([127, 0, 0, 1]));
+        Ok(vec![SocketAddr::new(ip, u16::try_from(self.port)?)])
     }
 
     pub fn set_network(&mut self, network: &str) -> anyhow::Result<()> {
@@ -280,3 +1391,231 @@ impl TrimmedString for String {
         self.trim().to_owned()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+
+    use super::{check, ConfSource, LampoConf, LampoConfBuilder};
+    use super::{parse_announce_addr, parse_bind_addr, parse_log_target};
+    use super::{parse_rgb_color, parse_webhook};
+    use super::{signet_magic, ScriptBuf, SIGNET_CHALLENGE};
+    use super::{Network, SocketAddress, WebhookConf};
+
+    #[test]
+    fn parse_color() {
+        assert_eq!(parse_rgb_color("ff8000").unwrap(), [0xff, 0x80, 0x00]);
+        assert_eq!(parse_rgb_color("#00ff00").unwrap(), [0x00, 0xff, 0x00]);
+        assert!(parse_rgb_color("ff80").is_err());
+        assert!(parse_rgb_color("gg0000").is_err());
+    }
+
+    #[test]
+    fn parse_announce_addresses() {
+        let addr = parse_announce_addr("1.2.3.4", 9735).unwrap();
+        assert_eq!(
+            addr,
+            SocketAddress::TcpIpV4 {
+                addr: [1, 2, 3, 4],
+                port: 9735
+            }
+        );
+        let addr = parse_announce_addr("1.2.3.4:19735", 9735).unwrap();
+        assert_eq!(
+            addr,
+            SocketAddress::TcpIpV4 {
+                addr: [1, 2, 3, 4],
+                port: 19735
+            }
+        );
+        let onion = "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion:9735";
+        assert!(matches!(
+            parse_announce_addr(onion, 9735).unwrap(),
+            SocketAddress::OnionV3 { port: 9735, .. }
+        ));
+        assert!(parse_announce_addr("not an address", 9735).is_err());
+    }
+
+    #[test]
+    fn parse_bind_addresses() {
+        let addr = parse_bind_addr("127.0.0.1", 9735).unwrap();
+        assert_eq!(addr, SocketAddr::from_str("127.0.0.1:9735").unwrap());
+        let addr = parse_bind_addr("0.0.0.0:19735", 9735).unwrap();
+        assert_eq!(addr, SocketAddr::from_str("0.0.0.0:19735").unwrap());
+        let addr = parse_bind_addr("::1", 9735).unwrap();
+        assert_eq!(addr, SocketAddr::from_str("[::1]:9735").unwrap());
+        let addr = parse_bind_addr("[::]:19735", 9735).unwrap();
+        assert_eq!(addr, SocketAddr::from_str("[::]:19735").unwrap());
+        assert!(parse_bind_addr("localhost:9735", 9735).is_err());
+    }
+
+    #[test]
+    fn parse_webhooks() {
+        let webhook =
+            parse_webhook("https://example.com/hook s3cret payment_received,channel_ready")
+                .unwrap();
+        assert_eq!(
+            webhook,
+            WebhookConf {
+                url: "https://example.com/hook".to_owned(),
+                secret: "s3cret".to_owned(),
+                events: vec!["payment_received".to_owned(), "channel_ready".to_owned()],
+            }
+        );
+        assert!(parse_webhook("http://127.0.0.1:8080 s3cret")
+            .unwrap()
+            .events
+            .is_empty());
+        assert!(parse_webhook("https://example.com/hook").is_err());
+        assert!(parse_webhook("example.com s3cret").is_err());
+    }
+
+    #[test]
+    fn parse_log_targets() {
+        let target = parse_log_target("lampo_bdk_wallet=DEBUG").unwrap();
+        assert_eq!(target, ("lampo_bdk_wallet".to_owned(), "debug".to_owned()));
+        assert!(parse_log_target("ldk").is_err());
+        assert!(parse_log_target("ldk=verbose").is_err());
+    }
+
+    #[test]
+    fn signet_magic_of_the_challenge() {
+        let public = ScriptBuf::from_hex(SIGNET_CHALLENGE).unwrap();
+        assert_eq!(signet_magic(&public), [0x0a, 0x03, 0xcf, 0x40]);
+        assert_eq!(signet_magic(&public), Network::Signet.magic().to_bytes());
+        let mutinynet = ScriptBuf::from_hex(
+            "512102f7561d208dd9ae99bf497273e16f389bdbd6c4742ddb8e6b216e64fa2928ad8f51ae",
+        )
+        .unwrap();
+        assert_eq!(signet_magic(&mutinynet), [0xa5, 0xdf, 0x2d, 0xcb]);
+
+        let mut conf = LampoConf::default();
+        conf.network = Network::Signet;
+        assert!(!conf.is_custom_signet());
+        assert_eq!(conf.network_magic(), [0x0a, 0x03, 0xcf, 0x40]);
+        assert_eq!(
+            conf.default_esplora_url(),
+            Some("https://mempool.space/signet/api")
+        );
+        conf.signet_challenge = Some(public);
+        assert!(!conf.is_custom_signet());
+
+        conf.signet_challenge = Some(mutinynet);
+        assert!(conf.is_custom_signet());
+        assert_eq!(conf.network_magic(), [0xa5, 0xdf, 0x2d, 0xcb]);
+        assert_eq!(conf.default_esplora_url(), None);
+    }
+
+    #[test]
+    fn changed_options() {
+        let conf = LampoConf::default();
+        let mut other = conf.clone();
+        assert!(conf.changed_options(&other).is_empty());
+        other.min_fee_rate_sat_kw = 1000;
+        other.port = 9735;
+        assert_eq!(
+            conf.changed_options(&other),
+            vec!["port", "min-fee-rate-sat-kw"]
+        );
+        let mut reloaded = conf.clone();
+        reloaded.apply_reloadable(&other);
+        assert_eq!(reloaded.changed_options(&other), vec!["port"]);
+    }
+
+    #[test]
+    fn options_precedence() {
+        let dir = std::env::temp_dir().join(format!("lampo-conf-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let root_path = dir.to_str().unwrap();
+        LampoConf::prepare_directories(root_path, Some(Network::Regtest)).unwrap();
+        std::fs::write(
+            dir.join("regtest/lampo.conf"),
+            "network=regtest\nport=9735\nalias=file\nlog-level=debug\nscan-concurrency=4\n",
+        )
+        .unwrap();
+        let env = vec![
+            ("LAMPO_NETWORK".to_owned(), "regtest".to_owned()),
+            ("LAMPO_ALIAS".to_owned(), "env".to_owned()),
+            ("LAMPO_LOG_LEVEL".to_owned(), "trace".to_owned()),
+        ];
+        let conf = LampoConfBuilder::new(root_path)
+            .env(env)
+            .unwrap()
+            .cli("alias", "cli")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(conf.network, Network::Regtest);
+        assert_eq!(conf.alias.as_deref(), Some("cli"));
+        assert_eq!(conf.log_level, "trace");
+        assert_eq!(conf.scan_concurrency, 4);
+        assert_eq!(conf.port, 9735);
+        assert_eq!(conf.announce_interval_secs, 3600);
+
+        let source = |key: &str| {
+            let options = conf.list_options();
+            options
+                .iter()
+                .find(|option| option.key == key)
+                .unwrap()
+                .source
+        };
+        assert_eq!(source("alias"), ConfSource::Cli);
+        assert_eq!(source("log-level"), ConfSource::Env);
+        assert_eq!(source("scan-concurrency"), ConfSource::File);
+        assert_eq!(source("announce-interval-secs"), ConfSource::Default);
+
+        // the file can not move the node to another network
+        std::fs::write(dir.join("regtest/lampo.conf"), "network=signet\n").unwrap();
+        let err = LampoConfBuilder::new(root_path)
+            .cli("network", "regtest")
+            .unwrap()
+            .build()
+            .unwrap_err();
+        assert!(err.to_string().contains("sets `network=signet`"), "{err}");
+        assert!(LampoConfBuilder::new(root_path)
+            .cli("port", "not a port")
+            .is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn list_options_redacts_the_secrets() {
+        let mut conf = LampoConf::default();
+        conf.core_user = Some("lampo".to_owned());
+        conf.core_pass = Some("hunter2".to_owned());
+        conf.remote_storage_token = Some("bearer".to_owned());
+        conf.webhooks = vec![WebhookConf {
+            url: "https://example.com/hook".to_owned(),
+            secret: "s3cret".to_owned(),
+            events: vec!["payment_received".to_owned()],
+        }];
+        let options = conf.list_options();
+        let values = |key: &str| {
+            options
+                .iter()
+                .find(|option| option.key == key)
+                .unwrap()
+                .values
+                .clone()
+        };
+        assert_eq!(values("core-user"), vec!["lampo"]);
+        assert_eq!(values("core-pass"), vec!["<redacted>"]);
+        assert_eq!(values("remote-storage-token"), vec!["<redacted>"]);
+        assert_eq!(
+            values("webhook"),
+            vec!["https://example.com/hook <redacted> payment_received"]
+        );
+        assert_eq!(values("tor-password"), Vec::<String>::new());
+        assert_eq!(values("port"), vec!["19735"]);
+
+        // every option of the file can be listed
+        for key in check::options() {
+            assert!(
+                options.iter().any(|option| option.key == key),
+                "`{key}` is not listed"
+            );
+        }
+    }
+}
@@ -0,0 +1,337 @@
+//! Strict check of the `lampo.conf` file.
+//!
+//! The file used to be parsed with `clightningrpc_conf` that accepts
+//! any key, so a typo in the name of an option was silently ignored.
+//! Here every line is checked against the options known by lampo and
+//! the type of their values, and the errors point to the file and the
+//! line. The same check is done on the values given by the environment
+//! and by the command line.
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use bitcoin::Network;
+
+use crate::logger;
+use crate::types::NodeId;
+
+/// The type of the value of an option.
+#[derive(Clone, Copy, Debug)]
+enum Kind {
+    /// Any string, checked (if needed) when the option is used.
+    Text,
+    Bool,
+    /// An unsigned integer.
+    Number,
+    Decimal,
+    Network,
+    /// An `ip:port`.
+    Addr,
+    LogLevel,
+    /// Node ids separated by a comma.
+    NodeIds,
+}
+
+/// The options known by lampo.
+const OPTIONS: [(&str, Kind); 83] = [
+    ("network", Kind::Network),
+    ("port", Kind::Number),
+    ("backend", Kind::Text),
+    ("wallet", Kind::Text),
+    ("core-url", Kind::Text),
+    ("core-user", Kind::Text),
+    ("core-pass", Kind::Text),
+    ("dev-private-key", Kind::Text),
+    ("dev-force-channel-secrets", Kind::Text),
+    ("log-level", Kind::LogLevel),
+    ("log-level-target", Kind::Text),
+    ("log-file", Kind::Text),
+    ("alias", Kind::Text),
+    ("rgb-color", Kind::Text),
+    ("announce-addr", Kind::Text),
+    ("announce-interval-secs", Kind::Number),
+    ("bind-addr", Kind::Text),
+    ("proxy", Kind::Addr),
+    ("tor-only", Kind::Bool),
+    ("tor-control", Kind::Addr),
+    ("tor-password", Kind::Text),
+    ("max-inbound-peers", Kind::Number),
+    ("peer-denylist", Kind::NodeIds),
+    ("peer-allowlist", Kind::NodeIds),
+    ("min-channel-size-sat", Kind::Number),
+    ("max-channel-size-sat", Kind::Number),
+    ("max-inbound-channels-per-peer", Kind::Number),
+    ("max-pending-channels", Kind::Number),
+    ("max-dust-htlc-exposure-msat", Kind::Number),
+    ("anchor-channels", Kind::Bool),
+    ("trusted-zero-conf-peers", Kind::NodeIds),
+    ("pending-close-deadline-secs", Kind::Number),
+    ("force-close-after-deadline", Kind::Bool),
+    ("scan-concurrency", Kind::Number),
+    ("esplora-url", Kind::Text),
+    ("bip157-peer", Kind::Text),
+    ("auto-reconnect", Kind::Bool),
+    ("ping-disconnect-secs", Kind::Number),
+    ("rgs-url", Kind::Text),
+    ("rgs-refresh-interval-secs", Kind::Number),
+    ("gossip-utxo-lookup", Kind::Bool),
+    ("event-driven-sync", Kind::Bool),
+    ("wallet-sync-interval-secs", Kind::Number),
+    ("min-fee-rate-sat-vb", Kind::Decimal),
+    ("account-index", Kind::Number),
+    ("funding-min-confirmations", Kind::Number),
+    ("wallet-birthday-height", Kind::Number),
+    ("pay-timeout-secs", Kind::Number),
+    ("fee-estimates-url", Kind::Text),
+    ("fee-refresh-interval-secs", Kind::Number),
+    ("min-fee-rate-sat-kw", Kind::Number),
+    ("max-fee-rate-sat-kw", Kind::Number),
+    ("shutdown-timeout-secs", Kind::Number),
+    ("event-queue-size", Kind::Number),
+    ("ready-max-blocks-behind", Kind::Number),
+    ("ready-wallet-staleness-secs", Kind::Number),
+    ("http-bind", Kind::Addr),
+    ("http-tls-cert", Kind::Text),
+    ("http-tls-key", Kind::Text),
+    ("http-cors-origin", Kind::Text),
+    ("http-max-body-bytes", Kind::Number),
+    ("webhook", Kind::Text),
+    ("webhook-max-attempts", Kind::Number),
+    ("plugin-dir", Kind::Text),
+    ("compat-cln", Kind::Bool),
+    ("developer", Kind::Bool),
+    ("monitor-backup-dir", Kind::Text),
+    ("remote-storage-url", Kind::Text),
+    ("remote-storage-token", Kind::Text),
+    ("remote-storage-max-lag-secs", Kind::Number),
+    ("remote-storage-restore", Kind::Bool),
+    ("keys-scheme", Kind::Text),
+    ("signet-challenge", Kind::Text),
+    ("watchtower", Kind::Text),
+    ("tower-bind", Kind::Addr),
+    ("tower-max-appointments", Kind::Number),
+    ("tower-subscription-blocks", Kind::Number),
+    ("autofees", Kind::Bool),
+    ("autofees-interval-secs", Kind::Number),
+    ("autofees-min-ppm", Kind::Number),
+    ("autofees-max-ppm", Kind::Number),
+    ("autofees-max-change-ppm", Kind::Number),
+    ("autofees-hysteresis-ppm", Kind::Number),
+];
+
+/// A `key=value` line of the configuration file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfLine {
+    /// The number of the line, from 1.
+    pub line: usize,
+    pub key: String,
+    pub value: String,
+}
+
+/// Read the options of the configuration file, the comments and the
+/// empty lines are skipped.
+pub fn parse_lines(path: &str, content: &str) -> Result<Vec<ConfLine>, anyhow::Error> {
+    let mut lines = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let number = index + 1;
+        let Some((key, value)) = line.split_once('=') else {
+            anyhow::bail!(
+                "{path}:{number}: option `{line}` without a value, it must be `{line}=<value>`"
+            );
+        };
+        lines.push(ConfLine {
+            line: number,
+            key: key.trim().to_owned(),
+            value: value.trim().to_owned(),
+        });
+    }
+    Ok(lines)
+}
+
+/// Check the configuration file at `path`, and return its options.
+///
+/// Fails at the first unknown option, value that has not the type
+/// of its option, or combination of options that is not valid.
+pub fn check_file(path: &str) -> Result<Vec<ConfLine>, anyhow::Error> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| anyhow::anyhow!("impossible read `{path}`: {err}"))?;
+    let lines = parse_lines(path, &content)?;
+    for line in &lines {
+        check_line(path, line)?;
+    }
+    check_combinations(path, &lines)?;
+    Ok(lines)
+}
+
+/// The name of the options known by lampo.
+pub fn options() -> impl Iterator<Item = &'static str> {
+    OPTIONS.iter().map(|(option, _)| *option)
+}
+
+/// Return true if `key` is an option that takes `true` or `false`.
+pub fn is_bool_option(key: &str) -> bool {
+    OPTIONS
+        .iter()
+        .any(|(option, kind)| *option == key && matches!(kind, Kind::Bool))
+}
+
+fn check_line(path: &str, line: &ConfLine) -> Result<(), anyhow::Error> {
+    let ConfLine { line, key, value } = line;
+    check_option(&format!("{path}:{line}"), key, value)
+}
+
+/// Check the `value` of the option `key`, the errors start with
+/// `origin` that tells where the value comes from.
+pub fn check_option(origin: &str, key: &str, value: &str) -> Result<(), anyhow::Error> {
+    let Some((_, kind)) = OPTIONS.iter().find(|(option, _)| *option == key) else {
+        anyhow::bail!("{origin}: unknown option `{key}`");
+    };
+    let valid = match kind {
+        Kind::Text => !value.is_empty(),
+        Kind::Bool => bool::from_str(value).is_ok(),
+        Kind::Number => u64::from_str(value).is_ok(),
+        Kind::Decimal => f64::from_str(value).is_ok_and(|value| value.is_finite()),
+        Kind::Network => Network::from_str(value).is_ok(),
+        Kind::Addr => SocketAddr::from_str(value).is_ok(),
+        Kind::LogLevel => logger::parse_level(value).is_ok(),
+        Kind::NodeIds => value
+            .split(',')
+            .all(|node_id| NodeId::from_str(node_id.trim()).is_ok()),
+    };
+    if !valid {
+        anyhow::bail!(
+            "{origin}: invalid value `{value}` for `{key}`, it must be {}",
+            expected(*kind)
+        );
+    }
+    Ok(())
+}
+
+fn expected(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Text => "a string",
+        Kind::Bool => "`true` or `false`",
+        Kind::Number => "a positive integer",
+        Kind::Decimal => "a number",
+        Kind::Network => "one of `bitcoin`, `testnet`, `signet` or `regtest`",
+        Kind::Addr => "an `ip:port`",
+        Kind::LogLevel => "one of `off`, `error`, `warn`, `info`, `debug` or `trace`",
+        Kind::NodeIds => "a list of node ids separated by a comma",
+    }
+}
+
+/// Check the options that are valid alone, but not together.
+fn check_combinations(path: &str, lines: &[ConfLine]) -> Result<(), anyhow::Error> {
+    // when an option is repeated the last value wins
+    let find = |key: &str| lines.iter().rev().find(|line| line.key == key);
+
+    if let Some(tor_only) = find("tor-only").filter(|line| line.value == "true") {
+        if find("proxy").is_none() {
+            anyhow::bail!(
+                "{path}:{}: `tor-only` needs a `proxy` to connect to the peers",
+                tor_only.line
+            );
+        }
+        // announcing a clearnet address would leak the ip that
+        // `tor-only` hides.
+        let clearnet = lines
+            .iter()
+            .filter(|line| line.key == "announce-addr")
+            .flat_map(|line| line.value.split(',').map(move |addr| (line, addr.trim())))
+            .find(|(_, addr)| IpAddr::from_str(addr).is_ok() || SocketAddr::from_str(addr).is_ok());
+        if let Some((line, addr)) = clearnet {
+            anyhow::bail!(
+                "{path}:{}: `announce-addr` `{addr}` is a clearnet address, but `tor-only` is set at line {}",
+                line.line,
+                tor_only.line
+            );
+        }
+    }
+
+    let cert = find("http-tls-cert");
+    let key = find("http-tls-key");
+    if let Some(line) = cert.xor(key) {
+        anyhow::bail!(
+            "{path}:{}: `http-tls-cert` and `http-tls-key` must be set together",
+            line.line
+        );
+    }
+    if let (Some(cert), None) = (cert, find("http-bind")) {
+        anyhow::bail!(
+            "{path}:{}: `http-tls-cert` needs the gateway enabled with `http-bind`",
+            cert.line
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_combinations, check_line, parse_lines};
+
+    const PATH: &str = "lampo.conf";
+
+    fn check(content: &str) -> Result<(), anyhow::Error> {
+        let lines = parse_lines(PATH, content)?;
+        for line in &lines {
+            check_line(PATH, line)?;
+        }
+        check_combinations(PATH, &lines)
+    }
+
+    #[test]
+    fn check_known_options() {
+        let content = "# comment\n\nnetwork=regtest\nport=19735\nlog-level-target=ldk=debug\n";
+        let lines = parse_lines(PATH, content).unwrap();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[2].line, 5);
+        assert_eq!(lines[2].value, "ldk=debug");
+        assert!(check(content).is_ok());
+    }
+
+    #[test]
+    fn reject_unknown_options() {
+        let err = check("network=regtest\nmin-fee-rate-sat-kv=300\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "lampo.conf:2: unknown option `min-fee-rate-sat-kv`"
+        );
+        let err = check("network=regtest\ntor-only\n").unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("lampo.conf:2: option `tor-only`"));
+    }
+
+    #[test]
+    fn reject_invalid_values() {
+        let err = check("port=-1").unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("lampo.conf:1: invalid value `-1` for `port`"));
+        assert!(check("tor-only=yes").is_err());
+        assert!(check("proxy=localhost").is_err());
+        assert!(check("log-level=verbose").is_err());
+        assert!(check("min-fee-rate-sat-vb=0.5").is_ok());
+    }
+
+    #[test]
+    fn reject_invalid_combinations() {
+        let err = check("tor-only=true").unwrap_err();
+        assert!(err.to_string().contains("needs a `proxy`"));
+        let err =
+            check("proxy=127.0.0.1:9050\ntor-only=true\nannounce-addr=1.2.3.4:9735").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "lampo.conf:3: `announce-addr` `1.2.3.4:9735` is a clearnet address, but `tor-only` is set at line 2"
+        );
+        let onion = "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion:9735";
+        assert!(check(&format!(
+            "proxy=127.0.0.1:9050\ntor-only=true\nannounce-addr={onion}"
+        ))
+        .is_ok());
+        assert!(check("http-bind=127.0.0.1:9835\nhttp-tls-cert=cert.pem").is_err());
+    }
+}
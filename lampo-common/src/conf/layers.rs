@@ -0,0 +1,328 @@
+//! The layers of the configuration.
+//!
+//! An option takes its value from the first layer that sets it: the
+//! command line, the environment, the `lampo.conf` file, and then the
+//! default of lampo. Every option can be set by the environment with
+//! `LAMPO_` and its name in upper case, with `_` instead of `-` (e.g.
+//! `LAMPO_ESPLORA_URL` for `esplora-url`), and the lines of the value
+//! of a variable are the values of an option that can be repeated.
+//!
+//! The layers are kept inside the configuration, so the node can tell
+//! where the value of an option comes from and read again only the
+//! file on `reload`.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+
+use bitcoin::{Network, ScriptBuf};
+
+use super::{check, SocketAddress, WebhookConf};
+use crate::keys::KeysScheme;
+use crate::types::NodeId;
+
+/// The prefix of the environment variables of the options.
+pub const ENV_PREFIX: &str = "LAMPO_";
+
+/// What is shown instead of a secret.
+pub const REDACTED: &str = "<redacted>";
+
+/// The options with a secret as value.
+const SECRET_OPTIONS: [&str; 5] = [
+    "core-pass",
+    "tor-password",
+    "remote-storage-token",
+    "dev-private-key",
+    "dev-force-channel-secrets",
+];
+
+/// Where the value of an option comes from, from the lowest precedence
+/// to the highest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ConfSource {
+    Default,
+    File,
+    Env,
+    Cli,
+}
+
+impl fmt::Display for ConfSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = match self {
+            Self::Default => "default",
+            Self::File => "file",
+            Self::Env => "env",
+            Self::Cli => "cli",
+        };
+        write!(f, "{source}")
+    }
+}
+
+/// The values of the options inside each layer.
+#[derive(Clone, Debug, Default)]
+pub struct ConfLayers {
+    values: BTreeMap<String, BTreeMap<ConfSource, Vec<String>>>,
+}
+
+impl ConfLayers {
+    /// Add a `value` to the option `key` inside the layer `source`.
+    pub fn push(&mut self, source: ConfSource, key: &str, value: &str) {
+        self.values
+            .entry(key.to_owned())
+            .or_default()
+            .entry(source)
+            .or_default()
+            .push(value.to_owned());
+    }
+
+    /// Replace the file layer with the options of the file at `path`,
+    /// after checking them.
+    pub fn load_file(&mut self, path: &str) -> Result<(), anyhow::Error> {
+        let lines = check::check_file(path)?;
+        for layers in self.values.values_mut() {
+            layers.remove(&ConfSource::File);
+        }
+        for line in lines {
+            self.push(ConfSource::File, &line.key, &line.value);
+        }
+        Ok(())
+    }
+
+    /// Take the options from the environment variables `vars`, the
+    /// variables that are not an option are ignored.
+    pub fn load_env<I>(&mut self, vars: I) -> Result<(), anyhow::Error>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        for (name, value) in vars {
+            let Some(key) = check::options().find(|key| env_var(key) == name) else {
+                continue;
+            };
+            for value in value
+                .lines()
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+            {
+                check::check_option(&name, key, value)?;
+                self.push(ConfSource::Env, key, value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Take the values of the option `key` in every layer from `other`.
+    pub fn copy_option(&mut self, other: &ConfLayers, key: &str) {
+        match other.values.get(key) {
+            Some(layers) => self.values.insert(key.to_owned(), layers.clone()),
+            None => self.values.remove(key),
+        };
+    }
+
+    /// The values of `key` inside the layer with the highest
+    /// precedence that sets it.
+    pub fn get_confs(&self, key: &str) -> Vec<String> {
+        self.values
+            .get(key)
+            .and_then(|layers| layers.values().next_back())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The value of `key`, the last one when it is repeated.
+    pub fn get_conf(&self, key: &str) -> Option<String> {
+        self.get_confs(key).pop()
+    }
+
+    /// The values of `key` inside the layer `source`.
+    pub fn get_confs_in(&self, source: ConfSource, key: &str) -> Vec<String> {
+        self.values
+            .get(key)
+            .and_then(|layers| layers.get(&source))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The layer where the value of `key` comes from.
+    pub fn source(&self, key: &str) -> ConfSource {
+        self.values
+            .get(key)
+            .and_then(|layers| layers.keys().next_back())
+            .copied()
+            .unwrap_or(ConfSource::Default)
+    }
+}
+
+/// The environment variable of the option `key`.
+pub fn env_var(key: &str) -> String {
+    format!("{ENV_PREFIX}{}", key.to_uppercase().replace('-', "_"))
+}
+
+/// The `value` of the option `key` without its secrets.
+pub fn redact(key: &str, value: &str) -> String {
+    if SECRET_OPTIONS.contains(&key) {
+        return REDACTED.to_owned();
+    }
+    // `<url> <secret> [<kind>,...]`
+    if key == "webhook" {
+        let mut parts = value.split_whitespace().collect::<Vec<_>>();
+        if let Some(secret) = parts.get_mut(1) {
+            *secret = REDACTED;
+        }
+        return parts.join(" ");
+    }
+    value.to_owned()
+}
+
+/// The effective value of an option.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfOption {
+    pub key: &'static str,
+    /// The values of the option, with the secrets redacted.
+    pub values: Vec<String>,
+    pub source: ConfSource,
+}
+
+/// A field of the configuration shown as the values of its option.
+pub(super) trait ConfValues {
+    fn conf_values(&self) -> Vec<String>;
+}
+
+macro_rules! display_values {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl ConfValues for $ty {
+                fn conf_values(&self) -> Vec<String> {
+                    vec![self.to_string()]
+                }
+            }
+        )*
+    };
+}
+
+display_values!(
+    String,
+    bool,
+    u32,
+    u64,
+    usize,
+    f32,
+    Network,
+    SocketAddr,
+    SocketAddress,
+    NodeId,
+    KeysScheme
+);
+
+impl<T: ConfValues> ConfValues for Option<T> {
+    fn conf_values(&self) -> Vec<String> {
+        self.iter().flat_map(ConfValues::conf_values).collect()
+    }
+}
+
+impl<T: ConfValues> ConfValues for Vec<T> {
+    fn conf_values(&self) -> Vec<String> {
+        self.iter().flat_map(ConfValues::conf_values).collect()
+    }
+}
+
+impl ConfValues for [u8; 3] {
+    fn conf_values(&self) -> Vec<String> {
+        vec![hex::encode(self)]
+    }
+}
+
+impl ConfValues for ScriptBuf {
+    fn conf_values(&self) -> Vec<String> {
+        vec![self.to_hex_string()]
+    }
+}
+
+/// A target of `log-level-target`.
+impl ConfValues for (String, String) {
+    fn conf_values(&self) -> Vec<String> {
+        vec![format!("{}={}", self.0, self.1)]
+    }
+}
+
+impl ConfValues for WebhookConf {
+    fn conf_values(&self) -> Vec<String> {
+        let mut webhook = format!("{} {}", self.url, self.secret);
+        if !self.events.is_empty() {
+            webhook.push_str(&format!(" {}", self.events.join(",")));
+        }
+        vec![webhook]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{env_var, redact, ConfLayers, ConfSource, REDACTED};
+
+    #[test]
+    fn env_var_of_the_options() {
+        assert_eq!(env_var("network"), "LAMPO_NETWORK");
+        assert_eq!(env_var("esplora-url"), "LAMPO_ESPLORA_URL");
+        assert_eq!(
+            env_var("remote-storage-token"),
+            "LAMPO_REMOTE_STORAGE_TOKEN"
+        );
+    }
+
+    #[test]
+    fn layers_precedence() {
+        let mut layers = ConfLayers::default();
+        layers.push(ConfSource::File, "port", "19735");
+        layers.push(ConfSource::File, "alias", "file");
+        layers.push(ConfSource::File, "esplora-url", "https://file.one");
+        layers.push(ConfSource::File, "esplora-url", "https://file.two");
+        layers.push(ConfSource::File, "log-level", "debug");
+        layers.push(ConfSource::Cli, "alias", "cli");
+        let env = vec![
+            ("LAMPO_ALIAS".to_owned(), "env".to_owned()),
+            ("LAMPO_PORT".to_owned(), "9735".to_owned()),
+            (
+                "LAMPO_ESPLORA_URL".to_owned(),
+                "https://env.one\nhttps://env.two\n".to_owned(),
+            ),
+            // not an option of lampo
+            ("LAMPO_UNIX".to_owned(), "/tmp/lampod.socket".to_owned()),
+        ];
+        layers.load_env(env).unwrap();
+
+        assert_eq!(layers.get_conf("alias").as_deref(), Some("cli"));
+        assert_eq!(layers.source("alias"), ConfSource::Cli);
+        assert_eq!(layers.get_conf("port").as_deref(), Some("9735"));
+        assert_eq!(layers.source("port"), ConfSource::Env);
+        // the values of a layer replace the ones of the lower layers
+        assert_eq!(
+            layers.get_confs("esplora-url"),
+            vec!["https://env.one", "https://env.two"]
+        );
+        assert_eq!(layers.get_conf("log-level").as_deref(), Some("debug"));
+        assert_eq!(layers.source("log-level"), ConfSource::File);
+        assert_eq!(layers.get_conf("proxy"), None);
+        assert_eq!(layers.source("proxy"), ConfSource::Default);
+        assert_eq!(layers.get_confs_in(ConfSource::File, "alias"), vec!["file"]);
+
+        let err = layers
+            .load_env(vec![("LAMPO_PORT".to_owned(), "x".to_owned())])
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("LAMPO_PORT: invalid value `x` for `port`"));
+    }
+
+    #[test]
+    fn redact_the_secrets() {
+        assert_eq!(redact("core-pass", "hunter2"), REDACTED);
+        assert_eq!(redact("remote-storage-token", "abcd"), REDACTED);
+        assert_eq!(redact("tor-password", "abcd"), REDACTED);
+        assert_eq!(
+            redact(
+                "webhook",
+                "https://example.com/hook s3cr3t payment_received"
+            ),
+            format!("https://example.com/hook {REDACTED} payment_received")
+        );
+        assert_eq!(redact("core-user", "lampo"), "lampo");
+    }
+}
@@ -0,0 +1,432 @@
+//! Lampo errors.
+//!
+//! Lampo uses `anyhow` errors, and the failures that a client needs
+//! to tell apart are `LampoError`s, each one with a stable code that
+//! is reported by the JSON RPC 2.0 interface.
+use std::fmt;
+
+pub use anyhow::*;
+
+use crate::backend::BroadcastError;
+use crate::json;
+use crate::limits::ChannelLimitError;
+use crate::wallet::WalletError;
+
+/// JSON RPC 2.0 code for a method that does not exist.
+pub const METHOD_NOT_FOUND: i32 = -32601;
+/// JSON RPC 2.0 code for invalid method params.
+pub const INVALID_PARAMS: i32 = -32602;
+/// Code for a request without a valid token.
+pub const UNAUTHORIZED: i32 = -32001;
+/// Code for a token that does not allow the method.
+pub const PERMISSION_DENIED: i32 = -32002;
+/// Code for a method of Core Lightning that the compat layer
+/// does not serve.
+pub const NOT_IMPLEMENTED: i32 = -32003;
+
+/// The category of a `LampoError`, every category has its own
+/// range of codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    /// The JSON RPC 2.0 codes.
+    Rpc,
+    /// From 100 to 199.
+    Wallet,
+    /// From 200 to 299.
+    Payment,
+    /// From 300 to 399.
+    Channel,
+    /// From 400 to 499.
+    Peer,
+    /// From 500 to 599.
+    Chain,
+    /// From 600 to 699.
+    Datastore,
+}
+
+impl Category {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Rpc => "rpc",
+            Self::Wallet => "wallet",
+            Self::Payment => "payment",
+            Self::Channel => "channel",
+            Self::Peer => "peer",
+            Self::Chain => "chain",
+            Self::Datastore => "datastore",
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// An error with a stable code, the codes must not change because
+/// the clients match on them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LampoError {
+    /// The params of the request are not valid.
+    InvalidParams(String),
+    /// The request does not carry a valid token.
+    Unauthorized(String),
+    /// The token is valid but its scope does not allow the method.
+    PermissionDenied { method: String, scope: String },
+    /// The method of Core Lightning is not served by the compat
+    /// layer, `alternative` is the native method to use.
+    CompatNotImplemented {
+        method: String,
+        alternative: Option<String>,
+    },
+    /// The wallet is not able to build the transaction.
+    Wallet(WalletError),
+    /// Any other failure of the wallet.
+    WalletFailure(String),
+    /// The invoice can not be decoded or it can not be paid as it is.
+    InvalidInvoice(String),
+    /// The invoice is expired.
+    InvoiceExpired,
+    /// There is no route to the destination.
+    RouteNotFound { amount_msat: u64 },
+    /// The amount exceeds the outbound capacity of the usable channels.
+    NotEnoughCapacity {
+        amount_msat: u64,
+        capacity_msat: u64,
+    },
+    /// A payment with the same payment hash was already sent.
+    DuplicatePayment,
+    /// The payment was not done before the timeout.
+    PaymentTimeout { timeout_secs: u64 },
+    /// The payment was canceled by the user.
+    PaymentCanceled,
+    /// An invoice with the same label was already generated.
+    DuplicateInvoiceLabel(String),
+    /// The network graph is still loaded from the disk, so there is
+    /// no route to use yet.
+    GraphLoading { nodes_loaded: usize },
+    /// Any other failure of a payment or of an invoice.
+    PaymentFailure(String),
+    /// The channel is not known.
+    ChannelNotFound(String),
+    /// The channel violates the limits configured by the user.
+    ChannelRejected(String),
+    /// Any other failure of a channel.
+    ChannelFailure(String),
+    /// There is no connection with the peer.
+    PeerNotConnected(String),
+    /// Any other failure with a peer.
+    PeerFailure(String),
+    /// The transaction is rejected by the chain backend.
+    Broadcast(BroadcastError),
+    /// Any other failure of the chain backend.
+    ChainFailure(String),
+    /// The key of the datastore exists, and the mode does not
+    /// allow to replace it.
+    DatastoreKeyExists(String),
+    /// The key of the datastore does not exist.
+    DatastoreKeyNotFound(String),
+    /// The entry of the datastore was changed by someone else.
+    DatastoreGenerationMismatch { expected: u64, current: u64 },
+    /// The value or the whole datastore exceeds its size cap.
+    DatastoreTooLarge(String),
+}
+
+impl LampoError {
+    pub fn code(&self) -> i32 {
+        match self {
+            Self::InvalidParams(_) => INVALID_PARAMS,
+            Self::Unauthorized(_) => UNAUTHORIZED,
+            Self::PermissionDenied { .. } => PERMISSION_DENIED,
+            Self::CompatNotImplemented { .. } => NOT_IMPLEMENTED,
+            Self::Wallet(WalletError::FeeTooLow { .. }) => 100,
+            Self::Wallet(WalletError::InsufficientFunds { .. }) => 101,
+            Self::WalletFailure(_) => 199,
+            Self::InvalidInvoice(_) => 200,
+            Self::InvoiceExpired => 201,
+            Self::RouteNotFound { .. } => 202,
+            Self::NotEnoughCapacity { .. } => 203,
+            Self::DuplicatePayment => 204,
+            Self::PaymentTimeout { .. } => 205,
+            Self::PaymentCanceled => 206,
+            Self::DuplicateInvoiceLabel(_) => 207,
+            Self::GraphLoading { .. } => 208,
+            Self::PaymentFailure(_) => 299,
+            Self::ChannelNotFound(_) => 300,
+            Self::ChannelRejected(_) => 301,
+            Self::ChannelFailure(_) => 399,
+            Self::PeerNotConnected(_) => 400,
+            Self::PeerFailure(_) => 499,
+            Self::Broadcast(_) => 500,
+            Self::ChainFailure(_) => 599,
+            Self::DatastoreKeyExists(_) => 600,
+            Self::DatastoreKeyNotFound(_) => 601,
+            Self::DatastoreGenerationMismatch { .. } => 602,
+            Self::DatastoreTooLarge(_) => 603,
+        }
+    }
+
+    pub fn category(&self) -> Category {
+        match self {
+            Self::InvalidParams(_)
+            | Self::Unauthorized(_)
+            | Self::PermissionDenied { .. }
+            | Self::CompatNotImplemented { .. } => Category::Rpc,
+            Self::Wallet(_) | Self::WalletFailure(_) => Category::Wallet,
+            Self::InvalidInvoice(_)
+            | Self::InvoiceExpired
+            | Self::RouteNotFound { .. }
+            | Self::NotEnoughCapacity { .. }
+            | Self::DuplicatePayment
+            | Self::PaymentTimeout { .. }
+            | Self::PaymentCanceled
+            | Self::DuplicateInvoiceLabel(_)
+            | Self::GraphLoading { .. }
+            | Self::PaymentFailure(_) => Category::Payment,
+            Self::ChannelNotFound(_) | Self::ChannelRejected(_) | Self::ChannelFailure(_) => {
+                Category::Channel
+            }
+            Self::PeerNotConnected(_) | Self::PeerFailure(_) => Category::Peer,
+            Self::Broadcast(_) | Self::ChainFailure(_) => Category::Chain,
+            Self::DatastoreKeyExists(_)
+            | Self::DatastoreKeyNotFound(_)
+            | Self::DatastoreGenerationMismatch { .. }
+            | Self::DatastoreTooLarge(_) => Category::Datastore,
+        }
+    }
+
+    /// The details of the error, reported as `data` of the
+    /// JSON RPC 2.0 error.
+    pub fn data(&self) -> json::Value {
+        let mut data = match self {
+            Self::Wallet(WalletError::FeeTooLow {
+                fee_rate,
+                min_fee_rate,
+            }) => json::json!({
+                "fee_rate": fee_rate,
+                "min_fee_rate": min_fee_rate,
+            }),
+            Self::Wallet(WalletError::InsufficientFunds {
+                needed_sat,
+                available_sat,
+                min_confirmations,
+            }) => json::json!({
+                "needed_sat": needed_sat,
+                "available_sat": available_sat,
+                "min_confirmations": min_confirmations,
+            }),
+            Self::PermissionDenied { method, scope } => json::json!({
+                "method": method,
+                "scope": scope,
+            }),
+            Self::CompatNotImplemented {
+                method,
+                alternative,
+            } => json::json!({
+                "method": method,
+                "alternative": alternative,
+            }),
+            Self::RouteNotFound { amount_msat } => json::json!({ "amount_msat": amount_msat }),
+            Self::NotEnoughCapacity {
+                amount_msat,
+                capacity_msat,
+            } => json::json!({
+                "amount_msat": amount_msat,
+                "capacity_msat": capacity_msat,
+            }),
+            Self::PaymentTimeout { timeout_secs } => json::json!({ "timeout_secs": timeout_secs }),
+            Self::DuplicateInvoiceLabel(label) => json::json!({ "label": label }),
+            Self::GraphLoading { nodes_loaded } => json::json!({ "nodes_loaded": nodes_loaded }),
+            Self::PeerNotConnected(node_id) => json::json!({ "node_id": node_id }),
+            Self::Broadcast(reason) => json::json!({ "reason": reason }),
+            Self::DatastoreKeyExists(key) | Self::DatastoreKeyNotFound(key) => {
+                json::json!({ "key": key })
+            }
+            Self::DatastoreGenerationMismatch { expected, current } => json::json!({
+                "expected": expected,
+                "current": current,
+            }),
+            _ => json::json!({}),
+        };
+        data["category"] = json::json!(self.category().as_str());
+        data
+    }
+
+    /// Find the `LampoError` inside `err`, the errors of the wallet,
+    /// of the backend and of the channel limits are converted.
+    pub fn find(err: &Error) -> Option<Self> {
+        if let Some(err) = err.downcast_ref::<LampoError>() {
+            return Some(err.clone());
+        }
+        if let Some(err) = err.downcast_ref::<WalletError>() {
+            return Some(err.clone().into());
+        }
+        if let Some(err) = err.downcast_ref::<ChannelLimitError>() {
+            return Some(err.clone().into());
+        }
+        err.downcast_ref::<BroadcastError>()
+            .map(|err| err.clone().into())
+    }
+}
+
+/// A suggestion for the user on how to solve the error with `code`,
+/// shown by the clients next to the message.
+pub fn hint(code: i32) -> Option<&'static str> {
+    let hint = match code {
+        METHOD_NOT_FOUND => "check the name of the method",
+        UNAUTHORIZED => "pass a valid token with `--token`",
+        PERMISSION_DENIED => "mint a token with a scope that allows the method",
+        NOT_IMPLEMENTED => "call the native method of lampo",
+        100 => "use a higher fee rate",
+        101 => "fund the wallet with an address from `newaddr`, or wait for the confirmations",
+        201 => "ask the payee for a new invoice",
+        202 => "open a channel with a node that is better connected",
+        203 => "pay a smaller amount, or open a new channel",
+        205 => "retry with a longer timeout",
+        207 => "use another label, or look up the invoice with `listinvoices`",
+        208 => "retry when `getinfo` reports `graph_loaded`",
+        300 => "list the channels with `channels`",
+        400 => "connect to the peer with `connect`",
+        602 => "read the entry again with `listdatastore`, and retry with its generation",
+        _ => return None,
+    };
+    Some(hint)
+}
+
+impl fmt::Display for LampoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidParams(msg) => write!(f, "invalid params: {msg}"),
+            Self::Unauthorized(msg) => write!(f, "unauthorized: {msg}"),
+            Self::PermissionDenied { method, scope } => write!(
+                f,
+                "permission denied: the `{scope}` token does not allow `{method}`"
+            ),
+            Self::CompatNotImplemented {
+                method,
+                alternative: Some(alternative),
+            } => write!(
+                f,
+                "`{method}` is not implemented in the Core Lightning compat layer, use `{alternative}`"
+            ),
+            Self::CompatNotImplemented {
+                method,
+                alternative: None,
+            } => write!(
+                f,
+                "`{method}` is not implemented in the Core Lightning compat layer, and lampo has no alternative"
+            ),
+            Self::Wallet(err) => write!(f, "{err}"),
+            Self::InvoiceExpired => write!(f, "the invoice is expired, ask for a new one"),
+            Self::RouteNotFound { .. } => write!(f, "no route found to the destination"),
+            Self::NotEnoughCapacity {
+                amount_msat,
+                capacity_msat,
+            } => write!(
+                f,
+                "payment amount of `{amount_msat}` msat exceeds the outbound capacity of `{capacity_msat}` msat of the usable channels"
+            ),
+            Self::DuplicatePayment => write!(
+                f,
+                "duplicate payment, a payment with the same hash was already sent"
+            ),
+            Self::PaymentTimeout { timeout_secs } => {
+                write!(f, "payment not done in {timeout_secs}s, abandoned")
+            }
+            Self::PaymentCanceled => write!(f, "payment canceled"),
+            Self::DuplicateInvoiceLabel(label) => {
+                write!(f, "an invoice with the label `{label}` exists already")
+            }
+            Self::GraphLoading { .. } => write!(f, "the network graph is still loading, retry later"),
+            Self::PeerNotConnected(node_id) => write!(f, "not connected with the peer `{node_id}`"),
+            Self::Broadcast(err) => write!(f, "{err}"),
+            Self::DatastoreKeyExists(key) => write!(f, "the key `{key}` exists already"),
+            Self::DatastoreKeyNotFound(key) => write!(f, "the key `{key}` does not exist"),
+            Self::DatastoreGenerationMismatch { expected, current } => write!(
+                f,
+                "the entry has the generation {current}, not {expected}"
+            ),
+            Self::DatastoreTooLarge(msg) => write!(f, "{msg}"),
+            Self::WalletFailure(msg)
+            | Self::InvalidInvoice(msg)
+            | Self::PaymentFailure(msg)
+            | Self::ChannelNotFound(msg)
+            | Self::ChannelRejected(msg)
+            | Self::ChannelFailure(msg)
+            | Self::PeerFailure(msg)
+            | Self::ChainFailure(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+impl std::error::Error for LampoError {}
+
+impl From<WalletError> for LampoError {
+    fn from(err: WalletError) -> Self {
+        Self::Wallet(err)
+    }
+}
+
+impl From<ChannelLimitError> for LampoError {
+    fn from(err: ChannelLimitError) -> Self {
+        Self::ChannelRejected(format!("{err}"))
+    }
+}
+
+impl From<BroadcastError> for LampoError {
+    fn from(err: BroadcastError) -> Self {
+        Self::Broadcast(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_inside_the_category_range() {
+        let errors = [
+            LampoError::Wallet(WalletError::InsufficientFunds {
+                needed_sat: 2,
+                available_sat: 1,
+                min_confirmations: 1,
+            }),
+            LampoError::InvoiceExpired,
+            LampoError::GraphLoading { nodes_loaded: 1 },
+            LampoError::ChannelNotFound("0".to_owned()),
+            LampoError::PeerNotConnected("0".to_owned()),
+            LampoError::Broadcast(BroadcastError::MissingInputs),
+            LampoError::DatastoreGenerationMismatch {
+                expected: 0,
+                current: 1,
+            },
+        ];
+        for err in errors {
+            let range = match err.category() {
+                Category::Wallet => 100..200,
+                Category::Payment => 200..300,
+                Category::Channel => 300..400,
+                Category::Peer => 400..500,
+                Category::Chain => 500..600,
+                Category::Datastore => 600..700,
+                Category::Rpc => unreachable!(),
+            };
+            assert!(range.contains(&err.code()), "{err:?}");
+            assert_eq!(err.data()["category"], err.category().as_str());
+        }
+    }
+
+    #[test]
+    fn find_the_wallet_error() {
+        let err: Error = WalletError::FeeTooLow {
+            fee_rate: 253,
+            min_fee_rate: 500,
+        }
+        .into();
+        let err = LampoError::find(&err.context("impossible create the transaction")).unwrap();
+        assert_eq!(err.code(), 100);
+        assert_eq!(err.data()["min_fee_rate"], 500);
+        assert!(LampoError::find(&anyhow!("generic error")).is_none());
+    }
+}
@@ -58,6 +58,16 @@ impl<T: Clone> Subscriber<T> {
         subs.push(sender);
         receiver
     }
+
+    /// The events emitted that the subscribers did not receive yet.
+    pub fn pending(&self) -> usize {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|sender| sender.len())
+            .sum()
+    }
 }
 
 #[derive(Debug, Clone)]
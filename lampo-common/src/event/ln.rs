@@ -5,14 +5,24 @@ use crate::types::{ChannelId, ChannelState, NodeId};
 
 #[derive(Clone, Debug)]
 pub enum LightningEvent {
-    // FIXME: add new peer event
     PeerConnect {
         counterparty_node_id: NodeId,
     },
+    PeerDisconnect {
+        counterparty_node_id: NodeId,
+    },
     ChannelPending {
         counterparty_node_id: NodeId,
         funding_transaction: OutPoint,
     },
+    /// The funding transaction of a pending channel got a new
+    /// confirmation, the channel is ready after `required`.
+    ChannelConfirmation {
+        channel_id: ChannelId,
+        funding_transaction: OutPoint,
+        confirmations: u32,
+        required: u32,
+    },
     ChannelReady {
         counterparty_node_id: NodeId,
         channel_id: ChannelId,
@@ -38,6 +48,26 @@ pub enum LightningEvent {
         state: ChannelState,
         message: String,
     },
+    /// A payment is claimed, so the invoice (if any) is settled.
+    PaymentReceived {
+        payment_hash: String,
+        amount_msat: u64,
+    },
+    /// A payment for an hold invoice is arrived, and the HTLCs
+    /// are held until the invoice is settled or canceled.
+    HoldInvoiceAccepted {
+        payment_hash: String,
+        amount_msat: u64,
+    },
+    /// A custom onion message is received, `id` is used to
+    /// answer through the reply path, if there is one.
+    OnionMessageReceived {
+        id: u64,
+        tlv_type: u64,
+        /// Hex of the payload.
+        payload: String,
+        reply_path: bool,
+    },
     CloseChannelEvent {
         channel_id: String,
         message: String,
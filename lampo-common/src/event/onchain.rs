@@ -9,11 +9,16 @@ use crate::bitcoin::{Block, Transaction, Txid};
 pub enum OnChainEvent {
     NewBlock(Block),
     NewBestBlock((Header, Height)),
+    /// A block that matched the compact block filters of the
+    /// scripts watched, at the height given.
+    BlockMatched((Block, Height)),
     FeeEstimation(u32),
     SendRawTransaction(Transaction),
     ConfirmedTransaction((Transaction, u32, Header, Height)),
     DiscardedTransaction(Txid),
     UnconfirmedTransaction(Txid),
+    /// The wallet is synced up to the height given.
+    WalletSynced(Height),
 }
 
 impl Debug for OnChainEvent {
@@ -29,8 +34,12 @@ impl Debug for OnChainEvent {
                 write!(f, "NewBestBlock({}, {height})", header.block_hash())
             }
             Self::NewBlock(block) => write!(f, "NewBlock({})", block.block_hash()),
+            Self::BlockMatched((block, height)) => {
+                write!(f, "BlockMatched({}, {height})", block.block_hash())
+            }
             Self::SendRawTransaction(tx) => write!(f, "SendRawTransaction({})", tx.txid()),
             Self::UnconfirmedTransaction(tx) => write!(f, "UnconfirmedTransaction({})", tx),
+            Self::WalletSynced(height) => write!(f, "WalletSynced({height})"),
             _ => write!(f, "Debug fmt not unsupported"),
         }
     }
@@ -1,13 +1,156 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
 use std::{sync::Arc, time::SystemTime};
 
-use bitcoin::secp256k1::{Secp256k1, SecretKey};
-use lightning::sign::{InMemorySigner, NodeSigner, OutputSpender, SignerProvider};
+use bitcoin::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, Fingerprint};
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use lightning::sign::{InMemorySigner, NodeSigner, OutputSpender, Recipient, SignerProvider};
 
+use crate::error;
 use crate::ldk::sign::{EntropySource, KeysManager};
+use crate::ldk::util::message_signing;
+
+/// The secrets expected by [`LampoKeys::with_channel_keys`], in order.
+#[cfg(debug_assertions)]
+const CHANNEL_KEYS_FORMAT: [&str; 7] = [
+    "bitcoin_key",
+    "funding_key",
+    "revocation_base_secret",
+    "payment_base_secret",
+    "delayed_payment_base_secret",
+    "htlc_base_secret",
+    "shachain_seed",
+];
+
+/// The path of the seed of ldk from the master key of the wallet.
+///
+/// It is hardened and outside of the purposes used on chain (BIP 84
+/// and BIP 86), so the lightning keys never share a branch with the
+/// keys of the wallet, and other wallets can be derived from the same
+/// master key.
+pub const LDK_SEED_PATH: &str = "m/535h/0h";
+
+/// The file inside the lampo directory with the `KeysScheme` of the node.
+const KEYS_SCHEME_FILE: &str = "keys_scheme";
+
+/// How the seed of ldk is derived from the master key of the wallet,
+/// the on chain keys are derived with BIP 84 in both the schemes.
+///
+/// - `legacy`: the seed is the master private key itself, as the
+///   nodes created before `LDK_SEED_PATH` do;
+/// - `hardened`: the seed is the private key at `LDK_SEED_PATH`.
+///
+/// The two schemes give different node ids for the same mnemonic, so
+/// the scheme is recorded inside the lampo directory when the node is
+/// created and it never changes after.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeysScheme {
+    Legacy,
+    Hardened,
+}
+
+impl KeysScheme {
+    /// The seed of ldk from the `master` key, encoded as in BIP 32.
+    pub fn ldk_seed(&self, master: &[u8; 78]) -> error::Result<[u8; 32]> {
+        let master = ExtendedPrivKey::decode(master)?;
+        let key = match self {
+            Self::Legacy => master,
+            Self::Hardened => {
+                let path = DerivationPath::from_str(LDK_SEED_PATH)?;
+                master.derive_priv(&Secp256k1::new(), &path)?
+            }
+        };
+        Ok(key.private_key.secret_bytes())
+    }
+
+    /// The scheme recorded inside `root_path`, the first time it is
+    /// recorded the `wanted` one.
+    ///
+    /// Without a `wanted` scheme a directory with a channel manager is
+    /// of a node created before the schemes, so it stays on the legacy
+    /// one, while a new node uses the hardened one.
+    pub fn load_or_init(root_path: &str, wanted: Option<KeysScheme>) -> error::Result<Self> {
+        let path = Path::new(root_path).join(KEYS_SCHEME_FILE);
+        match fs::read_to_string(&path) {
+            Ok(content) => {
+                let scheme = Self::from_str(content.trim())?;
+                if let Some(wanted) = wanted.filter(|wanted| *wanted != scheme) {
+                    error::bail!(
+                        "the node uses the `{scheme}` keys scheme, it can not change to `{wanted}`"
+                    );
+                }
+                Ok(scheme)
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let legacy = Path::new(root_path).join("manager").exists();
+                let scheme = match (wanted, legacy) {
+                    (Some(wanted), _) => wanted,
+                    (None, true) => Self::Legacy,
+                    (None, false) => Self::Hardened,
+                };
+                fs::create_dir_all(root_path)?;
+                fs::write(&path, scheme.to_string())?;
+                Ok(scheme)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// The scheme of a node restored from an imported mnemonic.
+    ///
+    /// The mnemonic can come from a node of either scheme and the
+    /// wrong one gives another node id, so on an empty directory the
+    /// `wanted` scheme must be given instead of using the hardened one.
+    pub fn load_or_init_imported(
+        root_path: &str,
+        wanted: Option<KeysScheme>,
+    ) -> error::Result<Self> {
+        let recorded = Path::new(root_path).join(KEYS_SCHEME_FILE).exists();
+        let legacy = Path::new(root_path).join("manager").exists();
+        if wanted.is_none() && !recorded && !legacy {
+            error::bail!(
+                "the keys scheme of the imported mnemonic is unknown, set `keys-scheme=legacy` if the mnemonic is of a node created before the keys schemes, otherwise `keys-scheme=hardened`"
+            );
+        }
+        Self::load_or_init(root_path, wanted)
+    }
+}
+
+impl fmt::Display for KeysScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Legacy => write!(f, "legacy"),
+            Self::Hardened => write!(f, "hardened"),
+        }
+    }
+}
+
+impl FromStr for KeysScheme {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "legacy" => Ok(Self::Legacy),
+            "hardened" => Ok(Self::Hardened),
+            _ => error::bail!("unknown keys scheme `{s}`, it must be `legacy` or `hardened`"),
+        }
+    }
+}
 
 /// Lampo keys implementations
+///
+/// The secrets stay inside the keys manager, the accessors of this
+/// struct return only public material.
 pub struct LampoKeys {
-    pub keys_manager: Arc<LampoKeysManager>,
+    keys_manager: Arc<LampoKeysManager>,
+    /// How the seed was derived from the mnemonic, `None` when the
+    /// seed is not derived from a master key (e.g. a descriptor).
+    scheme: Option<KeysScheme>,
+    /// The fingerprints of the keys of the on chain descriptors.
+    fingerprints: Vec<Fingerprint>,
 }
 
 impl LampoKeys {
@@ -23,17 +166,60 @@ impl LampoKeys {
                 start_time.as_secs(),
                 start_time.subsec_nanos(),
             )),
+            scheme: None,
+            fingerprints: Vec::new(),
         }
     }
 
+    /// The keys of the `master` key of the wallet, encoded as in
+    /// BIP 32, derived with the `scheme`.
+    pub fn from_master(master: &[u8; 78], scheme: KeysScheme) -> error::Result<Self> {
+        let seed = scheme.ldk_seed(master)?;
+        let fingerprint = ExtendedPrivKey::decode(master)?.fingerprint(&Secp256k1::new());
+        let mut keys = Self::new(seed).with_fingerprints(vec![fingerprint]);
+        keys.scheme = Some(scheme);
+        Ok(keys)
+    }
+
+    /// Set the fingerprints of the keys of the on chain descriptors.
+    pub fn with_fingerprints(mut self, fingerprints: Vec<Fingerprint>) -> Self {
+        self.fingerprints = fingerprints;
+        self
+    }
+
+    /// Build the keys with the channel secrets forced by the `dev-force-channel-secrets`
+    /// option, so the tests can predict the channel scripts.
+    ///
+    /// The secrets are separated by `/` in the following order:
+    ///
+    /// `<bitcoin_key>/<funding_key>/<revocation_base_secret>/<payment_base_secret>/<delayed_payment_base_secret>/<htlc_base_secret>/<shachain_seed>`
+    ///
+    /// where each of them is 32 bytes encoded in hex. The `bitcoin_key` is
+    /// not used by lampo but it is accepted to keep the same format of the
+    /// lnprototest runner.
     #[cfg(debug_assertions)]
-    pub fn with_channel_keys(seed: [u8; 32], channels_keys: String) -> Self {
+    pub fn with_channel_keys(seed: [u8; 32], channels_keys: String) -> error::Result<Self> {
         // Fill in random_32_bytes with secure random data, or, on restart, reload the seed from disk.
         let start_time = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap();
 
         let keys = channels_keys.split('/').collect::<Vec<_>>();
+        if keys.len() != CHANNEL_KEYS_FORMAT.len() {
+            error::bail!(
+                "malformed channel keys: expected {} secrets separated by `/` (`{}`), got {}",
+                CHANNEL_KEYS_FORMAT.len(),
+                CHANNEL_KEYS_FORMAT.join("/"),
+                keys.len()
+            );
+        }
+        for (name, key) in CHANNEL_KEYS_FORMAT.iter().zip(keys.iter()) {
+            if key.len() != 64 || !key.chars().all(|c| c.is_ascii_hexdigit()) {
+                error::bail!(
+                    "malformed channel keys: `{name}` must be 32 bytes encoded in hex, got `{key}`"
+                );
+            }
+        }
 
         let mut manager =
             LampoKeysManager::new(&seed, start_time.as_secs(), start_time.subsec_nanos());
@@ -44,19 +230,53 @@ impl LampoKeys {
             keys[4].to_string(),
             keys[5].to_string(),
             keys[6].to_string(),
-        );
-        LampoKeys {
+        )?;
+        Ok(LampoKeys {
             keys_manager: Arc::new(manager),
-        }
+            scheme: None,
+            fingerprints: Vec::new(),
+        })
     }
 
     pub fn inner(&self) -> Arc<LampoKeysManager> {
         self.keys_manager.clone()
     }
+
+    /// The public key of the node, the one seen by the peers.
+    pub fn node_id(&self) -> PublicKey {
+        // SAFETY: the keys manager has always the node secret.
+        self.keys_manager.get_node_id(Recipient::Node).unwrap()
+    }
+
+    /// The key that receives the funds of the channels closed
+    /// cooperatively.
+    pub fn shutdown_pubkey(&self) -> PublicKey {
+        self.keys_manager.shutdown_pubkey()
+    }
+
+    /// The fingerprints of the keys of the on chain descriptors, for
+    /// a wallet built from a mnemonic the fingerprint of its master key.
+    pub fn fingerprints(&self) -> &[Fingerprint] {
+        &self.fingerprints
+    }
+
+    /// How the keys were derived from the mnemonic.
+    pub fn scheme(&self) -> Option<KeysScheme> {
+        self.scheme
+    }
+}
+
+/// Recover the node key that signed `message` with the zbase32
+/// `signature` given by `signmessage`.
+pub fn recover_message_signer(message: &[u8], signature: &str) -> error::Result<PublicKey> {
+    message_signing::recover_pk(message, signature)
+        .map_err(|err| error::anyhow!("invalid signature `{signature}`: {err}"))
 }
 
 pub struct LampoKeysManager {
     pub(crate) inner: KeysManager,
+    /// The public key of the shutdown script of `inner`.
+    shutdown_pubkey: PublicKey,
 
     funding_key: Option<SecretKey>,
     revocation_base_secret: Option<SecretKey>,
@@ -71,6 +291,7 @@ impl LampoKeysManager {
         let inner = KeysManager::new(seed, starting_time_secs, starting_time_nanos);
         Self {
             inner,
+            shutdown_pubkey: derive_shutdown_pubkey(seed),
             funding_key: None,
             revocation_base_secret: None,
             payment_base_secret: None,
@@ -80,6 +301,19 @@ impl LampoKeysManager {
         }
     }
 
+    /// The key of the shutdown script given to ldk.
+    pub fn shutdown_pubkey(&self) -> PublicKey {
+        self.shutdown_pubkey
+    }
+
+    /// Sign `message` with the node key, the signature is encoded
+    /// in zbase32 as the `signmessage` of CLN and LND does.
+    pub fn sign_message(&self, message: &[u8]) -> error::Result<String> {
+        let secret = self.inner.get_node_secret_key();
+        message_signing::sign(message, &secret)
+            .map_err(|err| error::anyhow!("impossible sign the message: {err}"))
+    }
+
     // FIXME: put this under a debug a feature flag like `unsafa_channel_keys`
     #[cfg(debug_assertions)]
     pub fn set_channels_keys(
@@ -90,19 +324,39 @@ impl LampoKeysManager {
         delayed_payment_base_secret: String,
         htlc_base_secret: String,
         _shachain_seed: String,
-    ) {
+    ) -> error::Result<()> {
         use std::str::FromStr;
 
-        self.funding_key = Some(SecretKey::from_str(&funding_key).unwrap());
-        self.revocation_base_secret = Some(SecretKey::from_str(&revocation_base_secret).unwrap());
-        self.payment_base_secret = Some(SecretKey::from_str(&payment_base_secret).unwrap());
-        self.delayed_payment_base_secret =
-            Some(SecretKey::from_str(&delayed_payment_base_secret).unwrap());
-        self.htlc_base_secret = Some(SecretKey::from_str(&htlc_base_secret).unwrap());
-        self.shachain_seed = Some(self.inner.get_secure_random_bytes())
+        let parse = |name: &str, key: &str| {
+            SecretKey::from_str(key)
+                .map_err(|err| error::anyhow!("malformed channel keys: invalid `{name}`: {err}"))
+        };
+        self.funding_key = Some(parse("funding_key", &funding_key)?);
+        self.revocation_base_secret =
+            Some(parse("revocation_base_secret", &revocation_base_secret)?);
+        self.payment_base_secret = Some(parse("payment_base_secret", &payment_base_secret)?);
+        self.delayed_payment_base_secret = Some(parse(
+            "delayed_payment_base_secret",
+            &delayed_payment_base_secret,
+        )?);
+        self.htlc_base_secret = Some(parse("htlc_base_secret", &htlc_base_secret)?);
+        self.shachain_seed = Some(self.inner.get_secure_random_bytes());
+        Ok(())
     }
 }
 
+/// The shutdown key of the `KeysManager` of `seed`, ldk derives it
+/// at `m/2h` of the master key of the seed without exposing it.
+fn derive_shutdown_pubkey(seed: &[u8; 32]) -> PublicKey {
+    let secp = Secp256k1::new();
+    // SAFETY: `KeysManager::new` derives the same key and it panics
+    // before when the seed is not valid.
+    let master = ExtendedPrivKey::new_master(bitcoin::Network::Testnet, seed).unwrap();
+    let child = ChildNumber::from_hardened_idx(2).unwrap();
+    let key = master.ckd_priv(&secp, child).unwrap();
+    PublicKey::from_secret_key(&secp, &key.private_key)
+}
+
 impl EntropySource for LampoKeysManager {
     fn get_secure_random_bytes(&self) -> [u8; 32] {
         self.inner.get_secure_random_bytes()
@@ -239,3 +493,155 @@ impl SignerProvider for LampoKeysManager {
         self.inner.read_chan_signer(reader)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use lightning::ln::script::ShutdownScript;
+    use lightning::sign::{NodeSigner, Recipient, SignerProvider};
+
+    use super::{recover_message_signer, KeysScheme, LampoKeys, LampoKeysManager};
+
+    /// The seed of the mnemonic `abandon abandon ... about`.
+    const ABANDON_SEED: &str = "5eb00bbddcf069084889a8ab9155568165f5c453ccb85e70811aaed6f6da5fc19a5ac40b389cd370d086206dec8aa6c43daea6690f20ad3d8d48b2d2ce9e38e4";
+
+    #[cfg(debug_assertions)]
+    fn secret(byte: u8) -> String {
+        format!("{}{byte:02x}", "00".repeat(31))
+    }
+
+    #[test]
+    fn recover_the_signer_of_a_message() {
+        // `test message` signed by the secret key 1, CLN and LND sign with
+        // the deterministic nonces of RFC 6979, so they give the same signature.
+        let signature = "d9tibmnic9t5y41hg7hkakdcra94akas9ku3rmmj4ag9mritc8ok4p5qzefs78c9pqfhpuftqqzhydbdwfg7u6w6wdxcqpqn4sj4e73e";
+        let signer = recover_message_signer(b"test message", signature).unwrap();
+        assert_eq!(
+            signer.to_string(),
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+        );
+
+        // the same message signed by the secret key `2a2a..2a`
+        let signature = "d7qa68bmz45mcmwqztztdk4pjpsqwdbaymme6kgzxmte1njmjhtee7w9dqohpmd74uj7ia876p96dsjnbmeeoojg9ynd6g9skaa669o7";
+        let signer = recover_message_signer(b"test message", signature).unwrap();
+        assert_eq!(
+            signer.to_string(),
+            "035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c"
+        );
+
+        // another message gives another signer
+        let signer = recover_message_signer(b"other message", signature).unwrap();
+        assert_ne!(
+            signer.to_string(),
+            "035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c"
+        );
+        assert!(recover_message_signer(b"test message", "not a signature").is_err());
+    }
+
+    #[test]
+    fn sign_message_with_the_node_key() {
+        let keys = LampoKeysManager::new(&[1; 32], 0, 0);
+        let signature = keys.sign_message(b"lampo").unwrap();
+        let signer = recover_message_signer(b"lampo", &signature).unwrap();
+        assert_eq!(signer, keys.get_node_id(Recipient::Node).unwrap());
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn channel_keys_format() {
+        use super::LampoKeys;
+
+        let keys = (1..=6).map(secret).collect::<Vec<_>>();
+        let err = LampoKeys::with_channel_keys([1; 32], keys.join("/")).err().unwrap();
+        assert!(err.to_string().contains("expected 7 secrets"), "{err}");
+
+        let mut keys = (0..=5).map(secret).collect::<Vec<_>>();
+        keys.push("ff".repeat(32));
+        assert!(LampoKeys::with_channel_keys([1; 32], keys.join("/")).is_ok());
+
+        keys[3] = "zz".repeat(32);
+        let err = LampoKeys::with_channel_keys([1; 32], keys.join("/")).err().unwrap();
+        assert!(err.to_string().contains("payment_base_secret"), "{err}");
+    }
+
+    #[test]
+    fn shutdown_pubkey_of_the_shutdown_script() {
+        let keys = LampoKeys::new([1; 32]);
+        let script = ShutdownScript::new_p2wpkh_from_pubkey(keys.shutdown_pubkey());
+        let shutdown = keys.inner().get_shutdown_scriptpubkey().unwrap();
+        assert_eq!(shutdown.into_inner(), script.into_inner());
+    }
+
+    #[test]
+    fn node_id_of_each_keys_scheme() {
+        let seed = hex::decode(ABANDON_SEED).unwrap();
+        let master = bitcoin::bip32::ExtendedPrivKey::new_master(bitcoin::Network::Bitcoin, &seed)
+            .unwrap()
+            .encode();
+
+        let node_id = |scheme| {
+            let keys = LampoKeys::from_master(&master, scheme).unwrap();
+            assert_eq!(keys.scheme(), Some(scheme));
+            keys.node_id().to_string()
+        };
+        assert_eq!(
+            node_id(KeysScheme::Legacy),
+            "027cf7c81dc777e46572ff964f17650a0f6f783319fcc140fb9a69425e2fbdc93c"
+        );
+        assert_eq!(
+            node_id(KeysScheme::Hardened),
+            "0307d063a0bb85c655e519dc28c55688b39740bc3e69949f239c48ba74f581c538"
+        );
+    }
+
+    #[test]
+    fn keys_scheme_is_kept_by_the_node() {
+        let path = std::env::temp_dir().join(format!("lampo-keys-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let root = path.join("new");
+        let root = root.to_str().unwrap();
+        assert_eq!(
+            KeysScheme::load_or_init(root, None).unwrap(),
+            KeysScheme::Hardened
+        );
+        assert_eq!(
+            KeysScheme::load_or_init(root, None).unwrap(),
+            KeysScheme::Hardened
+        );
+        let err = KeysScheme::load_or_init(root, Some(KeysScheme::Legacy)).unwrap_err();
+        assert!(err.to_string().contains("can not change"), "{err}");
+
+        // a node created before the schemes has already a channel manager
+        let legacy = path.join("legacy");
+        std::fs::create_dir_all(&legacy).unwrap();
+        std::fs::write(legacy.join("manager"), b"manager").unwrap();
+        let legacy = legacy.to_str().unwrap();
+        assert_eq!(
+            KeysScheme::load_or_init(legacy, None).unwrap(),
+            KeysScheme::Legacy
+        );
+        assert_eq!(
+            KeysScheme::load_or_init(legacy, Some(KeysScheme::Legacy)).unwrap(),
+            KeysScheme::Legacy
+        );
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn imported_mnemonic_needs_a_keys_scheme() {
+        let path = std::env::temp_dir().join(format!("lampo-import-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let root = path.to_str().unwrap();
+        let err = KeysScheme::load_or_init_imported(root, None).unwrap_err();
+        assert!(err.to_string().contains("keys-scheme=legacy"), "{err}");
+        assert_eq!(
+            KeysScheme::load_or_init_imported(root, Some(KeysScheme::Legacy)).unwrap(),
+            KeysScheme::Legacy
+        );
+        // the next starts use the scheme recorded
+        assert_eq!(
+            KeysScheme::load_or_init_imported(root, None).unwrap(),
+            KeysScheme::Legacy
+        );
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
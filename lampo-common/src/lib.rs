@@ -1,11 +1,21 @@
+pub mod amount;
+pub mod auth;
+pub mod autofees;
 pub mod backend;
 pub mod chacha20;
+pub mod compat;
 pub mod conf;
+pub mod error;
 pub mod event;
 pub mod handler;
 pub mod keys;
+pub mod limits;
 pub mod logger;
+pub mod metrics;
 pub mod model;
+pub mod seed;
+pub mod sync;
+pub mod time;
 pub mod types;
 pub mod wallet;
 
@@ -15,10 +25,7 @@ pub mod ldk {
     pub use lightning_invoice as invoice;
     pub use lightning_net_tokio as net;
     pub use lightning_persister as persister;
-}
-
-pub mod error {
-    pub use anyhow::*;
+    pub use lightning_rapid_gossip_sync as rgs;
 }
 
 pub mod json {
@@ -0,0 +1,216 @@
+//! Channel limits enforced by lampo on inbound and outbound channels.
+//!
+//! The limits are configured inside the `LampoConf`, and all the checks
+//! are made here so the daemon can enforce them in one place.
+use std::fmt;
+
+use crate::conf::LampoConf;
+use crate::types::NodeId;
+
+/// Error returned when a channel violates one of the limits
+/// configured by the user.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelLimitError {
+    MinChannelSize { amount_sat: u64, limit: u64 },
+    MaxChannelSize { amount_sat: u64, limit: u64 },
+    MaxInboundChannelsPerPeer { channels: usize, limit: usize },
+    MaxPendingChannels { channels: usize, limit: usize },
+}
+
+impl ChannelLimitError {
+    /// Return the name of the configuration option that was hit.
+    pub fn limit_name(&self) -> &'static str {
+        match self {
+            Self::MinChannelSize { .. } => "min-channel-size-sat",
+            Self::MaxChannelSize { .. } => "max-channel-size-sat",
+            Self::MaxInboundChannelsPerPeer { .. } => "max-inbound-channels-per-peer",
+            Self::MaxPendingChannels { .. } => "max-pending-channels",
+        }
+    }
+}
+
+impl fmt::Display for ChannelLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.limit_name();
+        match self {
+            Self::MinChannelSize { amount_sat, limit } => write!(
+                f,
+                "channel size of `{amount_sat}` sat is below `{name}` configured to `{limit}`"
+            ),
+            Self::MaxChannelSize { amount_sat, limit } => write!(
+                f,
+                "channel size of `{amount_sat}` sat is above `{name}` configured to `{limit}`"
+            ),
+            Self::MaxInboundChannelsPerPeer { channels, limit } => write!(
+                f,
+                "peer has already `{channels}` inbound channels, `{name}` is configured to `{limit}`"
+            ),
+            Self::MaxPendingChannels { channels, limit } => write!(
+                f,
+                "there are already `{channels}` pending channels, `{name}` is configured to `{limit}`"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ChannelLimitError {}
+
+impl LampoConf {
+    fn check_channel_size(&self, amount_sat: u64) -> Result<(), ChannelLimitError> {
+        if let Some(limit) = self.min_channel_size_sat {
+            if amount_sat < limit {
+                return Err(ChannelLimitError::MinChannelSize { amount_sat, limit });
+            }
+        }
+        if let Some(limit) = self.max_channel_size_sat {
+            if amount_sat > limit {
+                return Err(ChannelLimitError::MaxChannelSize { amount_sat, limit });
+            }
+        }
+        Ok(())
+    }
+
+    fn check_pending_channels(&self, channels: usize) -> Result<(), ChannelLimitError> {
+        if let Some(limit) = self.max_pending_channels {
+            if channels >= limit {
+                return Err(ChannelLimitError::MaxPendingChannels { channels, limit });
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the limits before opening a channel with a peer.
+    ///
+    /// `pending_channels` is the number of channels that are not
+    /// ready yet.
+    pub fn check_outbound_channel(
+        &self,
+        amount_sat: u64,
+        pending_channels: usize,
+    ) -> Result<(), ChannelLimitError> {
+        self.check_channel_size(amount_sat)?;
+        self.check_pending_channels(pending_channels)
+    }
+
+    /// Check the limits before accepting a channel opened by a peer.
+    ///
+    /// `peer_inbound_channels` is the number of channels that the
+    /// peer has already opened with us.
+    pub fn check_inbound_channel(
+        &self,
+        amount_sat: u64,
+        pending_channels: usize,
+        peer_inbound_channels: usize,
+    ) -> Result<(), ChannelLimitError> {
+        self.check_channel_size(amount_sat)?;
+        self.check_pending_channels(pending_channels)?;
+        if let Some(limit) = self.max_inbound_channels_per_peer {
+            if peer_inbound_channels >= limit {
+                return Err(ChannelLimitError::MaxInboundChannelsPerPeer {
+                    channels: peer_inbound_channels,
+                    limit,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Return true if lampo needs to look at the inbound channels
+    /// before accepting them.
+    pub fn need_channel_acceptor(&self) -> bool {
+        self.max_inbound_channels_per_peer.is_some()
+            || self.max_pending_channels.is_some()
+            || self.min_channel_size_sat.is_some()
+            || self.max_channel_size_sat.is_some()
+            || !self.zero_conf_peers.is_empty()
+    }
+
+    /// Return true if the peer is trusted to open zero
+    /// confirmation channels with us.
+    pub fn is_zero_conf_peer(&self, node_id: &NodeId) -> bool {
+        self.zero_conf_peers.contains(node_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf::LampoConf;
+
+    use super::ChannelLimitError;
+
+    fn conf() -> LampoConf {
+        let mut conf = LampoConf::default();
+        conf.min_channel_size_sat = Some(20_000);
+        conf.max_channel_size_sat = Some(1_000_000);
+        conf.max_inbound_channels_per_peer = Some(2);
+        conf.max_pending_channels = Some(3);
+        conf
+    }
+
+    #[test]
+    fn min_channel_size() {
+        let conf = conf();
+        let expected = ChannelLimitError::MinChannelSize {
+            amount_sat: 10_000,
+            limit: 20_000,
+        };
+        assert_eq!(conf.check_outbound_channel(10_000, 0), Err(expected.clone()));
+        assert_eq!(conf.check_inbound_channel(10_000, 0, 0), Err(expected));
+        assert!(conf.check_outbound_channel(20_000, 0).is_ok());
+        assert!(conf.check_inbound_channel(20_000, 0, 0).is_ok());
+    }
+
+    #[test]
+    fn max_channel_size() {
+        let conf = conf();
+        let expected = ChannelLimitError::MaxChannelSize {
+            amount_sat: 2_000_000,
+            limit: 1_000_000,
+        };
+        assert_eq!(
+            conf.check_outbound_channel(2_000_000, 0),
+            Err(expected.clone())
+        );
+        assert_eq!(conf.check_inbound_channel(2_000_000, 0, 0), Err(expected));
+        assert!(conf.check_outbound_channel(1_000_000, 0).is_ok());
+        assert!(conf.check_inbound_channel(1_000_000, 0, 0).is_ok());
+    }
+
+    #[test]
+    fn max_pending_channels() {
+        let conf = conf();
+        let expected = ChannelLimitError::MaxPendingChannels {
+            channels: 3,
+            limit: 3,
+        };
+        assert_eq!(conf.check_outbound_channel(50_000, 3), Err(expected.clone()));
+        assert_eq!(conf.check_inbound_channel(50_000, 3, 0), Err(expected));
+        assert!(conf.check_outbound_channel(50_000, 2).is_ok());
+        assert!(conf.check_inbound_channel(50_000, 2, 0).is_ok());
+    }
+
+    #[test]
+    fn max_inbound_channels_per_peer() {
+        let conf = conf();
+        let err = conf.check_inbound_channel(50_000, 0, 2);
+        assert_eq!(
+            err,
+            Err(ChannelLimitError::MaxInboundChannelsPerPeer {
+                channels: 2,
+                limit: 2,
+            })
+        );
+        assert!(err.unwrap_err().to_string().contains("max-inbound-channels-per-peer"));
+        assert!(conf.check_inbound_channel(50_000, 0, 1).is_ok());
+        // the outbound channels are not affected by the inbound limit
+        assert!(conf.check_outbound_channel(50_000, 0).is_ok());
+    }
+
+    #[test]
+    fn no_limits() {
+        let conf = LampoConf::default();
+        assert!(!conf.need_channel_acceptor());
+        assert!(conf.check_outbound_channel(1, 100).is_ok());
+        assert!(conf.check_inbound_channel(u64::MAX, 100, 100).is_ok());
+    }
+}
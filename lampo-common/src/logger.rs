@@ -1,26 +1,112 @@
 //! Logging module.
 ///
 /// Credit to https://github.com/vincenzopalazzo/nakamoto/blob/master/node/src/logger.rs
+///
+/// The level of the records can be changed at runtime for each target,
+/// and the last records are kept in memory, so they are served by the
+/// RPC without reading the log file. The records of ldk are here too,
+/// with the `ldk` target.
+use std::collections::{BTreeMap, VecDeque};
 use std::io;
 use std::path::PathBuf;
 use std::str::FromStr;
-use std::time::SystemTime;
+use std::sync::{Mutex, OnceLock, PoisonError, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 // FIXME: this is not async we should modify it
 use std::fs::File;
 
 use chrono::prelude::*;
 use colored::*;
 
-pub use log::{Level, Log, Metadata, Record, SetLoggerError};
+pub use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+use crate::error::LampoError;
+use crate::model::response::{LogLevels, LogRecord};
+
+/// Number of the last records kept in memory.
+const MAX_RECORDS: usize = 1000;
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// The levels of the records that are logged.
+struct Filter {
+    level: LevelFilter,
+    /// The targets with a level different from the default one, a
+    /// target includes its submodules (e.g. `lampod` and `lampod::ln`).
+    targets: BTreeMap<String, LevelFilter>,
+}
+
+impl Filter {
+    fn level(&self, target: &str) -> LevelFilter {
+        // the targets are sorted, so the longest target that
+        // matches is the first one found from the end.
+        self.targets
+            .iter()
+            .rev()
+            .find(|(prefix, _)| {
+                target
+                    .strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .map(|(_, level)| *level)
+            .unwrap_or(self.level)
+    }
+
+    /// The most verbose level, the records above it are discarded
+    /// by the `log` macros.
+    fn max_level(&self) -> LevelFilter {
+        self.targets.values().copied().fold(self.level, Ord::max)
+    }
+
+    fn levels(&self) -> LogLevels {
+        LogLevels {
+            level: self.level.to_string().to_lowercase(),
+            targets: self
+                .targets
+                .iter()
+                .map(|(target, level)| (target.clone(), level.to_string().to_lowercase()))
+                .collect(),
+        }
+    }
+}
 
 struct Logger {
-    level: Level,
+    filter: RwLock<Filter>,
     file: Option<File>,
+    records: Mutex<VecDeque<(Level, LogRecord)>>,
+}
+
+impl Logger {
+    fn keep(&self, record: &Record) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        let log = LogRecord {
+            timestamp,
+            level: record.level().to_string().to_lowercase(),
+            target: record.target().to_owned(),
+            message: record.args().to_string(),
+        };
+        let mut records = self.records.lock().unwrap_or_else(recover);
+        if records.len() == MAX_RECORDS {
+            records.pop_front();
+        }
+        records.push_back((record.level(), log));
+    }
+}
+
+/// Take back the guard of a poisoned lock of the logger, like
+/// `crate::sync` but without logging it, that would call the logger
+/// again.
+fn recover<G>(err: PoisonError<G>) -> G {
+    err.into_inner()
 }
 
 impl Log for Logger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= self.level
+        let filter = self.filter.read().unwrap_or_else(recover);
+        metadata.level() <= filter.level(metadata.target())
     }
 
     fn log(&self, record: &Record) {
@@ -32,6 +118,7 @@ impl Log for Logger {
             } else {
                 write(record, target, io::stdout());
             }
+            self.keep(record);
 
             fn write(record: &log::Record, target: &str, mut stream: impl io::Write) {
                 let message = format!(
@@ -68,16 +155,132 @@ impl Log for Logger {
 
 /// Initialize a new logger.
 pub fn init(level: &str, file: Option<PathBuf>) -> anyhow::Result<()> {
+    init_with_targets(level, &[], file)
+}
+
+/// Initialize a new logger, with the level of some targets
+/// different from the default `level`.
+pub fn init_with_targets(
+    level: &str,
+    targets: &[(String, String)],
+    file: Option<PathBuf>,
+) -> anyhow::Result<()> {
     let file = if let Some(path) = file {
         Some(File::create(path)?)
     } else {
         None
     };
-    let level = Level::from_str(level).map_err(|err| anyhow::anyhow!("{err}"))?;
-    let logger = Logger { level, file };
-
-    log::set_boxed_logger(Box::new(logger)).map_err(|err| anyhow::anyhow!("{err}"))?;
-    log::set_max_level(level.to_level_filter());
+    let mut filter = Filter {
+        level: parse_level(level)?,
+        targets: BTreeMap::new(),
+    };
+    for (target, level) in targets {
+        filter.targets.insert(target.clone(), parse_level(level)?);
+    }
+    let max_level = filter.max_level();
+    let logger = Logger {
+        filter: RwLock::new(filter),
+        file,
+        records: Mutex::new(VecDeque::new()),
+    };
+    if LOGGER.set(logger).is_err() {
+        anyhow::bail!("the logger is already initialized");
+    }
+    // SAFETY: the logger is set just above.
+    log::set_logger(LOGGER.get().unwrap()).map_err(|err| anyhow::anyhow!("{err}"))?;
+    log::set_max_level(max_level);
 
     Ok(())
 }
+
+/// Parse one of `off`, `error`, `warn`, `info`, `debug` or `trace`.
+pub fn parse_level(level: &str) -> anyhow::Result<LevelFilter> {
+    let filter = LevelFilter::from_str(level.trim())
+        .map_err(|_| LampoError::InvalidParams(format!("unknown log level `{level}`")))?;
+    Ok(filter)
+}
+
+fn logger() -> anyhow::Result<&'static Logger> {
+    LOGGER
+        .get()
+        .ok_or_else(|| anyhow::anyhow!("the logger is not initialized"))
+}
+
+/// Change the level of `target` and of its submodules, or the default
+/// level when there is no target.
+pub fn set_level(target: Option<&str>, level: &str) -> anyhow::Result<LogLevels> {
+    let level = parse_level(level)?;
+    let logger = logger()?;
+    let mut filter = logger.filter.write().unwrap_or_else(recover);
+    match target {
+        Some(target) => {
+            filter.targets.insert(target.to_owned(), level);
+        }
+        None => filter.level = level,
+    }
+    log::set_max_level(filter.max_level());
+    Ok(filter.levels())
+}
+
+/// Replace the default level and the levels of all the targets.
+pub fn set_levels(level: &str, targets: &[(String, String)]) -> anyhow::Result<LogLevels> {
+    let level = parse_level(level)?;
+    let targets = targets
+        .iter()
+        .map(|(target, level)| Ok((target.clone(), parse_level(level)?)))
+        .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+    let logger = logger()?;
+    let mut filter = logger.filter.write().unwrap_or_else(recover);
+    filter.level = level;
+    filter.targets = targets;
+    log::set_max_level(filter.max_level());
+    Ok(filter.levels())
+}
+
+/// The levels used by the logger.
+pub fn levels() -> anyhow::Result<LogLevels> {
+    Ok(logger()?.filter.read().unwrap_or_else(recover).levels())
+}
+
+/// The last `limit` records kept in memory, with a level
+/// up to `level`.
+pub fn records(level: Option<&str>, limit: Option<usize>) -> anyhow::Result<Vec<LogRecord>> {
+    let level = level
+        .map(parse_level)
+        .transpose()?
+        .unwrap_or(LevelFilter::Trace);
+    let records = logger()?.records.lock().unwrap_or_else(recover);
+    let records = records
+        .iter()
+        .filter(|(record_level, _)| *record_level <= level)
+        .map(|(_, record)| record.clone())
+        .collect::<Vec<_>>();
+    let skip = records.len().saturating_sub(limit.unwrap_or(records.len()));
+    Ok(records.into_iter().skip(skip).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{Filter, LevelFilter};
+
+    #[test]
+    fn level_of_the_targets() {
+        let filter = Filter {
+            level: LevelFilter::Info,
+            targets: BTreeMap::from([
+                ("lampod".to_owned(), LevelFilter::Debug),
+                ("lampod::ln".to_owned(), LevelFilter::Off),
+                ("ldk".to_owned(), LevelFilter::Warn),
+            ]),
+        };
+        assert_eq!(filter.level("lampod"), LevelFilter::Debug);
+        assert_eq!(filter.level("lampod::jsonrpc"), LevelFilter::Debug);
+        assert_eq!(filter.level("lampod::ln::peer_manager"), LevelFilter::Off);
+        assert_eq!(filter.level("lampodx"), LevelFilter::Info);
+        assert_eq!(filter.level("ldk"), LevelFilter::Warn);
+        assert_eq!(filter.level("lampo_bdk_wallet"), LevelFilter::Info);
+        assert_eq!(filter.max_level(), LevelFilter::Debug);
+    }
+}
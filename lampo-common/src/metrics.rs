@@ -0,0 +1,320 @@
+//! Lightweight registry of the metrics of the node.
+//!
+//! The managers count what happens (payments, forwards, failures)
+//! and time the commands, the gauges are set from the state of the
+//! node when the metrics are scraped. The registry is rendered with
+//! the text format of Prometheus.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use crate::sync::MutexExt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl MetricKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Counter => "counter",
+            Self::Gauge => "gauge",
+            Self::Histogram => "histogram",
+        }
+    }
+}
+
+/// The name and the description of a metric.
+#[derive(Debug, Clone, Copy)]
+pub struct Metric {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub kind: MetricKind,
+}
+
+impl Metric {
+    pub const fn counter(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            kind: MetricKind::Counter,
+        }
+    }
+
+    pub const fn gauge(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            kind: MetricKind::Gauge,
+        }
+    }
+
+    pub const fn histogram(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            kind: MetricKind::Histogram,
+        }
+    }
+}
+
+pub const CHANNELS: Metric = Metric::gauge("lampo_channels", "Channels by state.");
+pub const CHANNEL_BALANCE_MSAT: Metric = Metric::gauge(
+    "lampo_channel_balance_msat",
+    "Balance of the channels in msat, on the local and on the remote side.",
+);
+pub const PAYMENTS_SENT: Metric =
+    Metric::counter("lampo_payments_sent_total", "Payments sent with success.");
+pub const PAYMENTS_RECEIVED: Metric =
+    Metric::counter("lampo_payments_received_total", "Payments received.");
+pub const PAYMENTS_FAILED: Metric =
+    Metric::counter("lampo_payments_failed_total", "Payments sent that failed.");
+pub const FORWARDS: Metric = Metric::counter("lampo_forwards_total", "Payments forwarded.");
+pub const FORWARD_FEES_MSAT: Metric = Metric::counter(
+    "lampo_forward_fees_msat_total",
+    "Fees in msat earned by forwarding the payments.",
+);
+pub const PEERS: Metric = Metric::gauge("lampo_peers", "Peers connected.");
+pub const WALLET_BALANCE_SAT: Metric = Metric::gauge(
+    "lampo_wallet_balance_sat",
+    "Satoshis of the on-chain wallet, confirmed and unconfirmed.",
+);
+pub const CHAIN_HEIGHT: Metric = Metric::gauge("lampo_chain_height", "Height of the best block.");
+pub const ESPLORA_FAILURES: Metric = Metric::counter(
+    "lampo_esplora_request_failures_total",
+    "Requests to esplora that failed.",
+);
+pub const WALLET_SYNC_FAILURES: Metric = Metric::counter(
+    "lampo_wallet_sync_failures_total",
+    "Syncs of the wallet that failed.",
+);
+pub const EVENT_QUEUE_DEPTH: Metric = Metric::gauge(
+    "lampo_event_queue_depth",
+    "Events emitted and not received yet by the subscribers.",
+);
+pub const LDK_EVENT_QUEUE_DEPTH: Metric = Metric::gauge(
+    "lampo_ldk_event_queue_depth",
+    "Events of ldk journaled and not handled yet.",
+);
+pub const RPC_DURATION: Metric = Metric::histogram(
+    "lampo_rpc_duration_seconds",
+    "Time taken by the commands, by method.",
+);
+
+/// The counters without labels, they are reported from zero.
+const COUNTERS: [Metric; 6] = [
+    PAYMENTS_SENT,
+    PAYMENTS_RECEIVED,
+    PAYMENTS_FAILED,
+    FORWARDS,
+    FORWARD_FEES_MSAT,
+    WALLET_SYNC_FAILURES,
+];
+
+/// The upper bounds in seconds of the buckets of the histograms.
+pub const DURATION_BUCKETS: [f64; 11] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0, 30.0,
+];
+
+type Labels = Vec<(String, String)>;
+
+enum Value {
+    Number(f64),
+    Histogram {
+        /// The observations inside each bucket, not cumulative.
+        buckets: [u64; DURATION_BUCKETS.len()],
+        sum: f64,
+        count: u64,
+    },
+}
+
+struct Family {
+    metric: Metric,
+    series: BTreeMap<Labels, Value>,
+}
+
+pub struct Metrics {
+    families: Mutex<BTreeMap<&'static str, Family>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let metrics = Self {
+            families: Mutex::new(BTreeMap::new()),
+        };
+        for counter in &COUNTERS {
+            metrics.add(counter, &[], 0);
+        }
+        metrics
+    }
+
+    pub fn inc(&self, metric: &Metric, labels: &[(&str, &str)]) {
+        self.add(metric, labels, 1);
+    }
+
+    /// Increase the counter by `value`.
+    pub fn add(&self, metric: &Metric, labels: &[(&str, &str)], value: u64) {
+        self.update(metric, labels, |current| match current {
+            Some(Value::Number(current)) => Value::Number(current + value as f64),
+            _ => Value::Number(value as f64),
+        });
+    }
+
+    /// Set the gauge to `value`.
+    pub fn set(&self, metric: &Metric, labels: &[(&str, &str)], value: f64) {
+        self.update(metric, labels, |_| Value::Number(value));
+    }
+
+    /// Add the observation `value` to the histogram.
+    pub fn observe(&self, metric: &Metric, labels: &[(&str, &str)], value: f64) {
+        self.update(metric, labels, |current| {
+            let (mut buckets, mut sum, mut count) = match current {
+                Some(Value::Histogram {
+                    buckets,
+                    sum,
+                    count,
+                }) => (buckets, sum, count),
+                _ => ([0; DURATION_BUCKETS.len()], 0.0, 0),
+            };
+            if let Some(bucket) = DURATION_BUCKETS.iter().position(|bound| value <= *bound) {
+                buckets[bucket] += 1;
+            }
+            sum += value;
+            count += 1;
+            Value::Histogram {
+                buckets,
+                sum,
+                count,
+            }
+        });
+    }
+
+    fn update<F: FnOnce(Option<Value>) -> Value>(
+        &self,
+        metric: &Metric,
+        labels: &[(&str, &str)],
+        update: F,
+    ) {
+        let labels = labels
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Labels>();
+        let mut families = self.families.lock_or_recover();
+        let family = families.entry(metric.name).or_insert_with(|| Family {
+            metric: *metric,
+            series: BTreeMap::new(),
+        });
+        let current = family.series.remove(&labels);
+        family.series.insert(labels, update(current));
+    }
+
+    /// The metrics in the text format of Prometheus.
+    pub fn render(&self) -> String {
+        let families = self.families.lock_or_recover();
+        let mut text = String::new();
+        for family in families.values() {
+            let metric = family.metric;
+            // SAFETY: writing into a string never fails.
+            writeln!(text, "# HELP {} {}", metric.name, metric.help).unwrap();
+            writeln!(text, "# TYPE {} {}", metric.name, metric.kind.as_str()).unwrap();
+            for (labels, value) in &family.series {
+                match value {
+                    Value::Number(value) => {
+                        let labels = render_labels(labels, None);
+                        writeln!(text, "{}{labels} {value}", metric.name).unwrap();
+                    }
+                    Value::Histogram {
+                        buckets,
+                        sum,
+                        count,
+                    } => {
+                        let mut cumulative = 0;
+                        for (bound, observations) in DURATION_BUCKETS.iter().zip(buckets) {
+                            cumulative += observations;
+                            let labels = render_labels(labels, Some(&bound.to_string()));
+                            writeln!(text, "{}_bucket{labels} {cumulative}", metric.name).unwrap();
+                        }
+                        let labels_inf = render_labels(labels, Some("+Inf"));
+                        writeln!(text, "{}_bucket{labels_inf} {count}", metric.name).unwrap();
+                        let labels = render_labels(labels, None);
+                        writeln!(text, "{}_sum{labels} {sum}", metric.name).unwrap();
+                        writeln!(text, "{}_count{labels} {count}", metric.name).unwrap();
+                    }
+                }
+            }
+        }
+        text
+    }
+}
+
+/// The labels of a series, with the bucket `le` of an histogram.
+fn render_labels(labels: &Labels, le: Option<&str>) -> String {
+    let mut labels = labels
+        .iter()
+        .map(|(name, value)| format!("{name}=\"{}\"", escape(value)))
+        .collect::<Vec<_>>();
+    if let Some(le) = le {
+        labels.push(format!("le=\"{le}\""));
+    }
+    if labels.is_empty() {
+        return String::new();
+    }
+    format!("{{{}}}", labels.join(","))
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_the_series() {
+        let metrics = Metrics::new();
+        metrics.inc(&PAYMENTS_SENT, &[]);
+        metrics.add(&FORWARD_FEES_MSAT, &[], 1_500);
+        metrics.set(&CHANNELS, &[("state", "active")], 2.0);
+        metrics.observe(&RPC_DURATION, &[("method", "getinfo")], 0.003);
+        metrics.observe(&RPC_DURATION, &[("method", "getinfo")], 0.2);
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE lampo_payments_sent_total counter\n"));
+        assert!(text.contains("\nlampo_payments_sent_total 1\n"));
+        assert!(text.contains("\nlampo_payments_failed_total 0\n"));
+        assert!(text.contains("\nlampo_forward_fees_msat_total 1500\n"));
+        assert!(text.contains("\nlampo_channels{state=\"active\"} 2\n"));
+        assert!(
+            text.contains("lampo_rpc_duration_seconds_bucket{method=\"getinfo\",le=\"0.001\"} 0\n")
+        );
+        assert!(
+            text.contains("lampo_rpc_duration_seconds_bucket{method=\"getinfo\",le=\"0.005\"} 1\n")
+        );
+        assert!(
+            text.contains("lampo_rpc_duration_seconds_bucket{method=\"getinfo\",le=\"+Inf\"} 2\n")
+        );
+        assert!(text.contains("lampo_rpc_duration_seconds_count{method=\"getinfo\"} 2\n"));
+    }
+
+    #[test]
+    fn escape_the_labels() {
+        let metrics = Metrics::new();
+        metrics.set(&PEERS, &[("alias", "a \"quoted\"\\name")], 1.0);
+        assert!(metrics
+            .render()
+            .contains("lampo_peers{alias=\"a \\\"quoted\\\"\\\\name\"} 1\n"));
+    }
+}
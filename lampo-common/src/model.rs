@@ -1,34 +1,93 @@
+mod amount;
+mod autofees;
 mod close_channel;
+mod configs;
 mod connect;
+mod datastore;
+mod dev;
+mod events;
+mod fee_bump;
+mod get_channel;
 mod getinfo;
+mod gossip;
+mod health;
 mod invoice;
 mod keysend;
+mod list_funds;
+mod list_htlcs;
+mod list_pays;
+mod logs;
+mod maintenance;
+mod message;
+mod metrics;
 mod new_addr;
 mod on_chain;
+mod onion_message;
 mod open_channel;
+mod peers;
+mod plugins;
+mod reload;
+mod token;
+mod towers;
 
+pub use amount::{Msat, Sat};
 pub use connect::Connect;
 pub use getinfo::GetInfo;
 
 pub mod request {
     pub use crate::model::close_channel::request::*;
     pub use crate::model::connect::Connect;
+    pub use crate::model::datastore::request::*;
+    pub use crate::model::dev::request::*;
+    pub use crate::model::events::request::*;
+    pub use crate::model::get_channel::request::*;
     pub use crate::model::getinfo::*;
+    pub use crate::model::gossip::request::*;
     pub use crate::model::invoice::request::*;
     pub use crate::model::keysend::request::*;
+    pub use crate::model::list_htlcs::request::*;
+    pub use crate::model::list_pays::request::*;
+    pub use crate::model::logs::request::*;
+    pub use crate::model::message::request::*;
     pub use crate::model::new_addr::request::*;
     #[allow(unused_imports)]
     pub use crate::model::on_chain::request::*;
+    pub use crate::model::onion_message::request::*;
     pub use crate::model::open_channel::request::*;
+    pub use crate::model::peers::request::*;
+    pub use crate::model::token::request::*;
+    pub use crate::model::towers::request::*;
 }
 
 pub mod response {
+    pub use crate::model::autofees::response::*;
     pub use crate::model::close_channel::response::*;
+    pub use crate::model::configs::response::*;
     pub use crate::model::connect::Connect;
+    pub use crate::model::datastore::response::*;
+    pub use crate::model::dev::response::*;
+    pub use crate::model::events::response::*;
+    pub use crate::model::fee_bump::response::*;
+    pub use crate::model::get_channel::response::*;
     pub use crate::model::getinfo::*;
+    pub use crate::model::gossip::response::*;
+    pub use crate::model::health::response::*;
     pub use crate::model::invoice::response::*;
     pub use crate::model::keysend::response::*;
+    pub use crate::model::list_funds::response::*;
+    pub use crate::model::list_htlcs::response::*;
+    pub use crate::model::list_pays::response::*;
+    pub use crate::model::logs::response::*;
+    pub use crate::model::maintenance::response::*;
+    pub use crate::model::message::response::*;
+    pub use crate::model::metrics::response::*;
     pub use crate::model::new_addr::response::*;
     pub use crate::model::on_chain::response::*;
+    pub use crate::model::onion_message::response::*;
     pub use crate::model::open_channel::response::*;
+    pub use crate::model::peers::response::*;
+    pub use crate::model::plugins::response::*;
+    pub use crate::model::reload::response::*;
+    pub use crate::model::token::response::*;
+    pub use crate::model::towers::response::*;
 }
@@ -0,0 +1,292 @@
+//! Amounts in millisatoshis and in satoshis.
+//!
+//! A bare `u64` does not tell its unit, so `Msat` and `Sat` are never
+//! made from a number implicitly, and they can not be mixed: the unit
+//! is written at every conversion. The conversions that can overflow
+//! or lose the millisatoshis fail, they never wrap or round in
+//! silence.
+//!
+//! ```compile_fail
+//! use lampo_common::model::Msat;
+//!
+//! let amount: Msat = 1_000u64.into();
+//! ```
+//!
+//! ```compile_fail
+//! use lampo_common::model::{Msat, Sat};
+//!
+//! let amount = Msat::from_msat(1_000).checked_add(Sat::from_sat(1));
+//! ```
+//!
+//! Inside the requests an amount is a number in its unit, or a string
+//! with the unit accepted by `parse_msat` (e.g. `"1000msat"` or
+//! `"1sat"`). The responses have always the number.
+use std::fmt;
+
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::amount::{format_msat, parse_msat, MSAT_PER_SAT};
+use crate::error::LampoError;
+
+/// An amount in millisatoshis.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Msat(u64);
+
+/// An amount in satoshis.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Sat(u64);
+
+fn too_big(what: fmt::Arguments<'_>) -> LampoError {
+    LampoError::InvalidParams(format!("amount {what} is too big"))
+}
+
+impl Msat {
+    pub const ZERO: Msat = Msat(0);
+
+    pub const fn from_msat(msat: u64) -> Self {
+        Self(msat)
+    }
+
+    pub const fn msat(self) -> u64 {
+        self.0
+    }
+
+    /// The amount in satoshis, it fails when it is not a whole number
+    /// of satoshis.
+    pub fn to_sat(self) -> Result<Sat, LampoError> {
+        if self.0 % MSAT_PER_SAT != 0 {
+            return Err(LampoError::InvalidParams(format!(
+                "amount `{self}` is not a whole number of satoshis"
+            )));
+        }
+        Ok(Sat(self.0 / MSAT_PER_SAT))
+    }
+
+    /// The satoshis inside the amount, without the millisatoshis.
+    pub const fn to_sat_floor(self) -> Sat {
+        Sat(self.0 / MSAT_PER_SAT)
+    }
+
+    /// The satoshis that cover the amount, e.g. for an on chain
+    /// payment of a lightning amount.
+    pub const fn to_sat_ceil(self) -> Sat {
+        Sat(self.0 / MSAT_PER_SAT + (self.0 % MSAT_PER_SAT != 0) as u64)
+    }
+
+    pub fn checked_add(self, other: Msat) -> Result<Msat, LampoError> {
+        self.0
+            .checked_add(other.0)
+            .map(Msat)
+            .ok_or_else(|| too_big(format_args!("`{self}` + `{other}`")))
+    }
+
+    /// Fails when `other` is bigger than the amount.
+    pub fn checked_sub(self, other: Msat) -> Result<Msat, LampoError> {
+        self.0.checked_sub(other.0).map(Msat).ok_or_else(|| {
+            LampoError::InvalidParams(format!("amount `{other}` is bigger than `{self}`"))
+        })
+    }
+
+    pub fn checked_sum<I>(amounts: I) -> Result<Msat, LampoError>
+    where
+        I: IntoIterator<Item = Msat>,
+    {
+        amounts
+            .into_iter()
+            .try_fold(Msat::ZERO, |total, amount| total.checked_add(amount))
+    }
+}
+
+impl Sat {
+    pub const ZERO: Sat = Sat(0);
+
+    pub const fn from_sat(sat: u64) -> Self {
+        Self(sat)
+    }
+
+    pub const fn sat(self) -> u64 {
+        self.0
+    }
+
+    /// The amount in millisatoshis, it fails when it does not fit.
+    pub fn to_msat(self) -> Result<Msat, LampoError> {
+        self.0
+            .checked_mul(MSAT_PER_SAT)
+            .map(Msat)
+            .ok_or_else(|| too_big(format_args!("`{self}`")))
+    }
+
+    pub fn checked_add(self, other: Sat) -> Result<Sat, LampoError> {
+        self.0
+            .checked_add(other.0)
+            .map(Sat)
+            .ok_or_else(|| too_big(format_args!("`{self}` + `{other}`")))
+    }
+
+    /// Fails when `other` is bigger than the amount.
+    pub fn checked_sub(self, other: Sat) -> Result<Sat, LampoError> {
+        self.0.checked_sub(other.0).map(Sat).ok_or_else(|| {
+            LampoError::InvalidParams(format!("amount `{other}` is bigger than `{self}`"))
+        })
+    }
+
+    pub fn checked_sum<I>(amounts: I) -> Result<Sat, LampoError>
+    where
+        I: IntoIterator<Item = Sat>,
+    {
+        amounts
+            .into_iter()
+            .try_fold(Sat::ZERO, |total, amount| total.checked_add(amount))
+    }
+}
+
+/// `1000msat`, or `1 sat` for a human with the alternate flag (`{:#}`).
+impl fmt::Display for Msat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return write!(f, "{}", format_msat(self.0));
+        }
+        write!(f, "{}msat", self.0)
+    }
+}
+
+/// `1sat`, or `1 sat` for a human with the alternate flag (`{:#}`).
+impl fmt::Display for Sat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return match self.0.checked_mul(MSAT_PER_SAT) {
+                Some(msat) => write!(f, "{}", format_msat(msat)),
+                None => write!(f, "{} sat", self.0),
+            };
+        }
+        write!(f, "{}sat", self.0)
+    }
+}
+
+impl Serialize for Msat {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.0)
+    }
+}
+
+impl Serialize for Sat {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.0)
+    }
+}
+
+/// Read an amount as a number in `unit_msat`, or as a string with the
+/// unit, into millisatoshis.
+struct AmountVisitor {
+    unit: &'static str,
+    unit_msat: u64,
+}
+
+impl<'de> Visitor<'de> for AmountVisitor {
+    type Value = u64;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "a number of {}, or a string with the unit like `1000msat`",
+            self.unit
+        )
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<u64, E> {
+        value
+            .checked_mul(self.unit_msat)
+            .ok_or_else(|| E::custom(too_big(format_args!("`{value}{}`", self.unit))))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<u64, E> {
+        let value =
+            u64::try_from(value).map_err(|_| E::custom(format!("amount `{value}` is negative")))?;
+        self.visit_u64(value)
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<u64, E> {
+        parse_msat(value).map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Msat {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let msat = deserializer.deserialize_any(AmountVisitor {
+            unit: "msat",
+            unit_msat: 1,
+        })?;
+        Ok(Msat(msat))
+    }
+}
+
+impl<'de> Deserialize<'de> for Sat {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let msat = deserializer.deserialize_any(AmountVisitor {
+            unit: "sat",
+            unit_msat: MSAT_PER_SAT,
+        })?;
+        Msat(msat).to_sat().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Msat, Sat};
+    use crate::json;
+
+    #[test]
+    fn convert_amounts() {
+        assert_eq!(Sat::from_sat(1).to_msat().unwrap(), Msat::from_msat(1_000));
+        assert_eq!(Msat::from_msat(2_000).to_sat().unwrap(), Sat::from_sat(2));
+        assert!(Msat::from_msat(2_500).to_sat().is_err());
+        assert_eq!(Msat::from_msat(2_500).to_sat_floor(), Sat::from_sat(2));
+        assert_eq!(Msat::from_msat(2_500).to_sat_ceil(), Sat::from_sat(3));
+        assert_eq!(Msat::from_msat(3_000).to_sat_ceil(), Sat::from_sat(3));
+        assert_eq!(
+            Msat::from_msat(u64::MAX).to_sat_ceil().sat(),
+            u64::MAX / 1_000 + 1
+        );
+    }
+
+    #[test]
+    fn overflow_is_an_error() {
+        assert!(Sat::from_sat(u64::MAX / 1_000 + 1).to_msat().is_err());
+        assert!(Msat::from_msat(u64::MAX)
+            .checked_add(Msat::from_msat(1))
+            .is_err());
+        assert!(Sat::from_sat(1).checked_sub(Sat::from_sat(2)).is_err());
+        assert!(Msat::checked_sum([Msat::from_msat(u64::MAX), Msat::from_msat(1)]).is_err());
+        assert_eq!(
+            Sat::checked_sum([Sat::from_sat(1), Sat::from_sat(2)]).unwrap(),
+            Sat::from_sat(3)
+        );
+    }
+
+    #[test]
+    fn display_amounts() {
+        assert_eq!(Msat::from_msat(25_500).to_string(), "25500msat");
+        assert_eq!(format!("{:#}", Msat::from_msat(25_500)), "25.5 sat");
+        assert_eq!(Sat::from_sat(100_000_000).to_string(), "100000000sat");
+        assert_eq!(format!("{:#}", Sat::from_sat(100_000_000)), "1 btc");
+    }
+
+    #[test]
+    fn deserialize_amounts() {
+        let msat: Msat = json::from_value(json::json!(1_000)).unwrap();
+        assert_eq!(msat, Msat::from_msat(1_000));
+        let msat: Msat = json::from_value(json::json!("1sat")).unwrap();
+        assert_eq!(msat, Msat::from_msat(1_000));
+        let sat: Sat = json::from_value(json::json!(1)).unwrap();
+        assert_eq!(sat, Sat::from_sat(1));
+        let sat: Sat = json::from_value(json::json!("2000msat")).unwrap();
+        assert_eq!(sat, Sat::from_sat(2));
+
+        assert!(json::from_value::<Sat>(json::json!("1500msat")).is_err());
+        assert!(json::from_value::<Sat>(json::json!(u64::MAX)).is_err());
+        assert!(json::from_value::<Msat>(json::json!(-1)).is_err());
+        assert!(json::from_value::<Msat>(json::json!("1000")).is_err());
+        assert_eq!(json::to_value(Sat::from_sat(1)).unwrap(), json::json!(1));
+    }
+}
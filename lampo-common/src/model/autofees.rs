@@ -0,0 +1,39 @@
+//! Autofees Model
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    use crate::model::Msat;
+
+    /// The last evaluation of a channel by the autofees policy.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct AutofeesChannel {
+        pub channel_id: String,
+        pub short_channel_id: Option<u64>,
+        /// The part of the capacity on our side, from 0 to 1.
+        pub outbound_ratio: f64,
+        /// Amount forwarded out through the channel since the
+        /// evaluation before.
+        pub forwarded_msat: Msat,
+        pub current_ppm: u32,
+        /// The fee asked by the liquidity of the channel.
+        pub target_ppm: u32,
+        /// The fee set by the evaluation, `None` when it was kept.
+        pub new_ppm: Option<u32>,
+        /// Why the fee was changed or kept.
+        pub reason: String,
+        /// When the channel was evaluated, in seconds since the epoch.
+        pub evaluated_at: u64,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct AutofeesStatus {
+        pub enabled: bool,
+        pub interval_secs: u64,
+        pub min_ppm: u32,
+        pub max_ppm: u32,
+        pub max_change_ppm: u32,
+        pub hysteresis_ppm: u32,
+        pub channels: Vec<AutofeesChannel>,
+    }
+}
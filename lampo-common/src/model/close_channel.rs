@@ -13,6 +13,13 @@ pub mod request {
         pub node_id: String,
         // Hex of the channel
         pub channel_id: Option<String>,
+        /// Address where our funds are sent, by default a
+        /// new wallet address is used.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub destination: Option<String>,
+        /// Target feerate of the closing transaction.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub feerate_sat_per_1000_weight: Option<u32>,
     }
 
     impl CloseChannel {
@@ -57,6 +64,23 @@ pub mod response {
         pub peer_id: String,
         pub funding_utxo: String,
     }
+
+    /// A cooperative close that is waiting that the peer
+    /// come back online.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct PendingClose {
+        pub channel_id: String,
+        pub peer_id: String,
+        pub destination: Option<String>,
+        pub feerate_sat_per_1000_weight: Option<u32>,
+        /// Unix timestamp when the close was queued.
+        pub queued_at: u64,
+        /// Unix timestamp after that the user should escalate
+        /// to a force close.
+        pub deadline: u64,
+        /// True if the deadline is passed.
+        pub expired: bool,
+    }
 }
 
 pub mod tests {
@@ -72,6 +96,8 @@ pub mod tests {
         let req = crate::model::request::CloseChannel {
             node_id: node_id.clone(),
             channel_id: channel_hex,
+            destination: None,
+            feerate_sat_per_1000_weight: None,
         };
         let channel_bytes = [
             10, 68, 103, 117, 38, 172, 140, 96, 118, 22, 189, 145, 37, 141, 126, 93, 241, 216, 111,
@@ -0,0 +1,23 @@
+//! List Configs Model
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct ListConfigs {
+        pub configs: Vec<Config>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Config {
+        /// The name of the option inside `lampo.conf`.
+        pub name: String,
+        /// The values of the option, with the secrets redacted.
+        pub values: Vec<String>,
+        /// Where the value comes from: `default`, `file`, `env`
+        /// or `cli`.
+        pub source: String,
+        /// The environment variable of the option.
+        pub env: String,
+    }
+}
@@ -1,6 +1,7 @@
 //! Connect Model
-use std::{net::SocketAddr, str::FromStr};
+use std::str::FromStr;
 
+use lightning::ln::msgs::SocketAddress;
 use serde::{Deserialize, Serialize};
 
 use super::request::OpenChannel;
@@ -9,23 +10,33 @@ use crate::types::NodeId;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Connect {
+    /// The node id of the peer, or `node_id@host:port`, in this
+    /// case `addr` and `port` can be omitted.
     pub node_id: String,
+    #[serde(default)]
     pub addr: String,
+    #[serde(default)]
     pub port: u64,
 }
 
 impl Connect {
     pub fn node_id(&self) -> error::Result<NodeId> {
-        Ok(NodeId::from_str(&self.node_id)?)
+        let node_id = match self.node_id.split_once('@') {
+            Some((node_id, _)) => node_id,
+            None => self.node_id.as_str(),
+        };
+        Ok(NodeId::from_str(node_id)?)
     }
 
-    pub fn addr(&self) -> error::Result<SocketAddr> {
-        let addr = format!("{}:{}", self.addr, self.port);
-        let result = SocketAddr::from_str(&addr);
-        match result {
-            Ok(res) => Ok(res),
-            Err(e) => Err(e.into()),
-        }
+    /// Return the address of the peer, a DNS name is not resolved
+    /// here, so it can be resolved by the proxy when we use one.
+    pub fn addr(&self) -> error::Result<SocketAddress> {
+        let host = match self.node_id.split_once('@') {
+            Some((_, host)) => host.to_owned(),
+            None => format!("{}:{}", self.addr, self.port),
+        };
+        SocketAddress::from_str(&host)
+            .map_err(|err| error::anyhow!("invalid address `{host}`: {err}"))
     }
 }
 
@@ -44,3 +55,48 @@ impl TryFrom<OpenChannel> for Connect {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Connect, SocketAddress};
+
+    const NODE_ID: &str = "02a1633cafcc01ebfb6d78e39f687a1f0995c62fc95f51ead10a02ee0be551b5dc";
+
+    #[test]
+    fn connect_with_uri() {
+        let connect = Connect {
+            node_id: format!("{NODE_ID}@127.0.0.1:9735"),
+            addr: String::new(),
+            port: 0,
+        };
+        assert_eq!(connect.node_id().unwrap().to_string(), NODE_ID);
+        assert_eq!(connect.addr().unwrap().to_string(), "127.0.0.1:9735");
+    }
+
+    #[test]
+    fn connect_with_dns_name() {
+        let connect = Connect {
+            node_id: NODE_ID.to_owned(),
+            addr: "localhost".to_owned(),
+            port: 9735,
+        };
+        assert!(matches!(
+            connect.addr().unwrap(),
+            SocketAddress::Hostname { port: 9735, .. }
+        ));
+    }
+
+    #[test]
+    fn connect_with_onion_address() {
+        let onion = "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion";
+        let connect = Connect {
+            node_id: format!("{NODE_ID}@{onion}:9735"),
+            addr: String::new(),
+            port: 0,
+        };
+        assert!(matches!(
+            connect.addr().unwrap(),
+            SocketAddress::OnionV3 { port: 9735, .. }
+        ));
+    }
+}
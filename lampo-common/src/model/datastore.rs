@@ -0,0 +1,60 @@
+//! Datastore Model
+
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    /// How `datastore` treats an entry that exists already.
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq)]
+    #[serde(rename_all = "kebab-case")]
+    pub enum DatastoreMode {
+        /// Fail if the key exists.
+        #[default]
+        MustCreate,
+        /// Fail if the key does not exist.
+        MustReplace,
+        /// Append the value to the one of the key, or create it.
+        Append,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Datastore {
+        /// The segments of the key, e.g. `["tips", "widget", "count"]`.
+        pub key: Vec<String>,
+        pub value: String,
+        #[serde(default)]
+        pub mode: DatastoreMode,
+        /// The change is done only if the entry has this generation.
+        pub generation: Option<u64>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct ListDatastore {
+        /// The entries with a key that starts with these segments,
+        /// all the entries without it.
+        pub prefix: Option<Vec<String>>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct DelDatastore {
+        pub key: Vec<String>,
+        /// The entry is deleted only if it has this generation.
+        pub generation: Option<u64>,
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+    pub struct DatastoreEntry {
+        pub key: Vec<String>,
+        pub value: String,
+        /// Incremented at every change of the entry, starting from 0.
+        pub generation: u64,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct ListDatastore {
+        pub datastore: Vec<DatastoreEntry>,
+    }
+}
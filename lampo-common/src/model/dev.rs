@@ -0,0 +1,55 @@
+//! Developer Model, for the `dev-*` methods served with `developer`.
+
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    /// A channel, with any identifier accepted by `getchannel`.
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct DevChannel {
+        pub id: String,
+    }
+
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct DevSetFeerate {
+        /// A target of `feerates`, like `on_chain_sweep`.
+        pub target: String,
+        /// The fee rate given to ldk, the estimation is used again
+        /// when it is missing.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub sat_per_kw: Option<u32>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct DevIgnoreHtlcs {
+        pub node_id: String,
+        #[serde(default = "default_ignore")]
+        pub ignore: bool,
+    }
+
+    fn default_ignore() -> bool {
+        true
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct DevFail {
+        pub channel_id: String,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct DevSignLastTx {
+        pub channel_id: String,
+        /// Our commitment transaction followed by the HTLC
+        /// transactions, in hex.
+        pub txs: Vec<String>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct DevIgnoreHtlcs {
+        pub node_id: String,
+        pub ignore: bool,
+    }
+}
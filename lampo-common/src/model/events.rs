@@ -0,0 +1,78 @@
+//! Events model
+
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct WaitEvent {
+        /// Return the events after this id, all the events kept
+        /// by the node when missing.
+        pub last_id: Option<u64>,
+        /// Return only the events of these kinds (e.g. `payment_sent`),
+        /// all the events when empty.
+        #[serde(default)]
+        pub kinds: Vec<String>,
+        /// Seconds to wait for a new event, 30 by default.
+        pub timeout_secs: Option<u64>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct ListWebhookDeliveries {
+        /// Return only the deliveries to this webhook url.
+        pub url: Option<String>,
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    use crate::json;
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct NotifiedEvent {
+        /// Increasing id of the event, without gaps.
+        pub id: u64,
+        pub kind: String,
+        pub data: json::Value,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct NotifiedEvents {
+        pub events: Vec<NotifiedEvent>,
+        /// The id to give to the next call to continue the stream,
+        /// it also counts the events filtered out by `kinds`.
+        pub last_id: u64,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum DeliveryStatus {
+        /// The webhook did not accept the event yet, it is retried.
+        Pending,
+        Delivered,
+        /// All the attempts failed, the event is not sent again.
+        Failed,
+    }
+
+    /// The delivery of an event to a webhook.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct WebhookDelivery {
+        /// Increasing id of the delivery, sent inside the
+        /// `X-Lampo-Delivery` header.
+        pub id: u64,
+        pub url: String,
+        /// The id of the event delivered.
+        pub event_id: u64,
+        pub kind: String,
+        pub status: DeliveryStatus,
+        pub attempts: u32,
+        /// The HTTP status of the last answer of the webhook.
+        pub status_code: Option<u16>,
+        /// Why the last attempt failed.
+        pub error: Option<String>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct WebhookDeliveries {
+        pub deliveries: Vec<WebhookDelivery>,
+    }
+}
@@ -0,0 +1,24 @@
+//! Fee bump model
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    /// Package of transactions that lampo is bumping after a force close.
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct BumpPackage {
+        pub claim_id: String,
+        pub channel_id: String,
+        pub counterparty_node_id: String,
+        /// `anchor` for the commitment CPFP, or `htlc` for the
+        /// HTLC resolution transactions.
+        pub kind: String,
+        pub target_feerate_sat_per_1000_weight: u32,
+        /// Number of times that ldk asked to (re)bump the package.
+        pub attempts: u32,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct BumpPackages {
+        pub packages: Vec<BumpPackage>,
+    }
+}
@@ -0,0 +1,180 @@
+//! Get Channel Request
+
+pub mod request {
+    use std::fmt;
+    use std::str::FromStr;
+
+    use bitcoin::OutPoint;
+    use serde::{Deserialize, Serialize};
+
+    use crate::error;
+    use crate::types::ChannelId;
+
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct GetChannel {
+        /// A short channel id (`BxTxO` or u64), an scid alias,
+        /// a hex channel id or a funding outpoint (`txid:vout`).
+        pub id: String,
+    }
+
+    impl GetChannel {
+        pub fn identifier(&self) -> error::Result<ChannelIdentifier> {
+            ChannelIdentifier::from_str(&self.id)
+        }
+    }
+
+    /// All the identifiers that can be used to refer to a channel.
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum ChannelIdentifier {
+        /// A short channel id or an scid alias.
+        ShortChannelId(u64),
+        ChannelId(ChannelId),
+        FundingOutpoint(OutPoint),
+    }
+
+    impl FromStr for ChannelIdentifier {
+        type Err = error::Error;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let s = s.trim();
+            if s.contains(':') {
+                let outpoint = OutPoint::from_str(s)
+                    .map_err(|err| error::anyhow!("invalid funding outpoint `{s}`: {err}"))?;
+                return Ok(Self::FundingOutpoint(outpoint));
+            }
+            if s.contains('x') {
+                let parts = s.split('x').collect::<Vec<_>>();
+                let [block, tx, output] = parts.as_slice() else {
+                    error::bail!("invalid short channel id `{s}`, expected `BxTxO`");
+                };
+                let block = u64::from_str(block)?;
+                let tx = u64::from_str(tx)?;
+                let output = u64::from_str(output)?;
+                if block >= 1 << 24 || tx >= 1 << 24 || output >= 1 << 16 {
+                    error::bail!("invalid short channel id `{s}`, value out of range");
+                }
+                return Ok(Self::ShortChannelId(block << 40 | tx << 16 | output));
+            }
+            if s.len() == 64 {
+                let bytes = hex::decode(s)?;
+                let mut channel_id = [0; 32];
+                channel_id.copy_from_slice(&bytes);
+                return Ok(Self::ChannelId(ChannelId::from_bytes(channel_id)));
+            }
+            match u64::from_str(s) {
+                Ok(scid) => Ok(Self::ShortChannelId(scid)),
+                Err(_) => error::bail!("`{s}` is not a valid channel identifier"),
+            }
+        }
+    }
+
+    impl fmt::Display for ChannelIdentifier {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Self::ShortChannelId(scid) => write!(
+                    f,
+                    "{}x{}x{}",
+                    scid >> 40,
+                    (scid >> 16) & 0xFFFFFF,
+                    scid & 0xFFFF
+                ),
+                Self::ChannelId(channel_id) => write!(f, "{channel_id}"),
+                Self::FundingOutpoint(outpoint) => write!(f, "{outpoint}"),
+            }
+        }
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    use crate::model::response::Channel;
+
+    /// Statistics about the payments forwarded through a channel.
+    #[derive(Clone, Default, Serialize, Deserialize, Debug)]
+    pub struct ForwardStats {
+        /// Number of payments forwarded where this was the incoming
+        /// or the outgoing channel.
+        pub forwards: u64,
+        /// Amount forwarded out through this channel.
+        pub amount_forwarded_msat: u64,
+        /// Fee earned by forwarding out through this channel.
+        pub fee_earned_msat: u64,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct ChannelStateChange {
+        pub state: String,
+        /// Unix timestamp of the change.
+        pub timestamp: u64,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct ChannelInfo {
+        #[serde(flatten)]
+        pub channel: Channel,
+        pub funding_txo: Option<String>,
+        pub forward_stats: ForwardStats,
+        /// The last state changes of the channel, the oldest first.
+        pub events: Vec<ChannelStateChange>,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bitcoin::OutPoint;
+
+    use super::request::ChannelIdentifier;
+
+    #[test]
+    fn parse_short_channel_id() {
+        let expected = ChannelIdentifier::ShortChannelId(103 << 40 | 1 << 16 | 1);
+        assert_eq!(ChannelIdentifier::from_str("103x1x1").unwrap(), expected);
+        assert_eq!(
+            ChannelIdentifier::from_str("113249697726465").unwrap(),
+            expected
+        );
+        assert_eq!(expected.to_string(), "103x1x1");
+    }
+
+    #[test]
+    fn parse_scid_alias() {
+        // an alias is a random u64 that is not a real position in the chain
+        let alias = ChannelIdentifier::from_str("17592186044416000000").unwrap();
+        assert_eq!(
+            alias,
+            ChannelIdentifier::ShortChannelId(17592186044416000000)
+        );
+    }
+
+    #[test]
+    fn parse_channel_id() {
+        let id = "0a44677526ac8c607616bd91258d7e5df1d86fae9c32e23aa18703a650944c64";
+        let channel_id = match ChannelIdentifier::from_str(id).unwrap() {
+            ChannelIdentifier::ChannelId(channel_id) => channel_id,
+            other => panic!("expected a channel id, got `{:?}`", other),
+        };
+        assert_eq!(channel_id.0[0], 0x0a);
+        assert_eq!(channel_id.0[31], 0x64);
+        assert_eq!(channel_id.to_string(), id);
+    }
+
+    #[test]
+    fn parse_funding_outpoint() {
+        let outpoint = "0a44677526ac8c607616bd91258d7e5df1d86fae9c32e23aa18703a650944c64:1";
+        assert_eq!(
+            ChannelIdentifier::from_str(outpoint).unwrap(),
+            ChannelIdentifier::FundingOutpoint(OutPoint::from_str(outpoint).unwrap())
+        );
+    }
+
+    #[test]
+    fn parse_invalid_identifiers() {
+        assert!(ChannelIdentifier::from_str("103x1").is_err());
+        assert!(ChannelIdentifier::from_str("103x1x70000").is_err());
+        assert!(ChannelIdentifier::from_str("not a channel").is_err());
+        assert!(ChannelIdentifier::from_str("0a44:foo").is_err());
+    }
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::model::Sat;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GetInfo {
     pub node_id: String,
@@ -7,9 +9,65 @@ pub struct GetInfo {
     pub channels: usize,
     pub chain: String,
     pub alias: String,
+    /// Hex of the color announced inside the network graph.
+    pub color: String,
     pub blockheight: u32,
+    /// The height where the wallet is synced, `None` while the
+    /// first sync is running.
+    pub wallet_synced_height: Option<u32>,
+    /// True when the wallet is synced with the tip of the chain.
+    pub synced_to_chain: bool,
+    /// The balances of the wallet, `None` while the wallet is syncing.
+    pub onchain: Option<OnChainBalance>,
+    /// The sum of the outbound capacity of all the channels.
+    pub outbound_capacity_msat: u64,
+    /// The sum of the inbound capacity of all the channels.
+    pub inbound_capacity_msat: u64,
+    pub channels_state: ChannelsState,
     pub lampo_dir: String,
+    /// The addresses announced inside the network graph.
     pub address: Vec<NetworkInfo>,
+    /// The addresses where the node listens for the connections.
+    pub binding: Vec<NetworkInfo>,
+    /// Timestamp of the last rapid gossip snapshot applied to the graph.
+    pub rgs_last_sync_timestamp: Option<u32>,
+    /// False while the network graph on the disk is loading, the
+    /// payments wait for it.
+    pub graph_loaded: bool,
+    /// The nodes inside the network graph loaded until now.
+    pub nodes_loaded: usize,
+    /// Unix timestamp of the last node announcement broadcasted.
+    pub last_announcement: Option<u64>,
+    /// The version of lampo.
+    pub version: String,
+    /// Seconds since the daemon started.
+    pub uptime_secs: u64,
+    /// How the keys of the node are derived from the mnemonic,
+    /// `legacy` or `hardened`, `None` when there is no mnemonic.
+    pub keys_scheme: Option<String>,
+    /// The key that receives the funds of the channels closed
+    /// cooperatively.
+    pub shutdown_pubkey: String,
+    /// The fingerprints of the keys of the on chain descriptors.
+    pub fingerprints: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OnChainBalance {
+    pub confirmed_sat: Sat,
+    /// Satoshis received that are not confirmed yet.
+    pub pending_sat: Sat,
+}
+
+/// The number of channels in each state.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct ChannelsState {
+    /// Channels waiting that the funding is confirmed.
+    pub pending: usize,
+    /// Channels ready to send and receive payments.
+    pub active: usize,
+    /// Channels ready but not usable, e.g. the peer is offline.
+    pub inactive: usize,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -17,3 +75,83 @@ pub struct NetworkInfo {
     pub address: String,
     pub port: u64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn getinfo_json_shape() {
+        let getinfo = GetInfo {
+            node_id: "02aa".to_owned(),
+            peers: 1,
+            channels: 2,
+            chain: "regtest".to_owned(),
+            alias: "lampo".to_owned(),
+            color: "000000".to_owned(),
+            blockheight: 101,
+            wallet_synced_height: None,
+            synced_to_chain: false,
+            onchain: None,
+            outbound_capacity_msat: 1_000,
+            inbound_capacity_msat: 2_000,
+            channels_state: ChannelsState {
+                pending: 1,
+                active: 1,
+                inactive: 0,
+            },
+            lampo_dir: "/tmp/lampo".to_owned(),
+            address: vec![],
+            binding: vec![NetworkInfo {
+                address: "127.0.0.1".to_owned(),
+                port: 9735,
+            }],
+            rgs_last_sync_timestamp: None,
+            graph_loaded: false,
+            nodes_loaded: 3,
+            last_announcement: None,
+            version: "0.1.0".to_owned(),
+            uptime_secs: 10,
+            keys_scheme: Some("hardened".to_owned()),
+            shutdown_pubkey: "03bb".to_owned(),
+            fingerprints: vec!["73c5da0a".to_owned()],
+        };
+        let expected = crate::json::json!({
+            "node_id": "02aa",
+            "peers": 1,
+            "channels": 2,
+            "chain": "regtest",
+            "alias": "lampo",
+            "color": "000000",
+            "blockheight": 101,
+            "wallet_synced_height": null,
+            "synced_to_chain": false,
+            "onchain": null,
+            "outbound_capacity_msat": 1_000,
+            "inbound_capacity_msat": 2_000,
+            "channels_state": { "pending": 1, "active": 1, "inactive": 0 },
+            "lampo_dir": "/tmp/lampo",
+            "address": [],
+            "binding": [{ "address": "127.0.0.1", "port": 9735 }],
+            "rgs_last_sync_timestamp": null,
+            "graph_loaded": false,
+            "nodes_loaded": 3,
+            "last_announcement": null,
+            "version": "0.1.0",
+            "uptime_secs": 10,
+            "keys_scheme": "hardened",
+            "shutdown_pubkey": "03bb",
+            "fingerprints": ["73c5da0a"],
+        });
+        assert_eq!(crate::json::to_value(&getinfo).unwrap(), expected);
+
+        let onchain = OnChainBalance {
+            confirmed_sat: Sat::from_sat(100),
+            pending_sat: Sat::from_sat(10),
+        };
+        assert_eq!(
+            crate::json::to_value(&onchain).unwrap(),
+            crate::json::json!({ "confirmed_sat": 100, "pending_sat": 10 })
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! Network Graph Model
+
+/// Number of entries returned in a page when the user does not
+/// specify a limit.
+pub const DEFAULT_PAGE_LIMIT: usize = 100;
+/// Maximum number of entries returned in a page.
+pub const MAX_PAGE_LIMIT: usize = 1000;
+
+pub mod request {
+    use std::str::FromStr;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::{DEFAULT_PAGE_LIMIT, MAX_PAGE_LIMIT};
+    use crate::error;
+    use crate::types::NodeId;
+
+    #[derive(Clone, Serialize, Deserialize, Debug, Default)]
+    pub struct ListNodes {
+        /// Return only the node with this id.
+        pub node_id: Option<String>,
+        /// The continuation token returned by the previous page.
+        pub start: Option<String>,
+        pub limit: Option<usize>,
+    }
+
+    impl ListNodes {
+        pub fn node_id(&self) -> error::Result<Option<NodeId>> {
+            self.node_id
+                .as_ref()
+                .map(|node_id| Ok(NodeId::from_str(node_id)?))
+                .transpose()
+        }
+
+        pub fn start(&self) -> error::Result<Option<NodeId>> {
+            self.start
+                .as_ref()
+                .map(|start| {
+                    NodeId::from_str(start)
+                        .map_err(|_| error::anyhow!("invalid continuation token `{start}`"))
+                })
+                .transpose()
+        }
+
+        pub fn limit(&self) -> error::Result<usize> {
+            page_limit(self.limit)
+        }
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug, Default)]
+    pub struct ListGossipChannels {
+        /// Return only the channel with this short channel id.
+        pub short_channel_id: Option<u64>,
+        /// Return only the channels of this node.
+        pub node_id: Option<String>,
+        /// The continuation token returned by the previous page.
+        pub start: Option<String>,
+        pub limit: Option<usize>,
+    }
+
+    impl ListGossipChannels {
+        pub fn node_id(&self) -> error::Result<Option<NodeId>> {
+            self.node_id
+                .as_ref()
+                .map(|node_id| Ok(NodeId::from_str(node_id)?))
+                .transpose()
+        }
+
+        pub fn start(&self) -> error::Result<Option<u64>> {
+            self.start
+                .as_ref()
+                .map(|start| {
+                    u64::from_str(start)
+                        .map_err(|_| error::anyhow!("invalid continuation token `{start}`"))
+                })
+                .transpose()
+        }
+
+        pub fn limit(&self) -> error::Result<usize> {
+            page_limit(self.limit)
+        }
+    }
+
+    fn page_limit(limit: Option<usize>) -> error::Result<usize> {
+        match limit {
+            None => Ok(DEFAULT_PAGE_LIMIT),
+            Some(limit) if limit == 0 || limit > MAX_PAGE_LIMIT => {
+                error::bail!("invalid limit `{limit}`, it must be between 1 and {MAX_PAGE_LIMIT}")
+            }
+            Some(limit) => Ok(limit),
+        }
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct GossipNode {
+        pub node_id: String,
+        /// The following fields are known only if we received
+        /// the node announcement.
+        pub alias: Option<String>,
+        /// Hex of the RGB color.
+        pub color: Option<String>,
+        pub addresses: Vec<String>,
+        /// Hex of the announced features.
+        pub features: Option<String>,
+        /// Unix timestamp of the last node announcement.
+        pub last_update: Option<u32>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct GossipNodes {
+        pub nodes: Vec<GossipNode>,
+        /// The token to pass as `start` to get the next page,
+        /// `None` when this is the last page.
+        pub next: Option<String>,
+    }
+
+    /// The routing policy of a direction of the channel.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct ChannelPolicy {
+        pub fee_base_msat: u32,
+        pub fee_proportional_millionths: u32,
+        pub cltv_expiry_delta: u16,
+        pub htlc_minimum_msat: u64,
+        pub htlc_maximum_msat: u64,
+        pub disabled: bool,
+        /// Unix timestamp of the last channel update.
+        pub last_update: u32,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct GossipChannel {
+        pub short_channel_id: u64,
+        pub node_one: String,
+        pub node_two: String,
+        pub capacity_sat: Option<u64>,
+        /// The policy used to forward from `node_one` to `node_two`.
+        pub one_to_two: Option<ChannelPolicy>,
+        /// The policy used to forward from `node_two` to `node_one`.
+        pub two_to_one: Option<ChannelPolicy>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct GossipChannels {
+        pub channels: Vec<GossipChannel>,
+        /// The token to pass as `start` to get the next page,
+        /// `None` when this is the last page.
+        pub next: Option<String>,
+    }
+}
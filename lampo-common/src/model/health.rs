@@ -0,0 +1,56 @@
+//! Health Model
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    /// What a check tells about the node.
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum HealthCheckKind {
+        /// The node is working, when it fails the node must be restarted.
+        Liveness,
+        /// The node can serve the payments.
+        Readiness,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct HealthCheck {
+        pub name: String,
+        pub kind: HealthCheckKind,
+        pub pass: bool,
+        /// Why the check passed or failed.
+        pub detail: String,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Health {
+        /// All the liveness checks passed.
+        pub alive: bool,
+        /// The node is alive and all the readiness checks passed.
+        pub ready: bool,
+        pub checks: Vec<HealthCheck>,
+    }
+
+    impl Health {
+        pub fn new(checks: Vec<HealthCheck>) -> Self {
+            let passed = |kind| {
+                checks
+                    .iter()
+                    .filter(|check| check.kind == kind)
+                    .all(|check| check.pass)
+            };
+            let alive = passed(HealthCheckKind::Liveness);
+            let ready = alive && passed(HealthCheckKind::Readiness);
+            Self {
+                alive,
+                ready,
+                checks,
+            }
+        }
+
+        /// The checks that failed.
+        pub fn failing(&self) -> impl Iterator<Item = &HealthCheck> {
+            self.checks.iter().filter(|check| !check.pass)
+        }
+    }
+}
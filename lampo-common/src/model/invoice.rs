@@ -3,11 +3,76 @@
 pub mod request {
     use serde::{Deserialize, Serialize};
 
+    use crate::error;
+    use crate::model::Msat;
+
     #[derive(Serialize, Deserialize, Debug)]
     pub struct GenerateInvoice {
         pub amount_msat: Option<u64>,
         pub description: String,
         pub expiring_in: Option<u32>,
+        /// Unix timestamp (in seconds) when the invoice expire, it
+        /// can not be used together with `expiring_in`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub expiry_unix: Option<u64>,
+        /// Generate an invoice with the maximum expiry, e.g. for a
+        /// static tip code. The invoice can still be paid only once,
+        /// and after the first payment the preimage is known by the
+        /// nodes of the route, so a reusable code should be an offer.
+        #[serde(default)]
+        pub no_expiry: bool,
+        /// A unique name of the invoice, to look it up with
+        /// `listinvoices`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub label: Option<String>,
+        /// An on chain address inside the invoice, where the payer
+        /// can send the amount when there is no route to us.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub fallback: Option<String>,
+        /// Stored with the invoice and never written inside it,
+        /// e.g. the id of the order.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub metadata: Option<String>,
+    }
+
+    /// List the invoices generated by the node, optionally only
+    /// the one with the `label` or the `payment_hash`.
+    #[derive(Serialize, Deserialize, Debug, Default)]
+    pub struct ListInvoices {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub label: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub payment_hash: Option<String>,
+    }
+
+    /// Generate an invoice for a payment hash provided by the
+    /// user, the preimage is released later with `settleinvoice`.
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct GenerateHoldInvoice {
+        pub payment_hash: String,
+        pub amount_msat: Option<u64>,
+        pub description: String,
+        pub expiring_in: Option<u32>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct SettleHoldInvoice {
+        pub preimage: String,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct CancelHoldInvoice {
+        pub payment_hash: String,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct IsInvoicePaid {
+        pub invoice_str: String,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct GetPaymentRoute {
+        pub payment_hash: String,
     }
 
     #[derive(Serialize, Deserialize, Debug)]
@@ -25,7 +90,86 @@ pub mod request {
     #[derive(Serialize, Deserialize)]
     pub struct Pay {
         pub invoice_str: String,
-        pub amount: Option<u64>,
+        pub amount: Option<Msat>,
+        /// Custom TLVs included in the onion of the recipient,
+        /// only supported when paying a bolt11 invoice.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub custom_tlvs: Vec<CustomTlv>,
+        /// Seconds that the payment is retried before it is abandoned,
+        /// `pay-timeout-secs` by default.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub timeout_secs: Option<u64>,
+        /// The maximum CLTV expiry delta of the whole route, it can not
+        /// be less than the final CLTV expiry delta of the invoice.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub max_total_cltv_expiry_delta: Option<u32>,
+    }
+
+    /// Pay a LNURL-pay link or a lightning address (`user@domain`).
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct PayLnurl {
+        pub lnurl: String,
+        pub amount_msat: Msat,
+        /// A comment for the recipient, if the service accepts it.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub comment: Option<String>,
+        /// Seconds that the payment is retried before it is abandoned,
+        /// `pay-timeout-secs` by default.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub timeout_secs: Option<u64>,
+    }
+
+    /// Cancel the payment in flight made with `pay`.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct CancelPayment {
+        /// The invoice or the offer given to `pay`.
+        pub invoice_str: String,
+    }
+
+    impl Pay {
+        /// Return the custom TLVs sorted by type, as required by the onion.
+        ///
+        /// The TLV types must be unique and inside the custom range
+        /// (>= 2^16), the keysend preimage type is refused because it is
+        /// not compatible with the payment secret of an invoice.
+        pub fn custom_tlvs(&self) -> error::Result<Vec<(u64, Vec<u8>)>> {
+            let mut tlvs = self
+                .custom_tlvs
+                .iter()
+                .map(|tlv| -> error::Result<(u64, Vec<u8>)> {
+                    if tlv.r#type < CUSTOM_TLV_MIN_TYPE {
+                        error::bail!(
+                            "custom TLV type `{}` is below the custom range `{CUSTOM_TLV_MIN_TYPE}`",
+                            tlv.r#type
+                        );
+                    }
+                    if tlv.r#type == KEYSEND_TLV_TYPE {
+                        error::bail!("custom TLV type `{KEYSEND_TLV_TYPE}` is reserved to keysend");
+                    }
+                    let value = hex::decode(&tlv.value).map_err(|err| {
+                        error::anyhow!("invalid value of custom TLV `{}`: {err}", tlv.r#type)
+                    })?;
+                    Ok((tlv.r#type, value))
+                })
+                .collect::<error::Result<Vec<_>>>()?;
+            tlvs.sort_by_key(|(typ, _)| *typ);
+            if let Some(tlv) = tlvs.windows(2).find(|tlvs| tlvs[0].0 == tlvs[1].0) {
+                error::bail!("custom TLV type `{}` is duplicated", tlv[0].0);
+            }
+            Ok(tlvs)
+        }
+    }
+
+    /// First TLV type that can be used by the applications.
+    const CUSTOM_TLV_MIN_TYPE: u64 = 1 << 16;
+    /// TLV type used by keysend to carry the preimage.
+    const KEYSEND_TLV_TYPE: u64 = 5482373484;
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct CustomTlv {
+        pub r#type: u64,
+        /// Hex encoded value of the TLV.
+        pub value: String,
     }
 }
 
@@ -37,10 +181,85 @@ pub mod response {
     use serde::{Deserialize, Serialize};
 
     use crate::ldk;
+    use crate::model::Msat;
 
     #[derive(Serialize, Deserialize, Debug)]
     pub struct Invoice {
         pub bolt11: String,
+        pub payment_hash: String,
+        pub payment_secret: String,
+        /// Unix timestamp (in seconds) when the invoice expires.
+        pub expiry_unix: u64,
+    }
+
+    impl From<&ldk::invoice::Bolt11Invoice> for Invoice {
+        fn from(value: &ldk::invoice::Bolt11Invoice) -> Self {
+            Self {
+                bolt11: value.to_string(),
+                payment_hash: value.payment_hash().to_string(),
+                payment_secret: hex::encode(value.payment_secret().0),
+                expiry_unix: value
+                    .duration_since_epoch()
+                    .saturating_add(value.expiry_time())
+                    .as_secs(),
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+    pub enum InboundPaymentState {
+        Unpaid,
+        /// The HTLCs are arrived but not claimed yet.
+        Pending,
+        Paid,
+    }
+
+    /// Status of a payment received by this node.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct PaymentStatus {
+        pub payment_hash: String,
+        pub state: InboundPaymentState,
+        pub amount_received_msat: Option<Msat>,
+    }
+
+    /// An invoice generated by the node, with what is stored
+    /// next to it.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct InvoiceRecord {
+        pub label: Option<String>,
+        pub bolt11: String,
+        pub payment_hash: String,
+        pub amount_msat: Option<u64>,
+        pub fallback: Option<String>,
+        pub metadata: Option<String>,
+        /// Unix timestamp (in seconds) when the invoice was generated.
+        pub created_at: u64,
+        pub expiry_unix: u64,
+        pub state: InboundPaymentState,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct ListInvoices {
+        pub invoices: Vec<InvoiceRecord>,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    pub enum HoldInvoiceState {
+        /// Waiting for the payment.
+        Open,
+        /// The HTLCs are held until the invoice is settled or canceled.
+        Accepted,
+        Settled,
+        Canceled,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct HoldInvoice {
+        pub bolt11: String,
+        pub payment_hash: String,
+        pub state: HoldInvoiceState,
+        /// The amount received, available once the invoice is accepted.
+        pub amount_received_msat: Option<Msat>,
     }
 
     #[derive(Serialize, Deserialize, Debug)]
@@ -68,6 +287,9 @@ pub mod response {
         pub hints: Vec<String>,
         pub network: String,
         pub amount_msa: Option<u64>,
+        /// The on chain addresses where the payer can send the amount.
+        #[serde(default)]
+        pub fallbacks: Vec<String>,
     }
 
     #[derive(Serialize, Deserialize, Debug)]
@@ -94,6 +316,35 @@ pub mod response {
         pub private_hop: bool,
     }
 
+    /// A path used to deliver (part of) a successful payment.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct PaymentPath {
+        pub hops: Vec<PaymentHop>,
+        /// Fee paid to the intermediate hops of this path.
+        pub fee_msat: u64,
+        /// Amount delivered to the recipient by this path.
+        pub amount_msat: u64,
+    }
+
+    impl From<&ldk::routing::router::Path> for PaymentPath {
+        fn from(value: &ldk::routing::router::Path) -> Self {
+            Self {
+                hops: value.hops.iter().cloned().map(PaymentHop::from).collect(),
+                fee_msat: value.fee_msat(),
+                amount_msat: value.final_value_msat(),
+            }
+        }
+    }
+
+    /// The route of a completed payment, a payment can use more
+    /// than one path when it is a multi part payment.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct PaymentRoute {
+        pub payment_hash: String,
+        pub paths: Vec<PaymentPath>,
+        pub total_fee_msat: u64,
+    }
+
     impl From<RouteHop> for PaymentHop {
         fn from(value: RouteHop) -> Self {
             Self {
@@ -106,3 +357,37 @@ pub mod response {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::request::{CustomTlv, Pay};
+
+    fn pay(tlvs: &[(u64, &str)]) -> Pay {
+        Pay {
+            invoice_str: String::new(),
+            amount: None,
+            timeout_secs: None,
+            custom_tlvs: tlvs
+                .iter()
+                .map(|(typ, value)| CustomTlv {
+                    r#type: *typ,
+                    value: value.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn custom_tlvs_are_sorted() {
+        let tlvs = pay(&[(65539, "ff"), (65537, "6f72646572")]).custom_tlvs().unwrap();
+        assert_eq!(tlvs, vec![(65537, b"order".to_vec()), (65539, vec![0xff])]);
+    }
+
+    #[test]
+    fn invalid_custom_tlvs() {
+        assert!(pay(&[(42, "00")]).custom_tlvs().is_err());
+        assert!(pay(&[(5482373484, "00")]).custom_tlvs().is_err());
+        assert!(pay(&[(65537, "not hex")]).custom_tlvs().is_err());
+        assert!(pay(&[(65537, "00"), (65537, "01")]).custom_tlvs().is_err());
+    }
+}
@@ -4,10 +4,16 @@ pub mod request {
     use bitcoin::secp256k1::PublicKey;
     use serde::{Deserialize, Serialize};
 
+    use crate::model::Msat;
+
     #[derive(Serialize, Deserialize)]
     pub struct KeySend {
         pub destination: PublicKey,
-        pub amount_msat: u64,
+        pub amount_msat: Msat,
+        /// The maximum CLTV expiry delta of the whole route, it can not
+        /// be less than the final CLTV expiry delta of the keysend.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub max_total_cltv_expiry_delta: Option<u32>,
     }
 }
 
@@ -0,0 +1,30 @@
+//! List funds model
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    use crate::model::on_chain::response::Utxo;
+    use crate::model::{Msat, Sat};
+
+    /// The funds of the node inside a channel.
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct ChannelFunds {
+        pub channel_id: String,
+        pub short_channel_id: Option<u64>,
+        pub peer_id: String,
+        /// What we can send plus the reserve that we must keep.
+        pub our_amount_msat: Msat,
+        pub amount_msat: Msat,
+        /// `pending`, `active` or `inactive`, as the channels counted
+        /// inside `getinfo`.
+        pub state: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct ListFunds {
+        pub outputs: Vec<Utxo>,
+        pub channels: Vec<ChannelFunds>,
+        pub total_onchain_sat: Sat,
+        pub total_channel_sat: Sat,
+    }
+}
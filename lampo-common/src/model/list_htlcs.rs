@@ -0,0 +1,50 @@
+//! List HTLCs Request
+
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct ListHtlcs {
+        /// Only list the HTLCs of this channel, any identifier
+        /// accepted by `getchannel` can be used.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub channel: Option<String>,
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+    #[serde(rename_all = "snake_case")]
+    pub enum HtlcDirection {
+        Inbound,
+        Outbound,
+    }
+
+    /// An HTLC that is not yet removed from the channel.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Htlc {
+        pub channel_id: String,
+        pub peer_id: String,
+        pub direction: HtlcDirection,
+        /// The id of the HTLC inside the channel, it is unknown
+        /// for an outbound HTLC not yet sent to the peer.
+        pub htlc_id: Option<u64>,
+        pub amount_msat: u64,
+        pub payment_hash: String,
+        pub cltv_expiry: u32,
+        /// Blocks left before the CLTV expiry, when it gets near
+        /// to zero the channel risk to be force closed.
+        pub deadline_blocks: i64,
+        /// Seconds since lampo has seen the HTLC the first time.
+        pub age_secs: u64,
+        pub state: String,
+        pub is_dust: bool,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Htlcs {
+        pub htlcs: Vec<Htlc>,
+    }
+}
@@ -0,0 +1,43 @@
+//! List Pays Request
+
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct ListPays {
+        /// Only list the payments with this payment hash.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub payment_hash: Option<String>,
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum PayStatus {
+        Pending,
+        Complete,
+        Failed,
+    }
+
+    /// An outbound payment made by this node.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct SentPayment {
+        /// Unknown while the invoice of an offer is not received.
+        pub payment_hash: Option<String>,
+        pub status: PayStatus,
+        /// Amount delivered to the recipient, unknown when ldk
+        /// forgot the payment.
+        pub amount_msat: Option<u64>,
+        /// Amount sent with the fees, known only when the payment
+        /// is complete.
+        pub amount_sent_msat: Option<u64>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Pays {
+        pub pays: Vec<SentPayment>,
+    }
+}
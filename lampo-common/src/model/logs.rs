@@ -0,0 +1,50 @@
+//! Logs Model
+
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct GetLog {
+        /// The most verbose level of the records, e.g. `info` returns
+        /// the `error`, `warn` and `info` ones.
+        pub level: Option<String>,
+        /// The number of the last records returned.
+        pub limit: Option<usize>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct SetLogLevel {
+        /// The target (e.g. `lampo_bdk_wallet` or `ldk`) and its
+        /// submodules, without it the default level is changed.
+        pub target: Option<String>,
+        /// One of `off`, `error`, `warn`, `info`, `debug` or `trace`.
+        pub level: String,
+    }
+}
+
+pub mod response {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct LogRecord {
+        /// Unix timestamp of the record.
+        pub timestamp: u64,
+        pub level: String,
+        pub target: String,
+        pub message: String,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Log {
+        pub records: Vec<LogRecord>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct LogLevels {
+        /// The level of the targets not inside `targets`.
+        pub level: String,
+        pub targets: BTreeMap<String, String>,
+    }
+}
@@ -0,0 +1,31 @@
+//! Maintenance Model
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    /// The size of a store before and after the maintenance.
+    #[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+    pub struct StoreSize {
+        pub name: String,
+        pub path: String,
+        pub bytes_before: u64,
+        pub bytes_after: u64,
+    }
+
+    /// A file that can not be read back.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct CorruptedFile {
+        pub name: String,
+        pub path: String,
+        pub error: String,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Maintenance {
+        pub stores: Vec<StoreSize>,
+        /// The files that failed the integrity check, empty when all
+        /// the files are fine.
+        pub corrupted: Vec<CorruptedFile>,
+        pub elapsed_ms: u64,
+    }
+}
@@ -0,0 +1,41 @@
+//! Signed Messages Model
+
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct SignMessage {
+        pub message: String,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct CheckMessage {
+        pub message: String,
+        /// The zbase32 signature given by `signmessage`.
+        pub zbase: String,
+        /// The key expected to sign the message, without it the
+        /// signer must be a node of our network graph.
+        pub pubkey: Option<String>,
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct SignMessage {
+        /// The signature encoded in zbase32, as CLN and LND do.
+        pub zbase: String,
+        /// The node id that signed the message.
+        pub pubkey: String,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct CheckMessage {
+        pub verified: bool,
+        /// The key that signed the message.
+        pub pubkey: String,
+        /// The alias of the signer, when it is inside our network graph.
+        pub alias: Option<String>,
+    }
+}
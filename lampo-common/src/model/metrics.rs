@@ -0,0 +1,11 @@
+//! Metrics Model
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Metrics {
+        /// The metrics in the text format of Prometheus.
+        pub text: String,
+    }
+}
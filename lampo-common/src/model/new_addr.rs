@@ -4,6 +4,21 @@ pub mod request {
 
     #[derive(Serialize, Deserialize)]
     pub struct NewAddress;
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Bip21Uri {
+        /// The amount to receive in satoshi.
+        pub amount_sat: Option<u64>,
+        pub label: Option<String>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct UnifiedReceive {
+        pub amount_msat: u64,
+        pub description: String,
+        /// Seconds before the invoice expires.
+        pub expiring_in: Option<u32>,
+    }
 }
 
 pub mod response {
@@ -13,4 +28,28 @@ pub mod response {
     pub struct NewAddress {
         pub address: String,
     }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Bip21Uri {
+        /// The `bitcoin:` URI to pay.
+        pub uri: String,
+        pub address: String,
+        pub amount_sat: Option<u64>,
+        pub label: Option<String>,
+        /// The invoice inside the URI, there is one when the amount
+        /// is specified and the node has a usable channel.
+        pub bolt11: Option<String>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct UnifiedReceive {
+        /// The BIP21 URI with both the address and the invoice.
+        pub uri: String,
+        pub address: String,
+        pub amount_msat: u64,
+        pub bolt11: String,
+        pub payment_hash: String,
+        /// Unix timestamp (in seconds) when the invoice expires.
+        pub expiry_unix: u64,
+    }
 }
@@ -3,17 +3,101 @@ pub mod request {}
 pub mod response {
     use serde::{Deserialize, Serialize};
 
+    use crate::backend::BroadcastError;
+    use crate::model::{Msat, Sat};
+
     #[derive(Debug, Serialize, Deserialize)]
     pub struct Utxo {
         pub txid: String,
         pub vout: u32,
         pub reserved: bool,
         pub confirmed: u32,
-        pub amount_msat: u64,
+        pub amount_msat: Msat,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub address: Option<String>,
     }
 
     #[derive(Debug, Serialize, Deserialize)]
     pub struct Utxos {
         pub transactions: Vec<Utxo>,
+        /// Satoshis received by the wallet that are not confirmed yet.
+        pub pending_incoming_sat: Sat,
+    }
+
+    /// A transaction that touched the wallet.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct TxDetail {
+        pub txid: String,
+        /// Satoshis received minus the satoshis sent by the wallet,
+        /// so a payment is negative and includes the fee.
+        pub net_amount_sat: i64,
+        /// The fee, when the wallet knows all the inputs.
+        pub fee_sat: Option<u64>,
+        pub confirmation_height: Option<u32>,
+        /// Unix timestamp of the block, or when the transaction was
+        /// seen if it is not confirmed.
+        pub timestamp: Option<u64>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct TxHistory {
+        /// The unconfirmed transactions first, then the newest ones.
+        pub transactions: Vec<TxDetail>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct FeeRate {
+        /// The ldk confirmation target.
+        pub target: String,
+        /// Blocks in which the transaction should confirm.
+        pub blocks: u16,
+        pub sat_per_kw: u32,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct FeeRates {
+        /// `esplora` or `backend`.
+        pub source: String,
+        /// Unix timestamp of the last refresh of the estimations.
+        pub updated_at: Option<u64>,
+        pub min_sat_per_kw: u32,
+        pub max_sat_per_kw: Option<u32>,
+        pub fee_rates: Vec<FeeRate>,
+    }
+
+    /// The chain as seen by the node.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct ChainInfo {
+        pub network: String,
+        /// The tip of the best chain known by the chain manager,
+        /// `None` until the backend notifies the first block.
+        pub best_block_hash: Option<String>,
+        pub best_block_height: Option<u32>,
+        /// The height of the last block synced by the wallet.
+        pub wallet_synced_height: Option<u32>,
+        /// The fee estimations were refreshed recently.
+        pub fees_fresh: bool,
+        /// Unix timestamp of the last refresh of the estimations.
+        pub fees_updated_at: Option<u64>,
+        /// The kind of the backend, e.g. `core`.
+        pub backend: String,
+        pub backend_endpoint: Option<String>,
+    }
+
+    /// A transaction rejected by the backend, that lampo broadcasts
+    /// again at every new block.
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct PendingBroadcast {
+        pub txid: String,
+        /// The error of the last broadcast.
+        pub error: BroadcastError,
+        /// The human readable version of `error`.
+        pub reason: String,
+        pub attempts: u32,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct PendingBroadcasts {
+        pub transactions: Vec<PendingBroadcast>,
     }
 }
@@ -0,0 +1,77 @@
+//! Onion Message Model
+
+pub mod request {
+    use std::str::FromStr;
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::error;
+    use crate::ldk::blinded_path::BlindedPath;
+    use crate::ldk::onion_message::messenger::Destination;
+    use crate::ldk::util::ser::Readable;
+    use crate::types::NodeId;
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct SendOnionMessage {
+        /// A node id, or the hex of a blinded path.
+        pub destination: Option<String>,
+        /// Answer the received onion message with this id through
+        /// its reply path, in place of the `destination`.
+        pub reply_to: Option<u64>,
+        /// The tlv type of the message, at least 64.
+        pub tlv_type: u64,
+        /// Hex of the payload.
+        pub payload: String,
+        /// Attach our reply path, so the destination can answer.
+        #[serde(default)]
+        pub reply_path: bool,
+    }
+
+    impl SendOnionMessage {
+        pub fn destination(&self) -> error::Result<Destination> {
+            let Some(ref destination) = self.destination else {
+                error::bail!("`destination` or `reply_to` is required");
+            };
+            if let Ok(node_id) = NodeId::from_str(destination) {
+                return Ok(Destination::Node(node_id));
+            }
+            let bytes = hex::decode(destination)
+                .map_err(|err| error::anyhow!("invalid destination `{destination}`: {err}"))?;
+            let path: BlindedPath = Readable::read(&mut bytes.as_slice())
+                .map_err(|err| error::anyhow!("invalid blinded path: {err:?}"))?;
+            Ok(Destination::BlindedPath(path))
+        }
+
+        pub fn payload(&self) -> error::Result<Vec<u8>> {
+            Ok(hex::decode(&self.payload)?)
+        }
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct WaitOnionMessage {
+        /// Seconds to wait for a message, 30 by default.
+        pub timeout_secs: Option<u64>,
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct OnionMessage {
+        /// Id of the message, used to answer it with `reply_to`.
+        pub id: u64,
+        pub tlv_type: u64,
+        /// Hex of the payload.
+        pub payload: String,
+        /// True if the sender attached a reply path.
+        pub reply_path: bool,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct SendOnionMessage {
+        /// True if the message waits for a connection with the
+        /// first hop before being sent.
+        pub awaiting_connection: bool,
+    }
+}
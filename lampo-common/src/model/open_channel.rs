@@ -17,12 +17,28 @@ pub mod request {
         pub public: bool,
     }
 
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct SetChannel {
+        /// A short channel id, an scid alias, a hex channel
+        /// id or a funding outpoint.
+        pub id: String,
+        pub enabled: bool,
+    }
+
     impl OpenChannel {
         pub fn node_id(&self) -> error::Result<NodeId> {
             let node_id = NodeId::from_str(&self.node_id)?;
             Ok(node_id)
         }
     }
+
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct EstimateOpenCost {
+        pub amount: u64,
+        /// Blocks in which the funding transaction should confirm,
+        /// 6 by default.
+        pub conf_target: Option<u16>,
+    }
 }
 
 pub mod response {
@@ -32,8 +48,19 @@ pub mod response {
 
     use crate::bitcoin::Transaction;
     use crate::error;
+    use crate::model::response::PendingClose;
     use crate::types::NodeId;
 
+    /// The on chain cost of opening a channel.
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct OpenCost {
+        pub amount_sat: u64,
+        pub fee_sat: u64,
+        /// The funding plus the fee, that the wallet must cover.
+        pub total_sat: u64,
+        pub conf_target: u16,
+    }
+
     #[derive(Serialize, Deserialize, Debug, Clone)]
     pub struct Channels {
         pub channels: Vec<Channel>,
@@ -61,13 +88,29 @@ pub mod response {
         // Channel_id needs to be string as it currently does not derive Serialize
         pub channel_id: String,
         pub short_channel_id: Option<u64>,
+        /// The alias that our peer should use to forward payments to us,
+        /// this is the one used inside the invoice route hints.
+        pub inbound_scid_alias: Option<u64>,
+        /// The alias that we use to forward payments to our peer.
+        pub outbound_scid_alias: Option<u64>,
         pub peer_id: String,
         pub peer_alias: Option<String>,
         pub ready: bool,
+        /// Confirmations of the funding transaction, none until it
+        /// is confirmed.
+        pub confirmations: Option<u32>,
+        /// Confirmations needed before the channel is ready.
+        pub confirmations_required: Option<u32>,
+        /// True if the channel is ready and the peer is connected.
+        pub usable: bool,
         pub amount_satoshis: u64,
         pub amount_msat: u64,
         pub public: bool,
         pub available_balance_for_send_msat: u64,
         pub available_balance_for_recv_msat: u64,
+        /// False if the forwarding through the channel is disabled.
+        pub enabled: bool,
+        /// The cooperative close queued while the peer is offline.
+        pub pending_close: Option<PendingClose>,
     }
 }
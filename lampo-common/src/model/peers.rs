@@ -0,0 +1,136 @@
+//! Peers Model
+
+pub mod request {
+    use std::str::FromStr;
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::error;
+    use crate::types::NodeId;
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Disconnect {
+        pub node_id: String,
+        /// Disconnect even if there are HTLCs in flight with the peer.
+        #[serde(default)]
+        pub force: bool,
+    }
+
+    impl Disconnect {
+        pub fn node_id(&self) -> error::Result<NodeId> {
+            Ok(NodeId::from_str(&self.node_id)?)
+        }
+    }
+
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum PeerListKind {
+        Allow,
+        Deny,
+    }
+
+    /// Add or remove a peer from the allow or the deny list.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct SetPeerList {
+        pub list: PeerListKind,
+        pub node_id: String,
+        #[serde(default)]
+        pub remove: bool,
+    }
+
+    impl SetPeerList {
+        pub fn node_id(&self) -> error::Result<NodeId> {
+            Ok(NodeId::from_str(&self.node_id)?)
+        }
+    }
+
+    /// Default number of pings sent by the `ping` command.
+    pub const DEFAULT_PING_COUNT: u32 = 3;
+    /// Maximum number of pings sent by a single `ping` command.
+    pub const MAX_PING_COUNT: u32 = 100;
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Ping {
+        pub node_id: String,
+        /// Number of pings to send, one after the other.
+        pub count: Option<u32>,
+    }
+
+    impl Ping {
+        pub fn node_id(&self) -> error::Result<NodeId> {
+            Ok(NodeId::from_str(&self.node_id)?)
+        }
+
+        pub fn count(&self) -> error::Result<u32> {
+            let count = self.count.unwrap_or(DEFAULT_PING_COUNT);
+            if count == 0 || count > MAX_PING_COUNT {
+                error::bail!(
+                    "invalid `count` `{count}`, it must be between 1 and {MAX_PING_COUNT}"
+                );
+            }
+            Ok(count)
+        }
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Peer {
+        pub node_id: String,
+        pub address: Option<String>,
+        /// True if the connection was opened by the peer.
+        pub inbound: bool,
+        /// Seconds since lampo has seen the connection the first time.
+        pub connected_secs: u64,
+        /// Hex of the init features negotiated with the peer.
+        pub features: String,
+        pub channels: usize,
+        /// The alias announced by the peer in the network graph.
+        pub alias: Option<String>,
+        /// False for a peer that we have channels with, but that
+        /// is not connected right now.
+        pub connected: bool,
+        /// The state of the automatic reconnection, if lampo failed
+        /// to reconnect with the peer.
+        pub reconnect: Option<Reconnect>,
+        /// Unix timestamp of the last pong received from the peer.
+        pub last_pong: Option<u64>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Reconnect {
+        /// Number of failed attempts.
+        pub attempts: u32,
+        /// Unix timestamp of the next attempt.
+        pub next_attempt: u64,
+        pub last_error: Option<String>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Peers {
+        pub peers: Vec<Peer>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Ping {
+        pub node_id: String,
+        pub sent: u32,
+        pub received: u32,
+        pub loss_percent: f64,
+        /// Round trip times in milliseconds, `None` when no
+        /// pong was received.
+        pub min_ms: Option<f64>,
+        pub avg_ms: Option<f64>,
+        pub max_ms: Option<f64>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct PeerLists {
+        /// When not empty, only these peers can connect with us.
+        pub allowlist: Vec<String>,
+        pub denylist: Vec<String>,
+        pub max_inbound_peers: Option<usize>,
+    }
+}
@@ -0,0 +1,57 @@
+//! Plugins Model
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    /// What a plugin answers to `getmanifest`.
+    #[derive(Clone, Serialize, Deserialize, Debug, Default)]
+    pub struct PluginManifest {
+        /// The methods added to the RPC of lampo.
+        #[serde(default)]
+        pub rpcmethods: Vec<PluginMethod>,
+        /// The kinds of the events notified to the plugin
+        /// (e.g. `payment_received`).
+        #[serde(default)]
+        pub subscriptions: Vec<String>,
+        #[serde(default)]
+        pub hooks: Vec<PluginHook>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct PluginMethod {
+        pub name: String,
+        #[serde(default)]
+        pub description: String,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct PluginHook {
+        /// One of `invoice_payment`, `openchannel` or `htlc_accepted`.
+        pub name: String,
+    }
+
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+    pub enum PluginStatus {
+        Running,
+        /// The plugin exited or failed the handshake, its methods
+        /// and hooks are not used anymore.
+        Crashed,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Plugin {
+        pub name: String,
+        pub path: String,
+        pub status: PluginStatus,
+        pub methods: Vec<String>,
+        pub subscriptions: Vec<String>,
+        pub hooks: Vec<String>,
+        /// Why the plugin crashed.
+        pub error: Option<String>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Plugins {
+        pub plugins: Vec<Plugin>,
+    }
+}
@@ -0,0 +1,15 @@
+//! Reload Model
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Reload {
+        /// The options changed inside the file and applied to
+        /// the running node.
+        pub applied: Vec<String>,
+        /// The options changed inside the file that are applied
+        /// only when the node restarts.
+        pub restart_required: Vec<String>,
+    }
+}
@@ -0,0 +1,34 @@
+//! RPC Tokens Model
+
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct MintToken {
+        /// One of `admin`, `readonly`, `invoice` or `nowithdraw`.
+        pub scope: String,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct RevokeToken {
+        /// The id given by `minttoken`.
+        pub id: u64,
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct MintToken {
+        pub id: u64,
+        pub scope: String,
+        /// The token to put in the `token` param of the requests.
+        pub token: String,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct RevokeToken {
+        pub id: u64,
+    }
+}
@@ -0,0 +1,112 @@
+//! Watchtowers Model
+
+pub mod request {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct AddTower {
+        /// The tower as `<tower id>@<host>[:<port>]`.
+        pub tower: String,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct TowerStatus {
+        pub tower_id: String,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct DelTowerClient {
+        /// The node id of the client.
+        pub user_id: String,
+    }
+}
+
+pub mod response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Tower {
+        pub tower_id: String,
+        pub address: String,
+        /// `active`, `unreachable` when the last request failed and
+        /// it is retried, or `misbehaving` when the tower signed an
+        /// appointment with another key, and nothing else is sent to it.
+        pub status: String,
+        /// The node is registered with the tower.
+        pub registered: bool,
+        pub available_slots: u32,
+        /// The height where the subscription expires.
+        pub subscription_expiry: u32,
+        /// The appointments accepted by the tower.
+        pub appointments: u64,
+        /// The appointments waiting to be sent.
+        pub pending_appointments: u64,
+        pub last_error: Option<String>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Towers {
+        pub towers: Vec<Tower>,
+    }
+
+    /// The appointments of a channel on a tower.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct TowerChannel {
+        /// The funding outpoint of the channel.
+        pub funding_txo: String,
+        pub appointments: u64,
+        pub pending_appointments: u64,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct TowerStatus {
+        #[serde(flatten)]
+        pub tower: Tower,
+        pub channels: Vec<TowerChannel>,
+    }
+
+    /// A client of the tower of the node.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct TowerClient {
+        /// The node id of the client.
+        pub user_id: String,
+        pub available_slots: u32,
+        /// The height where the subscription expires.
+        pub subscription_expiry: u32,
+        /// The appointments stored for the client.
+        pub appointments: u64,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct TowerClients {
+        pub clients: Vec<TowerClient>,
+    }
+
+    /// A revoked commitment punished by the tower.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct TowerBreach {
+        pub locator: String,
+        pub user_id: String,
+        pub commitment_txid: String,
+        pub justice_txid: String,
+        /// The height of the block with the revoked commitment.
+        pub height: u32,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct TowerInfo {
+        /// The id of the tower, the node id.
+        pub tower_id: String,
+        /// The address where the tower listens.
+        pub bind: String,
+        pub clients: u64,
+        pub appointments: u64,
+        /// The appointments of a registration.
+        pub max_appointments: u32,
+        /// The blocks that a registration lasts.
+        pub subscription_blocks: u32,
+        /// The last block looked for the revoked commitments.
+        pub scanned_height: Option<u32>,
+        pub breaches: Vec<TowerBreach>,
+    }
+}
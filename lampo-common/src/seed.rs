@@ -0,0 +1,250 @@
+//! Storage of the mnemonic of the wallet.
+//!
+//! The mnemonic is written inside the lampo directory when the wallet
+//! is generated or restored, and it is read at every start. The file
+//! is readable only by the user, and lampo refuses to start when the
+//! permissions are broader.
+//!
+//! The file has the mnemonic on the first line and, on the second
+//! one, the HMAC-SHA256 of the mnemonic keyed with a tagged hash of
+//! the mnemonic itself. The mac does not protect from someone that can
+//! write the file, but it catches a corrupted mnemonic before the node
+//! derives a valid wallet that is not its own.
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::{Hash, HashEngine};
+
+use crate::error;
+
+/// File with the mnemonic of the wallet.
+pub const SEED_FILE: &str = "seed";
+
+/// Tag of the key of the mac, so it is not the hash of the mnemonic.
+const SEED_MAC_TAG: &[u8] = b"lampo/seed-store/v1";
+
+/// The permissions that no one else than the user must have.
+const OTHERS_MODE: u32 = 0o077;
+
+/// Error returned when the mnemonic can not be read.
+#[derive(Debug)]
+pub enum SeedError {
+    /// There is no mnemonic stored.
+    Missing(PathBuf),
+    /// The file exists, but it can not be read.
+    Unreadable(PathBuf, io::Error),
+    /// The file does not contain a valid mnemonic, or the mac of the
+    /// mnemonic does not match.
+    Corrupt(PathBuf, String),
+    /// The file can be accessed by other users than the owner.
+    Permissions(PathBuf, u32),
+}
+
+impl fmt::Display for SeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(path) => write!(f, "there is no mnemonic at `{}`", path.display()),
+            Self::Unreadable(path, err) => {
+                write!(
+                    f,
+                    "impossible read the mnemonic at `{}`: {err}",
+                    path.display()
+                )
+            }
+            Self::Corrupt(path, reason) => {
+                write!(
+                    f,
+                    "the mnemonic at `{}` is corrupted: {reason}",
+                    path.display()
+                )
+            }
+            Self::Permissions(path, mode) => write!(
+                f,
+                "the mnemonic at `{}` has permissions `{mode:o}`, they must be `600`",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SeedError {}
+
+/// The mnemonic of the wallet inside the lampo directory.
+pub struct SeedStore {
+    path: PathBuf,
+}
+
+impl SeedStore {
+    pub fn new(root_path: &str) -> Self {
+        Self {
+            path: Path::new(root_path).join(SEED_FILE),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read the mnemonic, checking the permissions and the mac of
+    /// the file.
+    pub fn load(&self) -> Result<String, SeedError> {
+        let metadata = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(SeedError::Missing(self.path.clone()))
+            }
+            Err(err) => return Err(SeedError::Unreadable(self.path.clone(), err)),
+        };
+        let mode = metadata.permissions().mode() & 0o777;
+        if mode & OTHERS_MODE != 0 {
+            return Err(SeedError::Permissions(self.path.clone(), mode));
+        }
+        let content = fs::read_to_string(&self.path)
+            .map_err(|err| SeedError::Unreadable(self.path.clone(), err))?;
+        let corrupt = |reason: &str| SeedError::Corrupt(self.path.clone(), reason.to_owned());
+        let mut lines = content.lines();
+        let mnemonic = lines.next().unwrap_or_default().trim();
+        let mac = lines.next().unwrap_or_default().trim();
+        if mnemonic.is_empty() {
+            return Err(corrupt("the mnemonic is empty"));
+        }
+        if lines.any(|line| !line.trim().is_empty()) {
+            return Err(corrupt("unexpected content after the mac"));
+        }
+        let mac = hex::decode(mac).map_err(|_| corrupt("the mac is not valid hex"))?;
+        if mac != seed_mac(mnemonic) {
+            return Err(corrupt("the mac does not match the mnemonic"));
+        }
+        Ok(mnemonic.to_owned())
+    }
+
+    /// Read the mnemonic, `None` when it was never stored.
+    pub fn load_if_exists(&self) -> error::Result<Option<String>> {
+        match self.load() {
+            Ok(mnemonic) => Ok(Some(mnemonic)),
+            Err(SeedError::Missing(_)) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Store the `mnemonic`, readable only by the user.
+    ///
+    /// Storing again the same mnemonic does nothing, while a different
+    /// one is refused: the node would lose the keys of its funds.
+    pub fn store(&self, mnemonic: &str) -> error::Result<()> {
+        let mnemonic = mnemonic.split_whitespace().collect::<Vec<_>>().join(" ");
+        if let Some(stored) = self.load_if_exists()? {
+            if stored != mnemonic {
+                error::bail!(
+                    "there is already a different mnemonic at `{}`, remove it to use a new one",
+                    self.path.display()
+                );
+            }
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&self.path)?;
+        let mac = hex::encode(seed_mac(&mnemonic));
+        file.write_all(format!("{mnemonic}\n{mac}\n").as_bytes())?;
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+/// The mac of the `mnemonic`, keyed by a derivation of the mnemonic.
+fn seed_mac(mnemonic: &str) -> Vec<u8> {
+    let mut engine = sha256::Hash::engine();
+    engine.input(SEED_MAC_TAG);
+    engine.input(mnemonic.as_bytes());
+    let key = sha256::Hash::from_engine(engine);
+
+    let mut engine = HmacEngine::<sha256::Hash>::new(&key.to_byte_array());
+    engine.input(mnemonic.as_bytes());
+    Hmac::<sha256::Hash>::from_engine(engine)
+        .to_byte_array()
+        .to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    fn store(name: &str) -> SeedStore {
+        let path = std::env::temp_dir().join(format!("lampo-seed-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        SeedStore::new(path.to_str().unwrap())
+    }
+
+    #[test]
+    fn store_and_load_the_mnemonic() {
+        let seeds = store("roundtrip");
+        assert!(matches!(seeds.load(), Err(SeedError::Missing(_))));
+        assert_eq!(seeds.load_if_exists().unwrap(), None);
+
+        seeds.store(MNEMONIC).unwrap();
+        let mode = fs::metadata(seeds.path()).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(seeds.load().unwrap(), MNEMONIC);
+
+        // the same mnemonic again is fine, another one is refused
+        seeds.store(&format!("  {MNEMONIC} ")).unwrap();
+        let other = MNEMONIC.replace("about", "abandon");
+        assert!(seeds.store(&other).is_err());
+        assert_eq!(seeds.load().unwrap(), MNEMONIC);
+        fs::remove_dir_all(seeds.path().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn refuse_broad_permissions() {
+        let seeds = store("permissions");
+        seeds.store(MNEMONIC).unwrap();
+        fs::set_permissions(seeds.path(), fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(matches!(
+            seeds.load(),
+            Err(SeedError::Permissions(_, 0o644))
+        ));
+        assert!(seeds.load_if_exists().is_err());
+        fs::remove_dir_all(seeds.path().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn detect_a_corrupted_mnemonic() {
+        let seeds = store("corrupt");
+        seeds.store(MNEMONIC).unwrap();
+        let content = fs::read_to_string(seeds.path()).unwrap();
+        // a valid word of the list, so only the mac can catch it
+        fs::write(seeds.path(), content.replacen("about", "above", 1)).unwrap();
+        assert!(matches!(seeds.load(), Err(SeedError::Corrupt(_, _))));
+
+        fs::write(seeds.path(), format!("{MNEMONIC}\nnot hex\n")).unwrap();
+        assert!(matches!(seeds.load(), Err(SeedError::Corrupt(_, _))));
+
+        fs::write(seeds.path(), "").unwrap();
+        assert!(matches!(seeds.load(), Err(SeedError::Corrupt(_, _))));
+        fs::remove_dir_all(seeds.path().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn report_an_unreadable_seed() {
+        let seeds = store("unreadable");
+        // a directory has the right permissions, but it is not a file
+        fs::create_dir_all(seeds.path()).unwrap();
+        fs::set_permissions(seeds.path(), fs::Permissions::from_mode(0o700)).unwrap();
+        assert!(matches!(seeds.load(), Err(SeedError::Unreadable(_, _))));
+        fs::remove_dir_all(seeds.path().parent().unwrap()).unwrap();
+    }
+}
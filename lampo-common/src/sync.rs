@@ -0,0 +1,68 @@
+//! Locks that recover from the poisoning.
+//!
+//! A lock is poisoned when a thread panics while holding it, and from
+//! then on every `lock().unwrap()` panics too, so one bug takes down
+//! the whole node. The state behind the locks of lampo is changed in
+//! steps that leave it consistent, so the guard is taken back from
+//! the poisoned lock instead.
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+fn recover<G>(err: PoisonError<G>) -> G {
+    log::warn!(target: "lampo", "lock poisoned by a panic, recovering it");
+    err.into_inner()
+}
+
+pub trait MutexExt<T> {
+    /// Lock the mutex, recovering it when it is poisoned.
+    fn lock_or_recover(&self) -> MutexGuard<'_, T>;
+}
+
+impl<T> MutexExt<T> for Mutex<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T> {
+        self.lock().unwrap_or_else(recover)
+    }
+}
+
+pub trait RwLockExt<T> {
+    /// Take the read lock, recovering it when it is poisoned.
+    fn read_or_recover(&self) -> RwLockReadGuard<'_, T>;
+    /// Take the write lock, recovering it when it is poisoned.
+    fn write_or_recover(&self) -> RwLockWriteGuard<'_, T>;
+}
+
+impl<T> RwLockExt<T> for RwLock<T> {
+    fn read_or_recover(&self) -> RwLockReadGuard<'_, T> {
+        self.read().unwrap_or_else(recover)
+    }
+
+    fn write_or_recover(&self) -> RwLockWriteGuard<'_, T> {
+        self.write().unwrap_or_else(recover)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex, RwLock};
+
+    use super::{MutexExt, RwLockExt};
+
+    #[test]
+    fn recover_poisoned_locks() {
+        let mutex = Arc::new(Mutex::new(1));
+        let rwlock = Arc::new(RwLock::new(1));
+        let (inner_mutex, inner_rwlock) = (mutex.clone(), rwlock.clone());
+        let result = std::thread::spawn(move || {
+            let _mutex = inner_mutex.lock().unwrap();
+            let _rwlock = inner_rwlock.write().unwrap();
+            panic!("poison the locks");
+        })
+        .join();
+        assert!(result.is_err());
+        assert!(mutex.is_poisoned() && rwlock.is_poisoned());
+
+        *mutex.lock_or_recover() += 1;
+        assert_eq!(*mutex.lock_or_recover(), 2);
+        *rwlock.write_or_recover() += 1;
+        assert_eq!(*rwlock.read_or_recover(), 2);
+    }
+}
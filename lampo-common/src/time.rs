@@ -0,0 +1,52 @@
+//! The wall clock of the node, and the expiries computed from it.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::LampoError;
+
+/// The seconds since the unix epoch, zero when the clock of the
+/// system is before it.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default()
+}
+
+/// The seconds from `now` to the unix timestamp `expiry_unix`, that
+/// must be in the future and fit the expiry of an invoice.
+pub fn expiry_delta(expiry_unix: u64, now: u64) -> Result<u32, LampoError> {
+    if expiry_unix <= now {
+        return Err(LampoError::InvalidParams(format!(
+            "expiry `{expiry_unix}` is in the past, current time is `{now}`"
+        )));
+    }
+    u32::try_from(expiry_unix - now).map_err(|_| {
+        LampoError::InvalidParams(format!("expiry `{expiry_unix}` is too far in the future"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expiry_in_the_past_is_rejected() {
+        let err = expiry_delta(1_000, 1_000).unwrap_err();
+        assert!(err.to_string().contains("in the past"), "{err}");
+        let err = expiry_delta(999, 1_000).unwrap_err();
+        assert!(err.to_string().contains("in the past"), "{err}");
+    }
+
+    #[test]
+    fn expiry_too_far_is_rejected() {
+        let now = 1_000;
+        let err = expiry_delta(now + u32::MAX as u64 + 1, now).unwrap_err();
+        assert!(err.to_string().contains("too far"), "{err}");
+        assert_eq!(expiry_delta(now + u32::MAX as u64, now).unwrap(), u32::MAX);
+    }
+
+    #[test]
+    fn expiry_delta_from_now() {
+        assert_eq!(expiry_delta(1_000 + 3600, 1_000).unwrap(), 3600);
+    }
+}
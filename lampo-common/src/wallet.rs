@@ -1,10 +1,123 @@
+#[cfg(any(test, feature = "test-utils"))]
+pub mod memory;
+
+use std::collections::BTreeMap;
+use std::fmt;
 use std::sync::Arc;
 
-use crate::bitcoin::{ScriptBuf, Transaction};
+use crate::bitcoin::psbt::PartiallySignedTransaction;
+use crate::bitcoin::{Block, OutPoint, ScriptBuf, Transaction};
 use crate::conf::LampoConf;
 use crate::error;
 use crate::keys::LampoKeys;
-use crate::model::response::{NewAddress, Utxo};
+use crate::ldk::events::bump_transaction;
+use crate::model::response::{NewAddress, StoreSize, TxDetail, Utxo};
+use crate::model::Sat;
+
+/// Weight of the witness (plus the empty script sig) needed to spend
+/// a P2WPKH output: script sig len * 4, items count, sig len, sig,
+/// pubkey len, pubkey.
+pub const P2WPKH_SATISFACTION_WEIGHT: u64 = 4 + 1 + 1 + 73 + 1 + 33;
+
+/// Default minimum fee rate in sat/vB, that is the default
+/// min relay fee of bitcoin core.
+pub const DEFAULT_MIN_FEE_RATE_SAT_VB: f32 = 1.0;
+
+/// Error returned by the wallet when a transaction can not be built.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WalletError {
+    /// The fee rate (in sat/kW) is below the minimum fee rate, so
+    /// the transaction would not be relayed by the network.
+    FeeTooLow { fee_rate: u32, min_fee_rate: u32 },
+    /// The outputs with at least `min_confirmations` confirmations do
+    /// not cover the amount and the fee.
+    InsufficientFunds {
+        needed_sat: u64,
+        available_sat: u64,
+        min_confirmations: u32,
+    },
+}
+
+impl fmt::Display for WalletError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FeeTooLow {
+                fee_rate,
+                min_fee_rate,
+            } => write!(
+                f,
+                "fee rate of `{fee_rate}` sat/kW is below the minimum of `{min_fee_rate}` sat/kW"
+            ),
+            Self::InsufficientFunds {
+                needed_sat,
+                available_sat,
+                min_confirmations,
+            } => write!(
+                f,
+                "not enough funds with at least {min_confirmations} confirmations: needed {needed_sat} sats, available {available_sat} sats"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for WalletError {}
+
+/// Convert a fee rate from sat/vB to sat/kW, the unit used by ldk
+/// and by the `WalletManager`.
+///
+/// The fractional rates are kept, e.g. 0.5 sat/vB is 125 sat/kW. A
+/// sat/kW is 0.004 sat/vB, so a finer rate is rounded up to the next
+/// sat/kW and the transaction never pays less than asked.
+pub fn sat_per_vb_to_sat_per_kw(fee_rate: f32) -> u32 {
+    // 1 vB is 4 weight units, the cast saturates at `u32::MAX`.
+    (fee_rate.max(0.0) as f64 * 250.0).ceil() as u32
+}
+
+/// Return the minimum fee rate (in sat/kW) configured by the user.
+pub fn min_fee_rate(conf: &LampoConf) -> u32 {
+    sat_per_vb_to_sat_per_kw(conf.min_fee_rate_sat_vb)
+}
+
+/// Check that the fee rate (in sat/kW) is not below `min_fee_rate`.
+pub fn check_fee_rate(fee_rate: u32, min_fee_rate: u32) -> Result<(), WalletError> {
+    if fee_rate < min_fee_rate {
+        return Err(WalletError::FeeTooLow {
+            fee_rate,
+            min_fee_rate,
+        });
+    }
+    Ok(())
+}
+
+/// What changed in the wallet with a sync.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SyncReport {
+    /// The transactions of the wallet seen for the first time.
+    pub new_txs: Vec<String>,
+    /// The transactions of the wallet confirmed by this sync, also
+    /// the new ones that are already confirmed.
+    pub confirmed_txs: Vec<String>,
+    /// The height of the new tip, `None` if the tip did not change.
+    pub new_tip_height: Option<u32>,
+    /// The previous tip is not in the chain anymore.
+    pub reorged: bool,
+}
+
+impl SyncReport {
+    /// The sync did not change the wallet.
+    pub fn is_empty(&self) -> bool {
+        self.new_txs.is_empty()
+            && self.confirmed_txs.is_empty()
+            && self.new_tip_height.is_none()
+            && !self.reorged
+    }
+}
+
+/// Sort the transaction history with the unconfirmed transactions
+/// first, and then the newest ones.
+pub fn sort_history(history: &mut [TxDetail]) {
+    history.sort_by_key(|tx| std::cmp::Reverse(tx.confirmation_height.unwrap_or(u32::MAX)));
+}
 
 /// Wallet manager trait that define a generic interface
 /// over Wallet implementation!
@@ -26,20 +139,243 @@ pub trait WalletManager: Send + Sync {
     fn get_onchain_address(&self) -> error::Result<NewAddress>;
 
     /// Get the current balance of the wallet.
-    fn get_onchain_balance(&self) -> error::Result<u64>;
+    fn get_onchain_balance(&self) -> error::Result<Sat>;
+
+    /// Return the satoshis of the unconfirmed outputs owned by the
+    /// wallet, both our change (trusted) and the incoming payments
+    /// (untrusted), that are not counted in `get_onchain_balance`.
+    fn pending_incoming_balance(&self) -> error::Result<Sat>;
 
     /// Create the transaction from a script and return the transaction
     /// to propagate to the network.
+    ///
+    /// The `fee_rate` is in sat/kW, and a `WalletError::FeeTooLow` is
+    /// returned when it is below the minimum fee rate of the wallet.
     fn create_transaction(
         &self,
         script: ScriptBuf,
-        amount_sat: u64,
+        amount_sat: Sat,
+        fee_rate: u32,
+    ) -> error::Result<Transaction>;
+
+    /// Create the funding transaction of a channel as
+    /// `create_transaction` does, without selecting the outputs
+    /// that have less than `min_confirmations`.
+    ///
+    /// Fails if the outputs with enough confirmations do not cover
+    /// the amount and the fee.
+    fn create_funding_transaction(
+        &self,
+        script: ScriptBuf,
+        amount_sat: Sat,
+        fee_rate: u32,
+        min_confirmations: u32,
+    ) -> error::Result<Transaction>;
+
+    /// Estimate the fee in satoshis of a transaction that pays
+    /// `amount_sat` to `script` at `fee_rate` (in sat/kW), nothing
+    /// is signed or reserved.
+    fn estimate_fee(&self, script: ScriptBuf, amount_sat: Sat, fee_rate: u32)
+        -> error::Result<Sat>;
+
+    /// Create the transaction from a script spending only the
+    /// `utxos` given (coin control), and return the transaction
+    /// to propagate to the network.
+    ///
+    /// Fails if one of the `utxos` is unknown, already spent or
+    /// reserved, or if they do not cover the amount and the fee.
+    fn create_transaction_from_utxos(
+        &self,
+        utxos: Vec<OutPoint>,
+        script: ScriptBuf,
+        amount_sat: Sat,
         fee_rate: u32,
     ) -> error::Result<Transaction>;
 
-    /// Return the list of transaction stored inside the wallet
-    fn list_transactions(&self) -> error::Result<Vec<Utxo>>;
+    /// Return the unspent outputs owned by the wallet.
+    fn list_unspent(&self) -> error::Result<Vec<Utxo>>;
+
+    /// The unspent outputs known by the wallet, without syncing it
+    /// with the chain.
+    fn cached_unspent(&self) -> error::Result<Vec<Utxo>> {
+        self.list_unspent()
+    }
+
+    /// Return every transaction that sent or received funds of the
+    /// wallet, the unconfirmed first and then the newest ones.
+    fn list_transaction_history(&self) -> error::Result<Vec<TxDetail>>;
+
+    /// Sync the wallet, and report what changed.
+    fn sync(&self) -> error::Result<SyncReport>;
+
+    /// The height of the last block known by the wallet, `None`
+    /// if the wallet was never synced.
+    fn synced_height(&self) -> error::Result<Option<u32>>;
+
+    /// The height of the chain when the wallet was created, the blocks
+    /// before it have no transactions of the wallet so the sync (or a
+    /// rescan) starts from it. `None` when it is not known, so the
+    /// sync starts from the genesis.
+    fn get_birthday(&self) -> error::Result<Option<u32>> {
+        Ok(None)
+    }
+
+    /// Set the birthday of the wallet, e.g. the height of the first
+    /// transaction of a restored wallet.
+    fn set_birthday(&self, _height: u32) -> error::Result<()> {
+        error::bail!("the wallet does not store a birthday")
+    }
+
+    /// The scripts of the wallet (with the lookahead) to look for
+    /// inside the compact block filters, by default none.
+    fn watched_scripts(&self) -> error::Result<Vec<ScriptBuf>> {
+        Ok(Vec::new())
+    }
+
+    /// Apply a block that contains transactions of the wallet, when
+    /// the wallet is synced with the compact block filters, and
+    /// report what changed.
+    fn apply_block(&self, _block: &Block, _height: u32) -> error::Result<SyncReport> {
+        error::bail!("the wallet can not be synced with the blocks")
+    }
+
+    /// Write to disk the changes of the wallet that are not
+    /// persisted yet, by default the wallet has nothing to flush.
+    fn flush(&self) -> error::Result<()> {
+        Ok(())
+    }
+
+    /// Replace the esplora apis used by the sync, by default the
+    /// wallet does not sync with esplora so nothing changes.
+    fn set_esplora_urls(&self, _urls: Vec<String>) -> error::Result<()> {
+        Ok(())
+    }
+
+    /// Rewrite the stores of the wallet with only their current state,
+    /// and return their sizes. By default the wallet has no store to
+    /// compact.
+    fn compact(&self) -> error::Result<Vec<StoreSize>> {
+        Ok(Vec::new())
+    }
+
+    /// Return the confirmed UTXOs that can be used to fund
+    /// a fee bump transaction.
+    fn list_confirmed_utxos(&self) -> error::Result<Vec<bump_transaction::Utxo>>;
+
+    /// Return a script where the change of a transaction can be sent.
+    fn get_change_script(&self) -> error::Result<ScriptBuf>;
+
+    /// Sign all the inputs of the PSBT that are owned by the wallet,
+    /// and return the transaction. The inputs that are not owned by
+    /// the wallet are left unsigned.
+    fn sign_psbt(&self, psbt: PartiallySignedTransaction) -> error::Result<Transaction>;
+
+    /// Whether the wallet has the private keys of its coins, a watch
+    /// only wallet (or one with a remote signer that is not reachable)
+    /// can not fund the channels nor sign the fee bumps.
+    fn supports_signing(&self) -> bool {
+        true
+    }
+}
+
+/// Build the wallet `W`, a new one or the one of the `mnemonic`, it
+/// can be registered as it is inside the `WalletFactory`.
+pub fn build_wallet<W: WalletManager + 'static>(
+    conf: Arc<LampoConf>,
+    mnemonic: Option<&str>,
+) -> error::Result<(Arc<dyn WalletManager>, String)> {
+    let (wallet, mnemonic) = match mnemonic {
+        Some(mnemonic) => (W::restore(conf, mnemonic)?, mnemonic.to_owned()),
+        None => W::new(conf)?,
+    };
+    Ok((Arc::new(wallet), mnemonic))
+}
+
+/// Build a wallet from the configuration, a new one with its mnemonic
+/// when the mnemonic is `None`, otherwise the restored one.
+pub type WalletBuilder = Box<
+    dyn Fn(Arc<LampoConf>, Option<&str>) -> error::Result<(Arc<dyn WalletManager>, String)>
+        + Send
+        + Sync,
+>;
+
+/// The wallets that the node can run with, chosen by the `wallet`
+/// option of the configuration.
+///
+/// The daemon knows the wallet only as a `dyn WalletManager`, so a new
+/// wallet is one more builder registered here.
+#[derive(Default)]
+pub struct WalletFactory {
+    builders: BTreeMap<String, WalletBuilder>,
+}
+
+impl WalletFactory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the wallet `name`, replacing a previous one with the
+    /// same name.
+    pub fn register<F>(&mut self, name: &str, builder: F)
+    where
+        F: Fn(Arc<LampoConf>, Option<&str>) -> error::Result<(Arc<dyn WalletManager>, String)>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.builders.insert(name.to_owned(), Box::new(builder));
+    }
+
+    /// The names of the wallets registered.
+    pub fn wallets(&self) -> Vec<&str> {
+        self.builders.keys().map(String::as_str).collect()
+    }
+
+    /// Build the wallet chosen by `conf`, or the `default` one of the
+    /// backend when the configuration does not choose it, see
+    /// `WalletBuilder`.
+    pub fn build(
+        &self,
+        conf: Arc<LampoConf>,
+        default: Option<&str>,
+        mnemonic: Option<&str>,
+    ) -> error::Result<(Arc<dyn WalletManager>, String)> {
+        let Some(name) = conf.wallet.as_deref().or(default).map(str::to_owned) else {
+            error::bail!(
+                "the backend `{}` has no wallet, choose one with the `wallet` option",
+                conf.node
+            );
+        };
+        let Some(builder) = self.builders.get(&name) else {
+            error::bail!(
+                "wallet `{name}` not supported, the wallets are: {}",
+                self.wallets().join(", ")
+            );
+        };
+        log::debug!(target: "wallet", "building the `{name}` wallet");
+        builder(conf, mnemonic)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    /// Sync the wallet.
-    fn sync(&self) -> error::Result<()>;
+    #[test]
+    fn fee_rate_below_the_minimum() {
+        let min_fee_rate = sat_per_vb_to_sat_per_kw(DEFAULT_MIN_FEE_RATE_SAT_VB);
+        assert_eq!(min_fee_rate, 250);
+        assert_eq!(sat_per_vb_to_sat_per_kw(0.5), 125);
+        // rounded up to the next sat/kW
+        assert_eq!(sat_per_vb_to_sat_per_kw(0.101), 26);
+        assert!(check_fee_rate(253, min_fee_rate).is_ok());
+        assert!(check_fee_rate(250, min_fee_rate).is_ok());
+        assert_eq!(
+            check_fee_rate(100, min_fee_rate),
+            Err(WalletError::FeeTooLow {
+                fee_rate: 100,
+                min_fee_rate: 250,
+            })
+        );
+    }
 }
@@ -0,0 +1,847 @@
+//! A wallet that keeps its coins only in memory, for the tests.
+//!
+//! The keys are derived with BIP 84 from a seed, and the wallet sees
+//! the chain only through `apply_block` (e.g. with the compact block
+//! filters): `sync` does nothing. The seed is the SHA256 of the words
+//! given to `restore`, they are not a BIP 39 mnemonic, and a new wallet
+//! takes its words from the clock, so it must never hold real funds:
+//! it is built only with the `test-utils` feature, and it refuses to
+//! run on mainnet.
+//!
+//! It proves that the daemon works with any `WalletManager`, and it is
+//! fast enough for the unit tests that do not need a real wallet.
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bitcoin::absolute::LockTime;
+use bitcoin::bip32::{ChildNumber, ExtendedPrivKey};
+use bitcoin::ecdsa;
+use bitcoin::hashes::sha256::Hash as Sha256;
+use bitcoin::hashes::Hash;
+use bitcoin::psbt::PartiallySignedTransaction;
+use bitcoin::secp256k1::{All, Message, Secp256k1};
+use bitcoin::sighash::{EcdsaSighashType, SighashCache};
+use bitcoin::{
+    Address, Block, Network, OutPoint, PrivateKey, PublicKey, ScriptBuf, Sequence, Transaction,
+    TxIn, TxOut, Txid, Witness,
+};
+
+use super::{
+    check_fee_rate, min_fee_rate, sat_per_vb_to_sat_per_kw, sort_history, SyncReport, WalletError,
+    WalletManager, DEFAULT_MIN_FEE_RATE_SAT_VB, P2WPKH_SATISFACTION_WEIGHT,
+};
+use crate::conf::LampoConf;
+use crate::error;
+use crate::keys::{KeysScheme, LampoKeys};
+use crate::ldk::events::bump_transaction;
+use crate::model::response::{NewAddress, TxDetail, Utxo};
+use crate::model::Sat;
+use crate::sync::MutexExt;
+use crate::time::unix_now;
+
+/// The addresses watched after the last one used.
+const LOOKAHEAD: u32 = 20;
+
+/// Weight of the version, the lock time, the counts of the inputs and
+/// of the outputs, and the segwit marker and flag.
+const TX_BASE_WEIGHT: u64 = (4 + 4 + 1 + 1) * 4 + 2;
+/// Weight of a P2WPKH input: the outpoint and the sequence, plus
+/// the witness.
+const P2WPKH_INPUT_WEIGHT: u64 = (32 + 4 + 4) * 4 + P2WPKH_SATISFACTION_WEIGHT;
+
+struct Coin {
+    txout: TxOut,
+    /// The height of the block with the coin, `None` if unconfirmed.
+    height: Option<u32>,
+    /// The index of the key of the coin.
+    index: u32,
+}
+
+#[derive(Default)]
+struct State {
+    /// The next address given to the user.
+    next_index: u32,
+    /// The scripts watched, with the index of their key.
+    scripts: HashMap<ScriptBuf, u32>,
+    coins: HashMap<OutPoint, Coin>,
+    /// The coins spent by our transactions that are not confirmed yet.
+    reserved: HashSet<OutPoint>,
+    history: HashMap<Txid, TxDetail>,
+    synced_height: Option<u32>,
+    birthday: Option<u32>,
+}
+
+pub struct MemoryWallet {
+    network: Network,
+    master: ExtendedPrivKey,
+    keys: Arc<LampoKeys>,
+    secp: Secp256k1<All>,
+    /// The minimum fee rate in sat/kW.
+    min_fee_rate: u32,
+    signing: bool,
+    state: Mutex<State>,
+}
+
+impl MemoryWallet {
+    /// The wallet of the `words`, with the keys of ldk derived as
+    /// the `scheme` says, never on mainnet.
+    pub fn from_words(network: Network, words: &str, scheme: KeysScheme) -> error::Result<Self> {
+        if network == Network::Bitcoin {
+            error::bail!("the in memory wallet can not run on `{network}`");
+        }
+        let seed = Sha256::hash(words.as_bytes());
+        let master = ExtendedPrivKey::new_master(network, seed.as_byte_array())?;
+        let keys = LampoKeys::from_master(&master.encode(), scheme)?;
+        let wallet = Self {
+            network,
+            master,
+            keys: Arc::new(keys),
+            secp: Secp256k1::new(),
+            min_fee_rate: sat_per_vb_to_sat_per_kw(DEFAULT_MIN_FEE_RATE_SAT_VB),
+            signing: true,
+            state: Mutex::new(State::default()),
+        };
+        {
+            let mut state = wallet.state.lock_or_recover();
+            wallet.watch_up_to(&mut state, LOOKAHEAD)?;
+        }
+        Ok(wallet)
+    }
+
+    /// Forget the private keys of the coins, like a watch only wallet:
+    /// the transactions can be estimated but not created.
+    pub fn without_signing(mut self) -> Self {
+        self.signing = false;
+        self
+    }
+
+    /// The key at `m/84'/<coin>'/0'/0/<index>`.
+    fn key(&self, index: u32) -> error::Result<(PrivateKey, PublicKey)> {
+        let coin = match self.network {
+            Network::Bitcoin => 0,
+            _ => 1,
+        };
+        let path = [
+            ChildNumber::from_hardened_idx(84)?,
+            ChildNumber::from_hardened_idx(coin)?,
+            ChildNumber::from_hardened_idx(0)?,
+            ChildNumber::from_normal_idx(0)?,
+            ChildNumber::from_normal_idx(index)?,
+        ];
+        let private_key = self.master.derive_priv(&self.secp, &path)?.to_priv();
+        let public_key = private_key.public_key(&self.secp);
+        Ok((private_key, public_key))
+    }
+
+    fn address(&self, index: u32) -> error::Result<Address> {
+        let (_, public_key) = self.key(index)?;
+        Ok(Address::p2wpkh(&public_key, self.network)?)
+    }
+
+    /// Watch the scripts of the keys before `index`.
+    fn watch_up_to(&self, state: &mut State, index: u32) -> error::Result<()> {
+        let first = state.scripts.len() as u32;
+        for index in first..index {
+            let script = self.address(index)?.script_pubkey();
+            state.scripts.insert(script, index);
+        }
+        Ok(())
+    }
+
+    /// The script of a new address, the watched scripts move on.
+    fn next_script(&self) -> error::Result<ScriptBuf> {
+        let mut state = self.state.lock_or_recover();
+        let index = state.next_index;
+        state.next_index += 1;
+        self.watch_up_to(&mut state, index + 1 + LOOKAHEAD)?;
+        Ok(self.address(index)?.script_pubkey())
+    }
+
+    fn confirmations(state: &State, coin: &Coin) -> u32 {
+        match (coin.height, state.synced_height) {
+            (Some(height), Some(tip)) if tip >= height => tip - height + 1,
+            _ => 0,
+        }
+    }
+
+    fn fee(weight: u64, fee_rate: u32) -> u64 {
+        (weight * fee_rate as u64 + 999) / 1000
+    }
+
+    fn outputs_weight(scripts: &[&ScriptBuf]) -> u64 {
+        scripts
+            .iter()
+            .map(|script| (8 + 1 + script.len() as u64) * 4)
+            .sum()
+    }
+
+    /// The fee and the change of a transaction that spends `inputs`
+    /// coins worth `total_sat`, `None` when they do not pay `amount_sat`
+    /// and the fee.
+    fn fee_and_change(
+        total_sat: u64,
+        inputs: usize,
+        script: &ScriptBuf,
+        change_script: &ScriptBuf,
+        amount_sat: u64,
+        fee_rate: u32,
+    ) -> Option<(u64, u64)> {
+        let inputs = inputs as u64 * P2WPKH_INPUT_WEIGHT;
+        let outputs = Self::outputs_weight(&[script, change_script]);
+        let fee = Self::fee(TX_BASE_WEIGHT + inputs + outputs, fee_rate);
+        if total_sat >= amount_sat + fee + change_script.dust_value().to_sat() {
+            return Some((fee, total_sat - amount_sat - fee));
+        }
+        // the change would be dust, so it goes to the miners
+        let fee = Self::fee(
+            TX_BASE_WEIGHT + inputs + Self::outputs_weight(&[script]),
+            fee_rate,
+        );
+        if total_sat < amount_sat + fee {
+            return None;
+        }
+        Some((total_sat - amount_sat, 0))
+    }
+
+    /// Select the largest coins first until they pay `amount_sat` and
+    /// the fee, and return them with the fee and the change.
+    fn select(
+        &self,
+        mut candidates: Vec<(OutPoint, u64)>,
+        script: &ScriptBuf,
+        change_script: &ScriptBuf,
+        amount_sat: u64,
+        fee_rate: u32,
+        min_confirmations: u32,
+    ) -> error::Result<(Vec<OutPoint>, u64, u64)> {
+        check_fee_rate(fee_rate, self.min_fee_rate)?;
+        candidates.sort_by_key(|(_, value)| std::cmp::Reverse(*value));
+        let mut selected = Vec::new();
+        let mut total_sat = 0;
+        for (outpoint, value) in candidates {
+            selected.push(outpoint);
+            total_sat += value;
+            if let Some((fee, change)) = Self::fee_and_change(
+                total_sat,
+                selected.len(),
+                script,
+                change_script,
+                amount_sat,
+                fee_rate,
+            ) {
+                return Ok((selected, fee, change));
+            }
+        }
+        let weight = TX_BASE_WEIGHT
+            + selected.len().max(1) as u64 * P2WPKH_INPUT_WEIGHT
+            + Self::outputs_weight(&[script]);
+        Err(WalletError::InsufficientFunds {
+            needed_sat: amount_sat + Self::fee(weight, fee_rate),
+            available_sat: total_sat,
+            min_confirmations,
+        }
+        .into())
+    }
+
+    /// The coins that can be spent, with at least `min_confirmations`.
+    fn spendable(&self, min_confirmations: u32) -> Vec<(OutPoint, u64)> {
+        let state = self.state.lock_or_recover();
+        state
+            .coins
+            .iter()
+            .filter(|(outpoint, _)| !state.reserved.contains(outpoint))
+            .filter(|(_, coin)| Self::confirmations(&state, coin) >= min_confirmations)
+            .map(|(outpoint, coin)| (*outpoint, coin.txout.value))
+            .collect()
+    }
+
+    /// Build and sign the transaction that spends `inputs`, the
+    /// inputs are reserved and the change is a new coin.
+    fn build(
+        &self,
+        inputs: Vec<OutPoint>,
+        script: ScriptBuf,
+        amount_sat: u64,
+        fee_sat: u64,
+        change: Option<(ScriptBuf, u64)>,
+    ) -> error::Result<Transaction> {
+        if !self.signing {
+            error::bail!("the wallet has no private keys, so it can not sign the transaction");
+        }
+        let mut output = vec![TxOut {
+            value: amount_sat,
+            script_pubkey: script,
+        }];
+        if let Some((script_pubkey, value)) = change {
+            output.push(TxOut {
+                value,
+                script_pubkey,
+            });
+        }
+        let mut tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: inputs
+                .iter()
+                .map(|outpoint| TxIn {
+                    previous_output: *outpoint,
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output,
+        };
+        let mut state = self.state.lock_or_recover();
+        let spent = inputs
+            .iter()
+            .map(|outpoint| {
+                let coin = state
+                    .coins
+                    .get(outpoint)
+                    .ok_or(error::anyhow!("unknown output `{outpoint}`"))?;
+                Ok((coin.index, coin.txout.value))
+            })
+            .collect::<error::Result<Vec<_>>>()?;
+        self.sign(&mut tx, &spent)?;
+        let txid = tx.txid();
+        for outpoint in inputs {
+            state.reserved.insert(outpoint);
+        }
+        let mut received = 0;
+        for (vout, txout) in tx.output.iter().enumerate() {
+            if let Some(index) = state.scripts.get(&txout.script_pubkey).copied() {
+                received += txout.value;
+                state.coins.insert(
+                    OutPoint::new(txid, vout as u32),
+                    Coin {
+                        txout: txout.clone(),
+                        height: None,
+                        index,
+                    },
+                );
+            }
+        }
+        let sent: u64 = spent.iter().map(|(_, value)| value).sum();
+        state.history.insert(
+            txid,
+            TxDetail {
+                txid: txid.to_string(),
+                net_amount_sat: received as i64 - sent as i64,
+                fee_sat: Some(fee_sat),
+                confirmation_height: None,
+                timestamp: Some(unix_now()),
+            },
+        );
+        Ok(tx)
+    }
+
+    /// Sign the inputs of `tx` spending the coins of the keys at
+    /// `spent`, that are `(index, value)` in the order of the inputs.
+    fn sign(&self, tx: &mut Transaction, spent: &[(u32, u64)]) -> error::Result<()> {
+        let mut witnesses = Vec::with_capacity(spent.len());
+        let mut cache = SighashCache::new(&*tx);
+        for (input, (index, value)) in spent.iter().enumerate() {
+            let (private_key, public_key) = self.key(*index)?;
+            // the script code of P2WPKH is the P2PKH script of the key
+            let script_code = ScriptBuf::new_p2pkh(&public_key.pubkey_hash());
+            let sighash =
+                cache.segwit_signature_hash(input, &script_code, *value, EcdsaSighashType::All)?;
+            let message = Message::from_slice(sighash.as_byte_array())?;
+            let signature = ecdsa::Signature {
+                sig: self.secp.sign_ecdsa(&message, &private_key.inner),
+                hash_ty: EcdsaSighashType::All,
+            };
+            witnesses.push(Witness::p2wpkh(&signature, &public_key.inner));
+        }
+        for (input, witness) in tx.input.iter_mut().zip(witnesses) {
+            input.witness = witness;
+        }
+        Ok(())
+    }
+}
+
+impl WalletManager for MemoryWallet {
+    fn new(conf: Arc<LampoConf>) -> error::Result<(Self, String)>
+    where
+        Self: Sized,
+    {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos())
+            .unwrap_or_default();
+        let words = Sha256::hash(format!("{nanos}:{}", std::process::id()).as_bytes()).to_string();
+        let wallet = Self::restore(conf, &words)?;
+        Ok((wallet, words))
+    }
+
+    fn restore(conf: Arc<LampoConf>, mnemonic_words: &str) -> error::Result<Self>
+    where
+        Self: Sized,
+    {
+        let scheme = KeysScheme::load_or_init(&conf.path(), conf.keys_scheme)?;
+        let mut wallet = Self::from_words(conf.network, mnemonic_words, scheme)?;
+        wallet.min_fee_rate = min_fee_rate(&conf);
+        Ok(wallet)
+    }
+
+    fn ldk_keys(&self) -> Arc<LampoKeys> {
+        self.keys.clone()
+    }
+
+    fn get_onchain_address(&self) -> error::Result<NewAddress> {
+        let script = self.next_script()?;
+        let address = Address::from_script(&script, self.network)?;
+        Ok(NewAddress {
+            address: address.to_string(),
+        })
+    }
+
+    fn get_onchain_balance(&self) -> error::Result<Sat> {
+        let balance = self
+            .spendable(1)
+            .iter()
+            .map(|(_, value)| Sat::from_sat(*value));
+        Ok(Sat::checked_sum(balance)?)
+    }
+
+    fn pending_incoming_balance(&self) -> error::Result<Sat> {
+        let state = self.state.lock_or_recover();
+        let pending = state
+            .coins
+            .iter()
+            .filter(|(outpoint, coin)| coin.height.is_none() && !state.reserved.contains(outpoint))
+            .map(|(_, coin)| Sat::from_sat(coin.txout.value));
+        Ok(Sat::checked_sum(pending)?)
+    }
+
+    fn create_transaction(
+        &self,
+        script: ScriptBuf,
+        amount_sat: Sat,
+        fee_rate: u32,
+    ) -> error::Result<Transaction> {
+        self.create_funding_transaction(script, amount_sat, fee_rate, 0)
+    }
+
+    fn create_funding_transaction(
+        &self,
+        script: ScriptBuf,
+        amount_sat: Sat,
+        fee_rate: u32,
+        min_confirmations: u32,
+    ) -> error::Result<Transaction> {
+        let amount_sat = amount_sat.sat();
+        let change_script = self.next_script()?;
+        let (inputs, fee, change) = self.select(
+            self.spendable(min_confirmations),
+            &script,
+            &change_script,
+            amount_sat,
+            fee_rate,
+            min_confirmations,
+        )?;
+        let change = (change > 0).then_some((change_script, change));
+        self.build(inputs, script, amount_sat, fee, change)
+    }
+
+    fn estimate_fee(
+        &self,
+        script: ScriptBuf,
+        amount_sat: Sat,
+        fee_rate: u32,
+    ) -> error::Result<Sat> {
+        let change_script = self.address(0)?.script_pubkey();
+        let (_, fee, _) = self.select(
+            self.spendable(0),
+            &script,
+            &change_script,
+            amount_sat.sat(),
+            fee_rate,
+            0,
+        )?;
+        Ok(Sat::from_sat(fee))
+    }
+
+    fn create_transaction_from_utxos(
+        &self,
+        utxos: Vec<OutPoint>,
+        script: ScriptBuf,
+        amount_sat: Sat,
+        fee_rate: u32,
+    ) -> error::Result<Transaction> {
+        let amount_sat = amount_sat.sat();
+        let spendable = self.spendable(0);
+        let candidates = utxos
+            .iter()
+            .map(|outpoint| {
+                spendable
+                    .iter()
+                    .find(|(spendable, _)| spendable == outpoint)
+                    .copied()
+                    .ok_or(error::anyhow!(
+                        "the output `{outpoint}` is unknown, spent or reserved"
+                    ))
+            })
+            .collect::<error::Result<Vec<_>>>()?;
+        check_fee_rate(fee_rate, self.min_fee_rate)?;
+        let total_sat: u64 = candidates.iter().map(|(_, value)| value).sum();
+        let change_script = self.next_script()?;
+        // every output given is spent, the change takes the rest
+        let Some((fee, change)) = Self::fee_and_change(
+            total_sat,
+            utxos.len(),
+            &script,
+            &change_script,
+            amount_sat,
+            fee_rate,
+        ) else {
+            let weight = TX_BASE_WEIGHT
+                + utxos.len() as u64 * P2WPKH_INPUT_WEIGHT
+                + Self::outputs_weight(&[&script]);
+            return Err(WalletError::InsufficientFunds {
+                needed_sat: amount_sat + Self::fee(weight, fee_rate),
+                available_sat: total_sat,
+                min_confirmations: 0,
+            }
+            .into());
+        };
+        let change = (change > 0).then_some((change_script, change));
+        self.build(utxos, script, amount_sat, fee, change)
+    }
+
+    fn list_unspent(&self) -> error::Result<Vec<Utxo>> {
+        let state = self.state.lock_or_recover();
+        let utxos = state
+            .coins
+            .iter()
+            .map(|(outpoint, coin)| {
+                Ok(Utxo {
+                    txid: outpoint.txid.to_string(),
+                    vout: outpoint.vout,
+                    reserved: state.reserved.contains(outpoint),
+                    confirmed: Self::confirmations(&state, coin),
+                    amount_msat: Sat::from_sat(coin.txout.value).to_msat()?,
+                    address: Address::from_script(&coin.txout.script_pubkey, self.network)
+                        .ok()
+                        .map(|address| address.to_string()),
+                })
+            })
+            .collect::<error::Result<_>>()?;
+        Ok(utxos)
+    }
+
+    fn list_transaction_history(&self) -> error::Result<Vec<TxDetail>> {
+        let state = self.state.lock_or_recover();
+        let mut history = state.history.values().cloned().collect::<Vec<_>>();
+        sort_history(&mut history);
+        Ok(history)
+    }
+
+    fn sync(&self) -> error::Result<SyncReport> {
+        // the chain is seen only through `apply_block`
+        Ok(SyncReport::default())
+    }
+
+    fn synced_height(&self) -> error::Result<Option<u32>> {
+        Ok(self.state.lock_or_recover().synced_height)
+    }
+
+    fn get_birthday(&self) -> error::Result<Option<u32>> {
+        Ok(self.state.lock_or_recover().birthday)
+    }
+
+    fn set_birthday(&self, height: u32) -> error::Result<()> {
+        self.state.lock_or_recover().birthday = Some(height);
+        Ok(())
+    }
+
+    fn watched_scripts(&self) -> error::Result<Vec<ScriptBuf>> {
+        let state = self.state.lock_or_recover();
+        Ok(state.scripts.keys().cloned().collect())
+    }
+
+    fn apply_block(&self, block: &Block, height: u32) -> error::Result<SyncReport> {
+        let mut state = self.state.lock_or_recover();
+        let mut report = SyncReport::default();
+        for tx in &block.txdata {
+            let txid = tx.txid();
+            let mut sent = 0;
+            let mut known_inputs = true;
+            for input in &tx.input {
+                match state.coins.remove(&input.previous_output) {
+                    Some(coin) => sent += coin.txout.value,
+                    None => known_inputs = false,
+                }
+                state.reserved.remove(&input.previous_output);
+            }
+            let mut received = 0;
+            let mut last_index = None;
+            for (vout, txout) in tx.output.iter().enumerate() {
+                let Some(index) = state.scripts.get(&txout.script_pubkey).copied() else {
+                    continue;
+                };
+                received += txout.value;
+                last_index = last_index.max(Some(index));
+                state.coins.insert(
+                    OutPoint::new(txid, vout as u32),
+                    Coin {
+                        txout: txout.clone(),
+                        height: Some(height),
+                        index,
+                    },
+                );
+            }
+            if sent == 0 && received == 0 {
+                continue;
+            }
+            // the addresses used by another wallet with the same seed
+            if let Some(index) = last_index {
+                if index >= state.next_index {
+                    state.next_index = index + 1;
+                    self.watch_up_to(&mut state, index + 1 + LOOKAHEAD)?;
+                }
+            }
+            let fee_sat = known_inputs.then(|| {
+                let outputs: u64 = tx.output.iter().map(|txout| txout.value).sum();
+                sent.saturating_sub(outputs)
+            });
+            let detail = TxDetail {
+                txid: txid.to_string(),
+                net_amount_sat: received as i64 - sent as i64,
+                fee_sat,
+                confirmation_height: Some(height),
+                timestamp: Some(block.header.time as u64),
+            };
+            if state.history.insert(txid, detail).is_none() {
+                report.new_txs.push(txid.to_string());
+            }
+            report.confirmed_txs.push(txid.to_string());
+        }
+        if state.synced_height != Some(height) {
+            report.reorged = state.synced_height.is_some_and(|tip| tip >= height);
+            state.synced_height = Some(height);
+            report.new_tip_height = Some(height);
+        }
+        Ok(report)
+    }
+
+    fn list_confirmed_utxos(&self) -> error::Result<Vec<bump_transaction::Utxo>> {
+        let spendable = self.spendable(1);
+        let state = self.state.lock_or_recover();
+        spendable
+            .into_iter()
+            .filter_map(|(outpoint, value)| {
+                Some((outpoint, value, state.coins.get(&outpoint)?.index))
+            })
+            .map(|(outpoint, value, index)| {
+                let (_, public_key) = self.key(index)?;
+                let pubkey_hash = public_key
+                    .wpubkey_hash()
+                    .ok_or(error::anyhow!("the key of the wallet is not compressed"))?;
+                Ok(bump_transaction::Utxo::new_v0_p2wpkh(
+                    outpoint,
+                    value,
+                    &pubkey_hash,
+                ))
+            })
+            .collect()
+    }
+
+    fn get_change_script(&self) -> error::Result<ScriptBuf> {
+        self.next_script()
+    }
+
+    fn sign_psbt(&self, psbt: PartiallySignedTransaction) -> error::Result<Transaction> {
+        if !self.signing {
+            error::bail!("the wallet has no private keys, so it can not sign the psbt");
+        }
+        let mut tx = psbt.unsigned_tx.clone();
+        let state = self.state.lock_or_recover();
+        let mut cache = SighashCache::new(&psbt.unsigned_tx);
+        for (input, psbt_input) in psbt.inputs.iter().enumerate() {
+            if let Some(ref witness) = psbt_input.final_script_witness {
+                tx.input[input].witness = witness.clone();
+                continue;
+            }
+            let Some(ref txout) = psbt_input.witness_utxo else {
+                continue;
+            };
+            // the inputs of the other signers are left unsigned
+            let Some(index) = state.scripts.get(&txout.script_pubkey).copied() else {
+                continue;
+            };
+            let (private_key, public_key) = self.key(index)?;
+            let script_code = ScriptBuf::new_p2pkh(&public_key.pubkey_hash());
+            let sighash = cache.segwit_signature_hash(
+                input,
+                &script_code,
+                txout.value,
+                EcdsaSighashType::All,
+            )?;
+            let message = Message::from_slice(sighash.as_byte_array())?;
+            let signature = ecdsa::Signature {
+                sig: self.secp.sign_ecdsa(&message, &private_key.inner),
+                hash_ty: EcdsaSighashType::All,
+            };
+            tx.input[input].witness = Witness::p2wpkh(&signature, &public_key.inner);
+        }
+        Ok(tx)
+    }
+
+    fn supports_signing(&self) -> bool {
+        self.signing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::blockdata::constants::genesis_block;
+
+    use super::*;
+    use crate::model::Msat;
+
+    fn wallet() -> MemoryWallet {
+        MemoryWallet::from_words(
+            Network::Regtest,
+            "lampo memory wallet",
+            KeysScheme::Hardened,
+        )
+        .unwrap()
+    }
+
+    /// A block at `height` that pays `value` to the new address of `wallet`.
+    fn fund(wallet: &MemoryWallet, value: u64, height: u32) -> Txid {
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::from_consensus(height),
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                value,
+                script_pubkey: wallet.next_script().unwrap(),
+            }],
+        };
+        let txid = tx.txid();
+        let mut block = genesis_block(Network::Regtest);
+        block.txdata = vec![tx];
+        let report = wallet.apply_block(&block, height).unwrap();
+        assert_eq!(report.new_txs, vec![txid.to_string()]);
+        txid
+    }
+
+    #[test]
+    fn same_words_same_wallet() {
+        let first = wallet();
+        let second = wallet();
+        assert_eq!(first.ldk_keys().node_id(), second.ldk_keys().node_id());
+        assert_eq!(
+            first.get_onchain_address().unwrap().address,
+            second.get_onchain_address().unwrap().address
+        );
+    }
+
+    #[test]
+    fn refuse_mainnet() {
+        let result = MemoryWallet::from_words(
+            Network::Bitcoin,
+            "lampo memory wallet",
+            KeysScheme::Hardened,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn coins_from_the_blocks() {
+        let wallet = wallet();
+        fund(&wallet, 100_000, 1);
+        assert_eq!(
+            wallet.get_onchain_balance().unwrap(),
+            Sat::from_sat(100_000)
+        );
+        assert_eq!(wallet.synced_height().unwrap(), Some(1));
+        let utxos = wallet.list_unspent().unwrap();
+        assert_eq!(utxos.len(), 1);
+        assert_eq!(utxos[0].amount_msat, Msat::from_msat(100_000_000));
+        assert_eq!(utxos[0].confirmed, 1);
+        assert_eq!(wallet.list_confirmed_utxos().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn spend_with_change() {
+        let wallet = wallet();
+        fund(&wallet, 100_000, 1);
+        let script = ScriptBuf::new_v0_p2wsh(&bitcoin::WScriptHash::all_zeros());
+        let fee = wallet
+            .estimate_fee(script.clone(), Sat::from_sat(50_000), 1_000)
+            .unwrap()
+            .sat();
+        let tx = wallet
+            .create_transaction(script, Sat::from_sat(50_000), 1_000)
+            .unwrap();
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.input[0].witness.len(), 2);
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(tx.output[0].value, 50_000);
+        assert_eq!(tx.output[1].value, 100_000 - 50_000 - fee);
+        // at 1000 sat/kW the fee is a sat for each weight unit, and the
+        // estimated weight is never below the signed one
+        assert!(fee >= tx.weight().to_wu());
+        // the coin is reserved, and the change is pending
+        assert_eq!(wallet.get_onchain_balance().unwrap(), Sat::ZERO);
+        assert_eq!(
+            wallet.pending_incoming_balance().unwrap(),
+            Sat::from_sat(100_000 - 50_000 - fee)
+        );
+    }
+
+    #[test]
+    fn errors_of_the_wallet() {
+        let wallet = wallet();
+        fund(&wallet, 10_000, 1);
+        let script = ScriptBuf::new_v0_p2wsh(&bitcoin::WScriptHash::all_zeros());
+        let err = wallet
+            .create_transaction(script.clone(), Sat::from_sat(50_000), 1_000)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<WalletError>(),
+            Some(WalletError::InsufficientFunds { .. })
+        ));
+        let err = wallet
+            .create_transaction(script.clone(), Sat::from_sat(5_000), 100)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<WalletError>(),
+            Some(WalletError::FeeTooLow { .. })
+        ));
+        let err = wallet
+            .create_funding_transaction(script, Sat::from_sat(5_000), 1_000, 6)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<WalletError>(),
+            Some(WalletError::InsufficientFunds {
+                min_confirmations: 6,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn watch_only_does_not_sign() {
+        let wallet = wallet().without_signing();
+        assert!(!wallet.supports_signing());
+        fund(&wallet, 100_000, 1);
+        let script = ScriptBuf::new_v0_p2wsh(&bitcoin::WScriptHash::all_zeros());
+        assert!(wallet
+            .estimate_fee(script.clone(), Sat::from_sat(50_000), 1_000)
+            .is_ok());
+        assert!(wallet
+            .create_transaction(script, Sat::from_sat(50_000), 1_000)
+            .is_err());
+        // nothing is reserved by the failed transaction
+        assert_eq!(
+            wallet.get_onchain_balance().unwrap(),
+            Sat::from_sat(100_000)
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 use std::ops::Not;
+use std::str::FromStr;
 use std::sync::Arc;
 
 use bdk::bitcoin::Amount;
@@ -20,18 +21,26 @@ use crate::bitcoin::PrivateKey;
 
 use lampo_common::bitcoin;
 use lampo_common::bitcoin::consensus::Decodable;
+use lampo_common::bitcoin::psbt::PartiallySignedTransaction;
 use lampo_common::conf::{LampoConf, Network};
 use lampo_common::error;
 use lampo_common::json;
 use lampo_common::json::Deserialize;
-use lampo_common::keys::LampoKeys;
-use lampo_common::model::response::{NewAddress, Utxo};
-use lampo_common::wallet::WalletManager;
+use lampo_common::keys::{KeysScheme, LampoKeys};
+use lampo_common::ldk::events::bump_transaction;
+use lampo_common::model::response::{NewAddress, TxDetail, Utxo};
+use lampo_common::model::Sat;
+use lampo_common::wallet::{
+    check_fee_rate, min_fee_rate, sort_history, SyncReport, WalletManager,
+    P2WPKH_SATISFACTION_WEIGHT,
+};
 
 pub struct CoreWalletManager {
     rpc: Client,
     keymanager: Arc<LampoKeys>,
     network: Network,
+    /// Minimum fee rate in sat/kW of the transactions built.
+    min_fee_rate: u32,
 }
 
 impl CoreWalletManager {
@@ -56,7 +65,8 @@ impl CoreWalletManager {
             .into_xprv(network)
             .ok_or(error::anyhow!("impossible cast the private key"))?;
 
-        let ldk_kesy = LampoKeys::new(xprv.private_key.secret_bytes());
+        let scheme = KeysScheme::load_or_init(&conf.path(), conf.keys_scheme)?;
+        let ldk_kesy = LampoKeys::from_master(&xprv.encode(), scheme)?;
         // Create a BDK wallet structure using BIP 84 descriptor ("m/84h/1h/0h/0" and "m/84h/1h/0h/1")
         let wallet = bdk::Wallet::new(
             Bip84(xprv, KeychainKind::External),
@@ -74,10 +84,11 @@ impl CoreWalletManager {
     ) -> error::Result<(bdk::Wallet, LampoKeys)> {
         use bdk::bitcoin::bip32::Xpriv;
 
-        let ldk_keys = if channel_keys.is_some() {
-            LampoKeys::with_channel_keys(xprv.inner.secret_bytes(), channel_keys.unwrap())
-        } else {
-            LampoKeys::new(xprv.inner.secret_bytes())
+        let ldk_keys = match channel_keys {
+            Some(channel_keys) => {
+                LampoKeys::with_channel_keys(xprv.inner.secret_bytes(), channel_keys)?
+            }
+            None => LampoKeys::new(xprv.inner.secret_bytes()),
         };
         let network = match xprv.network.to_string().as_str() {
             "bitcoin" => bdk::bitcoin::Network::Bitcoin,
@@ -182,6 +193,101 @@ impl CoreWalletManager {
         )?;
         Ok(rpc)
     }
+
+    /// Fund, sign and return a transaction that pay `amount_sat` to the
+    /// `script`. When `utxos` is not empty, only the given outputs are
+    /// spent, otherwise bitcoin core is free to select the inputs.
+    fn fund_transaction(
+        &self,
+        utxos: Vec<bitcoin::OutPoint>,
+        script: bitcoin::ScriptBuf,
+        amount_sat: u64,
+        fee_rate: u32,
+        min_confirmations: u32,
+    ) -> error::Result<bitcoin::Transaction> {
+        let tx =
+            self.fund_raw_transaction(utxos, script, amount_sat, fee_rate, min_confirmations)?;
+        let hex: Tx = self
+            .rpc
+            .call("signrawtransactionwithwallet", &[json::json!(tx.hex)])?;
+        let hex = hex.hex.unwrap();
+        let mut reader = HexIterator::new(&hex)?;
+        let object = Decodable::consensus_decode(&mut reader)?;
+        Ok(object)
+    }
+
+    /// Build the transaction and add the inputs and the change
+    /// with `fundrawtransaction`, without signing it. The inputs
+    /// added have at least `min_confirmations`.
+    fn fund_raw_transaction(
+        &self,
+        utxos: Vec<bitcoin::OutPoint>,
+        script: bitcoin::ScriptBuf,
+        amount_sat: u64,
+        fee_rate: u32,
+        min_confirmations: u32,
+    ) -> error::Result<FundedTx> {
+        check_fee_rate(fee_rate, self.min_fee_rate)?;
+        let addr = bitcoin_bech32::WitnessProgram::from_scriptpubkey(
+            &script.as_bytes(),
+            match self.network {
+                Network::Bitcoin => bitcoin_bech32::constants::Network::Bitcoin,
+                Network::Testnet => bitcoin_bech32::constants::Network::Testnet,
+                Network::Regtest => bitcoin_bech32::constants::Network::Regtest,
+                Network::Signet => bitcoin_bech32::constants::Network::Signet,
+                _ => error::bail!("network `{}` not supported", self.network),
+            },
+        )?
+        .to_address();
+        let mut map = HashMap::new();
+        map.insert(addr, Amount::from_sat(amount_sat).to_btc());
+        let mut options = json::json!({
+            // LDK gives us feerates in satoshis per KW but Bitcoin Core here expects fees
+            // denominated in satoshis per vB. First we need to multiply by 4 to convert weight
+            // units to virtual bytes, then divide by 1000 to convert KvB to vB.
+            "fee_rate": fee_rate as f64 / 250.0,
+            // While users could "cancel" a channel open by RBF-bumping and paying back to
+            // themselves, we don't allow it here as its easy to have users accidentally RBF bump
+            // and pay to the channel funding address, which results in loss of funds. Real
+            // LDK-based applications should enable RBF bumping and RBF bump either to a local
+            // change address or to a new channel output negotiated with the same node.
+            "replaceable": false,
+            "include_unsafe": true,
+            "includeWatching": true,
+            "add_inputs": utxos.is_empty(),
+        });
+        if min_confirmations > 0 {
+            // the option is not known by the old versions of bitcoin core
+            options["minconf"] = json::json!(min_confirmations);
+        }
+        let inputs = utxos
+            .iter()
+            .map(|utxo| json::json!({ "txid": utxo.txid.to_string(), "vout": utxo.vout }))
+            .collect::<Vec<_>>();
+
+        let hex: String = self.rpc.call(
+            "createrawtransaction",
+            &[json::json!(inputs), json::json!(&map), json::json!(0)],
+        )?;
+
+        let tx: FundedTx = self
+            .rpc
+            .call(
+                "fundrawtransaction",
+                &[json::json!(hex), json::json!(options)],
+            )
+            .map_err(|err| {
+                let insufficient = err.to_string().contains("Insufficient funds");
+                if insufficient && !utxos.is_empty() {
+                    error::anyhow!("the selected utxos do not cover the amount and the fee")
+                } else if insufficient && min_confirmations > 0 {
+                    error::anyhow!("not enough funds with at least {min_confirmations} confirmations to fund the transaction")
+                } else {
+                    error::anyhow!("{err}")
+                }
+            })?;
+        Ok(tx)
+    }
 }
 
 #[macro_export]
@@ -199,6 +305,19 @@ struct Tx {
     hex: Option<String>,
 }
 
+/// The result of `fundrawtransaction`.
+#[derive(Debug, Deserialize)]
+struct FundedTx {
+    hex: String,
+    /// The fee in BTC.
+    fee: f64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProcessedPsbt {
+    psbt: String,
+}
+
 impl WalletManager for CoreWalletManager {
     fn new(conf: Arc<LampoConf>) -> error::Result<(Self, String)>
     where
@@ -218,6 +337,7 @@ impl WalletManager for CoreWalletManager {
                 rpc,
                 keymanager: keymanager.into(),
                 network: conf.network,
+                min_fee_rate: min_fee_rate(&conf),
             },
             mnemonic.to_string(),
         ))
@@ -226,55 +346,65 @@ impl WalletManager for CoreWalletManager {
     fn create_transaction(
         &self,
         script: bitcoin::ScriptBuf,
-        amount_sat: u64,
+        amount_sat: Sat,
         fee_rate: u32,
     ) -> error::Result<bitcoin::Transaction> {
-        let addr = bitcoin_bech32::WitnessProgram::from_scriptpubkey(
-            &script.as_bytes(),
-            match self.network {
-                Network::Bitcoin => bitcoin_bech32::constants::Network::Bitcoin,
-                Network::Testnet => bitcoin_bech32::constants::Network::Testnet,
-                Network::Regtest => bitcoin_bech32::constants::Network::Regtest,
-                Network::Signet => bitcoin_bech32::constants::Network::Signet,
-                _ => error::bail!("network `{}` not supported", self.network),
-            },
-        )?
-        .to_address();
-        let mut map = HashMap::new();
-        map.insert(addr, Amount::from_sat(amount_sat).to_btc());
-        let options = json::json!({
-            // LDK gives us feerates in satoshis per KW but Bitcoin Core here expects fees
-            // denominated in satoshis per vB. First we need to multiply by 4 to convert weight
-            // units to virtual bytes, then divide by 1000 to convert KvB to vB.
-            "fee_rate": fee_rate as f64 / 250.0,
-            // While users could "cancel" a channel open by RBF-bumping and paying back to
-            // themselves, we don't allow it here as its easy to have users accidentally RBF bump
-            // and pay to the channel funding address, which results in loss of funds. Real
-            // LDK-based applications should enable RBF bumping and RBF bump either to a local
-            // change address or to a new channel output negotiated with the same node.
-            "replaceable": false,
-            "include_unsafe": true,
-            "includeWatching": true,
-            "add_inputs": true,
-        });
+        self.fund_transaction(Vec::new(), script, amount_sat.sat(), fee_rate, 0)
+    }
 
-        let hex: String = self.rpc.call(
-            "createrawtransaction",
-            &[json::json!([]), json::json!(&map), json::json!(0)],
-        )?;
+    fn create_funding_transaction(
+        &self,
+        script: bitcoin::ScriptBuf,
+        amount_sat: Sat,
+        fee_rate: u32,
+        min_confirmations: u32,
+    ) -> error::Result<bitcoin::Transaction> {
+        self.fund_transaction(
+            Vec::new(),
+            script,
+            amount_sat.sat(),
+            fee_rate,
+            min_confirmations,
+        )
+    }
 
-        let tx: Tx = self.rpc.call(
-            "fundrawtransaction",
-            &[json::json!(hex), json::json!(options)],
-        )?;
+    fn estimate_fee(
+        &self,
+        script: bitcoin::ScriptBuf,
+        amount_sat: Sat,
+        fee_rate: u32,
+    ) -> error::Result<Sat> {
+        let tx = self.fund_raw_transaction(Vec::new(), script, amount_sat.sat(), fee_rate, 0)?;
+        Ok(Sat::from_sat(Amount::from_btc(tx.fee)?.to_sat()))
+    }
 
-        let hex: Tx = self
+    fn create_transaction_from_utxos(
+        &self,
+        utxos: Vec<bitcoin::OutPoint>,
+        script: bitcoin::ScriptBuf,
+        amount_sat: Sat,
+        fee_rate: u32,
+    ) -> error::Result<bitcoin::Transaction> {
+        if utxos.is_empty() {
+            error::bail!("no utxos specified for the transaction");
+        }
+        // bitcoin core does not list the outputs that are spent or
+        // locked, so an output that is missing here it is not
+        // spendable by us.
+        let unspent = self
             .rpc
-            .call("signrawtransactionwithwallet", &[json::json!(tx.hex)])?;
-        let hex = hex.hex.unwrap();
-        let mut reader = HexIterator::new(&hex)?;
-        let object = Decodable::consensus_decode(&mut reader)?;
-        Ok(object)
+            .list_unspent(Some(0), None, None, Some(true), None)?;
+        for utxo in &utxos {
+            let spendable = unspent.iter().any(|unspent| {
+                unspent.txid.to_string() == utxo.txid.to_string()
+                    && unspent.vout == utxo.vout
+                    && unspent.spendable
+            });
+            if !spendable {
+                error::bail!("utxo `{utxo}` not found in the wallet, or already spent or reserved");
+            }
+        }
+        self.fund_transaction(utxos, script, amount_sat.sat(), fee_rate, 0)
     }
 
     fn get_onchain_address(&self) -> error::Result<NewAddress> {
@@ -283,28 +413,75 @@ impl WalletManager for CoreWalletManager {
         Ok(NewAddress { address: addr })
     }
 
-    fn get_onchain_balance(&self) -> error::Result<u64> {
+    fn get_onchain_balance(&self) -> error::Result<Sat> {
         let balance = self.rpc.get_balance(None, Some(true))?;
-        Ok(balance.to_sat() * 1000)
+        Ok(Sat::from_sat(balance.to_sat()))
+    }
+
+    fn pending_incoming_balance(&self) -> error::Result<Sat> {
+        // the outputs with zero confirmations, also the unsafe ones
+        // that are not sent by us
+        let pending = self
+            .rpc
+            .list_unspent(Some(0), Some(0), None, Some(true), None)?
+            .iter()
+            .map(|utxo| Sat::from_sat(utxo.amount.to_sat()));
+        Ok(Sat::checked_sum(pending)?)
     }
 
     fn ldk_keys(&self) -> Arc<LampoKeys> {
         self.keymanager.clone()
     }
 
-    fn list_transactions(&self) -> error::Result<Vec<Utxo>> {
+    fn list_transaction_history(&self) -> error::Result<Vec<TxDetail>> {
+        // bitcoin core lists an entry for each output, so we take
+        // the details of each transaction only once, and it has no
+        // option to list all the entries.
+        let mut txids = Vec::new();
+        for entry in self
+            .rpc
+            .list_transactions(None, Some(i32::MAX as usize), None, Some(true))?
+        {
+            if !txids.contains(&entry.info.txid) {
+                txids.push(entry.info.txid);
+            }
+        }
+        let mut history = Vec::new();
+        for txid in txids {
+            let tx = self.rpc.get_transaction(&txid, Some(true))?;
+            // core reports the fee (negative) apart from the amount
+            let fee = tx.fee.map(|fee| fee.to_sat());
+            history.push(TxDetail {
+                txid: txid.to_string(),
+                net_amount_sat: tx.amount.to_sat() + fee.unwrap_or_default(),
+                fee_sat: fee.map(|fee| fee.unsigned_abs()),
+                confirmation_height: tx.info.blockheight,
+                timestamp: Some(tx.info.blocktime.unwrap_or(tx.info.time)),
+            });
+        }
+        sort_history(&mut history);
+        Ok(history)
+    }
+
+    fn list_unspent(&self) -> error::Result<Vec<Utxo>> {
         let unspend = self
             .rpc
             .list_unspent(None, None, None, Some(true), None)?
             .iter()
-            .map(|utxo| Utxo {
-                txid: utxo.txid.to_string(),
-                vout: utxo.vout,
-                reserved: utxo.spendable.not(),
-                confirmed: utxo.confirmations,
-                amount_msat: utxo.amount.to_sat() * 1000,
+            .map(|utxo| {
+                Ok(Utxo {
+                    txid: utxo.txid.to_string(),
+                    vout: utxo.vout,
+                    reserved: utxo.spendable.not(),
+                    confirmed: utxo.confirmations,
+                    amount_msat: Sat::from_sat(utxo.amount.to_sat()).to_msat()?,
+                    address: utxo
+                        .address
+                        .clone()
+                        .map(|address| address.assume_checked().to_string()),
+                })
             })
-            .collect::<Vec<_>>();
+            .collect::<error::Result<Vec<_>>>()?;
         Ok(unspend)
     }
 
@@ -334,11 +511,58 @@ impl WalletManager for CoreWalletManager {
             rpc,
             keymanager: keymanager.into(),
             network: conf.network,
+            min_fee_rate: min_fee_rate(&conf),
         })
     }
 
-    fn sync(&self) -> error::Result<()> {
-        Ok(())
+    fn synced_height(&self) -> error::Result<Option<u32>> {
+        // the wallet is kept in sync by bitcoind
+        Ok(Some(self.rpc.get_block_count()? as u32))
+    }
+
+    fn sync(&self) -> error::Result<SyncReport> {
+        // bitcoind keeps the wallet in sync, so nothing is applied here
+        Ok(SyncReport::default())
+    }
+
+    fn list_confirmed_utxos(&self) -> error::Result<Vec<bump_transaction::Utxo>> {
+        let mut utxos = Vec::new();
+        for utxo in self
+            .rpc
+            .list_unspent(Some(1), None, None, Some(false), None)?
+            .iter()
+            .filter(|utxo| utxo.spendable)
+        {
+            let txid = bitcoin::Txid::from_str(&utxo.txid.to_string())?;
+            utxos.push(bump_transaction::Utxo {
+                outpoint: bitcoin::OutPoint::new(txid, utxo.vout),
+                output: bitcoin::TxOut {
+                    value: utxo.amount.to_sat(),
+                    script_pubkey: bitcoin::ScriptBuf::from_bytes(
+                        utxo.script_pub_key.to_bytes(),
+                    ),
+                },
+                // The wallet is build with a BIP 84 descriptor.
+                satisfaction_weight: P2WPKH_SATISFACTION_WEIGHT,
+            });
+        }
+        Ok(utxos)
+    }
+
+    fn get_change_script(&self) -> error::Result<bitcoin::ScriptBuf> {
+        let addr: String = self.rpc.call("getrawchangeaddress", &["bech32".into()])?;
+        let addr = bitcoin::Address::from_str(&addr)?.require_network(self.network)?;
+        Ok(addr.script_pubkey())
+    }
+
+    fn sign_psbt(&self, psbt: PartiallySignedTransaction) -> error::Result<bitcoin::Transaction> {
+        // bitcoin core sign and finalize only the inputs that it owns,
+        // so the inputs owned by ldk (e.g. the anchor) are left untouched.
+        let processed: ProcessedPsbt = self
+            .rpc
+            .call("walletprocesspsbt", &[psbt.to_string().into(), true.into()])?;
+        let psbt = PartiallySignedTransaction::from_str(&processed.psbt)?;
+        Ok(psbt.extract_tx())
     }
 }
 
@@ -357,6 +581,7 @@ impl TryFrom<(PrivateKey, Option<String>, Arc<LampoConf>)> for CoreWalletManager
             keymanager: Arc::new(keymanager),
             rpc,
             network: conf.network,
+            min_fee_rate: min_fee_rate(&conf),
         })
     }
 }
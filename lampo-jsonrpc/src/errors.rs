@@ -4,6 +4,11 @@ use std::{error, fmt};
 use serde::{Deserialize, Serialize};
 use serde_json;
 
+/// JSON RPC 2.0 code for a method that does not exist.
+pub const METHOD_NOT_FOUND: i32 = -32601;
+/// JSON RPC 2.0 code for invalid method params.
+pub const INVALID_PARAMS: i32 = -32602;
+
 /// A library error
 #[derive(Debug)]
 pub enum Error {
@@ -77,6 +82,12 @@ impl From<Error> for RpcError {
     fn from(value: Error) -> Self {
         match value {
             Error::Rpc(rpc) => return rpc.clone(),
+            // the params are decoded with serde inside the callbacks
+            Error::Json(err) => RpcError {
+                code: INVALID_PARAMS,
+                message: format!("invalid params: {err}"),
+                data: None,
+            },
             _ => RpcError {
                 code: -1,
                 message: format!("{value}"),
@@ -47,6 +47,15 @@ impl<T: Serialize> Request<T> {
     }
 }
 
+/// A single request or response, or a batch of them.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Message<T> {
+    Batch(Vec<T>),
+    Single(T),
+}
+
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 /// A standard JSONRPC response object
@@ -62,6 +71,24 @@ pub struct Response<T> {
 }
 
 impl<T> Response<T> {
+    /// The response to the request with `id`.
+    pub fn new(id: Id, jsonrpc: String, result: Result<T, Error>) -> Self {
+        match result {
+            Ok(result) => Response {
+                result: Some(result),
+                error: None,
+                id,
+                jsonrpc,
+            },
+            Err(err) => Response {
+                result: None,
+                error: Some(err.into()),
+                id,
+                jsonrpc,
+            },
+        }
+    }
+
     /// Extract the result from a response, consuming the response
     pub fn into_result(self) -> Result<T, Error> {
         if let Some(e) = self.error {
@@ -1,6 +1,5 @@
 //! Full feature async JSON RPC 2.0 Server/client with a
 //! minimal dependencies footprint.
-use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::io;
 use std::io::ErrorKind;
@@ -8,8 +7,10 @@ use std::io::{Read, Write};
 use std::os::fd::AsRawFd;
 use std::os::unix::net::UnixListener;
 use std::os::unix::net::UnixStream;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 // FIXME: use mio for a better platform support.
 use popol::{Event, Sources, Timeout};
@@ -18,11 +19,17 @@ use serde_json::Value;
 pub mod command;
 pub mod errors;
 pub mod json_rpc2;
+mod sync;
 
 use command::Context;
 
 use crate::errors::Error;
-use crate::json_rpc2::{Request, Response};
+use crate::json_rpc2::{Message, Request, Response};
+use crate::sync::RwLockExt;
+
+/// Maximum number of the read methods of a batch that run at the
+/// same time.
+const MAX_CONCURRENT_READS: usize = 16;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum RPCEvent {
@@ -34,55 +41,190 @@ pub struct JSONRPCv2<T: Send + Sync + 'static> {
     socket_path: String,
     sources: Sources<RPCEvent>,
     open_streams: HashMap<i32, UnixStream>,
-    response_queue: HashMap<i32, Response<Value>>,
+    response_queue: HashMap<i32, Message<Response<Value>>>,
     socket: UnixListener,
     handler: Arc<Handler<T>>,
 }
 
+/// Authenticate the request of a client before the method runs, the
+/// hook can remove from the params what the method does not expect.
+pub type AuthHook =
+    Arc<dyn Fn(&str, &mut Value) -> Result<(), errors::Error> + Send + Sync + 'static>;
+
+/// Observe every method that ran with the time that it took, and if
+/// it succeeded.
+pub type ObserveHook = Arc<dyn Fn(&str, Duration, bool) + Send + Sync + 'static>;
+
+/// The callback of a method.
+pub type Callback<T> =
+    Arc<dyn Fn(&T, &Value) -> Result<Value, errors::Error> + Send + Sync + 'static>;
+
+/// What a method does with the state, the reads of a batch can run
+/// at the same time while the mutations run one at the time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MethodKind {
+    Read,
+    Mutate,
+}
+
+/// The methods of the server, shared by every transport that serves
+/// the requests.
 pub struct Handler<T: Send + Sync + 'static> {
-    stop: Cell<bool>,
-    rpc_method:
-        RefCell<HashMap<String, Arc<dyn Fn(&T, &Value) -> Result<Value, errors::Error> + 'static>>>,
+    stop: AtomicBool,
+    rpc_method: RwLock<HashMap<String, (Callback<T>, MethodKind)>>,
+    auth: RwLock<Option<AuthHook>>,
+    observer: RwLock<Option<ObserveHook>>,
     ctx: Arc<dyn Context<Ctx = T>>,
 }
 
-unsafe impl<T: Send + Sync> Sync for Handler<T> {}
-unsafe impl<T: Send + Sync> Send for Handler<T> {}
-
 impl<T: Send + Sync + 'static> Handler<T> {
     pub fn new(ctx: Arc<dyn Context<Ctx = T>>) -> Self {
         Handler::<T> {
-            stop: Cell::new(false),
-            rpc_method: RefCell::new(HashMap::new()),
+            stop: AtomicBool::new(false),
+            rpc_method: RwLock::new(HashMap::new()),
+            auth: RwLock::new(None),
+            observer: RwLock::new(None),
             ctx,
         }
     }
 
+    /// Add a method that changes the state.
     pub fn add_method<F>(&self, method: &str, callback: F)
     where
-        F: Fn(&T, &Value) -> Result<Value, errors::Error> + 'static,
+        F: Fn(&T, &Value) -> Result<Value, errors::Error> + Send + Sync + 'static,
     {
+        self.add_method_with_kind(method, MethodKind::Mutate, callback);
+    }
+
+    pub fn add_method_with_kind<F>(&self, method: &str, kind: MethodKind, callback: F)
+    where
+        F: Fn(&T, &Value) -> Result<Value, errors::Error> + Send + Sync + 'static,
+    {
+        self.rpc_method
+            .write_or_recover()
+            .insert(method.to_owned(), (Arc::new(callback), kind));
+    }
+
+    /// The kind of the method, `None` if the method does not exist.
+    pub fn method_kind(&self, method: &str) -> Option<MethodKind> {
         self.rpc_method
-            .borrow_mut()
-            .insert(method.to_owned(), Arc::new(callback));
+            .read_or_recover()
+            .get(method)
+            .map(|(_, kind)| *kind)
     }
 
     pub fn run_callback(&self, req: &Request<Value>) -> Option<Result<Value, errors::Error>> {
-        let binding = self.rpc_method.borrow();
-        let Some(callback) = binding.get(&req.method) else {
+        let callback = self
+            .rpc_method
+            .read_or_recover()
+            .get(&req.method)
+            .map(|(callback, _)| callback.clone());
+        let Some(callback) = callback else {
             return Some(Err(errors::RpcError {
                 message: format!("method `{}` not found", req.method),
-                code: -1,
+                code: errors::METHOD_NOT_FOUND,
                 data: None,
             }
             .into()));
         };
+        let started = Instant::now();
         let resp = callback(self.ctx(), &req.params);
+        let observer = self.observer.read_or_recover().clone();
+        if let Some(observer) = observer {
+            observer(&req.method, started.elapsed(), resp.is_ok());
+        }
         Some(resp)
     }
 
+    /// Set the hook that authenticates the requests of the clients.
+    pub fn set_auth<F>(&self, auth: F)
+    where
+        F: Fn(&str, &mut Value) -> Result<(), errors::Error> + Send + Sync + 'static,
+    {
+        *self.auth.write_or_recover() = Some(Arc::new(auth));
+    }
+
+    /// Set the hook that observes the methods that ran.
+    pub fn set_observer<F>(&self, observer: F)
+    where
+        F: Fn(&str, Duration, bool) + Send + Sync + 'static,
+    {
+        *self.observer.write_or_recover() = Some(Arc::new(observer));
+    }
+
+    /// Run the callback of a request received from a client, the request
+    /// is authenticated before the callback runs.
+    pub fn run_client_callback(
+        &self,
+        req: &Request<Value>,
+    ) -> Option<Result<Value, errors::Error>> {
+        let auth = self.auth.read_or_recover().clone();
+        let Some(auth) = auth else {
+            return self.run_callback(req);
+        };
+        let mut req = req.clone();
+        if let Err(err) = auth(&req.method, &mut req.params) {
+            return Some(Err(err));
+        }
+        self.run_callback(&req)
+    }
+
+    /// Run a batch of requests received from a client, and return
+    /// the results in the order of the requests.
+    ///
+    /// The read methods between two mutations run at the same time,
+    /// the mutations run alone in the order of the batch, so a read
+    /// sees the mutations that come before it.
+    pub fn run_client_batch(&self, reqs: &[Request<Value>]) -> Vec<Result<Value, errors::Error>> {
+        let is_read =
+            |req: &Request<Value>| self.method_kind(&req.method) == Some(MethodKind::Read);
+        let mut results = Vec::with_capacity(reqs.len());
+        let mut pending = reqs;
+        while let Some(req) = pending.first() {
+            if !is_read(req) {
+                results.push(self.run_client(req));
+                pending = &pending[1..];
+                continue;
+            }
+            let reads = pending.iter().take_while(|req| is_read(req)).count();
+            let (reads, rest) = pending.split_at(reads);
+            for reads in reads.chunks(MAX_CONCURRENT_READS) {
+                std::thread::scope(|scope| {
+                    let workers = reads
+                        .iter()
+                        .map(|req| scope.spawn(move || self.run_client(req)))
+                        .collect::<Vec<_>>();
+                    for (worker, req) in workers.into_iter().zip(reads) {
+                        let result = worker.join().unwrap_or_else(|_| {
+                            Err(errors::RpcError {
+                                message: format!("method `{}` panicked", req.method),
+                                code: -1,
+                                data: None,
+                            }
+                            .into())
+                        });
+                        results.push(result);
+                    }
+                });
+            }
+            pending = rest;
+        }
+        results
+    }
+
+    fn run_client(&self, req: &Request<Value>) -> Result<Value, errors::Error> {
+        self.run_client_callback(req).unwrap_or_else(|| {
+            Err(errors::RpcError {
+                message: format!("method `{}` not found", req.method),
+                code: errors::METHOD_NOT_FOUND,
+                data: None,
+            }
+            .into())
+        })
+    }
+
     pub fn has_rpc(&self, method: &str) -> bool {
-        self.rpc_method.borrow().contains_key(method)
+        self.rpc_method.read_or_recover().contains_key(method)
     }
 
     fn ctx(&self) -> &T {
@@ -90,7 +232,11 @@ impl<T: Send + Sync + 'static> Handler<T> {
     }
 
     pub fn stop(&self) {
-        self.stop.set(true);
+        self.stop.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
     }
 }
 
@@ -110,7 +256,7 @@ impl<T: Send + Sync + 'static> JSONRPCv2<T> {
 
     pub fn add_rpc<F>(&self, name: &str, callback: F) -> Result<(), ()>
     where
-        F: Fn(&T, &Value) -> Result<Value, errors::Error> + 'static,
+        F: Fn(&T, &Value) -> Result<Value, errors::Error> + Send + Sync + 'static,
     {
         if self.handler.has_rpc(name) {
             return Err(());
@@ -119,6 +265,20 @@ impl<T: Send + Sync + 'static> JSONRPCv2<T> {
         Ok(())
     }
 
+    /// Add a method that only reads the state, so it can run together
+    /// with the other reads of a batch.
+    pub fn add_read_rpc<F>(&self, name: &str, callback: F) -> Result<(), ()>
+    where
+        F: Fn(&T, &Value) -> Result<Value, errors::Error> + Send + Sync + 'static,
+    {
+        if self.handler.has_rpc(name) {
+            return Err(());
+        }
+        self.handler
+            .add_method_with_kind(name, MethodKind::Read, callback);
+        Ok(())
+    }
+
     #[allow(dead_code)]
     fn ctx(&self) -> &T {
         self.handler.ctx()
@@ -134,40 +294,29 @@ impl<T: Send + Sync + 'static> JSONRPCv2<T> {
         // we will be notified again if there is still data to be read on the socket.
         // Hence, there is no use in putting this socket read in a loop, as the second
         // invocation would likely block.
-        let mut buff = vec![0; 1064];
-        let resp = loop {
-            match stream.read(&mut buff) {
+        let mut buff = Vec::new();
+        let mut chunk = vec![0; 1064];
+        let message = loop {
+            match stream.read(&mut chunk) {
                 Ok(count) => {
-                    buff.truncate(count);
                     if count > 0 {
-                        log::info!(target: "jsonrpc", "buffer read {}", String::from_utf8(buff.to_vec()).unwrap());
-                        // Put this inside the unfinish queue
-                        let Ok(requ) = serde_json::from_slice::<Request<Value>>(&buff) else {
-                            log::warn!(target: "jsonrpc", "looks like that the json is not fully read ` {}`", String::from_utf8(buff.to_vec()).unwrap());
+                        buff.extend_from_slice(&chunk[..count]);
+                        // the params carry the token of the client, so they are never logged
+                        log::trace!(target: "jsonrpc", "buffer read of {} bytes", buff.len());
+                        match serde_json::from_slice::<Message<Request<Value>>>(&buff) {
+                            Ok(message) => break message,
                             // Usually this mean that we was too fast in reading and the sender too low
-                            continue;
-                        };
-                        log::trace!(target: "jsonrpc", "request {:?}", requ);
-                        let Some(resp) = self.handler.run_callback(&requ) else {
-                            log::error!(target: "jsonrpc", "`{}` not found!", requ.method);
-                            return Ok(());
-                        };
-                        // FIXME; the id in the JSON RPC can be null!
-                        let response = match resp {
-                            Ok(result) => Response {
-                                id: requ.id.clone().unwrap(),
-                                jsonrpc: requ.jsonrpc.to_owned(),
-                                result: Some(result),
-                                error: None,
-                            },
-                            Err(err) => Response {
-                                result: None,
-                                error: Some(err.into()),
-                                id: requ.id.unwrap().clone(),
-                                jsonrpc: requ.jsonrpc.clone(),
-                            },
-                        };
-                        break response;
+                            Err(err) if err.is_eof() => {
+                                log::warn!(target: "jsonrpc", "looks like that the json is not fully read, {} bytes", buff.len());
+                                continue;
+                            }
+                            Err(err) => {
+                                log::warn!(target: "jsonrpc", "invalid request of {} bytes: {err}", buff.len());
+                                self.sources.unregister(&event.key);
+                                self.open_streams.remove(&fd);
+                                return Ok(());
+                            }
+                        }
                     } else {
                         log::info!("Reading is not finished, so keep reading");
                         event.source.unset(popol::interest::READ);
@@ -188,6 +337,38 @@ impl<T: Send + Sync + 'static> JSONRPCv2<T> {
                 }
             }
         };
+        match &message {
+            Message::Single(requ) => log::trace!(target: "jsonrpc", "request `{}`", requ.method),
+            Message::Batch(reqs) => {
+                log::trace!(target: "jsonrpc", "batch of {} requests", reqs.len())
+            }
+        }
+
+        let resp = match message {
+            Message::Single(requ) => {
+                let Some(resp) = self.handler.run_client_callback(&requ) else {
+                    log::error!(target: "jsonrpc", "`{}` not found!", requ.method);
+                    return Ok(());
+                };
+                // FIXME; the id in the JSON RPC can be null!
+                Message::Single(Response::new(requ.id.unwrap(), requ.jsonrpc, resp))
+            }
+            Message::Batch(reqs) => {
+                let results = self.handler.run_client_batch(&reqs);
+                // the notifications do not have a response
+                let resps = reqs
+                    .into_iter()
+                    .zip(results)
+                    .filter_map(|(requ, resp)| Some(Response::new(requ.id?, requ.jsonrpc, resp)))
+                    .collect::<Vec<_>>();
+                if resps.is_empty() {
+                    self.sources.unregister(&event.key);
+                    self.open_streams.remove(&fd);
+                    return Ok(());
+                }
+                Message::Batch(resps)
+            }
+        };
 
         log::trace!(target: "jsonrpc", "send response: `{:?}`", resp);
         self.response_queue.insert(fd, resp);
@@ -201,7 +382,7 @@ impl<T: Send + Sync + 'static> JSONRPCv2<T> {
             .register(RPCEvent::Accept, &self.socket, popol::interest::READ);
         log::info!(target: "jsonrpc", "starting server on {}", self.socket_path);
         let mut events = vec![];
-        while !self.handler.stop.get() {
+        while !self.handler.is_stopped() {
             // Blocking while we are waiting new events!
             self.sources.poll(&mut events, Timeout::Never)?;
             for mut event in events.drain(..) {
@@ -309,15 +490,22 @@ impl<T: Send + Sync + 'static> Drop for JSONRPCv2<T> {
 #[cfg(test)]
 mod tests {
     use std::{
-        io::Write, os::unix::net::UnixStream, path::Path, str::FromStr, sync::Arc, time::Duration,
+        io::Write,
+        os::unix::net::UnixStream,
+        path::Path,
+        str::FromStr,
+        sync::atomic::{AtomicBool, AtomicU64, Ordering},
+        sync::Arc,
+        time::{Duration, Instant},
     };
 
     use lampo_common::logger;
     use ntest::timeout;
-    use serde_json::Value;
+    use serde_json::{json, Value};
 
     use crate::{
         command::Context,
+        errors::{RpcError, METHOD_NOT_FOUND},
         json_rpc2::{Id, Request, Response},
         JSONRPCv2,
     };
@@ -403,4 +591,115 @@ mod tests {
         assert_eq!(Id::Str("1".to_owned()), resp.id);
         handler.stop();
     }
+
+    #[derive(Default)]
+    struct BatchCtx {
+        counter: AtomicU64,
+        fast_done: AtomicBool,
+    }
+
+    impl Context for BatchCtx {
+        type Ctx = BatchCtx;
+
+        fn ctx(&self) -> &Self::Ctx {
+            self
+        }
+    }
+
+    #[test]
+    #[timeout(9000)]
+    fn run_batch() {
+        let path = "/tmp/batch.sock";
+        let _ = std::fs::remove_file(path);
+        let server = JSONRPCv2::new(Arc::new(BatchCtx::default()), path).unwrap();
+        // it waits the fast read, so it fails if the reads run one at the time
+        server
+            .add_read_rpc("slow", |ctx: &BatchCtx, _| {
+                let started = Instant::now();
+                while !ctx.fast_done.load(Ordering::SeqCst) {
+                    if started.elapsed() > Duration::from_secs(3) {
+                        return Err(RpcError {
+                            code: -1,
+                            message: "the fast read is blocked".to_owned(),
+                            data: None,
+                        }
+                        .into());
+                    }
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                Ok(json!("slow"))
+            })
+            .unwrap();
+        server
+            .add_read_rpc("fast", |ctx: &BatchCtx, _| {
+                ctx.fast_done.store(true, Ordering::SeqCst);
+                Ok(json!("fast"))
+            })
+            .unwrap();
+        server
+            .add_read_rpc("get", |ctx: &BatchCtx, _| {
+                Ok(json!(ctx.counter.load(Ordering::SeqCst)))
+            })
+            .unwrap();
+        server
+            .add_rpc("incr", |ctx: &BatchCtx, _| {
+                Ok(json!(ctx.counter.fetch_add(1, Ordering::SeqCst) + 1))
+            })
+            .unwrap();
+        server
+            .add_rpc("fail", |_: &BatchCtx, _| {
+                Err(RpcError {
+                    code: 42,
+                    message: "failed".to_owned(),
+                    data: None,
+                }
+                .into())
+            })
+            .unwrap();
+
+        let handler = server.handler();
+        let _worker = server.spawn();
+        let methods = [
+            "slow", "fast", "get", "incr", "get", "fail", "incr", "get", "unknown", "get",
+        ];
+        let batch = methods
+            .iter()
+            .enumerate()
+            .map(|(id, method)| Request::<Value> {
+                id: Some((id as u64).into()),
+                jsonrpc: "2.0".to_owned(),
+                method: method.to_string(),
+                params: json!({}),
+            })
+            .collect::<Vec<_>>();
+        let mut stream = UnixStream::connect(Path::new(path)).unwrap();
+        stream
+            .write_all(serde_json::to_string(&batch).unwrap().as_bytes())
+            .unwrap();
+        stream.flush().unwrap();
+        let resps: Vec<Response<Value>> = serde_json::from_reader(stream).unwrap();
+        handler.stop();
+
+        let ids = resps.iter().map(|resp| resp.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids, (0..10u64).map(Id::from).collect::<Vec<_>>());
+        let results = resps
+            .into_iter()
+            .map(|resp| resp.into_result().map_err(|err| RpcError::from(err).code))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            vec![
+                Ok(json!("slow")),
+                Ok(json!("fast")),
+                Ok(json!(0)),
+                Ok(json!(1)),
+                Ok(json!(1)),
+                Err(42),
+                Ok(json!(2)),
+                Ok(json!(2)),
+                Err(METHOD_NOT_FOUND),
+                Ok(json!(2)),
+            ]
+        );
+    }
 }
@@ -0,0 +1,25 @@
+//! Locks that recover from the poisoning, like `lampo_common::sync`
+//! that this crate does not depend on.
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+fn recover<G>(err: PoisonError<G>) -> G {
+    log::warn!("lock poisoned by a panic, recovering it");
+    err.into_inner()
+}
+
+pub(crate) trait RwLockExt<T> {
+    /// Take the read lock, recovering it when it is poisoned.
+    fn read_or_recover(&self) -> RwLockReadGuard<'_, T>;
+    /// Take the write lock, recovering it when it is poisoned.
+    fn write_or_recover(&self) -> RwLockWriteGuard<'_, T>;
+}
+
+impl<T> RwLockExt<T> for RwLock<T> {
+    fn read_or_recover(&self) -> RwLockReadGuard<'_, T> {
+        self.read().unwrap_or_else(recover)
+    }
+
+    fn write_or_recover(&self) -> RwLockWriteGuard<'_, T> {
+        self.write().unwrap_or_else(recover)
+    }
+}
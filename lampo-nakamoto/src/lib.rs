@@ -1,9 +1,7 @@
 //! Nakamoto backend implementation for Lampo
-use std::cell::Cell;
-use std::cell::RefCell;
 use std::collections::HashMap;
 use std::net::TcpStream;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, RwLock};
 
 use esplora_client::BlockingClient;
 use esplora_client::Builder;
@@ -19,19 +17,22 @@ use lampo_common::backend::Backend;
 use lampo_common::backend::BlockData;
 use lampo_common::backend::BlockHash;
 use lampo_common::backend::BlockHeaderData;
-use lampo_common::backend::UtxoResult;
+use lampo_common::backend::BroadcastError;
+use lampo_common::backend::TxOut;
 use lampo_common::backend::WatchedOutput;
 use lampo_common::error;
 use lampo_common::event::onchain::OnChainEvent;
 use lampo_common::event::Event;
 use lampo_common::handler::Handler;
+use lampo_common::sync::{MutexExt, RwLockExt};
 
 #[derive(Clone)]
 pub struct Nakamoto {
     nakamoto: nakamoto_client::Handle<Waker>,
-    current_height: Cell<Option<Height>>,
+    current_height: Arc<Mutex<Option<Height>>>,
     rest: BlockingClient,
-    handler: RefCell<Option<Arc<dyn Handler>>>,
+    url: String,
+    handler: Arc<RwLock<Option<Arc<dyn Handler>>>>,
 }
 
 impl Nakamoto {
@@ -54,11 +55,12 @@ impl Nakamoto {
         let _worker = std::thread::spawn(|| nakamoto.run(config));
         let client = Nakamoto {
             nakamoto: handler,
-            current_height: Cell::new(None),
+            current_height: Arc::new(Mutex::new(None)),
             rest: Builder::new(url)
                 .build_blocking()
                 .map_err(|err| error::anyhow!("{err}"))?,
-            handler: RefCell::new(None),
+            url: url.to_owned(),
+            handler: Arc::new(RwLock::new(None)),
         };
         Ok(client)
     }
@@ -80,6 +82,10 @@ impl Backend for Nakamoto {
         lampo_common::backend::BackendKind::Nakamoto
     }
 
+    fn endpoint(&self) -> Option<String> {
+        Some(self.url.clone())
+    }
+
     fn get_block<'a>(
         &'a self,
         header_hash: &'a nakamoto_common::block::BlockHash,
@@ -90,9 +96,9 @@ impl Backend for Nakamoto {
             .get_block(header_hash)?
             .ok_or(error::anyhow!("block `{header_hash}` not found"))?;
         log::info!("get block information {:?}", block);
-        self.current_height.set(Some(block.0));
+        *self.current_height.lock_or_recover() = Some(block.0);
 
-        let _ = self.handler.borrow().clone().map(|handler| {
+        let _ = self.handler.read_or_recover().clone().map(|handler| {
             let (blk, _) = blk_chan.recv().unwrap();
             handler.emit(Event::OnChain(OnChainEvent::NewBlock(blk)));
             handler
@@ -112,14 +118,16 @@ impl Backend for Nakamoto {
         unimplemented!()
     }
 
-    fn brodcast_tx(&self, tx: &nakamoto_common::block::Transaction) {
-        let result = self.nakamoto.submit_transaction(tx.clone());
-        if let Err(err) = result {
+    fn brodcast_tx(&self, tx: &nakamoto_common::block::Transaction) -> Result<(), BroadcastError> {
+        // the peers do not tell us why a transaction is rejected, so
+        // we can only report that it was not submitted.
+        if let Err(err) = self.nakamoto.submit_transaction(tx.clone()) {
             log::error!("brodcast tx fails: {err}");
-        } else {
-            let handler = self.handler.borrow().clone().unwrap();
-            handler.emit(Event::OnChain(OnChainEvent::SendRawTransaction(tx.clone())));
+            return Err(BroadcastError::Rejected(err.to_string()));
         }
+        let handler = self.handler.read_or_recover().clone().unwrap();
+        handler.emit(Event::OnChain(OnChainEvent::SendRawTransaction(tx.clone())));
+        Ok(())
     }
 
     fn is_lightway(&self) -> bool {
@@ -131,6 +139,12 @@ impl Backend for Nakamoto {
         Ok((tip.blk_header.block_hash(), Some(tip.height as u32)))
     }
 
+    fn get_block_hash_at(&self, height: u32) -> error::Result<BlockHash> {
+        self.rest
+            .get_block_hash(height)
+            .map_err(|err| error::anyhow!("{err}"))
+    }
+
     fn register_output(
         &self,
         _: WatchedOutput,
@@ -151,8 +165,32 @@ impl Backend for Nakamoto {
         self.fee_rate_estimation(2)
     }
 
-    fn get_utxo(&self, block: &BlockHash, idx: u64) -> UtxoResult {
-        todo!()
+    fn get_output_by_scid(
+        &self,
+        height: u32,
+        tx_index: u32,
+        vout: u16,
+    ) -> error::Result<Option<TxOut>> {
+        let hash = self.get_block_hash_at(height)?;
+        let Some(txid) = self
+            .rest
+            .get_txid_at_block_index(&hash, tx_index as usize)
+            .map_err(|err| error::anyhow!("{err}"))?
+        else {
+            return Ok(None);
+        };
+        let Some(tx) = self.rest.get_tx(&txid).map_err(|err| error::anyhow!("{err}"))? else {
+            return Ok(None);
+        };
+        let Some(output) = tx.output.get(vout as usize) else {
+            return Ok(None);
+        };
+        let spent = self
+            .rest
+            .get_output_status(&txid, vout as u64)
+            .map_err(|err| error::anyhow!("{err}"))?
+            .is_some_and(|status| status.spent);
+        Ok((!spent).then(|| output.clone()))
     }
 
     fn get_utxo_by_txid(
@@ -164,7 +202,7 @@ impl Backend for Nakamoto {
     }
 
     fn set_handler(&self, handler: std::sync::Arc<dyn lampo_common::handler::Handler>) {
-        self.handler.replace(Some(handler));
+        *self.handler.write_or_recover() = Some(handler);
     }
 
     fn get_transaction(
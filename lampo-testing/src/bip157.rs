@@ -0,0 +1,144 @@
+//! Test framework for the compact block filters backend, where
+//! lampo follows a bitcoind that serves the filters to the peers.
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+
+use clightning_testing::prelude::bitcoincore_rpc::{Auth, RpcApi};
+use clightning_testing::prelude::*;
+use tempfile::TempDir;
+
+use lampo_bdk_wallet::BDKWalletManager;
+use lampo_bip157::Bip157;
+use lampo_common::conf::LampoConf;
+use lampo_common::error;
+use lampo_jsonrpc::JSONRPCv2;
+use lampod::actions::handler::LampoHandler;
+use lampod::chain::WalletManager;
+use lampod::jsonrpc::inventory::get_info;
+use lampod::jsonrpc::onchain::json_chain_info;
+use lampod::jsonrpc::onchain::json_funds;
+use lampod::jsonrpc::onchain::json_new_addr;
+use lampod::jsonrpc::CommandHandler;
+use lampod::LampoDaemon;
+
+const RPC_USER: &str = "lampo";
+const RPC_PASS: &str = "lampo";
+
+/// A regtest bitcoind that serves the compact block filters.
+pub struct FilterBtcNode {
+    process: Child,
+    pub p2p_port: u16,
+    pub rpc_port: u16,
+    rpc: bitcoincore_rpc::Client,
+    _dir: TempDir,
+}
+
+impl FilterBtcNode {
+    pub fn tmp() -> error::Result<Self> {
+        let dir = tempfile::tempdir()?;
+        // SAFETY: this should be safe because if the system has no
+        // ports it is a bug
+        let p2p_port = port::random_free_port().unwrap();
+        let rpc_port = port::random_free_port().unwrap();
+        let process = Command::new("bitcoind")
+            .args([
+                "-regtest".to_owned(),
+                "-server".to_owned(),
+                "-listen=1".to_owned(),
+                "-blockfilterindex=1".to_owned(),
+                "-peerblockfilters=1".to_owned(),
+                format!("-datadir={}", dir.path().display()),
+                format!("-port={p2p_port}"),
+                format!("-rpcport={rpc_port}"),
+                format!("-rpcuser={RPC_USER}"),
+                format!("-rpcpassword={RPC_PASS}"),
+            ])
+            .stdout(Stdio::null())
+            .spawn()?;
+        let rpc = bitcoincore_rpc::Client::new(
+            &format!("127.0.0.1:{rpc_port}"),
+            Auth::UserPass(RPC_USER.to_owned(), RPC_PASS.to_owned()),
+        )?;
+        // wait that bitcoind is ready
+        crate::wait!(|| rpc.get_blockchain_info().map(|_| ()), 1);
+        Ok(Self {
+            process,
+            p2p_port,
+            rpc_port,
+            rpc,
+            _dir: dir,
+        })
+    }
+
+    pub fn rpc(&self) -> &bitcoincore_rpc::Client {
+        &self.rpc
+    }
+}
+
+impl Drop for FilterBtcNode {
+    fn drop(&mut self) {
+        let _ = self.rpc.stop();
+        let _ = self.process.wait();
+    }
+}
+
+/// A lampo node with the `bip157` backend, and a bdk wallet synced
+/// with the blocks that match the filters.
+pub struct LampoBip157Testing {
+    inner: Arc<LampoHandler>,
+    root_path: Arc<TempDir>,
+    pub wallet: Arc<dyn WalletManager>,
+    pub btc: Arc<FilterBtcNode>,
+}
+
+impl LampoBip157Testing {
+    pub fn new(btc: Arc<FilterBtcNode>) -> error::Result<Self> {
+        let dir = tempfile::tempdir()?;
+        // SAFETY: this should be safe because if the system has no
+        // ports it is a bug
+        let port = port::random_free_port().unwrap();
+        let mut lampo_conf = LampoConf::new(
+            Some(dir.path().to_string_lossy().to_string()),
+            Some(lampo_common::bitcoin::Network::Regtest),
+            Some(port.into()),
+        )?;
+        lampo_conf.node = "bip157".to_owned();
+        lampo_conf.bip157_peers = vec![format!("127.0.0.1:{}", btc.p2p_port)];
+        let (wallet, _) = BDKWalletManager::new(Arc::new(lampo_conf.clone()))?;
+        let wallet: Arc<dyn WalletManager> = Arc::new(wallet);
+        let mut lampo = LampoDaemon::new(lampo_conf.clone(), wallet.clone());
+        lampo.init(Arc::new(Bip157::new(&lampo_conf)?))?;
+
+        let lampo = Arc::new(lampo);
+        let socket_path = format!("{}/lampod.socket", lampo.root_path());
+        let server = JSONRPCv2::new(lampo.clone(), &socket_path)?;
+        server.add_rpc("getinfo", get_info).unwrap();
+        server.add_rpc("newaddr", json_new_addr).unwrap();
+        server.add_rpc("funds", json_funds).unwrap();
+        server.add_rpc("getchaininfo", json_chain_info).unwrap();
+        let handler = server.handler();
+        let rpc_handler = Arc::new(CommandHandler::new(&lampo_conf)?);
+        rpc_handler.set_handler(handler);
+        lampo.add_external_handler(rpc_handler)?;
+
+        let handler = lampo.handler();
+        std::thread::spawn(move || lampo.listen().unwrap().join());
+        // wait that lampo starts
+        std::thread::sleep(Duration::from_secs(1));
+        Ok(Self {
+            inner: handler,
+            root_path: Arc::new(dir),
+            wallet,
+            btc,
+        })
+    }
+
+    pub fn lampod(&self) -> Arc<LampoHandler> {
+        self.inner.clone()
+    }
+
+    pub fn root_path(&self) -> Arc<TempDir> {
+        self.root_path.clone()
+    }
+}
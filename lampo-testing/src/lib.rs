@@ -1,4 +1,7 @@
 //! Lampo test framework.
+#[cfg(feature = "bip157")]
+pub mod bip157;
+
 pub mod prelude {
     pub use clightning_testing::prelude::*;
     pub use clightning_testing::*;
@@ -6,16 +9,24 @@ pub mod prelude {
     pub use lampod::async_run;
 }
 
+use std::net::SocketAddr;
+use std::path::Path;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 
 use clightning_testing::btc::BtcNode;
 use clightning_testing::prelude::*;
+use lampo_common::event::ln::LightningEvent;
+use lampo_common::event::onchain::OnChainEvent;
+use lampo_common::event::Event;
+use lampo_common::handler::Handler as _;
 use lampo_common::json;
+use lampo_common::model::request;
 use lampo_common::model::response;
 use lampo_common::model::response::NewAddress;
 use lampod::jsonrpc::channels::json_close_channel;
+use lampod::jsonrpc::channels::json_get_channel;
 use lampod::jsonrpc::offchain::json_keysend;
 use tempfile::TempDir;
 
@@ -23,19 +34,79 @@ use lampo_bitcoind::BitcoinCore;
 use lampo_common::conf::LampoConf;
 use lampo_common::error;
 use lampo_core_wallet::CoreWalletManager;
+use lampo_jsonrpc::Handler;
 use lampo_jsonrpc::JSONRPCv2;
 use lampod::actions::handler::LampoHandler;
 use lampod::chain::WalletManager;
+use lampod::http::HttpServer;
+use lampod::jsonrpc::auth::authenticate;
+use lampod::jsonrpc::auth::json_mint_token;
+use lampod::jsonrpc::auth::json_revoke_token;
+use lampod::jsonrpc::channels::json_autofees_status;
 use lampod::jsonrpc::channels::json_list_channels;
+use lampod::jsonrpc::channels::json_list_htlcs;
+use lampod::jsonrpc::channels::json_set_channel;
+use lampod::jsonrpc::compat::add_cln_compat;
+use lampod::jsonrpc::datastore::json_datastore;
+use lampod::jsonrpc::datastore::json_del_datastore;
+use lampod::jsonrpc::datastore::json_list_datastore;
+use lampod::jsonrpc::dev::add_dev_methods;
+use lampod::jsonrpc::events::json_list_webhook_deliveries;
+use lampod::jsonrpc::events::json_wait_event;
+use lampod::jsonrpc::gossip::json_list_gossip_channels;
+use lampod::jsonrpc::gossip::json_list_nodes;
+use lampod::jsonrpc::health::json_health;
 use lampod::jsonrpc::inventory::get_info;
+use lampod::jsonrpc::inventory::json_list_configs;
+use lampod::jsonrpc::inventory::json_reload;
+use lampod::jsonrpc::inventory::json_stop;
+use lampod::jsonrpc::logs::json_get_log;
+use lampod::jsonrpc::logs::json_set_log_level;
+use lampod::jsonrpc::maintenance::json_maintenance;
+use lampod::jsonrpc::metrics::json_get_metrics;
+use lampod::jsonrpc::metrics::observe;
+use lampod::jsonrpc::offchain::json_cancel_invoice;
+use lampod::jsonrpc::offchain::json_cancel_payment;
+use lampod::jsonrpc::offchain::json_check_message;
 use lampod::jsonrpc::offchain::json_decode_invoice;
+use lampod::jsonrpc::offchain::json_get_payment_route;
+use lampod::jsonrpc::offchain::json_hold_invoice;
 use lampod::jsonrpc::offchain::json_invoice;
+use lampod::jsonrpc::offchain::json_is_invoice_paid;
+use lampod::jsonrpc::offchain::json_list_invoices;
+use lampod::jsonrpc::offchain::json_list_pays;
 use lampod::jsonrpc::offchain::json_offer;
 use lampod::jsonrpc::offchain::json_pay;
+use lampod::jsonrpc::offchain::json_pay_lnurl;
+use lampod::jsonrpc::offchain::json_settle_invoice;
+use lampod::jsonrpc::offchain::json_sign_message;
+use lampod::jsonrpc::onchain::json_bip21_uri;
+use lampod::jsonrpc::onchain::json_chain_info;
+use lampod::jsonrpc::onchain::json_fee_rates;
 use lampod::jsonrpc::onchain::json_funds;
+use lampod::jsonrpc::onchain::json_list_funds;
+use lampod::jsonrpc::onchain::json_list_transactions;
 use lampod::jsonrpc::onchain::json_new_addr;
+use lampod::jsonrpc::onchain::json_pending_broadcasts;
+use lampod::jsonrpc::onchain::json_pending_bumps;
+use lampod::jsonrpc::onchain::json_unified_receive;
+use lampod::jsonrpc::open_channel::json_estimate_open_cost;
 use lampod::jsonrpc::open_channel::json_open_channel;
 use lampod::jsonrpc::peer_control::json_connect;
+use lampod::jsonrpc::peer_control::json_disconnect;
+use lampod::jsonrpc::peer_control::json_list_peers;
+use lampod::jsonrpc::peer_control::json_ping;
+use lampod::jsonrpc::peer_control::json_send_onion_message;
+use lampod::jsonrpc::peer_control::json_set_peer_list;
+use lampod::jsonrpc::peer_control::json_wait_onion_message;
+use lampod::jsonrpc::plugins::add_plugin_methods;
+use lampod::jsonrpc::plugins::json_list_plugins;
+use lampod::jsonrpc::watchtower::json_add_tower;
+use lampod::jsonrpc::watchtower::json_del_tower_client;
+use lampod::jsonrpc::watchtower::json_list_tower_clients;
+use lampod::jsonrpc::watchtower::json_list_towers;
+use lampod::jsonrpc::watchtower::json_tower_info;
+use lampod::jsonrpc::watchtower::json_tower_status;
 use lampod::jsonrpc::CommandHandler;
 use lampod::LampoDaemon;
 
@@ -61,18 +132,38 @@ macro_rules! wait {
 
 pub struct LampoTesting {
     inner: Arc<LampoHandler>,
+    daemon: Arc<LampoDaemon>,
+    rpc: Arc<Handler<LampoDaemon>>,
+    http_addr: Option<SocketAddr>,
     root_path: Arc<TempDir>,
     pub port: u64,
     pub wallet: Arc<dyn WalletManager>,
     pub mnemonic: String,
     pub btc: Arc<BtcNode>,
     pub info: response::GetInfo,
+    conf: LampoConf,
 }
 
 impl LampoTesting {
     pub fn new(btc: Arc<BtcNode>) -> error::Result<Self> {
+        Self::new_with_conf(btc, |_| {})
+    }
+
+    /// Create a new lampo node, and allow the test to change
+    /// the configuration before the node starts.
+    pub fn new_with_conf<F>(btc: Arc<BtcNode>, callback: F) -> error::Result<Self>
+    where
+        F: FnOnce(&mut LampoConf),
+    {
         let dir = tempfile::tempdir()?;
+        let mut lampo_conf = Self::conf(&btc, &dir)?;
+        callback(&mut lampo_conf);
+        lampo_conf.apply_channel_limits();
+        Self::start(btc, Arc::new(dir), lampo_conf, None)
+    }
 
+    /// The configuration of a node inside `dir`, that uses `btc`.
+    fn conf(btc: &BtcNode, dir: &TempDir) -> error::Result<LampoConf> {
         // SAFETY: this should be safe because if the system has no
         // ports it is a bug
         let port = port::random_free_port().unwrap();
@@ -91,7 +182,103 @@ impl LampoTesting {
             .ldk_conf
             .channel_handshake_limits
             .force_announced_channel_preference = false;
-        let (wallet, mnemonic) = CoreWalletManager::new(Arc::new(lampo_conf.clone()))?;
+        Ok(lampo_conf)
+    }
+
+    /// Stop the node as the `stop` command does, and start it again
+    /// from its directory with the same configuration.
+    pub fn restart(self) -> error::Result<Self> {
+        self.rpc.stop();
+        self.daemon.shutdown(Duration::from_secs(30))?;
+        // the listener is closed within a second from the shutdown
+        std::thread::sleep(Duration::from_secs(2));
+        Self::start(self.btc, self.root_path, self.conf, Some(self.mnemonic))
+    }
+
+    /// Stop the node, run `offline` while it is stopped and start it
+    /// again, like a mobile node that goes offline for a while.
+    pub fn restart_after<F, R>(self, offline: F) -> error::Result<(Self, R)>
+    where
+        F: FnOnce() -> error::Result<R>,
+    {
+        self.rpc.stop();
+        self.daemon.shutdown(Duration::from_secs(30))?;
+        std::thread::sleep(Duration::from_secs(2));
+        let result = offline()?;
+        let node = Self::start(self.btc, self.root_path, self.conf, Some(self.mnemonic))?;
+        Ok((node, result))
+    }
+
+    /// Stop the node, and start its wallet inside an empty directory
+    /// with the configuration changed by `callback`, like a node that
+    /// lost its datadir.
+    pub fn restore_with_conf<F>(self, callback: F) -> error::Result<Self>
+    where
+        F: FnOnce(&mut LampoConf),
+    {
+        self.rpc.stop();
+        self.daemon.shutdown(Duration::from_secs(30))?;
+        std::thread::sleep(Duration::from_secs(2));
+        let dir = tempfile::tempdir()?;
+        let mut lampo_conf = Self::conf(&self.btc, &dir)?;
+        callback(&mut lampo_conf);
+        lampo_conf.apply_channel_limits();
+        Self::start(self.btc, Arc::new(dir), lampo_conf, Some(self.mnemonic))
+    }
+
+    /// Stop the node as the `stop` command does.
+    pub fn stop(self) -> error::Result<()> {
+        self.rpc.stop();
+        self.daemon.shutdown(Duration::from_secs(30))?;
+        std::thread::sleep(Duration::from_secs(2));
+        Ok(())
+    }
+
+    /// Stop the node, copy its datadir and start it again: the copy is
+    /// an old backup of the node, see `start_snapshot`.
+    pub fn snapshot(self) -> error::Result<(Self, TempDir)> {
+        self.rpc.stop();
+        self.daemon.shutdown(Duration::from_secs(30))?;
+        std::thread::sleep(Duration::from_secs(2));
+        let snapshot = tempfile::tempdir()?;
+        copy_dir(self.root_path.path(), snapshot.path())?;
+        let node = Self::start(self.btc, self.root_path, self.conf, Some(self.mnemonic))?;
+        Ok((node, snapshot))
+    }
+
+    /// Start the node from the `snapshot` of its datadir, with the
+    /// configuration changed by `callback`, like a node that does not
+    /// know the channel states after the snapshot.
+    pub fn start_snapshot<F>(&self, snapshot: TempDir, callback: F) -> error::Result<Self>
+    where
+        F: FnOnce(&mut LampoConf),
+    {
+        let mut lampo_conf = Self::conf(&self.btc, &snapshot)?;
+        callback(&mut lampo_conf);
+        lampo_conf.apply_channel_limits();
+        Self::start(
+            self.btc.clone(),
+            Arc::new(snapshot),
+            lampo_conf,
+            Some(self.mnemonic.clone()),
+        )
+    }
+
+    /// Start the node inside `dir`, with a new wallet or with the
+    /// one of the `mnemonic`.
+    fn start(
+        btc: Arc<BtcNode>,
+        dir: Arc<TempDir>,
+        lampo_conf: LampoConf,
+        mnemonic: Option<String>,
+    ) -> error::Result<Self> {
+        let (wallet, mnemonic) = match mnemonic {
+            Some(mnemonic) => (
+                CoreWalletManager::restore(Arc::new(lampo_conf.clone()), &mnemonic)?,
+                mnemonic,
+            ),
+            None => CoreWalletManager::new(Arc::new(lampo_conf.clone()))?,
+        };
         let wallet = Arc::new(wallet);
         let mut lampo = LampoDaemon::new(lampo_conf.clone(), wallet.clone());
         let node = BitcoinCore::new(
@@ -106,29 +293,108 @@ impl LampoTesting {
         // Configuring the JSON RPC over unix
         let lampo = Arc::new(lampo);
         let socket_path = format!("{}/lampod.socket", lampo.root_path());
+        // the socket of the node before the restart
+        let _ = std::fs::remove_file(&socket_path);
         let server = JSONRPCv2::new(lampo.clone(), &socket_path)?;
-        server.add_rpc("getinfo", get_info).unwrap();
+        server.handler().set_auth(authenticate(lampo.rpc_auth()));
+        server.handler().set_observer(observe(lampo.metrics()));
+        server.add_read_rpc("getinfo", get_info).unwrap();
+        server.add_rpc("stop", json_stop).unwrap();
+        server.add_rpc("reload", json_reload).unwrap();
+        server
+            .add_read_rpc("listconfigs", json_list_configs)
+            .unwrap();
+        server.add_rpc("maintenance", json_maintenance).unwrap();
+        server.add_rpc("minttoken", json_mint_token).unwrap();
+        server.add_rpc("revoketoken", json_revoke_token).unwrap();
         server.add_rpc("connect", json_connect).unwrap();
+        server.add_rpc("disconnect", json_disconnect).unwrap();
+        server.add_read_rpc("listpeers", json_list_peers).unwrap();
+        server.add_rpc("ping", json_ping).unwrap();
+        server.add_rpc("setpeerlist", json_set_peer_list).unwrap();
+        server.add_rpc("sendonionmessage", json_send_onion_message).unwrap();
+        server.add_rpc("waitonionmessage", json_wait_onion_message).unwrap();
+        server.add_read_rpc("waitevent", json_wait_event).unwrap();
+        server.add_read_rpc("listwebhookdeliveries", json_list_webhook_deliveries).unwrap();
+        server.add_read_rpc("getmetrics", json_get_metrics).unwrap();
+        server.add_read_rpc("health", json_health).unwrap();
+        server.add_rpc("datastore", json_datastore).unwrap();
+        server.add_read_rpc("listdatastore", json_list_datastore).unwrap();
+        server.add_rpc("deldatastore", json_del_datastore).unwrap();
+        server.add_read_rpc("listplugins", json_list_plugins).unwrap();
+        server.add_rpc("addtower", json_add_tower).unwrap();
+        server.add_read_rpc("listtowers", json_list_towers).unwrap();
+        server.add_read_rpc("towerstatus", json_tower_status).unwrap();
+        server.add_read_rpc("towerinfo", json_tower_info).unwrap();
+        server.add_read_rpc("listtowerclients", json_list_tower_clients).unwrap();
+        server.add_rpc("deltowerclient", json_del_tower_client).unwrap();
+        server.add_rpc("getlog", json_get_log).unwrap();
+        server.add_rpc("setloglevel", json_set_log_level).unwrap();
+        server.add_read_rpc("listnodes", json_list_nodes).unwrap();
+        server.add_read_rpc("listgossipchannels", json_list_gossip_channels).unwrap();
         server.add_rpc("fundchannel", json_open_channel).unwrap();
+        server.add_rpc("estimateopencost", json_estimate_open_cost).unwrap();
         server.add_rpc("newaddr", json_new_addr).unwrap();
-        server.add_rpc("channels", json_list_channels).unwrap();
-        server.add_rpc("funds", json_funds).unwrap();
+        server.add_rpc("getbip21uri", json_bip21_uri).unwrap();
+        server.add_rpc("unifiedreceive", json_unified_receive).unwrap();
+        server.add_read_rpc("channels", json_list_channels).unwrap();
+        server.add_read_rpc("getchannel", json_get_channel).unwrap();
+        server.add_rpc("setchannel", json_set_channel).unwrap();
+        server.add_read_rpc("listhtlcs", json_list_htlcs).unwrap();
+        server.add_read_rpc("autofees-status", json_autofees_status).unwrap();
+        server.add_read_rpc("funds", json_funds).unwrap();
+        server.add_read_rpc("listfunds", json_list_funds).unwrap();
+        server.add_read_rpc("listtransactions", json_list_transactions).unwrap();
+        server.add_read_rpc("feerates", json_fee_rates).unwrap();
+        server.add_read_rpc("getchaininfo", json_chain_info).unwrap();
         server.add_rpc("invoice", json_invoice).unwrap();
+        server.add_rpc("holdinvoice", json_hold_invoice).unwrap();
+        server.add_rpc("settleinvoice", json_settle_invoice).unwrap();
+        server.add_rpc("cancelinvoice", json_cancel_invoice).unwrap();
+        server.add_read_rpc("isinvoicepaid", json_is_invoice_paid).unwrap();
+        server.add_read_rpc("listinvoices", json_list_invoices).unwrap();
         server.add_rpc("offer", json_offer).unwrap();
         server
             .add_rpc("decode_invoice", json_decode_invoice)
             .unwrap();
 
         server.add_rpc("pay", json_pay).unwrap();
+        server.add_rpc("paylnurl", json_pay_lnurl).unwrap();
+        server.add_rpc("cancelpayment", json_cancel_payment).unwrap();
+        server.add_read_rpc("listpays", json_list_pays).unwrap();
+        server.add_rpc("getpaymentroute", json_get_payment_route).unwrap();
         server.add_rpc("keysend", json_keysend).unwrap();
+        server.add_rpc("signmessage", json_sign_message).unwrap();
+        server.add_read_rpc("checkmessage", json_check_message).unwrap();
         server.add_rpc("close", json_close_channel).unwrap();
-        let handler = server.handler();
+        server.add_read_rpc("pendingbumps", json_pending_bumps).unwrap();
+        server
+            .add_read_rpc("pendingbroadcasts", json_pending_broadcasts)
+            .unwrap();
+        if lampo_conf.developer {
+            add_dev_methods(&server.handler());
+        }
+        add_plugin_methods(&server.handler(), &lampo.plugins());
+        if lampo_conf.compat_cln {
+            add_cln_compat(&server.handler());
+        }
+        let rpc = server.handler();
         let rpc_handler = Arc::new(CommandHandler::new(&lampo_conf)?);
-        rpc_handler.set_handler(handler);
+        rpc_handler.set_handler(rpc.clone());
         lampo.add_external_handler(rpc_handler)?;
+        let http_addr = match lampo_conf.http_bind {
+            Some(_) => {
+                let http = HttpServer::new(&lampo_conf, rpc.clone())?;
+                let addr = http.local_addr()?;
+                http.spawn();
+                Some(addr)
+            }
+            None => None,
+        };
 
         // run lampo and take the handler over to run commands
         let handler = lampo.handler();
+        let daemon = lampo.clone();
         std::thread::spawn(move || lampo.listen().unwrap().join());
         // wait that lampo starts
         std::thread::sleep(Duration::from_secs(1));
@@ -136,12 +402,16 @@ impl LampoTesting {
         log::info!("ready for integration testing!");
         Ok(Self {
             inner: handler,
+            daemon,
+            rpc,
+            http_addr,
             mnemonic,
-            port: port.into(),
+            port: lampo_conf.port,
             wallet,
             btc,
-            root_path: Arc::new(dir),
+            root_path: dir,
             info,
+            conf: lampo_conf,
         })
     }
 
@@ -173,7 +443,97 @@ impl LampoTesting {
         self.inner.clone()
     }
 
+    /// The daemon of the node, to reach its managers.
+    pub fn daemon(&self) -> Arc<LampoDaemon> {
+        self.daemon.clone()
+    }
+
+    /// The dispatcher of the JSON RPC 2.0 requests of the clients.
+    pub fn rpc(&self) -> Arc<Handler<LampoDaemon>> {
+        self.rpc.clone()
+    }
+
+    /// The address of the HTTP gateway, when `http_bind` is set.
+    pub fn http_addr(&self) -> Option<SocketAddr> {
+        self.http_addr
+    }
+
     pub fn root_path(&self) -> Arc<TempDir> {
         self.root_path.clone()
     }
 }
+
+/// Fund the wallet of `node1` and open a public channel of `amount`
+/// sat to `node2`, then wait until the channel is ready.
+pub fn open_channel(node1: &LampoTesting, node2: &LampoTesting, amount: u64) -> error::Result<()> {
+    use clightning_testing::prelude::bitcoincore_rpc::RpcApi;
+
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101)?;
+    let height = node1.btc.rpc().get_block_count()?;
+    wait!(|| {
+        while let Ok(event) = events.recv_timeout(Duration::from_millis(100)) {
+            if let Event::OnChain(OnChainEvent::NewBestBlock((_, tip))) = event {
+                if u64::from(tip.to_consensus_u32()) >= height {
+                    return Ok(());
+                }
+            }
+        }
+        Err(())
+    });
+
+    let response: json::Value = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount,
+            public: true,
+            addr: Some("127.0.0.1".to_owned()),
+            port: Some(node2.port),
+        },
+    )?;
+    error::ensure!(response.get("tx").is_some(), "no funding tx in {response}");
+
+    wait!(|| {
+        while let Ok(event) = events.recv_timeout(Duration::from_millis(10)) {
+            node2.fund_wallet(6).unwrap();
+            if let Event::Lightning(LightningEvent::ChannelReady { .. }) = event {
+                return Ok(());
+            };
+        }
+        node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    for (node, peer) in [(node1, node2), (node2, node1)] {
+        wait!(|| {
+            let channels: response::Channels =
+                node.lampod().call("channels", json::json!({})).unwrap();
+            if channels
+                .channels
+                .iter()
+                .any(|channel| channel.peer_id == peer.info.node_id && channel.usable)
+            {
+                return Ok(());
+            }
+            Err(())
+        });
+    }
+    Ok(())
+}
+
+/// Copy the files inside `from` to `to`, the sockets are skipped.
+fn copy_dir(from: &Path, to: &Path) -> error::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let kind = entry.file_type()?;
+        let target = to.join(entry.file_name());
+        if kind.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else if kind.is_file() {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
@@ -1,6 +1,7 @@
 use radicle_term as term;
 
-use lampo_common::conf::{LampoConf, Network};
+use lampo_common::conf;
+use lampo_common::conf::{LampoConf, LampoConfBuilder};
 use lampo_common::error;
 
 struct Help {
@@ -31,65 +32,46 @@ Options
     --core-url         Set the url of the bitcoin core backend
     --core-user        Set the username of the bitcoin core backend
     --core-pass        Set the password of the bitcoin core backend
+    --<option> <value> Set any option of `lampo.conf`, a boolean option
+                       without a value is `true`
     --restore-wallet   Restore a wallet from a mnemonic 
+    --check-config     Check the configuration file and exit
+    --maintenance      Compact the stores and check the channel monitors of
+                       the stopped node, then exit
+    --developer        Serve the `dev-*` methods, only for testing
+    --restore-from-remote
+                       Restore a datadir without channels from the
+                       `remote-storage-url`
+
+Every option can be set also with the environment variable `LAMPO_`
+and the name of the option in upper case, with `_` instead of `-`
+(e.g. `LAMPO_ESPLORA_URL`). The command line wins over the environment,
+that wins over `lampo.conf`.
 "#,
 };
 
 #[derive(Debug)]
 pub struct LampoCliArgs {
     pub data_dir: Option<String>,
-    pub network: Option<String>,
-    pub client: Option<String>,
     pub restore_wallet: bool,
-    pub log_level: Option<String>,
-    pub log_file: Option<String>,
-    pub bitcoind_url: Option<String>,
-    pub bitcoind_user: Option<String>,
-    pub bitcoind_pass: Option<String>,
+    pub check_config: bool,
+    pub maintenance: bool,
+    /// The options of the configuration given on the command line.
+    pub options: Vec<(String, String)>,
 }
 
 impl TryInto<LampoConf> for LampoCliArgs {
     type Error = error::Error;
 
     fn try_into(self) -> Result<LampoConf, Self::Error> {
-        let mut conf = LampoConf::default();
-
-        // if network is not specified, set the testnet dir
-        let network = self.network.unwrap_or(String::from("testnet"));
-        conf.network = match network.as_str() {
-            "bitcoin" => Network::Bitcoin,
-            "testnet" => Network::Testnet,
-            "regtest" => Network::Regtest,
-            "signet" => Network::Signet,
-            _ => error::bail!("Invalid network {network}"),
-        };
-
-        let path = self.data_dir.unwrap_or(conf.root_path);
-        // FIXME: this override the full configuration, we should merge the two
-        conf = LampoConf::new(Some(path), Some(conf.network), None)?;
-        conf.prepare_dirs()?;
-
-        log::debug!(target: "lampod-cli", "lampo data dir `{}`", conf.path());
-        log::debug!(target: "lampod-cli", "client from args {:?}", self.client);
+        let path = self.data_dir.unwrap_or(LampoConf::default().root_path);
+        let mut builder = LampoConfBuilder::new(&path).env(std::env::vars())?;
         // Override the lampo conf with the args from the cli
-        if let Some(node) = self.client {
-            conf.node = node.clone();
-        }
-        if self.bitcoind_url.is_some() {
-            conf.core_url = self.bitcoind_url;
-        }
-        if self.bitcoind_user.is_some() {
-            conf.core_user = self.bitcoind_user;
-        }
-        if self.bitcoind_pass.is_some() {
-            conf.core_pass = self.bitcoind_pass;
-        }
-        if self.log_file.is_some() {
-            conf.log_file = self.log_file;
-        }
-        if self.log_level.is_some() {
-            conf.log_level = self.log_level.unwrap();
+        for (key, value) in &self.options {
+            builder = builder.cli(key, value)?;
         }
+        let conf = builder.build()?;
+        log::debug!(target: "lampod-cli", "lampo data dir `{}`", conf.path());
         Ok(conf)
     }
 }
@@ -98,14 +80,10 @@ pub fn parse_args() -> Result<LampoCliArgs, lexopt::Error> {
     use lexopt::prelude::*;
 
     let mut data_dir: Option<String> = None;
-    let mut log_file: Option<String> = None;
-    let mut level: Option<String> = None;
-    let mut network: Option<String> = None;
-    let mut client: Option<String> = None;
-    let mut bitcoind_url: Option<String> = None;
-    let mut bitcoind_user: Option<String> = None;
-    let mut bitcoind_pass: Option<String> = None;
     let mut restore_wallet = false;
+    let mut check_config = false;
+    let mut maintenance = false;
+    let mut options: Vec<(String, String)> = Vec::new();
 
     let mut parser = lexopt::Parser::from_env();
     while let Some(arg) = parser.next()? {
@@ -114,57 +92,52 @@ pub fn parse_args() -> Result<LampoCliArgs, lexopt::Error> {
                 let val: String = parser.value()?.parse()?;
                 data_dir = Some(val);
             }
-            Long("log-file") => {
-                let val: String = parser.value()?.parse()?;
-                log_file = Some(val);
-            }
-            Long("log-level") => {
+            Short('n') => {
                 let val: String = parser.value()?.parse()?;
-                level = Some(val);
-            }
-            Short('n') | Long("network") => {
-                let val: String = parser.value()?.parse()?;
-                network = Some(val);
+                options.push(("network".to_owned(), val));
             }
             Long("client") => {
                 let var: String = parser.value()?.parse()?;
-                client = Some(var);
+                options.push(("backend".to_owned(), var));
             }
-            Long("core-url") => {
-                let var: String = parser.value()?.parse()?;
-                bitcoind_url = Some(var);
+            Long("restore-wallet") => {
+                restore_wallet = true;
             }
-            Long("core-user") => {
-                let var: String = parser.value()?.parse()?;
-                bitcoind_user = Some(var);
+            Long("check-config") => {
+                check_config = true;
             }
-            Long("core-pass") => {
-                let var: String = parser.value()?.parse()?;
-                bitcoind_pass = Some(var);
+            Long("maintenance") => {
+                maintenance = true;
             }
-            Long("restore-wallet") => {
-                restore_wallet = true;
+            Long("restore-from-remote") => {
+                options.push(("remote-storage-restore".to_owned(), "true".to_owned()));
             }
             Long("help") => {
                 let _ = print_help();
                 std::process::exit(0);
             }
+            Long(option) if conf::check::options().any(|known| known == option) => {
+                let option = option.to_owned();
+                let val: String = if conf::check::is_bool_option(&option) {
+                    match parser.optional_value() {
+                        Some(val) => val.parse()?,
+                        None => "true".to_owned(),
+                    }
+                } else {
+                    parser.value()?.parse()?
+                };
+                options.push((option, val));
+            }
             _ => return Err(arg.unexpected()),
         }
     }
 
     Ok(LampoCliArgs {
         data_dir,
-        network,
-        client,
         restore_wallet,
-        log_file,
-        bitcoind_url,
-        bitcoind_pass,
-        bitcoind_user,
-        // Default log level is info if it is not specified
-        // in the command line
-        log_level: level,
+        check_config,
+        maintenance,
+        options,
     })
 }
 
@@ -7,32 +7,101 @@ use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::thread::JoinHandle;
+use std::time::Duration;
 
 use radicle_term as term;
 
 use lampo_bitcoind::BitcoinCore;
-use lampo_common::backend::Backend;
+#[cfg(feature = "bip157")]
+use lampo_bdk_wallet::BDKWalletManager;
+use lampo_common::backend::{Backend, BackendKind};
 use lampo_common::conf::LampoConf;
 use lampo_common::error;
+use lampo_common::keys::KeysScheme;
 use lampo_common::logger;
+use lampo_common::seed::SeedStore;
+#[cfg(feature = "test-utils")]
+use lampo_common::wallet::memory::MemoryWallet;
+use lampo_common::wallet::{build_wallet, WalletFactory};
 use lampo_core_wallet::CoreWalletManager;
 use lampo_jsonrpc::Handler;
 use lampo_jsonrpc::JSONRPCv2;
 use lampod::chain::WalletManager;
+use lampod::http::HttpServer;
+use lampod::jsonrpc::auth::authenticate;
+use lampod::jsonrpc::auth::json_mint_token;
+use lampod::jsonrpc::auth::json_revoke_token;
+use lampod::jsonrpc::channels::json_autofees_status;
 use lampod::jsonrpc::channels::json_close_channel;
+use lampod::jsonrpc::channels::json_get_channel;
 use lampod::jsonrpc::channels::json_list_channels;
+use lampod::jsonrpc::channels::json_list_htlcs;
+use lampod::jsonrpc::channels::json_set_channel;
+use lampod::jsonrpc::compat::add_cln_compat;
+use lampod::jsonrpc::datastore::json_datastore;
+use lampod::jsonrpc::datastore::json_del_datastore;
+use lampod::jsonrpc::datastore::json_list_datastore;
+use lampod::jsonrpc::dev::add_dev_methods;
+use lampod::jsonrpc::events::json_list_webhook_deliveries;
+use lampod::jsonrpc::events::json_wait_event;
+use lampod::jsonrpc::gossip::json_list_gossip_channels;
+use lampod::jsonrpc::gossip::json_list_nodes;
+use lampod::jsonrpc::health::json_health;
 use lampod::jsonrpc::inventory::get_info;
+use lampod::jsonrpc::inventory::json_list_configs;
+use lampod::jsonrpc::inventory::json_reload;
+use lampod::jsonrpc::inventory::json_stop;
+use lampod::jsonrpc::logs::json_get_log;
+use lampod::jsonrpc::logs::json_set_log_level;
+use lampod::jsonrpc::maintenance::json_maintenance;
+use lampod::jsonrpc::metrics::json_get_metrics;
+use lampod::jsonrpc::metrics::observe;
+use lampod::jsonrpc::offchain::json_cancel_invoice;
+use lampod::jsonrpc::offchain::json_cancel_payment;
+use lampod::jsonrpc::offchain::json_check_message;
 use lampod::jsonrpc::offchain::json_decode_invoice;
+use lampod::jsonrpc::offchain::json_get_payment_route;
+use lampod::jsonrpc::offchain::json_hold_invoice;
 use lampod::jsonrpc::offchain::json_invoice;
+use lampod::jsonrpc::offchain::json_is_invoice_paid;
 use lampod::jsonrpc::offchain::json_keysend;
+use lampod::jsonrpc::offchain::json_list_invoices;
+use lampod::jsonrpc::offchain::json_list_pays;
 use lampod::jsonrpc::offchain::json_offer;
 use lampod::jsonrpc::offchain::json_pay;
+use lampod::jsonrpc::offchain::json_pay_lnurl;
+use lampod::jsonrpc::offchain::json_settle_invoice;
+use lampod::jsonrpc::offchain::json_sign_message;
+use lampod::jsonrpc::onchain::json_bip21_uri;
+use lampod::jsonrpc::onchain::json_chain_info;
 use lampod::jsonrpc::onchain::json_estimate_fees;
+use lampod::jsonrpc::onchain::json_fee_rates;
 use lampod::jsonrpc::onchain::json_funds;
+use lampod::jsonrpc::onchain::json_list_funds;
+use lampod::jsonrpc::onchain::json_list_transactions;
 use lampod::jsonrpc::onchain::json_new_addr;
+use lampod::jsonrpc::onchain::json_pending_broadcasts;
+use lampod::jsonrpc::onchain::json_pending_bumps;
+use lampod::jsonrpc::onchain::json_unified_receive;
+use lampod::jsonrpc::open_channel::json_estimate_open_cost;
 use lampod::jsonrpc::open_channel::json_open_channel;
 use lampod::jsonrpc::peer_control::json_connect;
+use lampod::jsonrpc::peer_control::json_disconnect;
+use lampod::jsonrpc::peer_control::json_list_peers;
+use lampod::jsonrpc::peer_control::json_ping;
+use lampod::jsonrpc::peer_control::json_set_peer_list;
+use lampod::jsonrpc::peer_control::json_send_onion_message;
+use lampod::jsonrpc::peer_control::json_wait_onion_message;
+use lampod::jsonrpc::plugins::add_plugin_methods;
+use lampod::jsonrpc::plugins::json_list_plugins;
+use lampod::jsonrpc::watchtower::json_add_tower;
+use lampod::jsonrpc::watchtower::json_del_tower_client;
+use lampod::jsonrpc::watchtower::json_list_tower_clients;
+use lampod::jsonrpc::watchtower::json_list_towers;
+use lampod::jsonrpc::watchtower::json_tower_info;
+use lampod::jsonrpc::watchtower::json_tower_status;
 use lampod::jsonrpc::CommandHandler;
+use lampod::persistence::{LampoMaintenance, LampoPersistence};
 use lampod::LampoDaemon;
 
 use crate::args::LampoCliArgs;
@@ -46,6 +115,15 @@ fn main() -> error::Result<()> {
 
 /// Return the root directory.
 fn run(args: LampoCliArgs) -> error::Result<()> {
+    if args.check_config {
+        let lampo_conf: LampoConf = args.try_into()?;
+        radicle_term::println(
+            radicle_term::format::badge_primary("config"),
+            format!("the configuration of `{}` is valid", lampo_conf.path()),
+        );
+        return Ok(());
+    }
+    let maintenance = args.maintenance;
     let mnemonic = if args.restore_wallet {
         let inputs: String = term::input(
             "BIP 39 Mnemonic",
@@ -61,8 +139,9 @@ fn run(args: LampoCliArgs) -> error::Result<()> {
     let mut lampo_conf: LampoConf = args.try_into()?;
     log::debug!(target: "lampod-cli", "init wallet ..");
     // init the logger here
-    logger::init(
+    logger::init_with_targets(
         &lampo_conf.log_level,
+        &lampo_conf.log_targets,
         lampo_conf
             .log_file
             .as_ref()
@@ -94,41 +173,52 @@ fn run(args: LampoCliArgs) -> error::Result<()> {
             Arc::new(false),
             Some(60),
         )?),
+        #[cfg(feature = "bip157")]
+        "bip157" => Arc::new(lampo_bip157::Bip157::new(&lampo_conf)?),
         _ => error::bail!("client {:?} not supported", client),
     };
 
-    let wallet = if let Some(ref _private_key) = lampo_conf.private_key {
-        unimplemented!()
-    } else if mnemonic.is_none() {
-        let (wallet, mnemonic) = match client.kind() {
-            lampo_common::backend::BackendKind::Core => {
-                CoreWalletManager::new(Arc::new(lampo_conf.clone()))?
-            }
-            lampo_common::backend::BackendKind::Nakamoto => {
-                error::bail!("wallet is not implemented for nakamoto")
-            }
-        };
+    // a restored mnemonic is stored, otherwise the one stored by a
+    // previous start is used.
+    let seed_store = SeedStore::new(&lampo_conf.path());
+    let mnemonic = match mnemonic {
+        Some(mnemonic) => {
+            KeysScheme::load_or_init_imported(&lampo_conf.path(), lampo_conf.keys_scheme)?;
+            seed_store.store(&mnemonic)?;
+            Some(mnemonic)
+        }
+        None => seed_store.load_if_exists()?,
+    };
 
-        radicle_term::success!("Wallet Generated, please store these words in a safe way");
-        radicle_term::println(
-            radicle_term::format::badge_primary("wallet-keys"),
-            format!("{}", radicle_term::format::highlight(mnemonic)),
-        );
-        wallet
+    // the wallet of the backend, unless the configuration chooses another one
+    let default_wallet = match client.kind() {
+        BackendKind::Core => Some("core"),
+        BackendKind::Bip157 => Some("bdk"),
+        BackendKind::Nakamoto => None,
+    };
+    let wallet: Arc<dyn WalletManager> = if let Some(ref _private_key) = lampo_conf.private_key {
+        unimplemented!()
     } else {
-        match client.kind() {
-            lampo_common::backend::BackendKind::Core => {
-                // SAFETY: It is safe to unwrap the mnemonic because we check it
-                // before.
-                CoreWalletManager::restore(Arc::new(lampo_conf.clone()), &mnemonic.unwrap())?
-            }
-            lampo_common::backend::BackendKind::Nakamoto => {
-                error::bail!("wallet is not implemented for nakamoto")
-            }
+        let (wallet, words) = wallet_factory(client.clone()).build(
+            Arc::new(lampo_conf.clone()),
+            default_wallet,
+            mnemonic.as_deref(),
+        )?;
+        if mnemonic.is_none() {
+            seed_store.store(&words)?;
+            radicle_term::success!("Wallet Generated, please store these words in a safe way");
+            radicle_term::println(
+                radicle_term::format::badge_primary("wallet-keys"),
+                format!("{}", radicle_term::format::highlight(words)),
+            );
         }
+        wallet
     };
     log::debug!(target: "lampod-cli", "wallet created with success");
-    let mut lampod = LampoDaemon::new(lampo_conf.clone(), Arc::new(wallet));
+    if maintenance {
+        return run_maintenance(&lampo_conf, wallet);
+    }
+    let mut lampod = LampoDaemon::new(lampo_conf.clone(), wallet);
 
     // Init the lampod
     lampod.init(client)?;
@@ -144,21 +234,87 @@ fn run(args: LampoCliArgs) -> error::Result<()> {
         })?;
 
     let lampod = Arc::new(lampod);
-    let (jsorpc_worker, handler) = run_jsonrpc(lampod.clone()).unwrap();
+    let (_jsonrpc_worker, handler) = run_jsonrpc(lampod.clone()).unwrap();
     rpc_handler.set_handler(handler.clone());
+    let _http_worker = if lampo_conf.http_bind.is_some() {
+        Some(HttpServer::new(&lampo_conf, handler.clone())?.spawn())
+    } else {
+        None
+    };
 
-    ctrlc::set_handler(move || {
-        use std::time::Duration;
-        log::info!("Shutdown...");
-        handler.stop();
-        std::thread::sleep(Duration::from_secs(5));
-        std::process::exit(0);
-    })?;
+    // SIGINT and SIGTERM ask for the same shutdown of the `stop` command
+    let daemon = lampod.clone();
+    ctrlc::set_handler(move || daemon.request_stop())?;
 
-    let workder = lampod.listen().unwrap();
+    let _ = lampod.clone().listen()?;
     log::info!(target: "lampod-cli", "------------ Starting Server ------------");
-    let _ = workder.join();
-    let _ = jsorpc_worker.join().unwrap();
+    lampod.wait_stop_request();
+
+    log::info!(target: "lampod-cli", "Shutdown...");
+    // stop accepting commands, the one in progress can end
+    handler.stop();
+    let timeout = Duration::from_secs(lampo_conf.shutdown_timeout_secs);
+    if let Err(err) = lampod.shutdown(timeout) {
+        log::error!(target: "lampod-cli", "{err}");
+        std::process::exit(1);
+    }
+    log::info!(target: "lampod-cli", "lampod stopped, bye!");
+    Ok(())
+}
+
+/// The wallets that lampod can run with, see the `wallet` option.
+fn wallet_factory(client: Arc<dyn Backend>) -> WalletFactory {
+    let mut wallets = WalletFactory::new();
+    wallets.register("core", build_wallet::<CoreWalletManager>);
+    #[cfg(feature = "test-utils")]
+    wallets.register("memory", build_wallet::<MemoryWallet>);
+    #[cfg(feature = "bip157")]
+    wallets.register("bdk", move |conf, mnemonic| {
+        let (wallet, words) = build_wallet::<BDKWalletManager>(conf, mnemonic)?;
+        // a new wallet has nothing to find before the current tip.
+        if mnemonic.is_none() && wallet.get_birthday()?.is_none() {
+            match client.get_best_block() {
+                Ok((_, Some(height))) => wallet.set_birthday(height)?,
+                Ok((_, None)) => {}
+                Err(err) => {
+                    log::warn!(target: "lampod-cli", "impossible set the wallet birthday: {err}")
+                }
+            }
+        }
+        Ok((wallet, words))
+    });
+    #[cfg(not(feature = "bip157"))]
+    let _ = client;
+    wallets
+}
+
+/// Run the maintenance of the stores while the node is stopped.
+fn run_maintenance(lampo_conf: &LampoConf, wallet: Arc<dyn WalletManager>) -> error::Result<()> {
+    // the pid lock tells us that the node is not running
+    let _pid = filelock_rs::pid::Pid::new(lampo_conf.path(), "lampod".to_owned()).map_err(|err| {
+        log::error!("{err}");
+        error::anyhow!("impossible take a lock on the `lampod.pid` file, stop the node before the maintenance")
+    })?;
+    let persister = Arc::new(LampoPersistence::new(lampo_conf.path().into()));
+    let report = LampoMaintenance::new(persister, wallet).run()?;
+    for store in &report.stores {
+        radicle_term::println(
+            radicle_term::format::badge_primary("maintenance"),
+            format!(
+                "{} `{}` from {} to {} bytes",
+                store.name, store.path, store.bytes_before, store.bytes_after
+            ),
+        );
+    }
+    for file in &report.corrupted {
+        radicle_term::println(
+            radicle_term::format::badge_negative("corrupted"),
+            format!("{} `{}`: {}", file.name, file.path, file.error),
+        );
+    }
+    if !report.corrupted.is_empty() {
+        error::bail!("{} corrupted files found", report.corrupted.len());
+    }
     Ok(())
 }
 
@@ -171,20 +327,98 @@ fn run_jsonrpc(
     // that it is running.
     let _ = std::fs::remove_file(socket_path.clone());
     env::set_var("LAMPO_UNIX", socket_path.clone());
+    let auth = lampod.rpc_auth();
+    let metrics = lampod.metrics();
+    let plugins = lampod.plugins();
+    let compat_cln = lampod.conf().compat_cln;
+    let developer = lampod.conf().developer;
     let server = JSONRPCv2::new(lampod, &socket_path)?;
-    server.add_rpc("getinfo", get_info).unwrap();
+    server.handler().set_auth(authenticate(auth));
+    server.handler().set_observer(observe(metrics));
+    server.add_read_rpc("getinfo", get_info).unwrap();
+    server.add_rpc("stop", json_stop).unwrap();
+    server.add_rpc("reload", json_reload).unwrap();
+    server
+        .add_read_rpc("listconfigs", json_list_configs)
+        .unwrap();
+    server.add_rpc("maintenance", json_maintenance).unwrap();
+    server.add_rpc("minttoken", json_mint_token).unwrap();
+    server.add_rpc("revoketoken", json_revoke_token).unwrap();
     server.add_rpc("connect", json_connect).unwrap();
+    server.add_rpc("disconnect", json_disconnect).unwrap();
+    server.add_read_rpc("listpeers", json_list_peers).unwrap();
+    server.add_rpc("ping", json_ping).unwrap();
+    server.add_rpc("setpeerlist", json_set_peer_list).unwrap();
+    server.add_rpc("sendonionmessage", json_send_onion_message).unwrap();
+    server.add_rpc("waitonionmessage", json_wait_onion_message).unwrap();
+    server.add_read_rpc("waitevent", json_wait_event).unwrap();
+    server.add_read_rpc("listwebhookdeliveries", json_list_webhook_deliveries).unwrap();
+    server.add_read_rpc("getmetrics", json_get_metrics).unwrap();
+    server.add_read_rpc("health", json_health).unwrap();
+    server.add_rpc("datastore", json_datastore).unwrap();
+    server.add_read_rpc("listdatastore", json_list_datastore).unwrap();
+    server.add_rpc("deldatastore", json_del_datastore).unwrap();
+    server.add_read_rpc("listplugins", json_list_plugins).unwrap();
+    server.add_rpc("addtower", json_add_tower).unwrap();
+    server.add_read_rpc("listtowers", json_list_towers).unwrap();
+    server.add_read_rpc("towerstatus", json_tower_status).unwrap();
+    server.add_read_rpc("towerinfo", json_tower_info).unwrap();
+    server
+        .add_read_rpc("listtowerclients", json_list_tower_clients)
+        .unwrap();
+    server
+        .add_rpc("deltowerclient", json_del_tower_client)
+        .unwrap();
+    server.add_rpc("getlog", json_get_log).unwrap();
+    server.add_rpc("setloglevel", json_set_log_level).unwrap();
+    server.add_read_rpc("listnodes", json_list_nodes).unwrap();
+    server.add_read_rpc("listgossipchannels", json_list_gossip_channels).unwrap();
     server.add_rpc("fundchannel", json_open_channel).unwrap();
+    server.add_rpc("estimateopencost", json_estimate_open_cost).unwrap();
     server.add_rpc("newaddr", json_new_addr).unwrap();
-    server.add_rpc("channels", json_list_channels).unwrap();
-    server.add_rpc("funds", json_funds).unwrap();
+    server.add_rpc("getbip21uri", json_bip21_uri).unwrap();
+    server.add_rpc("unifiedreceive", json_unified_receive).unwrap();
+    server.add_read_rpc("channels", json_list_channels).unwrap();
+    server.add_read_rpc("getchannel", json_get_channel).unwrap();
+    server.add_rpc("setchannel", json_set_channel).unwrap();
+    server.add_read_rpc("listhtlcs", json_list_htlcs).unwrap();
+    server.add_read_rpc("autofees-status", json_autofees_status).unwrap();
+    server.add_read_rpc("funds", json_funds).unwrap();
+    server.add_read_rpc("listfunds", json_list_funds).unwrap();
+    server.add_read_rpc("listtransactions", json_list_transactions).unwrap();
     server.add_rpc("invoice", json_invoice).unwrap();
+    server.add_rpc("holdinvoice", json_hold_invoice).unwrap();
+    server.add_rpc("settleinvoice", json_settle_invoice).unwrap();
+    server.add_rpc("cancelinvoice", json_cancel_invoice).unwrap();
+    server.add_read_rpc("isinvoicepaid", json_is_invoice_paid).unwrap();
+    server.add_read_rpc("listinvoices", json_list_invoices).unwrap();
     server.add_rpc("offer", json_offer).unwrap();
-    server.add_rpc("decode", json_decode_invoice).unwrap();
+    server.add_read_rpc("decode", json_decode_invoice).unwrap();
     server.add_rpc("pay", json_pay).unwrap();
+    server.add_rpc("paylnurl", json_pay_lnurl).unwrap();
+    server.add_rpc("cancelpayment", json_cancel_payment).unwrap();
+    server.add_read_rpc("listpays", json_list_pays).unwrap();
+    server.add_rpc("getpaymentroute", json_get_payment_route).unwrap();
     server.add_rpc("keysend", json_keysend).unwrap();
-    server.add_rpc("fees", json_estimate_fees).unwrap();
+    server.add_rpc("signmessage", json_sign_message).unwrap();
+    server.add_read_rpc("checkmessage", json_check_message).unwrap();
+    server.add_read_rpc("fees", json_estimate_fees).unwrap();
+    server.add_read_rpc("feerates", json_fee_rates).unwrap();
+    server.add_read_rpc("getchaininfo", json_chain_info).unwrap();
     server.add_rpc("close", json_close_channel).unwrap();
+    server.add_read_rpc("pendingbumps", json_pending_bumps).unwrap();
+    server
+        .add_read_rpc("pendingbroadcasts", json_pending_broadcasts)
+        .unwrap();
+    if developer {
+        log::warn!(target: "lampod-cli", "serving the `dev-*` methods, do not use this node with real funds");
+        add_dev_methods(&server.handler());
+    }
+    // after the methods of lampo, so a plugin can not replace them
+    add_plugin_methods(&server.handler(), &plugins);
+    if compat_cln {
+        add_cln_compat(&server.handler());
+    }
     let handler = server.handler();
     Ok((server.spawn(), handler))
 }
@@ -1,5 +1,8 @@
 //! Actions crate implementation
+pub mod event_bus;
+pub mod event_journal;
 pub mod handler;
+pub mod webhooks;
 
 use crossbeam_channel as chan;
 
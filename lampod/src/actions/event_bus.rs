@@ -0,0 +1,217 @@
+//! Bus of the events notified to the users.
+//!
+//! The events emitted by the handler are numbered with an increasing
+//! id and kept inside a bounded buffer, so a client can wait for the
+//! next events and resume the stream from the last id that it saw.
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use lampo_common::chan;
+use lampo_common::error;
+use lampo_common::event::ln::LightningEvent;
+use lampo_common::event::onchain::OnChainEvent;
+use lampo_common::event::Event;
+use lampo_common::json;
+use lampo_common::model::response::{self, PaymentState};
+
+/// How many events are kept for the clients that are behind.
+pub const EVENT_BUFFER_SIZE: usize = 1024;
+
+#[derive(Default)]
+struct EventBuffer {
+    events: VecDeque<response::NotifiedEvent>,
+    last_id: u64,
+}
+
+pub struct LampoEventBus {
+    buffer: Mutex<EventBuffer>,
+    notify: Condvar,
+    capacity: usize,
+}
+
+impl Default for LampoEventBus {
+    fn default() -> Self {
+        Self::new(EVENT_BUFFER_SIZE)
+    }
+}
+
+impl LampoEventBus {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            buffer: Mutex::new(EventBuffer::default()),
+            notify: Condvar::new(),
+            capacity,
+        }
+    }
+
+    /// Spawn the task that publishes the events received from `events`.
+    pub fn start(self: Arc<Self>, events: chan::Receiver<Event>) -> JoinHandle<()> {
+        std::thread::spawn(move || {
+            while let Ok(event) = events.recv() {
+                if let Some((kind, data)) = Self::notification(&event) {
+                    self.publish(kind, data);
+                }
+            }
+            log::warn!(target: "lampo", "events channel closed, stop notifying the events");
+        })
+    }
+
+    /// The kind and the content of the event notified to the users,
+    /// `None` for the internal events.
+    fn notification(event: &Event) -> Option<(&'static str, json::Value)> {
+        let notification = match event {
+            Event::Lightning(LightningEvent::PeerConnect {
+                counterparty_node_id,
+            }) => (
+                "peer_connected",
+                json::json!({ "node_id": counterparty_node_id.to_string() }),
+            ),
+            Event::Lightning(LightningEvent::PeerDisconnect {
+                counterparty_node_id,
+            }) => (
+                "peer_disconnected",
+                json::json!({ "node_id": counterparty_node_id.to_string() }),
+            ),
+            Event::Lightning(LightningEvent::ChannelPending {
+                counterparty_node_id,
+                funding_transaction,
+            }) => (
+                "channel_opened",
+                json::json!({
+                    "node_id": counterparty_node_id.to_string(),
+                    "funding_txo": funding_transaction.to_string(),
+                }),
+            ),
+            Event::Lightning(LightningEvent::ChannelReady {
+                counterparty_node_id,
+                channel_id,
+                ..
+            }) => (
+                "channel_ready",
+                json::json!({
+                    "node_id": counterparty_node_id.to_string(),
+                    "channel_id": channel_id.to_string(),
+                }),
+            ),
+            Event::Lightning(LightningEvent::CloseChannelEvent {
+                channel_id,
+                message,
+                counterparty_node_id,
+                funding_utxo,
+            }) => (
+                "channel_closed",
+                json::json!({
+                    "channel_id": channel_id,
+                    "reason": message,
+                    "node_id": counterparty_node_id,
+                    "funding_txo": funding_utxo,
+                }),
+            ),
+            Event::Lightning(LightningEvent::PaymentEvent {
+                state,
+                payment_hash,
+                path,
+            }) => match state {
+                PaymentState::Success => (
+                    "payment_sent",
+                    json::json!({ "payment_hash": payment_hash, "path": path }),
+                ),
+                PaymentState::Faulure => (
+                    "payment_failed",
+                    json::json!({ "payment_hash": payment_hash }),
+                ),
+                PaymentState::Pending => return None,
+            },
+            Event::Lightning(LightningEvent::PaymentReceived {
+                payment_hash,
+                amount_msat,
+            }) => (
+                "payment_received",
+                json::json!({ "payment_hash": payment_hash, "amount_msat": amount_msat }),
+            ),
+            Event::Lightning(LightningEvent::HoldInvoiceAccepted {
+                payment_hash,
+                amount_msat,
+            }) => (
+                "invoice_accepted",
+                json::json!({ "payment_hash": payment_hash, "amount_msat": amount_msat }),
+            ),
+            Event::OnChain(OnChainEvent::WalletSynced(height)) => (
+                "wallet_synced",
+                json::json!({ "height": height.to_consensus_u32() }),
+            ),
+            _ => return None,
+        };
+        Some(notification)
+    }
+
+    /// Give the next id to the event and notify the clients that
+    /// are waiting, the oldest event is dropped when the buffer is full.
+    pub fn publish(&self, kind: &str, data: json::Value) -> u64 {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.last_id += 1;
+        let id = buffer.last_id;
+        log::debug!(target: "lampo", "notify event `{id}` of kind `{kind}`");
+        buffer.events.push_back(response::NotifiedEvent {
+            id,
+            kind: kind.to_owned(),
+            data,
+        });
+        if buffer.events.len() > self.capacity {
+            buffer.events.pop_front();
+        }
+        self.notify.notify_all();
+        id
+    }
+
+    /// Wait up to `timeout` for the events after `last_id` of one of
+    /// `kinds` (any kind when empty), the result is empty if none arrives.
+    pub fn wait(
+        &self,
+        last_id: Option<u64>,
+        kinds: &[String],
+        timeout: Duration,
+    ) -> error::Result<response::NotifiedEvents> {
+        let deadline = Instant::now() + timeout;
+        let mut buffer = self.buffer.lock().unwrap();
+        let mut last_id = match last_id {
+            Some(last_id) => last_id,
+            None => buffer
+                .events
+                .front()
+                .map_or(buffer.last_id, |event| event.id - 1),
+        };
+        if last_id > buffer.last_id {
+            error::bail!(
+                "unknown event `{last_id}`, the last event is `{}`",
+                buffer.last_id
+            );
+        }
+        if let Some(oldest) = buffer.events.front() {
+            if last_id + 1 < oldest.id {
+                error::bail!(
+                    "the events after `{last_id}` are dropped, the oldest event kept is `{}`",
+                    oldest.id
+                );
+            }
+        }
+        loop {
+            let events = buffer
+                .events
+                .iter()
+                .filter(|event| event.id > last_id)
+                .filter(|event| kinds.is_empty() || kinds.contains(&event.kind))
+                .cloned()
+                .collect::<Vec<_>>();
+            // the events filtered out are skipped by the next call
+            last_id = buffer.last_id;
+            let now = Instant::now();
+            if !events.is_empty() || now >= deadline {
+                return Ok(response::NotifiedEvents { events, last_id });
+            }
+            buffer = self.notify.wait_timeout(buffer, deadline - now).unwrap().0;
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! Journal of the ldk events, between ldk and the handler.
+//!
+//! ldk forgets an event as soon as the background processor gives it
+//! to us, so a crash while the event is handled loses it, and a
+//! channel manager persisted before the event was given makes ldk
+//! give it again after the restart. The events are written on the
+//! disk before they are queued and removed when the handler is done
+//! with them, the events left in the journal are handled again when
+//! the node starts.
+//!
+//! The events that happen once for a payment or for a channel are
+//! remembered for a while after they are handled, so the copies given
+//! again by ldk are skipped.
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::io::Cursor;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use lampo_common::bitcoin::hashes::hex::{FromHex, ToHex};
+use lampo_common::bitcoin::hashes::{sha256, Hash};
+use lampo_common::chan;
+use lampo_common::error;
+use lampo_common::json;
+use lampo_common::ldk::events::Event;
+use lampo_common::ldk::util::persist::KVStore;
+use lampo_common::ldk::util::ser::{MaybeReadable, Writeable};
+use lampo_common::sync::MutexExt;
+
+use crate::actions::handler::LampoHandler;
+use crate::actions::Handler;
+use crate::persistence::LampoPersistence;
+
+const LAMPO_NAMESPACE: &str = "lampo";
+const EVENT_JOURNAL_KEY: &str = "event_journal";
+/// How many handled events are remembered to skip the copies given
+/// again by ldk.
+pub const COMPLETED_EVENTS: usize = 1024;
+
+struct Entry {
+    /// The key of the events that happen once, see `event_key`.
+    key: Option<sha256::Hash>,
+    event: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Journal {
+    next_id: u64,
+    /// The events not handled yet, by the order of arrival.
+    pending: BTreeMap<u64, Entry>,
+    completed: VecDeque<sha256::Hash>,
+}
+
+type Queue = (chan::Sender<(u64, Event)>, chan::Receiver<(u64, Event)>);
+
+pub struct LampoEventJournal {
+    persister: Arc<LampoPersistence>,
+    journal: Mutex<Journal>,
+    queue: Queue,
+    /// Set by `halt_before_completion`.
+    halt: AtomicBool,
+}
+
+impl LampoEventJournal {
+    /// A journal that keeps in memory at most `capacity` events.
+    pub fn new(persister: Arc<LampoPersistence>, capacity: usize) -> Self {
+        let journal = Self::read(&persister).unwrap_or_else(|err| {
+            log::error!(target: "lampo", "impossible read the journal of the events: {err}");
+            Journal::default()
+        });
+        Self {
+            persister,
+            journal: Mutex::new(journal),
+            queue: chan::bounded(capacity),
+            halt: AtomicBool::new(false),
+        }
+    }
+
+    fn read(persister: &LampoPersistence) -> error::Result<Journal> {
+        let buf = match persister.read(LAMPO_NAMESPACE, "", EVENT_JOURNAL_KEY) {
+            Ok(buf) => buf,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Journal::default()),
+            Err(err) => return Err(err.into()),
+        };
+        let (next_id, pending, completed): (u64, Vec<(u64, Option<String>, String)>, Vec<String>) =
+            json::from_slice(&buf)?;
+        let pending = pending
+            .into_iter()
+            .map(|(id, key, event)| {
+                let entry = Entry {
+                    key: key.map(|key| key.parse()).transpose()?,
+                    event: Vec::<u8>::from_hex(&event)?,
+                };
+                Ok((id, entry))
+            })
+            .collect::<error::Result<_>>()?;
+        let completed = completed
+            .into_iter()
+            .map(|key| Ok(key.parse()?))
+            .collect::<error::Result<_>>()?;
+        Ok(Journal {
+            next_id,
+            pending,
+            completed,
+        })
+    }
+
+    fn write(&self, journal: &Journal) -> error::Result<()> {
+        let pending = journal
+            .pending
+            .iter()
+            .map(|(id, entry)| {
+                (
+                    *id,
+                    entry.key.map(|key| key.to_string()),
+                    entry.event.to_hex(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let completed = journal
+            .completed
+            .iter()
+            .map(|key| key.to_string())
+            .collect::<Vec<_>>();
+        let buf = json::to_vec(&(journal.next_id, pending, completed))?;
+        self.persister
+            .write(LAMPO_NAMESPACE, "", EVENT_JOURNAL_KEY, &buf)?;
+        Ok(())
+    }
+
+    /// Write `event` in the journal and queue it for the handler, it
+    /// waits while the queue is full so ldk does not give us more
+    /// events than we can handle.
+    pub fn push(&self, event: Event, stopped: &AtomicBool) {
+        let buf = event.encode();
+        let key = event_key(&event, &buf);
+        let id = {
+            let mut journal = self.journal.lock_or_recover();
+            if let Some(key) = key {
+                let seen = journal.completed.contains(&key)
+                    || journal.pending.values().any(|entry| entry.key == Some(key));
+                if seen {
+                    log::info!(target: "lampo", "skipping the ldk event {:?}, it was already handled", event);
+                    return;
+                }
+            }
+            let id = journal.next_id;
+            journal.next_id += 1;
+            journal.pending.insert(id, Entry { key, event: buf });
+            // the event is handled anyway, it is lost only by a crash
+            if let Err(err) = self.write(&journal) {
+                log::error!(target: "lampo", "impossible journal the ldk event {id}: {err}");
+            }
+            id
+        };
+        let mut item = (id, event);
+        loop {
+            match self.queue.0.send_timeout(item, Duration::from_secs(1)) {
+                Ok(()) => break,
+                // the event stays in the journal for the next start
+                Err(_) if stopped.load(Ordering::SeqCst) => break,
+                Err(chan::SendTimeoutError::Timeout(back)) => item = back,
+                Err(chan::SendTimeoutError::Disconnected(_)) => break,
+            }
+        }
+    }
+
+    /// Spawn the task that handles the events left by the last run,
+    /// then the events queued by `push`.
+    pub fn start(
+        self: Arc<Self>,
+        handler: Arc<LampoHandler>,
+        stopped: Arc<AtomicBool>,
+    ) -> JoinHandle<()> {
+        std::thread::spawn(move || {
+            let left = {
+                let journal = self.journal.lock_or_recover();
+                journal
+                    .pending
+                    .iter()
+                    .map(|(id, entry)| (*id, entry.event.clone()))
+                    .collect::<Vec<_>>()
+            };
+            if !left.is_empty() {
+                log::info!(target: "lampo", "handling again {} ldk events left by the last run", left.len());
+            }
+            for (id, buf) in left {
+                match Event::read(&mut Cursor::new(&buf)) {
+                    Ok(Some(event)) => {
+                        if !self.handle(&handler, id, event) {
+                            return;
+                        }
+                    }
+                    // ldk does not write the events that it gives
+                    // again on its own, like `FundingGenerationReady`
+                    Ok(None) => self.complete(id),
+                    Err(err) => {
+                        log::error!(target: "lampo", "impossible read the ldk event {id}: {err:?}");
+                        self.complete(id);
+                    }
+                }
+            }
+            while !stopped.load(Ordering::SeqCst) {
+                let Ok((id, event)) = self.queue.1.recv_timeout(Duration::from_secs(1)) else {
+                    continue;
+                };
+                if !self.handle(&handler, id, event) {
+                    return;
+                }
+            }
+        })
+    }
+
+    /// Handle the event `id`, false when the queue is halted.
+    fn handle(&self, handler: &LampoHandler, id: u64, event: Event) -> bool {
+        log::info!(target: "lampo", "ldk event {:?}", event);
+        if let Err(err) = handler.handle(event) {
+            log::error!("{err}");
+        }
+        if self.halt.load(Ordering::SeqCst) {
+            log::warn!(target: "lampo", "the queue of the ldk events is halted before completing the event {id}");
+            return false;
+        }
+        self.complete(id);
+        true
+    }
+
+    /// Remove the event `id` from the journal, remembering its key.
+    fn complete(&self, id: u64) {
+        let mut journal = self.journal.lock_or_recover();
+        let Some(entry) = journal.pending.remove(&id) else {
+            return;
+        };
+        if let Some(key) = entry.key {
+            journal.completed.push_back(key);
+            if journal.completed.len() > COMPLETED_EVENTS {
+                journal.completed.pop_front();
+            }
+        }
+        if let Err(err) = self.write(&journal) {
+            log::error!(target: "lampo", "impossible complete the ldk event {id}: {err}");
+        }
+    }
+
+    /// The events journaled and not handled yet.
+    pub fn depth(&self) -> usize {
+        self.journal.lock_or_recover().pending.len()
+    }
+
+    /// Stop the queue after the next event is handled and before it is
+    /// completed, like a node killed in the middle. It is for the tests.
+    pub fn halt_before_completion(&self) {
+        self.halt.store(true, Ordering::SeqCst);
+    }
+}
+
+/// The key of the events that happen once for a payment or for a
+/// channel. The other events (like `PendingHTLCsForwardable`) can be
+/// given again with the same content and they must be handled again.
+fn event_key(event: &Event, buf: &[u8]) -> Option<sha256::Hash> {
+    match event {
+        Event::PaymentClaimable { .. }
+        | Event::PaymentClaimed { .. }
+        | Event::PaymentSent { .. }
+        | Event::PaymentFailed { .. }
+        | Event::PaymentPathSuccessful { .. }
+        | Event::PaymentForwarded { .. }
+        | Event::ChannelPending { .. }
+        | Event::ChannelReady { .. }
+        | Event::ChannelClosed { .. }
+        | Event::SpendableOutputs { .. }
+        | Event::DiscardFunding { .. } => Some(sha256::Hash::hash(buf)),
+        _ => None,
+    }
+}
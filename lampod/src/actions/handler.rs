@@ -1,6 +1,5 @@
 //! Handler module implementation that
-use std::cell::RefCell;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 use lampo_common::chan;
 use lampo_common::error;
@@ -10,16 +9,25 @@ use lampo_common::event::{Emitter, Event, Subscriber};
 use lampo_common::handler::Handler as EventHandler;
 use lampo_common::json;
 use lampo_common::ldk;
+use lampo_common::metrics::{self, Metrics};
+use lampo_common::model::response::InboundPaymentState;
 use lampo_common::model::response::PaymentHop;
 use lampo_common::model::response::PaymentState;
+use lampo_common::model::{Msat, Sat};
+use lampo_common::sync::RwLockExt;
 use lampo_common::types::ChannelState;
+use lampo_common::wallet;
 use lampo_jsonrpc::json_rpc2::Request;
 
 use crate::chain::{LampoChainManager, WalletManager};
 use crate::command::Command;
 use crate::handler::external_handler::ExternalHandler;
 use crate::ln::events::PeerEvents;
-use crate::ln::{LampoChannelManager, LampoInventoryManager, LampoPeerManager};
+use crate::ln::{
+    LampoBumpManager, LampoChannelManager, LampoInventoryManager, LampoPeerManager,
+    OffchainManager,
+};
+use crate::plugins::{HookResult, LampoPlugins};
 use crate::{async_run, LampoDaemon};
 
 use super::{Handler, InventoryHandler};
@@ -30,7 +38,11 @@ pub struct LampoHandler {
     inventory_manager: Arc<LampoInventoryManager>,
     wallet_manager: Arc<dyn WalletManager>,
     chain_manager: Arc<LampoChainManager>,
-    external_handlers: RefCell<Vec<Arc<dyn ExternalHandler>>>,
+    bump_manager: Arc<LampoBumpManager>,
+    offchain_manager: Arc<OffchainManager>,
+    metrics: Arc<Metrics>,
+    plugins: Arc<LampoPlugins>,
+    external_handlers: RwLock<Vec<Arc<dyn ExternalHandler>>>,
     #[allow(dead_code)]
     emitter: Emitter<Event>,
     subscriber: Subscriber<Event>,
@@ -49,18 +61,46 @@ impl LampoHandler {
             inventory_manager: lampod.inventory_manager(),
             wallet_manager: lampod.wallet_manager(),
             chain_manager: lampod.onchain_manager(),
-            external_handlers: RefCell::new(Vec::new()),
+            bump_manager: lampod.bump_manager(),
+            offchain_manager: lampod.offchain_manager(),
+            metrics: lampod.metrics(),
+            plugins: lampod.plugins(),
+            external_handlers: RwLock::new(Vec::new()),
             emitter,
             subscriber,
         }
     }
 
     pub fn add_external_handler(&self, handler: Arc<dyn ExternalHandler>) -> error::Result<()> {
-        let mut vect = self.external_handlers.borrow_mut();
+        let mut vect = self.external_handlers.write_or_recover();
         vect.push(handler);
         Ok(())
     }
 
+    /// The events emitted that the subscribers did not receive yet.
+    pub fn pending_events(&self) -> usize {
+        self.subscriber.pending()
+    }
+
+    /// Raise the fee rate (in sat/kW) to the minimum configured by
+    /// the user or to the min relay fee of the backend, so we do not
+    /// build a transaction that is not relayed by the network.
+    fn clamp_fee_rate(&self, fee_rate: u32) -> u32 {
+        let mut min_fee_rate = wallet::min_fee_rate(&self.channel_manager.conf);
+        match self.chain_manager.backend.minimum_mempool_fee() {
+            Ok(min_relay) => min_fee_rate = min_fee_rate.max(min_relay),
+            Err(err) => log::warn!(target: "wallet", "impossible get the min relay fee: {err}"),
+        }
+        if wallet::check_fee_rate(fee_rate, min_fee_rate).is_err() {
+            log::info!(
+                target: "wallet",
+                "fee rate `{fee_rate}` sat/kW is below the minimum, using `{min_fee_rate}` sat/kW"
+            );
+            return min_fee_rate;
+        }
+        fee_rate
+    }
+
     /// Call any method supported by the lampod configuration. This includes
     /// a lot of handler code. This function serves as a broker pattern in some ways,
     /// but it may also function as a chain of responsibility pattern in certain cases.
@@ -110,11 +150,10 @@ impl Handler for LampoHandler {
                 Ok(())
             }
             Command::ExternalCommand(req, chan) => {
-                log::info!(
-                    "external handler size {}",
-                    self.external_handlers.borrow().len()
-                );
-                for handler in self.external_handlers.borrow().iter() {
+                // the handlers can add other handlers while they run
+                let handlers = self.external_handlers.read_or_recover().clone();
+                log::info!("external handler size {}", handlers.len());
+                for handler in handlers.iter() {
                     if let Some(resp) = handler.handle(&req)? {
                         chan.send(resp)?;
                         return Ok(());
@@ -135,7 +174,50 @@ impl Handler for LampoHandler {
                 push_msat,
                 channel_type,
             } => {
-                Err(error::anyhow!("Request for open a channel received, unfortunatly we do not support this feature yet."))
+                let manager = self.channel_manager.manager();
+                let result = self.channel_manager.conf.check_inbound_channel(
+                    funding_satoshis,
+                    self.channel_manager.pending_channels(),
+                    self.channel_manager.inbound_channels_with(&counterparty_node_id),
+                );
+                if let Err(err) = result {
+                    log::warn!(target: "lampo", "rejecting channel from `{counterparty_node_id}`: {err}");
+                    manager
+                        .force_close_without_broadcasting_txn(&temporary_channel_id, &counterparty_node_id)
+                        .map_err(|err| error::anyhow!("{:?}", err))?;
+                    self.emit(Event::Lightning(LightningEvent::ChannelEvent { state: ChannelState::OpeningError, message: format!("{err}") }));
+                    return Err(err.into());
+                }
+                let channel = json::json!({
+                    "node_id": counterparty_node_id.to_string(),
+                    "funding_sat": funding_satoshis,
+                    "push_msat": push_msat,
+                    "channel_type": channel_type.to_string(),
+                });
+                if let HookResult::Reject(reason) = self.plugins.hook("openchannel", channel) {
+                    log::warn!(target: "lampo", "rejecting channel from `{counterparty_node_id}`: {reason}");
+                    manager
+                        .force_close_without_broadcasting_txn(
+                            &temporary_channel_id,
+                            &counterparty_node_id,
+                        )
+                        .map_err(|err| error::anyhow!("{:?}", err))?;
+                    self.emit(Event::Lightning(LightningEvent::ChannelEvent {
+                        state: ChannelState::OpeningError,
+                        message: reason.clone(),
+                    }));
+                    error::bail!(
+                        "channel from `{counterparty_node_id}` rejected by a plugin: {reason}"
+                    );
+                }
+                log::info!(target: "lampo", "accepting channel of `{funding_satoshis}` sat from `{counterparty_node_id}`");
+                let result = if self.channel_manager.conf.is_zero_conf_peer(&counterparty_node_id) {
+                    manager.accept_inbound_channel_from_trusted_peer_0conf(&temporary_channel_id, &counterparty_node_id, 0)
+                } else {
+                    manager.accept_inbound_channel(&temporary_channel_id, &counterparty_node_id, 0)
+                };
+                result.map_err(|err| error::anyhow!("{:?}", err))?;
+                Ok(())
             }
             ldk::events::Event::ChannelReady {
                 channel_id,
@@ -144,6 +226,17 @@ impl Handler for LampoHandler {
                 channel_type,
             } => {
                 log::info!("channel ready with node `{counterparty_node_id}`, and channel type {channel_type}");
+                let scid = self
+                    .channel_manager
+                    .manager()
+                    .list_channels_with_counterparty(&counterparty_node_id)
+                    .into_iter()
+                    .find(|channel| channel.channel_id == channel_id)
+                    .and_then(|channel| channel.get_outbound_payment_scid());
+                let history = self.channel_manager.history();
+                history.record_ids(&channel_id, scid, None);
+                history.record_state(&channel_id, "ready");
+                self.chain_manager.funding.unwatch(&channel_id);
                 self.emit(Event::Lightning(LightningEvent::ChannelReady {
                     counterparty_node_id,
                     channel_id,
@@ -162,6 +255,13 @@ impl Handler for LampoHandler {
                 if let Some(node_id) = counterparty_node_id {
                     log::warn!("closing channels with `{node_id}`");
                 }
+                self.channel_manager.history().record_closed(
+                    &channel_id,
+                    channel_funding_txo.map(|txo| txo.into_bitcoin_outpoint()),
+                    &reason.to_string(),
+                );
+                self.channel_manager.forget_disabled_channel(&channel_id)?;
+                self.channel_manager.forget_pending_close(&channel_id)?;
                 let node_id = counterparty_node_id.map(|id| id.to_string());
                 let txo = channel_funding_txo.map(|txo| txo.to_string());
                 self.emit(Event::Lightning(LightningEvent::CloseChannelEvent { channel_id: channel_id.to_string(), message: reason.to_string(), counterparty_node_id : node_id, funding_utxo : txo}));
@@ -189,11 +289,29 @@ impl Handler for LampoHandler {
                     err
                 })?;
                 log::info!("fee estimated {:?} sats", fee);
-                let transaction = self.wallet_manager.create_transaction(
+                let fee = self.clamp_fee_rate(fee);
+                // the inputs with few confirmations can be reorged away
+                let transaction = match self.wallet_manager.create_funding_transaction(
                     output_script,
-                    channel_value_satoshis,
+                    Sat::from_sat(channel_value_satoshis),
                     fee,
-                )?;
+                    self.channel_manager.conf.funding_min_confirmations,
+                ) {
+                    Ok(transaction) => transaction,
+                    Err(err) => {
+                        // the channel can not be funded, so we drop it
+                        let _ = self
+                            .channel_manager
+                            .manager()
+                            .force_close_without_broadcasting_txn(
+                                &temporary_channel_id,
+                                &counterparty_node_id,
+                            );
+                        let msg = format!("Channel Opening Error: {err}");
+                        self.emit(Event::Lightning(LightningEvent::ChannelEvent { state: ChannelState::OpeningError, message: msg }));
+                        return Err(err);
+                    }
+                };
                 log::info!("funding transaction created `{}`", transaction.txid());
                 log::info!(
                     "transaction hex `{}`",
@@ -216,10 +334,15 @@ impl Handler for LampoHandler {
                 Ok(())
             }
             ldk::events::Event::ChannelPending {
+                channel_id,
                 counterparty_node_id,
                 funding_txo,
                 ..
             } => {
+                let history = self.channel_manager.history();
+                history.record_ids(&channel_id, None, Some(funding_txo));
+                history.record_state(&channel_id, "pending");
+                self.chain_manager.funding.watch(channel_id, funding_txo);
                 log::info!(
                     "channel pending with node `{}` with funding `{funding_txo}`",
                     counterparty_node_id.to_string()
@@ -231,6 +354,7 @@ impl Handler for LampoHandler {
                 self.channel_manager
                     .manager()
                     .process_pending_htlc_forwards();
+                self.channel_manager.track_htlcs();
                 Ok(())
             }
             ldk::events::Event::PaymentClaimable {
@@ -244,6 +368,69 @@ impl Handler for LampoHandler {
                 via_user_channel_id,
                 claim_deadline,
             } => {
+                self.channel_manager.track_htlcs();
+                if via_channel_id.is_some_and(|channel_id| self.channel_manager.ignores_htlcs(&channel_id)) {
+                    // ldk fails the payment back after the claim deadline
+                    log::info!(target: "lampo", "ignoring the payment `{payment_hash}` as asked by `dev-ignore-htlcs`");
+                    return Ok(());
+                }
+                let htlc = json::json!({
+                    "payment_hash": payment_hash.to_string(),
+                    "amount_msat": amount_msat,
+                    "claim_deadline": claim_deadline,
+                    "channel_id": via_channel_id.map(|channel_id| channel_id.to_string()),
+                });
+                match self.plugins.hook("htlc_accepted", htlc) {
+                    HookResult::Continue => {}
+                    HookResult::Reject(reason) => {
+                        log::info!(target: "lampo", "failing the payment `{payment_hash}`: {reason}");
+                        self.channel_manager
+                            .manager()
+                            .fail_htlc_backwards(&payment_hash);
+                        return Ok(());
+                    }
+                    HookResult::Resolve(preimage) => {
+                        self.offchain_manager.record_inbound_payment(
+                            payment_hash,
+                            InboundPaymentState::Pending,
+                            amount_msat,
+                        );
+                        self.channel_manager.manager().claim_funds(preimage);
+                        return Ok(());
+                    }
+                }
+                // the hook is only for the payments of our invoices
+                let payment = json::json!({
+                    "payment_hash": payment_hash.to_string(),
+                    "amount_msat": amount_msat,
+                });
+                let invoice_payment = match &purpose {
+                    ldk::events::PaymentPurpose::SpontaneousPayment(_) => HookResult::Continue,
+                    _ => self.plugins.hook("invoice_payment", payment),
+                };
+                if let HookResult::Reject(reason) = invoice_payment {
+                    log::info!(target: "lampo", "failing the payment `{payment_hash}`: {reason}");
+                    self.channel_manager
+                        .manager()
+                        .fail_htlc_backwards(&payment_hash);
+                    return Ok(());
+                }
+                self.offchain_manager.record_inbound_payment(
+                    payment_hash,
+                    InboundPaymentState::Pending,
+                    Msat::from_msat(amount_msat),
+                );
+                // The preimage of an hold invoice is released by the user.
+                if self
+                    .offchain_manager
+                    .accept_hold_invoice(&payment_hash, Msat::from_msat(amount_msat))
+                {
+                    self.emit(Event::Lightning(LightningEvent::HoldInvoiceAccepted {
+                        payment_hash: payment_hash.to_string(),
+                        amount_msat,
+                    }));
+                    return Ok(());
+                }
                 let preimage = match purpose {
                     ldk::events::PaymentPurpose::Bolt11InvoicePayment  {
                         payment_preimage, ..
@@ -274,20 +461,77 @@ impl Handler for LampoHandler {
                     ldk::events::PaymentPurpose::Bolt12RefundPayment { payment_preimage, payment_secret, .. } => (payment_preimage, Some(payment_secret)),
                     ldk::events::PaymentPurpose::SpontaneousPayment(preimage) => (Some(preimage), None),
                 };
+                self.offchain_manager.record_inbound_payment(
+                    payment_hash,
+                    InboundPaymentState::Paid,
+                    Msat::from_msat(amount_msat),
+                );
+                self.metrics.inc(&metrics::PAYMENTS_RECEIVED, &[]);
+                self.emit(Event::Lightning(LightningEvent::PaymentReceived {
+                    payment_hash: payment_hash.to_string(),
+                    amount_msat,
+                }));
                 log::warn!("please note the payments are not make persistent for the moment");
                 // FIXME: make peristant these information
                 Ok(())
             }
             ldk::events::Event::PaymentSent { .. } => {
                 log::info!("payment sent: `{:?}`", event);
+                self.metrics.inc(&metrics::PAYMENTS_SENT, &[]);
+                self.channel_manager.track_htlcs();
+                Ok(())
+            },
+            ldk::events::Event::PaymentFailed { payment_hash, reason, .. } => {
+                log::info!(target: "lampo", "payment `{payment_hash}` failed: {:?}", reason);
+                self.metrics.inc(&metrics::PAYMENTS_FAILED, &[]);
+                self.emit(Event::Lightning(LightningEvent::PaymentEvent { state: PaymentState::Faulure, payment_hash: Some(payment_hash.to_string()), path: Vec::new() }));
                 Ok(())
             },
             ldk::events::Event::PaymentPathSuccessful { payment_hash, path, .. } => {
+                if let Some(payment_hash) = payment_hash {
+                    self.offchain_manager.record_payment_path(payment_hash, &path);
+                }
                 let path = path.hops.iter().map(|hop| PaymentHop::from(hop.clone())).collect::<Vec<PaymentHop>>();
                 let hop = LightningEvent::PaymentEvent { state: PaymentState::Success, payment_hash: payment_hash.map(|hash| hash.to_string()), path };
                 self.emit(Event::Lightning(hop));
                 Ok(())
             },
+            ldk::events::Event::PaymentForwarded {
+                prev_channel_id,
+                next_channel_id,
+                total_fee_earned_msat,
+                outbound_amount_forwarded_msat,
+                ..
+            } => {
+                log::info!(target: "lampo", "payment forwarded from `{:?}` to `{:?}`", prev_channel_id, next_channel_id);
+                self.channel_manager.history().record_forward(
+                    prev_channel_id,
+                    next_channel_id,
+                    outbound_amount_forwarded_msat.unwrap_or_default(),
+                    total_fee_earned_msat.unwrap_or_default(),
+                );
+                let fee_msat = total_fee_earned_msat.unwrap_or_default();
+                self.metrics.inc(&metrics::FORWARDS, &[]);
+                self.metrics.add(&metrics::FORWARD_FEES_MSAT, &[], fee_msat);
+                self.channel_manager.track_htlcs();
+                Ok(())
+            }
+            ldk::events::Event::BumpTransaction(event) => {
+                self.bump_manager.handle(&event);
+                Ok(())
+            }
+            ldk::events::Event::SpendableOutputs { outputs, channel_id } => {
+                if let Some(channel_id) = channel_id {
+                    self.bump_manager.resolve(&channel_id);
+                }
+                // ldk generates the event only once, so the sweeper persists
+                // the outputs and spends them again until the spend confirms.
+                log::info!(target: "lampo", "sweeping {} spendable outputs", outputs.len());
+                self.channel_manager
+                    .sweeper()
+                    .track_spendable_outputs(outputs, channel_id, false, None);
+                Ok(())
+            }
             _ => Err(error::anyhow!("unexpected ldk event: {:?}", event)),
         }
     }
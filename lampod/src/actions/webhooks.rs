@@ -0,0 +1,253 @@
+//! Webhooks notified of the events of the node.
+//!
+//! Every webhook follows the event bus from its own thread, so a
+//! webhook that is slow or down never blocks the processing of the
+//! events, nor the other webhooks. An event is sent with a POST of
+//! the JSON `{"id": <id>, "type": <kind>, "payload": <data>}`, signed
+//! inside the `X-Lampo-Signature` header as `sha256=<hex>`, that is
+//! the HMAC-SHA256 of the body with the secret of the webhook.
+//!
+//! A delivery that fails is retried with an exponential backoff, up
+//! to `webhook-max-attempts` attempts.
+//!
+//! The webhooks can be replaced at runtime by `reload`, a webhook
+//! removed stops after its current delivery.
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use lampo_common::bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use lampo_common::bitcoin::hashes::sha256;
+use lampo_common::bitcoin::hashes::{Hash, HashEngine};
+use lampo_common::conf::{LampoConf, WebhookConf};
+use lampo_common::json;
+use lampo_common::model::response::{DeliveryStatus, NotifiedEvent, WebhookDelivery};
+use lampo_common::sync::MutexExt;
+
+use crate::actions::event_bus::LampoEventBus;
+
+/// The header with the signature of the body.
+pub const SIGNATURE_HEADER: &str = "X-Lampo-Signature";
+/// The header with the id of the delivery, the same for every attempt.
+pub const DELIVERY_HEADER: &str = "X-Lampo-Delivery";
+
+/// Seconds that a webhook has to answer.
+const DELIVERY_TIMEOUT_SECS: u64 = 10;
+/// The delay before the first retry, it doubles at every attempt.
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+/// How long a webhook waits for the next events before checking
+/// if the node is stopping.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How many deliveries are kept for `listwebhookdeliveries`.
+const DELIVERY_HISTORY_SIZE: usize = 1024;
+
+#[derive(Default)]
+struct DeliveryHistory {
+    deliveries: VecDeque<WebhookDelivery>,
+    last_id: u64,
+}
+
+pub struct LampoWebhooks {
+    webhooks: Mutex<Vec<WebhookConf>>,
+    max_attempts: u32,
+    history: Mutex<DeliveryHistory>,
+    /// The event bus and the stop flag given to `start`, to follow
+    /// the webhooks added at runtime.
+    started: Mutex<Option<(Arc<LampoEventBus>, Arc<AtomicBool>)>>,
+}
+
+impl LampoWebhooks {
+    pub fn new(conf: &LampoConf) -> Self {
+        Self {
+            webhooks: Mutex::new(conf.webhooks.clone()),
+            max_attempts: conf.webhook_max_attempts,
+            history: Mutex::new(DeliveryHistory::default()),
+            started: Mutex::new(None),
+        }
+    }
+
+    /// Spawn a thread for each webhook that delivers the events of
+    /// the `event_bus` until the node is `stopped`.
+    pub fn start(
+        self: Arc<Self>,
+        event_bus: Arc<LampoEventBus>,
+        stopped: Arc<AtomicBool>,
+    ) -> Vec<JoinHandle<()>> {
+        *self.started.lock_or_recover() = Some((event_bus.clone(), stopped.clone()));
+        let webhooks = self.webhooks.lock_or_recover().clone();
+        webhooks
+            .into_iter()
+            .map(|webhook| self.clone().spawn(webhook, event_bus.clone(), stopped.clone()))
+            .collect()
+    }
+
+    /// Replace the webhooks, the ones removed stop and the new ones
+    /// receive the events from now on.
+    pub fn set_webhooks(self: Arc<Self>, webhooks: Vec<WebhookConf>) {
+        let added = {
+            let mut current = self.webhooks.lock_or_recover();
+            let added = webhooks
+                .iter()
+                .filter(|webhook| !current.contains(webhook))
+                .cloned()
+                .collect::<Vec<_>>();
+            *current = webhooks;
+            added
+        };
+        let Some((event_bus, stopped)) = self.started.lock_or_recover().clone() else {
+            return;
+        };
+        for webhook in added {
+            let _ = self.clone().spawn(webhook, event_bus.clone(), stopped.clone());
+        }
+    }
+
+    fn spawn(
+        self: Arc<Self>,
+        webhook: WebhookConf,
+        event_bus: Arc<LampoEventBus>,
+        stopped: Arc<AtomicBool>,
+    ) -> JoinHandle<()> {
+        std::thread::spawn(move || self.follow(&webhook, &event_bus, &stopped))
+    }
+
+    /// The webhook is still configured.
+    fn is_active(&self, webhook: &WebhookConf) -> bool {
+        self.webhooks.lock_or_recover().contains(webhook)
+    }
+
+    /// The deliveries made, the oldest first, only the ones to `url`
+    /// when it is given.
+    pub fn deliveries(&self, url: Option<&str>) -> Vec<WebhookDelivery> {
+        let history = self.history.lock_or_recover();
+        history
+            .deliveries
+            .iter()
+            .filter(|delivery| url.map_or(true, |url| delivery.url == url))
+            .cloned()
+            .collect()
+    }
+
+    fn follow(&self, webhook: &WebhookConf, event_bus: &LampoEventBus, stopped: &AtomicBool) {
+        log::info!(target: "webhooks", "sending the events to `{}`", webhook.url);
+        let mut last_id = None;
+        while !stopped.load(Ordering::SeqCst) && self.is_active(webhook) {
+            match event_bus.wait(last_id, &webhook.events, POLL_INTERVAL) {
+                Ok(events) => {
+                    for event in events.events {
+                        self.deliver(webhook, &event, stopped);
+                    }
+                    last_id = Some(events.last_id);
+                }
+                // the webhook is so slow that its events are dropped,
+                // so it continues from the oldest event kept
+                Err(err) => {
+                    log::warn!(target: "webhooks", "`{}` missed events: {err}", webhook.url);
+                    last_id = None;
+                }
+            }
+        }
+    }
+
+    fn deliver(&self, webhook: &WebhookConf, event: &NotifiedEvent, stopped: &AtomicBool) {
+        let body = json::json!({ "id": event.id, "type": event.kind, "payload": event.data });
+        let body = body.to_string();
+        let signature = sign(&webhook.secret, body.as_bytes());
+        let id = self.record(webhook, event);
+        let mut delay = FIRST_RETRY_DELAY;
+        for attempt in 1..=self.max_attempts {
+            let response = minreq::post(&webhook.url)
+                .with_header("Content-Type", "application/json")
+                .with_header(SIGNATURE_HEADER, &signature)
+                .with_header(DELIVERY_HEADER, id.to_string())
+                .with_body(body.as_str())
+                .with_timeout(DELIVERY_TIMEOUT_SECS)
+                .send();
+            let (status_code, error) = match response {
+                Ok(response) if (200..300).contains(&response.status_code) => {
+                    self.update(id, |delivery| {
+                        delivery.status = DeliveryStatus::Delivered;
+                        delivery.attempts = attempt;
+                        delivery.status_code = u16::try_from(response.status_code).ok();
+                        delivery.error = None;
+                    });
+                    let url = &webhook.url;
+                    log::debug!(target: "webhooks", "event `{}` delivered to `{url}`", event.id);
+                    return;
+                }
+                Ok(response) => (
+                    u16::try_from(response.status_code).ok(),
+                    format!(
+                        "webhook replied with `{} {}`",
+                        response.status_code, response.reason_phrase
+                    ),
+                ),
+                Err(err) => (None, err.to_string()),
+            };
+            let last_attempt = attempt == self.max_attempts;
+            log::warn!(
+                target: "webhooks",
+                "attempt {attempt} to deliver the event `{}` to `{}` failed: {error}",
+                event.id,
+                webhook.url
+            );
+            self.update(id, |delivery| {
+                if last_attempt {
+                    delivery.status = DeliveryStatus::Failed;
+                }
+                delivery.attempts = attempt;
+                delivery.status_code = status_code;
+                delivery.error = Some(error);
+            });
+            if last_attempt || stopped.load(Ordering::SeqCst) {
+                return;
+            }
+            std::thread::sleep(delay);
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+        }
+    }
+
+    /// Record a new pending delivery, and return its id.
+    fn record(&self, webhook: &WebhookConf, event: &NotifiedEvent) -> u64 {
+        let mut history = self.history.lock_or_recover();
+        history.last_id += 1;
+        let id = history.last_id;
+        history.deliveries.push_back(WebhookDelivery {
+            id,
+            url: webhook.url.clone(),
+            event_id: event.id,
+            kind: event.kind.clone(),
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            status_code: None,
+            error: None,
+        });
+        if history.deliveries.len() > DELIVERY_HISTORY_SIZE {
+            history.deliveries.pop_front();
+        }
+        id
+    }
+
+    fn update<F: FnOnce(&mut WebhookDelivery)>(&self, id: u64, update: F) {
+        let mut history = self.history.lock_or_recover();
+        // the delivery can be dropped from the history in the meanwhile
+        if let Some(delivery) = history
+            .deliveries
+            .iter_mut()
+            .find(|delivery| delivery.id == id)
+        {
+            update(delivery);
+        }
+    }
+}
+
+/// The signature of the `body` with the `secret`, as sent inside
+/// the `X-Lampo-Signature` header.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut engine = HmacEngine::<sha256::Hash>::new(secret.as_bytes());
+    engine.input(body);
+    format!("sha256={}", Hmac::<sha256::Hash>::from_engine(engine))
+}
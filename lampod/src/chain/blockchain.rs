@@ -1,106 +1,128 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 
-use lampo_common::backend::Backend;
+use lampo_common::backend::{Backend, BroadcastError};
 use lampo_common::bitcoin;
 use lampo_common::bitcoin::blockdata::constants::ChainHash;
 use lampo_common::bitcoin::Transaction;
+use lampo_common::conf::{LampoConf, Network};
+use lampo_common::error;
 use lampo_common::ldk;
 use lampo_common::ldk::chain::chaininterface::{
     BroadcasterInterface, ConfirmationTarget, FeeEstimator,
 };
 use lampo_common::ldk::chain::Filter;
 use lampo_common::ldk::routing::utxo::UtxoLookup;
+use lampo_common::metrics::Metrics;
+use lampo_common::model::response::ChainInfo;
 use lampo_common::wallet::WalletManager;
 
+use super::broadcaster::LampoBroadcaster;
+use super::fee_estimator::{target_name, LampoFeeEstimator, CONFIRMATION_TARGETS};
+use super::funding_watcher::LampoFundingWatcher;
+use super::reorg::LampoChainTracker;
+use super::utxo_lookup::LampoUtxoLookup;
+
 #[derive(Clone)]
 pub struct LampoChainManager {
+    pub network: Network,
     pub backend: Arc<dyn Backend>,
     pub wallet_manager: Arc<dyn WalletManager>,
+    pub fees: Arc<LampoFeeEstimator>,
+    /// The last blocks of the best chain, to detect the reorgs.
+    pub chain: Arc<LampoChainTracker>,
+    /// The funding of the pending channels, to report their
+    /// confirmations.
+    pub funding: Arc<LampoFundingWatcher>,
+    /// The funding outputs of the channels announced by the gossip.
+    pub utxo: Arc<LampoUtxoLookup>,
+    /// The transactions rejected by the backend.
+    pub broadcaster: Arc<LampoBroadcaster>,
 }
 
 /// Personal Lampo implementation
 impl LampoChainManager {
-    /// Create a new instance of LampoFeeEstimator with the specified
+    /// Create a new instance of LampoChainManager with the specified
     /// Backend.
-    pub fn new(client: Arc<dyn Backend>, wallet_manager: Arc<dyn WalletManager>) -> Self {
+    pub fn new(
+        conf: &LampoConf,
+        client: Arc<dyn Backend>,
+        wallet_manager: Arc<dyn WalletManager>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        let fees = Arc::new(LampoFeeEstimator::new(conf, client.clone(), metrics));
         LampoChainManager {
+            network: conf.network,
+            broadcaster: Arc::new(LampoBroadcaster::new(
+                client.clone(),
+                wallet_manager.clone(),
+                fees.clone(),
+            )),
+            fees,
+            chain: Arc::new(LampoChainTracker::new(client.clone())),
+            funding: Arc::new(LampoFundingWatcher::new()),
+            utxo: Arc::new(LampoUtxoLookup::new(
+                client.clone(),
+                ChainHash::using_genesis_block(conf.network),
+            )),
             backend: client,
             wallet_manager,
         }
     }
 
+    /// Broadcast the transaction, and return the reason when the
+    /// backend rejects it.
+    pub fn broadcast(&self, tx: &Transaction) -> Result<(), BroadcastError> {
+        self.broadcaster.broadcast(tx)
+    }
+
     pub fn is_lightway(&self) -> bool {
         self.backend.is_lightway()
     }
 
-    fn print_ldk_target_to_string(&self, target: ConfirmationTarget) -> String {
-        match target {
-            ConfirmationTarget::OnChainSweep => String::from("on_chain_sweep"),
-            ConfirmationTarget::AnchorChannelFee => String::from("anchor_chanenl"),
-            ConfirmationTarget::NonAnchorChannelFee => String::from("non_anchor_channel"),
-            ConfirmationTarget::ChannelCloseMinimum => String::from("channel_close_minimum"),
-            ConfirmationTarget::MinAllowedAnchorChannelRemoteFee => {
-                String::from("min_allowed_anchor_channel_remote")
-            }
-            ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee => {
-                String::from("min_allowed_non_anchor_channel_remote")
-            }
-            ConfirmationTarget::OutputSpendingFee => String::from("output_spending"),
-        }
+    /// The state of the chain tracked by the manager, and of the
+    /// wallet and fee estimations kept in sync with it.
+    pub fn chain_info(&self) -> error::Result<ChainInfo> {
+        let tip = self.chain.tip();
+        Ok(ChainInfo {
+            network: self.network.to_string(),
+            best_block_hash: tip.map(|(_, hash)| hash.to_string()),
+            best_block_height: tip.map(|(height, _)| height),
+            wallet_synced_height: self.wallet_manager.synced_height()?,
+            fees_fresh: self.fees.is_fresh(),
+            fees_updated_at: self.fees.updated_at(),
+            backend: self.backend.kind().to_string(),
+            backend_endpoint: self.backend.endpoint(),
+        })
     }
 
     pub fn estimated_fees(&self) -> HashMap<String, Option<u32>> {
-        let fees_targets = vec![
-            ConfirmationTarget::OnChainSweep,
-            ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee,
-            ConfirmationTarget::NonAnchorChannelFee,
-            ConfirmationTarget::MinAllowedAnchorChannelRemoteFee,
-            ConfirmationTarget::AnchorChannelFee,
-            ConfirmationTarget::ChannelCloseMinimum,
-            ConfirmationTarget::OutputSpendingFee,
-        ];
-        let mut map: HashMap<String, Option<u32>> = HashMap::new();
-        for target in fees_targets {
-            let fee = self.get_est_sat_per_1000_weight(target);
-            let value = if fee == 0 { None } else { Some(fee) };
-            map.insert(self.print_ldk_target_to_string(target), value);
-        }
-        map
+        CONFIRMATION_TARGETS
+            .iter()
+            .map(|target| {
+                let fee = self.get_est_sat_per_1000_weight(*target);
+                let value = if fee == 0 { None } else { Some(fee) };
+                (target_name(*target).to_owned(), value)
+            })
+            .collect()
     }
 }
 
 /// Rust lightning FeeEstimator implementation
 impl FeeEstimator for LampoChainManager {
     fn get_est_sat_per_1000_weight(&self, confirmation_target: ConfirmationTarget) -> u32 {
-        //FIXME: use cache to avoid return default value (that is 0) on u32
-        match confirmation_target {
-            ConfirmationTarget::OnChainSweep => {
-                self.backend.fee_rate_estimation(1).unwrap_or_default()
-            }
-            ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee
-            | ConfirmationTarget::AnchorChannelFee
-            | ConfirmationTarget::NonAnchorChannelFee => {
-                self.backend.fee_rate_estimation(6).unwrap_or_default()
-            }
-            ConfirmationTarget::MinAllowedAnchorChannelRemoteFee => {
-                self.backend.minimum_mempool_fee().unwrap()
-            }
-            ConfirmationTarget::ChannelCloseMinimum => {
-                self.backend.fee_rate_estimation(100).unwrap_or_default()
-            }
-            ConfirmationTarget::OutputSpendingFee => {
-                self.backend.fee_rate_estimation(12).unwrap_or_default()
-            }
-        }
+        self.fees.fee_rate(confirmation_target)
     }
 }
 
 /// Brodcaster Interface implementation for Lampo.
 impl BroadcasterInterface for LampoChainManager {
-    fn broadcast_transactions(&self, tx: &[&Transaction]) {
-        // FIXME: change the brodcasting
-        self.backend.brodcast_tx(tx.first().unwrap());
+    fn broadcast_transactions(&self, txs: &[&Transaction]) {
+        // the errors are kept by the broadcaster, that tries again
+        // at the next block.
+        for tx in txs {
+            let _ = self.broadcast(tx);
+        }
     }
 }
 
@@ -115,8 +137,8 @@ impl Filter for LampoChainManager {
 }
 
 impl UtxoLookup for LampoChainManager {
-    fn get_utxo(&self, _: &ChainHash, _: u64) -> lampo_common::backend::UtxoResult {
-        todo!()
+    fn get_utxo(&self, chain_hash: &ChainHash, scid: u64) -> lampo_common::backend::UtxoResult {
+        self.utxo.get_utxo(chain_hash, scid)
     }
 }
 
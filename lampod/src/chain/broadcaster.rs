@@ -0,0 +1,135 @@
+//! Broadcast of the transactions.
+//!
+//! Before giving a transaction to the backend the fee rate is checked
+//! against the last mempool minimum, when the wallet knows all the
+//! inputs. The transactions rejected are kept with the reason of the
+//! rejection, and broadcasted again at every new block until the
+//! backend accepts them or their inputs are spent.
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use lampo_common::backend::{Backend, BroadcastError};
+use lampo_common::bitcoin::{Transaction, Txid};
+use lampo_common::model::response;
+use lampo_common::wallet::WalletManager;
+
+use super::fee_estimator::LampoFeeEstimator;
+
+/// A transaction rejected by the backend.
+struct PendingBroadcast {
+    tx: Transaction,
+    error: BroadcastError,
+    attempts: u32,
+}
+
+pub struct LampoBroadcaster {
+    backend: Arc<dyn Backend>,
+    wallet_manager: Arc<dyn WalletManager>,
+    fees: Arc<LampoFeeEstimator>,
+    pending: Mutex<HashMap<Txid, PendingBroadcast>>,
+}
+
+impl LampoBroadcaster {
+    pub fn new(
+        backend: Arc<dyn Backend>,
+        wallet_manager: Arc<dyn WalletManager>,
+        fees: Arc<LampoFeeEstimator>,
+    ) -> Self {
+        Self {
+            backend,
+            wallet_manager,
+            fees,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The fee rate in sat/kW of `tx`, when all the inputs are
+    /// outputs of the wallet.
+    fn fee_rate(&self, tx: &Transaction) -> Option<u32> {
+        let utxos = self.wallet_manager.list_unspent().ok()?;
+        let mut input_sat = 0;
+        for input in tx.input.iter() {
+            let utxo = utxos.iter().find(|utxo| {
+                Txid::from_str(&utxo.txid).ok() == Some(input.previous_output.txid)
+                    && utxo.vout == input.previous_output.vout
+            })?;
+            input_sat += utxo.amount_msat.to_sat_floor().sat();
+        }
+        let output_sat = tx.output.iter().map(|output| output.value).sum::<u64>();
+        let fee = input_sat.checked_sub(output_sat)?;
+        Some((fee * 1000 / tx.weight().to_wu()) as u32)
+    }
+
+    /// Check the fee rate of `tx` against the last minimum fee rate of
+    /// the mempool, the transactions with unknown inputs are not checked.
+    pub fn check(&self, tx: &Transaction) -> Result<(), BroadcastError> {
+        let Some(min_fee_rate) = self.fees.mempool_min_fee_rate() else {
+            return Ok(());
+        };
+        match self.fee_rate(tx) {
+            Some(fee_rate) if fee_rate < min_fee_rate => Err(BroadcastError::FeeTooLow {
+                fee_rate: Some(fee_rate),
+                min_fee_rate,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Broadcast `tx`, when it is rejected it is kept to be broadcasted
+    /// again at the next block.
+    pub fn broadcast(&self, tx: &Transaction) -> Result<(), BroadcastError> {
+        let result = self.check(tx).and_then(|_| self.backend.brodcast_tx(tx));
+        let mut pending = self.pending.lock().unwrap();
+        match result {
+            Ok(()) => {
+                pending.remove(&tx.txid());
+            }
+            Err(ref err) => {
+                log::warn!(target: "lampo", "broadcast of the transaction `{}` fails: {err}", tx.txid());
+                let entry = pending.entry(tx.txid()).or_insert_with(|| PendingBroadcast {
+                    tx: tx.clone(),
+                    error: err.clone(),
+                    attempts: 0,
+                });
+                entry.error = err.clone();
+                entry.attempts += 1;
+            }
+        }
+        result
+    }
+
+    /// Broadcast again the transactions rejected, the ones with the
+    /// inputs spent are dropped because they will never be accepted.
+    pub fn rebroadcast(&self) {
+        let txs = self
+            .pending
+            .lock()
+            .unwrap()
+            .values()
+            .map(|pending| pending.tx.clone())
+            .collect::<Vec<_>>();
+        for tx in txs {
+            if let Err(BroadcastError::MissingInputs) = self.broadcast(&tx) {
+                log::warn!(target: "lampo", "dropping the transaction `{}`, the inputs are spent", tx.txid());
+                self.pending.lock().unwrap().remove(&tx.txid());
+            }
+        }
+    }
+
+    pub fn pending(&self) -> response::PendingBroadcasts {
+        let transactions = self
+            .pending
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(txid, pending)| response::PendingBroadcast {
+                txid: txid.to_string(),
+                reason: pending.error.to_string(),
+                error: pending.error.clone(),
+                attempts: pending.attempts,
+            })
+            .collect();
+        response::PendingBroadcasts { transactions }
+    }
+}
@@ -0,0 +1,327 @@
+//! Fee estimation with a cache.
+//!
+//! Ldk asks the fee rates very often, so the estimations are fetched
+//! in background from the esplora api (or from the backend) and ldk
+//! reads the cache. When a refresh fails the last estimations are
+//! kept, so ldk never sees a zero fee rate.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use lampo_common::backend::Backend;
+use lampo_common::conf::LampoConf;
+use lampo_common::error;
+use lampo_common::json;
+use lampo_common::ldk::chain::chaininterface::ConfirmationTarget;
+use lampo_common::metrics::{Metrics, ESPLORA_FAILURES};
+use lampo_common::model::response;
+use lampo_common::sync::MutexExt;
+use lampo_common::time::unix_now;
+
+/// Timeout of the request to the esplora api.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// All the confirmation targets used by ldk.
+pub const CONFIRMATION_TARGETS: [ConfirmationTarget; 7] = [
+    ConfirmationTarget::OnChainSweep,
+    ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee,
+    ConfirmationTarget::NonAnchorChannelFee,
+    ConfirmationTarget::MinAllowedAnchorChannelRemoteFee,
+    ConfirmationTarget::AnchorChannelFee,
+    ConfirmationTarget::ChannelCloseMinimum,
+    ConfirmationTarget::OutputSpendingFee,
+];
+
+/// The number of blocks in which a transaction paying the fee
+/// of `target` should confirm.
+pub fn block_target(target: ConfirmationTarget) -> u16 {
+    match target {
+        ConfirmationTarget::OnChainSweep => 1,
+        ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee
+        | ConfirmationTarget::AnchorChannelFee
+        | ConfirmationTarget::NonAnchorChannelFee => 6,
+        ConfirmationTarget::OutputSpendingFee => 12,
+        ConfirmationTarget::ChannelCloseMinimum => 144,
+        ConfirmationTarget::MinAllowedAnchorChannelRemoteFee => 1008,
+    }
+}
+
+pub fn target_name(target: ConfirmationTarget) -> &'static str {
+    match target {
+        ConfirmationTarget::OnChainSweep => "on_chain_sweep",
+        ConfirmationTarget::AnchorChannelFee => "anchor_channel",
+        ConfirmationTarget::NonAnchorChannelFee => "non_anchor_channel",
+        ConfirmationTarget::ChannelCloseMinimum => "channel_close_minimum",
+        ConfirmationTarget::MinAllowedAnchorChannelRemoteFee => {
+            "min_allowed_anchor_channel_remote"
+        }
+        ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee => {
+            "min_allowed_non_anchor_channel_remote"
+        }
+        ConfirmationTarget::OutputSpendingFee => "output_spending",
+    }
+}
+
+/// The confirmation target with the `name` given by `target_name`.
+pub fn parse_target(name: &str) -> Option<ConfirmationTarget> {
+    CONFIRMATION_TARGETS
+        .iter()
+        .find(|target| target_name(**target) == name)
+        .copied()
+}
+
+/// Pick the estimation in sat/vB of an esplora `/fee-estimates` for
+/// `blocks`, that is the one with the nearest target not above it,
+/// or the fastest one when there is none.
+fn esplora_estimate(estimates: &HashMap<String, f64>, blocks: u16) -> Option<f64> {
+    let mut estimates = estimates
+        .iter()
+        .filter_map(|(target, fee)| Some((target.parse::<u16>().ok()?, *fee)))
+        .collect::<Vec<_>>();
+    estimates.sort_by_key(|(target, _)| *target);
+    estimates
+        .iter()
+        .rev()
+        .find(|(target, _)| *target <= blocks)
+        .or(estimates.first())
+        .map(|(_, fee)| *fee)
+}
+
+pub struct LampoFeeEstimator {
+    backend: Arc<dyn Backend>,
+    /// The esplora api, the fees are estimated by the backend
+    /// when it is `None`.
+    url: Mutex<Option<String>>,
+    /// The min and the max fee rates in sat/kW.
+    limits: Mutex<(u32, Option<u32>)>,
+    /// Seconds between two refreshes.
+    refresh_interval: u64,
+    /// The last fee rates estimated in sat/kW, before the clamp.
+    cache: Mutex<HashMap<ConfirmationTarget, u32>>,
+    /// The fee rates in sat/kW set by `dev-set-feerate`, they win
+    /// over the estimations and the limits.
+    overrides: Mutex<HashMap<ConfirmationTarget, u32>>,
+    /// Unix timestamp of the last successful refresh.
+    updated_at: Mutex<Option<u64>>,
+    /// The last minimum fee rate in sat/kW of the backend mempool.
+    mempool_min_fee_rate: Mutex<Option<u32>>,
+    metrics: Arc<Metrics>,
+}
+
+impl LampoFeeEstimator {
+    pub fn new(conf: &LampoConf, backend: Arc<dyn Backend>, metrics: Arc<Metrics>) -> Self {
+        let url = conf
+            .fee_estimates_url
+            .as_ref()
+            .map(|url| url.trim_end_matches('/').to_owned());
+        if url.is_some() {
+            metrics.add(&ESPLORA_FAILURES, &[("source", "fees")], 0);
+        }
+        Self {
+            backend,
+            url: Mutex::new(url),
+            limits: Mutex::new((conf.min_fee_rate_sat_kw, conf.max_fee_rate_sat_kw)),
+            refresh_interval: conf.fee_refresh_interval_secs,
+            cache: Mutex::new(HashMap::new()),
+            overrides: Mutex::new(HashMap::new()),
+            updated_at: Mutex::new(None),
+            mempool_min_fee_rate: Mutex::new(None),
+            metrics,
+        }
+    }
+
+    /// The fee rate in sat/kW for `target`, from the cache and
+    /// inside the configured limits.
+    pub fn fee_rate(&self, target: ConfirmationTarget) -> u32 {
+        if let Some(fee) = self.overrides.lock_or_recover().get(&target) {
+            return *fee;
+        }
+        let fee = self
+            .cache
+            .lock_or_recover()
+            .get(&target)
+            .copied()
+            .unwrap_or_default();
+        let (min, max) = self.limits();
+        let fee = fee.max(min);
+        match max {
+            Some(max) => fee.min(max),
+            None => fee,
+        }
+    }
+
+    /// The min and the max fee rates in sat/kW.
+    pub fn limits(&self) -> (u32, Option<u32>) {
+        *self.limits.lock_or_recover()
+    }
+
+    /// Change the limits of the fee rates given to ldk, the
+    /// estimations in the cache are clamped again on the next read.
+    pub fn set_limits(&self, min: u32, max: Option<u32>) {
+        *self.limits.lock_or_recover() = (min, max);
+    }
+
+    /// Give to ldk `fee` for `target` until it is reset with `None`,
+    /// whatever are the estimations and the limits.
+    pub fn set_override(&self, target: ConfirmationTarget, fee: Option<u32>) {
+        let mut overrides = self.overrides.lock_or_recover();
+        match fee {
+            Some(fee) => overrides.insert(target, fee),
+            None => overrides.remove(&target),
+        };
+    }
+
+    /// Estimate the fees with the esplora at `url`, or with the
+    /// backend when it is `None`, from the next refresh.
+    pub fn set_url(&self, url: Option<String>) {
+        let url = url.map(|url| url.trim_end_matches('/').to_owned());
+        if url.is_some() {
+            self.metrics.add(&ESPLORA_FAILURES, &[("source", "fees")], 0);
+        }
+        *self.url.lock_or_recover() = url;
+    }
+
+    /// The last minimum fee rate in sat/kW of the backend mempool,
+    /// `None` when the backend was never able to return it.
+    pub fn mempool_min_fee_rate(&self) -> Option<u32> {
+        *self.mempool_min_fee_rate.lock_or_recover()
+    }
+
+    /// Unix timestamp of the last successful refresh.
+    pub fn updated_at(&self) -> Option<u64> {
+        *self.updated_at.lock_or_recover()
+    }
+
+    /// The estimations are fresh when the last refresh did not fail,
+    /// so they are not older than two refresh intervals.
+    pub fn is_fresh(&self) -> bool {
+        self.updated_at().is_some_and(|updated_at| {
+            unix_now().saturating_sub(updated_at) <= 2 * self.refresh_interval
+        })
+    }
+
+    /// The fee rate in sat/kW of the slowest target that confirms
+    /// within `blocks`, or of the fastest one.
+    pub fn fee_rate_for_blocks(&self, blocks: u16) -> u32 {
+        let target = CONFIRMATION_TARGETS
+            .iter()
+            .filter(|target| block_target(**target) <= blocks)
+            .max_by_key(|target| block_target(**target))
+            .copied()
+            .unwrap_or(ConfirmationTarget::OnChainSweep);
+        self.fee_rate(target)
+    }
+
+    /// Fetch the new estimations, the targets that can not be
+    /// estimated keep the last fee rate.
+    pub fn refresh(&self) -> error::Result<()> {
+        match self.backend.minimum_mempool_fee() {
+            Ok(fee) => *self.mempool_min_fee_rate.lock_or_recover() = Some(fee),
+            Err(err) => {
+                log::debug!(target: "lampo", "impossible fetch the mempool minimum fee: {err}")
+            }
+        }
+        let url = self.url.lock_or_recover().clone();
+        let fees = match url {
+            Some(ref url) => self.fetch_esplora(url).map_err(|err| {
+                self.metrics.inc(&ESPLORA_FAILURES, &[("source", "fees")]);
+                err
+            })?,
+            None => self.fetch_backend()?,
+        };
+        self.cache.lock_or_recover().extend(fees);
+        *self.updated_at.lock_or_recover() = Some(unix_now());
+        Ok(())
+    }
+
+    fn fetch_esplora(&self, url: &str) -> error::Result<HashMap<ConfirmationTarget, u32>> {
+        let url = format!("{url}/fee-estimates");
+        let response = minreq::get(&url)
+            .with_timeout(FETCH_TIMEOUT.as_secs())
+            .send()?;
+        if response.status_code != 200 {
+            error::bail!(
+                "`{url}` replied with `{} {}`",
+                response.status_code,
+                response.reason_phrase
+            );
+        }
+        let estimates: HashMap<String, f64> = json::from_slice(response.as_bytes())?;
+        let mut fees = HashMap::new();
+        for target in CONFIRMATION_TARGETS {
+            let Some(fee) = esplora_estimate(&estimates, block_target(target)) else {
+                error::bail!("`{url}` returned no estimations");
+            };
+            // sat/vB to sat/kW
+            fees.insert(target, (fee * 250.0).round() as u32);
+        }
+        Ok(fees)
+    }
+
+    fn fetch_backend(&self) -> error::Result<HashMap<ConfirmationTarget, u32>> {
+        let mut fees = HashMap::new();
+        for target in CONFIRMATION_TARGETS {
+            let fee = match target {
+                ConfirmationTarget::MinAllowedAnchorChannelRemoteFee => {
+                    self.backend.minimum_mempool_fee()
+                }
+                _ => self.backend.fee_rate_estimation(block_target(target) as u64),
+            };
+            match fee {
+                Ok(fee) if fee > 0 => {
+                    fees.insert(target, fee);
+                }
+                Ok(_) => log::warn!(
+                    target: "lampo",
+                    "the backend estimated a zero fee for `{}`",
+                    target_name(target)
+                ),
+                Err(err) => log::warn!(
+                    target: "lampo",
+                    "impossible estimate the fee for `{}`: {err}",
+                    target_name(target)
+                ),
+            }
+        }
+        if fees.is_empty() {
+            error::bail!("the backend was not able to estimate the fees");
+        }
+        Ok(fees)
+    }
+
+    /// Refresh the estimations every `interval`.
+    pub fn start(self: Arc<Self>, interval: Duration) -> std::thread::JoinHandle<()> {
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            if let Err(err) = self.refresh() {
+                log::warn!(
+                    target: "lampo",
+                    "impossible refresh the fee estimations, using the last ones: {err}"
+                );
+            }
+        })
+    }
+
+    pub fn fee_rates(&self) -> response::FeeRates {
+        let fee_rates = CONFIRMATION_TARGETS
+            .iter()
+            .map(|target| response::FeeRate {
+                target: target_name(*target).to_owned(),
+                blocks: block_target(*target),
+                sat_per_kw: self.fee_rate(*target),
+            })
+            .collect();
+        let (min, max) = self.limits();
+        response::FeeRates {
+            source: if self.url.lock_or_recover().is_some() {
+                "esplora".to_owned()
+            } else {
+                "backend".to_owned()
+            },
+            updated_at: self.updated_at(),
+            min_sat_per_kw: min,
+            max_sat_per_kw: max,
+            fee_rates,
+        }
+    }
+}
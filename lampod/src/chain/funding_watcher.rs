@@ -0,0 +1,85 @@
+//! Funding confirmations watcher.
+//!
+//! Ldk tells us nothing while the funding transaction of a channel
+//! gets confirmations, so we track the funding of the pending channels
+//! and report each new confirmation until the channel is ready. The
+//! watcher only gives visibility, the channel is still made ready by
+//! the ldk flow.
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use lampo_common::bitcoin::OutPoint;
+use lampo_common::types::ChannelId;
+
+/// The confirmations of the funding of a pending channel.
+#[derive(Clone, Debug)]
+pub struct FundingConfirmation {
+    pub channel_id: ChannelId,
+    pub funding_txo: OutPoint,
+    pub confirmations: u32,
+    /// The confirmations needed to make the channel ready.
+    pub required: u32,
+}
+
+#[derive(Default)]
+pub struct LampoFundingWatcher {
+    /// The funding outpoints of the pending channels, with the last
+    /// confirmations reported.
+    pending: Mutex<HashMap<ChannelId, (OutPoint, u32)>>,
+}
+
+impl LampoFundingWatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Watch the funding of a pending channel, the channels already
+    /// watched are not touched.
+    pub fn watch(&self, channel_id: ChannelId, funding_txo: OutPoint) {
+        self.pending
+            .lock()
+            .unwrap()
+            .entry(channel_id)
+            .or_insert((funding_txo, 0));
+    }
+
+    /// Stop watching the funding of the channel, e.g. when it is ready.
+    pub fn unwatch(&self, channel_id: &ChannelId) {
+        self.pending.lock().unwrap().remove(channel_id);
+    }
+
+    pub fn is_watched(&self, channel_id: &ChannelId) -> bool {
+        self.pending.lock().unwrap().contains_key(channel_id)
+    }
+
+    /// Stop watching the channels that are not in `channels` anymore,
+    /// e.g. because they were closed before they were ready.
+    pub fn retain(&self, channels: &[ChannelId]) {
+        self.pending
+            .lock()
+            .unwrap()
+            .retain(|channel_id, _| channels.contains(channel_id));
+    }
+
+    /// Record the confirmations of the funding of `channel_id`, and
+    /// return the progress when they changed since the last report.
+    pub fn confirmed(
+        &self,
+        channel_id: &ChannelId,
+        confirmations: u32,
+        required: u32,
+    ) -> Option<FundingConfirmation> {
+        let mut pending = self.pending.lock().unwrap();
+        let (funding_txo, reported) = pending.get_mut(channel_id)?;
+        if *reported == confirmations {
+            return None;
+        }
+        *reported = confirmations;
+        Some(FundingConfirmation {
+            channel_id: *channel_id,
+            funding_txo: *funding_txo,
+            confirmations,
+            required,
+        })
+    }
+}
@@ -1,7 +1,21 @@
 //! Chain module implementation that contains all the code related to the blockchain communication.
 mod blockchain;
+mod broadcaster;
+mod fee_estimator;
+mod funding_watcher;
+mod reorg;
+mod utxo_lookup;
+mod wallet_source;
+mod wallet_sync;
 
 pub use lampo_common::bitcoin::Network;
 pub use lampo_common::wallet::WalletManager;
 
 pub use blockchain::LampoChainManager;
+pub use broadcaster::LampoBroadcaster;
+pub use fee_estimator::{parse_target, LampoFeeEstimator};
+pub use funding_watcher::{FundingConfirmation, LampoFundingWatcher};
+pub use reorg::{LampoChainTracker, TipUpdate};
+pub use utxo_lookup::LampoUtxoLookup;
+pub use wallet_source::LampoWalletSource;
+pub use wallet_sync::LampoWalletSync;
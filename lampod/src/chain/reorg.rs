@@ -0,0 +1,156 @@
+//! Reorg detection.
+//!
+//! The backends notify only the new tip of the chain, so we track the
+//! last blocks of the best chain and, when the new tip does not extend
+//! them, we walk back to the fork point to know which blocks were
+//! disconnected. The transactions confirmed inside them are unconfirmed
+//! and watched until they are mined again.
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use lampo_common::backend::{Backend, BlockData, BlockHash};
+use lampo_common::bitcoin::block::Header;
+use lampo_common::bitcoin::{Transaction, Txid};
+use lampo_common::error;
+
+/// Number of blocks tracked, the reorgs deeper than this are not
+/// handled.
+pub const MAX_TRACKED_BLOCKS: usize = 144;
+
+/// How a new tip changes the chain tracked.
+#[derive(Debug)]
+pub enum TipUpdate {
+    /// The tip extends the chain, the blocks from the height `from`
+    /// are new.
+    Connected { from: u32 },
+    /// The tip is already known.
+    Known,
+    /// The blocks `disconnected` left the best chain, that forks from
+    /// ours at the block `fork`.
+    Reorg {
+        fork: Header,
+        fork_height: u32,
+        disconnected: Vec<BlockHash>,
+    },
+}
+
+pub struct LampoChainTracker {
+    backend: Arc<dyn Backend>,
+    /// The last blocks of the best chain, by height.
+    blocks: Mutex<BTreeMap<u32, BlockHash>>,
+    /// The transactions unconfirmed by a reorg, waiting to be
+    /// mined again.
+    reorged: Mutex<HashSet<Txid>>,
+}
+
+impl LampoChainTracker {
+    pub fn new(backend: Arc<dyn Backend>) -> Self {
+        Self {
+            backend,
+            blocks: Mutex::new(BTreeMap::new()),
+            reorged: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Track the new tip of the best chain, and return the blocks
+    /// disconnected if it does not extend our chain.
+    pub fn update_tip(&self, header: &Header, height: u32) -> error::Result<TipUpdate> {
+        let hash = header.block_hash();
+        let mut blocks = self.blocks.lock().unwrap();
+        let Some((&tip_height, &tip_hash)) = blocks.last_key_value() else {
+            blocks.insert(height, hash);
+            return Ok(TipUpdate::Connected { from: height });
+        };
+        if tip_hash == hash {
+            return Ok(TipUpdate::Known);
+        }
+        let extends = if height == tip_height + 1 {
+            header.prev_blockhash == tip_hash
+        } else {
+            // the backend skipped some blocks
+            height > tip_height && self.backend.get_block_hash_at(tip_height)? == tip_hash
+        };
+        if extends {
+            blocks.insert(height, hash);
+            while blocks.len() > MAX_TRACKED_BLOCKS {
+                blocks.pop_first();
+            }
+            return Ok(TipUpdate::Connected {
+                from: tip_height + 1,
+            });
+        }
+
+        // walk back to the last block that is still in the best chain
+        let mut fork = None;
+        for (&block_height, &block_hash) in blocks.iter().rev() {
+            let in_best_chain = match block_height.cmp(&height) {
+                Ordering::Greater => false,
+                Ordering::Equal => block_hash == hash,
+                Ordering::Less => self.backend.get_block_hash_at(block_height)? == block_hash,
+            };
+            if in_best_chain {
+                fork = Some((block_height, block_hash));
+                break;
+            }
+        }
+        let Some((fork_height, fork_hash)) = fork else {
+            // start again from the new tip, there is nothing else to do
+            blocks.clear();
+            blocks.insert(height, hash);
+            error::bail!("the reorg is deeper than the {MAX_TRACKED_BLOCKS} blocks tracked");
+        };
+        let disconnected = blocks.split_off(&(fork_height + 1)).into_values().collect();
+        blocks.insert(height, hash);
+        let fork = match self.backend.get_block(&fork_hash)? {
+            BlockData::FullBlock(block) => block.header,
+            BlockData::HeaderOnly(header) => header,
+        };
+        Ok(TipUpdate::Reorg {
+            fork,
+            fork_height,
+            disconnected,
+        })
+    }
+
+    /// The height and the hash of the tip tracked, if any.
+    pub fn tip(&self) -> Option<(u32, BlockHash)> {
+        self.blocks
+            .lock()
+            .unwrap()
+            .last_key_value()
+            .map(|(height, hash)| (*height, *hash))
+    }
+
+    /// Watch a transaction unconfirmed by a reorg.
+    pub fn watch_reorged(&self, txid: Txid) {
+        self.reorged.lock().unwrap().insert(txid);
+    }
+
+    /// Look for the transactions unconfirmed by a reorg inside the
+    /// blocks from the height `from` to `to`, and return the ones
+    /// mined again with their block, height and index.
+    pub fn find_reorged(
+        &self,
+        from: u32,
+        to: u32,
+    ) -> error::Result<Vec<(Header, u32, usize, Transaction)>> {
+        let mut reorged = self.reorged.lock().unwrap();
+        let mut confirmed = Vec::new();
+        if reorged.is_empty() {
+            return Ok(confirmed);
+        }
+        for height in from..=to {
+            let hash = self.backend.get_block_hash_at(height)?;
+            let BlockData::FullBlock(block) = self.backend.get_block(&hash)? else {
+                error::bail!("the backend does not return the transactions of the block `{hash}`");
+            };
+            for (idx, tx) in block.txdata.iter().enumerate() {
+                if reorged.remove(&tx.txid()) {
+                    confirmed.push((block.header, height, idx, tx.clone()));
+                }
+            }
+        }
+        Ok(confirmed)
+    }
+}
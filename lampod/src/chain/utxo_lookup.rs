@@ -0,0 +1,128 @@
+//! Validation of the gossip channel announcements.
+//!
+//! Ldk asks the funding output of every channel announced, so a peer
+//! can not fill our graph with channels that do not exist. The lookups
+//! are resolved in background by a single worker that waits between
+//! two requests to the backend, so a peer that floods us with
+//! announcements does not flood the backend too. The results are
+//! cached, because the same channel is announced by many peers.
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use lampo_common::backend::Backend;
+use lampo_common::bitcoin::blockdata::constants::ChainHash;
+use lampo_common::bitcoin::TxOut;
+use lampo_common::chan;
+use lampo_common::ldk::routing::utxo::{UtxoFuture, UtxoLookupError, UtxoResult};
+use lampo_common::sync::MutexExt;
+
+use crate::ln::LampoGraph;
+
+/// Time waited between two requests to the backend.
+const LOOKUP_INTERVAL: Duration = Duration::from_millis(100);
+/// Number of lookups kept in the cache.
+const MAX_CACHED_LOOKUPS: usize = 1024;
+
+type LookupResult = Result<TxOut, UtxoLookupError>;
+
+pub struct LampoUtxoLookup {
+    backend: Arc<dyn Backend>,
+    chain_hash: ChainHash,
+    /// The graph where the lookups are resolved, set when the
+    /// channel manager is built.
+    graph: Mutex<Option<Arc<LampoGraph>>>,
+    cache: Mutex<(HashMap<u64, LookupResult>, VecDeque<u64>)>,
+    /// The lookups waiting for the worker, started by the first one.
+    queue: Mutex<Option<chan::Sender<(u64, UtxoFuture)>>>,
+}
+
+impl LampoUtxoLookup {
+    pub fn new(backend: Arc<dyn Backend>, chain_hash: ChainHash) -> Self {
+        Self {
+            backend,
+            chain_hash,
+            graph: Mutex::new(None),
+            cache: Mutex::new((HashMap::new(), VecDeque::new())),
+            queue: Mutex::new(None),
+        }
+    }
+
+    pub fn set_graph(&self, graph: Arc<LampoGraph>) {
+        *self.graph.lock_or_recover() = Some(graph);
+    }
+
+    fn cached(&self, scid: u64) -> Option<LookupResult> {
+        self.cache.lock_or_recover().0.get(&scid).cloned()
+    }
+
+    fn cache(&self, scid: u64, result: LookupResult) {
+        let (results, order) = &mut *self.cache.lock_or_recover();
+        if results.insert(scid, result).is_none() {
+            order.push_back(scid);
+        }
+        while order.len() > MAX_CACHED_LOOKUPS {
+            if let Some(oldest) = order.pop_front() {
+                results.remove(&oldest);
+            }
+        }
+    }
+
+    /// Look for the output of `scid` inside the best chain, it must
+    /// be unspent.
+    fn lookup(&self, scid: u64) -> LookupResult {
+        let height = (scid >> 40) as u32;
+        let tx_index = ((scid >> 16) & 0xFFFFFF) as u32;
+        let vout = (scid & 0xFFFF) as u16;
+        match self.backend.get_output_by_scid(height, tx_index, vout) {
+            Ok(Some(output)) => Ok(output),
+            Ok(None) => Err(UtxoLookupError::UnknownTx),
+            Err(err) => {
+                log::warn!(target: "gossip", "impossible look for the channel `{scid}`: {err}");
+                Err(UtxoLookupError::UnknownTx)
+            }
+        }
+    }
+
+    fn start(self: &Arc<Self>) -> chan::Sender<(u64, UtxoFuture)> {
+        let (sender, receiver) = chan::unbounded::<(u64, UtxoFuture)>();
+        let lookup = self.clone();
+        std::thread::spawn(move || {
+            while let Ok((scid, future)) = receiver.recv() {
+                let result = match lookup.cached(scid) {
+                    Some(result) => result,
+                    None => {
+                        let result = lookup.lookup(scid);
+                        lookup.cache(scid, result.clone());
+                        std::thread::sleep(LOOKUP_INTERVAL);
+                        result
+                    }
+                };
+                if result.is_err() {
+                    log::debug!(target: "gossip", "the channel `{scid}` does not exist, ignoring it");
+                }
+                match lookup.graph.lock_or_recover().as_ref() {
+                    Some(graph) => future.resolve_without_forwarding(graph, result),
+                    None => log::warn!(target: "gossip", "no graph where to resolve the channel `{scid}`"),
+                }
+            }
+        });
+        sender
+    }
+
+    pub fn get_utxo(self: &Arc<Self>, chain_hash: &ChainHash, scid: u64) -> UtxoResult {
+        if *chain_hash != self.chain_hash {
+            return UtxoResult::Sync(Err(UtxoLookupError::UnknownChain));
+        }
+        if let Some(result) = self.cached(scid) {
+            return UtxoResult::Sync(result);
+        }
+        let future = UtxoFuture::new();
+        let mut queue = self.queue.lock_or_recover();
+        let sender = queue.get_or_insert_with(|| self.start());
+        if sender.send((scid, future.clone())).is_err() {
+            return UtxoResult::Sync(Err(UtxoLookupError::UnknownTx));
+        }
+        UtxoResult::Async(future)
+    }
+}
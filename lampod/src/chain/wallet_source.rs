@@ -0,0 +1,46 @@
+//! Wallet source used by ldk to fund and sign the
+//! transactions that bump the fee of a channel package,
+//! and to receive the outputs swept after a channel close.
+use std::sync::Arc;
+
+use lampo_common::bitcoin::psbt::PartiallySignedTransaction;
+use lampo_common::bitcoin::{ScriptBuf, Transaction};
+use lampo_common::ldk::events::bump_transaction::{Utxo, WalletSource};
+use lampo_common::ldk::sign::ChangeDestinationSource;
+use lampo_common::wallet::WalletManager;
+
+pub struct LampoWalletSource {
+    wallet_manager: Arc<dyn WalletManager>,
+}
+
+impl LampoWalletSource {
+    pub fn new(wallet_manager: Arc<dyn WalletManager>) -> Self {
+        Self { wallet_manager }
+    }
+}
+
+impl WalletSource for LampoWalletSource {
+    fn list_confirmed_utxos(&self) -> Result<Vec<Utxo>, ()> {
+        self.wallet_manager.list_confirmed_utxos().map_err(|err| {
+            log::error!(target: "wallet", "impossible list the confirmed utxos: {err}");
+        })
+    }
+
+    fn get_change_script(&self) -> Result<ScriptBuf, ()> {
+        self.wallet_manager.get_change_script().map_err(|err| {
+            log::error!(target: "wallet", "impossible get a change script: {err}");
+        })
+    }
+
+    fn sign_psbt(&self, psbt: PartiallySignedTransaction) -> Result<Transaction, ()> {
+        self.wallet_manager.sign_psbt(psbt).map_err(|err| {
+            log::error!(target: "wallet", "impossible sign the psbt: {err}");
+        })
+    }
+}
+
+impl ChangeDestinationSource for LampoWalletSource {
+    fn get_change_destination_script(&self) -> Result<ScriptBuf, ()> {
+        WalletSource::get_change_script(self)
+    }
+}
@@ -0,0 +1,273 @@
+//! Background task that keeps the wallet in sync with the chain.
+//!
+//! When the backend notifies the new blocks the wallet is synced
+//! only when a new block arrives, otherwise (e.g. esplora) the
+//! wallet is polled at a fixed interval. With the compact block
+//! filters the wallet is synced with the blocks that match its
+//! scripts.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use lampo_common::backend::Backend;
+use lampo_common::bitcoin::absolute::Height;
+use lampo_common::chan;
+use lampo_common::event::onchain::OnChainEvent;
+use lampo_common::event::Event;
+use lampo_common::handler::Handler;
+use lampo_common::metrics::{Metrics, WALLET_SYNC_FAILURES};
+use lampo_common::sync::MutexExt;
+use lampo_common::wallet::{SyncReport, WalletManager};
+
+use crate::actions::handler::LampoHandler;
+
+/// How often the task checks if it was stopped.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The handler that is notified when the wallet is synced.
+type SyncNotifier = Arc<Mutex<Option<Arc<LampoHandler>>>>;
+/// When the wallet was synced the last time.
+type SyncedAt = Arc<Mutex<Option<Instant>>>;
+
+pub struct LampoWalletSync {
+    wallet: Arc<dyn WalletManager>,
+    stop: Arc<AtomicBool>,
+    handler: SyncNotifier,
+    synced_at: SyncedAt,
+    metrics: Arc<Metrics>,
+}
+
+impl LampoWalletSync {
+    pub fn new(wallet: Arc<dyn WalletManager>, metrics: Arc<Metrics>) -> Self {
+        Self {
+            wallet,
+            stop: Arc::new(AtomicBool::new(false)),
+            handler: Arc::new(Mutex::new(None)),
+            synced_at: Arc::new(Mutex::new(None)),
+            metrics,
+        }
+    }
+
+    pub fn set_handler(&self, handler: Arc<LampoHandler>) {
+        *self.handler.lock_or_recover() = Some(handler);
+    }
+
+    /// The time since the last successful sync, `None` when the
+    /// wallet was never synced.
+    pub fn synced_since(&self) -> Option<Duration> {
+        self.synced_at
+            .lock_or_recover()
+            .map(|synced_at| synced_at.elapsed())
+    }
+
+    /// Spawn the sync task, when `events` is `Some` the wallet is
+    /// synced on the new blocks, otherwise every `interval`.
+    pub fn start(
+        &self,
+        events: Option<chan::Receiver<Event>>,
+        interval: Duration,
+    ) -> JoinHandle<()> {
+        let wallet = self.wallet.clone();
+        let stop = self.stop.clone();
+        let handler = self.handler.clone();
+        let synced_at = self.synced_at.clone();
+        let metrics = self.metrics.clone();
+        std::thread::spawn(move || match events {
+            Some(events) => {
+                Self::sync_on_new_blocks(wallet, handler, synced_at, metrics, stop, events)
+            }
+            None => Self::poll(wallet, handler, synced_at, metrics, stop, interval),
+        })
+    }
+
+    /// Spawn the sync task that applies to the wallet the blocks
+    /// matched by the compact block filters of `backend`.
+    pub fn start_with_filters(
+        &self,
+        backend: Arc<dyn Backend>,
+        events: chan::Receiver<Event>,
+    ) -> JoinHandle<()> {
+        let wallet = self.wallet.clone();
+        let stop = self.stop.clone();
+        let handler = self.handler.clone();
+        let synced_at = self.synced_at.clone();
+        let metrics = self.metrics.clone();
+        std::thread::spawn(move || {
+            Self::sync_with_filters(wallet, handler, synced_at, metrics, backend, stop, events)
+        })
+    }
+
+    /// Stop the sync task, the sync in progress (if any) is completed.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+
+    /// Emit the event of the wallet synced at `height`.
+    fn notify(handler: &SyncNotifier, height: u32) {
+        let Ok(height) = Height::from_consensus(height) else {
+            return;
+        };
+        if let Some(ref handler) = *handler.lock_or_recover() {
+            handler.emit(Event::OnChain(OnChainEvent::WalletSynced(height)));
+        }
+    }
+
+    fn sync(
+        wallet: &Arc<dyn WalletManager>,
+        handler: &SyncNotifier,
+        synced_at: &SyncedAt,
+        metrics: &Metrics,
+    ) {
+        match wallet.sync() {
+            Ok(report) => {
+                Self::log_report(&report);
+                *synced_at.lock_or_recover() = Some(Instant::now());
+            }
+            Err(err) => {
+                log::warn!(target: "wallet", "wallet sync failed: {err}");
+                metrics.inc(&WALLET_SYNC_FAILURES, &[]);
+                return;
+            }
+        }
+        if let Ok(Some(height)) = wallet.synced_height() {
+            Self::notify(handler, height);
+        }
+    }
+
+    fn log_report(report: &SyncReport) {
+        if report.is_empty() {
+            return;
+        }
+        log::info!(
+            target: "wallet",
+            "wallet synced: {} new txs, {} confirmed txs, tip {:?}, reorged {}",
+            report.new_txs.len(),
+            report.confirmed_txs.len(),
+            report.new_tip_height,
+            report.reorged
+        );
+    }
+
+    fn sync_on_new_blocks(
+        wallet: Arc<dyn WalletManager>,
+        handler: SyncNotifier,
+        synced_at: SyncedAt,
+        metrics: Arc<Metrics>,
+        stop: Arc<AtomicBool>,
+        events: chan::Receiver<Event>,
+    ) {
+        log::info!(target: "wallet", "syncing the wallet on the new blocks");
+        Self::sync(&wallet, &handler, &synced_at, &metrics);
+        while !stop.load(Ordering::SeqCst) {
+            match events.recv_timeout(STOP_CHECK_INTERVAL) {
+                Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, mut height)))) => {
+                    // while the backend catches up with the chain it
+                    // notifies a block after the other, so we sync once
+                    // for all the blocks that are already notified.
+                    while let Ok(event) = events.try_recv() {
+                        if let Event::OnChain(OnChainEvent::NewBestBlock((_, new_height))) = event {
+                            height = new_height;
+                        }
+                    }
+                    log::debug!(target: "wallet", "new block at height {height}, syncing");
+                    Self::sync(&wallet, &handler, &synced_at, &metrics);
+                }
+                Ok(_) | Err(chan::RecvTimeoutError::Timeout) => continue,
+                Err(chan::RecvTimeoutError::Disconnected) => {
+                    log::warn!(target: "wallet", "events channel closed, stop syncing the wallet");
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Give to the backend the scripts of the wallet, the new ones
+    /// (e.g. a new address) are watched from the next block.
+    fn watch(wallet: &Arc<dyn WalletManager>, backend: &Arc<dyn Backend>, from: Option<u32>) {
+        match wallet.watched_scripts() {
+            Ok(scripts) => backend.watch_scripts(scripts, from),
+            Err(err) => log::warn!(target: "wallet", "impossible get the scripts to watch: {err}"),
+        }
+    }
+
+    /// The height where the first scan starts, the genesis when the
+    /// wallet has no birthday.
+    fn birthday(wallet: &Arc<dyn WalletManager>) -> u32 {
+        match wallet.get_birthday() {
+            Ok(birthday) => birthday.unwrap_or_default(),
+            Err(err) => {
+                log::warn!(target: "wallet", "impossible get the wallet birthday, scanning from the genesis: {err}");
+                0
+            }
+        }
+    }
+
+    fn sync_with_filters(
+        wallet: Arc<dyn WalletManager>,
+        handler: SyncNotifier,
+        synced_at: SyncedAt,
+        metrics: Arc<Metrics>,
+        backend: Arc<dyn Backend>,
+        stop: Arc<AtomicBool>,
+        events: chan::Receiver<Event>,
+    ) {
+        log::info!(target: "wallet", "syncing the wallet with the compact block filters");
+        // scan again the blocks after the last one known by the wallet,
+        // or from its birthday when the wallet was never synced.
+        let from = match wallet.synced_height() {
+            Ok(Some(height)) => height + 1,
+            Ok(None) => Self::birthday(&wallet),
+            Err(err) => {
+                log::warn!(target: "wallet", "impossible get the wallet height, scanning from the genesis: {err}");
+                0
+            }
+        };
+        Self::watch(&wallet, &backend, Some(from));
+        while !stop.load(Ordering::SeqCst) {
+            match events.recv_timeout(STOP_CHECK_INTERVAL) {
+                Ok(Event::OnChain(OnChainEvent::BlockMatched((block, height)))) => {
+                    let height = height.to_consensus_u32();
+                    log::debug!(target: "wallet", "block `{}` at height {height} matched, applying it", block.block_hash());
+                    match wallet.apply_block(&block, height) {
+                        Ok(report) => {
+                            Self::log_report(&report);
+                            *synced_at.lock_or_recover() = Some(Instant::now());
+                            Self::notify(&handler, height);
+                        }
+                        Err(err) => {
+                            log::warn!(target: "wallet", "wallet sync failed: {err}");
+                            metrics.inc(&WALLET_SYNC_FAILURES, &[]);
+                        }
+                    }
+                }
+                Ok(_) | Err(chan::RecvTimeoutError::Timeout) => {}
+                Err(chan::RecvTimeoutError::Disconnected) => {
+                    log::warn!(target: "wallet", "events channel closed, stop syncing the wallet");
+                    break;
+                }
+            }
+            // a block or the user can reveal new scripts of the wallet
+            Self::watch(&wallet, &backend, None);
+        }
+    }
+
+    fn poll(
+        wallet: Arc<dyn WalletManager>,
+        handler: SyncNotifier,
+        synced_at: SyncedAt,
+        metrics: Arc<Metrics>,
+        stop: Arc<AtomicBool>,
+        interval: Duration,
+    ) {
+        log::info!(target: "wallet", "syncing the wallet every {} secs", interval.as_secs());
+        let mut last_sync: Option<Instant> = None;
+        while !stop.load(Ordering::SeqCst) {
+            if !last_sync.is_some_and(|last| last.elapsed() < interval) {
+                Self::sync(&wallet, &handler, &synced_at, &metrics);
+                last_sync = Some(Instant::now());
+            }
+            std::thread::sleep(STOP_CHECK_INTERVAL);
+        }
+    }
+}
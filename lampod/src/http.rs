@@ -0,0 +1,652 @@
+//! HTTP gateway for the JSON RPC 2.0 commands.
+//!
+//! Every command is served at `/v1/<command>`, with `POST` and the
+//! params in a JSON body, or with `GET` and the params in the query
+//! string for the read only commands. The raw JSON RPC 2.0 requests
+//! are accepted at `/rpc`, also in a batch, and the description of the commands is at
+//! `/v1/openapi.json`.
+//!
+//! The requests go to the same handler of the unix socket, so they are
+//! authenticated with the token of the `Authorization: Bearer` header.
+//! The events of the node are streamed by the WebSocket at `/v1/ws`,
+//! and the metrics are scraped by Prometheus at `/metrics`.
+//!
+//! The probes of the orchestrators call `/healthz` (the node is alive)
+//! and `/readyz` (the node can serve the payments) without a token, and
+//! receive `503` with the failing checks.
+pub mod openapi;
+mod ws;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use lampo_common::auth::READ_ONLY_METHODS;
+use lampo_common::conf::LampoConf;
+use lampo_common::error;
+use lampo_common::error::{
+    INVALID_PARAMS, METHOD_NOT_FOUND, NOT_IMPLEMENTED, PERMISSION_DENIED, UNAUTHORIZED,
+};
+use lampo_common::json;
+use lampo_jsonrpc::errors::RpcError;
+use lampo_jsonrpc::json_rpc2::{Message, Request, Response};
+use lampo_jsonrpc::Handler;
+
+use crate::LampoDaemon;
+
+/// JSON RPC 2.0 code for a body that is not JSON.
+const PARSE_ERROR: i32 = -32700;
+/// JSON RPC 2.0 code for a request that is not valid.
+const INVALID_REQUEST: i32 = -32600;
+
+/// Maximum size in bytes of the request line and the headers.
+const MAX_HEAD_BYTES: u64 = 8 * 1024;
+/// A client that does not send its request in time is disconnected.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often the listener checks if the node is stopping.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The HTTP gateway, every connection is served by its own thread.
+pub struct HttpServer {
+    listener: TcpListener,
+    tls: Option<Arc<rustls::ServerConfig>>,
+    handler: Arc<Handler<LampoDaemon>>,
+    cors_origins: Vec<String>,
+    max_body_bytes: usize,
+}
+
+impl HttpServer {
+    pub fn new(conf: &LampoConf, handler: Arc<Handler<LampoDaemon>>) -> error::Result<Self> {
+        let Some(bind) = conf.http_bind else {
+            error::bail!("the HTTP gateway is disabled, set `http-bind` to enable it");
+        };
+        let tls = match (&conf.http_tls_cert, &conf.http_tls_key) {
+            (Some(cert), Some(key)) => Some(Arc::new(tls_config(cert, key)?)),
+            _ => None,
+        };
+        let listener = TcpListener::bind(bind)
+            .map_err(|err| error::anyhow!("impossible bind the HTTP gateway on `{bind}`: {err}"))?;
+        Ok(Self {
+            listener,
+            tls,
+            handler,
+            cors_origins: conf.http_cors_origins.clone(),
+            max_body_bytes: conf.http_max_body_bytes,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serve the requests until the handler is stopped.
+    pub fn spawn(self) -> JoinHandle<io::Result<()>> {
+        std::thread::spawn(move || self.listen())
+    }
+
+    fn listen(self) -> io::Result<()> {
+        self.listener.set_nonblocking(true)?;
+        log::info!(target: "http", "HTTP gateway listening on `{}`", self.local_addr()?);
+        let server = Arc::new(self);
+        while !server.handler.is_stopped() {
+            match server.listener.accept() {
+                Ok((stream, addr)) => {
+                    let server = server.clone();
+                    std::thread::spawn(move || {
+                        if let Err(err) = server.serve(stream) {
+                            log::debug!(target: "http", "connection with `{addr}` failed: {err}");
+                        }
+                    });
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(ACCEPT_INTERVAL)
+                }
+                Err(err) => {
+                    log::warn!(target: "http", "impossible accept a connection: {err}");
+                    std::thread::sleep(ACCEPT_INTERVAL);
+                }
+            }
+        }
+        log::info!(target: "http", "stopping the HTTP gateway");
+        Ok(())
+    }
+
+    fn serve(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let Some(tls) = &self.tls else {
+            return self.serve_stream(&mut stream);
+        };
+        let conn = rustls::ServerConnection::new(tls.clone())
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        let mut stream = rustls::StreamOwned::new(conn, stream);
+        self.serve_stream(&mut stream)?;
+        stream.conn.send_close_notify();
+        stream.flush()
+    }
+
+    fn serve_stream<S: Connection>(&self, stream: &mut S) -> io::Result<()> {
+        let request = match read_request(&mut *stream, self.max_body_bytes)? {
+            Ok(request) => request,
+            Err(response) => return response.write(stream),
+        };
+        if request.path == "/v1/ws" && request.method == "GET" && ws::is_upgrade(&request) {
+            return ws::serve(self, &request, stream);
+        }
+        self.respond(&request).write(stream)
+    }
+
+    fn respond(&self, request: &HttpRequest) -> HttpResponse {
+        let origin = request
+            .header("origin")
+            .and_then(|origin| self.allowed_origin(origin));
+        let mut response = if request.method == "OPTIONS" {
+            preflight(origin.is_some())
+        } else {
+            self.route(request)
+        };
+        if let Some(origin) = origin {
+            response.header("Access-Control-Allow-Origin", &origin);
+            response.header("Vary", "Origin");
+        }
+        response
+    }
+
+    /// The value of `Access-Control-Allow-Origin` for the `origin`, if
+    /// the origin is allowed.
+    fn allowed_origin(&self, origin: &str) -> Option<String> {
+        if self.cors_origins.iter().any(|allowed| allowed == "*") {
+            return Some("*".to_owned());
+        }
+        self.cors_origins
+            .iter()
+            .find(|allowed| allowed.as_str() == origin)
+            .cloned()
+    }
+
+    fn route(&self, request: &HttpRequest) -> HttpResponse {
+        let token = request.bearer_token();
+        if request.path == "/rpc" {
+            if request.method != "POST" {
+                return HttpResponse::method_not_allowed("POST");
+            }
+            return self.json_rpc(&request.body, token);
+        }
+        if request.path == "/metrics" {
+            if request.method != "GET" {
+                return HttpResponse::method_not_allowed("GET");
+            }
+            return match self.call("getmetrics", json::json!({}), token) {
+                Ok(result) => HttpResponse::prometheus(result["text"].as_str().unwrap_or_default()),
+                Err(err) => HttpResponse::rpc_error(&err),
+            };
+        }
+        if request.path == "/healthz" || request.path == "/readyz" {
+            if request.method != "GET" {
+                return HttpResponse::method_not_allowed("GET");
+            }
+            return self.health(request.path == "/readyz");
+        }
+        let Some(command) = request.path.strip_prefix("/v1/") else {
+            return HttpResponse::error(404, METHOD_NOT_FOUND, "not found");
+        };
+        if command == "openapi.json" {
+            if request.method != "GET" {
+                return HttpResponse::method_not_allowed("GET");
+            }
+            let served = |method: &str| self.handler.has_rpc(method);
+            return HttpResponse::json(200, &openapi::describe(served, &READ_ONLY_METHODS));
+        }
+        let read_only = READ_ONLY_METHODS.contains(&command);
+        let params = match request.method.as_str() {
+            "POST" => match parse_body(&request.body) {
+                Ok(params) => params,
+                Err(response) => return response,
+            },
+            "GET" if read_only => query_params(request.query.as_deref()),
+            _ if read_only => return HttpResponse::method_not_allowed("GET, POST"),
+            _ => return HttpResponse::method_not_allowed("POST"),
+        };
+        match self.call(command, params, token) {
+            Ok(result) => HttpResponse::json(200, &result),
+            Err(err) => HttpResponse::rpc_error(&err),
+        }
+    }
+
+    /// Serve a raw JSON RPC 2.0 request or a batch of requests, the
+    /// errors of the commands are inside the response like on the
+    /// unix socket.
+    fn json_rpc(&self, body: &[u8], token: Option<&str>) -> HttpResponse {
+        let message: Message<Request<json::Value>> = match json::from_slice(body) {
+            Ok(message) => message,
+            Err(err) => return HttpResponse::error(400, PARSE_ERROR, &format!("{err}")),
+        };
+        let response = match message {
+            Message::Single(request) => {
+                let result = self.call(&request.method, request.params, token);
+                // a notification does not have a response
+                let Some(id) = request.id else {
+                    return HttpResponse::empty(204);
+                };
+                json::to_value(Response::new(
+                    id,
+                    request.jsonrpc,
+                    result.map_err(Into::into),
+                ))
+            }
+            Message::Batch(requests) if requests.is_empty() => {
+                return HttpResponse::error(400, INVALID_REQUEST, "empty batch");
+            }
+            Message::Batch(mut requests) => {
+                for request in requests.iter_mut() {
+                    with_token(&mut request.params, token);
+                }
+                let results = self.handler.run_client_batch(&requests);
+                let responses = requests
+                    .into_iter()
+                    .zip(results)
+                    .filter_map(|(request, result)| {
+                        Some(Response::new(request.id?, request.jsonrpc, result))
+                    })
+                    .collect::<Vec<_>>();
+                if responses.is_empty() {
+                    return HttpResponse::empty(204);
+                }
+                json::to_value(responses)
+            }
+        };
+        match response {
+            Ok(response) => HttpResponse::json(200, &response),
+            Err(err) => HttpResponse::error(500, INVALID_REQUEST, &format!("{err}")),
+        }
+    }
+
+    /// Serve the probes, with `ready` the node must be ready and not
+    /// only alive.
+    fn health(&self, ready: bool) -> HttpResponse {
+        // the probes do not have a token, the checks are not secret
+        let request = Request::new("health", json::json!({}));
+        let health = match self.handler.run_callback(&request) {
+            Some(Ok(health)) => health,
+            Some(Err(err)) => return HttpResponse::rpc_error(&err.into()),
+            None => return HttpResponse::error(404, METHOD_NOT_FOUND, "not found"),
+        };
+        let key = if ready { "ready" } else { "alive" };
+        let status = if health[key].as_bool() == Some(true) {
+            200
+        } else {
+            503
+        };
+        HttpResponse::json(status, &health)
+    }
+
+    fn call(
+        &self,
+        method: &str,
+        mut params: json::Value,
+        token: Option<&str>,
+    ) -> Result<json::Value, RpcError> {
+        with_token(&mut params, token);
+        let request = Request::new(method, params);
+        match self.handler.run_client_callback(&request) {
+            Some(Ok(result)) => Ok(result),
+            Some(Err(err)) => Err(err.into()),
+            None => Err(RpcError {
+                code: METHOD_NOT_FOUND,
+                message: format!("method `{method}` not found"),
+                data: None,
+            }),
+        }
+    }
+}
+
+/// Put the bearer `token` inside the params, unless the params
+/// carry their own token.
+fn with_token(params: &mut json::Value, token: Option<&str>) {
+    if let (Some(token), Some(params)) = (token, params.as_object_mut()) {
+        params
+            .entry("token")
+            .or_insert_with(|| json::Value::String(token.to_owned()));
+    }
+}
+
+/// The HTTP status of a failed command.
+fn status_of(err: &RpcError) -> u16 {
+    match err.code {
+        METHOD_NOT_FOUND => 404,
+        UNAUTHORIZED => 401,
+        PERMISSION_DENIED => 403,
+        NOT_IMPLEMENTED => 501,
+        // the wallet, payment, channel, peer and chain failures are
+        // caused by the request.
+        code if code == INVALID_PARAMS || code > 0 => 400,
+        _ => 500,
+    }
+}
+
+fn preflight(allowed: bool) -> HttpResponse {
+    if !allowed {
+        return HttpResponse::error(403, INVALID_REQUEST, "origin not allowed");
+    }
+    let mut response = HttpResponse::empty(204);
+    response.header("Access-Control-Allow-Methods", "GET, POST, OPTIONS");
+    response.header(
+        "Access-Control-Allow-Headers",
+        "Authorization, Content-Type",
+    );
+    response.header("Access-Control-Max-Age", "600");
+    response
+}
+
+fn tls_config(cert: &str, key: &str) -> error::Result<rustls::ServerConfig> {
+    let mut certs = BufReader::new(
+        File::open(cert).map_err(|err| error::anyhow!("impossible open `{cert}`: {err}"))?,
+    );
+    let certs = rustls_pemfile::certs(&mut certs)?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        error::bail!("no certificate inside `{cert}`");
+    }
+    let mut keys = BufReader::new(
+        File::open(key).map_err(|err| error::anyhow!("impossible open `{key}`: {err}"))?,
+    );
+    let key = loop {
+        match rustls_pemfile::read_one(&mut keys)? {
+            Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => break rustls::PrivateKey(key),
+            Some(_) => continue,
+            None => error::bail!("no private key inside `{key}`"),
+        }
+    };
+    let config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(config)
+}
+
+/// A connection of a client, in plain text or over TLS.
+trait Connection: Read + Write {
+    fn tcp(&self) -> &TcpStream;
+}
+
+impl Connection for TcpStream {
+    fn tcp(&self) -> &TcpStream {
+        self
+    }
+}
+
+impl Connection for rustls::StreamOwned<rustls::ServerConnection, TcpStream> {
+    fn tcp(&self) -> &TcpStream {
+        &self.sock
+    }
+}
+
+pub(crate) struct HttpRequest {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    query: Option<String>,
+    /// The headers with the names in lowercase.
+    headers: HashMap<String, String>,
+    pub(crate) body: Vec<u8>,
+}
+
+impl HttpRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+
+    fn bearer_token(&self) -> Option<&str> {
+        self.header("authorization")?
+            .strip_prefix("Bearer ")
+            .map(str::trim)
+    }
+}
+
+/// Read a request, or the response for a request that is not valid.
+pub(crate) fn read_request<S: Read>(
+    stream: S,
+    max_body_bytes: usize,
+) -> io::Result<Result<HttpRequest, HttpResponse>> {
+    let mut reader = BufReader::new(stream);
+    let mut lines = Vec::new();
+    let mut budget = MAX_HEAD_BYTES;
+    loop {
+        let mut line = String::new();
+        let read = reader.by_ref().take(budget).read_line(&mut line)?;
+        budget -= read as u64;
+        if !line.ends_with('\n') {
+            if budget == 0 {
+                return Ok(Err(HttpResponse::error(
+                    431,
+                    INVALID_REQUEST,
+                    "the request headers are too large",
+                )));
+            }
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            // an empty line before the request line is ignored
+            if lines.is_empty() {
+                continue;
+            }
+            break;
+        }
+        lines.push(line.to_owned());
+    }
+
+    let mut request_line = lines[0].split_whitespace();
+    let (Some(method), Some(target), Some(_version), None) = (
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+    ) else {
+        return Ok(Err(HttpResponse::bad_request("malformed request line")));
+    };
+    let mut headers = HashMap::new();
+    for line in &lines[1..] {
+        let Some((name, value)) = line.split_once(':') else {
+            return Ok(Err(HttpResponse::bad_request("malformed header")));
+        };
+        headers.insert(name.trim().to_lowercase(), value.trim().to_owned());
+    }
+    if headers.contains_key("transfer-encoding") {
+        return Ok(Err(HttpResponse::error(
+            501,
+            INVALID_REQUEST,
+            "the `Transfer-Encoding` is not supported, send the `Content-Length`",
+        )));
+    }
+    let length = match headers
+        .get("content-length")
+        .map(|len| len.parse::<usize>())
+    {
+        Some(Ok(length)) => length,
+        Some(Err(_)) => return Ok(Err(HttpResponse::bad_request("malformed `Content-Length`"))),
+        None => 0,
+    };
+    if length > max_body_bytes {
+        return Ok(Err(HttpResponse::error(
+            413,
+            INVALID_REQUEST,
+            &format!("the body is larger than {max_body_bytes} bytes"),
+        )));
+    }
+    let mut body = Vec::with_capacity(length);
+    reader.take(length as u64).read_to_end(&mut body)?;
+    if body.len() < length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_owned())),
+        None => (target, None),
+    };
+    Ok(Ok(HttpRequest {
+        method: method.to_owned(),
+        path: path.to_owned(),
+        query,
+        headers,
+        body,
+    }))
+}
+
+/// The params of a `POST`, an empty body means no params.
+fn parse_body(body: &[u8]) -> Result<json::Value, HttpResponse> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(json::json!({}));
+    }
+    json::from_slice(body).map_err(|err| HttpResponse::error(400, PARSE_ERROR, &format!("{err}")))
+}
+
+/// The params of a `GET`, the numbers and the booleans are passed as
+/// JSON values and everything else as a string.
+fn query_params(query: Option<&str>) -> json::Value {
+    let mut params = json::Map::new();
+    let pairs = query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty());
+    for pair in pairs {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = percent_decode(value);
+        let value = json::from_str::<json::Value>(&value)
+            .ok()
+            .filter(|value| value.is_number() || value.is_boolean())
+            .unwrap_or(json::Value::String(value));
+        params.insert(percent_decode(key), value);
+    }
+    params.into()
+}
+
+fn percent_decode(value: &str) -> String {
+    let input = value.as_bytes();
+    let mut output = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        let decoded = match input[i] {
+            b'%' => input
+                .get(i + 1..i + 3)
+                .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match (decoded, input[i]) {
+            (Some(byte), _) => {
+                output.push(byte);
+                i += 3;
+            }
+            (None, b'+') => {
+                output.push(b' ');
+                i += 1;
+            }
+            (None, byte) => {
+                output.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+pub(crate) struct HttpResponse {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    fn empty(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub(crate) fn json(status: u16, body: &json::Value) -> Self {
+        let mut response = Self::empty(status);
+        response.header("Content-Type", "application/json");
+        response.body = body.to_string().into_bytes();
+        response
+    }
+
+    /// The metrics in the text format of Prometheus.
+    fn prometheus(text: &str) -> Self {
+        let mut response = Self::empty(200);
+        response.header("Content-Type", "text/plain; version=0.0.4");
+        response.body = text.as_bytes().to_vec();
+        response
+    }
+
+    fn error(status: u16, code: i32, message: &str) -> Self {
+        Self::json(status, &json::json!({ "code": code, "message": message }))
+    }
+
+    /// The response of a failed command.
+    fn rpc_error(err: &RpcError) -> Self {
+        Self::json(
+            status_of(err),
+            &json::json!({ "code": err.code, "message": err.message, "data": err.data }),
+        )
+    }
+
+    fn bad_request(message: &str) -> Self {
+        Self::error(400, INVALID_REQUEST, message)
+    }
+
+    pub(crate) fn method_not_allowed(allow: &str) -> Self {
+        let mut response = Self::error(405, INVALID_REQUEST, "method not allowed");
+        response.header("Allow", allow);
+        response
+    }
+
+    fn header(&mut self, name: &'static str, value: &str) {
+        self.headers.push((name, value.to_owned()));
+    }
+
+    pub(crate) fn write<S: Write>(&self, stream: &mut S) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        // the connection is kept open by an upgrade
+        if self.status != 101 {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+            head.push_str("Connection: close\r\n");
+        }
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(&self.body)?;
+        stream.flush()
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        101 => "Switching Protocols",
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        426 => "Upgrade Required",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        _ => "",
+    }
+}
@@ -0,0 +1,861 @@
+//! OpenAPI description of the HTTP gateway.
+//!
+//! The schemas are generated from the request and response models,
+//! by deserializing each model with a `Deserializer` that records the
+//! fields and their types instead of reading a value.
+use std::collections::HashMap;
+use std::fmt;
+
+use lampo_common::json;
+use lampo_common::json::prelude::serde::de;
+use lampo_common::json::prelude::serde::de::{DeserializeSeed, IntoDeserializer, Visitor};
+use lampo_common::json::DeserializeOwned;
+use lampo_common::model::{request, response};
+
+/// Models nested deeper than this are not described.
+const MAX_DEPTH: usize = 16;
+
+/// A command served by the gateway.
+struct Route {
+    method: &'static str,
+    summary: &'static str,
+    request: fn() -> json::Value,
+    response: fn() -> json::Value,
+}
+
+macro_rules! route {
+    ($method:literal, $summary:literal, $request:ty, $response:ty) => {
+        Route {
+            method: $method,
+            summary: $summary,
+            request: schema_of::<$request>,
+            response: schema_of::<$response>,
+        }
+    };
+}
+
+/// The commands with their models, a command without params or with
+/// a response that is not a model uses a free form `json::Value`.
+fn routes() -> Vec<Route> {
+    vec![
+        route!(
+            "getinfo",
+            "Information about the node",
+            json::Value,
+            response::GetInfo
+        ),
+        route!("stop", "Stop the node", json::Value, json::Value),
+        route!(
+            "reload",
+            "Apply the options of the configuration file that can change at runtime",
+            json::Value,
+            response::Reload
+        ),
+        route!(
+            "listconfigs",
+            "The configuration of the node with the source of every value, without the secrets",
+            json::Value,
+            response::ListConfigs
+        ),
+        route!(
+            "maintenance",
+            "Compact the stores of the node and check the integrity of the channel monitors",
+            json::Value,
+            response::Maintenance
+        ),
+        route!(
+            "minttoken",
+            "Mint a token with a scope",
+            request::MintToken,
+            response::MintToken
+        ),
+        route!(
+            "revoketoken",
+            "Revoke a token",
+            request::RevokeToken,
+            response::RevokeToken
+        ),
+        route!(
+            "connect",
+            "Connect to a peer",
+            request::Connect,
+            response::Connect
+        ),
+        route!(
+            "disconnect",
+            "Disconnect a peer",
+            request::Disconnect,
+            json::Value
+        ),
+        route!(
+            "listpeers",
+            "List the connected peers",
+            json::Value,
+            response::Peers
+        ),
+        route!("ping", "Ping a peer", request::Ping, response::Ping),
+        route!(
+            "setpeerlist",
+            "Allow or deny a peer",
+            request::SetPeerList,
+            response::PeerLists
+        ),
+        route!(
+            "sendonionmessage",
+            "Send an onion message",
+            request::SendOnionMessage,
+            response::SendOnionMessage
+        ),
+        route!(
+            "waitonionmessage",
+            "Wait for an onion message",
+            request::WaitOnionMessage,
+            response::OnionMessage
+        ),
+        route!(
+            "waitevent",
+            "Wait for the node events",
+            request::WaitEvent,
+            response::NotifiedEvents
+        ),
+        route!(
+            "listwebhookdeliveries",
+            "List the deliveries of the events to the webhooks",
+            request::ListWebhookDeliveries,
+            response::WebhookDeliveries
+        ),
+        route!(
+            "datastore",
+            "Store a value for the applications built on the node",
+            request::Datastore,
+            response::DatastoreEntry
+        ),
+        route!(
+            "listdatastore",
+            "List the values of the datastore with a key prefix",
+            request::ListDatastore,
+            response::ListDatastore
+        ),
+        route!(
+            "deldatastore",
+            "Delete a value of the datastore",
+            request::DelDatastore,
+            response::DatastoreEntry
+        ),
+        route!(
+            "getmetrics",
+            "The metrics of the node, also served at `/metrics`",
+            json::Value,
+            response::Metrics
+        ),
+        route!(
+            "health",
+            "The liveness and readiness checks, also served at `/healthz` and `/readyz`",
+            json::Value,
+            response::Health
+        ),
+        route!(
+            "listplugins",
+            "List the plugins started with the node",
+            json::Value,
+            response::Plugins
+        ),
+        route!(
+            "addtower",
+            "Add a watchtower that receives the justice transactions of the channels",
+            request::AddTower,
+            response::Tower
+        ),
+        route!(
+            "listtowers",
+            "List the watchtowers with their session",
+            json::Value,
+            response::Towers
+        ),
+        route!(
+            "towerstatus",
+            "The session with a watchtower and the appointments of every channel",
+            request::TowerStatus,
+            response::TowerStatus
+        ),
+        route!(
+            "towerinfo",
+            "The tower served to the other nodes, with the breaches punished",
+            json::Value,
+            response::TowerInfo
+        ),
+        route!(
+            "listtowerclients",
+            "List the clients of the tower served to the other nodes",
+            json::Value,
+            response::TowerClients
+        ),
+        route!(
+            "deltowerclient",
+            "Forget a client of the tower with its appointments",
+            request::DelTowerClient,
+            response::TowerClients
+        ),
+        route!(
+            "getlog",
+            "The last records of the log kept in memory",
+            request::GetLog,
+            response::Log
+        ),
+        route!(
+            "setloglevel",
+            "Change the log level of a target, or the default one",
+            request::SetLogLevel,
+            response::LogLevels
+        ),
+        route!(
+            "listnodes",
+            "List the nodes of the graph",
+            request::ListNodes,
+            response::GossipNodes
+        ),
+        route!(
+            "listgossipchannels",
+            "List the channels of the graph",
+            request::ListGossipChannels,
+            response::GossipChannels
+        ),
+        route!(
+            "fundchannel",
+            "Open a channel",
+            request::OpenChannel,
+            response::OpenChannel
+        ),
+        route!(
+            "estimateopencost",
+            "Estimate the cost of opening a channel",
+            request::EstimateOpenCost,
+            response::OpenCost
+        ),
+        route!(
+            "newaddr",
+            "Generate an on chain address",
+            json::Value,
+            response::NewAddress
+        ),
+        route!(
+            "getbip21uri",
+            "Generate a BIP21 URI to receive on chain, with an invoice when possible",
+            request::Bip21Uri,
+            response::Bip21Uri
+        ),
+        route!(
+            "unifiedreceive",
+            "Generate an address and an invoice, inside a BIP21 URI",
+            request::UnifiedReceive,
+            response::UnifiedReceive
+        ),
+        route!(
+            "channels",
+            "List the channels",
+            json::Value,
+            response::Channels
+        ),
+        route!(
+            "getchannel",
+            "Get a channel",
+            request::GetChannel,
+            response::ChannelInfo
+        ),
+        route!(
+            "setchannel",
+            "Enable or disable a channel",
+            request::SetChannel,
+            response::Channel
+        ),
+        route!(
+            "autofees-status",
+            "The last evaluation of the automatic fees",
+            json::Value,
+            response::AutofeesStatus
+        ),
+        route!(
+            "listhtlcs",
+            "List the pending HTLCs",
+            request::ListHtlcs,
+            response::Htlcs
+        ),
+        route!(
+            "funds",
+            "List the on chain transactions",
+            json::Value,
+            json::Value
+        ),
+        route!(
+            "listfunds",
+            "List the on chain and channel funds",
+            json::Value,
+            response::ListFunds
+        ),
+        route!(
+            "listtransactions",
+            "List the wallet transactions",
+            json::Value,
+            response::TxHistory
+        ),
+        route!(
+            "invoice",
+            "Create an invoice",
+            request::GenerateInvoice,
+            response::Invoice
+        ),
+        route!(
+            "holdinvoice",
+            "Create a hold invoice",
+            request::GenerateHoldInvoice,
+            response::HoldInvoice
+        ),
+        route!(
+            "settleinvoice",
+            "Settle a hold invoice",
+            request::SettleHoldInvoice,
+            response::HoldInvoice
+        ),
+        route!(
+            "cancelinvoice",
+            "Cancel a hold invoice",
+            request::CancelHoldInvoice,
+            response::HoldInvoice
+        ),
+        route!(
+            "listinvoices",
+            "List the invoices generated by the node, with their label and metadata",
+            request::ListInvoices,
+            response::ListInvoices
+        ),
+        route!(
+            "isinvoicepaid",
+            "Check if an invoice is paid",
+            request::IsInvoicePaid,
+            response::PaymentStatus
+        ),
+        route!(
+            "offer",
+            "Create an offer",
+            request::GenerateOffer,
+            response::Offer
+        ),
+        route!(
+            "decode",
+            "Decode an invoice or an offer",
+            request::DecodeInvoice,
+            response::InvoiceInfo
+        ),
+        route!(
+            "pay",
+            "Pay an invoice or an offer",
+            request::Pay,
+            response::PayResult
+        ),
+        route!(
+            "paylnurl",
+            "Pay a LNURL-pay link or a lightning address",
+            request::PayLnurl,
+            response::PayResult
+        ),
+        route!(
+            "cancelpayment",
+            "Cancel a payment",
+            request::CancelPayment,
+            json::Value
+        ),
+        route!(
+            "listpays",
+            "List the outbound payments",
+            request::ListPays,
+            response::Pays
+        ),
+        route!(
+            "getpaymentroute",
+            "Find a route for a payment",
+            request::GetPaymentRoute,
+            response::PaymentRoute
+        ),
+        route!(
+            "keysend",
+            "Send a spontaneous payment",
+            request::KeySend,
+            json::Value
+        ),
+        route!(
+            "signmessage",
+            "Sign a message",
+            request::SignMessage,
+            response::SignMessage
+        ),
+        route!(
+            "checkmessage",
+            "Check a signed message",
+            request::CheckMessage,
+            response::CheckMessage
+        ),
+        route!("fees", "Estimate the on chain fees", json::Value, HashMap<String, Option<u32>>),
+        route!(
+            "feerates",
+            "The on chain fee rates",
+            json::Value,
+            response::FeeRates
+        ),
+        route!(
+            "getchaininfo",
+            "Information about the chain",
+            json::Value,
+            response::ChainInfo
+        ),
+        route!(
+            "close",
+            "Close a channel",
+            request::CloseChannel,
+            json::Value
+        ),
+        route!(
+            "pendingbumps",
+            "List the pending fee bumps",
+            json::Value,
+            response::BumpPackages
+        ),
+        route!(
+            "pendingbroadcasts",
+            "List the transactions waiting to be broadcast",
+            json::Value,
+            response::PendingBroadcasts
+        ),
+    ]
+}
+
+/// Describe the commands for which `served` returns true, the read
+/// only commands can also be called with `GET`.
+pub fn describe<F: Fn(&str) -> bool>(served: F, read_only: &[&str]) -> json::Value {
+    let error = json::json!({
+        "type": "object",
+        "properties": {
+            "code": { "type": "integer" },
+            "message": { "type": "string" },
+            "data": {},
+        },
+        "required": ["code", "message"],
+    });
+    let mut paths = json::Map::new();
+    for route in routes().into_iter().filter(|route| served(route.method)) {
+        let request = (route.request)();
+        let responses = json::json!({
+            "200": {
+                "description": route.summary,
+                "content": { "application/json": { "schema": (route.response)() } },
+            },
+            "default": {
+                "description": "The command failed",
+                "content": { "application/json": { "schema": error } },
+            },
+        });
+        let mut operations = json::Map::new();
+        if read_only.contains(&route.method) {
+            let parameters = request
+                .get("properties")
+                .and_then(json::Value::as_object)
+                .map(|properties| {
+                    properties
+                        .iter()
+                        .map(|(name, schema)| {
+                            json::json!({ "name": name, "in": "query", "schema": schema })
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            operations.insert(
+                "get".to_owned(),
+                json::json!({
+                    "operationId": format!("get_{}", route.method),
+                    "summary": route.summary,
+                    "parameters": parameters,
+                    "responses": responses,
+                }),
+            );
+        }
+        operations.insert(
+            "post".to_owned(),
+            json::json!({
+                "operationId": route.method,
+                "summary": route.summary,
+                "requestBody": {
+                    "content": { "application/json": { "schema": request } },
+                },
+                "responses": responses,
+            }),
+        );
+        paths.insert(format!("/v1/{}", route.method), operations.into());
+    }
+    json::json!({
+        "openapi": "3.0.3",
+        "info": { "title": "lampo", "version": env!("CARGO_PKG_VERSION") },
+        "components": {
+            "securitySchemes": { "token": { "type": "http", "scheme": "bearer" } },
+        },
+        "security": [{ "token": [] }],
+        "paths": paths,
+    })
+}
+
+/// The schema of `T`, or a free form schema when the model can not be
+/// described.
+fn schema_of<T: DeserializeOwned>() -> json::Value {
+    let mut schema = json::json!({});
+    if let Err(err) = T::deserialize(Tracer::new(&mut schema, 0)) {
+        log::debug!(target: "http", "impossible describe `{}`: {err}", std::any::type_name::<T>());
+        return json::json!({});
+    }
+    schema
+}
+
+#[derive(Debug)]
+struct TraceError(String);
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+impl de::Error for TraceError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+}
+
+/// A `Deserializer` that writes in `schema` the type asked by the model.
+struct Tracer<'a> {
+    schema: &'a mut json::Value,
+    depth: usize,
+}
+
+impl<'a> Tracer<'a> {
+    fn new(schema: &'a mut json::Value, depth: usize) -> Self {
+        Self { schema, depth }
+    }
+
+    fn nested(&self) -> Result<usize, TraceError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(de::Error::custom("the model is too deep"));
+        }
+        Ok(self.depth + 1)
+    }
+}
+
+macro_rules! trace_integer {
+    ($($method:ident => $visit:ident),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+                *self.schema = json::json!({ "type": "integer" });
+                visitor.$visit(0)
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> de::Deserializer<'de> for Tracer<'a> {
+    type Error = TraceError;
+
+    trace_integer!(
+        deserialize_i8 => visit_i64,
+        deserialize_i16 => visit_i64,
+        deserialize_i32 => visit_i64,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u64,
+        deserialize_u16 => visit_u64,
+        deserialize_u32 => visit_u64,
+        deserialize_u64 => visit_u64
+    );
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        // a free form value, or an amount that is a number or a string
+        // with the unit, so the visitor is given a number
+        *self.schema = json::json!({});
+        visitor.visit_u64(0)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        *self.schema = json::json!({ "type": "boolean" });
+        visitor.visit_bool(false)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_f64(visitor)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        *self.schema = json::json!({ "type": "number" });
+        visitor.visit_f64(0.0)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        *self.schema = json::json!({ "type": "string" });
+        visitor.visit_char(' ')
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        *self.schema = json::json!({ "type": "string" });
+        visitor.visit_borrowed_str("")
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        *self.schema = json::json!({ "type": "string" });
+        visitor.visit_string(String::new())
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        *self.schema = json::json!({ "type": "string" });
+        visitor.visit_borrowed_bytes(&[])
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        *self.schema = json::json!({ "type": "string" });
+        visitor.visit_byte_buf(Vec::new())
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        let value = visitor.visit_some(Tracer::new(&mut *self.schema, self.depth))?;
+        if let Some(schema) = self.schema.as_object_mut() {
+            schema.insert("nullable".to_owned(), true.into());
+        }
+        Ok(value)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        *self.schema = json::json!({});
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_tuple(1, visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        let mut items = json::json!({});
+        let value = visitor.visit_seq(SeqTracer {
+            items: &mut items,
+            remaining: len,
+            depth: self.nested()?,
+        })?;
+        *self.schema = json::json!({ "type": "array", "items": items });
+        Ok(value)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        *self.schema = json::json!({ "type": "object" });
+        visitor.visit_map(StructTracer {
+            properties: &mut json::Map::new(),
+            fields: &[],
+            next: 0,
+            depth: self.depth,
+        })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        let mut properties = json::Map::new();
+        let value = visitor.visit_map(StructTracer {
+            properties: &mut properties,
+            fields,
+            next: 0,
+            depth: self.nested()?,
+        })?;
+        let required = properties
+            .iter()
+            .filter(|(_, schema)| schema.get("nullable").is_none())
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        *self.schema = json::json!({
+            "type": "object",
+            "properties": properties,
+            "required": required,
+        });
+        Ok(value)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        *self.schema = json::json!({ "type": "string", "enum": variants });
+        let Some(variant) = variants.first().copied() else {
+            return Err(de::Error::custom("enum without variants"));
+        };
+        visitor.visit_enum(EnumTracer {
+            variant,
+            depth: self.nested()?,
+        })
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_unit(visitor)
+    }
+}
+
+/// Yields `remaining` elements, all described by `items`.
+struct SeqTracer<'a> {
+    items: &'a mut json::Value,
+    remaining: usize,
+    depth: usize,
+}
+
+impl<'de, 'a> de::SeqAccess<'de> for SeqTracer<'a> {
+    type Error = TraceError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, TraceError> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(Tracer::new(&mut *self.items, self.depth))
+            .map(Some)
+    }
+}
+
+/// Yields every field of a struct, a map has no fields.
+struct StructTracer<'a> {
+    properties: &'a mut json::Map<String, json::Value>,
+    fields: &'static [&'static str],
+    next: usize,
+    depth: usize,
+}
+
+impl<'de, 'a> de::MapAccess<'de> for StructTracer<'a> {
+    type Error = TraceError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, TraceError> {
+        let Some(field) = self.fields.get(self.next) else {
+            return Ok(None);
+        };
+        seed.deserialize(name(*field)).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, TraceError> {
+        let Some(field) = self.fields.get(self.next).copied() else {
+            return Err(de::Error::custom("value without a field"));
+        };
+        self.next += 1;
+        let schema = self
+            .properties
+            .entry(field.to_string())
+            .or_insert(json::json!({}));
+        seed.deserialize(Tracer::new(schema, self.depth))
+    }
+}
+
+/// Picks the first variant of an enum.
+struct EnumTracer {
+    variant: &'static str,
+    depth: usize,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumTracer {
+    type Error = TraceError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self), TraceError> {
+        let variant = seed.deserialize(name(self.variant))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for EnumTracer {
+    type Error = TraceError;
+
+    fn unit_variant(self) -> Result<(), TraceError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, TraceError> {
+        seed.deserialize(Tracer::new(&mut json::json!({}), self.depth))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        de::Deserializer::deserialize_tuple(
+            Tracer::new(&mut json::json!({}), self.depth),
+            len,
+            visitor,
+        )
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        de::Deserializer::deserialize_struct(
+            Tracer::new(&mut json::json!({}), self.depth),
+            "",
+            fields,
+            visitor,
+        )
+    }
+}
+
+fn name(name: &'static str) -> de::value::StrDeserializer<'static, TraceError> {
+    name.into_deserializer()
+}
@@ -0,0 +1,312 @@
+//! WebSocket stream of the events of the node at `/v1/ws`.
+//!
+//! Every event is sent in a text frame with the JSON
+//! `{"id": <id>, "type": <kind>, "payload": <data>}`, the `since_id`
+//! query param replays the events after that id that are still kept
+//! by the node. The events are read with the `waitevent` command, so
+//! the token is checked again at every read.
+//!
+//! A client is never buffered: a client that does not read its events
+//! in time, or that is so slow that its events are dropped by the node,
+//! is disconnected.
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use lampo_common::bitcoin::hashes::{sha1, Hash};
+use lampo_common::error::INVALID_PARAMS;
+use lampo_common::json;
+use lampo_common::model::response::NotifiedEvents;
+use lampo_jsonrpc::errors::RpcError;
+
+use super::{Connection, HttpRequest, HttpResponse, HttpServer, INVALID_REQUEST};
+
+/// The GUID of RFC 6455 used to accept the handshake.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OP_TEXT: u8 = 0x1;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// Seconds that a `waitevent` waits before the frames of the client
+/// are read.
+const POLL_SECS: u64 = 1;
+/// How long the frames of the client are read after every poll.
+const READ_INTERVAL: Duration = Duration::from_millis(10);
+/// A client that does not read its frames in this time is disconnected.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Every interval the client is pinged, and it is disconnected if it
+/// did not answer to the previous ping.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+/// The frames of a client are only control frames, so they are small.
+const MAX_FRAME_BYTES: usize = 4 * 1024;
+
+/// The close codes of RFC 6455.
+const CLOSE_NORMAL: u16 = 1000;
+const CLOSE_GOING_AWAY: u16 = 1001;
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_POLICY_VIOLATION: u16 = 1008;
+const CLOSE_TOO_BIG: u16 = 1009;
+
+/// True if the `request` asks to upgrade the connection to a WebSocket.
+pub(super) fn is_upgrade(request: &HttpRequest) -> bool {
+    request
+        .header("upgrade")
+        .map_or(false, |upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+}
+
+/// Complete the handshake of the `request` and stream the events until
+/// the client goes away.
+pub(super) fn serve<S: Connection>(
+    server: &HttpServer,
+    request: &HttpRequest,
+    stream: &mut S,
+) -> io::Result<()> {
+    if let Some(origin) = request.header("origin") {
+        // the browsers do not apply CORS to the WebSockets
+        if server.allowed_origin(origin).is_none() {
+            return HttpResponse::error(403, INVALID_REQUEST, "origin not allowed").write(stream);
+        }
+    }
+    let Some(key) = request.header("sec-websocket-key") else {
+        return HttpResponse::bad_request("missing `Sec-WebSocket-Key`").write(stream);
+    };
+    if request.header("sec-websocket-version") != Some("13") {
+        let mut response = HttpResponse::error(426, INVALID_REQUEST, "unsupported version");
+        response.header("Sec-WebSocket-Version", "13");
+        return response.write(stream);
+    }
+    let params = super::query_params(request.query.as_deref());
+    // the browsers can not set the headers of a WebSocket
+    let token = request
+        .bearer_token()
+        .or_else(|| params.get("token").and_then(json::Value::as_str));
+    let since_id = match params.get("since_id") {
+        Some(json::Value::Number(id)) if id.is_u64() => id.as_u64(),
+        Some(_) => {
+            let message = "`since_id` must be an event id";
+            return HttpResponse::error(400, INVALID_PARAMS, message).write(stream);
+        }
+        None => None,
+    };
+
+    // the first read checks the token and `since_id` before the upgrade
+    let replay = match poll(server, since_id, 0, token) {
+        Ok(replay) => replay,
+        Err(err) => return HttpResponse::rpc_error(&err).write(stream),
+    };
+    let mut response = HttpResponse::empty(101);
+    response.header("Upgrade", "websocket");
+    response.header("Connection", "Upgrade");
+    response.header("Sec-WebSocket-Accept", &accept_key(key));
+    response.write(stream)?;
+
+    stream.tcp().set_write_timeout(Some(WRITE_TIMEOUT))?;
+    stream.tcp().set_read_timeout(Some(READ_INTERVAL))?;
+    let mut last_id = replay.last_id;
+    // without `since_id` only the new events are streamed
+    if since_id.is_some() {
+        send_events(stream, replay)?;
+    }
+    let mut incoming = Vec::new();
+    let mut last_ping = Instant::now();
+    let mut waiting_pong = false;
+    loop {
+        if server.handler.is_stopped() {
+            return close(stream, CLOSE_GOING_AWAY, "the node is stopping");
+        }
+        let events = match poll(server, Some(last_id), POLL_SECS, token) {
+            Ok(events) => events,
+            Err(err) => return close(stream, CLOSE_POLICY_VIOLATION, &err.message),
+        };
+        last_id = events.last_id;
+        send_events(stream, events)?;
+
+        match read_frames(stream, &mut incoming) {
+            Ok(Incoming::Frames(frames)) => {
+                for (opcode, payload) in frames {
+                    match opcode {
+                        OP_PING => write_frame(stream, OP_PONG, &payload)?,
+                        OP_PONG => waiting_pong = false,
+                        OP_CLOSE => return close(stream, CLOSE_NORMAL, ""),
+                        // the client has nothing to say
+                        _ => {}
+                    }
+                }
+            }
+            Ok(Incoming::Closed) => return Ok(()),
+            Err((code, reason)) => return close(stream, code, reason),
+        }
+
+        if last_ping.elapsed() >= PING_INTERVAL {
+            if waiting_pong {
+                log::debug!(target: "http", "closing a WebSocket that does not answer the pings");
+                return close(stream, CLOSE_POLICY_VIOLATION, "no pong received");
+            }
+            write_frame(stream, OP_PING, &[])?;
+            waiting_pong = true;
+            last_ping = Instant::now();
+        }
+    }
+}
+
+/// Read the events after `last_id`, waiting up to `timeout_secs`.
+fn poll(
+    server: &HttpServer,
+    last_id: Option<u64>,
+    timeout_secs: u64,
+    token: Option<&str>,
+) -> Result<NotifiedEvents, RpcError> {
+    let params = json::json!({ "last_id": last_id, "timeout_secs": timeout_secs });
+    let events = server.call("waitevent", params, token)?;
+    json::from_value(events).map_err(|err| RpcError {
+        code: INVALID_REQUEST,
+        message: format!("{err}"),
+        data: None,
+    })
+}
+
+fn send_events<S: Write>(stream: &mut S, events: NotifiedEvents) -> io::Result<()> {
+    for event in events.events {
+        let frame = json::json!({ "id": event.id, "type": event.kind, "payload": event.data });
+        write_frame(stream, OP_TEXT, frame.to_string().as_bytes())?;
+    }
+    Ok(())
+}
+
+enum Incoming {
+    Frames(Vec<(u8, Vec<u8>)>),
+    /// The client closed the connection without a close frame.
+    Closed,
+}
+
+/// Read the frames sent by the client, `incoming` keeps the bytes of
+/// a frame that is not completed yet.
+fn read_frames<S: Read>(
+    stream: &mut S,
+    incoming: &mut Vec<u8>,
+) -> Result<Incoming, (u16, &'static str)> {
+    let mut buf = [0u8; 1024];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) => return Ok(Incoming::Closed),
+            Ok(read) => incoming.extend_from_slice(&buf[..read]),
+            Err(err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                break
+            }
+            Err(_) => return Ok(Incoming::Closed),
+        }
+        if incoming.len() > 2 * MAX_FRAME_BYTES {
+            return Err((CLOSE_TOO_BIG, "too many bytes received"));
+        }
+    }
+    let mut frames = Vec::new();
+    while let Some(frame) = parse_frame(incoming)? {
+        frames.push(frame);
+    }
+    Ok(Incoming::Frames(frames))
+}
+
+/// Take the first complete frame out of `buffer`, the frames of a
+/// client are always masked.
+fn parse_frame(buffer: &mut Vec<u8>) -> Result<Option<(u8, Vec<u8>)>, (u16, &'static str)> {
+    if buffer.len() < 2 {
+        return Ok(None);
+    }
+    let opcode = buffer[0] & 0x0f;
+    if buffer[1] & 0x80 == 0 {
+        return Err((
+            CLOSE_PROTOCOL_ERROR,
+            "the frames of a client must be masked",
+        ));
+    }
+    let (len, start) = match buffer[1] & 0x7f {
+        126 if buffer.len() >= 4 => (u16::from_be_bytes([buffer[2], buffer[3]]) as u64, 4),
+        127 if buffer.len() >= 10 => {
+            let mut len = [0u8; 8];
+            len.copy_from_slice(&buffer[2..10]);
+            (u64::from_be_bytes(len), 10)
+        }
+        126 | 127 => return Ok(None),
+        len => (len as u64, 2),
+    };
+    if len > MAX_FRAME_BYTES as u64 {
+        return Err((CLOSE_TOO_BIG, "the frame is too large"));
+    }
+    let len = len as usize;
+    if buffer.len() < start + 4 + len {
+        return Ok(None);
+    }
+    let mask = [
+        buffer[start],
+        buffer[start + 1],
+        buffer[start + 2],
+        buffer[start + 3],
+    ];
+    let payload = buffer[start + 4..start + 4 + len]
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| byte ^ mask[i % 4])
+        .collect();
+    buffer.drain(..start + 4 + len);
+    Ok(Some((opcode, payload)))
+}
+
+fn write_frame<S: Write>(stream: &mut S, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)?;
+    stream.flush()
+}
+
+/// Send the close frame, the reason is cut to fit inside a control frame.
+fn close<S: Write>(stream: &mut S, code: u16, reason: &str) -> io::Result<()> {
+    let mut end = reason.len().min(123);
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend_from_slice(reason[..end].as_bytes());
+    write_frame(stream, OP_CLOSE, &payload)
+}
+
+/// The `Sec-WebSocket-Accept` for the `Sec-WebSocket-Key` of the client.
+fn accept_key(key: &str) -> String {
+    let hash = sha1::Hash::hash(format!("{key}{WEBSOCKET_GUID}").as_bytes());
+    base64(&hash.to_byte_array())
+}
+
+fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut output = String::with_capacity((input.len() + 2) / 3 * 4);
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or_default(),
+            chunk.get(2).copied().unwrap_or_default(),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
@@ -1,18 +1,32 @@
 //! JSON RPC 2.0 implementation
+pub mod auth;
 pub mod channels;
+pub mod compat;
+pub mod datastore;
+pub mod dev;
+pub mod events;
+pub mod gossip;
+pub mod health;
 pub mod inventory;
+pub mod logs;
+pub mod maintenance;
+pub mod metrics;
 pub mod offchain;
 pub mod onchain;
 pub mod open_channel;
 pub mod peer_control;
+pub mod plugins;
+pub mod watchtower;
 
-use std::cell::RefCell;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 use lampo_common::conf::LampoConf;
 use lampo_common::error;
+use lampo_common::error::LampoError;
 use lampo_common::json;
+use lampo_common::sync::RwLockExt;
 use lampo_jsonrpc::command::Context;
+use lampo_jsonrpc::errors::{Error, RpcError};
 use lampo_jsonrpc::json_rpc2;
 use lampo_jsonrpc::Handler;
 
@@ -29,32 +43,47 @@ macro_rules! rpc_error {
     }};
 }
 
+/// Convert an error to a JSON RPC error, a `LampoError` keeps its
+/// code and details, any other error has the generic code `-1`.
+pub fn to_rpc_error<E: Into<error::Error>>(err: E) -> Error {
+    let err: error::Error = err.into();
+    if let Some(lampo_err) = LampoError::find(&err) {
+        return Error::Rpc(RpcError {
+            code: lampo_err.code(),
+            message: format!("{err}"),
+            data: Some(lampo_err.data()),
+        });
+    }
+    // the error of a method called by another method
+    if let Some(Error::Rpc(rpc)) = err.downcast_ref::<Error>() {
+        return Error::Rpc(rpc.clone());
+    }
+    rpc_error!("{err}")
+}
+
 /// JSON RPC 2.0 Command handler!
 pub struct CommandHandler {
-    pub handler: RefCell<Option<Arc<Handler<LampoDaemon>>>>,
+    pub handler: RwLock<Option<Arc<Handler<LampoDaemon>>>>,
     pub conf: LampoConf,
 }
 
-unsafe impl Send for CommandHandler {}
-unsafe impl Sync for CommandHandler {}
-
 impl CommandHandler {
     pub fn new(lampo_conf: &LampoConf) -> error::Result<Self> {
         let handler = CommandHandler {
-            handler: RefCell::new(None),
+            handler: RwLock::new(None),
             conf: lampo_conf.clone(),
         };
         Ok(handler)
     }
 
     pub fn set_handler(&self, handler: Arc<Handler<LampoDaemon>>) {
-        self.handler.replace(Some(handler));
+        *self.handler.write_or_recover() = Some(handler);
     }
 }
 
 impl ExternalHandler for CommandHandler {
     fn handle(&self, req: &json_rpc2::Request<json::Value>) -> error::Result<Option<json::Value>> {
-        let handler = self.handler.borrow();
+        let handler = self.handler.read_or_recover().clone();
         let Some(handler) = handler.as_ref() else {
             log::info!("skipping the handling because it is not defined");
             return Ok(None);
@@ -0,0 +1,52 @@
+//! Authentication of the JSON RPC 2.0 requests.
+use std::str::FromStr;
+use std::sync::Arc;
+
+use lampo_common::auth::{RpcAuth, Scope};
+use lampo_common::error::LampoError;
+use lampo_common::json;
+use lampo_common::model::request::{MintToken, RevokeToken};
+use lampo_common::model::response;
+use lampo_jsonrpc::errors::Error;
+
+use crate::jsonrpc::to_rpc_error;
+use crate::LampoDaemon;
+
+/// Authenticate the requests of the clients with the `token` inside
+/// the params, the token is removed before the method runs.
+pub fn authenticate(
+    auth: Arc<RpcAuth>,
+) -> impl Fn(&str, &mut json::Value) -> Result<(), Error> + Send + Sync {
+    move |method, params| {
+        let token = params
+            .as_object_mut()
+            .and_then(|params| params.remove("token"));
+        let Some(json::Value::String(token)) = token else {
+            return Err(to_rpc_error(LampoError::Unauthorized(
+                "missing `token` in the params".to_owned(),
+            )));
+        };
+        auth.verify(&token, method, params).map_err(to_rpc_error)?;
+        Ok(())
+    }
+}
+
+pub fn json_mint_token(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `minttoken` with request `{:?}`", request);
+    let request: MintToken = json::from_value(request.clone())?;
+    let scope = Scope::from_str(&request.scope)
+        .map_err(|err| to_rpc_error(LampoError::InvalidParams(format!("{err}"))))?;
+    let (id, token) = ctx.rpc_auth().mint(scope).map_err(to_rpc_error)?;
+    Ok(json::to_value(&response::MintToken {
+        id,
+        scope: scope.to_string(),
+        token,
+    })?)
+}
+
+pub fn json_revoke_token(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `revoketoken` with request `{:?}`", request);
+    let request: RevokeToken = json::from_value(request.clone())?;
+    ctx.rpc_auth().revoke(request.id).map_err(to_rpc_error)?;
+    Ok(json::to_value(&response::RevokeToken { id: request.id })?)
+}
@@ -1,14 +1,17 @@
+use std::str::FromStr;
+
+use lampo_common::error::LampoError;
 use lampo_common::event::ln::LightningEvent;
 use lampo_common::event::Event;
 use lampo_common::handler::Handler;
 use lampo_common::json;
 use lampo_common::model::request;
+use lampo_common::model::request::ChannelIdentifier;
 use lampo_common::model::response;
 use lampo_jsonrpc::errors::Error;
-use lampo_jsonrpc::errors::RpcError;
 
+use crate::jsonrpc::to_rpc_error;
 use crate::ln::events::ChannelEvents;
-
 use crate::LampoDaemon;
 
 pub fn json_list_channels(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
@@ -17,6 +20,67 @@ pub fn json_list_channels(ctx: &LampoDaemon, request: &json::Value) -> Result<js
     Ok(json::to_value(resp)?)
 }
 
+pub fn json_get_channel(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `getchannel` with request {:?}", request);
+    let request: request::GetChannel = json::from_value(request.clone())?;
+    let id = request.identifier().map_err(to_rpc_error)?;
+    let channel = ctx
+        .channel_manager()
+        .get_channel(&id)
+        .map_err(to_rpc_error)?;
+    Ok(json::to_value(channel)?)
+}
+
+pub fn json_list_htlcs(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `listhtlcs` with request {:?}", request);
+    let request: request::ListHtlcs = json::from_value(request.clone())?;
+    let id = request
+        .channel
+        .as_deref()
+        .map(ChannelIdentifier::from_str)
+        .transpose()
+        .map_err(to_rpc_error)?;
+    let htlcs = ctx
+        .channel_manager()
+        .list_htlcs(id.as_ref())
+        .map_err(to_rpc_error)?;
+    Ok(json::to_value(htlcs)?)
+}
+
+pub fn json_set_channel(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `setchannel` with request {:?}", request);
+    let request: request::SetChannel = json::from_value(request.clone())?;
+    let id = ChannelIdentifier::from_str(&request.id).map_err(to_rpc_error)?;
+    let channel = ctx
+        .channel_manager()
+        .set_channel_enabled(&id, request.enabled)
+        .map_err(to_rpc_error)?;
+    Ok(json::to_value(channel)?)
+}
+
+pub fn json_autofees_status(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `autofees-status` with request {:?}", request);
+    let status = match ctx.autofees() {
+        Some(autofees) => autofees.status(),
+        None => {
+            let conf = ctx.conf();
+            response::AutofeesStatus {
+                enabled: false,
+                interval_secs: conf.autofees_interval_secs,
+                min_ppm: conf.autofees_min_ppm,
+                max_ppm: conf.autofees_max_ppm,
+                max_change_ppm: conf.autofees_max_change_ppm,
+                hysteresis_ppm: conf.autofees_hysteresis_ppm,
+                channels: vec![],
+            }
+        }
+    };
+    Ok(json::to_value(status)?)
+}
+
 pub fn json_close_channel(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
     log::info!("call for `closechannel` with request {:?}", request);
     let mut request: request::CloseChannel = json::from_value(request.clone())?;
@@ -30,22 +94,14 @@ pub fn json_close_channel(ctx: &LampoDaemon, request: &json::Value) -> Result<js
             "peer_id": request.node_id,
             }),
         )
-        .map_err(|err| {
-            Error::Rpc(RpcError {
-                code: -1,
-                message: format!("{err}"),
-                data: None,
-            })
-        })?;
+        .map_err(to_rpc_error)?;
     let res = if channels.channels.len() > 1 {
         // check the channel_id if it is not none, if it is return an error
         // and if it is not none then we need to have the channel_id that needs to be shut
         if request.channel_id.is_none() {
-            return Err(Error::Rpc(RpcError {
-                code: -1,
-                message: format!("Channels > 1, provide `channel_id`"),
-                data: None,
-            }));
+            return Err(to_rpc_error(LampoError::InvalidParams(
+                "Channels > 1, provide `channel_id`".to_owned(),
+            )));
         } else {
             request
         }
@@ -57,32 +113,27 @@ pub fn json_close_channel(ctx: &LampoDaemon, request: &json::Value) -> Result<js
         request
     } else {
         // No channels with the given peer.
-        return Err(Error::Rpc(RpcError {
-            code: -1,
-            message: format!("No channels with associated peer"),
-            data: None,
-        }));
+        return Err(to_rpc_error(LampoError::ChannelNotFound(
+            "No channels with associated peer".to_owned(),
+        )));
     };
-    match ctx.channel_manager().close_channel(res) {
-        Err(err) => {
-            return Err(Error::Rpc(RpcError {
-                code: -1,
-                message: format!("{err}"),
-                data: None,
+    match ctx.channel_manager().queue_close_if_offline(&res) {
+        Ok(Some(close)) => {
+            return Ok(json::json!({
+                "state": "pending_peer",
+                "close": close,
             }))
         }
-        Ok(_) => {}
+        Ok(None) => {}
+        Err(err) => return Err(to_rpc_error(err)),
     };
+    ctx.channel_manager()
+        .close_channel(res)
+        .map_err(to_rpc_error)?;
     let (message, channel_id, node_id, funding_utxo) = loop {
         let event = events
             .recv_timeout(std::time::Duration::from_secs(30))
-            .map_err(|err| {
-                Error::Rpc(RpcError {
-                    code: -1,
-                    message: format!("{err}"),
-                    data: None,
-                })
-            })?;
+            .map_err(to_rpc_error)?;
         if let Event::Lightning(LightningEvent::CloseChannelEvent {
             message,
             channel_id,
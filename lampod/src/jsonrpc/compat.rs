@@ -0,0 +1,61 @@
+//! Core Lightning compat layer, enabled with `compat-cln`.
+//!
+//! The methods of Core Lightning served by lampo replace the native
+//! ones with the same name, and the other methods of Core Lightning
+//! fail with the native method to use instead.
+use lampo_common::compat;
+use lampo_common::json;
+use lampo_jsonrpc::errors::Error;
+use lampo_jsonrpc::{Handler, MethodKind};
+
+use crate::jsonrpc::inventory::get_info;
+use crate::jsonrpc::offchain::{json_invoice, json_pay};
+use crate::jsonrpc::onchain::{json_list_funds, json_new_addr};
+use crate::jsonrpc::open_channel::json_open_channel;
+use crate::jsonrpc::to_rpc_error;
+use crate::LampoDaemon;
+
+type NativeMethod = fn(&LampoDaemon, &json::Value) -> Result<json::Value, Error>;
+
+/// The native method behind a method of Core Lightning.
+fn native_method(method: &str) -> Option<NativeMethod> {
+    let native: NativeMethod = match method {
+        "invoice" => json_invoice,
+        "pay" => json_pay,
+        "newaddr" => json_new_addr,
+        "getinfo" => get_info,
+        "listfunds" => json_list_funds,
+        "fundchannel" => json_open_channel,
+        _ => return None,
+    };
+    Some(native)
+}
+
+/// Serve the methods of Core Lightning, after the methods of lampo and
+/// of the plugins, so a plugin can still serve a method that the compat
+/// layer does not support.
+pub fn add_cln_compat(handler: &Handler<LampoDaemon>) {
+    for method in compat::CLN_METHODS {
+        let Some(native) = native_method(method) else {
+            continue;
+        };
+        let kind = handler.method_kind(method).unwrap_or(MethodKind::Mutate);
+        handler.add_method_with_kind(method, kind, move |ctx: &LampoDaemon, request| {
+            log::info!(
+                "call for `{method}` of Core Lightning with request `{:?}`",
+                request
+            );
+            let params = compat::to_native_params(method, request).map_err(to_rpc_error)?;
+            let resp = native(ctx, &params)?;
+            compat::to_cln_response(method, request, resp).map_err(to_rpc_error)
+        });
+    }
+    for (method, _) in compat::UNSUPPORTED_CLN_METHODS {
+        if handler.has_rpc(method) {
+            continue;
+        }
+        handler.add_method(method, move |_: &LampoDaemon, _| {
+            Err(to_rpc_error(compat::unsupported(method)))
+        });
+    }
+}
@@ -0,0 +1,34 @@
+//! Datastore JSON RPC Interface!
+use lampo_common::json;
+use lampo_common::model::{request, response};
+use lampo_jsonrpc::errors::Error;
+
+use crate::jsonrpc::to_rpc_error;
+use crate::LampoDaemon;
+
+pub fn json_datastore(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `datastore` with request `{:?}`", request);
+    let request: request::Datastore = json::from_value(request.clone())?;
+    let entry = ctx
+        .datastore()
+        .set(request.key, request.value, request.mode, request.generation)
+        .map_err(to_rpc_error)?;
+    Ok(json::to_value(entry)?)
+}
+
+pub fn json_list_datastore(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::debug!("call for `listdatastore` with request `{:?}`", request);
+    let request: request::ListDatastore = json::from_value(request.clone())?;
+    let datastore = ctx.datastore().list(&request.prefix.unwrap_or_default());
+    Ok(json::to_value(response::ListDatastore { datastore })?)
+}
+
+pub fn json_del_datastore(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `deldatastore` with request `{:?}`", request);
+    let request: request::DelDatastore = json::from_value(request.clone())?;
+    let entry = ctx
+        .datastore()
+        .delete(&request.key, request.generation)
+        .map_err(to_rpc_error)?;
+    Ok(json::to_value(entry)?)
+}
@@ -0,0 +1,108 @@
+//! Developer JSON RPC Interface, enabled with `developer`.
+//!
+//! The methods break the node on purpose to test the behaviour of
+//! lampo and of its peers, the most dangerous ones are compiled only
+//! in the debug builds.
+use std::str::FromStr;
+
+use lampo_common::error::LampoError;
+use lampo_common::json;
+use lampo_common::model::{request, response};
+use lampo_common::types::NodeId;
+use lampo_jsonrpc::errors::Error;
+use lampo_jsonrpc::Handler;
+
+use crate::chain::parse_target;
+use crate::jsonrpc::to_rpc_error;
+use crate::LampoDaemon;
+
+/// Serve the `dev-*` methods, after the methods of lampo and before
+/// the ones of the plugins.
+pub fn add_dev_methods(handler: &Handler<LampoDaemon>) {
+    handler.add_method("dev-fail", json_dev_fail);
+    handler.add_method("dev-set-feerate", json_dev_set_feerate);
+    handler.add_method("dev-ignore-htlcs", json_dev_ignore_htlcs);
+    #[cfg(debug_assertions)]
+    {
+        handler.add_method("dev-sign-last-tx", json_dev_sign_last_tx);
+        handler.add_method("dev-crash", json_dev_crash);
+    }
+}
+
+pub fn json_dev_fail(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `dev-fail` with request `{:?}`", request);
+    let request: request::DevChannel = json::from_value(request.clone())?;
+    let id = request::ChannelIdentifier::from_str(&request.id).map_err(to_rpc_error)?;
+    let channel_id = ctx.channel_manager().dev_fail(&id).map_err(to_rpc_error)?;
+    Ok(json::to_value(response::DevFail {
+        channel_id: channel_id.to_string(),
+    })?)
+}
+
+#[cfg(debug_assertions)]
+pub fn json_dev_sign_last_tx(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    use lampo_common::bitcoin::consensus::encode::serialize_hex;
+
+    log::info!("call for `dev-sign-last-tx` with request `{:?}`", request);
+    let request: request::DevChannel = json::from_value(request.clone())?;
+    let id = request::ChannelIdentifier::from_str(&request.id).map_err(to_rpc_error)?;
+    let (channel_id, txs) = ctx
+        .channel_manager()
+        .dev_sign_last_tx(&id)
+        .map_err(to_rpc_error)?;
+    Ok(json::to_value(response::DevSignLastTx {
+        channel_id: channel_id.to_string(),
+        txs: txs.iter().map(serialize_hex).collect(),
+    })?)
+}
+
+pub fn json_dev_set_feerate(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `dev-set-feerate` with request `{:?}`", request);
+    let request: request::DevSetFeerate = json::from_value(request.clone())?;
+    let Some(target) = parse_target(&request.target) else {
+        return Err(to_rpc_error(LampoError::InvalidParams(format!(
+            "unknown target `{}`, see `feerates` for the targets",
+            request.target
+        ))));
+    };
+    let fees = &ctx.onchain_manager().fees;
+    fees.set_override(target, request.sat_per_kw);
+    Ok(json::to_value(fees.fee_rates())?)
+}
+
+pub fn json_dev_ignore_htlcs(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `dev-ignore-htlcs` with request `{:?}`", request);
+    let request: request::DevIgnoreHtlcs = json::from_value(request.clone())?;
+    let node_id = NodeId::from_str(&request.node_id).map_err(|err| {
+        to_rpc_error(LampoError::InvalidParams(format!(
+            "invalid node id `{}`: {err}",
+            request.node_id
+        )))
+    })?;
+    ctx.channel_manager()
+        .set_ignore_htlcs(node_id, request.ignore);
+    Ok(json::to_value(response::DevIgnoreHtlcs {
+        node_id: request.node_id,
+        ignore: request.ignore,
+    })?)
+}
+
+/// Abort the process without stopping the node, to test what is
+/// restored from the disk.
+#[cfg(debug_assertions)]
+pub fn json_dev_crash(_: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::warn!(
+        "call for `dev-crash` with request `{:?}`, aborting",
+        request
+    );
+    std::process::abort()
+}
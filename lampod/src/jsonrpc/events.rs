@@ -0,0 +1,35 @@
+//! Events JSON RPC Interface!
+use std::time::Duration;
+
+use lampo_common::json;
+use lampo_common::model::request;
+use lampo_common::model::response;
+use lampo_jsonrpc::errors::Error;
+use lampo_jsonrpc::errors::RpcError;
+
+use crate::rpc_error;
+use crate::LampoDaemon;
+
+pub fn json_wait_event(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `waitevent` with request `{:?}`", request);
+    let input: request::WaitEvent = json::from_value(request.clone())?;
+    let timeout = Duration::from_secs(input.timeout_secs.unwrap_or(30));
+    let events = ctx
+        .event_bus()
+        .wait(input.last_id, &input.kinds, timeout)
+        .map_err(|err| rpc_error!("{err}"))?;
+    Ok(json::to_value(events)?)
+}
+
+pub fn json_list_webhook_deliveries(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!(
+        "call for `listwebhookdeliveries` with request `{:?}`",
+        request
+    );
+    let input: request::ListWebhookDeliveries = json::from_value(request.clone())?;
+    let deliveries = ctx.webhooks().deliveries(input.url.as_deref());
+    Ok(json::to_value(response::WebhookDeliveries { deliveries })?)
+}
@@ -0,0 +1,28 @@
+//! Network Graph JSON RPC Interface!
+use lampo_common::json;
+use lampo_common::model::request;
+use lampo_jsonrpc::errors::Error;
+use lampo_jsonrpc::errors::RpcError;
+
+use crate::ln::{list_gossip_channels, list_nodes};
+use crate::rpc_error;
+use crate::LampoDaemon;
+
+pub fn json_list_nodes(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `listnodes` with request `{:?}`", request);
+    let input: request::ListNodes = json::from_value(request.clone())?;
+    let nodes = list_nodes(&ctx.channel_manager().graph(), &input)
+        .map_err(|err| rpc_error!("{err}"))?;
+    Ok(json::to_value(nodes)?)
+}
+
+pub fn json_list_gossip_channels(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `listgossipchannels` with request `{:?}`", request);
+    let input: request::ListGossipChannels = json::from_value(request.clone())?;
+    let channels = list_gossip_channels(&ctx.channel_manager().graph(), &input)
+        .map_err(|err| rpc_error!("{err}"))?;
+    Ok(json::to_value(channels)?)
+}
@@ -0,0 +1,154 @@
+//! Health JSON RPC Interface!
+//!
+//! The node is alive when it is working, and it is ready when it
+//! can serve the payments. The orchestrators restart the node that
+//! is not alive, and do not send the traffic to a node not ready.
+use std::time::{Instant, SystemTime};
+
+use lampo_common::json;
+use lampo_common::ldk::util::persist::KVStore;
+use lampo_common::model::response::{Health, HealthCheck, HealthCheckKind};
+use lampo_jsonrpc::errors::Error;
+
+use crate::LampoDaemon;
+
+const LAMPO_NAMESPACE: &str = "lampo";
+const HEALTH_KEY: &str = "health";
+
+pub fn json_health(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::debug!("call for `health` with request `{:?}`", request);
+    Ok(json::to_value(health(ctx))?)
+}
+
+/// Run all the checks of the node.
+pub fn health(ctx: &LampoDaemon) -> Health {
+    let mut checks = vec![event_loop(ctx), persistence(ctx), channel_manager(ctx)];
+    // the tip of the backend is the reference of the other checks
+    let backend_tip = ctx
+        .onchain_manager()
+        .backend
+        .get_best_block()
+        .map_err(|err| err.to_string())
+        .and_then(|(_, height)| height.ok_or_else(|| "unknown height".to_owned()));
+    checks.push(chain(ctx, &backend_tip));
+    checks.push(wallet(ctx, &backend_tip));
+    checks.push(fees(ctx));
+    Health::new(checks)
+}
+
+fn check(name: &str, kind: HealthCheckKind, pass: bool, detail: String) -> HealthCheck {
+    HealthCheck {
+        name: name.to_owned(),
+        kind,
+        pass,
+        detail,
+    }
+}
+
+fn event_loop(ctx: &LampoDaemon) -> HealthCheck {
+    let (pass, detail) = if ctx.is_stopped() {
+        (false, "the node is stopping".to_owned())
+    } else if !ctx.is_processing() {
+        (false, "the background processor is not running".to_owned())
+    } else {
+        let pending = ctx.handler().pending_events();
+        (true, format!("running, {pending} events pending"))
+    };
+    check("event_loop", HealthCheckKind::Liveness, pass, detail)
+}
+
+/// Write a value and read it back, so a full disk or a store that
+/// hangs is reported.
+fn persistence(ctx: &LampoDaemon) -> HealthCheck {
+    let persister = ctx.persister();
+    let start = Instant::now();
+    let value = format!("{:?}", SystemTime::now());
+    let result = persister
+        .write(LAMPO_NAMESPACE, "", HEALTH_KEY, value.as_bytes())
+        .and_then(|_| persister.read(LAMPO_NAMESPACE, "", HEALTH_KEY));
+    let (pass, detail) = match result {
+        Ok(read) if read == value.as_bytes() => (
+            true,
+            format!("written and read in {} ms", start.elapsed().as_millis()),
+        ),
+        Ok(_) => (false, "the value read is not the one written".to_owned()),
+        Err(err) => (false, format!("impossible write and read: {err}")),
+    };
+    check("persistence", HealthCheckKind::Liveness, pass, detail)
+}
+
+fn channel_manager(ctx: &LampoDaemon) -> HealthCheck {
+    let manager = ctx.channel_manager();
+    let (pass, detail) = if manager.is_loaded() {
+        let channels = manager.manager().list_channels().len();
+        (true, format!("loaded with {channels} channels"))
+    } else {
+        (false, "not loaded".to_owned())
+    };
+    check("channel_manager", HealthCheckKind::Readiness, pass, detail)
+}
+
+/// The blocks given to ldk are close to the tip of the backend.
+fn chain(ctx: &LampoDaemon, backend_tip: &Result<u32, String>) -> HealthCheck {
+    let max_behind = ctx.conf().ready_max_blocks_behind;
+    let (pass, detail) = match backend_tip {
+        Ok(tip) => {
+            let height = ctx.channel_manager().manager().current_best_block().height;
+            let behind = tip.saturating_sub(height);
+            (
+                behind <= max_behind,
+                format!(
+                    "at height {height}, {behind} blocks behind the backend (max {max_behind})"
+                ),
+            )
+        }
+        Err(err) => (
+            false,
+            format!("impossible get the tip of the backend: {err}"),
+        ),
+    };
+    check("chain", HealthCheckKind::Readiness, pass, detail)
+}
+
+/// The wallet was synced recently, or it is already at the tip (e.g.
+/// when it is synced only on the new blocks).
+fn wallet(ctx: &LampoDaemon, backend_tip: &Result<u32, String>) -> HealthCheck {
+    let staleness = ctx.conf().ready_wallet_staleness_secs;
+    let synced_since = ctx.wallet_sync().synced_since();
+    let height = ctx.wallet_manager().synced_height();
+    let at_tip = match (&height, backend_tip) {
+        (Ok(Some(height)), Ok(tip)) => {
+            tip.saturating_sub(*height) <= ctx.conf().ready_max_blocks_behind
+        }
+        _ => false,
+    };
+    let (pass, detail) = match (synced_since, height) {
+        (_, Err(err)) => (false, format!("impossible get the wallet height: {err}")),
+        (Some(since), _) if since.as_secs() <= staleness => (
+            true,
+            format!("synced {} secs ago (max {staleness})", since.as_secs()),
+        ),
+        (_, Ok(Some(height))) if at_tip => (true, format!("at height {height}, the tip")),
+        (Some(since), _) => (
+            false,
+            format!(
+                "synced {} secs ago (max {staleness}) and not at the tip",
+                since.as_secs()
+            ),
+        ),
+        (None, _) => (false, "never synced".to_owned()),
+    };
+    check("wallet", HealthCheckKind::Readiness, pass, detail)
+}
+
+/// The fee estimations are refreshed, otherwise the channels can not
+/// agree on the fees with the peers.
+fn fees(ctx: &LampoDaemon) -> HealthCheck {
+    let fees = &ctx.onchain_manager().fees;
+    let (pass, detail) = match (fees.is_fresh(), fees.updated_at()) {
+        (true, _) => (true, "fresh".to_owned()),
+        (false, Some(updated_at)) => (false, format!("stale, last refresh at {updated_at}")),
+        (false, None) => (false, "never refreshed".to_owned()),
+    };
+    check("fees", HealthCheckKind::Readiness, pass, detail)
+}
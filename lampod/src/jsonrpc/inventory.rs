@@ -1,7 +1,10 @@
 //! Inventory method implementation
+use lampo_common::conf::layers;
 use lampo_common::json;
+use lampo_common::model::response;
 use lampo_jsonrpc::errors::{Error, RpcError};
 
+use crate::jsonrpc::to_rpc_error;
 use crate::LampoDaemon;
 
 pub fn get_info(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
@@ -17,3 +20,36 @@ pub fn get_info(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value,
     };
     Ok(result)
 }
+
+/// Ask the node to stop, the shutdown is done after the answer.
+pub fn json_stop(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("calling `stop` with request `{:?}`", request);
+    ctx.request_stop();
+    Ok(json::json!({ "message": "shutdown in progress" }))
+}
+
+/// Read the configuration file again, and apply the options
+/// that can change at runtime.
+pub fn json_reload(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("calling `reload` with request `{:?}`", request);
+    let reload = ctx.reload().map_err(to_rpc_error)?;
+    Ok(json::to_value(reload)?)
+}
+
+/// The effective configuration of the node, with the source of every
+/// value and without the secrets.
+pub fn json_list_configs(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("calling `listconfigs` with request `{:?}`", request);
+    let configs = ctx
+        .loaded_conf()
+        .list_options()
+        .into_iter()
+        .map(|option| response::Config {
+            name: option.key.to_owned(),
+            values: option.values,
+            source: option.source.to_string(),
+            env: layers::env_var(option.key),
+        })
+        .collect();
+    Ok(json::to_value(response::ListConfigs { configs })?)
+}
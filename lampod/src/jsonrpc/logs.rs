@@ -0,0 +1,23 @@
+//! Logs JSON RPC Interface!
+use lampo_common::json;
+use lampo_common::logger;
+use lampo_common::model::{request, response};
+use lampo_jsonrpc::errors::Error;
+
+use crate::jsonrpc::to_rpc_error;
+use crate::LampoDaemon;
+
+pub fn json_get_log(_: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::debug!("call for `getlog` with request `{:?}`", request);
+    let request: request::GetLog = json::from_value(request.clone())?;
+    let records = logger::records(request.level.as_deref(), request.limit).map_err(to_rpc_error)?;
+    Ok(json::to_value(response::Log { records })?)
+}
+
+pub fn json_set_log_level(_: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `setloglevel` with request `{:?}`", request);
+    let request: request::SetLogLevel = json::from_value(request.clone())?;
+    let levels =
+        logger::set_level(request.target.as_deref(), &request.level).map_err(to_rpc_error)?;
+    Ok(json::to_value(levels)?)
+}
@@ -0,0 +1,14 @@
+//! Maintenance JSON RPC Interface!
+use lampo_common::json;
+use lampo_jsonrpc::errors::Error;
+
+use crate::jsonrpc::to_rpc_error;
+use crate::persistence::LampoMaintenance;
+use crate::LampoDaemon;
+
+pub fn json_maintenance(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `maintenance` with request `{:?}`", request);
+    let maintenance = LampoMaintenance::new(ctx.persister(), ctx.wallet_manager());
+    let report = maintenance.run().map_err(to_rpc_error)?;
+    Ok(json::to_value(report)?)
+}
@@ -0,0 +1,106 @@
+//! Metrics JSON RPC Interface!
+use std::sync::Arc;
+use std::time::Duration;
+
+use lampo_common::json;
+use lampo_common::metrics::{self, Metrics};
+use lampo_common::model::response;
+use lampo_jsonrpc::errors::Error;
+
+use crate::LampoDaemon;
+
+/// Observe the time taken by the methods, so it is inside the metrics.
+pub fn observe(metrics: Arc<Metrics>) -> impl Fn(&str, Duration, bool) + Send + Sync {
+    move |method, elapsed, _| {
+        let labels = [("method", method)];
+        metrics.observe(&metrics::RPC_DURATION, &labels, elapsed.as_secs_f64());
+    }
+}
+
+pub fn json_get_metrics(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::debug!("call for `getmetrics` with request `{:?}`", request);
+    let metrics = ctx.metrics();
+    update_gauges(ctx, &metrics);
+    let text = metrics.render();
+    Ok(json::to_value(response::Metrics { text })?)
+}
+
+/// Set the gauges from the state of the node.
+fn update_gauges(ctx: &LampoDaemon, metrics: &Metrics) {
+    let channels = match ctx.channel_manager().list_channel_funds() {
+        Ok(channels) => channels,
+        Err(err) => {
+            log::debug!(target: "metrics", "impossible get the channel funds: {err}");
+            Vec::new()
+        }
+    };
+    for state in ["pending", "active", "inactive"] {
+        let count = channels
+            .iter()
+            .filter(|channel| channel.state == state)
+            .count();
+        metrics.set(&metrics::CHANNELS, &[("state", state)], count as f64);
+    }
+    let local_msat = channels
+        .iter()
+        .map(|channel| channel.our_amount_msat.msat())
+        .sum::<u64>();
+    let remote_msat = channels
+        .iter()
+        .map(|channel| {
+            let remote = channel.amount_msat.checked_sub(channel.our_amount_msat);
+            remote.unwrap_or_default().msat()
+        })
+        .sum::<u64>();
+    metrics.set(
+        &metrics::CHANNEL_BALANCE_MSAT,
+        &[("side", "local")],
+        local_msat as f64,
+    );
+    metrics.set(
+        &metrics::CHANNEL_BALANCE_MSAT,
+        &[("side", "remote")],
+        remote_msat as f64,
+    );
+
+    let peers = ctx.peer_manager().manager().list_peers().len();
+    metrics.set(&metrics::PEERS, &[], peers as f64);
+
+    // the cached state, so the scrape does not wait on a chain sync
+    let wallet = ctx.wallet_manager();
+    match wallet.cached_unspent() {
+        Ok(outputs) => {
+            let confirmed_sat = outputs
+                .iter()
+                .filter(|utxo| utxo.confirmed > 0)
+                .map(|utxo| utxo.amount_msat.to_sat_floor().sat())
+                .sum::<u64>();
+            metrics.set(
+                &metrics::WALLET_BALANCE_SAT,
+                &[("status", "confirmed")],
+                confirmed_sat as f64,
+            );
+        }
+        Err(err) => log::debug!(target: "metrics", "impossible get the wallet outputs: {err}"),
+    }
+    match wallet.pending_incoming_balance() {
+        Ok(unconfirmed_sat) => metrics.set(
+            &metrics::WALLET_BALANCE_SAT,
+            &[("status", "unconfirmed")],
+            unconfirmed_sat.sat() as f64,
+        ),
+        Err(err) => log::debug!(target: "metrics", "impossible get the pending balance: {err}"),
+    }
+
+    if let Some((height, _)) = ctx.onchain_manager().chain.tip() {
+        metrics.set(&metrics::CHAIN_HEIGHT, &[], height as f64);
+    }
+    let pending_events = ctx.handler().pending_events();
+    metrics.set(&metrics::EVENT_QUEUE_DEPTH, &[], pending_events as f64);
+    let journaled_events = ctx.event_journal().depth();
+    metrics.set(
+        &metrics::LDK_EVENT_QUEUE_DEPTH,
+        &[],
+        journaled_events as f64,
+    );
+}
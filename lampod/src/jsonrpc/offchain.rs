@@ -1,45 +1,125 @@
 //! Offchain RPC methods
 use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 use std::time::Duration;
 
-use lampo_common::event::ln::LightningEvent;
-use lampo_common::event::Event;
+use lampo_common::bitcoin::hashes::hex::FromHex;
+use lampo_common::bitcoin::hashes::Hash;
+use lampo_common::bitcoin::secp256k1::PublicKey;
 use lampo_common::handler::Handler;
 use lampo_common::ldk;
+use lampo_common::ldk::ln::{PaymentHash, PaymentPreimage};
 use lampo_common::ldk::offers::offer;
+use lampo_common::model::request::CancelHoldInvoice;
+use lampo_common::model::request::CancelPayment;
+use lampo_common::model::request::CheckMessage;
+use lampo_common::model::request::GenerateHoldInvoice;
 use lampo_common::model::request::GenerateInvoice;
 use lampo_common::model::request::GenerateOffer;
+use lampo_common::model::request::GetPaymentRoute;
+use lampo_common::model::request::IsInvoicePaid;
 use lampo_common::model::request::KeySend;
+use lampo_common::model::request::ListInvoices;
+use lampo_common::model::request::ListPays;
 use lampo_common::model::request::Pay;
+use lampo_common::model::request::PayLnurl;
+use lampo_common::model::request::SettleHoldInvoice;
+use lampo_common::model::request::SignMessage;
 use lampo_common::model::response;
-use lampo_common::model::response::PayResult;
 use lampo_common::model::response::{Invoice, InvoiceInfo};
 use lampo_common::{json, model::request::DecodeInvoice};
-use lampo_jsonrpc::errors::{Error, RpcError};
+use lampo_jsonrpc::errors::Error;
 
+use crate::jsonrpc::to_rpc_error;
 use crate::rpc_error;
 use crate::LampoDaemon;
 
 pub fn json_invoice(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
     log::info!("call for `invoice` with request `{:?}`", request);
     let request: GenerateInvoice = json::from_value(request.clone())?;
+    let offchain_manager = ctx.offchain_manager();
+    if request.no_expiry && (request.expiring_in.is_some() || request.expiry_unix.is_some()) {
+        return Err(rpc_error!(
+            "`no_expiry` can not be used together with `expiring_in` or `expiry_unix`"
+        ));
+    }
+    let invoice = match (request.expiring_in, request.expiry_unix) {
+        (Some(_), Some(_)) => {
+            return Err(rpc_error!("`expiring_in` and `expiry_unix` can not be used together"))
+        }
+        (_, Some(expiry_unix)) => offchain_manager.generate_invoice_expiring_at(
+            request.amount_msat,
+            &request.description,
+            expiry_unix,
+            request.label.as_deref(),
+            request.fallback.as_deref(),
+            request.metadata.as_deref(),
+        ),
+        (expiring_in, None) => offchain_manager.generate_invoice(
+            request.amount_msat,
+            &request.description,
+            if request.no_expiry {
+                None
+            } else {
+                Some(expiring_in.unwrap_or(10000))
+            },
+            request.label.as_deref(),
+            request.fallback.as_deref(),
+            request.metadata.as_deref(),
+        ),
+    }
+    .map_err(to_rpc_error)?;
+    Ok(json::to_value(Invoice::from(&invoice))?)
+}
+
+pub fn json_list_invoices(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `listinvoices` with request `{:?}`", request);
+    let request: ListInvoices = json::from_value(request.clone())?;
+    let invoices = ctx
+        .offchain_manager()
+        .list_invoices(request.label.as_deref(), request.payment_hash.as_deref());
+    Ok(json::to_value(response::ListInvoices { invoices })?)
+}
+
+pub fn json_hold_invoice(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `holdinvoice` with request `{:?}`", request);
+    let request: GenerateHoldInvoice = json::from_value(request.clone())?;
+    let payment_hash = <[u8; 32]>::from_hex(&request.payment_hash)
+        .map_err(|err| rpc_error!("invalid payment hash `{}`: {err}", request.payment_hash))?;
     let invoice = ctx
         .offchain_manager()
-        .generate_invoice(
+        .generate_hold_invoice(
+            PaymentHash(payment_hash),
             request.amount_msat,
             &request.description,
             request.expiring_in.unwrap_or(10000),
         )
-        .map_err(|err| {
-            Error::Rpc(RpcError {
-                code: -1,
-                message: format!("{err}"),
-                data: None,
-            })
-        })?;
-    let invoice = Invoice {
-        bolt11: invoice.to_string(),
-    };
+        .map_err(to_rpc_error)?;
+    Ok(json::to_value(&invoice)?)
+}
+
+pub fn json_settle_invoice(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `settleinvoice` with request `{:?}`", request);
+    let request: SettleHoldInvoice = json::from_value(request.clone())?;
+    let preimage = <[u8; 32]>::from_hex(&request.preimage)
+        .map_err(|err| rpc_error!("invalid preimage: {err}"))?;
+    let invoice = ctx
+        .offchain_manager()
+        .settle_hold_invoice(PaymentPreimage(preimage))
+        .map_err(to_rpc_error)?;
+    Ok(json::to_value(&invoice)?)
+}
+
+pub fn json_cancel_invoice(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `cancelinvoice` with request `{:?}`", request);
+    let request: CancelHoldInvoice = json::from_value(request.clone())?;
+    let payment_hash = <[u8; 32]>::from_hex(&request.payment_hash)
+        .map_err(|err| rpc_error!("invalid payment hash `{}`: {err}", request.payment_hash))?;
+    let invoice = ctx
+        .offchain_manager()
+        .cancel_hold_invoice(PaymentHash(payment_hash))
+        .map_err(to_rpc_error)?;
     Ok(json::to_value(&invoice)?)
 }
 
@@ -73,13 +153,7 @@ pub fn json_decode_invoice(ctx: &LampoDaemon, request: &json::Value) -> Result<j
     let invoice = ctx
         .offchain_manager()
         .decode_invoice(&request.invoice_str)
-        .map_err(|err| {
-            Error::Rpc(RpcError {
-                code: -1,
-                message: format!("{err}"),
-                data: None,
-            })
-        })?;
+        .map_err(to_rpc_error)?;
     let invoice = InvoiceInfo {
         amount_msa: invoice.amount_milli_satoshis(),
         network: invoice.network().to_string(),
@@ -90,8 +164,19 @@ pub fn json_decode_invoice(ctx: &LampoDaemon, request: &json::Value) -> Result<j
             }
         },
         routes: Vec::new(),
-        hints: Vec::new(),
+        // The short channel id inside the hints can be an scid alias.
+        hints: invoice
+            .route_hints()
+            .iter()
+            .flat_map(|hint| hint.0.iter())
+            .map(|hop| format!("{}:{}", hop.src_node_id, hop.short_channel_id))
+            .collect(),
         expiry_time: invoice.expiry_time().as_millis() as u64,
+        fallbacks: invoice
+            .fallback_addresses()
+            .iter()
+            .map(|address| address.to_string())
+            .collect(),
     };
     Ok(json::to_value(&invoice)?)
 }
@@ -100,54 +185,156 @@ pub fn json_pay(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value,
     log::info!("call for `pay` with request `{:?}`", request);
     let request: Pay = json::from_value(request.clone())?;
     let events = ctx.handler().events();
-    if let Ok(_) = offer::Offer::from_str(&request.invoice_str) {
-        ctx.offchain_manager()
-            .pay_offer(&request.invoice_str, request.amount)
-            .map_err(|err| rpc_error!("{err}"))?;
-    } else {
-        ctx.offchain_manager()
-            .pay_invoice(&request.invoice_str, request.amount)
-            .map_err(|err| rpc_error!("{err}"))?;
-    }
-    // FIXME: this will loop when the Payment event is not generated
-    loop {
-        let event = events
-            .recv_timeout(Duration::from_secs(30))
-            // FIXME: this should be avoided, the `?` should be used here
-            .map_err(|err| {
-                Error::Rpc(RpcError {
-                    code: -1,
-                    message: format!("{err}"),
-                    data: None,
-                })
-            })?;
-
-        if let Event::Lightning(LightningEvent::PaymentEvent {
-            payment_hash,
-            path,
-            state,
-        }) = event
-        {
-            return Ok(json::to_value(PayResult {
-                state,
-                path,
-                payment_hash,
-            })?);
+    let custom_tlvs = request.custom_tlvs().map_err(to_rpc_error)?;
+    let timeout = Duration::from_secs(request.timeout_secs.unwrap_or(ctx.conf().pay_timeout_secs));
+    let offchain_manager = ctx.offchain_manager();
+    // set by `cancelpayment`
+    let cancel = Arc::new(AtomicBool::new(false));
+    let (payment_id, payment_hash) = if offer::Offer::from_str(&request.invoice_str).is_ok() {
+        if !custom_tlvs.is_empty() {
+            return Err(rpc_error!("custom TLVs are not supported when paying an offer"));
         }
-    }
+        if request.max_total_cltv_expiry_delta.is_some() {
+            return Err(rpc_error!(
+                "max total CLTV expiry delta is not supported when paying an offer"
+            ));
+        }
+        let payment_id = offchain_manager
+            .pay_offer(&request.invoice_str, request.amount, timeout, cancel)
+            .map_err(to_rpc_error)?;
+        (payment_id, None)
+    } else {
+        let payment_id = offchain_manager
+            .pay_invoice(
+                &request.invoice_str,
+                request.amount,
+                custom_tlvs,
+                request.max_total_cltv_expiry_delta,
+                timeout,
+                cancel,
+            )
+            .map_err(to_rpc_error)?;
+        (payment_id, Some(PaymentHash(payment_id.0)))
+    };
+    let result = offchain_manager
+        .wait_payment(events, payment_id, payment_hash, timeout)
+        .map_err(to_rpc_error)?;
+    Ok(json::to_value(result)?)
+}
+
+pub fn json_pay_lnurl(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `paylnurl` with request `{:?}`", request);
+    let request: PayLnurl = json::from_value(request.clone())?;
+    let events = ctx.handler().events();
+    let timeout = Duration::from_secs(request.timeout_secs.unwrap_or(ctx.conf().pay_timeout_secs));
+    let offchain_manager = ctx.offchain_manager();
+    let cancel = Arc::new(AtomicBool::new(false));
+    let (payment_id, invoice) = offchain_manager
+        .pay_lnurl(
+            &request.lnurl,
+            request.amount_msat,
+            request.comment,
+            timeout,
+            cancel,
+        )
+        .map_err(to_rpc_error)?;
+    let payment_hash = PaymentHash(invoice.payment_hash().to_byte_array());
+    let result = offchain_manager
+        .wait_payment(events, payment_id, Some(payment_hash), timeout)
+        .map_err(to_rpc_error)?;
+    Ok(json::to_value(result)?)
+}
+
+pub fn json_cancel_payment(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `cancelpayment` with request `{:?}`", request);
+    let request: CancelPayment = json::from_value(request.clone())?;
+    let offchain_manager = ctx.offchain_manager();
+    let payment_id = offchain_manager
+        .payment_id(&request.invoice_str)
+        .map_err(to_rpc_error)?;
+    offchain_manager
+        .cancel_payment(&payment_id)
+        .map_err(to_rpc_error)?;
+    Ok(json::json!({}))
+}
+
+pub fn json_is_invoice_paid(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `isinvoicepaid` with request `{:?}`", request);
+    let request: IsInvoicePaid = json::from_value(request.clone())?;
+    let status = ctx
+        .offchain_manager()
+        .is_invoice_paid(&request.invoice_str)
+        .map_err(to_rpc_error)?;
+    Ok(json::to_value(&status)?)
+}
+
+pub fn json_get_payment_route(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `getpaymentroute` with request `{:?}`", request);
+    let request: GetPaymentRoute = json::from_value(request.clone())?;
+    let payment_hash = <[u8; 32]>::from_hex(&request.payment_hash)
+        .map_err(|err| rpc_error!("invalid payment hash `{}`: {err}", request.payment_hash))?;
+    let route = ctx
+        .offchain_manager()
+        .get_payment_route(&PaymentHash(payment_hash))
+        .map_err(to_rpc_error)?;
+    Ok(json::to_value(&route)?)
+}
+
+pub fn json_list_pays(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `listpays` with request `{:?}`", request);
+    let request: ListPays = json::from_value(request.clone())?;
+    let payment_hash = request
+        .payment_hash
+        .as_deref()
+        .map(|hash| {
+            <[u8; 32]>::from_hex(hash)
+                .map(PaymentHash)
+                .map_err(|err| rpc_error!("invalid payment hash `{hash}`: {err}"))
+        })
+        .transpose()?;
+    let pays = ctx.offchain_manager().list_payments(payment_hash.as_ref());
+    Ok(json::to_value(response::Pays { pays })?)
 }
 
 pub fn json_keysend(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
     log::debug!("call for `keysend` with request `{:?}`", request);
     let request: KeySend = json::from_value(request.clone())?;
     ctx.offchain_manager()
-        .keysend(request.destination, request.amount_msat)
-        .map_err(|err| {
-            Error::Rpc(RpcError {
-                code: -1,
-                message: format!("{err}"),
-                data: None,
-            })
-        })?;
+        .keysend(
+            request.destination,
+            request.amount_msat,
+            request.max_total_cltv_expiry_delta,
+        )
+        .map_err(to_rpc_error)?;
     Ok(json::json!({}))
 }
+
+pub fn json_sign_message(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `signmessage` with request `{:?}`", request);
+    let request: SignMessage = json::from_value(request.clone())?;
+    let signature = ctx
+        .offchain_manager()
+        .sign_message(&request.message)
+        .map_err(to_rpc_error)?;
+    Ok(json::to_value(&signature)?)
+}
+
+pub fn json_check_message(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `checkmessage` with request `{:?}`", request);
+    let request: CheckMessage = json::from_value(request.clone())?;
+    let pubkey = request
+        .pubkey
+        .map(|pubkey| {
+            PublicKey::from_str(&pubkey)
+                .map_err(|err| rpc_error!("invalid pubkey `{pubkey}`: {err}"))
+        })
+        .transpose()?;
+    let result = ctx
+        .offchain_manager()
+        .check_message(&request.message, &request.zbase, pubkey)
+        .map_err(to_rpc_error)?;
+    Ok(json::to_value(&result)?)
+}
@@ -1,36 +1,116 @@
 //! On Chain RPC methods
 use lampo_common::json;
+use lampo_common::model::request;
+use lampo_common::model::response::{ListFunds, TxHistory};
+use lampo_common::model::Sat;
 use lampo_jsonrpc::errors::{Error, RpcError};
 
+use crate::jsonrpc::to_rpc_error;
 use crate::LampoDaemon;
 
 pub fn json_new_addr(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
     log::info!("call for `new_addr` with request {:?}", request);
-    let resp = ctx.wallet_manager().get_onchain_address();
-    match resp {
-        Ok(resp) => Ok(json::to_value(resp)?),
-        Err(err) => Err(Error::Rpc(RpcError {
-            code: -1,
-            message: format!("{err}"),
-            data: None,
-        })),
-    }
+    let resp = ctx
+        .wallet_manager()
+        .get_onchain_address()
+        .map_err(to_rpc_error)?;
+    Ok(json::to_value(resp)?)
+}
+
+pub fn json_bip21_uri(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `getbip21uri` with request `{:?}`", request);
+    let request: request::Bip21Uri = json::from_value(request.clone())?;
+    let uri = ctx
+        .offchain_manager()
+        .get_bip21_uri(request.amount_sat, request.label)
+        .map_err(to_rpc_error)?;
+    Ok(json::to_value(uri)?)
+}
+
+pub fn json_unified_receive(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `unifiedreceive` with request `{:?}`", request);
+    let request: request::UnifiedReceive = json::from_value(request.clone())?;
+    let receive = ctx
+        .offchain_manager()
+        .unified_receive(
+            request.amount_msat,
+            &request.description,
+            request.expiring_in,
+        )
+        .map_err(to_rpc_error)?;
+    Ok(json::to_value(receive)?)
 }
 
 pub fn json_funds(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
     log::info!("call for `funds` with request `{:?}`", request);
-    match ctx.wallet_manager().list_transactions() {
-        Ok(transactions) => Ok(json::json!({
+    let wallet = ctx.wallet_manager();
+    match wallet
+        .list_unspent()
+        .and_then(|transactions| Ok((transactions, wallet.pending_incoming_balance()?)))
+    {
+        Ok((transactions, pending_incoming_sat)) => Ok(json::json!({
             "transactions": transactions,
+            "pending_incoming_sat": pending_incoming_sat,
         })),
-        Err(err) => Err(Error::Rpc(RpcError {
-            code: -1,
-            message: format!("{err}"),
-            data: None,
-        })),
+        Err(err) => Err(to_rpc_error(err)),
     }
 }
 
+pub fn json_list_funds(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `listfunds` with request `{:?}`", request);
+    // the cached state, so the call does not wait on a chain sync
+    let outputs = ctx
+        .wallet_manager()
+        .cached_unspent()
+        .map_err(to_rpc_error)?;
+    let channels = ctx
+        .channel_manager()
+        .list_channel_funds()
+        .map_err(to_rpc_error)?;
+    let funds = ListFunds {
+        total_onchain_sat: Sat::checked_sum(
+            outputs.iter().map(|utxo| utxo.amount_msat.to_sat_floor()),
+        )
+        .map_err(to_rpc_error)?,
+        total_channel_sat: Sat::checked_sum(
+            channels
+                .iter()
+                .map(|channel| channel.our_amount_msat.to_sat_floor()),
+        )
+        .map_err(to_rpc_error)?,
+        outputs,
+        channels,
+    };
+    Ok(json::to_value(funds)?)
+}
+
+pub fn json_list_transactions(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `listtransactions` with request `{:?}`", request);
+    let transactions = ctx
+        .wallet_manager()
+        .list_transaction_history()
+        .map_err(to_rpc_error)?;
+    Ok(json::to_value(TxHistory { transactions })?)
+}
+
+pub fn json_fee_rates(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `feerates` with request `{:?}`", request);
+    let fee_rates = ctx.onchain_manager().fees.fee_rates();
+    Ok(json::to_value(fee_rates)?)
+}
+
+pub fn json_chain_info(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `getchaininfo` with request `{:?}`", request);
+    let info = ctx.onchain_manager().chain_info().map_err(to_rpc_error)?;
+    Ok(json::to_value(info)?)
+}
+
 pub fn json_estimate_fees(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
     log::info!("call for `estimate_fees` with request `{:?}`", request);
     let response = ctx.onchain_manager().estimated_fees();
@@ -43,3 +123,18 @@ pub fn json_estimate_fees(ctx: &LampoDaemon, request: &json::Value) -> Result<js
         })),
     }
 }
+
+pub fn json_pending_bumps(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `pendingbumps` with request `{:?}`", request);
+    let packages = ctx.bump_manager().packages();
+    Ok(json::to_value(packages)?)
+}
+
+pub fn json_pending_broadcasts(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `pendingbroadcasts` with request `{:?}`", request);
+    let pending = ctx.onchain_manager().broadcaster.pending();
+    Ok(json::to_value(pending)?)
+}
@@ -1,9 +1,11 @@
 //! Open Channel RPC Method implementation
 
+use lampo_common::error::LampoError;
 use lampo_common::json;
-use lampo_common::model::request;
-use lampo_jsonrpc::errors::{Error, RpcError};
+use lampo_common::model::{request, response};
+use lampo_jsonrpc::errors::Error;
 
+use crate::jsonrpc::to_rpc_error;
 use crate::ln::events::ChannelEvents;
 use crate::LampoDaemon;
 
@@ -11,6 +13,12 @@ pub fn json_open_channel(ctx: &LampoDaemon, request: &json::Value) -> Result<jso
     log::info!("call for `openchannel` with request {:?}", request);
     let request: request::OpenChannel = json::from_value(request.clone())?;
 
+    // Reject the channel before contacting the peer if it
+    // violates one of the limits configured by the user.
+    ctx.channel_manager()
+        .check_outbound_limits(request.amount)
+        .map_err(to_rpc_error)?;
+
     // LDK's `create_channel()` doesn't check if you are currently connected
     // to the given peer so we need to check ourselves
     // FIXME: remove unwrap!
@@ -19,21 +27,13 @@ pub fn json_open_channel(ctx: &LampoDaemon, request: &json::Value) -> Result<jso
         .is_connected_with(request.node_id().unwrap())
     {
         log::trace!("we are not connected with the peer {}", request.node_id);
-        let conn = request::Connect::try_from(request.clone()).map_err(|err| {
-            Error::Rpc(RpcError {
-                code: -1,
-                message: format!("{err}"),
-                data: None,
-            })
-        })?;
+        let conn = request::Connect::try_from(request.clone()).map_err(to_rpc_error)?;
         let conn = json::to_value(conn)?;
         let _ = ctx.rt.enter();
         ctx.call("connect", conn).map_err(|err| {
-            Error::Rpc(RpcError {
-                code: -1,
-                message: format!("connect fails with: {err}"),
-                data: None,
-            })
+            to_rpc_error(LampoError::PeerFailure(format!(
+                "connect fails with: {err}"
+            )))
         })?;
     }
 
@@ -41,14 +41,28 @@ pub fn json_open_channel(ctx: &LampoDaemon, request: &json::Value) -> Result<jso
     // - When there is an error how we return back to the user?
     // - In this case there is some feedback that ldk need to give us
     // before return the message, so we should design a solution for this.
-    let resp = ctx.channel_manager().open_channel(request);
-    let resp = match resp {
-        Ok(resp) => Ok(resp),
-        Err(err) => Err(Error::Rpc(RpcError {
-            code: -1,
-            message: format!("{err}"),
-            data: None,
-        })),
-    };
-    Ok(json::to_value(resp?)?)
+    let resp = ctx
+        .channel_manager()
+        .open_channel(request)
+        .map_err(to_rpc_error)?;
+    Ok(json::to_value(resp)?)
+}
+
+pub fn json_estimate_open_cost(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `estimateopencost` with request {:?}", request);
+    let request: request::EstimateOpenCost = json::from_value(request.clone())?;
+    let conf_target = request.conf_target.unwrap_or(6);
+    let total_sat = ctx
+        .channel_manager()
+        .estimate_channel_open_cost(request.amount, conf_target)
+        .map_err(to_rpc_error)?;
+    Ok(json::to_value(response::OpenCost {
+        amount_sat: request.amount,
+        fee_sat: total_sat - request.amount,
+        total_sat,
+        conf_target,
+    })?)
 }
@@ -1,45 +1,120 @@
 //! Peer Control JSON RPC Interface!
+use std::time::Duration;
+
 use lampo_common::json;
 use lampo_common::model::Connect;
+use lampo_common::model::{request, response};
 use lampo_jsonrpc::errors::Error;
 use lampo_jsonrpc::errors::RpcError;
 
+use crate::ln::onion_message::CustomOnionMessage;
+use crate::ln::tor;
+use crate::rpc_error;
 use crate::{ln::events::PeerEvents, LampoDaemon};
 
 pub fn json_connect(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
     log::info!("call for `connect` with request `{:?}`", request);
     let input: Connect = json::from_value(request.clone())?;
-    let host = input.addr();
-    let node_id = match input.node_id() {
-        Err(err) => {
-            let rpc_error = Error::Rpc(RpcError {
-                code: -1,
-                message: format!("{err}"),
-                data: None,
-            });
-            return Err(rpc_error);
-        }
-        Ok(id) => id,
+    let node_id = input.node_id().map_err(|err| rpc_error!("{err}"))?;
+    let host = input.addr().map_err(|err| rpc_error!("{err}"))?;
+    let (addr, port) = tor::host_and_port(&host).map_err(|err| rpc_error!("{err}"))?;
+    ctx.rt
+        .block_on(ctx.peer_manager().connect(node_id, host))
+        .map_err(|err| rpc_error!("{err}"))?;
+    let connect = Connect {
+        node_id: node_id.to_string(),
+        addr,
+        port: port as u64,
     };
-    let result = match host {
-        Err(err) => {
-            let rpc_error = Error::Rpc(RpcError {
-                code: -1,
-                message: format!("{err}"),
-                data: None,
-            });
-            Err(rpc_error)
+    Ok(json::to_value(connect)?)
+}
+
+pub fn json_disconnect(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `disconnect` with request `{:?}`", request);
+    let input: request::Disconnect = json::from_value(request.clone())?;
+    let node_id = input.node_id().map_err(|err| rpc_error!("{err}"))?;
+    ctx.rt
+        .block_on(ctx.peer_manager().disconnect(node_id, input.force))
+        .map_err(|err| rpc_error!("{err}"))?;
+    Ok(json::json!({}))
+}
+
+pub fn json_list_peers(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `listpeers` with request `{:?}`", request);
+    let peers = ctx
+        .peer_manager()
+        .list_peers()
+        .map_err(|err| rpc_error!("{err}"))?;
+    Ok(json::to_value(peers)?)
+}
+
+pub fn json_ping(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `ping` with request `{:?}`", request);
+    let input: request::Ping = json::from_value(request.clone())?;
+    let node_id = input.node_id().map_err(|err| rpc_error!("{err}"))?;
+    let count = input.count().map_err(|err| rpc_error!("{err}"))?;
+    let ping = ctx
+        .peer_manager()
+        .ping(node_id, count)
+        .map_err(|err| rpc_error!("{err}"))?;
+    Ok(json::to_value(ping)?)
+}
+
+pub fn json_set_peer_list(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `setpeerlist` with request `{:?}`", request);
+    let input: request::SetPeerList = json::from_value(request.clone())?;
+    let node_id = input.node_id().map_err(|err| rpc_error!("{err}"))?;
+    let lists = ctx
+        .peer_manager()
+        .set_peer_list(input.list, node_id, input.remove)
+        .map_err(|err| rpc_error!("{err}"))?;
+    Ok(json::to_value(lists)?)
+}
+
+pub fn json_send_onion_message(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `sendonionmessage` with request `{:?}`", request);
+    let input: request::SendOnionMessage = json::from_value(request.clone())?;
+    let message = CustomOnionMessage {
+        tlv_type: input.tlv_type,
+        payload: input.payload().map_err(|err| rpc_error!("{err}"))?,
+    };
+    let peer_manager = ctx.peer_manager();
+    let awaiting_connection = match input.reply_to {
+        Some(id) => {
+            if input.destination.is_some() {
+                return Err(rpc_error!("`destination` and `reply_to` can not be used together"));
+            }
+            peer_manager
+                .reply_onion_message(id, message)
+                .map_err(|err| rpc_error!("{err}"))?;
+            false
         }
-        Ok(host_value) => {
-            ctx.rt
-                .block_on(ctx.peer_manager().connect(node_id, host_value))
-                .map_err(|err| RpcError {
-                    code: -1,
-                    message: format!("{err}"),
-                    data: None,
-                })?;
-            Ok(request.clone())
+        None => {
+            let destination = input.destination().map_err(|err| rpc_error!("{err}"))?;
+            peer_manager
+                .send_onion_message(message, destination, input.reply_path)
+                .map_err(|err| rpc_error!("{err}"))?
         }
     };
-    result
+    Ok(json::to_value(response::SendOnionMessage {
+        awaiting_connection,
+    })?)
+}
+
+pub fn json_wait_onion_message(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `waitonionmessage` with request `{:?}`", request);
+    let input: request::WaitOnionMessage = json::from_value(request.clone())?;
+    let timeout = Duration::from_secs(input.timeout_secs.unwrap_or(30));
+    let message = ctx
+        .peer_manager()
+        .onion_messages()
+        .wait_message(timeout)
+        .map_err(|err| rpc_error!("{err}"))?;
+    Ok(json::to_value(message)?)
 }
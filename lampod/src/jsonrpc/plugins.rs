@@ -0,0 +1,36 @@
+//! Plugins JSON RPC Interface!
+use lampo_common::json;
+use lampo_common::model::response;
+use lampo_jsonrpc::errors::Error;
+use lampo_jsonrpc::Handler;
+
+use crate::jsonrpc::to_rpc_error;
+use crate::plugins::LampoPlugins;
+use crate::LampoDaemon;
+
+/// Serve the methods added by the plugins, so they are called as the
+/// methods of lampo. A method that lampo already serves is not replaced,
+/// so they must be added after the methods of lampo.
+pub fn add_plugin_methods(handler: &Handler<LampoDaemon>, plugins: &LampoPlugins) {
+    for method in plugins.methods() {
+        if handler.has_rpc(&method) {
+            log::warn!(target: "plugins", "method `{method}` of the plugins is already served by lampo");
+            continue;
+        }
+        let name = method.clone();
+        handler.add_method(&method, move |ctx: &LampoDaemon, request: &json::Value| {
+            log::info!(
+                "call for `{name}` of the plugins with request `{:?}`",
+                request
+            );
+            let result = ctx.plugins().call_method(&name, request);
+            result.map_err(to_rpc_error)
+        });
+    }
+}
+
+pub fn json_list_plugins(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `listplugins` with request `{:?}`", request);
+    let plugins = ctx.plugins().list();
+    Ok(json::to_value(response::Plugins { plugins })?)
+}
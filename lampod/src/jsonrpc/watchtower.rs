@@ -0,0 +1,91 @@
+//! Watchtowers JSON RPC Interface!
+use std::sync::Arc;
+
+use lampo_common::error::LampoError;
+use lampo_common::json;
+use lampo_common::model::{request, response};
+use lampo_jsonrpc::errors::Error;
+
+use crate::jsonrpc::to_rpc_error;
+use crate::watchtower::protocol::TowerAddress;
+use crate::watchtower::LampoTowerServer;
+use crate::LampoDaemon;
+
+/// Add a tower and send to it the backlog of the channels, the tower
+/// is kept also when it can not be reached.
+pub fn json_add_tower(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::info!("call for `addtower` with request `{:?}`", request);
+    let request: request::AddTower = json::from_value(request.clone())?;
+    TowerAddress::parse(&request.tower)
+        .map_err(|err| to_rpc_error(LampoError::InvalidParams(format!("{err}"))))?;
+    let towers = ctx.towers();
+    let tower_id = towers.add_tower(&request.tower).map_err(to_rpc_error)?;
+    // the error is reported inside the status of the tower
+    let _ = towers.flush_tower(&tower_id);
+    let tower = towers
+        .towers()
+        .into_iter()
+        .find(|tower| tower.tower_id == tower_id);
+    Ok(json::to_value(tower)?)
+}
+
+pub fn json_list_towers(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::debug!("call for `listtowers` with request `{:?}`", request);
+    let towers = ctx.towers().towers();
+    Ok(json::to_value(response::Towers { towers })?)
+}
+
+pub fn json_tower_status(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::debug!("call for `towerstatus` with request `{:?}`", request);
+    let request: request::TowerStatus = json::from_value(request.clone())?;
+    let Some(status) = ctx.towers().status(&request.tower_id) else {
+        return Err(to_rpc_error(LampoError::InvalidParams(format!(
+            "the tower `{}` is unknown",
+            request.tower_id
+        ))));
+    };
+    Ok(json::to_value(status)?)
+}
+
+/// The tower served by the node, when it is enabled.
+fn tower(ctx: &LampoDaemon) -> Result<Arc<LampoTowerServer>, Error> {
+    ctx.tower().ok_or_else(|| {
+        to_rpc_error(LampoError::InvalidParams(
+            "the tower is disabled, set `tower-bind` to enable it".to_owned(),
+        ))
+    })
+}
+
+pub fn json_tower_info(ctx: &LampoDaemon, request: &json::Value) -> Result<json::Value, Error> {
+    log::debug!("call for `towerinfo` with request `{:?}`", request);
+    let info = tower(ctx)?.info().map_err(to_rpc_error)?;
+    Ok(json::to_value(info)?)
+}
+
+pub fn json_list_tower_clients(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::debug!("call for `listtowerclients` with request `{:?}`", request);
+    let clients = tower(ctx)?.clients().map_err(to_rpc_error)?;
+    Ok(json::to_value(response::TowerClients { clients })?)
+}
+
+/// Forget a client of the tower with its appointments, the client can
+/// register again.
+pub fn json_del_tower_client(
+    ctx: &LampoDaemon,
+    request: &json::Value,
+) -> Result<json::Value, Error> {
+    log::info!("call for `deltowerclient` with request `{:?}`", request);
+    let request: request::DelTowerClient = json::from_value(request.clone())?;
+    let tower = tower(ctx)?;
+    if !tower.del_client(&request.user_id).map_err(to_rpc_error)? {
+        return Err(to_rpc_error(LampoError::InvalidParams(format!(
+            "the client `{}` is unknown",
+            request.user_id
+        ))));
+    }
+    let clients = tower.clients().map_err(to_rpc_error)?;
+    Ok(json::to_value(response::TowerClients { clients })?)
+}
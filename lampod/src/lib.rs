@@ -15,35 +15,55 @@ mod builtin;
 pub mod chain;
 pub mod command;
 pub mod handler;
+pub mod http;
 pub mod jsonrpc;
 pub mod ln;
 pub mod persistence;
+pub mod plugins;
 pub mod utils;
+pub mod watchtower;
 
-use std::cell::Cell;
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
+use std::time::Duration;
 
 use tokio::runtime::Runtime;
 
+use lampo_common::auth::RpcAuth;
 use lampo_common::backend::Backend;
 use lampo_common::bitcoin::absolute::Height;
-use lampo_common::conf::LampoConf;
+use lampo_common::chan;
+use lampo_common::conf::{LampoConf, RELOADABLE_OPTIONS};
 use lampo_common::error;
+use lampo_common::handler::Handler as EventHandler;
 use lampo_common::json;
 use lampo_common::ldk::events::Event;
 use lampo_common::ldk::processor::{BackgroundProcessor, GossipSync};
 use lampo_common::ldk::routing::gossip::P2PGossipSync;
+use lampo_common::ldk::sign::EntropySource;
+use lampo_common::logger;
+use lampo_common::metrics::Metrics;
+use lampo_common::model::response;
+use lampo_common::sync::MutexExt;
 use lampo_common::wallet::WalletManager;
 
+use crate::actions::event_bus::LampoEventBus;
+use crate::actions::event_journal::LampoEventJournal;
 use crate::actions::handler::LampoHandler;
-use crate::actions::Handler;
-use crate::chain::LampoChainManager;
+use crate::actions::webhooks::LampoWebhooks;
+use crate::chain::{LampoChainManager, LampoWalletSync};
 use crate::handler::external_handler::ExternalHandler;
-use crate::ln::OffchainManager;
+use crate::ln::{LampoAutofees, LampoBumpManager, LampoGraph, OffchainManager};
+use crate::ln::{LampoGraphPersister, GRAPH_PERSIST_INTERVAL};
+use crate::ln::PING_INTERVAL;
+use crate::ln::{LampoRapidGossip, LampoRapidGossipSync};
 use crate::ln::{LampoChannelManager, LampoInventoryManager, LampoPeerManager};
-use crate::persistence::LampoPersistence;
+use crate::persistence::{LampoDatastore, LampoPersistence, LampoRemoteStore};
+use crate::plugins::LampoPlugins;
 use crate::utils::logger::LampoLogger;
+use crate::watchtower::{LampoTowerClient, LampoTowerServer};
 
 /// LampoDaemon is the main data structure that uses the facade
 /// pattern to hide the complexity of the LDK library. You can interact
@@ -57,16 +77,40 @@ use crate::utils::logger::LampoLogger;
 #[repr(C)]
 pub struct LampoDaemon {
     conf: LampoConf,
+    /// The configuration with the options applied by `reload`.
+    loaded_conf: Mutex<LampoConf>,
     peer_manager: Option<Arc<LampoPeerManager>>,
     onchain_manager: Option<Arc<LampoChainManager>>,
     channel_manager: Option<Arc<LampoChannelManager>>,
     inventory_manager: Option<Arc<LampoInventoryManager>>,
     wallet_manager: Arc<dyn WalletManager>,
+    wallet_sync: Arc<LampoWalletSync>,
     offchain_manager: Option<Arc<OffchainManager>>,
+    bump_manager: Option<Arc<LampoBumpManager>>,
+    rapid_gossip: Option<Arc<LampoRapidGossip>>,
+    graph_persister: Option<Arc<LampoGraphPersister>>,
+    /// The automatic fees of the channels, with `autofees`.
+    autofees: Option<Arc<LampoAutofees>>,
     logger: Arc<LampoLogger>,
     persister: Arc<LampoPersistence>,
+    datastore: Arc<LampoDatastore>,
+    towers: Arc<LampoTowerClient>,
+    /// The tower served to the other nodes, with `tower-bind`.
+    tower: Option<Arc<LampoTowerServer>>,
     handler: Option<Arc<LampoHandler>>,
-    process: Cell<Option<BackgroundProcessor>>,
+    rpc_auth: Option<Arc<RpcAuth>>,
+    event_bus: Arc<LampoEventBus>,
+    /// The ldk events between ldk and the handler.
+    event_journal: Arc<LampoEventJournal>,
+    webhooks: Arc<LampoWebhooks>,
+    metrics: Arc<Metrics>,
+    plugins: Arc<LampoPlugins>,
+    process: Mutex<Option<BackgroundProcessor>>,
+    /// Set on shutdown, the background tasks end.
+    stopped: Arc<AtomicBool>,
+    stop_request: (chan::Sender<()>, chan::Receiver<()>),
+    /// The step of the shutdown in progress, to report what is stuck.
+    shutdown_step: Mutex<Option<&'static str>>,
 
     // FIXME: remove this
     rt: Runtime,
@@ -78,21 +122,62 @@ unsafe impl Sync for LampoDaemon {}
 impl LampoDaemon {
     pub fn new(config: LampoConf, wallet_manager: Arc<dyn WalletManager>) -> Self {
         let root_path = config.path();
-        //FIXME: sync some where else
-        let wallet = wallet_manager.clone();
-        let _ = std::thread::spawn(move || wallet.sync().unwrap());
+        let webhooks = Arc::new(LampoWebhooks::new(&config));
+        let metrics = Arc::new(Metrics::new());
+        let plugins = Arc::new(LampoPlugins::new(&config));
+        let remote = config.remote_storage_url.as_ref().map(|url| {
+            LampoRemoteStore::new(
+                url,
+                config.remote_storage_token.clone(),
+                Duration::from_secs(config.remote_storage_max_lag_secs),
+            )
+        });
+        let persister = Arc::new(
+            LampoPersistence::new(root_path.into())
+                .with_backup_dir(config.monitor_backup_dir.as_ref().map(PathBuf::from))
+                .with_remote(remote),
+        );
+        let event_journal = Arc::new(LampoEventJournal::new(
+            persister.clone(),
+            config.event_queue_size,
+        ));
+        let towers = Arc::new(LampoTowerClient::new(
+            persister.clone(),
+            wallet_manager.ldk_keys(),
+        ));
         LampoDaemon {
+            loaded_conf: Mutex::new(config.clone()),
             conf: config,
             logger: Arc::new(LampoLogger {}),
-            persister: Arc::new(LampoPersistence::new(root_path.into())),
+            datastore: Arc::new(LampoDatastore::new(persister.clone())),
+            towers,
+            tower: None,
+            persister,
             peer_manager: None,
             onchain_manager: None,
             channel_manager: None,
             inventory_manager: None,
+            wallet_sync: Arc::new(LampoWalletSync::new(
+                wallet_manager.clone(),
+                metrics.clone(),
+            )),
             wallet_manager,
             offchain_manager: None,
+            bump_manager: None,
+            rapid_gossip: None,
+            graph_persister: None,
+            autofees: None,
             handler: None,
-            process: Cell::new(None),
+            rpc_auth: None,
+            event_bus: Arc::new(LampoEventBus::default()),
+            event_journal,
+            webhooks,
+            metrics,
+            plugins,
+            process: Mutex::new(None),
+            stopped: Arc::new(AtomicBool::new(false)),
+            stop_request: chan::bounded(1),
+            shutdown_step: Mutex::new(None),
             rt: Runtime::new().unwrap(),
         }
     }
@@ -105,9 +190,24 @@ impl LampoDaemon {
         &self.conf
     }
 
+    /// The configuration of the running node, with the options
+    /// applied by `reload`.
+    pub fn loaded_conf(&self) -> LampoConf {
+        self.loaded_conf.lock().unwrap().clone()
+    }
+
     pub fn init_onchaind(&mut self, client: Arc<dyn Backend>) -> error::Result<()> {
         log::debug!(target: "lampod", "init onchaind ..");
-        let onchain_manager = LampoChainManager::new(client, self.wallet_manager.clone());
+        let onchain_manager = LampoChainManager::new(
+            &self.conf,
+            client,
+            self.wallet_manager.clone(),
+            self.metrics.clone(),
+        );
+        // ldk asks the fees before the first refresh in background
+        if let Err(err) = onchain_manager.fees.refresh() {
+            log::warn!(target: "lampod", "impossible estimate the fees: {err}");
+        }
         self.onchain_manager = Some(Arc::new(onchain_manager));
         Ok(())
     }
@@ -124,6 +224,7 @@ impl LampoDaemon {
             self.onchain_manager(),
             self.wallet_manager.clone(),
             self.persister.clone(),
+            self.towers.clone(),
         );
         let (block_hash, height) = self.onchain_manager().backend.get_best_block()?;
         let block = self.onchain_manager().backend.get_block(&block_hash)?;
@@ -139,6 +240,8 @@ impl LampoDaemon {
         } else {
             manager.start(block_hash, Height::from_consensus(height)?, timestamp)?;
         }
+        // the announcements are validated inside the graph of the manager
+        self.onchain_manager().utxo.set_graph(manager.graph());
 
         self.channel_manager = Some(Arc::new(manager));
         Ok(())
@@ -148,6 +251,22 @@ impl LampoDaemon {
         self.channel_manager.clone().unwrap()
     }
 
+    pub fn init_graph_persister(&mut self) -> error::Result<()> {
+        log::debug!(target: "lampod", "init graph persister ...");
+        let graph_persister = LampoGraphPersister::new(
+            self.persister.clone(),
+            self.channel_manager().graph(),
+            self.channel_manager().scorer(),
+            self.channel_manager().graph_load(),
+        );
+        self.graph_persister = Some(Arc::new(graph_persister));
+        Ok(())
+    }
+
+    pub fn graph_persister(&self) -> Arc<LampoGraphPersister> {
+        self.graph_persister.clone().unwrap()
+    }
+
     pub fn offchain_manager(&self) -> Arc<OffchainManager> {
         self.offchain_manager.clone().unwrap()
     }
@@ -155,25 +274,101 @@ impl LampoDaemon {
     pub fn init_offchain_manager(&mut self) -> error::Result<()> {
         log::debug!(target: "lampod", "init offchain manager ...");
         let manager = OffchainManager::new(
-            self.wallet_manager().ldk_keys().keys_manager.clone(),
+            self.wallet_manager().ldk_keys().inner(),
             self.channel_manager(),
             self.logger.clone(),
             Arc::new(self.conf.clone()),
             self.onchain_manager(),
+            self.persister(),
         )?;
         self.offchain_manager = Some(Arc::new(manager));
         Ok(())
     }
 
+    pub fn init_bump_manager(&mut self) -> error::Result<()> {
+        log::debug!(target: "lampod", "init bump manager ...");
+        let manager = LampoBumpManager::new(
+            self.onchain_manager(),
+            self.wallet_manager.clone(),
+            self.logger.clone(),
+        );
+        self.bump_manager = Some(Arc::new(manager));
+        Ok(())
+    }
+
+    pub fn bump_manager(&self) -> Arc<LampoBumpManager> {
+        self.bump_manager.clone().unwrap()
+    }
+
+    /// Init the rapid gossip sync, if the user configured a server.
+    pub fn init_rapid_gossip(&mut self) -> error::Result<()> {
+        let Some(ref url) = self.conf.rgs_url else {
+            return Ok(());
+        };
+        log::debug!(target: "lampod", "init rapid gossip sync ...");
+        let rapid_gossip =
+            LampoRapidGossip::new(url, self.channel_manager().graph(), self.logger.clone());
+        self.rapid_gossip = Some(Arc::new(rapid_gossip));
+        Ok(())
+    }
+
+    /// Return the rapid gossip sync if it is enabled.
+    pub fn rapid_gossip(&self) -> Option<Arc<LampoRapidGossip>> {
+        self.rapid_gossip.clone()
+    }
+
+    /// Init the automatic fees of the channels, if the user enabled them.
+    pub fn init_autofees(&mut self) -> error::Result<()> {
+        let Some(autofees) = LampoAutofees::new(&self.conf, self.channel_manager()) else {
+            return Ok(());
+        };
+        log::debug!(target: "lampod", "init autofees ...");
+        self.autofees = Some(Arc::new(autofees));
+        Ok(())
+    }
+
+    /// Return the automatic fees of the channels if they are enabled.
+    pub fn autofees(&self) -> Option<Arc<LampoAutofees>> {
+        self.autofees.clone()
+    }
+
+    /// Init the tower served to the other nodes, if the user
+    /// configured it.
+    pub fn init_tower(&mut self) -> error::Result<()> {
+        if self.conf.tower_bind.is_none() {
+            return Ok(());
+        }
+        log::debug!(target: "lampod", "init watchtower ...");
+        let tower = LampoTowerServer::new(
+            &self.conf,
+            self.wallet_manager.ldk_keys(),
+            self.onchain_manager(),
+        )?;
+        self.tower = Some(Arc::new(tower));
+        Ok(())
+    }
+
+    /// Return the tower served to the other nodes if it is enabled.
+    pub fn tower(&self) -> Option<Arc<LampoTowerServer>> {
+        self.tower.clone()
+    }
+
     pub fn init_peer_manager(&mut self) -> error::Result<()> {
         log::debug!(target: "lampo", "init peer manager ...");
-        let mut peer_manager = LampoPeerManager::new(&self.conf, self.logger.clone());
+        let mut peer_manager = LampoPeerManager::new(
+            &self.conf,
+            self.logger.clone(),
+            self.persister.clone(),
+        );
         peer_manager.init(
             self.onchain_manager(),
             self.wallet_manager.clone(),
             self.channel_manager(),
         )?;
-        self.peer_manager = Some(Arc::new(peer_manager));
+        let peer_manager = Arc::new(peer_manager);
+        self.offchain_manager()
+            .set_peer_manager(peer_manager.clone(), self.rt.handle().clone());
+        self.peer_manager = Some(peer_manager);
         Ok(())
     }
 
@@ -199,6 +394,23 @@ impl LampoDaemon {
         self.wallet_manager.clone()
     }
 
+    pub fn wallet_sync(&self) -> Arc<LampoWalletSync> {
+        self.wallet_sync.clone()
+    }
+
+    pub fn datastore(&self) -> Arc<LampoDatastore> {
+        self.datastore.clone()
+    }
+
+    pub fn persister(&self) -> Arc<LampoPersistence> {
+        self.persister.clone()
+    }
+
+    /// The client of the watchtowers that back up the channels.
+    pub fn towers(&self) -> Arc<LampoTowerClient> {
+        self.towers.clone()
+    }
+
     pub fn init_event_handler(&mut self) -> error::Result<()> {
         log::debug!(target: "lampod", "init inventory manager ...");
         let handler = LampoHandler::new(self);
@@ -210,20 +422,85 @@ impl LampoDaemon {
         self.handler.clone().unwrap()
     }
 
+    pub fn init_rpc_auth(&mut self) -> error::Result<()> {
+        log::debug!(target: "lampod", "init rpc auth ...");
+        let entropy = self
+            .wallet_manager
+            .ldk_keys()
+            .inner()
+            .get_secure_random_bytes();
+        let auth = RpcAuth::load_or_create(&self.conf.path(), entropy)?;
+        self.rpc_auth = Some(Arc::new(auth));
+        Ok(())
+    }
+
+    /// The authenticator of the requests received by the JSON RPC 2.0 interface.
+    pub fn rpc_auth(&self) -> Arc<RpcAuth> {
+        self.rpc_auth.clone().unwrap()
+    }
+
+    pub fn event_bus(&self) -> Arc<LampoEventBus> {
+        self.event_bus.clone()
+    }
+
+    /// The journal of the ldk events not handled yet.
+    pub fn event_journal(&self) -> Arc<LampoEventJournal> {
+        self.event_journal.clone()
+    }
+
+    pub fn webhooks(&self) -> Arc<LampoWebhooks> {
+        self.webhooks.clone()
+    }
+
+    /// The registry of the metrics of the node.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// The plugins started with the node.
+    pub fn plugins(&self) -> Arc<LampoPlugins> {
+        self.plugins.clone()
+    }
+
     pub fn init_reactor(&mut self) -> error::Result<()> {
         Ok(())
     }
 
     pub fn init(&mut self, client: Arc<dyn Backend>) -> error::Result<()> {
         log::debug!(target: "lampod", "init lampod ...");
+        let magic = self.conf.network_magic();
+        log::info!(
+            target: "lampod",
+            "running on `{}` with the network magic `{}`",
+            self.conf.network,
+            magic.iter().map(|byte| format!("{byte:02x}")).collect::<String>()
+        );
+        // the channels must not go on from a state older than the remote one
+        self.persister
+            .sync_remote(self.conf.remote_storage_restore)?;
+        for tower in &self.conf.watchtowers {
+            self.towers.add_tower(tower)?;
+        }
         self.init_onchaind(client.clone())?;
         self.init_channeld()?;
+        self.init_graph_persister()?;
         self.init_offchain_manager()?;
+        self.init_bump_manager()?;
+        self.init_rapid_gossip()?;
+        self.init_autofees()?;
+        self.init_tower()?;
         self.init_peer_manager()?;
         self.init_inventory_manager()?;
         self.init_event_handler()?;
+        self.init_rpc_auth()?;
+        // the hooks of the plugins are called by the handler
+        self.plugins.start(self.event_bus(), self.stopped.clone())?;
         client.set_handler(self.handler());
         self.channel_manager().set_handler(self.handler());
+        self.peer_manager()
+            .onion_messages()
+            .set_handler(self.handler());
+        self.wallet_sync.set_handler(self.handler());
         Ok(())
     }
 
@@ -243,44 +520,295 @@ impl LampoDaemon {
 
     pub fn listen(self: Arc<Self>) -> error::Result<JoinHandle<std::io::Result<()>>> {
         log::info!(target: "lampod", "Starting lightning node version `{}`", env!("CARGO_PKG_VERSION"));
-        let gossip_sync = Arc::new(P2PGossipSync::new(
-            self.channel_manager().graph(),
-            None::<Arc<LampoChainManager>>,
-            self.logger.clone(),
-        ));
-
-        let handler = self.handler();
-        let event_handler = move |event: Event| {
-            log::info!(target: "lampo", "ldk event {:?}", event);
-            if let Err(err) = handler.handle(event) {
-                log::error!("{err}");
-            }
+        // the rpc and the peers do not wait for the network graph
+        let _ = self
+            .graph_persister()
+            .spawn_load(self.conf.network, self.logger.clone());
+        // fall back to the p2p gossip when the rapid gossip sync is not enabled
+        let gossip_sync: GossipSync<
+            Arc<P2PGossipSync<Arc<LampoGraph>, Arc<LampoChainManager>, Arc<LampoLogger>>>,
+            Arc<LampoRapidGossipSync>,
+            Arc<LampoGraph>,
+            Arc<LampoChainManager>,
+            Arc<LampoLogger>,
+        > = match self.rapid_gossip() {
+            Some(rapid_gossip) => GossipSync::Rapid(rapid_gossip.inner()),
+            None => GossipSync::P2P(Arc::new(P2PGossipSync::new(
+                self.channel_manager().graph(),
+                self.conf.gossip_utxo_lookup.then(|| self.onchain_manager()),
+                self.logger.clone(),
+            ))),
         };
 
+        // the events are handled outside the background processor, after
+        // they are journaled, so a crash does not lose them.
+        let _ = self
+            .event_journal
+            .clone()
+            .start(self.handler(), self.stopped.clone());
+        let event_journal = self.event_journal.clone();
+        let stopped = self.stopped.clone();
+        let event_handler = move |event: Event| event_journal.push(event, &stopped);
+
+        // subscribe before starting the services, so no event is missed
+        let _ = self.event_bus.clone().start(self.handler().events());
+        let _ = self
+            .webhooks
+            .clone()
+            .start(self.event_bus(), self.stopped.clone());
+
         log::info!(target: "lampo", "Stating onchaind");
         let _ = self.onchain_manager().backend.clone().listen();
+        let _ = self
+            .onchain_manager()
+            .fees
+            .clone()
+            .start(Duration::from_secs(self.conf.fee_refresh_interval_secs));
         log::info!(target: "lampo", "Starting peer manager");
-        let _ = self.peer_manager().run();
+        self.peer_manager().run()?;
+        log::info!(target: "lampo", "Starting wallet sync");
+        let backend = self.onchain_manager().backend.clone();
+        if backend.filter_blocks() {
+            let _ = self
+                .wallet_sync
+                .start_with_filters(backend, self.handler().events());
+        } else {
+            let events = (self.conf.event_driven_sync && backend.notify_new_blocks())
+                .then(|| self.handler().events());
+            let _ = self.wallet_sync.start(
+                events,
+                Duration::from_secs(self.conf.wallet_sync_interval_secs),
+            );
+        }
         log::info!(target: "lampo", "Starting channel manager");
         let _ = self.channel_manager().listen();
+        let channel_manager = self.channel_manager();
+        // the queued closes are retried when the peer connects, while
+        // the deadline of the ones with the peer still offline is polled.
+        self.every(Duration::from_secs(10), move || {
+            channel_manager.expire_pending_closes()
+        });
+        if self.conf.remote_storage_url.is_some() {
+            let persister = self.persister.clone();
+            self.every(Duration::from_secs(10), move || {
+                if let Err(err) = persister.flush_remote() {
+                    log::error!(target: "lampo", "{err}");
+                }
+            });
+        }
+        let _ = self.towers.clone().start(self.stopped.clone());
+        if let Some(tower) = self.tower() {
+            tower.start(self.handler().events(), self.stopped.clone())?;
+        }
+        let graph_persister = self.graph_persister();
+        self.every(GRAPH_PERSIST_INTERVAL, move || {
+            if let Err(err) = graph_persister.persist() {
+                log::warn!(target: "lampo", "impossible persist the network graph: {err}");
+            }
+        });
+        if let Some(rapid_gossip) = self.rapid_gossip() {
+            let interval = Duration::from_secs(self.conf.rgs_refresh_interval_secs);
+            let graph_persister = self.graph_persister();
+            let graph_load = self.channel_manager().graph_load();
+            let stopped = self.stopped.clone();
+            std::thread::spawn(move || loop {
+                // the snapshot is asked from the last sync, loaded with the graph
+                graph_load.wait_loaded();
+                match rapid_gossip.sync() {
+                    // the snapshot can change a large part of the graph
+                    Ok(_) => {
+                        if let Err(err) = graph_persister.persist() {
+                            log::warn!(target: "lampo", "impossible persist the graph: {err}");
+                        }
+                    }
+                    Err(err) => log::warn!(target: "rgs", "rapid gossip sync failed: {err}"),
+                }
+                std::thread::sleep(interval);
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+            });
+        }
+        let peer_manager = self.peer_manager();
+        self.every(Duration::from_secs(1), move || {
+            if let Err(err) = peer_manager.announce_node() {
+                log::warn!(target: "lampo", "impossible announce the node: {err}");
+            }
+        });
+        let peer_manager = self.peer_manager();
+        let handler = self.handler();
+        self.every(Duration::from_secs(1), move || {
+            peer_manager.notify_connections(&handler)
+        });
+        let peer_manager = self.peer_manager();
+        self.every(PING_INTERVAL, move || peer_manager.ping_peers());
+        if let Some(autofees) = self.autofees() {
+            self.every(autofees.interval(), move || autofees.evaluate());
+        }
+        if self.conf.auto_reconnect {
+            let peer_manager = self.peer_manager();
+            let rt = self.rt.handle().clone();
+            self.every(Duration::from_secs(1), move || {
+                rt.block_on(peer_manager.reconnect_peers())
+            });
+        }
 
         let background_processor = BackgroundProcessor::start(
             self.persister.clone(),
             event_handler,
             self.channel_manager().chain_monitor(),
             self.channel_manager().manager(),
-            GossipSync::p2p(gossip_sync),
+            gossip_sync,
             self.peer_manager().manager(),
             self.logger.clone(),
             Some(self.channel_manager().scorer()),
         );
 
+        *self.process.lock().unwrap() = Some(background_processor);
+
+        let stopped = self.stopped.clone();
         Ok(std::thread::spawn(move || {
-            let _ = background_processor.join();
+            while !stopped.load(Ordering::SeqCst) {
+                std::thread::sleep(Duration::from_secs(1));
+            }
             Ok(())
         }))
     }
 
+    /// Run `task` every `interval` in background, until the node is stopped.
+    fn every<F>(&self, interval: Duration, mut task: F)
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let stopped = self.stopped.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            if stopped.load(Ordering::SeqCst) {
+                break;
+            }
+            task();
+        });
+    }
+
+    /// Ask to stop the node, the one waiting in `wait_stop_request`
+    /// does the shutdown.
+    pub fn request_stop(&self) {
+        log::info!(target: "lampod", "stop requested");
+        let _ = self.stop_request.0.try_send(());
+    }
+
+    /// Block until the node is asked to stop, with the `stop` command
+    /// or with a signal.
+    pub fn wait_stop_request(&self) {
+        let _ = self.stop_request.1.recv();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    /// The background processor of ldk is running, it is started
+    /// by `listen` and stopped by the shutdown.
+    pub fn is_processing(&self) -> bool {
+        self.process.lock_or_recover().is_some()
+    }
+
+    fn shutdown_step(&self, step: &'static str) {
+        log::info!(target: "lampod", "shutdown: {step}");
+        *self.shutdown_step.lock().unwrap() = Some(step);
+    }
+
+    /// Stop the node in order, so the last state of the channels and
+    /// of the wallet is on disk, it fails when it takes more than
+    /// `timeout` with the step that is still pending.
+    ///
+    /// The peers are disconnected, the background tasks are stopped,
+    /// then the channel manager, the network graph, the scorer and the
+    /// wallet are persisted. The channel monitors are persisted at
+    /// every update, so nothing is pending for them.
+    pub fn shutdown(self: &Arc<Self>, timeout: Duration) -> error::Result<()> {
+        let (sender, receiver) = chan::bounded(1);
+        let daemon = self.clone();
+        std::thread::spawn(move || {
+            let _ = sender.send(daemon.stop_services());
+        });
+        match receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(_) => {
+                let step = self.shutdown_step.lock().unwrap().unwrap_or("starting");
+                error::bail!(
+                    "shutdown not completed in {}s, still {step}",
+                    timeout.as_secs()
+                );
+            }
+        }
+    }
+
+    fn stop_services(&self) -> error::Result<()> {
+        if self.stopped.swap(true, Ordering::SeqCst) {
+            error::bail!("the node is already stopped");
+        }
+        self.shutdown_step("stopping the plugins");
+        self.plugins.stop();
+        self.shutdown_step("disconnecting the peers");
+        self.peer_manager().stop();
+        self.shutdown_step("stopping the wallet sync");
+        self.wallet_sync.stop();
+        self.shutdown_step("persisting the channel manager");
+        self.channel_manager().stop();
+        // the background processor persists the channel manager, the
+        // graph and the scorer for the last time when it is stopped.
+        if let Some(process) = self.process.lock().unwrap().take() {
+            process.stop()?;
+        }
+        self.shutdown_step("persisting the network graph");
+        self.graph_persister().persist_now()?;
+        self.shutdown_step("flushing the wallet");
+        self.wallet_manager.flush()?;
+        *self.shutdown_step.lock().unwrap() = None;
+        log::info!(target: "lampod", "lampod stopped");
+        Ok(())
+    }
+
+    /// Read the configuration file again, and apply to the running node
+    /// the options changed that are inside `RELOADABLE_OPTIONS`, the
+    /// other changes are reported as needing a restart. The options
+    /// given by the environment and by the command line still win
+    /// over the file.
+    pub fn reload(&self) -> error::Result<response::Reload> {
+        let mut loaded = self.loaded_conf.lock().unwrap();
+        let conf = loaded.reloaded()?;
+        let (applied, restart_required): (Vec<_>, Vec<_>) = loaded
+            .changed_options(&conf)
+            .into_iter()
+            .partition(|option| RELOADABLE_OPTIONS.contains(option));
+        if applied.contains(&"log-level") || applied.contains(&"log-level-target") {
+            logger::set_levels(&conf.log_level, &conf.log_targets)?;
+        }
+        let fees = self.onchain_manager().fees.clone();
+        if applied.contains(&"min-fee-rate-sat-kw") || applied.contains(&"max-fee-rate-sat-kw") {
+            fees.set_limits(conf.min_fee_rate_sat_kw, conf.max_fee_rate_sat_kw);
+        }
+        if applied.contains(&"fee-estimates-url") {
+            fees.set_url(conf.fee_estimates_url.clone());
+        }
+        if applied.contains(&"esplora-url") {
+            self.wallet_manager.set_esplora_urls(conf.esplora_urls.clone())?;
+        }
+        if applied.contains(&"webhook") {
+            self.webhooks.clone().set_webhooks(conf.webhooks.clone());
+        }
+        loaded.apply_reloadable(&conf);
+        log::info!(target: "lampod", "configuration reloaded, applied {applied:?}");
+        if !restart_required.is_empty() {
+            log::warn!(target: "lampod", "the options {restart_required:?} are applied on restart");
+        }
+        Ok(response::Reload {
+            applied: applied.into_iter().map(str::to_owned).collect(),
+            restart_required: restart_required.into_iter().map(str::to_owned).collect(),
+        })
+    }
+
     /// Call any method supported by the lampod configuration. This includes
     /// a lot of handler code. This function serves as a broker pattern in some ways,
     /// but it may also function as a chain of responsibility pattern in certain cases.
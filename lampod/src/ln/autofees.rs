@@ -0,0 +1,140 @@
+//! Automatic fees of the channels.
+//!
+//! Every interval the channels are evaluated by the `AutofeesPolicy`
+//! with their balance and the amount forwarded out since the last
+//! evaluation, and the new fees are set with the same machinery of
+//! `setchannel`. The last evaluation of every channel is kept for the
+//! `autofees-status` command.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use lampo_common::autofees::{AutofeesPolicy, FeeDecision};
+use lampo_common::conf::LampoConf;
+use lampo_common::model::request::ChannelIdentifier;
+use lampo_common::model::response::{AutofeesChannel, AutofeesStatus};
+use lampo_common::model::Msat;
+use lampo_common::sync::MutexExt;
+use lampo_common::time::unix_now;
+use lampo_common::types::ChannelId;
+
+use crate::ln::LampoChannelManager;
+
+pub struct LampoAutofees {
+    policy: AutofeesPolicy,
+    interval: Duration,
+    channel_manager: Arc<LampoChannelManager>,
+    /// The amount forwarded out through every channel at the last
+    /// evaluation, the channel history has only the total.
+    forwarded: Mutex<HashMap<ChannelId, u64>>,
+    last: Mutex<HashMap<ChannelId, AutofeesChannel>>,
+}
+
+impl LampoAutofees {
+    /// The autofees of the node, `None` when `autofees` is not enabled.
+    pub fn new(conf: &LampoConf, channel_manager: Arc<LampoChannelManager>) -> Option<Self> {
+        let policy = AutofeesPolicy::new(conf)?;
+        Some(Self {
+            policy,
+            interval: Duration::from_secs(conf.autofees_interval_secs),
+            channel_manager,
+            forwarded: Mutex::new(HashMap::new()),
+            last: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Evaluate all the channels ready, and set the new fees.
+    pub fn evaluate(&self) {
+        let channels = self.channel_manager.manager().list_channels();
+        let now = unix_now();
+        let mut forwarded = self.forwarded.lock_or_recover();
+        let mut evaluated = HashMap::new();
+        for channel in channels {
+            let Some(config) = channel.config else {
+                continue;
+            };
+            let total = channel.outbound_capacity_msat + channel.inbound_capacity_msat;
+            if !channel.is_channel_ready || total == 0 {
+                continue;
+            }
+            let amount_forwarded_msat = self
+                .channel_manager
+                .history()
+                .get(&channel.channel_id)
+                .map_or(0, |record| record.forward_stats.amount_forwarded_msat);
+            let before = forwarded.insert(channel.channel_id, amount_forwarded_msat);
+            let forwarded_msat = amount_forwarded_msat.saturating_sub(before.unwrap_or(0));
+            let outbound_ratio = channel.outbound_capacity_msat as f64 / total as f64;
+            let current_ppm = config.forwarding_fee_proportional_millionths;
+            let mut entry = AutofeesChannel {
+                channel_id: channel.channel_id.to_string(),
+                short_channel_id: channel.short_channel_id,
+                outbound_ratio,
+                forwarded_msat: Msat::from_msat(forwarded_msat),
+                current_ppm,
+                target_ppm: current_ppm,
+                new_ppm: None,
+                reason: "disabled".to_owned(),
+                evaluated_at: now,
+            };
+            // the fee of a disabled channel is the one that keeps it disabled
+            if !self.channel_manager.is_channel_enabled(&channel.channel_id) {
+                evaluated.insert(channel.channel_id, entry);
+                continue;
+            }
+            let FeeDecision {
+                target_ppm,
+                new_ppm,
+                reason,
+            } = self
+                .policy
+                .decide(current_ppm, outbound_ratio, forwarded_msat);
+            entry.target_ppm = target_ppm;
+            entry.reason = reason.to_string();
+            if let Some(new_ppm) = new_ppm {
+                let id = ChannelIdentifier::ChannelId(channel.channel_id);
+                match self.channel_manager.set_channel_fee_ppm(&id, new_ppm) {
+                    Ok(_) => {
+                        log::info!(target: "lampo", "autofees: fee of channel `{}` from {current_ppm}ppm to {new_ppm}ppm, target {target_ppm}ppm ({reason})", channel.channel_id);
+                        entry.new_ppm = Some(new_ppm);
+                    }
+                    Err(err) => {
+                        log::warn!(target: "lampo", "autofees: impossible set the fee of channel `{}`: {err}", channel.channel_id);
+                        entry.reason = format!("error: {err}");
+                    }
+                }
+            } else {
+                log::debug!(target: "lampo", "autofees: fee of channel `{}` kept at {current_ppm}ppm, target {target_ppm}ppm ({reason})", channel.channel_id);
+            }
+            evaluated.insert(channel.channel_id, entry);
+        }
+        // the closed channels are forgotten
+        forwarded.retain(|channel_id, _| evaluated.contains_key(channel_id));
+        drop(forwarded);
+        *self.last.lock_or_recover() = evaluated;
+    }
+
+    /// The last evaluation of every channel.
+    pub fn status(&self) -> AutofeesStatus {
+        let mut channels = self
+            .last
+            .lock_or_recover()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        channels.sort_by(|a, b| a.channel_id.cmp(&b.channel_id));
+        AutofeesStatus {
+            enabled: true,
+            interval_secs: self.interval.as_secs(),
+            min_ppm: self.policy.min_ppm,
+            max_ppm: self.policy.max_ppm,
+            max_change_ppm: self.policy.max_change_ppm,
+            hysteresis_ppm: self.policy.hysteresis_ppm,
+            channels,
+        }
+    }
+}
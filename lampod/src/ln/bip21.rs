@@ -0,0 +1,48 @@
+//! BIP21 `bitcoin:` URIs to receive a payment.
+//!
+//! When the URI carries a bolt11 invoice in the `lightning` parameter
+//! (BIP21 unified), a wallet that speaks lightning pays the invoice
+//! and the others pay the address.
+use super::lnurl::percent_encode;
+
+/// Seconds before the invoice inside the URI expires.
+pub const INVOICE_EXPIRY_SECS: u32 = 3600;
+
+const SAT_PER_BTC: u64 = 100_000_000;
+
+/// The URI to pay `amount_sat` to `address`, with the `label`
+/// and the `invoice` if any.
+pub fn uri(
+    address: &str,
+    amount_sat: Option<u64>,
+    label: Option<&str>,
+    invoice: Option<&str>,
+) -> String {
+    let mut params = Vec::new();
+    if let Some(amount_sat) = amount_sat {
+        params.push(format!("amount={}", btc_amount(amount_sat)));
+    }
+    if let Some(label) = label {
+        params.push(format!("label={}", percent_encode(label)));
+    }
+    if let Some(invoice) = invoice {
+        params.push(format!("lightning={invoice}"));
+    }
+    if params.is_empty() {
+        return format!("bitcoin:{address}");
+    }
+    format!("bitcoin:{address}?{}", params.join("&"))
+}
+
+/// The amount in BTC, the unit of BIP21, without the trailing zeros.
+fn btc_amount(amount_sat: u64) -> String {
+    let amount = format!(
+        "{}.{:08}",
+        amount_sat / SAT_PER_BTC,
+        amount_sat % SAT_PER_BTC
+    );
+    amount
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_owned()
+}
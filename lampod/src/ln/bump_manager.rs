@@ -0,0 +1,121 @@
+//! Fee bump manager implementation.
+//!
+//! When a channel with anchor outputs is force closed, ldk
+//! asks us to bump the fee of the commitment transaction (with
+//! a CPFP on the anchor) and of the HTLC resolution transactions.
+//!
+//! ldk keeps generating a new `BumpTransactionEvent` with an
+//! escalating fee rate until the package confirms, so the manager
+//! only need to fund and sign the transactions with the wallet
+//! UTXOs, and keep track of the in-flight packages.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use lampo_common::keys::LampoKeysManager;
+use lampo_common::ldk::chain::ClaimId;
+use lampo_common::ldk::events::bump_transaction::{
+    BumpTransactionEvent, BumpTransactionEventHandler, Wallet,
+};
+use lampo_common::model::response::{BumpPackage, BumpPackages};
+use lampo_common::sync::MutexExt;
+use lampo_common::types::ChannelId;
+use lampo_common::wallet::WalletManager;
+
+use crate::chain::{LampoChainManager, LampoWalletSource};
+use crate::utils::logger::LampoLogger;
+
+pub type LampoBumpHandler = BumpTransactionEventHandler<
+    Arc<LampoChainManager>,
+    Arc<Wallet<Arc<LampoWalletSource>, Arc<LampoLogger>>>,
+    Arc<LampoKeysManager>,
+    Arc<LampoLogger>,
+>;
+
+pub struct LampoBumpManager {
+    inner: LampoBumpHandler,
+    packages: Mutex<HashMap<ClaimId, BumpPackage>>,
+}
+
+impl LampoBumpManager {
+    pub fn new(
+        chain_manager: Arc<LampoChainManager>,
+        wallet_manager: Arc<dyn WalletManager>,
+        logger: Arc<LampoLogger>,
+    ) -> Self {
+        let keys = wallet_manager.ldk_keys().inner();
+        let source = Arc::new(LampoWalletSource::new(wallet_manager));
+        let wallet = Arc::new(Wallet::new(source, logger.clone()));
+        Self {
+            inner: BumpTransactionEventHandler::new(chain_manager, wallet, keys, logger),
+            packages: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Fund, sign and broadcast the transactions requested by ldk.
+    pub fn handle(&self, event: &BumpTransactionEvent) {
+        let (claim_id, channel_id, node_id, kind, feerate) = match event {
+            BumpTransactionEvent::ChannelClose {
+                claim_id,
+                channel_id,
+                counterparty_node_id,
+                package_target_feerate_sat_per_1000_weight,
+                ..
+            } => (
+                claim_id,
+                channel_id,
+                counterparty_node_id,
+                "anchor",
+                *package_target_feerate_sat_per_1000_weight,
+            ),
+            BumpTransactionEvent::HTLCResolution {
+                claim_id,
+                channel_id,
+                counterparty_node_id,
+                target_feerate_sat_per_1000_weight,
+                ..
+            } => (
+                claim_id,
+                channel_id,
+                counterparty_node_id,
+                "htlc",
+                *target_feerate_sat_per_1000_weight,
+            ),
+        };
+        log::info!(target: "bump", "bumping `{kind}` package for channel `{channel_id}` at `{feerate}` sat/kw");
+        {
+            let mut packages = self.packages.lock_or_recover();
+            let package = packages.entry(*claim_id).or_insert_with(|| BumpPackage {
+                claim_id: claim_id
+                    .0
+                    .iter()
+                    .map(|byte| format!("{byte:02x}"))
+                    .collect(),
+                channel_id: channel_id.to_string(),
+                counterparty_node_id: node_id.to_string(),
+                kind: kind.to_owned(),
+                target_feerate_sat_per_1000_weight: feerate,
+                attempts: 0,
+            });
+            package.target_feerate_sat_per_1000_weight = feerate;
+            package.attempts += 1;
+        }
+        self.inner.handle_event(event);
+    }
+
+    /// Forget all the packages of a channel, this is called when
+    /// the channel outputs are spendable again by us.
+    pub fn resolve(&self, channel_id: &ChannelId) {
+        let channel_id = channel_id.to_string();
+        self.packages
+            .lock_or_recover()
+            .retain(|_, package| package.channel_id != channel_id);
+    }
+
+    /// Return the packages that are still in flight.
+    pub fn packages(&self) -> BumpPackages {
+        let packages = self.packages.lock_or_recover();
+        BumpPackages {
+            packages: packages.values().cloned().collect(),
+        }
+    }
+}
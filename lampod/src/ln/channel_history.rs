@@ -0,0 +1,116 @@
+//! Channel history implementation.
+//!
+//! ldk forgets about a channel as soon as it is closed, so
+//! lampo keeps a small history for each channel with the last
+//! state changes and the forward statistics, this is what
+//! allow us to tell the user that a channel was closed instead
+//! of that it never existed.
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use lampo_common::bitcoin::OutPoint;
+use lampo_common::model::response::{ChannelStateChange, ForwardStats};
+use lampo_common::sync::MutexExt;
+use lampo_common::time::unix_now;
+use lampo_common::types::ChannelId;
+
+/// Number of state changes that we keep for each channel.
+const MAX_STATE_CHANGES: usize = 10;
+
+#[derive(Clone, Debug, Default)]
+pub struct ChannelRecord {
+    pub short_channel_id: Option<u64>,
+    pub funding_txo: Option<OutPoint>,
+    pub forward_stats: ForwardStats,
+    pub events: VecDeque<ChannelStateChange>,
+    /// The reason of the close, if the channel is closed.
+    pub closed: Option<String>,
+}
+
+#[derive(Default)]
+pub struct LampoChannelHistory {
+    records: Mutex<HashMap<ChannelId, ChannelRecord>>,
+}
+
+impl LampoChannelHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a new state of the channel.
+    pub fn record_state(&self, channel_id: &ChannelId, state: &str) {
+        let timestamp = unix_now();
+        let mut records = self.records.lock_or_recover();
+        let record = records.entry(*channel_id).or_default();
+        if record.events.len() == MAX_STATE_CHANGES {
+            record.events.pop_front();
+        }
+        record.events.push_back(ChannelStateChange {
+            state: state.to_owned(),
+            timestamp,
+        });
+    }
+
+    /// Remember how the channel can be found after it is closed.
+    pub fn record_ids(
+        &self,
+        channel_id: &ChannelId,
+        short_channel_id: Option<u64>,
+        funding_txo: Option<OutPoint>,
+    ) {
+        let mut records = self.records.lock_or_recover();
+        let record = records.entry(*channel_id).or_default();
+        record.short_channel_id = short_channel_id.or(record.short_channel_id);
+        record.funding_txo = funding_txo.or(record.funding_txo);
+    }
+
+    pub fn record_closed(
+        &self,
+        channel_id: &ChannelId,
+        funding_txo: Option<OutPoint>,
+        reason: &str,
+    ) {
+        self.record_ids(channel_id, None, funding_txo);
+        self.record_state(channel_id, "closed");
+        let mut records = self.records.lock_or_recover();
+        // SAFETY: the record is created by `record_ids`.
+        let record = records.get_mut(channel_id).unwrap();
+        record.closed = Some(reason.to_owned());
+    }
+
+    /// Record a payment forwarded from `prev_channel_id` to `next_channel_id`.
+    pub fn record_forward(
+        &self,
+        prev_channel_id: Option<ChannelId>,
+        next_channel_id: Option<ChannelId>,
+        amount_forwarded_msat: u64,
+        fee_earned_msat: u64,
+    ) {
+        let mut records = self.records.lock_or_recover();
+        if let Some(channel_id) = prev_channel_id {
+            records.entry(channel_id).or_default().forward_stats.forwards += 1;
+        }
+        if let Some(channel_id) = next_channel_id {
+            let stats = &mut records.entry(channel_id).or_default().forward_stats;
+            stats.forwards += 1;
+            stats.amount_forwarded_msat += amount_forwarded_msat;
+            stats.fee_earned_msat += fee_earned_msat;
+        }
+    }
+
+    pub fn get(&self, channel_id: &ChannelId) -> Option<ChannelRecord> {
+        self.records.lock_or_recover().get(channel_id).cloned()
+    }
+
+    /// Find a closed channel by short channel id or by funding outpoint.
+    pub fn find_closed<F>(&self, filter: F) -> Option<(ChannelId, ChannelRecord)>
+    where
+        F: Fn(&ChannelRecord) -> bool,
+    {
+        self.records
+            .lock_or_recover()
+            .iter()
+            .find(|(_, record)| record.closed.is_some() && filter(record))
+            .map(|(channel_id, record)| (*channel_id, record.clone()))
+    }
+}
@@ -1,42 +1,74 @@
 //! Channel Manager Implementation
-use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::BufReader;
+use std::io;
 use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 use lampo_common::bitcoin::absolute::Height;
-use lampo_common::bitcoin::{BlockHash, Transaction};
+use lampo_common::bitcoin::block::Header;
+use lampo_common::bitcoin::hashes::Hash;
+use lampo_common::bitcoin::{Address, BlockHash, ScriptBuf, Transaction, WScriptHash};
 use lampo_common::conf::LampoConf;
 use lampo_common::error;
+use lampo_common::error::LampoError;
+use lampo_common::event::ln::LightningEvent;
 use lampo_common::event::onchain::OnChainEvent;
 use lampo_common::event::Event;
 use lampo_common::handler::Handler;
+use lampo_common::json;
 use lampo_common::keys::LampoKeysManager;
 use lampo_common::ldk::chain::chainmonitor::ChainMonitor;
 use lampo_common::ldk::chain::channelmonitor::ChannelMonitor;
 use lampo_common::ldk::chain::{BestBlock, Confirm, Filter, Watch};
 use lampo_common::ldk::ln::channelmanager::{
-    ChainParameters, ChannelManager, ChannelManagerReadArgs,
+    ChainParameters, ChannelDetails, ChannelManager, ChannelManagerReadArgs,
 };
-use lampo_common::ldk::persister::fs_store::FilesystemStore;
 use lampo_common::ldk::routing::gossip::NetworkGraph;
+use lampo_common::ldk::ln::script::ShutdownScript;
 use lampo_common::ldk::routing::router::DefaultRouter;
-use lampo_common::ldk::routing::scoring::{
-    ProbabilisticScorer, ProbabilisticScoringDecayParameters, ProbabilisticScoringFeeParameters,
-};
+use lampo_common::ldk::routing::scoring::{ProbabilisticScorer, ProbabilisticScoringFeeParameters};
 use lampo_common::ldk::sign::InMemorySigner;
-use lampo_common::ldk::util::persist::read_channel_monitors;
+use lampo_common::ldk::util::persist::{read_channel_monitors, KVStore};
 use lampo_common::ldk::util::ser::ReadableArgs;
-use lampo_common::model::request;
-use lampo_common::model::response::{self, Channel, Channels};
+use lampo_common::model::request::{self, ChannelIdentifier};
+use lampo_common::model::response::{self, Channel, ChannelFunds, ChannelInfo, Channels};
+use lampo_common::model::response::{Htlc, HtlcDirection, Htlcs, PendingClose};
+use lampo_common::model::{Msat, Sat};
+use lampo_common::sync::{MutexExt, RwLockExt};
+use lampo_common::time::unix_now;
+use lampo_common::types::{ChannelId, ChannelState, NodeId};
+use lampo_common::wallet;
 
 use crate::actions::handler::LampoHandler;
-use crate::chain::{LampoChainManager, WalletManager};
+use crate::chain::{LampoChainManager, TipUpdate, WalletManager};
+use crate::ln::channel_history::LampoChannelHistory;
+use crate::ln::close_queue::LampoCloseQueue;
+use crate::ln::graph_persister::{GraphLoad, LampoGraphPersister};
+use crate::ln::htlc_tracker::{HtlcKey, LampoHtlcTracker};
+use crate::ln::sweeper::{load_sweeper, LampoSweeper};
 use crate::ln::events::{ChangeStateChannelEvent, ChannelEvents};
-use crate::persistence::LampoPersistence;
+use crate::persistence::{LampoMonitorPersister, LampoPersistence};
 use crate::utils::logger::LampoLogger;
+use crate::watchtower::LampoTowerClient;
+
+const LAMPO_NAMESPACE: &str = "lampo";
+const DISABLED_CHANNELS_KEY: &str = "disabled_channels";
+/// How long a peer back online has to reestablish its channels before
+/// the queued closes wait for the next connection.
+const REESTABLISH_TIMEOUT: Duration = Duration::from_secs(30);
+const REESTABLISH_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Forwarding fees of a channel before it was disabled.
+#[derive(Clone, Copy, Debug)]
+struct ForwardingFees {
+    base_msat: u32,
+    proportional_millionths: u32,
+}
 
 pub type LampoChainMonitor = ChainMonitor<
     InMemorySigner,
@@ -44,7 +76,7 @@ pub type LampoChainMonitor = ChainMonitor<
     Arc<LampoChainManager>,
     Arc<LampoChainManager>,
     Arc<LampoLogger>,
-    Arc<FilesystemStore>,
+    Arc<LampoMonitorPersister>,
 >;
 
 pub type LampoArcChannelManager<M, T, F, L> = ChannelManager<
@@ -74,12 +106,31 @@ pub type LampoRouter = DefaultRouter<
 
 pub struct LampoChannelManager {
     monitor: Option<Arc<LampoChainMonitor>>,
+    /// Spends the outputs of the closed channels until they confirm.
+    sweeper: Option<Arc<LampoSweeper>>,
     wallet_manager: Arc<dyn WalletManager>,
     persister: Arc<LampoPersistence>,
+    /// The towers told about the updates of the channels.
+    towers: Arc<LampoTowerClient>,
     graph: Option<Arc<LampoGraph>>,
     score: Option<Arc<Mutex<LampoScorer>>>,
-    handler: RefCell<Option<Arc<LampoHandler>>>,
+    /// The graph starts empty, and the one on the disk is loaded in
+    /// background.
+    graph_load: Arc<GraphLoad>,
+    handler: RwLock<Option<Arc<LampoHandler>>>,
     router: Option<Arc<LampoRouter>>,
+    history: LampoChannelHistory,
+    /// Channels where the forwarding is disabled by the user,
+    /// with the fees to restore when they are enabled again.
+    disabled_channels: Mutex<HashMap<ChannelId, ForwardingFees>>,
+    /// Cooperative closes waiting that the peer come back online.
+    close_queue: LampoCloseQueue,
+    htlcs: LampoHtlcTracker,
+    /// Peers whose payments are left pending instead of claimed,
+    /// set by `dev-ignore-htlcs`.
+    ignored_htlc_peers: Mutex<HashSet<NodeId>>,
+    /// Stop applying the chain events, set when the node is stopped.
+    stopped: AtomicBool,
 
     pub(crate) onchain: Arc<LampoChainManager>,
     pub(crate) conf: LampoConf,
@@ -87,11 +138,6 @@ pub struct LampoChannelManager {
     pub(crate) logger: Arc<LampoLogger>,
 }
 
-// SAFETY: due the init workflow of the lampod, we should
-// store the handler later and not use the new contructor.
-//
-// Due the constructor is called only one time as the sethandler
-// it is safe use the ref cell across thread.
 unsafe impl Send for LampoChannelManager {}
 unsafe impl Sync for LampoChannelManager {}
 
@@ -102,28 +148,42 @@ impl LampoChannelManager {
         onchain: Arc<LampoChainManager>,
         wallet_manager: Arc<dyn WalletManager>,
         persister: Arc<LampoPersistence>,
+        towers: Arc<LampoTowerClient>,
     ) -> Self {
+        let disabled_channels = Self::read_disabled_channels(&persister).unwrap_or_else(|err| {
+            log::warn!(target: "lampo", "impossible read the disabled channels: {err}");
+            HashMap::new()
+        });
         LampoChannelManager {
             conf: conf.to_owned(),
             monitor: None,
+            sweeper: None,
             onchain,
             channeld: None,
             wallet_manager,
             logger,
+            close_queue: LampoCloseQueue::new(persister.clone()),
             persister,
-            handler: RefCell::new(None),
+            towers,
+            handler: RwLock::new(None),
             graph: None,
             score: None,
+            graph_load: Arc::new(GraphLoad::default()),
             router: None,
+            history: LampoChannelHistory::new(),
+            htlcs: LampoHtlcTracker::new(),
+            ignored_htlc_peers: Mutex::new(HashSet::new()),
+            disabled_channels: Mutex::new(disabled_channels),
+            stopped: AtomicBool::new(false),
         }
     }
 
     pub fn set_handler(&self, handler: Arc<LampoHandler>) {
-        self.handler.replace(Some(handler));
+        *self.handler.write_or_recover() = Some(handler);
     }
 
     pub fn handler(&self) -> Arc<LampoHandler> {
-        self.handler.borrow().clone().unwrap()
+        self.handler.read_or_recover().clone().unwrap()
     }
 
     pub fn listen(self: Arc<Self>) -> JoinHandle<()> {
@@ -135,17 +195,49 @@ impl LampoChannelManager {
             log::info!(target: "manager", "listening on chain event on the channel manager");
             let events = self.handler().events();
             loop {
-                let Ok(Event::OnChain(event)) = events.recv() else {
-                    continue;
+                let event = match events.recv() {
+                    Ok(Event::OnChain(event)) => event,
+                    Ok(Event::Lightning(LightningEvent::PeerConnect {
+                        counterparty_node_id,
+                    })) => {
+                        self.retry_pending_closes_of(&counterparty_node_id);
+                        continue;
+                    }
+                    _ => continue,
                 };
+                // the event can arrive while the node is stopping
+                if self.stopped.load(Ordering::SeqCst) {
+                    break;
+                }
                 log::trace!(target: "channel_manager", "event received {:?}", event);
                 match event {
                     OnChainEvent::NewBestBlock((hash, height)) => {
                         log::info!(target: "channel_manager", "new best block with hash `{}` at height `{height}`", hash.block_hash());
-                        self.chain_monitor()
-                            .best_block_updated(&hash, height.to_consensus_u32());
-                        self.manager()
-                            .best_block_updated(&hash, height.to_consensus_u32());
+                        let height = height.to_consensus_u32();
+                        let from = match self.onchain.chain.update_tip(&hash, height) {
+                            Ok(TipUpdate::Connected { from }) => Some(from),
+                            Ok(TipUpdate::Known) => None,
+                            Ok(TipUpdate::Reorg {
+                                fork,
+                                fork_height,
+                                disconnected,
+                            }) => {
+                                self.disconnect_blocks(&fork, fork_height, &disconnected);
+                                Some(fork_height + 1)
+                            }
+                            Err(err) => {
+                                log::error!(target: "channel_manager", "impossible track the new best block: {err}");
+                                None
+                            }
+                        };
+                        if let Some(from) = from {
+                            self.confirm_reorged(from, height);
+                        }
+                        self.chain_monitor().best_block_updated(&hash, height);
+                        self.manager().best_block_updated(&hash, height);
+                        self.sweeper().best_block_updated(&hash, height);
+                        self.track_funding();
+                        self.onchain.broadcaster.rebroadcast();
                     }
                     OnChainEvent::ConfirmedTransaction((tx, idx, header, height)) => {
                         log::info!(target: "channel_manager", "confirmed transaction with txid `{}` at height `{height}`", tx.txid());
@@ -159,11 +251,18 @@ impl LampoChannelManager {
                             &[(idx as usize, &tx)],
                             height.to_consensus_u32(),
                         );
+                        self.sweeper().transactions_confirmed(
+                            &header,
+                            &[(idx as usize, &tx)],
+                            height.to_consensus_u32(),
+                        );
+                        self.track_funding();
                     }
                     OnChainEvent::UnconfirmedTransaction(txid) => {
                         log::info!(target: "channel_manager", "transaction with txid `{txid}` is still unconfirmed");
                         self.chain_monitor().transaction_unconfirmed(&txid);
                         self.manager().transaction_unconfirmed(&txid);
+                        self.sweeper().transaction_unconfirmed(&txid);
                     }
                     OnChainEvent::DiscardedTransaction(txid) => {
                         log::warn!(target: "channel_manager", "transaction with txid `{txid}` discarded");
@@ -171,16 +270,117 @@ impl LampoChannelManager {
                     _ => continue,
                 }
             }
+            log::info!(target: "channel_manager", "stop listening on chain event");
         })
     }
 
+    /// Stop applying the chain events, so the monitors are not
+    /// updated anymore after the last persistence.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    /// Tell ldk that the blocks `disconnected` left the best chain, so
+    /// the transactions confirmed inside them are unconfirmed until
+    /// they are mined again.
+    fn disconnect_blocks(&self, fork: &Header, fork_height: u32, disconnected: &[BlockHash]) {
+        log::warn!(
+            target: "channel_manager",
+            "reorg of {} blocks, the chain forks at height `{fork_height}`",
+            disconnected.len()
+        );
+        let mut txids = self.chain_monitor().get_relevant_txids();
+        txids.extend(self.manager().get_relevant_txids());
+        txids.extend(self.sweeper().get_relevant_txids());
+        txids.sort();
+        txids.dedup();
+        for (txid, _, block_hash) in txids {
+            if !block_hash.is_some_and(|block_hash| disconnected.contains(&block_hash)) {
+                continue;
+            }
+            log::info!(target: "channel_manager", "transaction with txid `{txid}` unconfirmed by the reorg");
+            self.chain_monitor().transaction_unconfirmed(&txid);
+            self.manager().transaction_unconfirmed(&txid);
+            self.sweeper().transaction_unconfirmed(&txid);
+            self.onchain.chain.watch_reorged(txid);
+        }
+        self.chain_monitor().best_block_updated(fork, fork_height);
+        self.manager().best_block_updated(fork, fork_height);
+        self.sweeper().best_block_updated(fork, fork_height);
+    }
+
+    /// Report the new confirmations of the funding of the pending
+    /// channels, the pending channels that are not watched (e.g. after
+    /// a restart) are watched from now.
+    fn track_funding(&self) {
+        let funding = &self.onchain.funding;
+        let pending = self
+            .manager()
+            .list_channels()
+            .into_iter()
+            .filter(|channel| !channel.is_channel_ready)
+            .collect::<Vec<_>>();
+        funding.retain(&pending.iter().map(|channel| channel.channel_id).collect::<Vec<_>>());
+        for channel in pending {
+            let Some(funding_txo) = channel.funding_txo else {
+                continue;
+            };
+            funding.watch(channel.channel_id, funding_txo.into_bitcoin_outpoint());
+            let (Some(confirmations), Some(required)) =
+                (channel.confirmations, channel.confirmations_required)
+            else {
+                continue;
+            };
+            let Some(progress) = funding.confirmed(&channel.channel_id, confirmations, required)
+            else {
+                continue;
+            };
+            log::info!(
+                target: "channel_manager",
+                "funding of the channel `{}` has {confirmations}/{required} confirmations",
+                progress.channel_id
+            );
+            self.handler().emit(Event::Lightning(LightningEvent::ChannelConfirmation {
+                channel_id: progress.channel_id,
+                funding_transaction: progress.funding_txo,
+                confirmations: progress.confirmations,
+                required: progress.required,
+            }));
+        }
+    }
+
+    /// Confirm the transactions unconfirmed by a reorg that are mined
+    /// again in the blocks from the height `from` to `to`.
+    fn confirm_reorged(&self, from: u32, to: u32) {
+        let confirmed = match self.onchain.chain.find_reorged(from, to) {
+            Ok(confirmed) => confirmed,
+            Err(err) => {
+                log::error!(target: "channel_manager", "impossible look for the reorged transactions: {err}");
+                return;
+            }
+        };
+        for (header, height, idx, tx) in confirmed {
+            log::info!(target: "channel_manager", "transaction with txid `{}` confirmed again at height `{height}`", tx.txid());
+            self.chain_monitor()
+                .transactions_confirmed(&header, &[(idx, &tx)], height);
+            self.manager()
+                .transactions_confirmed(&header, &[(idx, &tx)], height);
+            self.sweeper()
+                .transactions_confirmed(&header, &[(idx, &tx)], height);
+        }
+    }
+
     fn build_channel_monitor(&self) -> LampoChainMonitor {
         ChainMonitor::new(
             Some(self.onchain.clone()),
             self.onchain.clone(),
             self.logger.clone(),
             self.onchain.clone(),
-            self.persister.clone(),
+            Arc::new(LampoMonitorPersister::new(
+                self.persister.clone(),
+                self.towers.clone(),
+                self.onchain.clone(),
+            )),
         )
     }
 
@@ -192,25 +392,667 @@ impl LampoChannelManager {
         self.channeld.clone().unwrap()
     }
 
+    pub fn sweeper(&self) -> Arc<LampoSweeper> {
+        self.sweeper.clone().unwrap()
+    }
+
+    /// The channel manager and the chain monitor are loaded.
+    pub fn is_loaded(&self) -> bool {
+        self.channeld.is_some() && self.monitor.is_some()
+    }
+
     pub fn list_channel(&self) -> Channels {
         let channels: Vec<Channel> = self
+            .manager()
+            .list_channels()
+            .iter()
+            .map(|channel| self.to_channel(channel))
+            .collect();
+        Channels { channels }
+    }
+
+    /// The funds of the node inside each channel.
+    pub fn list_channel_funds(&self) -> error::Result<Vec<ChannelFunds>> {
+        self.manager()
+            .list_channels()
+            .iter()
+            .map(|channel| {
+                let reserve =
+                    Sat::from_sat(channel.unspendable_punishment_reserve.unwrap_or_default());
+                Ok(ChannelFunds {
+                    channel_id: channel.channel_id.to_string(),
+                    short_channel_id: channel.short_channel_id,
+                    peer_id: channel.counterparty.node_id.to_string(),
+                    our_amount_msat: Msat::from_msat(channel.outbound_capacity_msat)
+                        .checked_add(reserve.to_msat()?)?,
+                    amount_msat: Sat::from_sat(channel.channel_value_satoshis).to_msat()?,
+                    state: match (channel.is_channel_ready, channel.is_usable) {
+                        (false, _) => "pending",
+                        (true, true) => "active",
+                        (true, false) => "inactive",
+                    }
+                    .to_owned(),
+                })
+            })
+            .collect()
+    }
+
+    fn to_channel(&self, channel: &ChannelDetails) -> Channel {
+        Channel {
+            channel_id: channel.channel_id.to_string(),
+            short_channel_id: channel.short_channel_id,
+            inbound_scid_alias: channel.inbound_scid_alias,
+            outbound_scid_alias: channel.outbound_scid_alias,
+            peer_id: channel.counterparty.node_id.to_string(),
+            peer_alias: None,
+            ready: channel.is_channel_ready,
+            confirmations: channel.confirmations,
+            confirmations_required: channel.confirmations_required,
+            usable: channel.is_usable,
+            amount_satoshis: channel.channel_value_satoshis,
+            amount_msat: channel.next_outbound_htlc_limit_msat,
+            public: channel.is_public,
+            available_balance_for_send_msat: channel.outbound_capacity_msat,
+            available_balance_for_recv_msat: channel.inbound_capacity_msat,
+            enabled: self.is_channel_enabled(&channel.channel_id),
+            pending_close: self.close_queue.get(&channel.channel_id.to_string()),
+        }
+    }
+
+    pub fn history(&self) -> &LampoChannelHistory {
+        &self.history
+    }
+
+    /// Look for an open channel by short channel id, scid alias,
+    /// channel id or funding outpoint.
+    fn find_channel(&self, id: &ChannelIdentifier) -> Result<ChannelDetails, LampoError> {
+        let channel = self
             .manager()
             .list_channels()
             .into_iter()
-            .map(|channel| Channel {
-                channel_id: channel.channel_id.to_string(),
-                short_channel_id: channel.short_channel_id,
-                peer_id: channel.counterparty.node_id.to_string(),
-                peer_alias: None,
-                ready: channel.is_channel_ready,
-                amount_satoshis: channel.channel_value_satoshis,
-                amount_msat: channel.next_outbound_htlc_limit_msat,
-                public: channel.is_public,
-                available_balance_for_send_msat: channel.outbound_capacity_msat,
-                available_balance_for_recv_msat: channel.inbound_capacity_msat,
+            .find(|channel| match id {
+                ChannelIdentifier::ShortChannelId(scid) => {
+                    channel.short_channel_id == Some(*scid)
+                        || channel.inbound_scid_alias == Some(*scid)
+                        || channel.outbound_scid_alias == Some(*scid)
+                }
+                ChannelIdentifier::ChannelId(channel_id) => channel.channel_id == *channel_id,
+                ChannelIdentifier::FundingOutpoint(outpoint) => channel
+                    .funding_txo
+                    .map(|txo| txo.into_bitcoin_outpoint() == *outpoint)
+                    .unwrap_or(false),
+            });
+        if let Some(channel) = channel {
+            return Ok(channel);
+        }
+        let closed = match id {
+            ChannelIdentifier::ShortChannelId(scid) => self
+                .history
+                .find_closed(|record| record.short_channel_id == Some(*scid)),
+            ChannelIdentifier::ChannelId(channel_id) => self
+                .history
+                .get(channel_id)
+                .filter(|record| record.closed.is_some())
+                .map(|record| (*channel_id, record)),
+            ChannelIdentifier::FundingOutpoint(outpoint) => self
+                .history
+                .find_closed(|record| record.funding_txo == Some(*outpoint)),
+        };
+        if let Some((channel_id, record)) = closed {
+            // SAFETY: we are looking only for closed channels.
+            let reason = record.closed.unwrap();
+            return Err(LampoError::ChannelNotFound(format!(
+                "channel `{id}` (`{channel_id}`) is closed: {reason}"
+            )));
+        }
+        Err(LampoError::ChannelNotFound(format!(
+            "channel `{id}` never existed"
+        )))
+    }
+
+    /// Look for a channel by short channel id, scid alias,
+    /// channel id or funding outpoint.
+    pub fn get_channel(&self, id: &ChannelIdentifier) -> error::Result<ChannelInfo> {
+        let channel = self.find_channel(id)?;
+        let record = self.history.get(&channel.channel_id).unwrap_or_default();
+        Ok(ChannelInfo {
+            channel: self.to_channel(&channel),
+            funding_txo: channel
+                .funding_txo
+                .map(|txo| txo.into_bitcoin_outpoint().to_string()),
+            forward_stats: record.forward_stats,
+            events: record.events.into_iter().collect(),
+        })
+    }
+
+    /// List the HTLCs that are in flight, optionally only the one
+    /// of a single channel.
+    pub fn list_htlcs(&self, id: Option<&ChannelIdentifier>) -> error::Result<Htlcs> {
+        let channel_id = id
+            .map(|id| self.find_channel(id))
+            .transpose()?
+            .map(|channel| channel.channel_id);
+        let height = self.manager().current_best_block().height;
+        let mut htlcs = Vec::new();
+        for channel in self.manager().list_channels() {
+            let peer_id = channel.counterparty.node_id.to_string();
+            for htlc in &channel.pending_inbound_htlcs {
+                let key = HtlcKey {
+                    channel_id: channel.channel_id,
+                    direction: HtlcDirection::Inbound,
+                    payment_hash: htlc.payment_hash,
+                    amount_msat: htlc.amount_msat,
+                    cltv_expiry: htlc.cltv_expiry,
+                };
+                let state = htlc.state.as_ref().map(|state| format!("{:?}", state));
+                htlcs.push((key, peer_id.clone(), Some(htlc.htlc_id), state, htlc.is_dust));
+            }
+            for htlc in &channel.pending_outbound_htlcs {
+                let key = HtlcKey {
+                    channel_id: channel.channel_id,
+                    direction: HtlcDirection::Outbound,
+                    payment_hash: htlc.payment_hash,
+                    amount_msat: htlc.amount_msat,
+                    cltv_expiry: htlc.cltv_expiry,
+                };
+                let state = htlc.state.as_ref().map(|state| format!("{:?}", state));
+                htlcs.push((key, peer_id.clone(), htlc.htlc_id, state, htlc.is_dust));
+            }
+        }
+        // track all the channels, so the one not requested are not forgotten
+        let keys = htlcs.iter().map(|(key, ..)| key.clone()).collect::<Vec<_>>();
+        let first_seen = self.htlcs.track(&keys);
+        let now = unix_now();
+        let htlcs = htlcs
+            .into_iter()
+            .zip(first_seen)
+            .filter(|((key, ..), _)| channel_id.map_or(true, |id| key.channel_id == id))
+            .map(|((key, peer_id, htlc_id, state, is_dust), first_seen)| Htlc {
+                channel_id: key.channel_id.to_string(),
+                peer_id,
+                direction: key.direction,
+                htlc_id,
+                amount_msat: key.amount_msat,
+                payment_hash: key.payment_hash.to_string(),
+                cltv_expiry: key.cltv_expiry,
+                deadline_blocks: key.cltv_expiry as i64 - height as i64,
+                age_secs: now.saturating_sub(first_seen),
+                state: state.unwrap_or("unknown".to_owned()),
+                is_dust,
             })
             .collect();
-        Channels { channels }
+        Ok(Htlcs { htlcs })
+    }
+
+    /// Refresh the bookkeeping of the in-flight HTLCs, this is called
+    /// by the event handler every time that ldk tells us that an HTLC
+    /// was added or removed.
+    pub fn track_htlcs(&self) {
+        if let Err(err) = self.list_htlcs(None) {
+            log::warn!(target: "lampo", "impossible track the htlcs: {err}");
+        }
+    }
+
+    /// Return false if the user disabled the forwarding through the channel.
+    pub fn is_channel_enabled(&self, channel_id: &ChannelId) -> bool {
+        !self.disabled_channels.lock().unwrap().contains_key(channel_id)
+    }
+
+    /// Enable or disable the forwarding through a channel without closing it.
+    ///
+    /// ldk does not allow to set the disable bit of our `channel_update`, so
+    /// the channel is disabled by setting a forwarding fee that no payer can
+    /// pay, and ldk will refuse all the forwards with an insufficient fee.
+    /// The original fees are stored, and restored when the channel is
+    /// enabled again. The payments where we are the sender or the receiver
+    /// are not affected.
+    pub fn set_channel_enabled(
+        &self,
+        id: &ChannelIdentifier,
+        enabled: bool,
+    ) -> error::Result<Channel> {
+        let channel = self.find_channel(id)?;
+        if self.is_channel_enabled(&channel.channel_id) == enabled {
+            return Ok(self.to_channel(&channel));
+        }
+        let Some(mut config) = channel.config else {
+            error::bail!("channel `{id}` has no config yet, retry later");
+        };
+        let mut disabled_channels = self.disabled_channels.lock().unwrap();
+        let fees = if enabled {
+            // SAFETY: the channel is disabled, so it is inside the map.
+            let fees = disabled_channels.get(&channel.channel_id).unwrap();
+            config.forwarding_fee_base_msat = fees.base_msat;
+            config.forwarding_fee_proportional_millionths = fees.proportional_millionths;
+            None
+        } else {
+            let fees = ForwardingFees {
+                base_msat: config.forwarding_fee_base_msat,
+                proportional_millionths: config.forwarding_fee_proportional_millionths,
+            };
+            config.forwarding_fee_base_msat = u32::MAX;
+            config.forwarding_fee_proportional_millionths = u32::MAX;
+            Some(fees)
+        };
+        self.manager()
+            .update_channel_config(&channel.counterparty.node_id, &[channel.channel_id], &config)
+            .map_err(|err| error::anyhow!("{:?}", err))?;
+        match fees {
+            Some(fees) => disabled_channels.insert(channel.channel_id, fees),
+            None => disabled_channels.remove(&channel.channel_id),
+        };
+        self.write_disabled_channels(&disabled_channels)?;
+        drop(disabled_channels);
+        log::info!(target: "lampo", "forwarding through channel `{}` enabled: {enabled}", channel.channel_id);
+        Ok(self.to_channel(&channel))
+    }
+
+    /// Set the proportional forwarding fee of a channel, the new fee is
+    /// gossiped with the next `channel_update`.
+    ///
+    /// The disabled channels are refused, their fee is the one that keeps
+    /// them disabled.
+    pub fn set_channel_fee_ppm(
+        &self,
+        id: &ChannelIdentifier,
+        proportional_millionths: u32,
+    ) -> error::Result<Channel> {
+        let channel = self.find_channel(id)?;
+        if !self.is_channel_enabled(&channel.channel_id) {
+            error::bail!("channel `{id}` is disabled");
+        }
+        let Some(mut config) = channel.config else {
+            error::bail!("channel `{id}` has no config yet, retry later");
+        };
+        config.forwarding_fee_proportional_millionths = proportional_millionths;
+        self.manager()
+            .update_channel_config(&channel.counterparty.node_id, &[channel.channel_id], &config)
+            .map_err(|err| error::anyhow!("{:?}", err))?;
+        Ok(self.to_channel(&channel))
+    }
+
+    /// Forget about a disabled channel when it is closed.
+    pub fn forget_disabled_channel(&self, channel_id: &ChannelId) -> error::Result<()> {
+        let mut disabled_channels = self.disabled_channels.lock().unwrap();
+        if disabled_channels.remove(channel_id).is_some() {
+            self.write_disabled_channels(&disabled_channels)?;
+        }
+        Ok(())
+    }
+
+    fn write_disabled_channels(
+        &self,
+        disabled_channels: &HashMap<ChannelId, ForwardingFees>,
+    ) -> error::Result<()> {
+        let channels = disabled_channels
+            .iter()
+            .map(|(channel_id, fees)| {
+                (
+                    channel_id.to_string(),
+                    json::json!({
+                        "fee_base_msat": fees.base_msat,
+                        "fee_proportional_millionths": fees.proportional_millionths,
+                    }),
+                )
+            })
+            .collect::<json::Map<_, _>>();
+        let buf = json::to_vec(&channels)?;
+        self.persister
+            .write(LAMPO_NAMESPACE, "", DISABLED_CHANNELS_KEY, &buf)?;
+        Ok(())
+    }
+
+    fn read_disabled_channels(
+        persister: &LampoPersistence,
+    ) -> error::Result<HashMap<ChannelId, ForwardingFees>> {
+        let buf = match persister.read(LAMPO_NAMESPACE, "", DISABLED_CHANNELS_KEY) {
+            Ok(buf) => buf,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let channels: json::Map<String, json::Value> = json::from_slice(&buf)?;
+        let mut disabled_channels = HashMap::new();
+        for (channel_id, fees) in channels {
+            let ChannelIdentifier::ChannelId(channel_id) = ChannelIdentifier::from_str(&channel_id)?
+            else {
+                error::bail!("invalid channel id `{channel_id}` inside the disabled channels");
+            };
+            let fee = |key: &str| -> error::Result<u32> {
+                let fee = fees
+                    .get(key)
+                    .and_then(|fee| fee.as_u64())
+                    .ok_or(error::anyhow!("`{key}` not found for channel `{channel_id}`"))?;
+                Ok(u32::try_from(fee)?)
+            };
+            let fees = ForwardingFees {
+                base_msat: fee("fee_base_msat")?,
+                proportional_millionths: fee("fee_proportional_millionths")?,
+            };
+            disabled_channels.insert(channel_id, fees);
+        }
+        Ok(disabled_channels)
+    }
+
+    /// Queue the cooperative close when the peer is offline.
+    ///
+    /// ldk refuses to start the shutdown when the peer is not
+    /// connected, so the close is stored and retried by
+    /// `retry_pending_closes_of` when the peer comes back online.
+    /// Return `None` if the channel can be closed right now.
+    pub fn queue_close_if_offline(
+        &self,
+        request: &request::CloseChannel,
+    ) -> error::Result<Option<PendingClose>> {
+        let channel_id = request.channel_id()?;
+        let Some(channel) = self
+            .manager()
+            .list_channels()
+            .into_iter()
+            .find(|channel| channel.channel_id == channel_id)
+        else {
+            // let the close report the error to the user
+            return Ok(None);
+        };
+        if !channel.is_channel_ready || channel.is_usable {
+            return Ok(None);
+        }
+        let queued_at = unix_now();
+        let close = PendingClose {
+            channel_id: channel_id.to_string(),
+            peer_id: channel.counterparty.node_id.to_string(),
+            destination: request.destination.clone(),
+            feerate_sat_per_1000_weight: request.feerate_sat_per_1000_weight,
+            queued_at,
+            deadline: queued_at + self.conf.pending_close_deadline_secs,
+            expired: false,
+        };
+        self.close_queue.push(close.clone())?;
+        log::info!(target: "lampo", "peer `{}` is offline, close of channel `{channel_id}` queued", close.peer_id);
+        Ok(Some(close))
+    }
+
+    /// Retry the queued cooperative closes with `node_id`, that is
+    /// back online.
+    ///
+    /// The channels can be closed only once the peer reestablished
+    /// them, so the closes wait for it up to `REESTABLISH_TIMEOUT`.
+    pub fn retry_pending_closes_of(self: &Arc<Self>, node_id: &NodeId) {
+        let peer_id = node_id.to_string();
+        let mut closes = self
+            .close_queue
+            .list()
+            .into_iter()
+            .filter(|close| close.peer_id == peer_id)
+            .collect::<Vec<_>>();
+        if closes.is_empty() {
+            return;
+        }
+        let manager = self.clone();
+        std::thread::spawn(move || {
+            let started = Instant::now();
+            loop {
+                closes.retain(|close| match manager.retry_pending_close(close) {
+                    Ok(pending) => pending,
+                    Err(err) => {
+                        log::warn!(target: "lampo", "impossible retry the close of channel `{}`: {err}", close.channel_id);
+                        false
+                    }
+                });
+                if closes.is_empty() || started.elapsed() >= REESTABLISH_TIMEOUT {
+                    break;
+                }
+                std::thread::sleep(REESTABLISH_POLL_INTERVAL);
+            }
+        });
+    }
+
+    /// Start the queued `close` if the channel is usable, return true
+    /// while the close is still pending.
+    fn retry_pending_close(&self, close: &PendingClose) -> error::Result<bool> {
+        let Some(channel) = self.pending_close_channel(close)? else {
+            return Ok(false);
+        };
+        if !channel.is_usable {
+            return Ok(true);
+        }
+        let node_id = channel.counterparty.node_id;
+        let channel_id = channel.channel_id;
+        log::info!(target: "lampo", "peer `{node_id}` is back online, closing channel `{channel_id}`");
+        self.close_channel_with_options(
+            &channel_id,
+            &node_id,
+            close.destination.as_deref(),
+            close.feerate_sat_per_1000_weight,
+        )?;
+        self.close_queue.remove(&close.channel_id)?;
+        Ok(false)
+    }
+
+    /// The channel of the queued `close`, the close is forgotten when
+    /// the channel was closed in the meanwhile.
+    fn pending_close_channel(&self, close: &PendingClose) -> error::Result<Option<ChannelDetails>> {
+        let ChannelIdentifier::ChannelId(channel_id) =
+            ChannelIdentifier::from_str(&close.channel_id)?
+        else {
+            error::bail!(
+                "invalid channel id `{}` inside the pending closes",
+                close.channel_id
+            );
+        };
+        let channel = self
+            .manager()
+            .list_channels()
+            .into_iter()
+            .find(|channel| channel.channel_id == channel_id);
+        if channel.is_none() {
+            self.close_queue.remove(&close.channel_id)?;
+        }
+        Ok(channel)
+    }
+
+    /// Escalate the queued cooperative closes with the deadline passed
+    /// while the peer is still offline.
+    pub fn expire_pending_closes(&self) {
+        for close in self.close_queue.list() {
+            if let Err(err) = self.expire_pending_close(&close) {
+                log::warn!(target: "lampo", "impossible expire the close of channel `{}`: {err}", close.channel_id);
+            }
+        }
+    }
+
+    fn expire_pending_close(&self, close: &PendingClose) -> error::Result<()> {
+        if unix_now() < close.deadline {
+            return Ok(());
+        }
+        let Some(channel) = self.pending_close_channel(close)? else {
+            return Ok(());
+        };
+        // the peer is back, and the close is started by the retry
+        if channel.is_usable {
+            return Ok(());
+        }
+        let node_id = channel.counterparty.node_id;
+        let channel_id = channel.channel_id;
+        if self.conf.force_close_after_deadline {
+            log::warn!(target: "lampo", "peer `{node_id}` is still offline after the deadline, force closing channel `{channel_id}`");
+            self.manager()
+                .force_close_broadcasting_latest_txn(&channel_id, &node_id)
+                .map_err(|err| error::anyhow!("{:?}", err))?;
+            self.close_queue.remove(&close.channel_id)?;
+        } else if self.close_queue.expire(&close.channel_id)? {
+            log::warn!(target: "lampo", "peer `{node_id}` is still offline after the deadline, consider to force close the channel `{channel_id}`");
+        }
+        Ok(())
+    }
+
+    /// Forget about a pending close when the channel is closed.
+    pub fn forget_pending_close(&self, channel_id: &ChannelId) -> error::Result<()> {
+        self.close_queue.remove(&channel_id.to_string())?;
+        Ok(())
+    }
+
+    /// Force close the channel without broadcasting our commitment
+    /// transaction, so the channel is forgotten by us and the peer
+    /// must close it.
+    pub fn dev_fail(&self, id: &ChannelIdentifier) -> error::Result<ChannelId> {
+        let channel = self.find_channel(id)?;
+        self.manager()
+            .force_close_without_broadcasting_txn(
+                &channel.channel_id,
+                &channel.counterparty.node_id,
+            )
+            .map_err(|err| error::anyhow!("{:?}", err))?;
+        Ok(channel.channel_id)
+    }
+
+    /// Sign our latest commitment transaction of the channel, and
+    /// the HTLC transactions that spend it, without broadcasting them.
+    ///
+    /// Broadcasting them after the channel moves on publishes a
+    /// revoked state, so this is available only in the debug builds.
+    #[cfg(debug_assertions)]
+    pub fn dev_sign_last_tx(
+        &self,
+        id: &ChannelIdentifier,
+    ) -> error::Result<(ChannelId, Vec<Transaction>)> {
+        let channel = self.find_channel(id)?;
+        let Some(funding_txo) = channel.funding_txo else {
+            error::bail!(
+                "channel `{}` has no funding transaction yet",
+                channel.channel_id
+            );
+        };
+        let monitor = self
+            .chain_monitor()
+            .get_monitor(funding_txo)
+            .map_err(|_| error::anyhow!("no monitor for channel `{}`", channel.channel_id))?;
+        let txs = monitor.get_latest_holder_commitment_txn(&self.logger);
+        Ok((channel.channel_id, txs))
+    }
+
+    /// Leave pending the payments received from `node_id` instead of
+    /// claiming them, until `ignore` is false.
+    pub fn set_ignore_htlcs(&self, node_id: NodeId, ignore: bool) {
+        let mut peers = self.ignored_htlc_peers.lock_or_recover();
+        if ignore {
+            peers.insert(node_id);
+        } else {
+            peers.remove(&node_id);
+        }
+    }
+
+    /// Return true if the HTLCs received through the channel must be
+    /// left pending, see `set_ignore_htlcs`.
+    pub fn ignores_htlcs(&self, channel_id: &ChannelId) -> bool {
+        let peers = self.ignored_htlc_peers.lock_or_recover();
+        if peers.is_empty() {
+            return false;
+        }
+        self.manager()
+            .list_channels()
+            .iter()
+            .find(|channel| channel.channel_id == *channel_id)
+            .is_some_and(|channel| peers.contains(&channel.counterparty.node_id))
+    }
+
+    fn close_channel_with_options(
+        &self,
+        channel_id: &ChannelId,
+        node_id: &NodeId,
+        destination: Option<&str>,
+        feerate_sat_per_1000_weight: Option<u32>,
+    ) -> error::Result<()> {
+        let shutdown_script = destination
+            .map(|destination| -> error::Result<ShutdownScript> {
+                let address = Address::from_str(destination)?.require_network(self.conf.network)?;
+                ShutdownScript::try_from(address.script_pubkey())
+                    .map_err(|err| error::anyhow!("invalid destination `{destination}`: {:?}", err))
+            })
+            .transpose()?;
+        self.manager()
+            .close_channel_with_feerate_and_script(
+                channel_id,
+                node_id,
+                feerate_sat_per_1000_weight,
+                shutdown_script,
+            )
+            .map_err(|err| error::anyhow!("{:?}", err))?;
+        Ok(())
+    }
+
+    /// Return the number of channels that are not ready yet.
+    pub fn pending_channels(&self) -> usize {
+        self.manager()
+            .list_channels()
+            .iter()
+            .filter(|channel| !channel.is_channel_ready)
+            .count()
+    }
+
+    /// Return the number of channels that the peer opened with us.
+    pub fn inbound_channels_with(&self, node_id: &NodeId) -> usize {
+        self.manager()
+            .list_channels_with_counterparty(node_id)
+            .iter()
+            .filter(|channel| !channel.is_outbound)
+            .count()
+    }
+
+    /// The on chain cost of opening a channel of `funding_amount`
+    /// satoshis, that is the funding plus the fee of a funding
+    /// transaction that confirms within `conf_target` blocks.
+    ///
+    /// The fee is measured on a dummy funding transaction, so it
+    /// fails when the wallet can not fund the channel.
+    pub fn estimate_channel_open_cost(&self, funding_amount: u64, conf_target: u16) -> error::Result<u64> {
+        let fee_rate = self
+            .onchain
+            .fees
+            .fee_rate_for_blocks(conf_target)
+            .max(wallet::min_fee_rate(&self.conf));
+        // the funding output is a P2WSH of the 2-of-2 multisig
+        let script = ScriptBuf::new_v0_p2wsh(&WScriptHash::all_zeros());
+        let funding_amount = Sat::from_sat(funding_amount);
+        let fee = self.wallet_manager.estimate_fee(script, funding_amount, fee_rate)?;
+        Ok(funding_amount.checked_add(fee)?.sat())
+    }
+
+    /// Check the channel limits configured by the user before
+    /// opening a channel of `amount_sat`, and that the wallet can
+    /// sign the funding transaction.
+    pub fn check_outbound_limits(&self, amount_sat: u64) -> Result<(), LampoError> {
+        if !self.wallet_manager.supports_signing() {
+            return Err(LampoError::WalletFailure(
+                "the wallet can not sign, so it can not fund a channel".to_owned(),
+            ));
+        }
+        self.conf
+            .check_outbound_channel(amount_sat, self.pending_channels())?;
+        Ok(())
+    }
+
+    /// Check that the outputs with at least `funding-min-confirmations`
+    /// cover the channel amount, the fee is checked by the wallet
+    /// when the funding transaction is created.
+    fn check_confirmed_funds(&self, amount_sat: u64) -> Result<(), LampoError> {
+        let min_confirmations = self.conf.funding_min_confirmations;
+        let available_sat = self
+            .wallet_manager
+            .list_unspent()
+            .map_err(|err| LampoError::WalletFailure(format!("{err}")))?
+            .iter()
+            .filter(|utxo| !utxo.reserved && utxo.confirmed >= min_confirmations)
+            .map(|utxo| utxo.amount_msat.to_sat_floor().sat())
+            .sum::<u64>();
+        if available_sat < amount_sat {
+            return Err(wallet::WalletError::InsufficientFunds {
+                needed_sat: amount_sat,
+                available_sat,
+                min_confirmations,
+            }
+            .into());
+        }
+        Ok(())
     }
 
     pub fn load_channel_monitors(&self, watch: bool) -> error::Result<()> {
@@ -249,6 +1091,10 @@ impl LampoChannelManager {
         self.score.clone().unwrap()
     }
 
+    pub fn graph_load(&self) -> Arc<GraphLoad> {
+        self.graph_load.clone()
+    }
+
     // FIXME: Step 11: Optional: Initialize the NetGraphMsgHandler
     pub fn network_graph(
         &mut self,
@@ -264,20 +1110,25 @@ impl LampoChannelManager {
     > {
         if self.router.is_none() {
             // Step 9: Initialize routing ProbabilisticScorer
-            let network_graph_path = format!("{}/network_graph", self.conf.path());
-            let network_graph = self.read_network(Path::new(&network_graph_path));
-
-            let scorer_path = format!("{}/scorer", self.conf.path());
-            let scorer = Arc::new(Mutex::new(
-                self.read_scorer(Path::new(&scorer_path), &network_graph),
+            //
+            // the graph on the disk is loaded later, so the node
+            // does not wait for it to start
+            let network_graph = Arc::new(LampoGraphPersister::empty_graph(
+                &self.persister,
+                self.conf.network,
+                self.logger.clone(),
             ));
+            let scorer = Arc::new(Mutex::new(LampoGraphPersister::empty_scorer(
+                network_graph.clone(),
+                self.logger.clone(),
+            )));
 
             self.graph = Some(network_graph.clone());
             self.score = Some(scorer.clone());
             self.router = Some(Arc::new(DefaultRouter::new(
                 network_graph,
                 self.logger.clone(),
-                self.wallet_manager.ldk_keys().keys_manager.clone(),
+                self.wallet_manager.ldk_keys().inner(),
                 scorer,
                 ProbabilisticScoringFeeParameters::default(),
             )))
@@ -285,30 +1136,6 @@ impl LampoChannelManager {
         self.router.clone().unwrap()
     }
 
-    pub(crate) fn read_scorer(
-        &self,
-        path: &Path,
-        graph: &Arc<LampoGraph>,
-    ) -> ProbabilisticScorer<Arc<LampoGraph>, Arc<LampoLogger>> {
-        let params = ProbabilisticScoringDecayParameters::default();
-        if let Ok(file) = File::open(path) {
-            let args = (params, Arc::clone(graph), self.logger.clone());
-            if let Ok(scorer) = ProbabilisticScorer::read(&mut BufReader::new(file), args) {
-                return scorer;
-            }
-        }
-        ProbabilisticScorer::new(params, graph.clone(), self.logger.clone())
-    }
-
-    pub(crate) fn read_network(&self, path: &Path) -> Arc<LampoGraph> {
-        if let Ok(file) = File::open(path) {
-            if let Ok(graph) = NetworkGraph::read(&mut BufReader::new(file), self.logger.clone()) {
-                return Arc::new(graph);
-            }
-        }
-        Arc::new(NetworkGraph::new(self.conf.network, self.logger.clone()))
-    }
-
     pub fn is_restarting(&self) -> error::Result<bool> {
         Ok(Path::exists(Path::new(&format!(
             "{}/manager",
@@ -320,12 +1147,15 @@ impl LampoChannelManager {
         let monitor = self.build_channel_monitor();
         self.monitor = Some(Arc::new(monitor));
         let _ = self.network_graph();
+        // a monitor torn by a crash is restored before ldk reads it
+        let keys = self.wallet_manager.ldk_keys().inner();
+        self.persister.recover_channel_monitors(&keys)?;
         let mut monitors = self.get_channel_monitors()?;
         let monitors = monitors.iter_mut().collect::<Vec<_>>();
         let read_args = ChannelManagerReadArgs::new(
-            self.wallet_manager.ldk_keys().keys_manager.clone(),
-            self.wallet_manager.ldk_keys().keys_manager.clone(),
-            self.wallet_manager.ldk_keys().keys_manager.clone(),
+            self.wallet_manager.ldk_keys().inner(),
+            self.wallet_manager.ldk_keys().inner(),
+            self.wallet_manager.ldk_keys().inner(),
             self.onchain.clone(),
             self.chain_monitor(),
             self.onchain.clone(),
@@ -339,6 +1169,14 @@ impl LampoChannelManager {
             <(BlockHash, LampoChannel)>::read(&mut channel_manager_file, read_args)
                 .map_err(|err| error::anyhow!("{err}"))?;
         self.channeld = Some(channel_manager.into());
+        let best_block = self.manager().current_best_block();
+        self.sweeper = Some(Arc::new(load_sweeper(
+            best_block,
+            self.onchain.clone(),
+            self.wallet_manager.clone(),
+            self.persister.clone(),
+            self.logger.clone(),
+        )?));
         Ok(())
     }
 
@@ -357,10 +1195,18 @@ impl LampoChannelManager {
             .iter()
             .map(|(txid, _, _)| txid.clone())
             .collect::<Vec<_>>();
+        // the transactions that sweep the outputs of the closed channels
+        let mut relevant_txids_three = self
+            .sweeper()
+            .get_relevant_txids()
+            .iter()
+            .map(|(txid, _, _)| txid.clone())
+            .collect::<Vec<_>>();
         log::debug!(
-            "transactions {:?} {:?}",
+            "transactions {:?} {:?} {:?}",
             relevant_txids_one,
-            relevant_txids_two
+            relevant_txids_two,
+            relevant_txids_three
         );
         // FIXME: check if some of these transaction are out of chain
         self.onchain
@@ -369,6 +1215,9 @@ impl LampoChannelManager {
         self.onchain
             .backend
             .manage_transactions(&mut relevant_txids_two)?;
+        self.onchain
+            .backend
+            .manage_transactions(&mut relevant_txids_three)?;
         self.onchain.backend.process_transactions()?;
         Ok(())
     }
@@ -387,7 +1236,7 @@ impl LampoChannelManager {
         let monitor = self.build_channel_monitor();
         self.monitor = Some(Arc::new(monitor));
 
-        let keymanagers = self.wallet_manager.ldk_keys().keys_manager.clone();
+        let keymanagers = self.wallet_manager.ldk_keys().inner();
         self.channeld = Some(Arc::new(LampoArcChannelManager::new(
             self.onchain.clone(),
             self.monitor.clone().unwrap(),
@@ -401,6 +1250,13 @@ impl LampoChannelManager {
             chain_params,
             block_timestamp,
         )));
+        self.sweeper = Some(Arc::new(load_sweeper(
+            BestBlock::new(block, height.to_consensus_u32()),
+            self.onchain.clone(),
+            self.wallet_manager.clone(),
+            self.persister.clone(),
+            self.logger.clone(),
+        )?));
         Ok(())
     }
 }
@@ -409,26 +1265,39 @@ impl ChannelEvents for LampoChannelManager {
     fn open_channel(
         &self,
         open_channel: request::OpenChannel,
-    ) -> error::Result<response::OpenChannel> {
+    ) -> Result<response::OpenChannel, LampoError> {
+        self.check_outbound_limits(open_channel.amount)?;
+        self.check_confirmed_funds(open_channel.amount)?;
+        let node_id = open_channel
+            .node_id()
+            .map_err(|err| LampoError::InvalidParams(format!("{err}")))?;
         self.manager()
             .create_channel(
-                open_channel.node_id()?,
+                node_id,
                 open_channel.amount,
                 0,
                 0,
                 None,
                 Some(self.conf.ldk_conf),
             )
-            .map_err(|err| error::anyhow!("{:?}", err))?;
+            .map_err(|err| LampoError::ChannelFailure(format!("{:?}", err)))?;
 
         // Wait for SendRawTransaction to be received so to get the funding transaction
         // FIXME: we can loop forever here
         let tx: Option<Transaction> = loop {
             let events = self.handler().events();
-            let event = events.recv_timeout(std::time::Duration::from_secs(30))?;
+            let event = events
+                .recv_timeout(std::time::Duration::from_secs(30))
+                .map_err(|err| LampoError::ChannelFailure(format!("{err}")))?;
 
-            if let Event::OnChain(OnChainEvent::SendRawTransaction(tx)) = event {
-                break Some(tx);
+            match event {
+                Event::OnChain(OnChainEvent::SendRawTransaction(tx)) => break Some(tx),
+                // the wallet was not able to fund the channel
+                Event::Lightning(LightningEvent::ChannelEvent {
+                    state: ChannelState::OpeningError,
+                    message,
+                }) => return Err(LampoError::ChannelFailure(message)),
+                _ => continue,
             }
         };
 
@@ -442,14 +1311,20 @@ impl ChannelEvents for LampoChannelManager {
         })
     }
 
-    fn close_channel(&self, channel: request::CloseChannel) -> error::Result<()> {
-        let channel_id = channel.channel_id()?;
-        let node_id = channel.counterpart_node_id()?;
-
-        self.manager()
-            .close_channel(&channel_id, &node_id)
-            .map_err(|err| error::anyhow!("{:?}", err))?;
-        Ok(())
+    fn close_channel(&self, channel: request::CloseChannel) -> Result<(), LampoError> {
+        let channel_id = channel
+            .channel_id()
+            .map_err(|err| LampoError::InvalidParams(format!("{err}")))?;
+        let node_id = channel
+            .counterpart_node_id()
+            .map_err(|err| LampoError::InvalidParams(format!("{err}")))?;
+        self.close_channel_with_options(
+            &channel_id,
+            &node_id,
+            channel.destination.as_deref(),
+            channel.feerate_sat_per_1000_weight,
+        )
+        .map_err(|err| LampoError::ChannelFailure(format!("{err}")))
     }
     fn change_state_channel(&self, _: ChangeStateChannelEvent) -> error::Result<()> {
         unimplemented!()
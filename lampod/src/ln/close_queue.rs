@@ -0,0 +1,99 @@
+//! Queue of the cooperative closes for offline peers.
+//!
+//! ldk refuses to start a cooperative close when the peer is
+//! offline, so lampo records the intent and retries the close
+//! when the peer comes back online. The queue is persisted so
+//! it survives a restart of the node.
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use lampo_common::error;
+use lampo_common::json;
+use lampo_common::ldk::util::persist::KVStore;
+use lampo_common::model::response::PendingClose;
+use lampo_common::sync::MutexExt;
+
+use crate::persistence::LampoPersistence;
+
+const LAMPO_NAMESPACE: &str = "lampo";
+const PENDING_CLOSES_KEY: &str = "pending_closes";
+
+pub struct LampoCloseQueue {
+    persister: Arc<LampoPersistence>,
+    /// Pending closes indexed by channel id.
+    closes: Mutex<HashMap<String, PendingClose>>,
+}
+
+impl LampoCloseQueue {
+    pub fn new(persister: Arc<LampoPersistence>) -> Self {
+        let closes = Self::read(&persister).unwrap_or_else(|err| {
+            log::warn!(target: "lampo", "impossible read the pending closes: {err}");
+            Vec::new()
+        });
+        let closes = closes
+            .into_iter()
+            .map(|close| (close.channel_id.clone(), close))
+            .collect();
+        Self {
+            persister,
+            closes: Mutex::new(closes),
+        }
+    }
+
+    fn read(persister: &LampoPersistence) -> error::Result<Vec<PendingClose>> {
+        match persister.read(LAMPO_NAMESPACE, "", PENDING_CLOSES_KEY) {
+            Ok(buf) => Ok(json::from_slice::<Vec<PendingClose>>(&buf)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Queue a cooperative close, if the channel has already a
+    /// pending close it is replaced.
+    pub fn push(&self, close: PendingClose) -> error::Result<()> {
+        let mut closes = self.closes.lock_or_recover();
+        closes.insert(close.channel_id.clone(), close);
+        self.write(&closes)
+    }
+
+    pub fn remove(&self, channel_id: &str) -> error::Result<Option<PendingClose>> {
+        let mut closes = self.closes.lock_or_recover();
+        let close = closes.remove(channel_id);
+        if close.is_some() {
+            self.write(&closes)?;
+        }
+        Ok(close)
+    }
+
+    pub fn get(&self, channel_id: &str) -> Option<PendingClose> {
+        self.closes.lock_or_recover().get(channel_id).cloned()
+    }
+
+    pub fn list(&self) -> Vec<PendingClose> {
+        self.closes.lock_or_recover().values().cloned().collect()
+    }
+
+    /// Mark the close as expired, return true if the close
+    /// was not already expired.
+    pub fn expire(&self, channel_id: &str) -> error::Result<bool> {
+        let mut closes = self.closes.lock_or_recover();
+        let Some(close) = closes.get_mut(channel_id) else {
+            return Ok(false);
+        };
+        if close.expired {
+            return Ok(false);
+        }
+        close.expired = true;
+        self.write(&closes)?;
+        Ok(true)
+    }
+
+    fn write(&self, closes: &HashMap<String, PendingClose>) -> error::Result<()> {
+        let closes = closes.values().collect::<Vec<_>>();
+        let buf = json::to_vec(&closes)?;
+        self.persister
+            .write(LAMPO_NAMESPACE, "", PENDING_CLOSES_KEY, &buf)?;
+        Ok(())
+    }
+}
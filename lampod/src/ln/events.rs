@@ -1,10 +1,10 @@
 //! Lightning Events handler implementation
-use std::net::SocketAddr;
-
 use async_trait::async_trait;
 
 use lampo_common::error;
+use lampo_common::error::LampoError;
 use lampo_common::ldk::ln::features::ChannelTypeFeatures;
+use lampo_common::ldk::ln::msgs::SocketAddress;
 use lampo_common::model::request;
 use lampo_common::model::response;
 use lampo_common::types::{ChannelId, ChannelState, NodeId};
@@ -28,10 +28,10 @@ pub trait ChannelEvents {
     fn open_channel(
         &self,
         open_channel: request::OpenChannel,
-    ) -> error::Result<response::OpenChannel>;
+    ) -> Result<response::OpenChannel, LampoError>;
 
     /// Close a channel
-    fn close_channel(&self, channel: request::CloseChannel) -> error::Result<()>;
+    fn close_channel(&self, channel: request::CloseChannel) -> Result<(), LampoError>;
 
     fn change_state_channel(&self, event: ChangeStateChannelEvent) -> error::Result<()>;
 }
@@ -41,7 +41,11 @@ pub trait ChannelEvents {
 pub trait PeerEvents {
     async fn handle(&self, event: peer_event::PeerCommand) -> error::Result<()>;
 
-    async fn connect(&self, node_id: NodeId, host: SocketAddr) -> error::Result<()>;
+    /// Connect to the peer, through the proxy if the address is an
+    /// onion address or if we are configured to use only Tor.
+    async fn connect(&self, node_id: NodeId, host: SocketAddress) -> error::Result<()>;
 
-    async fn disconnect(&self, node_id: NodeId) -> error::Result<()>;
+    /// Disconnect from the peer, refusing if there are HTLCs
+    /// in flight unless `force` is true.
+    async fn disconnect(&self, node_id: NodeId, force: bool) -> error::Result<()>;
 }
@@ -0,0 +1,146 @@
+//! Queries over the network graph.
+//!
+//! The mainnet graph is large, so the queries are paginated and
+//! they walk the graph behind its read lock, without copying more
+//! than the page that is returned.
+use std::ops::Bound;
+
+use lampo_common::bitcoin::hashes::hex::ToHex;
+use lampo_common::error;
+use lampo_common::ldk::routing::gossip::{ChannelInfo, ChannelUpdateInfo, NodeId, NodeInfo};
+use lampo_common::model::request::{ListGossipChannels, ListNodes};
+use lampo_common::model::response::{
+    ChannelPolicy, GossipChannel, GossipChannels, GossipNode, GossipNodes,
+};
+
+use crate::ln::channel_manager::LampoGraph;
+
+/// List the nodes of the graph ordered by node id, starting from
+/// the continuation token of the request.
+pub fn list_nodes(graph: &LampoGraph, request: &ListNodes) -> error::Result<GossipNodes> {
+    let limit = request.limit()?;
+    let graph = graph.read_only();
+    if let Some(node_id) = request.node_id()? {
+        let node_id = NodeId::from_pubkey(&node_id);
+        let nodes = graph
+            .node(&node_id)
+            .map(|info| vec![gossip_node(&node_id, info)])
+            .unwrap_or_default();
+        return Ok(GossipNodes { nodes, next: None });
+    }
+
+    let start = match request.start()? {
+        Some(start) => Bound::Included(NodeId::from_pubkey(&start)),
+        None => Bound::Unbounded,
+    };
+    let mut nodes = Vec::with_capacity(limit);
+    let mut next = None;
+    for (node_id, info) in graph.nodes().range((start, Bound::Unbounded)) {
+        if nodes.len() == limit {
+            next = Some(node_id.to_string());
+            break;
+        }
+        nodes.push(gossip_node(node_id, info));
+    }
+    Ok(GossipNodes { nodes, next })
+}
+
+/// List the channels of the graph ordered by short channel id,
+/// starting from the continuation token of the request.
+pub fn list_gossip_channels(
+    graph: &LampoGraph,
+    request: &ListGossipChannels,
+) -> error::Result<GossipChannels> {
+    let limit = request.limit()?;
+    let graph = graph.read_only();
+    if let Some(scid) = request.short_channel_id {
+        let channels = graph
+            .channel(scid)
+            .map(|info| vec![gossip_channel(scid, info)])
+            .unwrap_or_default();
+        return Ok(GossipChannels {
+            channels,
+            next: None,
+        });
+    }
+
+    let start = request.start()?.unwrap_or_default();
+    let mut channels = Vec::with_capacity(limit);
+    let mut next = None;
+    if let Some(node_id) = request.node_id()? {
+        let Some(node) = graph.node(&NodeId::from_pubkey(&node_id)) else {
+            return Ok(GossipChannels {
+                channels,
+                next: None,
+            });
+        };
+        // the channels of a node are few, so we can sort them
+        let mut scids = node
+            .channels
+            .iter()
+            .filter(|scid| **scid >= start)
+            .collect::<Vec<_>>();
+        scids.sort();
+        for scid in scids {
+            let Some(info) = graph.channel(*scid) else {
+                continue;
+            };
+            if channels.len() == limit {
+                next = Some(scid.to_string());
+                break;
+            }
+            channels.push(gossip_channel(*scid, info));
+        }
+    } else {
+        for (scid, info) in graph.channels().range(start..) {
+            if channels.len() == limit {
+                next = Some(scid.to_string());
+                break;
+            }
+            channels.push(gossip_channel(*scid, info));
+        }
+    }
+    Ok(GossipChannels { channels, next })
+}
+
+fn gossip_node(node_id: &NodeId, info: &NodeInfo) -> GossipNode {
+    let announcement = info.announcement_info.as_ref();
+    GossipNode {
+        node_id: node_id.to_string(),
+        alias: announcement.map(|info| info.alias.to_string()),
+        color: announcement.map(|info| info.rgb.to_hex()),
+        addresses: announcement
+            .map(|info| info.addresses().iter().map(|addr| addr.to_string()).collect())
+            .unwrap_or_default(),
+        features: announcement.map(|info| {
+            // the features are encoded as big endian, like in the spec
+            let mut features = info.features.le_flags().to_vec();
+            features.reverse();
+            features.to_hex()
+        }),
+        last_update: announcement.map(|info| info.last_update),
+    }
+}
+
+fn gossip_channel(scid: u64, info: &ChannelInfo) -> GossipChannel {
+    GossipChannel {
+        short_channel_id: scid,
+        node_one: info.node_one.to_string(),
+        node_two: info.node_two.to_string(),
+        capacity_sat: info.capacity_sats,
+        one_to_two: info.one_to_two.as_ref().map(channel_policy),
+        two_to_one: info.two_to_one.as_ref().map(channel_policy),
+    }
+}
+
+fn channel_policy(update: &ChannelUpdateInfo) -> ChannelPolicy {
+    ChannelPolicy {
+        fee_base_msat: update.fees.base_msat,
+        fee_proportional_millionths: update.fees.proportional_millionths,
+        cltv_expiry_delta: update.cltv_expiry_delta,
+        htlc_minimum_msat: update.htlc_minimum_msat,
+        htlc_maximum_msat: update.htlc_maximum_msat,
+        disabled: !update.enabled,
+        last_update: update.last_update,
+    }
+}
@@ -0,0 +1,382 @@
+//! Network graph and scorer persistence.
+//!
+//! The network graph and the scorer contain what the node learned
+//! about the network (the gossip and the liquidity of the channels),
+//! so without persisting them the node restarts from scratch and the
+//! success rate of the payments regresses.
+//!
+//! They are stored with the same keys used by the ldk background
+//! processor, so the files written by the two are interchangeable.
+//!
+//! On mainnet the graph is hundreds of MB, so the node starts with an
+//! empty graph and the one on the disk is read in background, then
+//! copied inside the graph used by the node. Until the copy is done
+//! the files are not overwritten, and the payments wait for it.
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use lampo_common::bitcoin::blockdata::constants::ChainHash;
+use lampo_common::bitcoin::{Network, TxOut};
+use lampo_common::error;
+use lampo_common::error::LampoError;
+use lampo_common::ldk::ln::chan_utils::make_funding_redeemscript;
+use lampo_common::ldk::ln::msgs::{
+    UnsignedChannelAnnouncement, UnsignedChannelUpdate, UnsignedNodeAnnouncement,
+};
+use lampo_common::ldk::routing::gossip::{NetworkGraph, NodeAlias};
+use lampo_common::ldk::routing::scoring::{ProbabilisticScorer, ProbabilisticScoringDecayParameters};
+use lampo_common::ldk::routing::utxo::{UtxoLookup, UtxoResult};
+use lampo_common::ldk::util::persist::KVStore;
+use lampo_common::ldk::util::ser::{ReadableArgs, Writeable};
+use lampo_common::sync::MutexExt;
+use lampo_common::time::unix_now;
+
+use crate::ln::channel_manager::{LampoGraph, LampoScorer};
+use crate::persistence::LampoPersistence;
+use crate::utils::logger::LampoLogger;
+
+const NETWORK_GRAPH_KEY: &str = "network_graph";
+const SCORER_KEY: &str = "scorer";
+
+/// Minimum time between two writes of the graph, so a gossip
+/// storm does not hammer the disk.
+pub const GRAPH_PERSIST_INTERVAL: Duration = Duration::from_secs(30);
+/// How many channels are copied between two updates of the progress
+/// of the load.
+const PROGRESS_STEP: usize = 1024;
+
+/// The progress of the load of the graph from the disk.
+#[derive(Default)]
+pub struct GraphLoad {
+    loaded: Mutex<bool>,
+    done: Condvar,
+    nodes_loaded: AtomicUsize,
+}
+
+impl GraphLoad {
+    pub fn is_loaded(&self) -> bool {
+        *self.loaded.lock_or_recover()
+    }
+
+    /// The nodes inside the graph of the node until now.
+    pub fn nodes_loaded(&self) -> usize {
+        self.nodes_loaded.load(Ordering::SeqCst)
+    }
+
+    /// Wait the end of the load for at most `timeout`.
+    pub fn wait(&self, timeout: Duration) -> Result<(), LampoError> {
+        let loaded = self.loaded.lock_or_recover();
+        let (loaded, _) = self
+            .done
+            .wait_timeout_while(loaded, timeout, |loaded| !*loaded)
+            .unwrap_or_else(PoisonError::into_inner);
+        if !*loaded {
+            return Err(LampoError::GraphLoading {
+                nodes_loaded: self.nodes_loaded(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Wait the end of the load without a timeout.
+    pub fn wait_loaded(&self) {
+        let loaded = self.loaded.lock_or_recover();
+        let _ = self
+            .done
+            .wait_while(loaded, |loaded| !*loaded)
+            .unwrap_or_else(PoisonError::into_inner);
+    }
+
+    fn finish(&self, nodes: usize) {
+        self.nodes_loaded.store(nodes, Ordering::SeqCst);
+        *self.loaded.lock_or_recover() = true;
+        self.done.notify_all();
+    }
+}
+
+/// Give the capacity already known of a channel, so its announcement
+/// is copied without asking the chain again.
+struct KnownCapacity(TxOut);
+
+impl KnownCapacity {
+    fn new(msg: &UnsignedChannelAnnouncement, capacity_sats: u64) -> Option<Self> {
+        let bitcoin_key_1 = msg.bitcoin_key_1.as_pubkey().ok()?;
+        let bitcoin_key_2 = msg.bitcoin_key_2.as_pubkey().ok()?;
+        Some(Self(TxOut {
+            value: capacity_sats,
+            script_pubkey: make_funding_redeemscript(&bitcoin_key_1, &bitcoin_key_2).to_v0_p2wsh(),
+        }))
+    }
+}
+
+impl UtxoLookup for KnownCapacity {
+    fn get_utxo(&self, _: &ChainHash, _: u64) -> UtxoResult {
+        UtxoResult::Sync(Ok(self.0.clone()))
+    }
+}
+
+pub struct LampoGraphPersister {
+    persister: Arc<LampoPersistence>,
+    graph: Arc<LampoGraph>,
+    scorer: Arc<Mutex<LampoScorer>>,
+    loading: Arc<GraphLoad>,
+    last_persist: Mutex<Option<Instant>>,
+}
+
+impl LampoGraphPersister {
+    pub fn new(
+        persister: Arc<LampoPersistence>,
+        graph: Arc<LampoGraph>,
+        scorer: Arc<Mutex<LampoScorer>>,
+        loading: Arc<GraphLoad>,
+    ) -> Self {
+        Self {
+            persister,
+            graph,
+            scorer,
+            loading,
+            last_persist: Mutex::new(None),
+        }
+    }
+
+    /// An empty graph for the node to start, the files on the disk
+    /// are held until `spawn_load` copies them inside it.
+    pub fn empty_graph(
+        persister: &LampoPersistence,
+        network: Network,
+        logger: Arc<LampoLogger>,
+    ) -> LampoGraph {
+        persister.hold("", "", NETWORK_GRAPH_KEY);
+        persister.hold("", "", SCORER_KEY);
+        NetworkGraph::new(network, logger)
+    }
+
+    /// A scorer without history, until `spawn_load` reads the one on
+    /// the disk.
+    pub fn empty_scorer(graph: Arc<LampoGraph>, logger: Arc<LampoLogger>) -> LampoScorer {
+        ProbabilisticScorer::new(
+            ProbabilisticScoringDecayParameters::default(),
+            graph,
+            logger,
+        )
+    }
+
+    /// Load the network graph from the disk, a missing or corrupted
+    /// file gives an empty graph.
+    pub fn load_graph(
+        persister: &LampoPersistence,
+        network: Network,
+        logger: Arc<LampoLogger>,
+    ) -> LampoGraph {
+        match Self::open(persister, NETWORK_GRAPH_KEY) {
+            Some(mut reader) => match NetworkGraph::read(&mut reader, logger.clone()) {
+                Ok(graph) => return graph,
+                Err(err) => log::warn!(
+                    target: "lampo",
+                    "network graph corrupted, starting with an empty one: {:?}",
+                    err
+                ),
+            },
+            None => {
+                log::info!(target: "lampo", "network graph not found, starting with an empty one")
+            }
+        }
+        NetworkGraph::new(network, logger)
+    }
+
+    /// Load the scorer from the disk, a missing or corrupted file
+    /// gives a scorer without history.
+    pub fn load_scorer(
+        persister: &LampoPersistence,
+        graph: Arc<LampoGraph>,
+        logger: Arc<LampoLogger>,
+    ) -> LampoScorer {
+        let params = ProbabilisticScoringDecayParameters::default();
+        if let Some(mut reader) = Self::open(persister, SCORER_KEY) {
+            let args = (params, graph.clone(), logger.clone());
+            match ProbabilisticScorer::read(&mut reader, args) {
+                Ok(scorer) => return scorer,
+                Err(err) => log::warn!(
+                    target: "lampo",
+                    "scorer corrupted, starting with an empty one: {:?}",
+                    err
+                ),
+            }
+        }
+        ProbabilisticScorer::new(params, graph, logger)
+    }
+
+    fn open(persister: &LampoPersistence, key: &str) -> Option<BufReader<File>> {
+        match persister.open("", "", key) {
+            Ok(reader) => Some(reader),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => {
+                log::warn!(target: "lampo", "impossible read `{key}`: {err}");
+                None
+            }
+        }
+    }
+
+    /// Read the graph and the scorer on the disk in background, and
+    /// copy them inside the ones used by the node.
+    pub fn spawn_load(
+        self: Arc<Self>,
+        network: Network,
+        logger: Arc<LampoLogger>,
+    ) -> JoinHandle<()> {
+        std::thread::spawn(move || {
+            let started = Instant::now();
+            let graph = Self::load_graph(&self.persister, network, logger.clone());
+            self.merge(&graph, network);
+            drop(graph);
+            let scorer = Self::load_scorer(&self.persister, self.graph.clone(), logger);
+            *self.scorer.lock_or_recover() = scorer;
+            self.persister.release("", "", NETWORK_GRAPH_KEY);
+            self.persister.release("", "", SCORER_KEY);
+            let nodes = self.graph.read_only().nodes().len();
+            self.loading.finish(nodes);
+            log::info!(
+                target: "lampo",
+                "network graph with {nodes} nodes loaded in {:?}",
+                started.elapsed()
+            );
+        })
+    }
+
+    /// Copy the channels and the nodes of `from` inside the graph of
+    /// the node. The gossip received in the meanwhile is newer, so
+    /// the copies rejected by the graph are skipped.
+    fn merge(&self, from: &LampoGraph, network: Network) {
+        let chain_hash = ChainHash::using_genesis_block(network);
+        let now = unix_now();
+        let from_graph = from.read_only();
+        let mut skipped = 0;
+        for (copied, (scid, channel)) in from_graph.channels().unordered_iter().enumerate() {
+            if copied % PROGRESS_STEP == 0 {
+                let nodes = self.graph.read_only().nodes().len();
+                self.loading.nodes_loaded.store(nodes, Ordering::SeqCst);
+            }
+            let added = match &channel.announcement_message {
+                Some(msg) => {
+                    let lookup = channel
+                        .capacity_sats
+                        .and_then(|capacity| KnownCapacity::new(&msg.contents, capacity));
+                    self.graph
+                        .update_channel_from_unsigned_announcement(&msg.contents, &lookup.as_ref())
+                }
+                None => {
+                    let (Ok(node_one), Ok(node_two)) =
+                        (channel.node_one.as_pubkey(), channel.node_two.as_pubkey())
+                    else {
+                        skipped += 1;
+                        continue;
+                    };
+                    // the channels without updates are pruned as if
+                    // they were received now
+                    let timestamp = [&channel.one_to_two, &channel.two_to_one]
+                        .into_iter()
+                        .flatten()
+                        .map(|update| update.last_update as u64)
+                        .max()
+                        .unwrap_or(now);
+                    self.graph.add_channel_from_partial_announcement(
+                        *scid,
+                        timestamp,
+                        channel.features.clone(),
+                        node_one,
+                        node_two,
+                    )
+                }
+            };
+            if added.is_err() {
+                skipped += 1;
+            }
+            for (direction, update) in [(0, &channel.one_to_two), (1, &channel.two_to_one)] {
+                let Some(update) = update else {
+                    continue;
+                };
+                let msg = UnsignedChannelUpdate {
+                    chain_hash,
+                    short_channel_id: *scid,
+                    timestamp: update.last_update,
+                    // the second bit tells that the direction is disabled
+                    flags: direction | if update.enabled { 0 } else { 2 },
+                    cltv_expiry_delta: update.cltv_expiry_delta,
+                    htlc_minimum_msat: update.htlc_minimum_msat,
+                    htlc_maximum_msat: update.htlc_maximum_msat,
+                    fee_base_msat: update.fees.base_msat,
+                    fee_proportional_millionths: update.fees.proportional_millionths,
+                    excess_data: Vec::new(),
+                };
+                if self.graph.update_channel_unsigned(&msg).is_err() {
+                    skipped += 1;
+                }
+            }
+        }
+        for (node_id, node) in from_graph.nodes().unordered_iter() {
+            let Some(info) = &node.announcement_info else {
+                continue;
+            };
+            let msg = UnsignedNodeAnnouncement {
+                features: info.features.clone(),
+                timestamp: info.last_update,
+                node_id: *node_id,
+                rgb: info.rgb,
+                alias: NodeAlias(info.alias.0),
+                addresses: info.addresses().to_vec(),
+                excess_address_data: Vec::new(),
+                excess_data: Vec::new(),
+            };
+            if self
+                .graph
+                .update_node_from_unsigned_announcement(&msg)
+                .is_err()
+            {
+                skipped += 1;
+            }
+        }
+        if let Some(timestamp) = from.get_last_rapid_gossip_sync_timestamp() {
+            let last = self.graph.get_last_rapid_gossip_sync_timestamp();
+            if last.map_or(true, |last| last < timestamp) {
+                self.graph.set_last_rapid_gossip_sync_timestamp(timestamp);
+            }
+        }
+        if skipped > 0 {
+            log::debug!(target: "lampo", "{skipped} gossip messages of the network graph on the disk are skipped");
+        }
+    }
+
+    /// Persist the graph and the scorer, unless they were persisted
+    /// less than `GRAPH_PERSIST_INTERVAL` ago.
+    ///
+    /// Return true if they are written on disk.
+    pub fn persist(&self) -> error::Result<bool> {
+        let mut last_persist = self.last_persist.lock_or_recover();
+        if last_persist.is_some_and(|last| last.elapsed() < GRAPH_PERSIST_INTERVAL) {
+            return Ok(false);
+        }
+        self.write()?;
+        *last_persist = Some(Instant::now());
+        Ok(true)
+    }
+
+    /// Persist the graph and the scorer right now, e.g. on shutdown.
+    pub fn persist_now(&self) -> error::Result<()> {
+        let mut last_persist = self.last_persist.lock_or_recover();
+        self.write()?;
+        *last_persist = Some(Instant::now());
+        Ok(())
+    }
+
+    fn write(&self) -> error::Result<()> {
+        self.persister.write("", "", NETWORK_GRAPH_KEY, &self.graph.encode())?;
+        let scorer = self.scorer.lock_or_recover().encode();
+        self.persister.write("", "", SCORER_KEY, &scorer)?;
+        log::debug!(target: "lampo", "network graph and scorer persisted");
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+//! Bookkeeping of the in-flight HTLCs.
+//!
+//! ldk tells us which HTLCs are still inside a channel, but not
+//! since when, so lampo remembers the first time that an HTLC
+//! was seen and forgets it as soon as it is removed from the
+//! channel.
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use lampo_common::ldk::ln::PaymentHash;
+use lampo_common::model::response::HtlcDirection;
+use lampo_common::time::unix_now;
+use lampo_common::types::ChannelId;
+
+/// Identify an HTLC without the htlc id, because an outbound
+/// HTLC get the id only when it is sent to the peer.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HtlcKey {
+    pub channel_id: ChannelId,
+    pub direction: HtlcDirection,
+    pub payment_hash: PaymentHash,
+    pub amount_msat: u64,
+    pub cltv_expiry: u32,
+}
+
+#[derive(Default)]
+pub struct LampoHtlcTracker {
+    first_seen: Mutex<HashMap<HtlcKey, u64>>,
+}
+
+impl LampoHtlcTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Track the HTLCs that are currently in flight, and return
+    /// for each of them the unix timestamp when it was seen the
+    /// first time. The HTLCs that are not in flight anymore are
+    /// forgotten.
+    pub fn track(&self, htlcs: &[HtlcKey]) -> Vec<u64> {
+        let now = unix_now();
+        let mut first_seen = self.first_seen.lock().unwrap();
+        first_seen.retain(|key, _| htlcs.contains(key));
+        htlcs
+            .iter()
+            .map(|key| *first_seen.entry(key.clone()).or_insert(now))
+            .collect()
+    }
+}
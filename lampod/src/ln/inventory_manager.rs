@@ -1,9 +1,11 @@
 //! Inventory Manager Implementation
 use std::sync::Arc;
+use std::time::Instant;
 
+use lampo_common::bitcoin::hashes::hex::ToHex;
 use lampo_common::error;
 use lampo_common::json;
-use lampo_common::model::response::NetworkInfo;
+use lampo_common::model::response::{ChannelsState, NetworkInfo, OnChainBalance};
 
 use super::{LampoChannelManager, LampoPeerManager};
 use crate::actions::InventoryHandler;
@@ -12,6 +14,7 @@ use crate::command;
 pub struct LampoInventoryManager {
     peer_manager: Arc<LampoPeerManager>,
     channel_manager: Arc<LampoChannelManager>,
+    started_at: Instant,
 }
 
 impl LampoInventoryManager {
@@ -22,8 +25,20 @@ impl LampoInventoryManager {
         Self {
             peer_manager,
             channel_manager,
+            started_at: Instant::now(),
         }
     }
+
+    /// The balances of the wallet, `None` while the wallet did not
+    /// finish the first sync.
+    fn onchain_balance(&self) -> Option<OnChainBalance> {
+        let wallet = &self.channel_manager.onchain.wallet_manager;
+        wallet.synced_height().ok().flatten()?;
+        Some(OnChainBalance {
+            confirmed_sat: wallet.get_onchain_balance().ok()?,
+            pending_sat: wallet.pending_incoming_balance().ok()?,
+        })
+    }
 }
 
 impl InventoryHandler for LampoInventoryManager {
@@ -37,30 +52,100 @@ impl InventoryHandler for LampoInventoryManager {
                 let alias = self.channel_manager.conf.alias.clone();
                 // we have to put "" in case of alias missing as cln provide us with a random alias.
                 let alias = alias.unwrap_or_default();
-                let (_, height) = self.channel_manager.onchain.backend.get_best_block()?;
-                let blockheight = height.unwrap_or_default();
-                let lampo_dir = self.channel_manager.conf.root_path.to_string();
-                // We provide a vector here as there may be other types of address in future like tor and ipv6.
-                let mut address_vec = Vec::new();
-                let address = self.channel_manager.conf.announce_addr.clone();
-                if let Some(addr) = address {
-                    let port = self.channel_manager.conf.port.clone();
-                    // For now we don't iterate as there is only one type of address.
-                    let address_info = NetworkInfo {
-                        address: addr,
-                        port,
-                    };
-                    address_vec.push(address_info);
+                // use the tip tracked by lampo, so getinfo does not wait
+                // on the backend.
+                let blockheight = match self.channel_manager.onchain.chain.tip() {
+                    Some((height, _)) => height,
+                    None => self
+                        .channel_manager
+                        .onchain
+                        .backend
+                        .get_best_block()
+                        .ok()
+                        .and_then(|(_, height)| height)
+                        .unwrap_or_default(),
+                };
+                let wallet_synced_height = self
+                    .channel_manager
+                    .onchain
+                    .wallet_manager
+                    .synced_height()
+                    .ok()
+                    .flatten();
+                let channels = self.channel_manager.manager().list_channels();
+                let mut channels_state = ChannelsState::default();
+                for channel in channels.iter() {
+                    match (channel.is_channel_ready, channel.is_usable) {
+                        (false, _) => channels_state.pending += 1,
+                        (true, true) => channels_state.active += 1,
+                        (true, false) => channels_state.inactive += 1,
+                    }
                 }
+                let lampo_dir = self.channel_manager.conf.root_path.to_string();
+                let address = self
+                    .peer_manager
+                    .announced_addrs()
+                    .iter()
+                    .filter_map(|addr| {
+                        let addr = addr.to_string();
+                        let (address, port) = addr.rsplit_once(':')?;
+                        Some(NetworkInfo {
+                            address: address.to_owned(),
+                            port: port.parse().ok()?,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let binding = self
+                    .peer_manager
+                    .listen_addrs()
+                    .into_iter()
+                    .map(|addr| NetworkInfo {
+                        address: addr.ip().to_string(),
+                        port: addr.port().into(),
+                    })
+                    .collect::<Vec<_>>();
+                let keys = self.channel_manager.onchain.wallet_manager.ldk_keys();
+                let graph_load = self.channel_manager.graph_load();
                 let getinfo = GetInfo {
-                    node_id: self.channel_manager.manager().get_our_node_id().to_string(),
+                    node_id: keys.node_id().to_string(),
                     peers: self.peer_manager.manager().list_peers().len(),
-                    channels: self.channel_manager.manager().list_channels().len(),
+                    channels: channels.len(),
                     chain,
                     alias,
+                    color: self.channel_manager.conf.rgb_color.to_hex(),
                     blockheight,
+                    wallet_synced_height,
+                    synced_to_chain: wallet_synced_height
+                        .is_some_and(|height| height >= blockheight),
+                    onchain: self.onchain_balance(),
+                    outbound_capacity_msat: channels
+                        .iter()
+                        .map(|channel| channel.outbound_capacity_msat)
+                        .sum(),
+                    inbound_capacity_msat: channels
+                        .iter()
+                        .map(|channel| channel.inbound_capacity_msat)
+                        .sum(),
+                    channels_state,
                     lampo_dir,
-                    address: address_vec,
+                    address,
+                    binding,
+                    rgs_last_sync_timestamp: self
+                        .channel_manager
+                        .graph()
+                        .get_last_rapid_gossip_sync_timestamp(),
+                    graph_loaded: graph_load.is_loaded(),
+                    nodes_loaded: graph_load.nodes_loaded(),
+                    last_announcement: self.peer_manager.last_announcement(),
+                    version: env!("CARGO_PKG_VERSION").to_owned(),
+                    uptime_secs: self.started_at.elapsed().as_secs(),
+                    keys_scheme: keys.scheme().map(|scheme| scheme.to_string()),
+                    shutdown_pubkey: keys.shutdown_pubkey().to_string(),
+                    fingerprints: keys
+                        .fingerprints()
+                        .iter()
+                        .map(|fingerprint| fingerprint.to_string())
+                        .collect(),
                 };
                 let getinfo = json::to_value(getinfo)?;
                 chan.send(getinfo)?;
@@ -0,0 +1,93 @@
+//! Store of the invoices generated by the node.
+//!
+//! ldk does not remember the invoices, only the inbound payments,
+//! so the label and the metadata given by the user are kept here
+//! next to the invoice. The store is persisted so the invoices can
+//! be looked up after a restart of the node.
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use lampo_common::error;
+use lampo_common::error::LampoError;
+use lampo_common::json;
+use lampo_common::ldk::util::persist::KVStore;
+use lampo_common::model::response::InvoiceRecord;
+use lampo_common::sync::MutexExt;
+
+use crate::persistence::LampoPersistence;
+
+const LAMPO_NAMESPACE: &str = "lampo";
+const INVOICES_KEY: &str = "invoices";
+
+pub struct LampoInvoiceStore {
+    persister: Arc<LampoPersistence>,
+    /// Invoices indexed by payment hash.
+    invoices: Mutex<HashMap<String, InvoiceRecord>>,
+}
+
+impl LampoInvoiceStore {
+    pub fn new(persister: Arc<LampoPersistence>) -> Self {
+        let invoices = Self::read(&persister).unwrap_or_else(|err| {
+            log::warn!(target: "lampo", "impossible read the invoices: {err}");
+            Vec::new()
+        });
+        let invoices = invoices
+            .into_iter()
+            .map(|invoice| (invoice.payment_hash.clone(), invoice))
+            .collect();
+        Self {
+            persister,
+            invoices: Mutex::new(invoices),
+        }
+    }
+
+    fn read(persister: &LampoPersistence) -> error::Result<Vec<InvoiceRecord>> {
+        match persister.read(LAMPO_NAMESPACE, "", INVOICES_KEY) {
+            Ok(buf) => Ok(json::from_slice::<Vec<InvoiceRecord>>(&buf)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Return true if an invoice has the `label`.
+    pub fn has_label(&self, label: &str) -> bool {
+        let invoices = self.invoices.lock_or_recover();
+        invoices
+            .values()
+            .any(|invoice| invoice.label.as_deref() == Some(label))
+    }
+
+    /// Store a new invoice, it fails if another invoice has the
+    /// same label.
+    pub fn insert(&self, invoice: InvoiceRecord) -> error::Result<()> {
+        let mut invoices = self.invoices.lock_or_recover();
+        if let Some(label) = &invoice.label {
+            if invoices
+                .values()
+                .any(|other| other.label.as_ref() == Some(label))
+            {
+                return Err(LampoError::DuplicateInvoiceLabel(label.clone()).into());
+            }
+        }
+        invoices.insert(invoice.payment_hash.clone(), invoice);
+        self.write(&invoices)
+    }
+
+    /// The invoices sorted by the time when they were generated.
+    pub fn list(&self) -> Vec<InvoiceRecord> {
+        let invoices = self.invoices.lock_or_recover();
+        let mut invoices = invoices.values().cloned().collect::<Vec<_>>();
+        invoices
+            .sort_by(|a, b| (a.created_at, &a.payment_hash).cmp(&(b.created_at, &b.payment_hash)));
+        invoices
+    }
+
+    fn write(&self, invoices: &HashMap<String, InvoiceRecord>) -> error::Result<()> {
+        let invoices = invoices.values().collect::<Vec<_>>();
+        let buf = json::to_vec(&invoices)?;
+        self.persister
+            .write(LAMPO_NAMESPACE, "", INVOICES_KEY, &buf)?;
+        Ok(())
+    }
+}
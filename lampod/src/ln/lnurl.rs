@@ -0,0 +1,208 @@
+//! LNURL-pay links (LUD-06) and lightning addresses (LUD-16).
+//!
+//! A link is resolved to the url of the service, that gives the
+//! parameters of the payment. Then the service is asked for an invoice
+//! of the amount, that is paid only if its description hash commits
+//! to the metadata of the service.
+use lampo_common::bitcoin::hashes::sha256::Hash as Sha256;
+use lampo_common::bitcoin::hashes::Hash;
+use lampo_common::conf::Network;
+use lampo_common::error::LampoError;
+use lampo_common::json;
+use lampo_common::ldk::invoice::bech32::{self, FromBase32};
+use lampo_common::ldk::invoice::{Bolt11Invoice, Bolt11InvoiceDescription};
+
+/// Seconds that the service has to answer.
+const LNURL_TIMEOUT_SECS: u64 = 30;
+
+/// The parameters of a LNURL-pay service.
+#[derive(Debug, Clone)]
+pub struct LnurlPayParams {
+    /// The url that gives the invoices.
+    pub callback: String,
+    pub min_sendable_msat: u64,
+    pub max_sendable_msat: u64,
+    /// The metadata of the payment, committed by the invoices.
+    pub metadata: String,
+    /// Maximum length of the comment, `0` when it is not allowed.
+    pub comment_allowed: usize,
+}
+
+/// The url of the service behind a bech32 LNURL or a lightning
+/// address, with or without the `lightning:` prefix.
+///
+/// The services must use https, except the onion ones and the ones
+/// of a regtest node.
+pub fn pay_url(lnurl: &str, network: Network) -> Result<String, LampoError> {
+    let lnurl = lnurl.trim();
+    let lnurl = lnurl
+        .strip_prefix("lightning:")
+        .or_else(|| lnurl.strip_prefix("LIGHTNING:"))
+        .unwrap_or(lnurl);
+    let url = if let Some((user, domain)) = lnurl.split_once('@') {
+        if user.is_empty() || domain.is_empty() || domain.contains('/') {
+            return Err(LampoError::InvalidParams(format!(
+                "invalid lightning address `{lnurl}`"
+            )));
+        }
+        let scheme = if domain.ends_with(".onion") || network == Network::Regtest {
+            "http"
+        } else {
+            "https"
+        };
+        format!(
+            "{scheme}://{domain}/.well-known/lnurlp/{}",
+            user.to_lowercase()
+        )
+    } else {
+        let invalid = |err: String| LampoError::InvalidParams(format!("invalid LNURL: {err}"));
+        let (hrp, data, _) = bech32::decode(lnurl).map_err(|err| invalid(format!("{err}")))?;
+        if hrp != "lnurl" {
+            return Err(invalid(format!("unexpected prefix `{hrp}`")));
+        }
+        let url = Vec::<u8>::from_base32(&data).map_err(|err| invalid(format!("{err}")))?;
+        String::from_utf8(url).map_err(|err| invalid(format!("{err}")))?
+    };
+    let host = url
+        .split_once("://")
+        .map(|(_, rest)| rest.split(['/', '?', ':']).next().unwrap_or_default())
+        .unwrap_or_default();
+    let secure = url.starts_with("https://")
+        || (url.starts_with("http://")
+            && (host.ends_with(".onion") || network == Network::Regtest));
+    if !secure {
+        return Err(LampoError::InvalidParams(format!(
+            "the LNURL service `{url}` must use https"
+        )));
+    }
+    Ok(url)
+}
+
+/// Fetch the parameters of the service at `url`.
+pub fn fetch_params(url: &str) -> Result<LnurlPayParams, LampoError> {
+    let params = get_json(url)?;
+    if params["tag"] != "payRequest" {
+        return Err(LampoError::PaymentFailure(format!(
+            "`{url}` is not a LNURL-pay service, its tag is `{}`",
+            params["tag"]
+        )));
+    }
+    let malformed = |field: &str| {
+        LampoError::PaymentFailure(format!("the LNURL service sent an invalid `{field}`"))
+    };
+    let callback = params["callback"]
+        .as_str()
+        .ok_or_else(|| malformed("callback"))?;
+    let min_sendable_msat = params["minSendable"]
+        .as_u64()
+        .ok_or_else(|| malformed("minSendable"))?;
+    let max_sendable_msat = params["maxSendable"]
+        .as_u64()
+        .ok_or_else(|| malformed("maxSendable"))?;
+    if min_sendable_msat > max_sendable_msat {
+        return Err(malformed("minSendable"));
+    }
+    let metadata = params["metadata"]
+        .as_str()
+        .ok_or_else(|| malformed("metadata"))?;
+    let comment_allowed = match params.get("commentAllowed") {
+        None | Some(json::Value::Null) => 0,
+        Some(comment_allowed) => comment_allowed
+            .as_u64()
+            .ok_or_else(|| malformed("commentAllowed"))? as usize,
+    };
+    Ok(LnurlPayParams {
+        callback: callback.to_owned(),
+        min_sendable_msat,
+        max_sendable_msat,
+        metadata: metadata.to_owned(),
+        comment_allowed,
+    })
+}
+
+/// Ask the service for an invoice of `amount_msat` with the `comment`,
+/// and check that the invoice is the one described by `params`.
+pub fn fetch_invoice(
+    params: &LnurlPayParams,
+    amount_msat: u64,
+    comment: Option<&str>,
+) -> Result<Bolt11Invoice, LampoError> {
+    if amount_msat < params.min_sendable_msat || amount_msat > params.max_sendable_msat {
+        return Err(LampoError::InvalidParams(format!(
+            "the amount must be between {} and {} msat",
+            params.min_sendable_msat, params.max_sendable_msat
+        )));
+    }
+    let mut url = params.callback.clone();
+    url.push(if url.contains('?') { '&' } else { '?' });
+    url.push_str(&format!("amount={amount_msat}"));
+    if let Some(comment) = comment.filter(|comment| !comment.is_empty()) {
+        let len = comment.chars().count();
+        if len > params.comment_allowed {
+            return Err(LampoError::InvalidParams(format!(
+                "the comment is {len} characters long, the service allows up to {}",
+                params.comment_allowed
+            )));
+        }
+        url.push_str(&format!("&comment={}", percent_encode(comment)));
+    }
+
+    let response = get_json(&url)?;
+    let invoice = response["pr"].as_str().ok_or_else(|| {
+        LampoError::PaymentFailure("the LNURL service did not send an invoice".to_owned())
+    })?;
+    let invoice = invoice
+        .parse::<Bolt11Invoice>()
+        .map_err(|err| LampoError::InvalidInvoice(format!("{err}")))?;
+    let metadata_hash = Sha256::hash(params.metadata.as_bytes());
+    match invoice.description() {
+        Bolt11InvoiceDescription::Hash(hash) if hash.0 == metadata_hash => {}
+        _ => {
+            return Err(LampoError::InvalidInvoice(
+                "the description hash of the invoice does not commit to the LNURL metadata"
+                    .to_owned(),
+            ))
+        }
+    }
+    if invoice.amount_milli_satoshis() != Some(amount_msat) {
+        return Err(LampoError::InvalidInvoice(format!(
+            "the invoice is for {:?} msat instead of {amount_msat} msat",
+            invoice.amount_milli_satoshis()
+        )));
+    }
+    Ok(invoice)
+}
+
+/// Get the JSON at `url`, failing when the service answers with an error.
+fn get_json(url: &str) -> Result<json::Value, LampoError> {
+    let failure = |err: String| LampoError::PaymentFailure(format!("LNURL service `{url}`: {err}"));
+    let response = minreq::get(url)
+        .with_timeout(LNURL_TIMEOUT_SECS)
+        .send()
+        .map_err(|err| failure(format!("{err}")))?;
+    let body: json::Value = json::from_slice(response.as_bytes())
+        .map_err(|_| failure(format!("unexpected answer `{}`", response.status_code)))?;
+    if body["status"] == "ERROR" {
+        let reason = body["reason"].as_str().unwrap_or("unknown error");
+        return Err(failure(reason.to_owned()));
+    }
+    if response.status_code != 200 {
+        return Err(failure(format!(
+            "unexpected answer `{}`",
+            response.status_code
+        )));
+    }
+    Ok(body)
+}
+
+pub(super) fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{byte:02X}"),
+        })
+        .collect()
+}
@@ -1,13 +1,37 @@
 //! Lampo Channel Manager
+mod autofees;
+mod bip21;
+mod bump_manager;
+mod channel_history;
 mod channel_manager;
+mod close_queue;
+mod gossip_query;
+mod graph_persister;
+mod htlc_tracker;
 mod inventory_manager;
+mod invoice_store;
+mod lnurl;
 mod offchain_manager;
+mod peer_book;
+mod peer_filter;
 mod peer_manager;
+mod ping;
+mod rapid_gossip;
+mod sweeper;
 
 pub mod events;
+pub mod onion_message;
 pub mod peer_event;
+pub(crate) mod tor;
 
-pub use channel_manager::LampoChannelManager;
+pub use autofees::LampoAutofees;
+pub use bump_manager::LampoBumpManager;
+pub use channel_manager::{LampoChannelManager, LampoGraph};
+pub use gossip_query::{list_gossip_channels, list_nodes};
+pub use graph_persister::{GraphLoad, LampoGraphPersister, GRAPH_PERSIST_INTERVAL};
 pub use inventory_manager::LampoInventoryManager;
 pub use offchain_manager::OffchainManager;
 pub use peer_manager::LampoPeerManager;
+pub use ping::{LampoPing, PING_INTERVAL};
+pub use rapid_gossip::{LampoRapidGossip, LampoRapidGossipSync};
+pub use sweeper::LampoSweeper;
@@ -10,35 +10,123 @@
 //! with the network graph. But this is not so clear yet.
 //!
 //! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::str::FromStr;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use lampo_common::bitcoin::address::Payload;
+use lampo_common::bitcoin::hashes::hex::ToHex;
 use lampo_common::bitcoin::hashes::sha256::Hash as Sha256;
 use lampo_common::bitcoin::hashes::Hash;
 use lampo_common::bitcoin::secp256k1::PublicKey as pubkey;
+use lampo_common::bitcoin::Address;
+use lampo_common::chan;
 use lampo_common::conf::LampoConf;
 use lampo_common::error;
-use lampo_common::keys::LampoKeysManager;
+use lampo_common::error::LampoError;
+use lampo_common::event::ln::LightningEvent;
+use lampo_common::event::Event;
+use lampo_common::keys::{self, LampoKeysManager};
 use lampo_common::ldk;
-use lampo_common::ldk::ln::channelmanager::Retry;
+use lampo_common::ldk::invoice::bech32::ToBase32;
+use lampo_common::ldk::invoice::Fallback;
+use lampo_common::ldk::ln::channelmanager::RecentPaymentDetails;
+use lampo_common::ldk::ln::channelmanager::MIN_FINAL_CLTV_EXPIRY_DELTA;
 use lampo_common::ldk::ln::channelmanager::{PaymentId, RecipientOnionFields};
+use lampo_common::ldk::ln::channelmanager::{Retry, RetryableSendFailure};
+use lampo_common::ldk::ln::msgs::SocketAddress;
 use lampo_common::ldk::ln::{PaymentHash, PaymentPreimage};
 use lampo_common::ldk::offers::offer::Amount;
 use lampo_common::ldk::offers::offer::Offer;
+use lampo_common::ldk::offers::parse::Bolt12SemanticError;
+use lampo_common::ldk::routing::gossip::{NodeId, RoutingFees};
+use lampo_common::ldk::routing::router::{Path, RouteHint, RouteHintHop};
 use lampo_common::ldk::routing::router::{PaymentParameters, RouteParameters};
-use lampo_common::ldk::sign::EntropySource;
+use lampo_common::ldk::sign::{EntropySource, NodeSigner, Recipient};
+use lampo_common::model::response::Bip21Uri;
+use lampo_common::model::response::Invoice;
+use lampo_common::model::response::InvoiceRecord;
+use lampo_common::model::response::PaymentState;
+use lampo_common::model::response::UnifiedReceive;
+use lampo_common::model::response::{Channel, HoldInvoice, HoldInvoiceState, InboundPaymentState};
+use lampo_common::model::response::{CheckMessage, SignMessage};
+use lampo_common::model::response::{PayResult, PaymentPath, PaymentRoute, PaymentStatus};
+use lampo_common::model::response::{PayStatus, SentPayment};
+use lampo_common::model::{Msat, Sat};
+use lampo_common::time;
+use tokio::runtime::Handle;
 
-use super::LampoChannelManager;
+use super::bip21;
+use super::events::PeerEvents;
+use super::invoice_store::LampoInvoiceStore;
+use super::lnurl;
+use super::{LampoChannelManager, LampoPeerManager};
 use crate::chain::LampoChainManager;
+use crate::persistence::LampoPersistence;
 use crate::utils::logger::LampoLogger;
 
+/// The maximum expiry of an invoice, ldk takes the expiry as a u32 of
+/// seconds, so an invoice with it lasts for more than a century.
+pub const MAX_INVOICE_EXPIRY_SECS: u32 = u32::MAX;
+
+/// The CLTV expiry delta required by the recipient of a keysend.
+const KEYSEND_FINAL_CLTV_EXPIRY_DELTA: u32 = 40;
+
+/// How long a payment waits for the network graph that is still
+/// loading, before failing with `LampoError::GraphLoading`.
+const GRAPH_LOAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The description of an invoice, or the hash of a longer one.
+enum InvoiceDescription<'a> {
+    Direct(&'a str),
+    Hash(Sha256),
+}
+
+/// Limit the total CLTV expiry delta of the routes used by a payment,
+/// the limit can not be less than the CLTV expiry delta required by
+/// the recipient.
+fn limit_total_cltv(
+    route: &mut RouteParameters,
+    max_total_cltv_expiry_delta: Option<u32>,
+    final_cltv_expiry_delta: u32,
+) -> Result<(), LampoError> {
+    let Some(max_total_cltv_expiry_delta) = max_total_cltv_expiry_delta else {
+        return Ok(());
+    };
+    if max_total_cltv_expiry_delta < final_cltv_expiry_delta {
+        return Err(LampoError::InvalidParams(format!(
+            "max total CLTV expiry delta `{max_total_cltv_expiry_delta}` is below the final CLTV expiry delta `{final_cltv_expiry_delta}`"
+        )));
+    }
+    route.payment_params.max_total_cltv_expiry_delta = max_total_cltv_expiry_delta;
+    Ok(())
+}
+
 pub struct OffchainManager {
     channel_manager: Arc<LampoChannelManager>,
     keys_manager: Arc<LampoKeysManager>,
+    #[allow(dead_code)]
     logger: Arc<LampoLogger>,
     lampo_conf: Arc<LampoConf>,
     chain_manager: Arc<LampoChainManager>,
+    /// Invoices where the preimage is not known by lampo,
+    /// so the payment must be held until the user settle it.
+    hold_invoices: Mutex<HashMap<PaymentHash, HoldInvoice>>,
+    /// The invoices generated by `generate_invoice`, with their
+    /// label and metadata.
+    invoices: LampoInvoiceStore,
+    /// Payments received by this node.
+    inbound_payments: Mutex<HashMap<PaymentHash, PaymentStatus>>,
+    /// Paths used by the successful outbound payments.
+    payment_routes: Mutex<HashMap<PaymentHash, PaymentRoute>>,
+    /// The cancellation flags of the outbound payments in flight.
+    pending_payments: Mutex<HashMap<PaymentId, Arc<AtomicBool>>>,
+    /// The peer manager with the runtime of its connections, known
+    /// after the peer manager is initialized.
+    peer_manager: Mutex<Option<(Arc<LampoPeerManager>, Handle)>>,
 }
 
 impl OffchainManager {
@@ -49,6 +137,7 @@ impl OffchainManager {
         logger: Arc<LampoLogger>,
         lampo_conf: Arc<LampoConf>,
         chain_manager: Arc<LampoChainManager>,
+        persister: Arc<LampoPersistence>,
     ) -> error::Result<Self> {
         Ok(Self {
             channel_manager,
@@ -56,42 +145,638 @@ impl OffchainManager {
             logger,
             lampo_conf,
             chain_manager,
+            hold_invoices: Mutex::new(HashMap::new()),
+            invoices: LampoInvoiceStore::new(persister),
+            inbound_payments: Mutex::new(HashMap::new()),
+            payment_routes: Mutex::new(HashMap::new()),
+            pending_payments: Mutex::new(HashMap::new()),
+            peer_manager: Mutex::new(None),
+        })
+    }
+
+    pub fn set_peer_manager(&self, peer_manager: Arc<LampoPeerManager>, rt: Handle) {
+        *self.peer_manager.lock().unwrap() = Some((peer_manager, rt));
+    }
+
+    /// Connect to the peer `node_id` at `addr`, and return once the
+    /// handshake is completed or it timed out. Connecting to a peer that
+    /// is already connected does nothing.
+    pub fn connect_peer(&self, node_id: pubkey, addr: SocketAddr) -> error::Result<()> {
+        let Some((peer_manager, rt)) = self.peer_manager.lock().unwrap().clone() else {
+            error::bail!("the peer manager is not initialized");
+        };
+        if peer_manager.is_connected_with(node_id) {
+            return Ok(());
+        }
+        rt.block_on(peer_manager.connect(node_id, SocketAddress::from(addr)))
+            .map_err(|err| LampoError::PeerFailure(format!("{err}")))?;
+        log::info!(target: "offchain", "connected with the peer `{node_id}` at `{addr}`");
+        Ok(())
+    }
+
+    /// The node id of this node, that is the public key used by the
+    /// peers to connect and by the invoices.
+    pub fn node_id(&self) -> pubkey {
+        self.chain_manager.wallet_manager.ldk_keys().node_id()
+    }
+
+    /// The open channels, with the peer, the capacity and the balances,
+    /// the ones that can be used to pay now are `usable`.
+    pub fn list_channels(&self) -> Vec<Channel> {
+        self.channel_manager.list_channel().channels
+    }
+
+    /// Sign `message` with the key of the node, so the others can
+    /// check that we control the node with `check_message`.
+    pub fn sign_message(&self, message: &str) -> error::Result<SignMessage> {
+        let zbase = self.keys_manager.sign_message(message.as_bytes())?;
+        Ok(SignMessage {
+            zbase,
+            pubkey: self.node_id().to_string(),
+        })
+    }
+
+    /// Recover the key that signed `message`, the signature is verified
+    /// if the key is `pubkey` or, without `pubkey`, if the signer is
+    /// our node or a node of our network graph (as CLN does).
+    pub fn check_message(
+        &self,
+        message: &str,
+        zbase: &str,
+        pubkey: Option<pubkey>,
+    ) -> error::Result<CheckMessage> {
+        let signer = keys::recover_message_signer(message.as_bytes(), zbase)?;
+        let graph = self.channel_manager.graph();
+        let graph = graph.read_only();
+        let node = graph.node(&NodeId::from_pubkey(&signer));
+        let verified = match pubkey {
+            Some(pubkey) => pubkey == signer,
+            None => node.is_some() || signer == self.node_id(),
+        };
+        Ok(CheckMessage {
+            verified,
+            pubkey: signer.to_string(),
+            alias: node
+                .and_then(|node| node.announcement_info.as_ref())
+                .map(|info| info.alias.to_string()),
         })
     }
 
     /// Generate an invoice with a specific amount and a specific
-    /// description.
+    /// description, without `expiring_in` the invoice has the maximum
+    /// expiry (see `MAX_INVOICE_EXPIRY_SECS`).
+    ///
+    /// The invoice is stored with its unique `label` and the
+    /// `metadata`, while the `fallback` address is written inside
+    /// the invoice.
     pub fn generate_invoice(
         &self,
         amount_msat: Option<u64>,
         description: &str,
-        expiring_in: u32,
+        expiring_in: Option<u32>,
+        label: Option<&str>,
+        fallback: Option<&str>,
+        metadata: Option<&str>,
     ) -> error::Result<ldk::invoice::Bolt11Invoice> {
-        let currency = ldk::invoice::Currency::try_from(self.lampo_conf.network)?;
-        let invoice = ldk::invoice::utils::create_invoice_from_channelmanager(
-            &self.channel_manager.manager(),
-            self.keys_manager.clone(),
-            self.logger.clone(),
-            currency,
+        if let Some(label) = label.filter(|label| self.invoices.has_label(label)) {
+            return Err(LampoError::DuplicateInvoiceLabel(label.to_owned()).into());
+        }
+        let expiring_in = expiring_in.unwrap_or(MAX_INVOICE_EXPIRY_SECS);
+        let description = InvoiceDescription::Direct(description);
+        let invoice = self.create_invoice(
             amount_msat,
-            description.to_string(),
+            description,
             expiring_in,
             None,
+            fallback.map(|address| self.fallback(address)).transpose()?,
+        )?;
+        self.invoices.insert(InvoiceRecord {
+            label: label.map(str::to_owned),
+            bolt11: invoice.to_string(),
+            payment_hash: invoice.payment_hash().to_string(),
+            amount_msat: invoice.amount_milli_satoshis(),
+            fallback: fallback.map(str::to_owned),
+            metadata: metadata.map(str::to_owned),
+            created_at: invoice.duration_since_epoch().as_secs(),
+            expiry_unix: Invoice::from(&invoice).expiry_unix,
+            state: InboundPaymentState::Unpaid,
+        })?;
+        Ok(invoice)
+    }
+
+    /// The fallback of an invoice that pays to the on chain `address`.
+    fn fallback(&self, address: &str) -> Result<Fallback, LampoError> {
+        let invalid =
+            |err: String| LampoError::InvalidParams(format!("invalid fallback `{address}`: {err}"));
+        let address = Address::from_str(address)
+            .map_err(|err| invalid(format!("{err}")))?
+            .require_network(self.lampo_conf.network)
+            .map_err(|err| invalid(format!("{err}")))?;
+        let fallback = match address.payload {
+            Payload::PubkeyHash(hash) => Fallback::PubKeyHash(hash),
+            Payload::ScriptHash(hash) => Fallback::ScriptHash(hash),
+            Payload::WitnessProgram(program) => Fallback::SegWitProgram {
+                version: program.version(),
+                program: program.program().as_bytes().to_vec(),
+            },
+            _ => return Err(invalid("unsupported address type".to_owned())),
+        };
+        Ok(fallback)
+    }
+
+    /// The invoices generated by `generate_invoice`, optionally only
+    /// the one with the `label` or the `payment_hash`.
+    pub fn list_invoices(
+        &self,
+        label: Option<&str>,
+        payment_hash: Option<&str>,
+    ) -> Vec<InvoiceRecord> {
+        let states = self
+            .inbound_payments
+            .lock()
+            .unwrap()
+            .values()
+            .map(|payment| (payment.payment_hash.clone(), payment.state))
+            .collect::<HashMap<_, _>>();
+        self.invoices
+            .list()
+            .into_iter()
+            .filter(|invoice| label.map_or(true, |label| invoice.label.as_deref() == Some(label)))
+            .filter(|invoice| payment_hash.map_or(true, |hash| invoice.payment_hash == hash))
+            .map(|mut invoice| {
+                if let Some(state) = states.get(&invoice.payment_hash) {
+                    invoice.state = *state;
+                }
+                invoice
+            })
+            .collect()
+    }
+
+    /// Generate an invoice that commits to the `metadata` with its
+    /// description hash, as the LNURL-pay services do.
+    pub fn generate_invoice_for_metadata(
+        &self,
+        amount_msat: u64,
+        metadata: &str,
+        expiring_in: Option<u32>,
+    ) -> Result<ldk::invoice::Bolt11Invoice, LampoError> {
+        let expiring_in = expiring_in.unwrap_or(MAX_INVOICE_EXPIRY_SECS);
+        let description = InvoiceDescription::Hash(Sha256::hash(metadata.as_bytes()));
+        self.create_invoice(Some(amount_msat), description, expiring_in, None, None)
+    }
+
+    /// Generate an invoice that expire at the unix timestamp `expiry_unix`
+    /// (in seconds), the relative expiry is computed at generation time.
+    pub fn generate_invoice_expiring_at(
+        &self,
+        amount_msat: Option<u64>,
+        description: &str,
+        expiry_unix: u64,
+        label: Option<&str>,
+        fallback: Option<&str>,
+        metadata: Option<&str>,
+    ) -> error::Result<ldk::invoice::Bolt11Invoice> {
+        let expiring_in = time::expiry_delta(expiry_unix, time::unix_now())?;
+        self.generate_invoice(
+            amount_msat,
+            description,
+            Some(expiring_in),
+            label,
+            fallback,
+            metadata,
         )
-        .map_err(|err| error::anyhow!(err))?;
+    }
+
+    /// A BIP21 URI to receive `amount_sat` on a fresh address, with
+    /// the `label` if any.
+    ///
+    /// When the amount is specified and the node has a usable channel,
+    /// the URI carries an invoice of the same amount too (BIP21 unified).
+    pub fn get_bip21_uri(
+        &self,
+        amount_sat: Option<u64>,
+        label: Option<String>,
+    ) -> error::Result<Bip21Uri> {
+        let address = self
+            .chain_manager
+            .wallet_manager
+            .get_onchain_address()?
+            .address;
+        let has_channels = !self
+            .channel_manager
+            .manager()
+            .list_usable_channels()
+            .is_empty();
+        let invoice = match amount_sat {
+            Some(amount_sat) if has_channels => {
+                let amount_msat = Sat::from_sat(amount_sat).to_msat()?;
+                let description = label.as_deref().unwrap_or_default();
+                let invoice = self.generate_invoice(
+                    Some(amount_msat.msat()),
+                    description,
+                    Some(bip21::INVOICE_EXPIRY_SECS),
+                    None,
+                    None,
+                    None,
+                )?;
+                Some(invoice.to_string())
+            }
+            _ => None,
+        };
+        Ok(Bip21Uri {
+            uri: bip21::uri(&address, amount_sat, label.as_deref(), invoice.as_deref()),
+            address,
+            amount_sat,
+            label,
+            bolt11: invoice,
+        })
+    }
+
+    /// A fresh address and an invoice of `amount_msat`, bundled inside
+    /// a BIP21 unified URI, so the payer chooses how to pay.
+    ///
+    /// The amount of the URI is rounded up to the satoshi, because an
+    /// on chain payment can not carry the millisatoshis.
+    pub fn unified_receive(
+        &self,
+        amount_msat: u64,
+        description: &str,
+        expiring_in: Option<u32>,
+    ) -> error::Result<UnifiedReceive> {
+        if amount_msat == 0 {
+            return Err(LampoError::InvalidParams(
+                "the amount of a unified receive can not be zero".to_owned(),
+            )
+            .into());
+        }
+        let address = self
+            .chain_manager
+            .wallet_manager
+            .get_onchain_address()?
+            .address;
+        let expiring_in = expiring_in.unwrap_or(bip21::INVOICE_EXPIRY_SECS);
+        let invoice = self.generate_invoice(
+            Some(amount_msat),
+            description,
+            Some(expiring_in),
+            None,
+            None,
+            None,
+        )?;
+        let invoice = Invoice::from(&invoice);
+        let amount_sat = Msat::from_msat(amount_msat).to_sat_ceil();
+        Ok(UnifiedReceive {
+            uri: bip21::uri(
+                &address,
+                Some(amount_sat.sat()),
+                None,
+                Some(&invoice.bolt11),
+            ),
+            address,
+            amount_msat,
+            bolt11: invoice.bolt11,
+            payment_hash: invoice.payment_hash,
+            expiry_unix: invoice.expiry_unix,
+        })
+    }
+
+    /// Build and sign a bolt11 invoice, if the `payment_hash` is not
+    /// specified ldk will generate a new one for us.
+    ///
+    /// The `fallback` is written inside the `f` field of the invoice.
+    ///
+    /// The route hints are built by us (and not by ldk) because the
+    /// channels where the user disabled the forwarding must be skipped.
+    fn create_invoice(
+        &self,
+        amount_msat: Option<u64>,
+        description: InvoiceDescription<'_>,
+        expiring_in: u32,
+        payment_hash: Option<PaymentHash>,
+        fallback: Option<Fallback>,
+    ) -> Result<ldk::invoice::Bolt11Invoice, LampoError> {
+        let manager = self.channel_manager.manager();
+        let inbound_failure =
+            |_| LampoError::PaymentFailure("impossible create the inbound payment".to_owned());
+        let (payment_hash, payment_secret) = match payment_hash {
+            Some(payment_hash) => {
+                let secret = manager
+                    .create_inbound_payment_for_hash(payment_hash, amount_msat, expiring_in, None)
+                    .map_err(inbound_failure)?;
+                (payment_hash, secret)
+            }
+            None => manager
+                .create_inbound_payment(amount_msat, expiring_in, None)
+                .map_err(inbound_failure)?,
+        };
+        let currency = ldk::invoice::Currency::try_from(self.lampo_conf.network)
+            .map_err(|err| LampoError::PaymentFailure(format!("{err}")))?;
+        let builder = ldk::invoice::InvoiceBuilder::new(currency);
+        let builder = match description {
+            InvoiceDescription::Direct(description) => builder.description(description.to_owned()),
+            InvoiceDescription::Hash(hash) => builder.description_hash(hash),
+        };
+        let mut builder = builder
+            .payment_hash(Sha256::from_byte_array(payment_hash.0))
+            .payment_secret(payment_secret)
+            .current_timestamp()
+            .min_final_cltv_expiry_delta(MIN_FINAL_CLTV_EXPIRY_DELTA.into())
+            .expiry_time(Duration::from_secs(expiring_in.into()))
+            .basic_mpp();
+        if let Some(amount_msat) = amount_msat {
+            builder = builder.amount_milli_satoshis(amount_msat);
+        }
+        if let Some(fallback) = fallback {
+            builder = builder.fallback(fallback);
+        }
+        for hint in self.route_hints() {
+            builder = builder.private_route(hint);
+        }
+        let invoice = builder
+            .build_raw()
+            .map_err(|err| LampoError::InvalidParams(format!("{err}")))?;
+        let hrp = invoice.hrp.to_string();
+        let data = invoice.data.to_base32();
+        let invoice = invoice
+            .sign(|_| {
+                self.keys_manager
+                    .sign_invoice(hrp.as_bytes(), &data, Recipient::Node)
+            })
+            .map_err(|_| LampoError::PaymentFailure("impossible sign the invoice".to_owned()))?;
+        let invoice = ldk::invoice::Bolt11Invoice::from_signed(invoice)
+            .map_err(|err| LampoError::PaymentFailure(format!("{err}")))?;
         Ok(invoice)
     }
 
+    /// Build the route hints for our private channels, if we have
+    /// a public channel the payer can find us inside the graph.
+    fn route_hints(&self) -> Vec<RouteHint> {
+        let channels = self.channel_manager.manager().list_usable_channels();
+        if channels.iter().any(|channel| channel.is_public) {
+            return Vec::new();
+        }
+        channels
+            .iter()
+            .filter(|channel| self.channel_manager.is_channel_enabled(&channel.channel_id))
+            .filter_map(|channel| {
+                let forwarding_info = channel.counterparty.forwarding_info.as_ref()?;
+                let hop = RouteHintHop {
+                    src_node_id: channel.counterparty.node_id,
+                    // prefer the alias, so we do not leak the funding outpoint
+                    short_channel_id: channel
+                        .inbound_scid_alias
+                        .or(channel.short_channel_id)?,
+                    fees: RoutingFees {
+                        base_msat: forwarding_info.fee_base_msat,
+                        proportional_millionths: forwarding_info.fee_proportional_millionths,
+                    },
+                    cltv_expiry_delta: forwarding_info.cltv_expiry_delta,
+                    htlc_minimum_msat: channel.inbound_htlc_minimum_msat,
+                    htlc_maximum_msat: channel.inbound_htlc_maximum_msat,
+                };
+                Some(RouteHint(vec![hop]))
+            })
+            .collect()
+    }
+
+    /// Generate an invoice that commit to a payment hash provided by
+    /// the user.
+    ///
+    /// The incoming HTLCs are not claimed by lampo, but they are held
+    /// until the invoice is settled with `settle_hold_invoice` or
+    /// canceled with `cancel_hold_invoice`.
+    pub fn generate_hold_invoice(
+        &self,
+        payment_hash: PaymentHash,
+        amount_msat: Option<u64>,
+        description: &str,
+        expiring_in: u32,
+    ) -> error::Result<HoldInvoice> {
+        let mut hold_invoices = self.hold_invoices.lock().unwrap();
+        if hold_invoices.contains_key(&payment_hash) {
+            error::bail!("an invoice for the payment hash `{payment_hash}` already exist");
+        }
+        let description = InvoiceDescription::Direct(description);
+        let invoice = self.create_invoice(
+            amount_msat,
+            description,
+            expiring_in,
+            Some(payment_hash),
+            None,
+        )?;
+        let hold_invoice = HoldInvoice {
+            bolt11: invoice.to_string(),
+            payment_hash: payment_hash.to_string(),
+            state: HoldInvoiceState::Open,
+            amount_received_msat: None,
+        };
+        hold_invoices.insert(payment_hash, hold_invoice.clone());
+        Ok(hold_invoice)
+    }
+
+    /// Mark the hold invoice as accepted when the payment is claimable.
+    ///
+    /// Return false if the payment hash is not an hold invoice, so the
+    /// caller should claim the payment as usual.
+    pub fn accept_hold_invoice(&self, payment_hash: &PaymentHash, amount_msat: Msat) -> bool {
+        let mut hold_invoices = self.hold_invoices.lock().unwrap();
+        let Some(invoice) = hold_invoices.get_mut(payment_hash) else {
+            return false;
+        };
+        if invoice.state == HoldInvoiceState::Canceled {
+            log::info!(target: "offchain", "failing payment for the canceled invoice `{payment_hash}`");
+            self.channel_manager
+                .manager()
+                .fail_htlc_backwards(payment_hash);
+            return true;
+        }
+        log::info!(target: "offchain", "holding payment of `{amount_msat}` for `{payment_hash}`");
+        invoice.state = HoldInvoiceState::Accepted;
+        invoice.amount_received_msat = Some(amount_msat);
+        true
+    }
+
+    /// Claim the HTLCs held for the hold invoice with the preimage
+    /// provided by the user.
+    pub fn settle_hold_invoice(&self, preimage: PaymentPreimage) -> error::Result<HoldInvoice> {
+        let payment_hash = PaymentHash(Sha256::hash(&preimage.0).to_byte_array());
+        let mut hold_invoices = self.hold_invoices.lock().unwrap();
+        let Some(invoice) = hold_invoices.get_mut(&payment_hash) else {
+            error::bail!("hold invoice with payment hash `{payment_hash}` not found");
+        };
+        if invoice.state != HoldInvoiceState::Accepted {
+            error::bail!(
+                "hold invoice `{payment_hash}` is in state `{:?}`, it must be `Accepted` to be settled",
+                invoice.state
+            );
+        }
+        self.channel_manager.manager().claim_funds(preimage);
+        invoice.state = HoldInvoiceState::Settled;
+        Ok(invoice.clone())
+    }
+
+    /// Fail back the HTLCs held for the hold invoice, and stop
+    /// accepting payments for it.
+    pub fn cancel_hold_invoice(&self, payment_hash: PaymentHash) -> error::Result<HoldInvoice> {
+        let mut hold_invoices = self.hold_invoices.lock().unwrap();
+        let Some(invoice) = hold_invoices.get_mut(&payment_hash) else {
+            error::bail!("hold invoice with payment hash `{payment_hash}` not found");
+        };
+        if invoice.state == HoldInvoiceState::Settled {
+            error::bail!("hold invoice `{payment_hash}` is already settled");
+        }
+        self.channel_manager
+            .manager()
+            .fail_htlc_backwards(&payment_hash);
+        invoice.state = HoldInvoiceState::Canceled;
+        Ok(invoice.clone())
+    }
+
+    /// Record the state of an inbound payment.
+    pub fn record_inbound_payment(
+        &self,
+        payment_hash: PaymentHash,
+        state: InboundPaymentState,
+        amount_msat: Msat,
+    ) {
+        let mut payments = self.inbound_payments.lock().unwrap();
+        let payment = payments.entry(payment_hash).or_insert_with(|| PaymentStatus {
+            payment_hash: payment_hash.to_string(),
+            state,
+            amount_received_msat: None,
+        });
+        payment.state = state;
+        payment.amount_received_msat = Some(amount_msat);
+    }
+
+    /// Check if the invoice generated by this node was paid.
+    pub fn is_invoice_paid(&self, invoice_str: &str) -> error::Result<PaymentStatus> {
+        let invoice = self.decode_invoice(invoice_str)?;
+        if invoice.recover_payee_pub_key() != self.node_id() {
+            error::bail!("invoice `{invoice_str}` was not generated by this node");
+        }
+        let payment_hash = PaymentHash(invoice.payment_hash().to_byte_array());
+        let payments = self.inbound_payments.lock().unwrap();
+        let status = payments
+            .get(&payment_hash)
+            .cloned()
+            .unwrap_or_else(|| PaymentStatus {
+                payment_hash: payment_hash.to_string(),
+                state: InboundPaymentState::Unpaid,
+                amount_received_msat: None,
+            });
+        Ok(status)
+    }
+
+    /// Record a successful path of an outbound payment.
+    pub fn record_payment_path(&self, payment_hash: PaymentHash, path: &Path) {
+        let mut routes = self.payment_routes.lock().unwrap();
+        let route = routes.entry(payment_hash).or_insert_with(|| PaymentRoute {
+            payment_hash: payment_hash.to_string(),
+            paths: Vec::new(),
+            total_fee_msat: 0,
+        });
+        let path = PaymentPath::from(path);
+        route.total_fee_msat += path.fee_msat;
+        route.paths.push(path);
+    }
+
+    /// Return the route taken by a successful payment.
+    pub fn get_payment_route(&self, payment_hash: &PaymentHash) -> error::Result<PaymentRoute> {
+        let routes = self.payment_routes.lock().unwrap();
+        let Some(route) = routes.get(payment_hash) else {
+            error::bail!("no successful payment found with payment hash `{payment_hash}`");
+        };
+        Ok(route.clone())
+    }
+
+    /// List the outbound payments, only the ones with `payment_hash`
+    /// when it is given.
+    ///
+    /// The complete payments are the ones with a route recorded, the
+    /// pending and the failed ones are known only while ldk keeps them
+    /// between its recent payments.
+    pub fn list_payments(&self, payment_hash: Option<&PaymentHash>) -> Vec<SentPayment> {
+        let routes = self.payment_routes.lock().unwrap();
+        let mut pays = routes
+            .iter()
+            .map(|(hash, route)| {
+                let amount_msat = route.paths.iter().map(|path| path.amount_msat).sum::<u64>();
+                (
+                    Some(*hash),
+                    SentPayment {
+                        payment_hash: Some(hash.to_string()),
+                        status: PayStatus::Complete,
+                        amount_msat: Some(amount_msat),
+                        amount_sent_msat: Some(amount_msat + route.total_fee_msat),
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        for payment in self.channel_manager.manager().list_recent_payments() {
+            let (hash, status, amount_msat) = match payment {
+                RecentPaymentDetails::AwaitingInvoice { .. } => (None, PayStatus::Pending, None),
+                RecentPaymentDetails::Pending {
+                    payment_hash,
+                    total_msat,
+                    ..
+                } => (Some(payment_hash), PayStatus::Pending, Some(total_msat)),
+                RecentPaymentDetails::Fulfilled { payment_hash, .. } => {
+                    (payment_hash, PayStatus::Complete, None)
+                }
+                RecentPaymentDetails::Abandoned { payment_hash, .. } => {
+                    (Some(payment_hash), PayStatus::Failed, None)
+                }
+            };
+            // the route of a complete payment is already listed
+            if hash.is_some_and(|hash| routes.contains_key(&hash)) {
+                continue;
+            }
+            pays.push((
+                hash,
+                SentPayment {
+                    payment_hash: hash.map(|hash| hash.to_string()),
+                    status,
+                    amount_msat,
+                    amount_sent_msat: None,
+                },
+            ));
+        }
+        pays.into_iter()
+            .filter(|(hash, _)| {
+                payment_hash.map_or(true, |payment_hash| *hash == Some(*payment_hash))
+            })
+            .map(|(_, pay)| pay)
+            .collect()
+    }
+
     pub fn decode_invoice(&self, invoice_str: &str) -> error::Result<ldk::invoice::Bolt11Invoice> {
         let invoice = invoice_str.parse::<ldk::invoice::Bolt11Invoice>()?;
         Ok(invoice)
     }
 
-    pub fn pay_offer(&self, offer_str: &str, amount_msat: Option<u64>) -> error::Result<()> {
-        // check if it is an invoice or an offer
-        let offer_hash = Sha256::hash(offer_str.as_bytes());
-        let payment_id = PaymentId(*offer_hash.as_ref());
+    /// Return the id of the payment made for the invoice or the offer.
+    pub fn payment_id(&self, invoice_str: &str) -> error::Result<PaymentId> {
+        if Offer::from_str(invoice_str).is_ok() {
+            let offer_hash = Sha256::hash(invoice_str.as_bytes());
+            return Ok(PaymentId(*offer_hash.as_ref()));
+        }
+        let invoice = self.decode_invoice(invoice_str)?;
+        Ok(PaymentId((*invoice.payment_hash()).to_byte_array()))
+    }
+
+    /// Pay an offer, the payment is retried for `timeout` and it can be
+    /// abandoned by setting `cancel`, see `wait_payment`.
+    pub fn pay_offer(
+        &self,
+        offer_str: &str,
+        amount_msat: Option<Msat>,
+        timeout: Duration,
+        cancel: Arc<AtomicBool>,
+    ) -> error::Result<PaymentId> {
+        let payment_id = self.payment_id(offer_str)?;
         let offer = Offer::from_str(offer_str).map_err(|err| error::anyhow!("{:?}", err))?;
+        self.channel_manager.graph_load().wait(GRAPH_LOAD_TIMEOUT)?;
 
         let amount = match offer.amount() {
             Some(Amount::Bitcoin { amount_msats }) => amount_msats.clone(),
@@ -99,7 +784,9 @@ impl OffchainManager {
                 "Cannot process non-Bitcoin-denominated offer value {:?}",
                 offer.amount()
             ),
-            None => amount_msat.ok_or(error::anyhow!("An amount need to be specified"))?,
+            None => amount_msat
+                .ok_or(error::anyhow!("An amount need to be specified"))?
+                .msat(),
         };
 
         self.channel_manager
@@ -110,54 +797,291 @@ impl OffchainManager {
                 Some(amount),
                 None,
                 payment_id,
-                Retry::Attempts(10),
+                Retry::Timeout(timeout),
                 None,
             )
-            .map_err(|err| error::anyhow!("{:?}", err))?;
-        Ok(())
+            .map_err(|err| match err {
+                Bolt12SemanticError::DuplicatePaymentId => {
+                    error::anyhow!("duplicate payment, the offer is already being paid")
+                }
+                err => error::anyhow!("impossible pay the offer: {:?}", err),
+            })?;
+        self.pending_payments
+            .lock()
+            .unwrap()
+            .insert(payment_id, cancel);
+        Ok(payment_id)
     }
 
-    pub fn pay_invoice(&self, invoice_str: &str, amount_msat: Option<u64>) -> error::Result<()> {
+    /// Pay a bolt11 invoice, the `custom_tlvs` must be sorted by type
+    /// and are sent to the recipient together with the payment secret.
+    ///
+    /// The routes with a total CLTV expiry delta above
+    /// `max_total_cltv_expiry_delta` are not used.
+    ///
+    /// The payment is retried for `timeout` and it can be abandoned
+    /// by setting `cancel`, see `wait_payment`.
+    ///
+    /// An invoice already paid, or with its payment in flight, is not
+    /// paid again and the id of the existing payment is returned.
+    pub fn pay_invoice(
+        &self,
+        invoice_str: &str,
+        amount_msat: Option<Msat>,
+        custom_tlvs: Vec<(u64, Vec<u8>)>,
+        max_total_cltv_expiry_delta: Option<u32>,
+        timeout: Duration,
+        cancel: Arc<AtomicBool>,
+    ) -> Result<PaymentId, LampoError> {
         // check if it is an invoice or an offer
-        let invoice = self.decode_invoice(invoice_str)?;
+        let invoice = self
+            .decode_invoice(invoice_str)
+            .map_err(|err| LampoError::InvalidInvoice(format!("{err}")))?;
         let payment_id = PaymentId((*invoice.payment_hash()).to_byte_array());
-        let (payment_hash, onion, route) = if invoice.amount_milli_satoshis().is_none() {
+        if self.is_payment_sent(payment_id, invoice.payment_hash()) {
+            let payment_hash = invoice.payment_hash();
+            log::info!(target: "offchain", "payment `{payment_hash}` already sent");
+            self.pending_payments
+                .lock()
+                .unwrap()
+                .entry(payment_id)
+                .or_insert(cancel);
+            return Ok(payment_id);
+        }
+        self.channel_manager.graph_load().wait(GRAPH_LOAD_TIMEOUT)?;
+        let (payment_hash, onion, mut route) = if invoice.amount_milli_satoshis().is_none() {
             ldk::invoice::payment::payment_parameters_from_zero_amount_invoice(
                 &invoice,
-                amount_msat.ok_or(error::anyhow!(
-                    "invoice with no amount, and amount must be specified"
-                ))?,
+                amount_msat
+                    .ok_or(LampoError::InvalidParams(
+                        "invoice with no amount, and amount must be specified".to_owned(),
+                    ))?
+                    .msat(),
             )
-            .map_err(|err| error::anyhow!("{:?}", err))?
+            .map_err(|err| LampoError::InvalidInvoice(format!("{:?}", err)))?
         } else {
             ldk::invoice::payment::payment_parameters_from_invoice(&invoice)
-                .map_err(|err| error::anyhow!("{:?}", err))?
+                .map_err(|err| LampoError::InvalidInvoice(format!("{:?}", err)))?
+        };
+        let onion = if custom_tlvs.is_empty() {
+            onion
+        } else {
+            onion.with_custom_tlvs(custom_tlvs).map_err(|_| {
+                LampoError::InvalidParams("invalid custom TLVs for the invoice payment".to_owned())
+            })?
         };
+        limit_total_cltv(
+            &mut route,
+            max_total_cltv_expiry_delta,
+            invoice.min_final_cltv_expiry_delta() as u32,
+        )?;
+        self.channel_manager
+            .manager()
+            .send_payment(
+                payment_hash,
+                onion,
+                payment_id,
+                route.clone(),
+                Retry::Timeout(timeout),
+            )
+            .map_err(|err| self.send_failure(err, Msat::from_msat(route.final_value_msat)))?;
+        self.pending_payments
+            .lock()
+            .unwrap()
+            .insert(payment_id, cancel);
+        Ok(payment_id)
+    }
+
+    /// Whether the payment is in flight or succeeded, so it must not
+    /// be sent again.
+    fn is_payment_sent(&self, payment_id: PaymentId, payment_hash: &PaymentHash) -> bool {
+        if self.get_payment_route(payment_hash).is_ok() {
+            return true;
+        }
         self.channel_manager
             .manager()
-            .send_payment(payment_hash, onion, payment_id, route, Retry::Attempts(10))
-            .map_err(|err| error::anyhow!("{:?}", err))?;
+            .list_recent_payments()
+            .iter()
+            .any(|payment| match payment {
+                RecentPaymentDetails::Pending { payment_id: id, .. }
+                | RecentPaymentDetails::Fulfilled { payment_id: id, .. } => *id == payment_id,
+                _ => false,
+            })
+    }
+
+    /// Pay `amount_msat` to a LNURL-pay link or to a lightning address,
+    /// with the `comment` if the service allows it.
+    ///
+    /// The invoice given by the service is paid only if it commits to
+    /// the metadata of the service, and it is returned with the id of
+    /// the payment, see `pay_invoice`.
+    pub fn pay_lnurl(
+        &self,
+        lnurl: &str,
+        amount_msat: Msat,
+        comment: Option<String>,
+        timeout: Duration,
+        cancel: Arc<AtomicBool>,
+    ) -> Result<(PaymentId, ldk::invoice::Bolt11Invoice), LampoError> {
+        let url = lnurl::pay_url(lnurl, self.lampo_conf.network)?;
+        log::info!(target: "offchain", "paying {amount_msat} to the LNURL service `{url}`");
+        let params = lnurl::fetch_params(&url)?;
+        let invoice = lnurl::fetch_invoice(&params, amount_msat.msat(), comment.as_deref())?;
+        let payment_id = self.pay_invoice(
+            &invoice.to_string(),
+            None,
+            Vec::new(),
+            None,
+            timeout,
+            cancel,
+        )?;
+        Ok((payment_id, invoice))
+    }
+
+    /// A readable error for the reason why ldk did not send a payment
+    /// of `amount_msat`.
+    fn send_failure(&self, err: RetryableSendFailure, amount_msat: Msat) -> LampoError {
+        match err {
+            RetryableSendFailure::PaymentExpired => LampoError::InvoiceExpired,
+            RetryableSendFailure::DuplicatePayment => LampoError::DuplicatePayment,
+            RetryableSendFailure::RouteNotFound => {
+                let capacity = self
+                    .channel_manager
+                    .manager()
+                    .list_usable_channels()
+                    .iter()
+                    .map(|channel| channel.next_outbound_htlc_limit_msat)
+                    .sum::<u64>();
+                let amount_msat = amount_msat.msat();
+                if amount_msat > capacity {
+                    LampoError::NotEnoughCapacity {
+                        amount_msat,
+                        capacity_msat: capacity,
+                    }
+                } else {
+                    LampoError::RouteNotFound { amount_msat }
+                }
+            }
+        }
+    }
+
+    /// Cancel the payment in flight, the thread waiting for it
+    /// abandons the payment.
+    pub fn cancel_payment(&self, payment_id: &PaymentId) -> error::Result<()> {
+        let payments = self.pending_payments.lock().unwrap();
+        let Some(cancel) = payments.get(payment_id) else {
+            error::bail!("no payment in flight with id `{}`", payment_id.0.to_hex());
+        };
+        cancel.store(true, Ordering::SeqCst);
         Ok(())
     }
 
-    pub fn keysend(&self, destination: pubkey, amount_msat: u64) -> error::Result<PaymentHash> {
+    /// Wait for the result of the payment from the `events`, the
+    /// payment is abandoned when it is canceled or when it is not
+    /// done before the `timeout`.
+    ///
+    /// When the `payment_hash` is not known (e.g. paying an offer)
+    /// the first payment event is the result, otherwise a payment that
+    /// already succeeded is returned without waiting.
+    pub fn wait_payment(
+        &self,
+        events: chan::Receiver<Event>,
+        payment_id: PaymentId,
+        payment_hash: Option<PaymentHash>,
+        timeout: Duration,
+    ) -> Result<PayResult, LampoError> {
+        let deadline = Instant::now() + timeout;
+        let cancel = self
+            .pending_payments
+            .lock()
+            .unwrap()
+            .get(&payment_id)
+            .cloned()
+            .unwrap_or_default();
+        let result = loop {
+            if let Some(route) = payment_hash.and_then(|hash| self.get_payment_route(&hash).ok()) {
+                break Ok(PayResult {
+                    state: PaymentState::Success,
+                    path: route
+                        .paths
+                        .into_iter()
+                        .next()
+                        .map(|path| path.hops)
+                        .unwrap_or_default(),
+                    payment_hash: Some(route.payment_hash),
+                });
+            }
+            if cancel.load(Ordering::SeqCst) {
+                self.channel_manager.manager().abandon_payment(payment_id);
+                break Err(LampoError::PaymentCanceled);
+            }
+            if Instant::now() >= deadline {
+                self.channel_manager.manager().abandon_payment(payment_id);
+                break Err(LampoError::PaymentTimeout {
+                    timeout_secs: timeout.as_secs(),
+                });
+            }
+            let event = match events.recv_timeout(Duration::from_millis(100)) {
+                Ok(event) => event,
+                Err(chan::RecvTimeoutError::Timeout) => continue,
+                Err(err) => break Err(LampoError::PaymentFailure(format!("{err}"))),
+            };
+            if let Event::Lightning(LightningEvent::PaymentEvent {
+                payment_hash: hash,
+                path,
+                state,
+            }) = event
+            {
+                if payment_hash.is_some_and(|payment_hash| {
+                    hash.as_ref() != Some(&payment_hash.to_string())
+                }) {
+                    continue;
+                }
+                break Ok(PayResult {
+                    state,
+                    path,
+                    payment_hash: hash,
+                });
+            }
+        };
+        self.pending_payments.lock().unwrap().remove(&payment_id);
+        result
+    }
+
+    /// Send a spontaneous payment to `destination`, the routes with a
+    /// total CLTV expiry delta above `max_total_cltv_expiry_delta` are
+    /// not used.
+    pub fn keysend(
+        &self,
+        destination: pubkey,
+        amount_msat: Msat,
+        max_total_cltv_expiry_delta: Option<u32>,
+    ) -> error::Result<PaymentHash> {
+        self.channel_manager.graph_load().wait(GRAPH_LOAD_TIMEOUT)?;
         let payment_preimage = PaymentPreimage(
             self.chain_manager
                 .wallet_manager
                 .ldk_keys()
-                .keys_manager
-                .clone()
+                .inner()
                 .get_secure_random_bytes(),
         );
         let PaymentPreimage(bytes) = payment_preimage;
         let payment_hash = PaymentHash(Sha256::hash(&bytes).to_byte_array());
-        // The 40 here is the max CheckLockTimeVerify which locks the output of the transaction for a certain
-        // period of time.The false here stands for the allow_mpp, which is to allow the multi part route payments.
-        let route_params = RouteParameters {
-            payment_params: PaymentParameters::for_keysend(destination, 40, false),
-            final_value_msat: amount_msat,
+        // The false here stands for the allow_mpp, which is to allow the multi part route payments.
+        let mut route_params = RouteParameters {
+            payment_params: PaymentParameters::for_keysend(
+                destination,
+                KEYSEND_FINAL_CLTV_EXPIRY_DELTA,
+                false,
+            ),
+            final_value_msat: amount_msat.msat(),
             max_total_routing_fee_msat: None,
         };
+        limit_total_cltv(
+            &mut route_params,
+            max_total_cltv_expiry_delta,
+            KEYSEND_FINAL_CLTV_EXPIRY_DELTA,
+        )?;
         log::info!("Initialised Keysend");
         let payment_result = self
             .channel_manager
@@ -169,7 +1093,7 @@ impl OffchainManager {
                 route_params,
                 Retry::Timeout(Duration::from_secs(10)),
             )
-            .map_err(|err| error::anyhow!("{:?}", err))?;
+            .map_err(|err| self.send_failure(err, amount_msat))?;
         log::info!("Keysend successfully done!");
         Ok(payment_result)
     }
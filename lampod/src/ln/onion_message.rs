@@ -0,0 +1,161 @@
+//! Custom onion messages.
+//!
+//! The messages with a custom tlv type are queued when received, and
+//! the user can answer through the reply path attached by the sender.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use lampo_common::bitcoin::hashes::hex::ToHex;
+use lampo_common::chan;
+use lampo_common::error;
+use lampo_common::event::ln::LightningEvent;
+use lampo_common::event::Event;
+use lampo_common::handler::Handler;
+use lampo_common::ldk::io;
+use lampo_common::ldk::ln::msgs::DecodeError;
+use lampo_common::ldk::onion_message::messenger::{
+    CustomOnionMessageHandler, PendingOnionMessage, Responder, ResponseInstruction,
+};
+use lampo_common::ldk::onion_message::packet::OnionMessageContents;
+use lampo_common::ldk::util::ser::{Writeable, Writer};
+use lampo_common::model::response;
+use lampo_common::sync::MutexExt;
+
+use crate::actions::handler::LampoHandler;
+
+/// The tlv types below this are reserved by the spec.
+pub const MIN_CUSTOM_TLV_TYPE: u64 = 64;
+
+/// A custom onion message, the payload is opaque to lampo.
+#[derive(Clone, Debug)]
+pub struct CustomOnionMessage {
+    pub tlv_type: u64,
+    pub payload: Vec<u8>,
+}
+
+impl OnionMessageContents for CustomOnionMessage {
+    fn tlv_type(&self) -> u64 {
+        self.tlv_type
+    }
+
+    fn msg_type(&self) -> &'static str {
+        "Lampo Custom Onion Message"
+    }
+}
+
+impl Writeable for CustomOnionMessage {
+    fn write<W: Writer>(&self, writer: &mut W) -> Result<(), io::Error> {
+        writer.write_all(&self.payload)
+    }
+}
+
+pub struct LampoOnionMessages {
+    received: (
+        chan::Sender<response::OnionMessage>,
+        chan::Receiver<response::OnionMessage>,
+    ),
+    /// The reply paths of the received messages, by message id.
+    responders: Mutex<HashMap<u64, Responder>>,
+    handler: Mutex<Option<Arc<LampoHandler>>>,
+    next_id: AtomicU64,
+}
+
+impl Default for LampoOnionMessages {
+    fn default() -> Self {
+        Self {
+            received: chan::unbounded(),
+            responders: Mutex::new(HashMap::new()),
+            handler: Mutex::new(None),
+            next_id: AtomicU64::new(0),
+        }
+    }
+}
+
+impl LampoOnionMessages {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_handler(&self, handler: Arc<LampoHandler>) {
+        *self.handler.lock_or_recover() = Some(handler);
+    }
+
+    /// Wait for the next custom onion message received.
+    pub fn wait_message(&self, timeout: Duration) -> error::Result<response::OnionMessage> {
+        self.received
+            .1
+            .recv_timeout(timeout)
+            .map_err(|_| error::anyhow!("no onion message received in {}s", timeout.as_secs()))
+    }
+
+    /// Take the reply path of the received message `id`.
+    pub fn take_responder(&self, id: u64) -> error::Result<Responder> {
+        self.responders
+            .lock_or_recover()
+            .remove(&id)
+            .ok_or(error::anyhow!(
+                "no reply path for the onion message `{id}`, or it was already used"
+            ))
+    }
+}
+
+impl CustomOnionMessageHandler for LampoOnionMessages {
+    type CustomMessage = CustomOnionMessage;
+
+    fn handle_custom_message(
+        &self,
+        message: Self::CustomMessage,
+        responder: Option<Responder>,
+    ) -> ResponseInstruction<Self::CustomMessage> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let reply_path = responder.is_some();
+        if let Some(responder) = responder {
+            self.responders.lock_or_recover().insert(id, responder);
+        }
+        let payload = message.payload.to_hex();
+        log::info!(
+            target: "lampo",
+            "received onion message `{id}` with tlv type `{}`",
+            message.tlv_type
+        );
+        if let Some(ref handler) = *self.handler.lock_or_recover() {
+            handler.emit(Event::Lightning(LightningEvent::OnionMessageReceived {
+                id,
+                tlv_type: message.tlv_type,
+                payload: payload.clone(),
+                reply_path,
+            }));
+        }
+        let _ = self.received.0.send(response::OnionMessage {
+            id,
+            tlv_type: message.tlv_type,
+            payload,
+            reply_path,
+        });
+        // the user answers later with `sendonionmessage`
+        ResponseInstruction::NoResponse
+    }
+
+    fn read_custom_message<R: io::Read>(
+        &self,
+        message_type: u64,
+        buffer: &mut R,
+    ) -> Result<Option<Self::CustomMessage>, DecodeError> {
+        if message_type < MIN_CUSTOM_TLV_TYPE {
+            return Ok(None);
+        }
+        let mut payload = Vec::new();
+        buffer.read_to_end(&mut payload)?;
+        Ok(Some(CustomOnionMessage {
+            tlv_type: message_type,
+            payload,
+        }))
+    }
+
+    fn release_pending_custom_messages(&self) -> Vec<PendingOnionMessage<Self::CustomMessage>> {
+        // the messages are sent directly with the onion messenger
+        Vec::new()
+    }
+}
@@ -0,0 +1,131 @@
+//! Address book of the peers and their reconnection state.
+//!
+//! lampo remembers the last addresses where a peer was reachable,
+//! so it can dial again the peers we have channels with when the
+//! connection drops. The reconnection attempts are done with an
+//! exponential backoff for each peer.
+use std::collections::HashMap;
+use std::io;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use lampo_common::error;
+use lampo_common::json;
+use lampo_common::ldk::util::persist::KVStore;
+use lampo_common::model::response::Reconnect;
+use lampo_common::sync::MutexExt;
+use lampo_common::types::NodeId;
+
+use crate::persistence::LampoPersistence;
+
+const LAMPO_NAMESPACE: &str = "lampo";
+const PEER_ADDRESSES_KEY: &str = "peer_addresses";
+/// Number of addresses that we keep for each peer.
+const MAX_ADDRESSES: usize = 5;
+/// Maximum delay between two reconnection attempts in seconds.
+const MAX_BACKOFF_SECS: u64 = 300;
+
+pub struct LampoPeerBook {
+    persister: Arc<LampoPersistence>,
+    /// Known addresses of the peers, the last reachable first.
+    addresses: Mutex<HashMap<NodeId, Vec<String>>>,
+    reconnects: Mutex<HashMap<NodeId, Reconnect>>,
+}
+
+impl LampoPeerBook {
+    pub fn new(persister: Arc<LampoPersistence>) -> Self {
+        let addresses = Self::read(&persister).unwrap_or_else(|err| {
+            log::warn!(target: "lampo", "impossible read the peer addresses: {err}");
+            HashMap::new()
+        });
+        Self {
+            persister,
+            addresses: Mutex::new(addresses),
+            reconnects: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn read(persister: &LampoPersistence) -> error::Result<HashMap<NodeId, Vec<String>>> {
+        let buf = match persister.read(LAMPO_NAMESPACE, "", PEER_ADDRESSES_KEY) {
+            Ok(buf) => buf,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let addresses: HashMap<String, Vec<String>> = json::from_slice(&buf)?;
+        addresses
+            .into_iter()
+            .map(|(node_id, addresses)| Ok((NodeId::from_str(&node_id)?, addresses)))
+            .collect()
+    }
+
+    fn write(&self, addresses: &HashMap<NodeId, Vec<String>>) -> error::Result<()> {
+        let addresses = addresses
+            .iter()
+            .map(|(node_id, addresses)| (node_id.to_string(), addresses))
+            .collect::<HashMap<_, _>>();
+        let buf = json::to_vec(&addresses)?;
+        self.persister
+            .write(LAMPO_NAMESPACE, "", PEER_ADDRESSES_KEY, &buf)?;
+        Ok(())
+    }
+
+    /// Remember that the peer was reachable at `addr`.
+    pub fn record_address(&self, node_id: &NodeId, addr: &str) -> error::Result<()> {
+        let mut addresses = self.addresses.lock_or_recover();
+        let known = addresses.entry(*node_id).or_default();
+        if known.first().map(|first| first == addr).unwrap_or(false) {
+            return Ok(());
+        }
+        known.retain(|known| known != addr);
+        known.insert(0, addr.to_owned());
+        known.truncate(MAX_ADDRESSES);
+        self.write(&addresses)
+    }
+
+    pub fn addresses(&self, node_id: &NodeId) -> Vec<String> {
+        self.addresses
+            .lock_or_recover()
+            .get(node_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn reconnect_state(&self, node_id: &NodeId) -> Option<Reconnect> {
+        self.reconnects.lock_or_recover().get(node_id).cloned()
+    }
+
+    /// Return true if it is time to try to reconnect with the peer.
+    pub fn should_reconnect(&self, node_id: &NodeId, now: u64) -> bool {
+        self.reconnects
+            .lock_or_recover()
+            .get(node_id)
+            .map(|state| now >= state.next_attempt)
+            .unwrap_or(true)
+    }
+
+    /// Record a failed attempt, and schedule the next one.
+    pub fn record_failure(&self, node_id: &NodeId, now: u64, err: &str) {
+        let mut reconnects = self.reconnects.lock_or_recover();
+        let state = reconnects.entry(*node_id).or_insert(Reconnect {
+            attempts: 0,
+            next_attempt: now,
+            last_error: None,
+        });
+        state.attempts += 1;
+        state.next_attempt = now + Self::backoff(state.attempts);
+        state.last_error = Some(err.to_owned());
+    }
+
+    /// The peer is connected, so reset the backoff.
+    pub fn record_success(&self, node_id: &NodeId) {
+        self.reconnects.lock_or_recover().remove(node_id);
+    }
+
+    /// Delay in seconds before the next attempt: 1s, 2s, 4s, ... up to 5 minutes.
+    fn backoff(attempts: u32) -> u64 {
+        1u64.checked_shl(attempts.saturating_sub(1))
+            .unwrap_or(MAX_BACKOFF_SECS)
+            .min(MAX_BACKOFF_SECS)
+    }
+}
+
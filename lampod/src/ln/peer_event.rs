@@ -1,11 +1,10 @@
 //! Implementation of all the peers events
-use std::net::SocketAddr;
-
 use crossbeam_channel as chan;
 
+use lampo_common::ldk::ln::msgs::SocketAddress;
 use lampo_common::{model::Connect, types::NodeId};
 
 #[derive(Debug, Clone)]
 pub enum PeerCommand {
-    Connect(NodeId, SocketAddr, chan::Sender<Connect>),
+    Connect(NodeId, SocketAddress, chan::Sender<Connect>),
 }
@@ -0,0 +1,160 @@
+//! Filter of the peers that can connect with us.
+//!
+//! The node id of an inbound peer is known only at the end of the
+//! handshake, so a peer that is not allowed is disconnected as soon
+//! as the handshake is done. The lists in the configuration are used
+//! until they are changed at runtime, then the persisted ones win.
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use lampo_common::conf::LampoConf;
+use lampo_common::error;
+use lampo_common::json;
+use lampo_common::ldk::util::persist::KVStore;
+use lampo_common::model::request::PeerListKind;
+use lampo_common::model::response::PeerLists;
+use lampo_common::sync::MutexExt;
+use lampo_common::types::NodeId;
+
+use crate::persistence::LampoPersistence;
+
+const LAMPO_NAMESPACE: &str = "lampo";
+const PEER_LISTS_KEY: &str = "peer_lists";
+
+pub struct LampoPeerFilter {
+    persister: Arc<LampoPersistence>,
+    allowlist: Mutex<BTreeSet<NodeId>>,
+    denylist: Mutex<BTreeSet<NodeId>>,
+    max_inbound_peers: Option<usize>,
+    /// When the inbound peers completed the handshake.
+    inbound_since: Mutex<HashMap<NodeId, Instant>>,
+}
+
+impl LampoPeerFilter {
+    pub fn new(conf: &LampoConf, persister: Arc<LampoPersistence>) -> Self {
+        let (allowlist, denylist) = match Self::read(&persister) {
+            Ok(Some(lists)) => lists,
+            Ok(None) => (
+                conf.peer_allowlist.iter().copied().collect(),
+                conf.peer_denylist.iter().copied().collect(),
+            ),
+            Err(err) => {
+                log::warn!(target: "lampo", "impossible read the peer lists: {err}");
+                (
+                    conf.peer_allowlist.iter().copied().collect(),
+                    conf.peer_denylist.iter().copied().collect(),
+                )
+            }
+        };
+        Self {
+            persister,
+            allowlist: Mutex::new(allowlist),
+            denylist: Mutex::new(denylist),
+            max_inbound_peers: conf.max_inbound_peers,
+            inbound_since: Mutex::new(HashMap::new()),
+        }
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn read(
+        persister: &LampoPersistence,
+    ) -> error::Result<Option<(BTreeSet<NodeId>, BTreeSet<NodeId>)>> {
+        let buf = match persister.read(LAMPO_NAMESPACE, "", PEER_LISTS_KEY) {
+            Ok(buf) => buf,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let lists: PeerLists = json::from_slice(&buf)?;
+        let parse = |node_ids: &[String]| {
+            node_ids
+                .iter()
+                .map(|node_id| Ok(NodeId::from_str(node_id)?))
+                .collect::<error::Result<BTreeSet<_>>>()
+        };
+        Ok(Some((parse(&lists.allowlist)?, parse(&lists.denylist)?)))
+    }
+
+    /// The current lists.
+    pub fn lists(&self) -> PeerLists {
+        let to_strings = |node_ids: &BTreeSet<NodeId>| {
+            node_ids
+                .iter()
+                .map(|node_id| node_id.to_string())
+                .collect::<Vec<_>>()
+        };
+        PeerLists {
+            allowlist: to_strings(&self.allowlist.lock_or_recover()),
+            denylist: to_strings(&self.denylist.lock_or_recover()),
+            max_inbound_peers: self.max_inbound_peers,
+        }
+    }
+
+    /// Add or remove the peer from a list, and persist the lists.
+    pub fn set(&self, list: PeerListKind, node_id: NodeId, remove: bool) -> error::Result<()> {
+        {
+            let mut list = match list {
+                PeerListKind::Allow => self.allowlist.lock_or_recover(),
+                PeerListKind::Deny => self.denylist.lock_or_recover(),
+            };
+            if remove {
+                list.remove(&node_id);
+            } else {
+                list.insert(node_id);
+            }
+        }
+        let buf = json::to_vec(&self.lists())?;
+        self.persister
+            .write(LAMPO_NAMESPACE, "", PEER_LISTS_KEY, &buf)?;
+        Ok(())
+    }
+
+    /// Return true if the peer is inside the deny list.
+    pub fn is_denied(&self, node_id: &NodeId) -> bool {
+        self.denylist.lock_or_recover().contains(node_id)
+    }
+
+    /// Return true if the peer can connect with us.
+    pub fn is_allowed(&self, node_id: &NodeId) -> bool {
+        if self.is_denied(node_id) {
+            return false;
+        }
+        let allowlist = self.allowlist.lock_or_recover();
+        allowlist.is_empty() || allowlist.contains(node_id)
+    }
+
+    /// Record the inbound peer that completed the handshake, and return
+    /// the peers to disconnect to respect `max-inbound-peers`, the
+    /// oldest inbound peers first.
+    ///
+    /// The peers with channels are not counted, so they are never
+    /// disconnected.
+    pub fn inbound_connected(
+        &self,
+        node_id: NodeId,
+        inbound_peers: &[NodeId],
+        channel_peers: &HashSet<NodeId>,
+    ) -> Vec<NodeId> {
+        let mut inbound_since = self.inbound_since.lock_or_recover();
+        inbound_since.retain(|node_id, _| inbound_peers.contains(node_id));
+        inbound_since.entry(node_id).or_insert_with(Instant::now);
+        let Some(max_inbound_peers) = self.max_inbound_peers else {
+            return Vec::new();
+        };
+        let mut peers = inbound_peers
+            .iter()
+            .filter(|node_id| !channel_peers.contains(node_id))
+            .map(|node_id| (inbound_since.get(node_id).copied(), *node_id))
+            .collect::<Vec<_>>();
+        // the peers that we did not see connecting are the oldest
+        peers.sort();
+        let exceeding = peers.len().saturating_sub(max_inbound_peers);
+        peers
+            .into_iter()
+            .take(exceeding)
+            .map(|(_, node_id)| node_id)
+            .collect()
+    }
+}
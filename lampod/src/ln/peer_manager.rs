@@ -1,32 +1,52 @@
-use std::net::SocketAddr;
+use std::collections::{HashMap, HashSet};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
 use std::str::FromStr;
-use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
 use async_trait::async_trait;
 
+use lampo_common::bitcoin::hashes::hex::ToHex;
+use lampo_common::chan;
 use lampo_common::conf::LampoConf;
 use lampo_common::error;
+use lampo_common::event::ln::LightningEvent;
+use lampo_common::event::Event;
+use lampo_common::handler::Handler;
 use lampo_common::keys::LampoKeysManager;
-use lampo_common::ldk;
-use lampo_common::ldk::blinded_path::EmptyNodeIdLookUp;
+use lampo_common::ldk::blinded_path::{BlindedPath, EmptyNodeIdLookUp};
+use lampo_common::ldk::ln::msgs::SocketAddress;
 use lampo_common::ldk::ln::peer_handler::MessageHandler;
 use lampo_common::ldk::ln::peer_handler::{IgnoringMessageHandler, PeerManager};
 use lampo_common::ldk::net;
 use lampo_common::ldk::net::SocketDescriptor;
-use lampo_common::ldk::onion_message::messenger::{DefaultMessageRouter, OnionMessenger};
-use lampo_common::ldk::routing::gossip::{NetworkGraph, P2PGossipSync};
+use lampo_common::ldk::onion_message::messenger::{
+    DefaultMessageRouter, Destination, OnionMessenger, SendSuccess,
+};
+use lampo_common::ldk::routing::gossip::{self, NetworkGraph, P2PGossipSync};
+use lampo_common::model::request::PeerListKind;
+use lampo_common::model::response::{self, Peer, PeerLists, Peers};
 use lampo_common::model::Connect;
+use lampo_common::secp256k1::Secp256k1;
+use lampo_common::time::unix_now;
 use lampo_common::types::NodeId;
 
+use crate::actions::handler::LampoHandler;
 use crate::async_run;
 use crate::chain::{LampoChainManager, WalletManager};
+use crate::ln::peer_book::LampoPeerBook;
 use crate::ln::LampoChannelManager;
+use crate::persistence::LampoPersistence;
 use crate::utils::logger::LampoLogger;
 
 use super::channel_manager::{LampoArcChannelManager, LampoChainMonitor, LampoGraph};
 use super::events::PeerEvents;
+use super::onion_message::{CustomOnionMessage, LampoOnionMessages, MIN_CUSTOM_TLV_TYPE};
 use super::peer_event;
+use super::peer_filter::LampoPeerFilter;
+use super::ping::LampoPing;
+use super::tor::{self, TorHiddenService};
 
 pub type LampoArcOnionMessenger<L> = OnionMessenger<
     Arc<LampoKeysManager>,
@@ -35,7 +55,7 @@ pub type LampoArcOnionMessenger<L> = OnionMessenger<
     Arc<EmptyNodeIdLookUp>,
     Arc<DefaultMessageRouter<Arc<LampoGraph>, Arc<L>, Arc<LampoKeysManager>>>,
     IgnoringMessageHandler,
-    IgnoringMessageHandler,
+    Arc<LampoOnionMessages>,
 >;
 
 pub type SimpleArcPeerManager<M, T, L> = PeerManager<
@@ -44,7 +64,7 @@ pub type SimpleArcPeerManager<M, T, L> = PeerManager<
     Arc<P2PGossipSync<Arc<NetworkGraph<Arc<L>>>, Arc<T>, Arc<L>>>,
     Arc<LampoArcOnionMessenger<L>>,
     Arc<L>,
-    IgnoringMessageHandler,
+    Arc<LampoPing>,
     Arc<LampoKeysManager>,
 >;
 
@@ -56,15 +76,61 @@ pub struct LampoPeerManager {
     channel_manager: Option<Arc<LampoChannelManager>>,
     conf: LampoConf,
     logger: Arc<LampoLogger>,
+    /// Unix timestamp when a peer was seen connected the first time.
+    connected_since: Mutex<HashMap<NodeId, u64>>,
+    /// The peers connected at the last `notify_connections`.
+    connected: Mutex<HashSet<NodeId>>,
+    book: LampoPeerBook,
+    last_announcement: Mutex<Option<NodeAnnouncement>>,
+    listen_addrs: Mutex<Vec<SocketAddr>>,
+    hidden_service: Mutex<Option<TorHiddenService>>,
+    ping: Arc<LampoPing>,
+    onion_messenger: Option<Arc<LampoArcOnionMessenger<LampoLogger>>>,
+    onion_messages: Arc<LampoOnionMessages>,
+    keys: Option<Arc<LampoKeysManager>>,
+    filter: Arc<LampoPeerFilter>,
+    /// Set on shutdown, the inbound connections are not accepted anymore.
+    stopped: Arc<AtomicBool>,
 }
 
+/// The last node announcement broadcasted.
+#[derive(Clone, Copy, Debug)]
+struct NodeAnnouncement {
+    /// Unix timestamp of the announcement.
+    at: u64,
+    /// Number of our channels inside the graph at that time.
+    channels: usize,
+}
+
+/// How long `connect` waits for the handshake with the peer.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long the `ping` command waits for each pong.
+const PONG_TIMEOUT: Duration = Duration::from_secs(5);
+
 impl LampoPeerManager {
-    pub fn new(conf: &LampoConf, logger: Arc<LampoLogger>) -> LampoPeerManager {
+    pub fn new(
+        conf: &LampoConf,
+        logger: Arc<LampoLogger>,
+        persister: Arc<LampoPersistence>,
+    ) -> LampoPeerManager {
         LampoPeerManager {
             peer_manager: None,
             conf: conf.to_owned(),
             logger,
             channel_manager: None,
+            connected_since: Mutex::new(HashMap::new()),
+            connected: Mutex::new(HashSet::new()),
+            filter: Arc::new(LampoPeerFilter::new(conf, persister.clone())),
+            book: LampoPeerBook::new(persister),
+            last_announcement: Mutex::new(None),
+            listen_addrs: Mutex::new(Vec::new()),
+            hidden_service: Mutex::new(None),
+            ping: Arc::new(LampoPing::new()),
+            onion_messenger: None,
+            onion_messages: Arc::new(LampoOnionMessages::new()),
+            keys: None,
+            stopped: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -74,7 +140,7 @@ impl LampoPeerManager {
 
     pub fn init(
         &mut self,
-        _onchain_manager: Arc<LampoChainManager>,
+        onchain_manager: Arc<LampoChainManager>,
         wallet_manager: Arc<dyn WalletManager>,
         channel_manager: Arc<LampoChannelManager>,
     ) -> error::Result<()> {
@@ -84,7 +150,7 @@ impl LampoPeerManager {
             .unwrap()
             .as_secs();
 
-        let keys = wallet_manager.ldk_keys().keys_manager.clone();
+        let keys = wallet_manager.ldk_keys().inner();
         let graph = channel_manager.graph();
         let onion_messenger = Arc::new(OnionMessenger::new(
             keys.clone(),
@@ -93,20 +159,17 @@ impl LampoPeerManager {
             Arc::new(EmptyNodeIdLookUp {}),
             Arc::new(DefaultMessageRouter::new(graph.clone(), keys.clone())),
             IgnoringMessageHandler {},
-            IgnoringMessageHandler {},
+            self.onion_messages.clone(),
         ));
 
-        let gossip_sync = Arc::new(P2PGossipSync::new(
-            graph.clone(),
-            None::<Arc<LampoChainManager>>,
-            self.logger.clone(),
-        ));
+        let utxo_lookup = channel_manager.conf.gossip_utxo_lookup.then(|| onchain_manager);
+        let gossip_sync = Arc::new(P2PGossipSync::new(graph.clone(), utxo_lookup, self.logger.clone()));
 
         let lightning_msg_handler = MessageHandler {
             chan_handler: channel_manager.channeld.clone().unwrap(),
-            onion_message_handler: onion_messenger,
+            onion_message_handler: onion_messenger.clone(),
             route_handler: gossip_sync,
-            custom_message_handler: IgnoringMessageHandler {},
+            custom_message_handler: self.ping.clone(),
         };
 
         let peer_manager = InnerLampoPeerManager::new(
@@ -114,104 +177,572 @@ impl LampoPeerManager {
             current_time.try_into().unwrap(),
             &ephemeral_bytes,
             channel_manager.logger.clone(),
-            wallet_manager.ldk_keys().keys_manager.clone(),
+            wallet_manager.ldk_keys().inner(),
         );
         self.peer_manager = Some(Arc::new(peer_manager));
         self.channel_manager = Some(channel_manager.clone());
+        self.onion_messenger = Some(onion_messenger);
+        self.keys = Some(keys);
         Ok(())
     }
 
+    /// Listen for the inbound connections on all the addresses
+    /// configured, with an accept loop for each of them.
+    ///
+    /// All the addresses are bound before starting to accept, so
+    /// an address that can not be bound is an error at startup.
     pub fn run(&self) -> error::Result<()> {
-        let listen_port = self.conf.port;
         let Some(ref peer_manager) = self.peer_manager else {
             error::bail!("peer manager is None, at this point this should be not None");
         };
-        let peer_manager = peer_manager.clone();
-        let chan_manager = self
+        let channel_manager = self
             .channel_manager
             .clone()
             .ok_or(error::anyhow!("channel manager is None"))?;
-        let alias = self.conf.alias.clone().unwrap_or_default();
-        let addr = self
-            .conf
-            .announce_addr
-            .clone()
-            .unwrap_or_else(|| "127.0.0.1".to_string());
-        std::thread::spawn(move || {
-            let result = async_run!(async move {
-                let bind_addr = format!("{addr}:{listen_port}");
-                log::info!(target: "lampo", "Listening for in-bound connection on {bind_addr}");
-                let listener = match tokio::net::TcpListener::bind(bind_addr.clone()).await {
-                    Ok(listener) => listener,
-                    Err(e) => {
-                        return Err::<(), _>(error::anyhow!("Error binding to address: {}", e));
-                    }
-                };
-
-                loop {
-                    let alias = alias.clone();
-                    let peer_manager = peer_manager.clone();
-                    let chan_manager = chan_manager.clone();
-                    let accept = listener.accept().await;
-                    let accept = accept
-                        .map_err(|err| error::anyhow!("Error accepting connection: {}", err))?;
-                    match accept {
-                        (tcp_stream, _) => {
-                            log::info!(target: "lampo", "Got new connection {}", tcp_stream.peer_addr().unwrap());
-                            let addr = bind_addr.clone();
-                            let _ = tokio::spawn(async move {
-                                // Use LDK's supplied networking battery to facilitate inbound
-                                // connections.
-                                net::setup_inbound(
-                                    peer_manager.clone(),
-                                    tcp_stream.into_std().expect("impossible to convert a tpc_stream from tokio to std"),
-                                )
-                                .await;
-
-                                // Then, update our announcement once an hour to keep it fresh but avoid unnecessary churn
-                                // in the global gossip network.
-                                // FIXME: this value should be possible to alterate from config
-                                let mut interval = tokio::time::interval(Duration::from_secs(1));
-                                loop {
-                                    interval.tick().await;
-                                    // Don't bother trying to announce if we don't have any public channls, though our
-                                    // peers should drop such an announcement anyway. Note that announcement may not
-                                    // propagate until we have a channel with 6+ confirmations.
-                                    if chan_manager
-                                        .manager()
-                                        .list_channels()
-                                        .iter()
-                                        .any(|chan| chan.is_public)
-                                    {
-                                        peer_manager.broadcast_node_announcement(
-                                            [0; 3],
-                                            alias.as_bytes().try_into().unwrap_or([0u8; 32]),
-                                            vec![ldk::ln::msgs::SocketAddress::from_str(&addr)
-                                                .expect("impossible to convert an addr to ln socket addr (wire format)")],
-                                        );
-                                    }
-                                }
-                            })
-                            .await;
-                        }
-                    }
+        let mut listeners = Vec::new();
+        for addr in self.conf.bind_addrs()? {
+            let listener = std::net::TcpListener::bind(addr)
+                .map_err(|err| error::anyhow!("impossible to bind `{addr}`: {err}"))?;
+            // tokio requires a non blocking listener
+            listener.set_nonblocking(true)?;
+            listeners.push((addr, listener));
+        }
+        let mut listen_addrs = self.listen_addrs.lock().unwrap();
+        listen_addrs.clear();
+        for (addr, listener) in listeners {
+            // use the real address, so the port 0 gives the port
+            // chosen by the os.
+            let addr = listener.local_addr().unwrap_or(addr);
+            listen_addrs.push(addr);
+            let peer_manager = peer_manager.clone();
+            let filter = self.filter.clone();
+            let channel_manager = channel_manager.clone();
+            let stopped = self.stopped.clone();
+            std::thread::spawn(move || {
+                let result = async_run!(Self::accept_loop(
+                    peer_manager,
+                    filter,
+                    channel_manager,
+                    listener,
+                    addr,
+                    stopped
+                ));
+                if let Err(err) = &result {
+                    log::error!(target: "lampo", "error while listening on `{addr}`: `{err}`");
                 }
+                result
             });
+        }
+        if let Some(control) = self.conf.tor_control {
+            // SAFETY: there is at least one address to bind.
+            let target = listen_addrs.first().copied().unwrap();
+            let target = match target.ip() {
+                ip if ip.is_unspecified() && ip.is_ipv4() => {
+                    SocketAddr::new(Ipv4Addr::LOCALHOST.into(), target.port())
+                }
+                ip if ip.is_unspecified() => {
+                    SocketAddr::new(Ipv6Addr::LOCALHOST.into(), target.port())
+                }
+                _ => target,
+            };
+            let service = TorHiddenService::create(
+                control,
+                self.conf.tor_password.as_deref(),
+                target.port(),
+                target,
+            )?;
+            *self.hidden_service.lock().unwrap() = Some(service);
+        }
+        Ok(())
+    }
 
-            if let Err(err) = &result {
-                log::error!("error while try to listen on inbound connection: `{err}`");
-            }
-            result
-        });
+    /// The addresses that we announce, the configured ones plus the
+    /// onion address of our hidden service.
+    pub fn announced_addrs(&self) -> Vec<SocketAddress> {
+        let mut addrs = self.conf.announce_addr.clone();
+        if let Some(ref service) = *self.hidden_service.lock().unwrap() {
+            addrs.push(service.onion_addr.clone());
+        }
+        addrs
+    }
+
+    async fn accept_loop(
+        peer_manager: Arc<InnerLampoPeerManager>,
+        filter: Arc<LampoPeerFilter>,
+        channel_manager: Arc<LampoChannelManager>,
+        listener: std::net::TcpListener,
+        addr: SocketAddr,
+        stopped: Arc<AtomicBool>,
+    ) -> error::Result<()> {
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        log::info!(target: "lampo", "Listening for in-bound connection on {addr}");
+        while !stopped.load(Ordering::SeqCst) {
+            // wake up every second, so the loop ends on shutdown
+            let Ok(accepted) =
+                tokio::time::timeout(Duration::from_secs(1), listener.accept()).await
+            else {
+                continue;
+            };
+            let (tcp_stream, peer_addr) =
+                accepted.map_err(|err| error::anyhow!("Error accepting connection: {}", err))?;
+            log::info!(target: "lampo", "Got new connection {peer_addr} on {addr}");
+            tokio::spawn(Self::filter_inbound(
+                peer_manager.clone(),
+                filter.clone(),
+                channel_manager.clone(),
+                peer_addr,
+            ));
+            let peer_manager = peer_manager.clone();
+            tokio::spawn(async move {
+                // Use LDK's supplied networking battery to facilitate inbound
+                // connections.
+                net::setup_inbound(
+                    peer_manager,
+                    tcp_stream
+                        .into_std()
+                        .expect("impossible to convert a tpc_stream from tokio to std"),
+                )
+                .await;
+            });
+        }
+        log::info!(target: "lampo", "Stop listening on {addr}");
         Ok(())
     }
 
+    /// Wait for the handshake of the inbound connection from `peer_addr`,
+    /// then disconnect the peer if it is not allowed, or the oldest
+    /// inbound peers over `max-inbound-peers`.
+    async fn filter_inbound(
+        peer_manager: Arc<InnerLampoPeerManager>,
+        filter: Arc<LampoPeerFilter>,
+        channel_manager: Arc<LampoChannelManager>,
+        peer_addr: SocketAddr,
+    ) {
+        let peer_addr = SocketAddress::from(peer_addr);
+        let start = Instant::now();
+        let node_id = loop {
+            if let Some(peer) = peer_manager.list_peers().into_iter().find(|peer| {
+                peer.is_inbound_connection && peer.socket_address.as_ref() == Some(&peer_addr)
+            }) {
+                break peer.counterparty_node_id;
+            }
+            if start.elapsed() > CONNECT_TIMEOUT {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        if !filter.is_allowed(&node_id) {
+            log::info!(target: "lampo", "peer `{node_id}` is not allowed, disconnecting");
+            peer_manager.disconnect_by_node_id(node_id);
+            return;
+        }
+        let inbound_peers = peer_manager
+            .list_peers()
+            .iter()
+            .filter(|peer| peer.is_inbound_connection)
+            .map(|peer| peer.counterparty_node_id)
+            .collect::<Vec<_>>();
+        let channel_peers = channel_manager
+            .manager()
+            .list_channels()
+            .iter()
+            .map(|channel| channel.counterparty.node_id)
+            .collect::<HashSet<_>>();
+        for node_id in filter.inbound_connected(node_id, &inbound_peers, &channel_peers) {
+            log::info!(target: "lampo", "too many inbound peers, disconnecting `{node_id}`");
+            peer_manager.disconnect_by_node_id(node_id);
+        }
+    }
+
+    /// Add or remove a peer from the allow or the deny list, the
+    /// connected peers that are not allowed anymore are disconnected.
+    pub fn set_peer_list(
+        &self,
+        list: PeerListKind,
+        node_id: NodeId,
+        remove: bool,
+    ) -> error::Result<PeerLists> {
+        self.filter.set(list, node_id, remove)?;
+        let manager = self.manager();
+        for peer in manager.list_peers() {
+            let node_id = peer.counterparty_node_id;
+            if self.filter.is_denied(&node_id)
+                || (peer.is_inbound_connection && !self.filter.is_allowed(&node_id))
+            {
+                log::info!(target: "lampo", "peer `{node_id}` is not allowed, disconnecting");
+                manager.disconnect_by_node_id(node_id);
+                self.connected_since.lock().unwrap().remove(&node_id);
+            }
+        }
+        Ok(self.filter.lists())
+    }
+
+    /// The addresses where we are listening for the inbound connections.
+    pub fn listen_addrs(&self) -> Vec<SocketAddr> {
+        self.listen_addrs.lock().unwrap().clone()
+    }
+
+    /// Broadcast our node announcement, if some of our channels are
+    /// announced inside the network graph.
+    ///
+    /// The peers drop the announcement of a node without channels, so
+    /// we announce again when a new channel of ours is announced, and
+    /// then every `announce_interval_secs` to keep it fresh. The first
+    /// announcement after the startup uses the current configuration.
+    ///
+    /// Return true if the announcement is broadcasted.
+    pub fn announce_node(&self) -> error::Result<bool> {
+        let channel_manager = self
+            .channel_manager
+            .clone()
+            .ok_or(error::anyhow!("channel manager is None"))?;
+        let our_node = gossip::NodeId::from_pubkey(&channel_manager.manager().get_our_node_id());
+        let graph = channel_manager.graph();
+        let channels = graph
+            .read_only()
+            .node(&our_node)
+            .map(|node| node.channels.len())
+            .unwrap_or_default();
+        if channels == 0 {
+            return Ok(false);
+        }
+
+        let now = unix_now();
+        let mut last_announcement = self.last_announcement.lock().unwrap();
+        if let Some(last) = *last_announcement {
+            if channels <= last.channels && now < last.at + self.conf.announce_interval_secs {
+                return Ok(false);
+            }
+        }
+        let mut alias = [0u8; 32];
+        if let Some(ref conf_alias) = self.conf.alias {
+            for (byte, alias_byte) in alias.iter_mut().zip(conf_alias.as_bytes()) {
+                *byte = *alias_byte;
+            }
+        }
+        self.manager()
+            .broadcast_node_announcement(self.conf.rgb_color, alias, self.announced_addrs());
+        log::info!(target: "lampo", "node announcement broadcasted with {channels} channels");
+        *last_announcement = Some(NodeAnnouncement { at: now, channels });
+        Ok(true)
+    }
+
+    /// Unix timestamp of the last node announcement broadcasted.
+    pub fn last_announcement(&self) -> Option<u64> {
+        self.last_announcement
+            .lock()
+            .unwrap()
+            .map(|announcement| announcement.at)
+    }
+
     pub fn is_connected_with(&self, peer_id: NodeId) -> bool {
         let Some(ref manager) = self.peer_manager else {
             panic!("at this point the peer manager should be known");
         };
         manager.peer_by_node_id(&peer_id).is_some()
     }
+
+    /// List the connected peers.
+    pub fn list_peers(&self) -> error::Result<Peers> {
+        let channel_manager = self
+            .channel_manager
+            .clone()
+            .ok_or(error::anyhow!("channel manager is None"))?;
+        let peers = self.manager().list_peers();
+        let now = unix_now();
+        let mut connected_since = self.connected_since.lock().unwrap();
+        connected_since.retain(|node_id, _| {
+            peers
+                .iter()
+                .any(|peer| peer.counterparty_node_id == *node_id)
+        });
+        let graph = channel_manager.graph();
+        let graph = graph.read_only();
+        let alias = |node_id| {
+            graph
+                .node(&gossip::NodeId::from_pubkey(node_id))
+                .and_then(|node| node.announcement_info.as_ref())
+                .map(|info| info.alias.to_string())
+        };
+        let channels = channel_manager.manager().list_channels();
+        let mut offline = channels
+            .iter()
+            .map(|channel| channel.counterparty.node_id)
+            .filter(|node_id| {
+                !peers
+                    .iter()
+                    .any(|peer| peer.counterparty_node_id == *node_id)
+            })
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .map(|node_id| Peer {
+                node_id: node_id.to_string(),
+                address: self.book.addresses(&node_id).first().cloned(),
+                inbound: false,
+                connected_secs: 0,
+                features: String::new(),
+                channels: channels
+                    .iter()
+                    .filter(|channel| channel.counterparty.node_id == node_id)
+                    .count(),
+                alias: alias(&node_id),
+                connected: false,
+                reconnect: self.book.reconnect_state(&node_id),
+                last_pong: self.ping.last_pong(&node_id),
+            })
+            .collect::<Vec<_>>();
+        let mut peers = peers
+            .into_iter()
+            .map(|peer| {
+                let node_id = peer.counterparty_node_id;
+                let since = *connected_since.entry(node_id).or_insert(now);
+                // the features are encoded as big endian, like in the spec
+                let mut features = peer.init_features.le_flags().to_vec();
+                features.reverse();
+                Peer {
+                    node_id: node_id.to_string(),
+                    address: peer.socket_address.map(|addr| addr.to_string()),
+                    inbound: peer.is_inbound_connection,
+                    connected_secs: now.saturating_sub(since),
+                    features: features.to_hex(),
+                    channels: channels
+                        .iter()
+                        .filter(|channel| channel.counterparty.node_id == node_id)
+                        .count(),
+                    alias: alias(&node_id),
+                    connected: true,
+                    reconnect: None,
+                    last_pong: self.ping.last_pong(&node_id),
+                }
+            })
+            .collect::<Vec<_>>();
+        peers.append(&mut offline);
+        Ok(Peers { peers })
+    }
+
+    /// Send `count` pings to the peer, one after the other, and
+    /// measure the round trip time of each of them.
+    pub fn ping(&self, node_id: NodeId, count: u32) -> error::Result<response::Ping> {
+        let manager = self.manager();
+        let Some(peer) = manager.peer_by_node_id(&node_id) else {
+            error::bail!("peer `{node_id}` is not connected");
+        };
+        if !LampoPing::is_supported_by(&peer.init_features) {
+            error::bail!("peer `{node_id}` does not support the lampo ping");
+        }
+        let mut rtts = Vec::new();
+        for _ in 0..count {
+            let (sender, receiver) = chan::bounded(1);
+            let sent_at = Instant::now();
+            let id = self.ping.queue_ping(node_id, Some(sender), unix_now());
+            manager.process_events();
+            match receiver.recv_timeout(PONG_TIMEOUT) {
+                Ok(_) => rtts.push(sent_at.elapsed().as_secs_f64() * 1000.0),
+                Err(_) => {
+                    log::debug!(target: "lampo", "ping `{id}` to `{node_id}` timed out");
+                    self.ping.forget_ping(id);
+                }
+            }
+        }
+        let received = rtts.len() as u32;
+        let avg_ms = (!rtts.is_empty()).then(|| rtts.iter().sum::<f64>() / rtts.len() as f64);
+        Ok(response::Ping {
+            node_id: node_id.to_string(),
+            sent: count,
+            received,
+            loss_percent: (count - received) as f64 * 100.0 / count as f64,
+            min_ms: rtts.iter().copied().reduce(f64::min),
+            avg_ms,
+            max_ms: rtts.iter().copied().reduce(f64::max),
+        })
+    }
+
+    /// Stop accepting the inbound connections and disconnect all
+    /// the peers, used on shutdown.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        self.manager().disconnect_all_peers();
+    }
+
+    /// Emit an event for the peers connected or disconnected since the
+    /// last call, ldk does not tell us about them so we poll the peers.
+    pub fn notify_connections(&self, handler: &Arc<LampoHandler>) {
+        let peers = self
+            .manager()
+            .list_peers()
+            .iter()
+            .map(|peer| peer.counterparty_node_id)
+            .collect::<HashSet<_>>();
+        let mut connected = self.connected.lock().unwrap();
+        for node_id in peers.difference(&connected) {
+            handler.emit(Event::Lightning(LightningEvent::PeerConnect {
+                counterparty_node_id: *node_id,
+            }));
+        }
+        for node_id in connected.difference(&peers) {
+            handler.emit(Event::Lightning(LightningEvent::PeerDisconnect {
+                counterparty_node_id: *node_id,
+            }));
+        }
+        *connected = peers;
+    }
+
+    /// Ping all the connected peers that support it, so a connection
+    /// that is dead but still open is detected by the missing pongs.
+    ///
+    /// With `ping-disconnect-secs` the peers that do not answer for
+    /// so long are disconnected.
+    pub fn ping_peers(&self) {
+        let manager = self.manager();
+        let now = unix_now();
+        let peers = manager.list_peers();
+        // a peer that reconnected starts again without pings in flight
+        self.ping.retain_peers(
+            &peers
+                .iter()
+                .map(|peer| peer.counterparty_node_id)
+                .collect::<Vec<_>>(),
+        );
+        for peer in peers {
+            let node_id = peer.counterparty_node_id;
+            if !LampoPing::is_supported_by(&peer.init_features) {
+                continue;
+            }
+            if let (Some(limit), Some(since)) = (
+                self.conf.ping_disconnect_secs,
+                self.ping.waiting_since(&node_id),
+            ) {
+                if now.saturating_sub(since) >= limit {
+                    log::warn!(
+                        target: "lampo",
+                        "peer `{node_id}` did not answer our pings for {limit} seconds, disconnecting"
+                    );
+                    manager.disconnect_by_node_id(node_id);
+                    self.ping.peer_disconnected(&node_id);
+                    self.connected_since.lock().unwrap().remove(&node_id);
+                    continue;
+                }
+            }
+            self.ping.queue_ping(node_id, None, now);
+        }
+        manager.process_events();
+    }
+
+    /// The custom onion messages that we receive.
+    pub fn onion_messages(&self) -> Arc<LampoOnionMessages> {
+        self.onion_messages.clone()
+    }
+
+    /// Send a custom onion message to the destination, with our reply
+    /// path if `reply_path` is true.
+    ///
+    /// Return true if the message waits for a connection with the
+    /// first hop of the path.
+    pub fn send_onion_message(
+        &self,
+        message: CustomOnionMessage,
+        destination: Destination,
+        reply_path: bool,
+    ) -> error::Result<bool> {
+        if message.tlv_type < MIN_CUSTOM_TLV_TYPE {
+            error::bail!("the tlv type must be at least {MIN_CUSTOM_TLV_TYPE}");
+        }
+        let (Some(ref messenger), Some(ref keys), Some(ref channel_manager)) =
+            (&self.onion_messenger, &self.keys, &self.channel_manager)
+        else {
+            error::bail!("peer manager is not initialized");
+        };
+        let reply_path = if reply_path {
+            let our_node_id = channel_manager.manager().get_our_node_id();
+            let path = BlindedPath::one_hop_for_message(our_node_id, &**keys, &Secp256k1::new())
+                .map_err(|_| error::anyhow!("impossible create the reply path"))?;
+            Some(path)
+        } else {
+            None
+        };
+        let success = messenger
+            .send_onion_message(message, destination, reply_path)
+            .map_err(|err| error::anyhow!("impossible send the onion message: {err:?}"))?;
+        self.manager().process_events();
+        Ok(matches!(success, SendSuccess::BufferedAwaitingConnection(_)))
+    }
+
+    /// Answer the received onion message `id` through its reply path.
+    pub fn reply_onion_message(&self, id: u64, message: CustomOnionMessage) -> error::Result<()> {
+        if message.tlv_type < MIN_CUSTOM_TLV_TYPE {
+            error::bail!("the tlv type must be at least {MIN_CUSTOM_TLV_TYPE}");
+        }
+        let Some(ref messenger) = self.onion_messenger else {
+            error::bail!("peer manager is not initialized");
+        };
+        let responder = self.onion_messages.take_responder(id)?;
+        messenger.handle_onion_message_response(responder.respond(message));
+        self.manager().process_events();
+        Ok(())
+    }
+
+    /// Try to reconnect to the peers we have channels with, each peer
+    /// is retried with an exponential backoff.
+    pub async fn reconnect_peers(&self) {
+        let Some(ref channel_manager) = self.channel_manager else {
+            return;
+        };
+        let now = unix_now();
+        let peers = channel_manager
+            .manager()
+            .list_channels()
+            .iter()
+            .map(|channel| channel.counterparty.node_id)
+            .collect::<HashSet<_>>();
+        for node_id in peers {
+            if self.manager().peer_by_node_id(&node_id).is_some() {
+                // the peer may reconnected to us
+                self.book.record_success(&node_id);
+                continue;
+            }
+            if !self.book.should_reconnect(&node_id, now) {
+                continue;
+            }
+            let mut result = Err(error::anyhow!("no address known for the peer"));
+            for addr in self.peer_addresses(&node_id) {
+                result = self.connect(node_id, addr).await;
+                if result.is_ok() {
+                    break;
+                }
+            }
+            match result {
+                Ok(_) => log::info!(target: "lampo", "reconnected with peer `{node_id}`"),
+                Err(err) => {
+                    log::debug!(target: "lampo", "impossible reconnect with peer `{node_id}`: {err}");
+                    self.book.record_failure(&node_id, now, &err.to_string());
+                }
+            }
+        }
+    }
+
+    /// The known addresses of the peer, the one where we were
+    /// connected before first and then the one inside the
+    /// node announcement.
+    fn peer_addresses(&self, node_id: &NodeId) -> Vec<SocketAddress> {
+        let mut addresses = self
+            .book
+            .addresses(node_id)
+            .iter()
+            .filter_map(|addr| SocketAddress::from_str(addr).ok())
+            .collect::<Vec<_>>();
+        if let Some(ref channel_manager) = self.channel_manager {
+            let graph = channel_manager.graph();
+            let graph = graph.read_only();
+            if let Some(info) = graph
+                .node(&gossip::NodeId::from_pubkey(node_id))
+                .and_then(|node| node.announcement_info.as_ref())
+            {
+                addresses.extend(info.addresses().iter().cloned());
+            }
+        }
+        addresses.dedup();
+        addresses
+    }
 }
 
 #[async_trait]
@@ -219,10 +750,11 @@ impl PeerEvents for LampoPeerManager {
     async fn handle(&self, event: super::peer_event::PeerCommand) -> error::Result<()> {
         match event {
             peer_event::PeerCommand::Connect(node_id, addr, chan) => {
+                let (host, port) = tor::host_and_port(&addr)?;
                 let connect = Connect {
                     node_id: node_id.to_string(),
-                    addr: addr.ip().to_string(),
-                    port: addr.port() as u64,
+                    addr: host,
+                    port: port as u64,
                 };
                 self.connect(node_id, addr).await?;
                 chan.send(connect)?;
@@ -231,36 +763,93 @@ impl PeerEvents for LampoPeerManager {
         Ok(())
     }
 
-    async fn connect(&self, node_id: NodeId, host: SocketAddr) -> error::Result<()> {
-        let Some(close_callback) = net::connect_outbound(self.manager(), node_id, host).await
-        else {
-            error::bail!("impossible connect with the peer `{node_id}`");
+    async fn connect(&self, node_id: NodeId, host: SocketAddress) -> error::Result<()> {
+        let manager = self.manager();
+        if self.filter.is_denied(&node_id) {
+            error::bail!("peer `{node_id}` is inside the deny list");
+        }
+        // connecting twice is not an error
+        if manager.peer_by_node_id(&node_id).is_some() {
+            return Ok(());
+        }
+        let close_callback = if tor::is_onion(&host) || self.conf.tor_only {
+            let Some(proxy) = self.conf.proxy else {
+                error::bail!("a `proxy` is needed to connect to `{host}`");
+            };
+            let (addr, port) = tor::host_and_port(&host)?;
+            let stream =
+                tokio::task::spawn_blocking(move || tor::socks5_connect(proxy, &addr, port))
+                    .await??;
+            log::debug!(target: "tor", "connected to `{host}` through the proxy `{proxy}`");
+            futures::future::Either::Left(net::setup_outbound(manager.clone(), node_id, stream))
+        } else {
+            let addr = host
+                .to_socket_addrs()
+                .map_err(|err| error::anyhow!("impossible resolve `{host}`: {err}"))?
+                .next()
+                .ok_or(error::anyhow!("no address found for `{host}`"))?;
+            let Some(close_callback) =
+                net::connect_outbound(manager.clone(), node_id, addr).await
+            else {
+                error::bail!("impossible connect with the peer `{node_id}`");
+            };
+            futures::future::Either::Right(close_callback)
         };
         let mut connection_closed_future = Box::pin(close_callback);
-        let manager = self.manager();
-        loop {
-            match futures::poll!(&mut connection_closed_future) {
-                std::task::Poll::Ready(_) => {
-                    log::info!("node `{node_id}` disconnected");
-                    return Ok(());
+        let handshake = async {
+            loop {
+                match futures::poll!(&mut connection_closed_future) {
+                    std::task::Poll::Ready(_) => {
+                        error::bail!("node `{node_id}` disconnected during the handshake");
+                    }
+                    std::task::Poll::Pending => {}
+                }
+                // Avoid blocking the tokio context by sleeping a bit
+                match manager.peer_by_node_id(&node_id) {
+                    Some(_) => return Ok(()),
+                    None => tokio::time::sleep(Duration::from_millis(10)).await,
                 }
-                std::task::Poll::Pending => {}
-            }
-            // Avoid blocking the tokio context by sleeping a bit
-            match manager.peer_by_node_id(&node_id) {
-                Some(_) => return Ok(()),
-                None => tokio::time::sleep(Duration::from_millis(10)).await,
             }
-        }
+        };
+        tokio::time::timeout(CONNECT_TIMEOUT, handshake)
+            .await
+            .map_err(|_| error::anyhow!("timeout during the handshake with `{node_id}`"))??;
+        self.connected_since
+            .lock()
+            .unwrap()
+            .insert(node_id, unix_now());
+        self.book.record_success(&node_id);
+        self.book.record_address(&node_id, &host.to_string())?;
+        Ok(())
     }
 
-    async fn disconnect(&self, node_id: NodeId) -> error::Result<()> {
+    async fn disconnect(&self, node_id: NodeId, force: bool) -> error::Result<()> {
         //check the pubkey matches a valid connected peer
         if self.manager().peer_by_node_id(&node_id).is_none() {
             error::bail!("Error: Could not find peer `{node_id}`");
         }
-
+        if !force {
+            let channel_manager = self
+                .channel_manager
+                .clone()
+                .ok_or(error::anyhow!("channel manager is None"))?;
+            let htlcs = channel_manager
+                .manager()
+                .list_channels_with_counterparty(&node_id)
+                .iter()
+                .map(|channel| {
+                    channel.pending_inbound_htlcs.len() + channel.pending_outbound_htlcs.len()
+                })
+                .sum::<usize>();
+            if htlcs > 0 {
+                error::bail!(
+                    "peer `{node_id}` has `{htlcs}` htlcs in flight, use `force` to disconnect anyway"
+                );
+            }
+        }
         self.manager().disconnect_by_node_id(node_id);
+        self.ping.peer_disconnected(&node_id);
+        self.connected_since.lock().unwrap().remove(&node_id);
         Ok(())
     }
 }
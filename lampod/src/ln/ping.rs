@@ -0,0 +1,210 @@
+//! Ping between lampo peers to measure the latency.
+//!
+//! ldk answers the lightning pings by itself, and it does not tell
+//! us when a pong arrives, so lampo uses its own ping and pong as
+//! custom messages. The message types are odd, so the peers that do
+//! not know them ignore them, and the support is advertised with a
+//! custom feature bit inside the init message.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use lampo_common::chan;
+use lampo_common::ldk::io;
+use lampo_common::ldk::ln::features::{InitFeatures, NodeFeatures};
+use lampo_common::ldk::ln::msgs::{DecodeError, LightningError};
+use lampo_common::ldk::ln::peer_handler::CustomMessageHandler;
+use lampo_common::ldk::ln::wire::{CustomMessageReader, Type};
+use lampo_common::ldk::util::ser::{Readable, Writeable, Writer};
+use lampo_common::sync::MutexExt;
+use lampo_common::time::unix_now;
+use lampo_common::types::NodeId;
+
+/// The optional feature bit that tells that the peer answers our pings.
+pub const PING_FEATURE_BIT: usize = 257;
+
+/// Seconds between two pings sent to check that the peers are alive.
+pub const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+const PING_TYPE: u16 = 48879;
+const PONG_TYPE: u16 = 48881;
+
+#[derive(Debug, Clone)]
+pub enum PingMessage {
+    Ping(u64),
+    Pong(u64),
+}
+
+impl Type for PingMessage {
+    fn type_id(&self) -> u16 {
+        match self {
+            Self::Ping(_) => PING_TYPE,
+            Self::Pong(_) => PONG_TYPE,
+        }
+    }
+}
+
+impl Writeable for PingMessage {
+    fn write<W: Writer>(&self, writer: &mut W) -> Result<(), io::Error> {
+        match self {
+            Self::Ping(id) | Self::Pong(id) => id.write(writer),
+        }
+    }
+}
+
+/// A ping that is waiting for its pong.
+struct InFlightPing {
+    node_id: NodeId,
+    /// Notified when the pong arrives, `None` for the pings
+    /// sent to check that the peer is alive.
+    waiter: Option<chan::Sender<()>>,
+}
+
+#[derive(Default)]
+pub struct LampoPing {
+    pending_msgs: Mutex<Vec<(NodeId, PingMessage)>>,
+    in_flight: Mutex<HashMap<u64, InFlightPing>>,
+    /// Unix timestamp of the last pong received from the peer.
+    last_pong: Mutex<HashMap<NodeId, u64>>,
+    /// Unix timestamp of the oldest ping that is still waiting
+    /// for a pong from the peer.
+    waiting_since: Mutex<HashMap<NodeId, u64>>,
+    next_id: AtomicU64,
+}
+
+impl LampoPing {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a ping for the peer and return its id, the ping is sent
+    /// when the peer manager processes the events.
+    pub fn queue_ping(
+        &self,
+        node_id: NodeId,
+        waiter: Option<chan::Sender<()>>,
+        now: u64,
+    ) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.in_flight
+            .lock_or_recover()
+            .insert(id, InFlightPing { node_id, waiter });
+        self.waiting_since
+            .lock_or_recover()
+            .entry(node_id)
+            .or_insert(now);
+        self.pending_msgs
+            .lock_or_recover()
+            .push((node_id, PingMessage::Ping(id)));
+        id
+    }
+
+    /// Forget a ping that did not get a pong in time.
+    pub fn forget_ping(&self, id: u64) {
+        self.in_flight.lock_or_recover().remove(&id);
+    }
+
+    /// Unix timestamp of the last pong received from the peer.
+    pub fn last_pong(&self, node_id: &NodeId) -> Option<u64> {
+        self.last_pong.lock_or_recover().get(node_id).copied()
+    }
+
+    /// Unix timestamp of the oldest ping without a pong.
+    pub fn waiting_since(&self, node_id: &NodeId) -> Option<u64> {
+        self.waiting_since.lock_or_recover().get(node_id).copied()
+    }
+
+    /// Forget the state of a peer that is disconnected.
+    pub fn peer_disconnected(&self, node_id: &NodeId) {
+        self.waiting_since.lock_or_recover().remove(node_id);
+        self.in_flight
+            .lock_or_recover()
+            .retain(|_, ping| ping.node_id != *node_id);
+    }
+
+    /// Forget the state of all the peers that are not connected.
+    pub fn retain_peers(&self, connected: &[NodeId]) {
+        self.waiting_since
+            .lock_or_recover()
+            .retain(|node_id, _| connected.contains(node_id));
+        self.in_flight
+            .lock_or_recover()
+            .retain(|_, ping| connected.contains(&ping.node_id));
+    }
+
+    /// Return true if the peer advertised that it answers our pings.
+    pub fn is_supported_by(features: &InitFeatures) -> bool {
+        features
+            .le_flags()
+            .get(PING_FEATURE_BIT / 8)
+            .is_some_and(|byte| byte & (1 << (PING_FEATURE_BIT % 8)) != 0)
+    }
+}
+
+impl CustomMessageReader for LampoPing {
+    type CustomMessage = PingMessage;
+
+    fn read<R: io::Read>(
+        &self,
+        message_type: u16,
+        buffer: &mut R,
+    ) -> Result<Option<Self::CustomMessage>, DecodeError> {
+        match message_type {
+            PING_TYPE => Ok(Some(PingMessage::Ping(Readable::read(buffer)?))),
+            PONG_TYPE => Ok(Some(PingMessage::Pong(Readable::read(buffer)?))),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl CustomMessageHandler for LampoPing {
+    fn handle_custom_message(
+        &self,
+        msg: Self::CustomMessage,
+        sender_node_id: &NodeId,
+    ) -> Result<(), LightningError> {
+        match msg {
+            PingMessage::Ping(id) => {
+                self.pending_msgs
+                    .lock_or_recover()
+                    .push((*sender_node_id, PingMessage::Pong(id)));
+            }
+            PingMessage::Pong(id) => {
+                let mut in_flight = self.in_flight.lock_or_recover();
+                // a pong for a ping that we did not send, or that
+                // was sent by another peer, is ignored.
+                if in_flight
+                    .get(&id)
+                    .is_some_and(|ping| ping.node_id == *sender_node_id)
+                {
+                    // SAFETY: we just checked that the ping exists.
+                    let ping = in_flight.remove(&id).unwrap();
+                    if let Some(waiter) = ping.waiter {
+                        let _ = waiter.send(());
+                    }
+                    self.last_pong
+                        .lock_or_recover()
+                        .insert(*sender_node_id, unix_now());
+                    self.waiting_since.lock_or_recover().remove(sender_node_id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn get_and_clear_pending_msg(&self) -> Vec<(NodeId, Self::CustomMessage)> {
+        std::mem::take(&mut *self.pending_msgs.lock_or_recover())
+    }
+
+    fn provided_node_features(&self) -> NodeFeatures {
+        NodeFeatures::empty()
+    }
+
+    fn provided_init_features(&self, _their_node_id: &NodeId) -> InitFeatures {
+        let mut features = InitFeatures::empty();
+        // SAFETY: the bit is a custom bit, so it is accepted.
+        features.set_optional_custom_bit(PING_FEATURE_BIT).unwrap();
+        features
+    }
+}
@@ -0,0 +1,81 @@
+//! Rapid Gossip Sync implementation.
+//!
+//! A fresh node needs a lot of time to learn the network graph
+//! with the p2p gossip, so when the user configures an RGS server
+//! lampo downloads a snapshot of the graph at startup and keeps it
+//! updated by downloading only the changes since the last snapshot.
+use std::sync::Arc;
+use std::time::Duration;
+
+use lampo_common::error;
+use lampo_common::ldk::rgs::RapidGossipSync;
+
+use crate::ln::channel_manager::LampoGraph;
+use crate::utils::logger::LampoLogger;
+
+pub type LampoRapidGossipSync = RapidGossipSync<Arc<LampoGraph>, Arc<LampoLogger>>;
+
+/// Timeout of the snapshot download.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct LampoRapidGossip {
+    url: String,
+    graph: Arc<LampoGraph>,
+    inner: Arc<LampoRapidGossipSync>,
+}
+
+impl LampoRapidGossip {
+    pub fn new(url: &str, graph: Arc<LampoGraph>, logger: Arc<LampoLogger>) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_owned(),
+            inner: Arc::new(RapidGossipSync::new(graph.clone(), logger)),
+            graph,
+        }
+    }
+
+    pub fn inner(&self) -> Arc<LampoRapidGossipSync> {
+        self.inner.clone()
+    }
+
+    /// Download the changes since the last snapshot applied, and
+    /// apply them to the network graph.
+    ///
+    /// The timestamp of the last snapshot is stored inside the graph,
+    /// so a graph loaded from disk is updated incrementally.
+    pub fn sync(&self) -> error::Result<u32> {
+        let last_sync = self
+            .graph
+            .get_last_rapid_gossip_sync_timestamp()
+            .unwrap_or_default();
+        let url = format!("{}/{last_sync}", self.url);
+        log::info!(target: "rgs", "downloading the rapid gossip snapshot from `{url}`");
+        let response = minreq::get(&url)
+            .with_timeout(DOWNLOAD_TIMEOUT.as_secs())
+            .send()?;
+        if response.status_code != 200 {
+            error::bail!(
+                "rapid gossip server replied with `{} {}`",
+                response.status_code,
+                response.reason_phrase
+            );
+        }
+        self.apply(response.as_bytes())
+    }
+
+    /// Apply a snapshot to the network graph, and return the
+    /// timestamp of the snapshot.
+    pub fn apply(&self, snapshot: &[u8]) -> error::Result<u32> {
+        let timestamp = self
+            .inner
+            .update_network_graph(snapshot)
+            .map_err(|err| error::anyhow!("impossible apply the rapid gossip snapshot: {:?}", err))?;
+        let graph = self.graph.read_only();
+        log::info!(
+            target: "rgs",
+            "rapid gossip snapshot applied, the graph has {} nodes and {} channels",
+            graph.nodes().len(),
+            graph.channels().len()
+        );
+        Ok(timestamp)
+    }
+}
@@ -0,0 +1,78 @@
+//! Sweeper of the outputs given back by a closed channel.
+//!
+//! ldk generates the `SpendableOutputs` event only once, so the
+//! descriptors are given to the `OutputSweeper` of ldk, that persists
+//! them and spends them again at every block with the current fee rate,
+//! until the spending transaction is buried under enough blocks.
+use std::io;
+use std::io::Cursor;
+use std::sync::Arc;
+
+use lampo_common::error;
+use lampo_common::keys::LampoKeysManager;
+use lampo_common::ldk::chain::BestBlock;
+use lampo_common::ldk::util::persist::{
+    KVStore, OUTPUT_SWEEPER_PERSISTENCE_KEY, OUTPUT_SWEEPER_PERSISTENCE_PRIMARY_NAMESPACE,
+    OUTPUT_SWEEPER_PERSISTENCE_SECONDARY_NAMESPACE,
+};
+use lampo_common::ldk::util::ser::ReadableArgs;
+use lampo_common::ldk::util::sweep::OutputSweeper;
+use lampo_common::wallet::WalletManager;
+
+use crate::chain::{LampoChainManager, LampoWalletSource};
+use crate::persistence::LampoPersistence;
+use crate::utils::logger::LampoLogger;
+
+pub type LampoSweeper = OutputSweeper<
+    Arc<LampoChainManager>,
+    Arc<LampoWalletSource>,
+    Arc<LampoChainManager>,
+    Arc<LampoChainManager>,
+    Arc<LampoPersistence>,
+    Arc<LampoLogger>,
+    Arc<LampoKeysManager>,
+>;
+
+/// Load the sweeper with the outputs that are not swept yet, or build
+/// a new one at `best_block` when nothing was persisted.
+pub fn load_sweeper(
+    best_block: BestBlock,
+    onchain: Arc<LampoChainManager>,
+    wallet_manager: Arc<dyn WalletManager>,
+    persister: Arc<LampoPersistence>,
+    logger: Arc<LampoLogger>,
+) -> error::Result<LampoSweeper> {
+    let keys = wallet_manager.ldk_keys().inner();
+    let destination = Arc::new(LampoWalletSource::new(wallet_manager));
+    let state = persister.read(
+        OUTPUT_SWEEPER_PERSISTENCE_PRIMARY_NAMESPACE,
+        OUTPUT_SWEEPER_PERSISTENCE_SECONDARY_NAMESPACE,
+        OUTPUT_SWEEPER_PERSISTENCE_KEY,
+    );
+    match state {
+        Ok(state) => {
+            let args = (
+                onchain.clone(),
+                onchain.clone(),
+                Some(onchain),
+                keys,
+                destination,
+                persister,
+                logger,
+            );
+            LampoSweeper::read(&mut Cursor::new(state), args)
+                .map_err(|err| error::anyhow!("impossible read the output sweeper: {err}"))
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(OutputSweeper::new(
+            best_block,
+            onchain.clone(),
+            onchain.clone(),
+            Some(onchain),
+            keys,
+            destination,
+            persister,
+            logger,
+        )),
+        Err(err) => Err(err.into()),
+    }
+}
@@ -0,0 +1,178 @@
+//! Tor support for the p2p layer.
+//!
+//! The outbound connections go through a SOCKS5 proxy, and the
+//! destination is always sent to the proxy as a domain name, so an
+//! onion address is never resolved locally. The hidden service is
+//! created with the control port, and it lives as long as the control
+//! connection is open.
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use lampo_common::error;
+use lampo_common::ldk::ln::msgs::SocketAddress;
+
+/// Timeout of the connection with the proxy, and of the
+/// SOCKS5 handshake.
+const PROXY_TIMEOUT: Duration = Duration::from_secs(30);
+
+const SOCKS_VERSION: u8 = 5;
+const SOCKS_NO_AUTH: u8 = 0;
+const SOCKS_CMD_CONNECT: u8 = 1;
+const SOCKS_ATYP_IPV4: u8 = 1;
+const SOCKS_ATYP_DOMAIN: u8 = 3;
+const SOCKS_ATYP_IPV6: u8 = 4;
+
+/// Return the host and the port to dial for the address.
+pub fn host_and_port(addr: &SocketAddress) -> error::Result<(String, u16)> {
+    match addr {
+        SocketAddress::TcpIpV4 { .. }
+        | SocketAddress::TcpIpV6 { .. }
+        | SocketAddress::OnionV3 { .. } => {
+            let addr = addr.to_string();
+            // SAFETY: the display of these addresses is `host:port`.
+            let (host, port) = addr.rsplit_once(':').unwrap();
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            Ok((host.to_owned(), port.parse()?))
+        }
+        SocketAddress::Hostname { hostname, port } => Ok((hostname.to_string(), *port)),
+        SocketAddress::OnionV2(_) => error::bail!("onion v2 addresses are not supported"),
+    }
+}
+
+/// Return true if the address can be reached only through Tor.
+pub fn is_onion(addr: &SocketAddress) -> bool {
+    matches!(
+        addr,
+        SocketAddress::OnionV2(_) | SocketAddress::OnionV3 { .. }
+    )
+}
+
+/// Open a connection with `host:port` through the SOCKS5 `proxy`.
+pub fn socks5_connect(proxy: SocketAddr, host: &str, port: u16) -> error::Result<TcpStream> {
+    if host.len() > u8::MAX as usize {
+        error::bail!("the host `{host}` is too long for the proxy");
+    }
+    let mut stream = TcpStream::connect_timeout(&proxy, PROXY_TIMEOUT)
+        .map_err(|err| error::anyhow!("impossible connect to the proxy `{proxy}`: {err}"))?;
+    stream.set_read_timeout(Some(PROXY_TIMEOUT))?;
+    stream.set_write_timeout(Some(PROXY_TIMEOUT))?;
+
+    stream.write_all(&[SOCKS_VERSION, 1, SOCKS_NO_AUTH])?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply)?;
+    if reply != [SOCKS_VERSION, SOCKS_NO_AUTH] {
+        error::bail!("the proxy `{proxy}` refused the authentication method");
+    }
+
+    let mut request = vec![
+        SOCKS_VERSION,
+        SOCKS_CMD_CONNECT,
+        0,
+        SOCKS_ATYP_DOMAIN,
+        host.len() as u8,
+    ];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request)?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[0] != SOCKS_VERSION {
+        error::bail!("invalid reply from the proxy `{proxy}`");
+    }
+    if reply[1] != 0 {
+        error::bail!(
+            "the proxy `{proxy}` is not able to connect to `{host}:{port}`: error code {}",
+            reply[1]
+        );
+    }
+    // skip the address bound by the proxy, plus the port
+    let bound_len = match reply[3] {
+        SOCKS_ATYP_IPV4 => 4,
+        SOCKS_ATYP_IPV6 => 16,
+        SOCKS_ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        atyp => error::bail!("invalid address type `{atyp}` from the proxy `{proxy}`"),
+    };
+    let mut bound = vec![0u8; bound_len + 2];
+    stream.read_exact(&mut bound)?;
+
+    stream.set_read_timeout(None)?;
+    stream.set_write_timeout(None)?;
+    Ok(stream)
+}
+
+/// An ephemeral hidden service, removed by Tor when this is dropped.
+pub struct TorHiddenService {
+    /// The control connection that owns the service.
+    _control: TcpStream,
+    pub onion_addr: SocketAddress,
+}
+
+impl TorHiddenService {
+    /// Create a hidden service that forwards `port` to `target`.
+    pub fn create(
+        control: SocketAddr,
+        password: Option<&str>,
+        port: u16,
+        target: SocketAddr,
+    ) -> error::Result<Self> {
+        let stream = TcpStream::connect_timeout(&control, PROXY_TIMEOUT).map_err(|err| {
+            error::anyhow!("impossible connect to the tor control port `{control}`: {err}")
+        })?;
+        stream.set_read_timeout(Some(PROXY_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+
+        let auth = match password {
+            Some(password) => format!("AUTHENTICATE \"{}\"\r\n", password.replace('"', "\\\"")),
+            None => "AUTHENTICATE\r\n".to_owned(),
+        };
+        writer.write_all(auth.as_bytes())?;
+        Self::read_reply(&mut reader)?;
+
+        let cmd = format!("ADD_ONION NEW:ED25519-V3 Flags=DiscardPK Port={port},{target}\r\n");
+        writer.write_all(cmd.as_bytes())?;
+        let service_id = Self::read_reply(&mut reader)?
+            .iter()
+            .find_map(|line| line.strip_prefix("ServiceID=").map(str::to_owned))
+            .ok_or(error::anyhow!("tor did not return the onion address"))?;
+        let onion_addr = format!("{service_id}.onion:{port}")
+            .parse::<SocketAddress>()
+            .map_err(|err| error::anyhow!("invalid onion address `{service_id}`: {err}"))?;
+        log::info!(target: "tor", "hidden service `{onion_addr}` forwards to `{target}`");
+        Ok(Self {
+            _control: writer,
+            onion_addr,
+        })
+    }
+
+    /// Read a reply of the control port, and return its lines
+    /// without the status code.
+    fn read_reply(reader: &mut impl BufRead) -> error::Result<Vec<String>> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                error::bail!("tor closed the control connection");
+            }
+            let line = line.trim_end();
+            if line.len() < 4 {
+                error::bail!("invalid reply from tor `{line}`");
+            }
+            let (code, rest) = line.split_at(3);
+            if code != "250" {
+                error::bail!("tor replied with an error `{line}`");
+            }
+            lines.push(rest[1..].to_owned());
+            // the last line of the reply is `250 ...`
+            if rest.starts_with(' ') {
+                return Ok(lines);
+            }
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! Datastore for the applications built on lampo.
+//!
+//! A small key value store next to the node, where the keys are made
+//! of path segments. Every entry has a generation that is incremented
+//! at each change, so a client can change an entry only if nobody
+//! else changed it since the client read it.
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use lampo_common::error;
+use lampo_common::error::LampoError;
+use lampo_common::json;
+use lampo_common::ldk::util::persist::KVStore;
+use lampo_common::model::request::DatastoreMode;
+use lampo_common::model::response::DatastoreEntry;
+use lampo_common::sync::MutexExt;
+
+use crate::persistence::LampoPersistence;
+
+const LAMPO_NAMESPACE: &str = "lampo";
+const DATASTORE_KEY: &str = "datastore";
+/// Maximum size of a value in bytes.
+pub const MAX_VALUE_BYTES: usize = 64 * 1024;
+/// Maximum size of all the keys and values in bytes.
+pub const MAX_TOTAL_BYTES: usize = 4 * 1024 * 1024;
+
+type Entries = BTreeMap<Vec<String>, DatastoreEntry>;
+
+pub struct LampoDatastore {
+    persister: Arc<LampoPersistence>,
+    entries: Mutex<Entries>,
+}
+
+impl LampoDatastore {
+    pub fn new(persister: Arc<LampoPersistence>) -> Self {
+        let entries = Self::read(&persister).unwrap_or_else(|err| {
+            log::warn!(target: "lampo", "impossible read the datastore: {err}");
+            BTreeMap::new()
+        });
+        Self {
+            persister,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    fn read(persister: &LampoPersistence) -> error::Result<Entries> {
+        let buf = match persister.read(LAMPO_NAMESPACE, "", DATASTORE_KEY) {
+            Ok(buf) => buf,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let entries: Vec<DatastoreEntry> = json::from_slice(&buf)?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| (entry.key.clone(), entry))
+            .collect())
+    }
+
+    fn write(&self, entries: &Entries) -> error::Result<()> {
+        let buf = json::to_vec(&entries.values().collect::<Vec<_>>())?;
+        self.persister
+            .write(LAMPO_NAMESPACE, "", DATASTORE_KEY, &buf)?;
+        Ok(())
+    }
+
+    /// Store `value` under `key` as `mode` says, if `generation` is
+    /// given the entry must exist with that generation.
+    pub fn set(
+        &self,
+        key: Vec<String>,
+        value: String,
+        mode: DatastoreMode,
+        generation: Option<u64>,
+    ) -> error::Result<DatastoreEntry> {
+        check_key(&key)?;
+        let mut entries = self.entries.lock_or_recover();
+        let current = entries.get(&key);
+        if let Some(expected) = generation {
+            check_generation(&key, current, expected)?;
+        }
+        let entry = match (mode, current) {
+            (DatastoreMode::MustCreate, Some(_)) => {
+                return Err(LampoError::DatastoreKeyExists(key.join("/")).into());
+            }
+            (DatastoreMode::MustReplace, None) => {
+                return Err(LampoError::DatastoreKeyNotFound(key.join("/")).into());
+            }
+            (DatastoreMode::MustCreate | DatastoreMode::Append, None) => DatastoreEntry {
+                key: key.clone(),
+                value,
+                generation: 0,
+            },
+            (DatastoreMode::MustReplace, Some(current)) => DatastoreEntry {
+                key: key.clone(),
+                value,
+                generation: current.generation + 1,
+            },
+            (DatastoreMode::Append, Some(current)) => DatastoreEntry {
+                key: key.clone(),
+                value: format!("{}{value}", current.value),
+                generation: current.generation + 1,
+            },
+        };
+
+        if entry.value.len() > MAX_VALUE_BYTES {
+            return Err(LampoError::DatastoreTooLarge(format!(
+                "the value of {} bytes exceeds the maximum of {MAX_VALUE_BYTES} bytes",
+                entry.value.len()
+            ))
+            .into());
+        }
+        let total = entries.values().map(size).sum::<usize>() - current.map(size).unwrap_or(0)
+            + size(&entry);
+        if total > MAX_TOTAL_BYTES {
+            return Err(LampoError::DatastoreTooLarge(format!(
+                "the datastore exceeds the maximum of {MAX_TOTAL_BYTES} bytes"
+            ))
+            .into());
+        }
+
+        let previous = entries.insert(key.clone(), entry.clone());
+        if let Err(err) = self.write(&entries) {
+            match previous {
+                Some(previous) => entries.insert(key, previous),
+                None => entries.remove(&key),
+            };
+            return Err(err);
+        }
+        Ok(entry)
+    }
+
+    /// The entries with a key that starts with `prefix`, sorted by key.
+    pub fn list(&self, prefix: &[String]) -> Vec<DatastoreEntry> {
+        let entries = self.entries.lock_or_recover();
+        entries
+            .values()
+            .filter(|entry| entry.key.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+
+    /// Delete the entry of `key`, if `generation` is given the entry
+    /// must have that generation.
+    pub fn delete(&self, key: &[String], generation: Option<u64>) -> error::Result<DatastoreEntry> {
+        let mut entries = self.entries.lock_or_recover();
+        let Some(current) = entries.get(key) else {
+            return Err(LampoError::DatastoreKeyNotFound(key.join("/")).into());
+        };
+        if let Some(expected) = generation {
+            check_generation(key, Some(current), expected)?;
+        }
+        // SAFETY: the entry exists, it is checked above.
+        let entry = entries.remove(key).unwrap();
+        if let Err(err) = self.write(&entries) {
+            entries.insert(key.to_vec(), entry);
+            return Err(err);
+        }
+        Ok(entry)
+    }
+}
+
+fn check_key(key: &[String]) -> error::Result<()> {
+    if key.is_empty() || key.iter().any(|segment| segment.is_empty()) {
+        return Err(LampoError::InvalidParams(
+            "the key must have at least one segment, and no empty segment".to_owned(),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn check_generation(
+    key: &[String],
+    current: Option<&DatastoreEntry>,
+    expected: u64,
+) -> error::Result<()> {
+    let Some(current) = current else {
+        return Err(LampoError::DatastoreKeyNotFound(key.join("/")).into());
+    };
+    if current.generation != expected {
+        return Err(LampoError::DatastoreGenerationMismatch {
+            expected,
+            current: current.generation,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// The bytes that the entry counts for the total size.
+fn size(entry: &DatastoreEntry) -> usize {
+    entry.key.iter().map(String::len).sum::<usize>() + entry.value.len()
+}
@@ -0,0 +1,121 @@
+//! Maintenance of the stores of the node.
+//!
+//! The wallet store grows with every change appended to it, so it is
+//! rewritten with only the state that the changes give, and the channel
+//! monitors are read back to find the files that are corrupted.
+//!
+//! It runs also while the node is running: the wallet is locked only
+//! while its store is rewritten, and the monitors are read without
+//! locks because the monitors are replaced with a rename, so a file is
+//! never read while it is written.
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Instant;
+
+use lampo_common::bitcoin::BlockHash;
+use lampo_common::error;
+use lampo_common::ldk::chain::channelmonitor::ChannelMonitor;
+use lampo_common::ldk::sign::InMemorySigner;
+use lampo_common::ldk::util::persist::{
+    KVStore, CHANNEL_MONITOR_PERSISTENCE_PRIMARY_NAMESPACE,
+    CHANNEL_MONITOR_PERSISTENCE_SECONDARY_NAMESPACE,
+};
+use lampo_common::ldk::util::ser::ReadableArgs;
+use lampo_common::model::response::{CorruptedFile, Maintenance, StoreSize};
+use lampo_common::wallet::WalletManager;
+
+use crate::persistence::LampoPersistence;
+
+pub struct LampoMaintenance {
+    persister: Arc<LampoPersistence>,
+    wallet_manager: Arc<dyn WalletManager>,
+}
+
+impl LampoMaintenance {
+    pub fn new(persister: Arc<LampoPersistence>, wallet_manager: Arc<dyn WalletManager>) -> Self {
+        Self {
+            persister,
+            wallet_manager,
+        }
+    }
+
+    /// Compact the stores and check the integrity of the monitors.
+    pub fn run(&self) -> error::Result<Maintenance> {
+        let start = Instant::now();
+        let mut stores = self.wallet_manager.compact()?;
+        let (monitors, corrupted) = self.check_channel_monitors()?;
+        stores.push(monitors);
+        for file in &corrupted {
+            log::error!(target: "lampo", "corrupted {} `{}`: {}", file.name, file.path, file.error);
+        }
+        Ok(Maintenance {
+            stores,
+            corrupted,
+            elapsed_ms: start.elapsed().as_millis() as u64,
+        })
+    }
+
+    /// Read back every channel monitor, and return the size of the
+    /// monitors with the ones that can not be read.
+    fn check_channel_monitors(&self) -> error::Result<(StoreSize, Vec<CorruptedFile>)> {
+        let keys = self.wallet_manager.ldk_keys().inner();
+        let dir = self
+            .persister
+            .get_data_dir()
+            .join(CHANNEL_MONITOR_PERSISTENCE_PRIMARY_NAMESPACE);
+        let mut bytes = 0;
+        let mut corrupted = Vec::new();
+        let monitors = self.persister.list(
+            CHANNEL_MONITOR_PERSISTENCE_PRIMARY_NAMESPACE,
+            CHANNEL_MONITOR_PERSISTENCE_SECONDARY_NAMESPACE,
+        )?;
+        for key in monitors {
+            let path = dir.join(&key).display().to_string();
+            let buf = match self.persister.read(
+                CHANNEL_MONITOR_PERSISTENCE_PRIMARY_NAMESPACE,
+                CHANNEL_MONITOR_PERSISTENCE_SECONDARY_NAMESPACE,
+                &key,
+            ) {
+                Ok(buf) => buf,
+                Err(err) => {
+                    corrupted.push(corrupted_monitor(path, err.to_string()));
+                    continue;
+                }
+            };
+            bytes += buf.len() as u64;
+            let monitor = <(BlockHash, ChannelMonitor<InMemorySigner>)>::read(
+                &mut Cursor::new(&buf),
+                (&*keys, &*keys),
+            );
+            match monitor {
+                // the monitors are stored as `<txid>_<vout>` of the funding
+                Ok((_, monitor)) => {
+                    let (funding, _) = monitor.get_funding_txo();
+                    let expected = format!("{}_{}", funding.txid, funding.index);
+                    if key != expected {
+                        corrupted.push(corrupted_monitor(
+                            path,
+                            format!("the monitor is of the funding `{expected}`"),
+                        ));
+                    }
+                }
+                Err(err) => corrupted.push(corrupted_monitor(path, format!("{err:?}"))),
+            }
+        }
+        let size = StoreSize {
+            name: "channel_monitors".to_owned(),
+            path: dir.display().to_string(),
+            bytes_before: bytes,
+            bytes_after: bytes,
+        };
+        Ok((size, corrupted))
+    }
+}
+
+fn corrupted_monitor(path: String, error: String) -> CorruptedFile {
+    CorruptedFile {
+        name: "channel_monitor".to_owned(),
+        path,
+        error,
+    }
+}
@@ -0,0 +1,89 @@
+//! Persister of the channel monitors used by the chain monitor.
+//!
+//! The monitors are written by the store of the node, then the
+//! towers are told about the new commitments of the peer, after the
+//! update is durable.
+use std::sync::Arc;
+
+use lampo_common::ldk::chain::chaininterface::{ConfirmationTarget, FeeEstimator};
+use lampo_common::ldk::chain::chainmonitor::{MonitorUpdateId, Persist};
+use lampo_common::ldk::chain::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate};
+use lampo_common::ldk::chain::transaction::OutPoint;
+use lampo_common::ldk::chain::ChannelMonitorUpdateStatus;
+use lampo_common::ldk::sign::InMemorySigner;
+
+use super::LampoPersistence;
+use crate::chain::LampoChainManager;
+use crate::watchtower::LampoTowerClient;
+
+pub struct LampoMonitorPersister {
+    store: Arc<LampoPersistence>,
+    towers: Arc<LampoTowerClient>,
+    /// The fees of the justice transactions.
+    fees: Arc<LampoChainManager>,
+}
+
+impl LampoMonitorPersister {
+    pub fn new(
+        store: Arc<LampoPersistence>,
+        towers: Arc<LampoTowerClient>,
+        fees: Arc<LampoChainManager>,
+    ) -> Self {
+        Self {
+            store,
+            towers,
+            fees,
+        }
+    }
+
+    fn justice_feerate(&self) -> u32 {
+        self.fees
+            .get_est_sat_per_1000_weight(ConfirmationTarget::OnChainSweep)
+    }
+}
+
+impl Persist<InMemorySigner> for LampoMonitorPersister {
+    fn persist_new_channel(
+        &self,
+        funding_txo: OutPoint,
+        monitor: &ChannelMonitor<InMemorySigner>,
+        update_id: MonitorUpdateId,
+    ) -> ChannelMonitorUpdateStatus {
+        let status = self
+            .store
+            .as_ref()
+            .persist_new_channel(funding_txo, monitor, update_id);
+        if status == ChannelMonitorUpdateStatus::Completed {
+            // the channel goes on without the towers
+            if let Err(err) = self
+                .towers
+                .new_channel(funding_txo, monitor, self.justice_feerate())
+            {
+                log::error!(target: "watchtower", "impossible back up the channel `{funding_txo}`: {err}");
+            }
+        }
+        status
+    }
+
+    fn update_persisted_channel(
+        &self,
+        funding_txo: OutPoint,
+        update: Option<&ChannelMonitorUpdate>,
+        monitor: &ChannelMonitor<InMemorySigner>,
+        update_id: MonitorUpdateId,
+    ) -> ChannelMonitorUpdateStatus {
+        let status =
+            self.store
+                .as_ref()
+                .update_persisted_channel(funding_txo, update, monitor, update_id);
+        if let (ChannelMonitorUpdateStatus::Completed, Some(update)) = (status, update) {
+            if let Err(err) =
+                self.towers
+                    .channel_updated(funding_txo, update, monitor, self.justice_feerate())
+            {
+                log::error!(target: "watchtower", "impossible back up the channel `{funding_txo}`: {err}");
+            }
+        }
+        status
+    }
+}
@@ -0,0 +1,286 @@
+//! Replica of the channel state on a remote storage.
+//!
+//! The channel manager, the channel monitors and the invoices are
+//! written on the disk first, and then sent to a versioned storage
+//! over HTTP, authenticated with a bearer token:
+//!
+//! - `PUT <url>/objects/<key>` with the `X-Lampo-Version` header
+//!   stores the body, the storage answers `409` with its version when
+//!   it has the same version or a newer one;
+//! - `GET <url>/objects/<key>` gives the body with its version;
+//! - `GET <url>/objects` gives the `[{"key", "version"}]` stored.
+//!
+//! A write that can not reach the storage is retried later, and the
+//! writes fail when the storage lags more than `max_lag`.
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use lampo_common::error;
+use lampo_common::json;
+use lampo_common::ldk::persister::fs_store::FilesystemStore;
+use lampo_common::ldk::util::persist::KVStore;
+use lampo_common::sync::MutexExt;
+
+const LAMPO_NAMESPACE: &str = "lampo";
+const REMOTE_VERSIONS_KEY: &str = "remote_versions";
+const VERSION_HEADER: &str = "X-Lampo-Version";
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+
+/// A write not accepted yet by the storage.
+struct PendingWrite {
+    version: u64,
+    buf: Vec<u8>,
+    /// When the oldest write of the key not sent was done.
+    since: Instant,
+}
+
+#[derive(Default)]
+struct RemoteState {
+    /// The last version written of every key.
+    versions: HashMap<String, u64>,
+    pending: BTreeMap<String, PendingWrite>,
+}
+
+/// The answer of the storage to a `PUT`.
+enum Put {
+    Stored,
+    /// The storage has already this version or a newer one.
+    Conflict(u64),
+}
+
+pub struct LampoRemoteStore {
+    url: String,
+    token: Option<String>,
+    max_lag: Duration,
+    state: Mutex<RemoteState>,
+}
+
+impl LampoRemoteStore {
+    pub fn new(url: &str, token: Option<String>, max_lag: Duration) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_owned(),
+            token,
+            max_lag,
+            state: Mutex::new(RemoteState::default()),
+        }
+    }
+
+    /// Read the versions written by this node.
+    pub(super) fn load_versions(&self, local: &FilesystemStore) -> io::Result<()> {
+        let versions = match local.read(LAMPO_NAMESPACE, "", REMOTE_VERSIONS_KEY) {
+            Ok(buf) => json::from_slice::<HashMap<String, u64>>(&buf)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err),
+        };
+        self.state.lock_or_recover().versions = versions;
+        Ok(())
+    }
+
+    fn write_versions(local: &FilesystemStore, versions: &HashMap<String, u64>) -> io::Result<()> {
+        let buf = json::to_vec(versions)?;
+        local.write(LAMPO_NAMESPACE, "", REMOTE_VERSIONS_KEY, &buf)
+    }
+
+    /// The version of `key` written by this node, zero when it was
+    /// never sent.
+    pub(super) fn version(&self, key: &str) -> u64 {
+        let state = self.state.lock_or_recover();
+        state.versions.get(key).copied().unwrap_or_default()
+    }
+
+    /// Send a new version of `key`, after it is written on the disk.
+    pub(super) fn replicate(
+        &self,
+        local: &FilesystemStore,
+        key: &str,
+        buf: &[u8],
+    ) -> io::Result<()> {
+        let mut state = self.state.lock_or_recover();
+        let version = state.versions.get(key).copied().unwrap_or_default() + 1;
+        state.versions.insert(key.to_owned(), version);
+        Self::write_versions(local, &state.versions)?;
+        self.enqueue(&mut state, key, version, buf);
+        self.flush_pending(&mut state)
+    }
+
+    /// Send `buf` with the `version` that the storage does not have.
+    pub(super) fn resend(
+        &self,
+        local: &FilesystemStore,
+        key: &str,
+        version: u64,
+        buf: &[u8],
+    ) -> io::Result<()> {
+        let mut state = self.state.lock_or_recover();
+        state.versions.insert(key.to_owned(), version);
+        Self::write_versions(local, &state.versions)?;
+        self.enqueue(&mut state, key, version, buf);
+        Ok(())
+    }
+
+    /// Take the `versions` of the objects restored from the storage.
+    pub(super) fn restored(
+        &self,
+        local: &FilesystemStore,
+        versions: HashMap<String, u64>,
+    ) -> io::Result<()> {
+        let mut state = self.state.lock_or_recover();
+        state.versions = versions;
+        Self::write_versions(local, &state.versions)
+    }
+
+    fn enqueue(&self, state: &mut RemoteState, key: &str, version: u64, buf: &[u8]) {
+        let since = state
+            .pending
+            .get(key)
+            .map_or_else(Instant::now, |write| write.since);
+        state.pending.insert(
+            key.to_owned(),
+            PendingWrite {
+                version,
+                buf: buf.to_vec(),
+                since,
+            },
+        );
+    }
+
+    /// Retry the writes that did not reach the storage.
+    pub fn flush(&self) -> io::Result<()> {
+        let mut state = self.state.lock_or_recover();
+        self.flush_pending(&mut state)
+    }
+
+    fn flush_pending(&self, state: &mut RemoteState) -> io::Result<()> {
+        let keys = state.pending.keys().cloned().collect::<Vec<_>>();
+        for key in keys {
+            let write = &state.pending[&key];
+            match self.put(&key, write.version, &write.buf) {
+                Ok(Put::Stored) => {}
+                // a retry of a write that was stored, but the answer
+                // was lost
+                Ok(Put::Conflict(version))
+                    if version == write.version && self.is_stored(&key, &write.buf) => {}
+                Ok(Put::Conflict(version)) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!(
+                            "the remote storage has the version {version} of `{key}`, newer than ours {}: \
+                             another node is writing the same channels",
+                            write.version
+                        ),
+                    ));
+                }
+                Err(err) => {
+                    log::warn!(target: "lampo", "impossible send `{key}` to the remote storage: {err}");
+                    break;
+                }
+            }
+            state.pending.remove(&key);
+        }
+        let Some(since) = state.pending.values().map(|write| write.since).min() else {
+            return Ok(());
+        };
+        let lag = since.elapsed();
+        if lag > self.max_lag {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "the remote storage is {} writes and {}s behind, more than the {}s allowed",
+                    state.pending.len(),
+                    lag.as_secs(),
+                    self.max_lag.as_secs()
+                ),
+            ));
+        }
+        log::warn!(
+            target: "lampo",
+            "the remote storage is {} writes and {}s behind",
+            state.pending.len(),
+            lag.as_secs()
+        );
+        Ok(())
+    }
+
+    fn object_url(&self, key: &str) -> String {
+        format!("{}/objects/{key}", self.url)
+    }
+
+    fn authorized(&self, request: minreq::Request) -> minreq::Request {
+        let request = request.with_timeout(REQUEST_TIMEOUT_SECS);
+        match &self.token {
+            Some(token) => request.with_header("Authorization", format!("Bearer {token}")),
+            None => request,
+        }
+    }
+
+    fn put(&self, key: &str, version: u64, buf: &[u8]) -> error::Result<Put> {
+        let request = minreq::put(self.object_url(key))
+            .with_header(VERSION_HEADER, version.to_string())
+            .with_body(buf.to_vec());
+        let response = self.authorized(request).send()?;
+        match response.status_code {
+            200..=299 => Ok(Put::Stored),
+            409 => Ok(Put::Conflict(version_of(&response)?)),
+            status => error::bail!(
+                "the remote storage replied `{status} {}`",
+                response.reason_phrase
+            ),
+        }
+    }
+
+    /// The body of `key` with its version.
+    pub(super) fn get(&self, key: &str) -> error::Result<(Vec<u8>, u64)> {
+        let response = self.authorized(minreq::get(self.object_url(key))).send()?;
+        if response.status_code != 200 {
+            error::bail!(
+                "impossible read `{key}` from the remote storage: `{} {}`",
+                response.status_code,
+                response.reason_phrase
+            );
+        }
+        let version = version_of(&response)?;
+        Ok((response.into_bytes(), version))
+    }
+
+    /// Return true if the storage has `buf` as the content of `key`.
+    fn is_stored(&self, key: &str, buf: &[u8]) -> bool {
+        self.get(key).map_or(false, |(stored, _)| stored == buf)
+    }
+
+    /// The keys stored with their version.
+    pub(super) fn list(&self) -> error::Result<HashMap<String, u64>> {
+        let url = format!("{}/objects", self.url);
+        let response = self.authorized(minreq::get(url)).send()?;
+        if response.status_code != 200 {
+            error::bail!(
+                "impossible list the remote storage: `{} {}`",
+                response.status_code,
+                response.reason_phrase
+            );
+        }
+        let objects: Vec<json::Value> = json::from_slice(response.as_bytes())?;
+        objects
+            .iter()
+            .map(|object| {
+                let key = object["key"].as_str();
+                let version = object["version"].as_u64();
+                match (key, version) {
+                    (Some(key), Some(version)) => Ok((key.to_owned(), version)),
+                    _ => error::bail!("invalid object `{object}` listed by the remote storage"),
+                }
+            })
+            .collect()
+    }
+}
+
+fn version_of(response: &minreq::Response) -> error::Result<u64> {
+    let version = response
+        .headers
+        .get(&VERSION_HEADER.to_lowercase())
+        .and_then(|version| version.parse().ok());
+    version.ok_or(error::anyhow!(
+        "the remote storage replied without a valid `{VERSION_HEADER}`"
+    ))
+}
@@ -0,0 +1,513 @@
+//! Store of the node, the ldk filesystem store with the channel
+//! monitors written by lampo.
+//!
+//! The channel monitors are the most important bytes of the node, a
+//! monitor torn by a crash can lose the funds of the channel. So a
+//! monitor is written to `<key>.tmp`, synced and renamed over the old
+//! one, and the old version is kept as `<key>.bak` until the directory
+//! is synced. When the node starts the monitors are read back, and a
+//! monitor that can not be read is restored from the temporary file
+//! or from the backups.
+//!
+//! With a remote storage the channel manager, the monitors and the
+//! invoices are sent to it after they are written on the disk, see
+//! `LampoRemoteStore`.
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use lampo_common::bitcoin::BlockHash;
+use lampo_common::error;
+use lampo_common::keys::LampoKeysManager;
+use lampo_common::ldk::chain::channelmonitor::ChannelMonitor;
+use lampo_common::ldk::persister::fs_store::FilesystemStore;
+use lampo_common::ldk::sign::InMemorySigner;
+use lampo_common::ldk::util::persist::{
+    KVStore, CHANNEL_MANAGER_PERSISTENCE_KEY, CHANNEL_MANAGER_PERSISTENCE_PRIMARY_NAMESPACE,
+    CHANNEL_MANAGER_PERSISTENCE_SECONDARY_NAMESPACE, CHANNEL_MONITOR_PERSISTENCE_PRIMARY_NAMESPACE,
+    CHANNEL_MONITOR_PERSISTENCE_SECONDARY_NAMESPACE,
+};
+use lampo_common::ldk::util::ser::ReadableArgs;
+use lampo_common::sync::MutexExt;
+
+use super::remote::LampoRemoteStore;
+
+const TMP_EXTENSION: &str = "tmp";
+const BACKUP_EXTENSION: &str = "bak";
+const LAMPO_NAMESPACE: &str = "lampo";
+const INVOICES_KEY: &str = "invoices";
+
+pub struct LampoPersistence {
+    inner: FilesystemStore,
+    data_dir: PathBuf,
+    /// A second directory, on another filesystem, where every
+    /// monitor is written too.
+    backup_dir: Option<PathBuf>,
+    /// The storage where the channel state is sent.
+    remote: Option<LampoRemoteStore>,
+    /// Keys whose writes are skipped, see `hold`.
+    held: Mutex<HashSet<String>>,
+}
+
+impl LampoPersistence {
+    pub fn new(data_dir: PathBuf) -> Self {
+        Self {
+            inner: FilesystemStore::new(data_dir.clone()),
+            data_dir,
+            backup_dir: None,
+            remote: None,
+            held: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Write every channel monitor also inside `backup_dir`.
+    pub fn with_backup_dir(mut self, backup_dir: Option<PathBuf>) -> Self {
+        self.backup_dir = backup_dir;
+        self
+    }
+
+    /// Send the channel state also to the `remote` storage.
+    pub fn with_remote(mut self, remote: Option<LampoRemoteStore>) -> Self {
+        self.remote = remote;
+        self
+    }
+
+    pub fn get_data_dir(&self) -> PathBuf {
+        self.data_dir.clone()
+    }
+
+    /// Open `key` to read it as a stream, without reading the whole
+    /// file in memory.
+    pub fn open(
+        &self,
+        primary_namespace: &str,
+        secondary_namespace: &str,
+        key: &str,
+    ) -> io::Result<BufReader<File>> {
+        let mut path = self.data_dir.clone();
+        for namespace in [primary_namespace, secondary_namespace] {
+            if !namespace.is_empty() {
+                path.push(namespace);
+            }
+        }
+        Ok(BufReader::new(File::open(path.join(key))?))
+    }
+
+    /// Skip the writes of `key` until it is released, so a file that
+    /// is still loading is not overwritten by what is in memory.
+    pub fn hold(&self, primary_namespace: &str, secondary_namespace: &str, key: &str) {
+        let object = remote_key(primary_namespace, secondary_namespace, key);
+        self.held.lock_or_recover().insert(object);
+    }
+
+    pub fn release(&self, primary_namespace: &str, secondary_namespace: &str, key: &str) {
+        let object = remote_key(primary_namespace, secondary_namespace, key);
+        self.held.lock_or_recover().remove(&object);
+    }
+
+    fn is_held(&self, primary_namespace: &str, secondary_namespace: &str, key: &str) -> bool {
+        let object = remote_key(primary_namespace, secondary_namespace, key);
+        self.held.lock_or_recover().contains(&object)
+    }
+
+    fn monitors_dir(&self) -> PathBuf {
+        self.data_dir
+            .join(CHANNEL_MONITOR_PERSISTENCE_PRIMARY_NAMESPACE)
+    }
+
+    fn write_monitor(&self, key: &str, buf: &[u8]) -> io::Result<()> {
+        write_durable(&self.monitors_dir(), key, buf)?;
+        if let Some(backup_dir) = &self.backup_dir {
+            // the channel must not stop because the copy failed
+            if let Err(err) = write_durable(backup_dir, key, buf) {
+                log::error!(
+                    target: "lampo",
+                    "impossible write the monitor `{key}` inside `{}`: {err}",
+                    backup_dir.display()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Write on the disk without sending to the remote storage.
+    fn write_local(
+        &self,
+        primary_namespace: &str,
+        secondary_namespace: &str,
+        key: &str,
+        buf: &[u8],
+    ) -> io::Result<()> {
+        if is_monitor(primary_namespace, secondary_namespace) {
+            return self.write_monitor(key, buf);
+        }
+        self.inner
+            .write(primary_namespace, secondary_namespace, key, buf)
+    }
+
+    /// The monitors inside the directory, without the temporary files
+    /// and the backups.
+    fn list_monitors(&self) -> io::Result<Vec<String>> {
+        let dir = self.monitors_dir();
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut keys = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if !path.is_file() || path.extension().is_some() {
+                continue;
+            }
+            if let Some(key) = path.file_name().and_then(|name| name.to_str()) {
+                keys.push(key.to_owned());
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Read back every channel monitor before ldk loads them, and
+    /// restore the ones that can not be read.
+    ///
+    /// A monitor is replaced by its temporary file when the node was
+    /// killed before the rename and the temporary file is newer, or
+    /// when the monitor is missing or corrupted. Otherwise a corrupted
+    /// monitor is restored from `<key>.bak` or from the backup
+    /// directory, and the node does not start if none of them can
+    /// be read.
+    pub fn recover_channel_monitors(&self, keys: &LampoKeysManager) -> error::Result<()> {
+        let dir = self.monitors_dir();
+        if !dir.exists() {
+            return Ok(());
+        }
+        let mut monitors = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let Some(key) = path.file_stem().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if path.is_file() && !monitors.iter().any(|monitor| monitor == key) {
+                monitors.push(key.to_owned());
+            }
+        }
+        for key in monitors {
+            self.recover_channel_monitor(&dir, &key, keys)?;
+        }
+        Ok(())
+    }
+
+    /// The keys on the disk that are sent to the remote storage.
+    fn replicated_keys(&self) -> io::Result<Vec<(String, String, String)>> {
+        let mut keys = Vec::new();
+        if self.data_dir.join(CHANNEL_MANAGER_PERSISTENCE_KEY).exists() {
+            keys.push((
+                CHANNEL_MANAGER_PERSISTENCE_PRIMARY_NAMESPACE.to_owned(),
+                CHANNEL_MANAGER_PERSISTENCE_SECONDARY_NAMESPACE.to_owned(),
+                CHANNEL_MANAGER_PERSISTENCE_KEY.to_owned(),
+            ));
+        }
+        for key in self.list_monitors()? {
+            keys.push((
+                CHANNEL_MONITOR_PERSISTENCE_PRIMARY_NAMESPACE.to_owned(),
+                CHANNEL_MONITOR_PERSISTENCE_SECONDARY_NAMESPACE.to_owned(),
+                key,
+            ));
+        }
+        if self
+            .data_dir
+            .join(LAMPO_NAMESPACE)
+            .join(INVOICES_KEY)
+            .exists()
+        {
+            keys.push((
+                LAMPO_NAMESPACE.to_owned(),
+                String::new(),
+                INVOICES_KEY.to_owned(),
+            ));
+        }
+        Ok(keys)
+    }
+
+    /// Compare the state on the disk with the remote storage, before
+    /// the node starts.
+    ///
+    /// A datadir without channels is restored from the storage when
+    /// `restore` is set. The node does not start when the storage has
+    /// a version newer than the one on the disk, because the channels
+    /// would go on from an old state, and the versions that the
+    /// storage does not have are sent again.
+    pub fn sync_remote(&self, restore: bool) -> error::Result<()> {
+        let Some(remote) = &self.remote else {
+            return Ok(());
+        };
+        remote.load_versions(&self.inner)?;
+        let objects = remote.list()?;
+        let local = self.replicated_keys()?;
+        if local.is_empty() && !objects.is_empty() {
+            if !restore {
+                error::bail!(
+                    "the datadir has no channels while the remote storage has {} objects, \
+                     start with `--restore-from-remote` to restore them",
+                    objects.len()
+                );
+            }
+            let mut versions = HashMap::new();
+            for object in objects.keys() {
+                let (buf, version) = remote.get(object)?;
+                let (primary_namespace, secondary_namespace, key) = split_remote_key(object)?;
+                self.write_local(primary_namespace, secondary_namespace, key, &buf)?;
+                versions.insert(object.clone(), version);
+            }
+            remote.restored(&self.inner, versions)?;
+            log::info!(target: "lampo", "restored {} objects from the remote storage", objects.len());
+            return Ok(());
+        }
+        for (object, stored) in &objects {
+            let version = remote.version(object);
+            if *stored > version {
+                error::bail!(
+                    "the remote storage has the version {stored} of `{object}`, newer than the \
+                     local version {version}: refusing to start from an old channel state"
+                );
+            }
+        }
+        for (primary_namespace, secondary_namespace, key) in local {
+            let object = remote_key(&primary_namespace, &secondary_namespace, &key);
+            let version = remote.version(&object);
+            if objects.get(&object).copied().unwrap_or_default() < version.max(1) {
+                let buf = self
+                    .inner
+                    .read(&primary_namespace, &secondary_namespace, &key)?;
+                remote.resend(&self.inner, &object, version.max(1), &buf)?;
+            }
+        }
+        remote.flush()?;
+        Ok(())
+    }
+
+    /// Retry the writes that did not reach the remote storage.
+    pub fn flush_remote(&self) -> io::Result<()> {
+        match &self.remote {
+            Some(remote) => remote.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn recover_channel_monitor(
+        &self,
+        dir: &Path,
+        key: &str,
+        keys: &LampoKeysManager,
+    ) -> error::Result<()> {
+        let path = dir.join(key);
+        let tmp = sibling(dir, key, TMP_EXTENSION);
+        let backup = sibling(dir, key, BACKUP_EXTENSION);
+        let current = read_monitor(&path, keys).map(|(_, update_id)| update_id);
+        match (current, read_monitor(&tmp, keys)) {
+            (Some(current_id), Some((buf, tmp_id))) if tmp_id > current_id => {
+                log::warn!(
+                    target: "lampo",
+                    "the node stopped while writing the monitor `{key}`, restoring the update {tmp_id} from `{}`",
+                    tmp.display()
+                );
+                replace(dir, key, &buf)?;
+            }
+            (Some(_), _) => {}
+            (None, from_tmp) => {
+                let candidates = [
+                    (tmp.clone(), from_tmp),
+                    (backup.clone(), read_monitor(&backup, keys)),
+                    match &self.backup_dir {
+                        Some(backup_dir) => {
+                            let path = backup_dir.join(key);
+                            let monitor = read_monitor(&path, keys);
+                            (path, monitor)
+                        }
+                        None => (PathBuf::new(), None),
+                    },
+                ];
+                let restored = candidates
+                    .into_iter()
+                    .filter_map(|(path, monitor)| monitor.map(|monitor| (path, monitor)))
+                    .max_by_key(|(_, (_, update_id))| *update_id);
+                match restored {
+                    Some((from, (buf, update_id))) => {
+                        log::error!(
+                            target: "lampo",
+                            "the channel monitor `{}` is missing or corrupted, restoring the update {update_id} from `{}`",
+                            path.display(),
+                            from.display()
+                        );
+                        replace(dir, key, &buf)?;
+                    }
+                    // the first write of the monitor never completed,
+                    // so ldk never used it
+                    None if !path.exists() => log::warn!(
+                        target: "lampo",
+                        "removing the torn monitor `{key}`, it was never written"
+                    ),
+                    None => error::bail!(
+                        "the channel monitor `{}` is corrupted and there is no backup to restore it, \
+                         restore it from a copy of the node before starting again",
+                        path.display()
+                    ),
+                }
+            }
+        }
+        remove_if_exists(&tmp)?;
+        remove_if_exists(&backup)?;
+        Ok(())
+    }
+}
+
+impl KVStore for LampoPersistence {
+    fn read(
+        &self,
+        primary_namespace: &str,
+        secondary_namespace: &str,
+        key: &str,
+    ) -> io::Result<Vec<u8>> {
+        self.inner.read(primary_namespace, secondary_namespace, key)
+    }
+
+    fn write(
+        &self,
+        primary_namespace: &str,
+        secondary_namespace: &str,
+        key: &str,
+        buf: &[u8],
+    ) -> io::Result<()> {
+        if self.is_held(primary_namespace, secondary_namespace, key) {
+            log::debug!(target: "lampo", "skipping the write of `{key}`, it is held");
+            return Ok(());
+        }
+        self.write_local(primary_namespace, secondary_namespace, key, buf)?;
+        match &self.remote {
+            Some(remote) if is_replicated(primary_namespace, secondary_namespace, key) => {
+                let object = remote_key(primary_namespace, secondary_namespace, key);
+                remote.replicate(&self.inner, &object, buf)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn remove(
+        &self,
+        primary_namespace: &str,
+        secondary_namespace: &str,
+        key: &str,
+        lazy: bool,
+    ) -> io::Result<()> {
+        // the copies inside the backup directory are never removed
+        self.inner
+            .remove(primary_namespace, secondary_namespace, key, lazy)
+    }
+
+    fn list(&self, primary_namespace: &str, secondary_namespace: &str) -> io::Result<Vec<String>> {
+        if is_monitor(primary_namespace, secondary_namespace) {
+            return self.list_monitors();
+        }
+        self.inner.list(primary_namespace, secondary_namespace)
+    }
+}
+
+fn is_monitor(primary_namespace: &str, secondary_namespace: &str) -> bool {
+    primary_namespace == CHANNEL_MONITOR_PERSISTENCE_PRIMARY_NAMESPACE
+        && secondary_namespace == CHANNEL_MONITOR_PERSISTENCE_SECONDARY_NAMESPACE
+}
+
+/// The channel manager, the monitors and the invoices.
+fn is_replicated(primary_namespace: &str, secondary_namespace: &str, key: &str) -> bool {
+    let manager = primary_namespace == CHANNEL_MANAGER_PERSISTENCE_PRIMARY_NAMESPACE
+        && secondary_namespace == CHANNEL_MANAGER_PERSISTENCE_SECONDARY_NAMESPACE
+        && key == CHANNEL_MANAGER_PERSISTENCE_KEY;
+    let invoices = primary_namespace == LAMPO_NAMESPACE
+        && secondary_namespace.is_empty()
+        && key == INVOICES_KEY;
+    manager || invoices || is_monitor(primary_namespace, secondary_namespace)
+}
+
+/// The key on the remote storage, the namespaces and the key joined
+/// with `/`.
+fn remote_key(primary_namespace: &str, secondary_namespace: &str, key: &str) -> String {
+    [primary_namespace, secondary_namespace, key]
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn split_remote_key(object: &str) -> error::Result<(&str, &str, &str)> {
+    let parts = object.split('/').collect::<Vec<_>>();
+    match parts[..] {
+        [key] => Ok(("", "", key)),
+        [primary_namespace, key] => Ok((primary_namespace, "", key)),
+        [primary_namespace, secondary_namespace, key] => {
+            Ok((primary_namespace, secondary_namespace, key))
+        }
+        _ => error::bail!("invalid key `{object}` on the remote storage"),
+    }
+}
+
+fn sibling(dir: &Path, key: &str, extension: &str) -> PathBuf {
+    dir.join(format!("{key}.{extension}"))
+}
+
+/// Write `dir/key` without ever leaving a torn file, the previous
+/// version stays as `<key>.bak` until the new one is durable.
+fn write_durable(dir: &Path, key: &str, buf: &[u8]) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(key);
+    let backup = sibling(dir, key, BACKUP_EXTENSION);
+    if path.exists() {
+        remove_if_exists(&backup)?;
+        // the link keeps the old version when the new one is renamed
+        // over it, the copy is for the filesystems without links
+        if fs::hard_link(&path, &backup).is_err() {
+            fs::copy(&path, &backup)?;
+            File::open(&backup)?.sync_all()?;
+        }
+        sync_dir(dir)?;
+    }
+    replace(dir, key, buf)?;
+    remove_if_exists(&backup)
+}
+
+/// Replace `dir/key` with `buf`: write the temporary file, sync it,
+/// rename it over the old file and sync the directory.
+fn replace(dir: &Path, key: &str, buf: &[u8]) -> io::Result<()> {
+    let tmp = sibling(dir, key, TMP_EXTENSION);
+    let mut file = File::create(&tmp)?;
+    file.write_all(buf)?;
+    file.sync_all()?;
+    fs::rename(&tmp, dir.join(key))?;
+    sync_dir(dir)
+}
+
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    // the directories can not be opened on windows
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// The content of the monitor at `path` with its latest update id,
+/// if the file exists and it is a monitor.
+fn read_monitor(path: &Path, keys: &LampoKeysManager) -> Option<(Vec<u8>, u64)> {
+    let buf = fs::read(path).ok()?;
+    let (_, monitor) =
+        <(BlockHash, ChannelMonitor<InMemorySigner>)>::read(&mut Cursor::new(&buf), (keys, keys))
+            .ok()?;
+    let update_id = monitor.get_latest_update_id();
+    Some((buf, update_id))
+}
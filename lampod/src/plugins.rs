@@ -0,0 +1,514 @@
+//! Plugins, executables started with lampo that extend it.
+//!
+//! Every executable inside `plugin-dir` is started with the node and
+//! talks JSON RPC 2.0 over its stdin and stdout, one message for each
+//! line. lampo asks the manifest of the plugin with `getmanifest` and
+//! configures it with `init`, then with the manifest a plugin can:
+//!
+//! - add methods to the RPC of lampo, the requests of the clients are
+//!   forwarded to the plugin;
+//! - subscribe to the events, notified with the kind of the event as
+//!   method (e.g. `payment_received`) and `{"id", "data"}` as params;
+//! - register the hooks `invoice_payment`, `openchannel` and
+//!   `htlc_accepted`, called before lampo does the operation, so the
+//!   plugin can reject it or, for an HTLC, resolve it with a preimage.
+//!
+//! A plugin can write in the logs of lampo with the `log` notification.
+//! A plugin that exits is reported as crashed by `listplugins`, and
+//! lampo continues without its methods and hooks.
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use lampo_common::bitcoin::hashes::hex::FromHex;
+use lampo_common::chan;
+use lampo_common::conf::LampoConf;
+use lampo_common::error;
+use lampo_common::json;
+use lampo_common::ldk::ln::PaymentPreimage;
+use lampo_common::model::response::{Plugin as PluginInfo, PluginManifest, PluginStatus};
+use lampo_common::sync::{MutexExt, RwLockExt};
+use lampo_jsonrpc::errors::{Error, RpcError};
+use lampo_jsonrpc::json_rpc2::{Id, Request, Response};
+
+use crate::actions::event_bus::LampoEventBus;
+
+/// The hooks that a plugin can register.
+pub const HOOKS: [&str; 3] = ["invoice_payment", "openchannel", "htlc_accepted"];
+
+/// Time that a plugin has to answer to `getmanifest` and to `init`.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time that a plugin has to answer to a hook, then it is skipped.
+const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+/// Time that a plugin has to answer to a method called by a client.
+const METHOD_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long a plugin waits for the next events before checking if
+/// the node is stopping.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What the plugins decided about the operation of a hook.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HookResult {
+    /// Go on with the operation.
+    Continue,
+    /// Do not do the operation, for the reason given by the plugin.
+    Reject(String),
+    /// Claim the HTLCs with the preimage given by the plugin, only
+    /// for `htlc_accepted`.
+    Resolve(PaymentPreimage),
+}
+
+/// The answer of the plugin to a request.
+type Answer = Result<json::Value, RpcError>;
+
+struct Plugin {
+    name: String,
+    path: PathBuf,
+    manifest: RwLock<PluginManifest>,
+    child: Mutex<Option<Child>>,
+    stdin: Mutex<Option<ChildStdin>>,
+    /// The requests waiting for an answer, by id.
+    pending: Mutex<HashMap<u64, chan::Sender<Answer>>>,
+    next_id: AtomicU64,
+    /// Why the plugin is not running, `None` while it runs.
+    crashed: Mutex<Option<String>>,
+}
+
+impl Plugin {
+    /// Start the executable at `path`, its messages are read from
+    /// another thread.
+    fn spawn(path: &Path) -> Arc<Self> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let plugin = Arc::new(Self {
+            name,
+            path: path.to_owned(),
+            manifest: RwLock::new(PluginManifest::default()),
+            child: Mutex::new(None),
+            stdin: Mutex::new(None),
+            pending: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+            crashed: Mutex::new(None),
+        });
+        let child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn();
+        match child {
+            Ok(mut child) => {
+                // SAFETY: the stdin and the stdout are piped.
+                let stdin = child.stdin.take().unwrap();
+                let stdout = child.stdout.take().unwrap();
+                *plugin.stdin.lock_or_recover() = Some(stdin);
+                *plugin.child.lock_or_recover() = Some(child);
+                let reader = plugin.clone();
+                std::thread::spawn(move || reader.read(stdout));
+            }
+            Err(err) => plugin.crash(format!("impossible start the plugin: {err}")),
+        }
+        plugin
+    }
+
+    /// Ask the manifest of the plugin and configure it.
+    fn handshake(&self, configuration: &json::Value) -> error::Result<()> {
+        let manifest = self.request("getmanifest", json::json!({}), HANDSHAKE_TIMEOUT)?;
+        let manifest: PluginManifest = json::from_value(manifest)?;
+        if let Some(hook) = manifest
+            .hooks
+            .iter()
+            .find(|hook| !HOOKS.contains(&hook.name.as_str()))
+        {
+            error::bail!("unknown hook `{}`", hook.name);
+        }
+        *self.manifest.write_or_recover() = manifest;
+        let params = json::json!({ "configuration": configuration });
+        self.request("init", params, HANDSHAKE_TIMEOUT)?;
+        Ok(())
+    }
+
+    /// Read the messages of the plugin until it exits.
+    fn read(&self, stdout: ChildStdout) {
+        for line in BufReader::new(stdout).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => {
+                    log::warn!(target: "plugins", "impossible read from `{}`: {err}", self.name);
+                    break;
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match json::from_str::<json::Value>(&line) {
+                Ok(message) => self.receive(message),
+                Err(err) => {
+                    log::warn!(target: "plugins", "`{}` sent an invalid message: {err}", self.name)
+                }
+            }
+        }
+        let status = self
+            .child
+            .lock_or_recover()
+            .as_mut()
+            .and_then(|child| child.try_wait().ok().flatten());
+        match status {
+            Some(status) => self.crash(format!("the plugin exited with {status}")),
+            None => self.crash("the plugin closed its stdout".to_owned()),
+        }
+    }
+
+    fn receive(&self, message: json::Value) {
+        // the plugin sends only the notifications of its logs
+        if message.get("method").is_some() {
+            if message["method"] == "log" {
+                let text = message["params"]["message"].as_str().unwrap_or_default();
+                log::info!(target: "plugins", "{}: {text}", self.name);
+            }
+            return;
+        }
+        let response = match json::from_value::<Response<json::Value>>(message) {
+            Ok(response) => response,
+            Err(err) => {
+                log::warn!(target: "plugins", "`{}` sent an invalid response: {err}", self.name);
+                return;
+            }
+        };
+        let id = match response.id {
+            Id::Str(ref id) => id.parse::<u64>().ok(),
+            Id::Int(id) => Some(id as u64),
+        };
+        let sender = id.and_then(|id| self.pending.lock_or_recover().remove(&id));
+        let Some(sender) = sender else {
+            log::warn!(target: "plugins", "`{}` answered to an unknown request", self.name);
+            return;
+        };
+        let answer = match response.error {
+            Some(err) => Err(err),
+            None => Ok(response.result.unwrap_or_default()),
+        };
+        let _ = sender.send(answer);
+    }
+
+    /// Send the request to the plugin and wait up to `timeout` for
+    /// its answer.
+    fn request(
+        &self,
+        method: &str,
+        params: json::Value,
+        timeout: Duration,
+    ) -> error::Result<json::Value> {
+        if let Some(reason) = self.crashed() {
+            error::bail!("plugin `{}` crashed: {reason}", self.name);
+        }
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (sender, receiver) = chan::bounded(1);
+        self.pending.lock_or_recover().insert(id, sender);
+        let mut request = Request::new(method, params);
+        request.id = Some(Id::from(id));
+        if let Err(err) = self.send(&request) {
+            self.pending.lock_or_recover().remove(&id);
+            return Err(err);
+        }
+        match receiver.recv_timeout(timeout) {
+            Ok(Ok(result)) => Ok(result),
+            Ok(Err(err)) => Err(Error::Rpc(err).into()),
+            Err(chan::RecvTimeoutError::Timeout) => {
+                self.pending.lock_or_recover().remove(&id);
+                error::bail!(
+                    "plugin `{}` did not answer to `{method}` in {}s",
+                    self.name,
+                    timeout.as_secs()
+                );
+            }
+            Err(chan::RecvTimeoutError::Disconnected) => {
+                error::bail!("plugin `{}` crashed during `{method}`", self.name)
+            }
+        }
+    }
+
+    /// Send a notification, the plugin does not answer to it.
+    fn notify(&self, method: &str, params: json::Value) -> error::Result<()> {
+        let mut notification = Request::new(method, params);
+        notification.id = None;
+        self.send(&notification)
+    }
+
+    fn send(&self, message: &Request<json::Value>) -> error::Result<()> {
+        let mut line = json::to_string(message)?;
+        line.push('\n');
+        let mut stdin = self.stdin.lock_or_recover();
+        let Some(stdin) = stdin.as_mut() else {
+            error::bail!("plugin `{}` is not running", self.name);
+        };
+        stdin.write_all(line.as_bytes())?;
+        stdin.flush()?;
+        Ok(())
+    }
+
+    /// Notify the plugin of the events that it subscribed to, until it
+    /// crashes or the node is `stopped`.
+    fn follow(&self, event_bus: &LampoEventBus, stopped: &AtomicBool) {
+        let kinds = self.manifest.read_or_recover().subscriptions.clone();
+        let mut last_id = None;
+        while !stopped.load(Ordering::SeqCst) && self.crashed().is_none() {
+            match event_bus.wait(last_id, &kinds, POLL_INTERVAL) {
+                Ok(events) => {
+                    for event in events.events {
+                        let params = json::json!({ "id": event.id, "data": event.data });
+                        if let Err(err) = self.notify(&event.kind, params) {
+                            log::warn!(target: "plugins", "impossible notify `{}`: {err}", self.name);
+                        }
+                    }
+                    last_id = Some(events.last_id);
+                }
+                // the plugin is so slow that its events are dropped,
+                // so it continues from the oldest event kept
+                Err(err) => {
+                    log::warn!(target: "plugins", "`{}` missed events: {err}", self.name);
+                    last_id = None;
+                }
+            }
+        }
+    }
+
+    /// Mark the plugin as crashed, the requests waiting for it fail.
+    fn crash(&self, reason: String) {
+        {
+            let mut crashed = self.crashed.lock_or_recover();
+            if crashed.is_some() {
+                return;
+            }
+            log::error!(target: "plugins", "plugin `{}` crashed: {reason}", self.name);
+            *crashed = Some(reason);
+        }
+        self.pending.lock_or_recover().clear();
+        self.kill();
+    }
+
+    /// Kill the plugin when lampo stops, without reporting a crash.
+    fn stop(&self) {
+        self.crashed
+            .lock_or_recover()
+            .get_or_insert_with(|| "lampo stopped".to_owned());
+        self.pending.lock_or_recover().clear();
+        self.kill();
+    }
+
+    fn kill(&self) {
+        if let Some(mut child) = self.child.lock_or_recover().take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+
+    fn crashed(&self) -> Option<String> {
+        self.crashed.lock_or_recover().clone()
+    }
+
+    fn has_method(&self, method: &str) -> bool {
+        let manifest = self.manifest.read_or_recover();
+        manifest.rpcmethods.iter().any(|rpc| rpc.name == method)
+    }
+
+    fn has_hook(&self, hook: &str) -> bool {
+        let manifest = self.manifest.read_or_recover();
+        manifest
+            .hooks
+            .iter()
+            .any(|registered| registered.name == hook)
+    }
+
+    fn info(&self) -> PluginInfo {
+        let manifest = self.manifest.read_or_recover();
+        let error = self.crashed();
+        PluginInfo {
+            name: self.name.clone(),
+            path: self.path.display().to_string(),
+            status: match error {
+                Some(_) => PluginStatus::Crashed,
+                None => PluginStatus::Running,
+            },
+            methods: manifest
+                .rpcmethods
+                .iter()
+                .map(|rpc| rpc.name.clone())
+                .collect(),
+            subscriptions: manifest.subscriptions.clone(),
+            hooks: manifest
+                .hooks
+                .iter()
+                .map(|hook| hook.name.clone())
+                .collect(),
+            error,
+        }
+    }
+}
+
+pub struct LampoPlugins {
+    dir: Option<PathBuf>,
+    /// Given to the plugins with `init`.
+    configuration: json::Value,
+    plugins: RwLock<Vec<Arc<Plugin>>>,
+}
+
+impl LampoPlugins {
+    pub fn new(conf: &LampoConf) -> Self {
+        let configuration = json::json!({
+            "lampo_dir": conf.path(),
+            "network": conf.network.to_string(),
+            "rpc_file": format!("{}/lampod.socket", conf.path()),
+        });
+        Self {
+            dir: conf.plugin_dir.as_ref().map(PathBuf::from),
+            configuration,
+            plugins: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Start the plugins inside `plugin-dir` in the order of their
+    /// names, the events of the `event_bus` are notified to them
+    /// until the node is `stopped`.
+    pub fn start(
+        &self,
+        event_bus: Arc<LampoEventBus>,
+        stopped: Arc<AtomicBool>,
+    ) -> error::Result<()> {
+        let Some(ref dir) = self.dir else {
+            return Ok(());
+        };
+        let mut paths = fs::read_dir(dir)
+            .map_err(|err| {
+                error::anyhow!("impossible read the plugin dir `{}`: {err}", dir.display())
+            })?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| is_executable(path))
+            .collect::<Vec<_>>();
+        paths.sort();
+        let mut plugins = self.plugins.write_or_recover();
+        for path in paths {
+            log::info!(target: "plugins", "starting plugin `{}`", path.display());
+            let plugin = Plugin::spawn(&path);
+            if let Err(err) = plugin.handshake(&self.configuration) {
+                plugin.crash(format!("handshake failed: {err}"));
+            } else if !plugin.manifest.read_or_recover().subscriptions.is_empty() {
+                let follower = plugin.clone();
+                let event_bus = event_bus.clone();
+                let stopped = stopped.clone();
+                std::thread::spawn(move || follower.follow(&event_bus, &stopped));
+            }
+            plugins.push(plugin);
+        }
+        Ok(())
+    }
+
+    /// The methods added by the plugins that are running, a method
+    /// added by more plugins is served by the first one.
+    pub fn methods(&self) -> Vec<String> {
+        let mut methods = Vec::<String>::new();
+        for plugin in self.plugins.read_or_recover().iter() {
+            if plugin.crashed().is_some() {
+                continue;
+            }
+            for rpc in plugin.manifest.read_or_recover().rpcmethods.iter() {
+                if !methods.contains(&rpc.name) {
+                    methods.push(rpc.name.clone());
+                }
+            }
+        }
+        methods
+    }
+
+    /// Forward the call of `method` to the plugin that added it.
+    pub fn call_method(&self, method: &str, params: &json::Value) -> error::Result<json::Value> {
+        let plugin = self
+            .plugins
+            .read_or_recover()
+            .iter()
+            .find(|plugin| plugin.has_method(method))
+            .cloned();
+        let Some(plugin) = plugin else {
+            error::bail!("no plugin serves the method `{method}`");
+        };
+        plugin.request(method, params.clone(), METHOD_TIMEOUT)
+    }
+
+    /// Call the plugins registered to `hook` in order, until one of
+    /// them does not let the operation continue. A plugin that crashed
+    /// or that does not answer is skipped.
+    pub fn hook(&self, hook: &str, payload: json::Value) -> HookResult {
+        let plugins = self
+            .plugins
+            .read_or_recover()
+            .iter()
+            .filter(|plugin| plugin.has_hook(hook) && plugin.crashed().is_none())
+            .cloned()
+            .collect::<Vec<_>>();
+        for plugin in plugins {
+            let answer = match plugin.request(hook, payload.clone(), HOOK_TIMEOUT) {
+                Ok(answer) => answer,
+                Err(err) => {
+                    log::warn!(target: "plugins", "skipping the hook `{hook}` of `{}`: {err}", plugin.name);
+                    continue;
+                }
+            };
+            match answer["result"].as_str() {
+                Some("continue") => continue,
+                Some("reject") | Some("fail") => {
+                    let reason = answer["error_message"]
+                        .as_str()
+                        .map(str::to_owned)
+                        .unwrap_or_else(|| format!("rejected by the plugin `{}`", plugin.name));
+                    log::info!(target: "plugins", "hook `{hook}` rejected by `{}`: {reason}", plugin.name);
+                    return HookResult::Reject(reason);
+                }
+                Some("resolve") if hook == "htlc_accepted" => {
+                    let preimage = answer["payment_key"]
+                        .as_str()
+                        .and_then(|key| <[u8; 32]>::from_hex(key).ok());
+                    if let Some(preimage) = preimage {
+                        return HookResult::Resolve(PaymentPreimage(preimage));
+                    }
+                    log::warn!(target: "plugins", "`{}` resolved `{hook}` without a valid `payment_key`", plugin.name);
+                }
+                _ => {
+                    log::warn!(target: "plugins", "`{}` gave an invalid answer to `{hook}`: {answer}", plugin.name)
+                }
+            }
+        }
+        HookResult::Continue
+    }
+
+    /// The plugins started, with their state.
+    pub fn list(&self) -> Vec<PluginInfo> {
+        let plugins = self.plugins.read_or_recover();
+        plugins.iter().map(|plugin| plugin.info()).collect()
+    }
+
+    /// Kill the plugins, called when the node stops.
+    pub fn stop(&self) {
+        for plugin in self.plugins.read_or_recover().iter() {
+            plugin.stop();
+        }
+    }
+}
+
+/// A file that can be executed, the hidden files are skipped.
+fn is_executable(path: &Path) -> bool {
+    let hidden = path
+        .file_name()
+        .map_or(true, |name| name.to_string_lossy().starts_with('.'));
+    let Ok(metadata) = fs::metadata(path) else {
+        return false;
+    };
+    !hidden && metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+}
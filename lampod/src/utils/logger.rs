@@ -30,6 +30,8 @@ impl FromStr for LogLevel {
     }
 }
 
+/// Log the records of ldk with the `ldk` target, so they end up
+/// in `getlog` and their level is changed with `setloglevel`.
 #[derive(Clone)]
 pub struct LampoLogger;
 
@@ -0,0 +1,724 @@
+//! Client of the watchtowers, that keep punishing a revoked commitment
+//! of a peer while the node is offline.
+//!
+//! Every commitment of the peer is kept with its justice transaction
+//! not signed, and when the peer revokes it the justice transaction
+//! is signed and sent to every tower as an appointment, see
+//! `protocol`. The appointments are queued per tower and sent by a
+//! background thread, so a tower down never blocks the updates of the
+//! channels: a tower that can not be reached keeps its backlog and the
+//! appointments are sent again when it comes back.
+//!
+//! The sessions with the towers and the commitments not revoked yet
+//! are persisted, so the backups go on after a restart.
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use lampo_common::bitcoin::consensus::{deserialize, serialize};
+use lampo_common::bitcoin::hashes::hex::{FromHex, ToHex};
+use lampo_common::bitcoin::{ScriptBuf, Transaction};
+use lampo_common::chan;
+use lampo_common::error;
+use lampo_common::json;
+use lampo_common::keys::{recover_message_signer, LampoKeys};
+use lampo_common::ldk::chain::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate};
+use lampo_common::ldk::chain::transaction::OutPoint;
+use lampo_common::ldk::ln::chan_utils::CommitmentTransaction;
+use lampo_common::ldk::sign::{InMemorySigner, SignerProvider};
+use lampo_common::ldk::util::persist::KVStore;
+use lampo_common::model::response;
+use lampo_common::sync::MutexExt;
+
+use super::protocol::{Appointment, TowerAddress};
+use crate::persistence::LampoPersistence;
+
+const LAMPO_NAMESPACE: &str = "lampo";
+const TOWERS_NAMESPACE: &str = "towers";
+const JUSTICE_TXS_KEY: &str = "tower_justice_txs";
+
+/// Seconds that a tower has to answer.
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+/// How often the appointments not accepted are sent again.
+pub const TOWER_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A justice transaction of a commitment not revoked yet.
+#[derive(Clone, Debug)]
+struct UnsignedJustice {
+    tx: Transaction,
+    /// The value of the output punished.
+    value: u64,
+    commitment_number: u64,
+}
+
+impl UnsignedJustice {
+    /// The justice transaction of the `commitment` of the peer, `None`
+    /// when the peer has no output to punish.
+    fn new(
+        commitment: &CommitmentTransaction,
+        feerate_per_kw: u32,
+        sweep: ScriptBuf,
+    ) -> Option<Self> {
+        let trusted = commitment.trust();
+        let output = trusted.revokeable_output_index()?;
+        let value = trusted.built_transaction().transaction.output[output].value;
+        let tx = trusted
+            .build_to_local_justice_tx(feerate_per_kw as u64, sweep)
+            .ok()?;
+        Some(Self {
+            tx,
+            value,
+            commitment_number: trusted.commitment_number(),
+        })
+    }
+
+    fn to_json(&self) -> json::Value {
+        json::json!({
+            "tx": serialize(&self.tx).to_hex(),
+            "value": self.value,
+            "commitment_number": self.commitment_number,
+        })
+    }
+
+    fn from_json(value: &json::Value) -> error::Result<Self> {
+        let tx = value["tx"]
+            .as_str()
+            .and_then(|tx| Vec::<u8>::from_hex(tx).ok())
+            .and_then(|tx| deserialize(&tx).ok());
+        match (
+            tx,
+            value["value"].as_u64(),
+            value["commitment_number"].as_u64(),
+        ) {
+            (Some(tx), Some(value), Some(commitment_number)) => Ok(Self {
+                tx,
+                value,
+                commitment_number,
+            }),
+            _ => error::bail!("invalid justice transaction `{value}`"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TowerState {
+    Active,
+    /// The last request failed, it is retried.
+    Unreachable,
+    /// The tower signed an appointment with another key.
+    Misbehaving,
+}
+
+impl TowerState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Active => "active",
+            Self::Unreachable => "unreachable",
+            Self::Misbehaving => "misbehaving",
+        }
+    }
+
+    fn parse(state: &str) -> Self {
+        match state {
+            "misbehaving" => Self::Misbehaving,
+            "unreachable" => Self::Unreachable,
+            _ => Self::Active,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct PendingAppointment {
+    funding_txo: String,
+    appointment: Appointment,
+}
+
+/// The session of the node with a tower.
+#[derive(Clone, Debug)]
+struct TowerSession {
+    address: TowerAddress,
+    registered: bool,
+    available_slots: u32,
+    subscription_expiry: u32,
+    state: TowerState,
+    /// The appointments accepted for every channel.
+    accepted: BTreeMap<String, u64>,
+    /// The appointments not accepted yet, in order.
+    pending: VecDeque<PendingAppointment>,
+    last_error: Option<String>,
+}
+
+impl TowerSession {
+    fn new(address: TowerAddress) -> Self {
+        Self {
+            address,
+            registered: false,
+            available_slots: 0,
+            subscription_expiry: 0,
+            state: TowerState::Active,
+            accepted: BTreeMap::new(),
+            pending: VecDeque::new(),
+            last_error: None,
+        }
+    }
+
+    fn failed(&mut self, state: TowerState, err: &error::Error) {
+        log::warn!(target: "watchtower", "tower `{}`: {err}", self.address);
+        self.state = state;
+        self.last_error = Some(err.to_string());
+    }
+
+    fn to_model(&self) -> response::Tower {
+        response::Tower {
+            tower_id: self.address.tower_id.clone(),
+            address: format!("{}:{}", self.address.host, self.address.port),
+            status: self.state.as_str().to_owned(),
+            registered: self.registered,
+            available_slots: self.available_slots,
+            subscription_expiry: self.subscription_expiry,
+            appointments: self.accepted.values().sum(),
+            pending_appointments: self.pending.len() as u64,
+            last_error: self.last_error.clone(),
+        }
+    }
+
+    fn to_json(&self) -> json::Value {
+        let pending = self
+            .pending
+            .iter()
+            .map(|pending| {
+                json::json!({
+                    "funding_txo": pending.funding_txo,
+                    "appointment": pending.appointment.to_json(),
+                })
+            })
+            .collect::<Vec<_>>();
+        json::json!({
+            "address": self.address.to_string(),
+            "registered": self.registered,
+            "available_slots": self.available_slots,
+            "subscription_expiry": self.subscription_expiry,
+            "status": self.state.as_str(),
+            "accepted": self.accepted,
+            "pending": pending,
+            "last_error": self.last_error,
+        })
+    }
+
+    fn from_json(value: &json::Value) -> error::Result<Self> {
+        let Some(address) = value["address"].as_str() else {
+            error::bail!("invalid tower session `{value}`");
+        };
+        let mut session = Self::new(TowerAddress::parse(address)?);
+        session.registered = value["registered"].as_bool().unwrap_or_default();
+        session.available_slots = value["available_slots"].as_u64().unwrap_or_default() as u32;
+        session.subscription_expiry =
+            value["subscription_expiry"].as_u64().unwrap_or_default() as u32;
+        session.state = TowerState::parse(value["status"].as_str().unwrap_or_default());
+        session.accepted = json::from_value(value["accepted"].clone()).unwrap_or_default();
+        for pending in value["pending"].as_array().into_iter().flatten() {
+            let Some(funding_txo) = pending["funding_txo"].as_str() else {
+                error::bail!("invalid pending appointment `{pending}`");
+            };
+            session.pending.push_back(PendingAppointment {
+                funding_txo: funding_txo.to_owned(),
+                appointment: Appointment::from_json(&pending["appointment"])?,
+            });
+        }
+        session.last_error = value["last_error"].as_str().map(str::to_owned);
+        Ok(session)
+    }
+}
+
+/// The answer of a tower to an appointment.
+enum Sent {
+    Accepted {
+        available_slots: u32,
+        subscription_expiry: u32,
+    },
+    /// The tower refused the appointment, e.g. because the
+    /// subscription is expired.
+    Refused(String),
+}
+
+pub struct LampoTowerClient {
+    persister: Arc<LampoPersistence>,
+    keys: Arc<LampoKeys>,
+    /// The sessions indexed by tower id.
+    sessions: Mutex<BTreeMap<String, TowerSession>>,
+    /// The justice transactions of the commitments not revoked yet,
+    /// indexed by funding outpoint.
+    justice_txs: Mutex<HashMap<String, VecDeque<UnsignedJustice>>>,
+    /// Only one thread sends to the towers at a time.
+    flushing: Mutex<()>,
+    wake: (chan::Sender<()>, chan::Receiver<()>),
+}
+
+impl LampoTowerClient {
+    pub fn new(persister: Arc<LampoPersistence>, keys: Arc<LampoKeys>) -> Self {
+        let sessions = Self::read_sessions(&persister).unwrap_or_else(|err| {
+            log::warn!(target: "watchtower", "impossible read the tower sessions: {err}");
+            BTreeMap::new()
+        });
+        let justice_txs = Self::read_justice_txs(&persister).unwrap_or_else(|err| {
+            log::warn!(target: "watchtower", "impossible read the justice transactions: {err}");
+            HashMap::new()
+        });
+        Self {
+            persister,
+            keys,
+            sessions: Mutex::new(sessions),
+            justice_txs: Mutex::new(justice_txs),
+            flushing: Mutex::new(()),
+            wake: chan::unbounded(),
+        }
+    }
+
+    fn read_sessions(
+        persister: &LampoPersistence,
+    ) -> error::Result<BTreeMap<String, TowerSession>> {
+        let mut sessions = BTreeMap::new();
+        for tower_id in persister.list(LAMPO_NAMESPACE, TOWERS_NAMESPACE)? {
+            let buf = persister.read(LAMPO_NAMESPACE, TOWERS_NAMESPACE, &tower_id)?;
+            let session = TowerSession::from_json(&json::from_slice(&buf)?)?;
+            sessions.insert(tower_id, session);
+        }
+        Ok(sessions)
+    }
+
+    fn write_session(&self, session: &TowerSession) -> io::Result<()> {
+        let buf = json::to_vec(&session.to_json())?;
+        self.persister.write(
+            LAMPO_NAMESPACE,
+            TOWERS_NAMESPACE,
+            &session.address.tower_id,
+            &buf,
+        )
+    }
+
+    fn read_justice_txs(
+        persister: &LampoPersistence,
+    ) -> error::Result<HashMap<String, VecDeque<UnsignedJustice>>> {
+        let buf = match persister.read(LAMPO_NAMESPACE, "", JUSTICE_TXS_KEY) {
+            Ok(buf) => buf,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let channels: HashMap<String, Vec<json::Value>> = json::from_slice(&buf)?;
+        channels
+            .into_iter()
+            .map(|(funding_txo, txs)| {
+                let txs = txs
+                    .iter()
+                    .map(UnsignedJustice::from_json)
+                    .collect::<error::Result<_>>()?;
+                Ok((funding_txo, txs))
+            })
+            .collect()
+    }
+
+    fn write_justice_txs(
+        &self,
+        channels: &HashMap<String, VecDeque<UnsignedJustice>>,
+    ) -> io::Result<()> {
+        let channels = channels
+            .iter()
+            .map(|(funding_txo, txs)| {
+                let txs = txs.iter().map(UnsignedJustice::to_json).collect::<Vec<_>>();
+                (funding_txo.clone(), txs)
+            })
+            .collect::<HashMap<_, _>>();
+        let buf = json::to_vec(&channels)?;
+        self.persister
+            .write(LAMPO_NAMESPACE, "", JUSTICE_TXS_KEY, &buf)
+    }
+
+    /// Add the tower at `address`, `<tower id>@<host>[:<port>]`, the node
+    /// registers with it at the next flush. A tower already known takes
+    /// the new address and its errors are cleared.
+    pub fn add_tower(&self, address: &str) -> error::Result<String> {
+        let address = TowerAddress::parse(address)?;
+        let tower_id = address.tower_id.clone();
+        let mut sessions = self.sessions.lock_or_recover();
+        let session = sessions
+            .entry(tower_id.clone())
+            .or_insert_with(|| TowerSession::new(address.clone()));
+        if session.address != address {
+            session.address = address;
+            session.registered = false;
+        }
+        session.state = TowerState::Active;
+        session.last_error = None;
+        self.write_session(session)?;
+        log::info!(target: "watchtower", "added the tower `{}`", session.address);
+        let _ = self.wake.0.send(());
+        Ok(tower_id)
+    }
+
+    fn has_towers(&self) -> bool {
+        !self.sessions.lock_or_recover().is_empty()
+    }
+
+    /// The address where the justice transactions send the funds.
+    fn sweep_script(&self) -> error::Result<ScriptBuf> {
+        let script = self.keys.inner().get_shutdown_scriptpubkey().map_err(|_| {
+            error::anyhow!("impossible get the sweep script of the justice transactions")
+        })?;
+        Ok(script.into_inner())
+    }
+
+    /// Keep the first commitment of the peer of a new channel.
+    pub fn new_channel(
+        &self,
+        funding_txo: OutPoint,
+        monitor: &ChannelMonitor<InMemorySigner>,
+        feerate_per_kw: u32,
+    ) -> error::Result<()> {
+        // the channels loaded at the start are not new
+        if !self.has_towers() || monitor.get_latest_update_id() != 0 {
+            return Ok(());
+        }
+        let Some(commitment) = monitor.initial_counterparty_commitment_tx() else {
+            return Ok(());
+        };
+        self.track(funding_txo, monitor, vec![commitment], feerate_per_kw)
+    }
+
+    /// Keep the new commitments of the peer inside `update`, and send
+    /// to the towers the justice transactions of the ones revoked.
+    pub fn channel_updated(
+        &self,
+        funding_txo: OutPoint,
+        update: &ChannelMonitorUpdate,
+        monitor: &ChannelMonitor<InMemorySigner>,
+        feerate_per_kw: u32,
+    ) -> error::Result<()> {
+        if !self.has_towers() {
+            return Ok(());
+        }
+        let commitments = monitor.counterparty_commitment_txs_from_update(update);
+        self.track(funding_txo, monitor, commitments, feerate_per_kw)
+    }
+
+    fn track(
+        &self,
+        funding_txo: OutPoint,
+        monitor: &ChannelMonitor<InMemorySigner>,
+        commitments: Vec<CommitmentTransaction>,
+        feerate_per_kw: u32,
+    ) -> error::Result<()> {
+        let channel = funding_txo.to_string();
+        let sweep = self.sweep_script()?;
+        let mut channels = self.justice_txs.lock_or_recover();
+        let justice_txs = channels.entry(channel.clone()).or_default();
+        let mut changed = false;
+        for commitment in &commitments {
+            if let Some(justice) = UnsignedJustice::new(commitment, feerate_per_kw, sweep.clone()) {
+                justice_txs.push_back(justice);
+                changed = true;
+            }
+        }
+        let mut signed = Vec::new();
+        while let Some(justice) = justice_txs.front() {
+            // it fails until the peer revokes the commitment
+            let Ok(tx) = monitor.sign_to_local_justice_tx(
+                justice.tx.clone(),
+                0,
+                justice.value,
+                justice.commitment_number,
+            ) else {
+                break;
+            };
+            signed.push(tx);
+            justice_txs.pop_front();
+            changed = true;
+        }
+        if changed {
+            self.write_justice_txs(&channels)?;
+        }
+        drop(channels);
+        if signed.is_empty() {
+            return Ok(());
+        }
+        let appointments = signed
+            .iter()
+            .map(Appointment::new)
+            .collect::<error::Result<Vec<_>>>()?;
+        let mut sessions = self.sessions.lock_or_recover();
+        for session in sessions.values_mut() {
+            for appointment in &appointments {
+                session.pending.push_back(PendingAppointment {
+                    funding_txo: channel.clone(),
+                    appointment: appointment.clone(),
+                });
+            }
+            self.write_session(session)?;
+        }
+        log::debug!(
+            target: "watchtower",
+            "queued {} appointments of the channel `{channel}`",
+            appointments.len()
+        );
+        let _ = self.wake.0.send(());
+        Ok(())
+    }
+
+    /// Send the backlog of every tower.
+    pub fn flush(&self) {
+        let tower_ids = self
+            .sessions
+            .lock_or_recover()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        for tower_id in tower_ids {
+            // the error is kept inside the session
+            let _ = self.flush_tower(&tower_id);
+        }
+    }
+
+    /// Register with the tower if needed, and send its backlog until
+    /// it is empty or the tower fails.
+    pub fn flush_tower(&self, tower_id: &str) -> error::Result<()> {
+        let _flushing = self.flushing.lock_or_recover();
+        let mut renewed = false;
+        loop {
+            // the requests are sent without the lock, so the channels
+            // can queue new appointments in the meanwhile
+            let (address, registered, next) = {
+                let sessions = self.sessions.lock_or_recover();
+                let Some(session) = sessions.get(tower_id) else {
+                    error::bail!("the tower `{tower_id}` is unknown");
+                };
+                if session.state == TowerState::Misbehaving {
+                    return Ok(());
+                }
+                let next = session.pending.front().cloned();
+                (session.address.clone(), session.registered, next)
+            };
+            if !registered {
+                let result = self.register(&address);
+                let mut sessions = self.sessions.lock_or_recover();
+                let Some(session) = sessions.get_mut(tower_id) else {
+                    return Ok(());
+                };
+                let result = match result {
+                    Ok((available_slots, subscription_expiry)) => {
+                        session.registered = true;
+                        session.available_slots = available_slots;
+                        session.subscription_expiry = subscription_expiry;
+                        session.state = TowerState::Active;
+                        session.last_error = None;
+                        renewed = true;
+                        Ok(())
+                    }
+                    Err(err) => {
+                        session.failed(TowerState::Unreachable, &err);
+                        Err(err)
+                    }
+                };
+                self.write_session(session)?;
+                result?;
+                continue;
+            }
+            let Some(next) = next else {
+                return Ok(());
+            };
+            let result = self.send_appointment(&address, &next.appointment);
+            let mut sessions = self.sessions.lock_or_recover();
+            let Some(session) = sessions.get_mut(tower_id) else {
+                return Ok(());
+            };
+            let result = match result {
+                Ok(Sent::Accepted {
+                    available_slots,
+                    subscription_expiry,
+                }) => {
+                    session.pending.pop_front();
+                    *session.accepted.entry(next.funding_txo).or_default() += 1;
+                    session.available_slots = available_slots;
+                    session.subscription_expiry = subscription_expiry;
+                    session.state = TowerState::Active;
+                    session.last_error = None;
+                    Ok(())
+                }
+                // the subscription is renewed once, then the tower
+                // refuses the appointment itself
+                Ok(Sent::Refused(reason)) if renewed => {
+                    session.pending.pop_front();
+                    let err = error::anyhow!(
+                        "appointment `{}` of the channel `{}` refused: {reason}",
+                        next.appointment.locator.to_hex(),
+                        next.funding_txo
+                    );
+                    session.failed(TowerState::Active, &err);
+                    Ok(())
+                }
+                Ok(Sent::Refused(reason)) => {
+                    session.registered = false;
+                    session.last_error = Some(reason);
+                    Ok(())
+                }
+                Err(err) => {
+                    let state = if err.is::<Misbehaving>() {
+                        TowerState::Misbehaving
+                    } else {
+                        TowerState::Unreachable
+                    };
+                    session.failed(state, &err);
+                    Err(err)
+                }
+            };
+            self.write_session(session)?;
+            result?;
+        }
+    }
+
+    /// Register the node with the tower, returning the slots available
+    /// and the expiry of the subscription.
+    fn register(&self, address: &TowerAddress) -> error::Result<(u32, u32)> {
+        let body = json::json!({ "user_id": self.keys.node_id().to_string() });
+        let reply = post(&format!("{}/register", address.url()), &body)?;
+        let (Some(available_slots), Some(subscription_expiry)) = (
+            reply["available_slots"].as_u64(),
+            reply["subscription_expiry"].as_u64(),
+        ) else {
+            error::bail!("invalid registration `{reply}` of the tower");
+        };
+        Ok((available_slots as u32, subscription_expiry as u32))
+    }
+
+    fn send_appointment(
+        &self,
+        address: &TowerAddress,
+        appointment: &Appointment,
+    ) -> error::Result<Sent> {
+        let signature = self
+            .keys
+            .inner()
+            .sign_message(&appointment.to_signed_bytes())?;
+        let body = json::json!({
+            "appointment": appointment.to_json(),
+            "signature": signature,
+        });
+        let reply = match post(&format!("{}/add_appointment", address.url()), &body) {
+            Ok(reply) => reply,
+            Err(err) => match err.downcast::<Refused>() {
+                Ok(Refused(reason)) => return Ok(Sent::Refused(reason)),
+                Err(err) => return Err(err),
+            },
+        };
+        let Some(signature) = reply["signature"].as_str() else {
+            error::bail!("the tower accepted the appointment without a signature");
+        };
+        let signer = recover_message_signer(&appointment.to_signed_bytes(), signature)?;
+        if signer.to_string() != address.tower_id {
+            return Err(
+                Misbehaving(format!("the tower signed the appointment with `{signer}`")).into(),
+            );
+        }
+        Ok(Sent::Accepted {
+            available_slots: reply["available_slots"].as_u64().unwrap_or_default() as u32,
+            subscription_expiry: reply["subscription_expiry"].as_u64().unwrap_or_default() as u32,
+        })
+    }
+
+    /// The towers with the state of their session.
+    pub fn towers(&self) -> Vec<response::Tower> {
+        let sessions = self.sessions.lock_or_recover();
+        sessions.values().map(TowerSession::to_model).collect()
+    }
+
+    /// The state of the session with `tower_id` and the appointments
+    /// of every channel.
+    pub fn status(&self, tower_id: &str) -> Option<response::TowerStatus> {
+        let sessions = self.sessions.lock_or_recover();
+        let session = sessions.get(tower_id)?;
+        let mut channels = BTreeMap::<String, response::TowerChannel>::new();
+        for (funding_txo, appointments) in &session.accepted {
+            channels
+                .entry(funding_txo.clone())
+                .or_insert_with(|| tower_channel(funding_txo))
+                .appointments = *appointments;
+        }
+        for pending in &session.pending {
+            channels
+                .entry(pending.funding_txo.clone())
+                .or_insert_with(|| tower_channel(&pending.funding_txo))
+                .pending_appointments += 1;
+        }
+        Some(response::TowerStatus {
+            tower: session.to_model(),
+            channels: channels.into_values().collect(),
+        })
+    }
+
+    /// Send the appointments in background when they are queued, and
+    /// retry the towers that failed every `TOWER_RETRY_INTERVAL`.
+    pub fn start(self: Arc<Self>, stopped: Arc<AtomicBool>) -> JoinHandle<()> {
+        std::thread::spawn(move || {
+            while !stopped.load(Ordering::SeqCst) {
+                let _ = self.wake.1.recv_timeout(TOWER_RETRY_INTERVAL);
+                // the appointments queued together are sent together
+                while self.wake.1.try_recv().is_ok() {}
+                self.flush();
+            }
+        })
+    }
+}
+
+fn tower_channel(funding_txo: &str) -> response::TowerChannel {
+    response::TowerChannel {
+        funding_txo: funding_txo.to_owned(),
+        appointments: 0,
+        pending_appointments: 0,
+    }
+}
+
+/// The tower answered with an error to the request.
+#[derive(Debug)]
+struct Refused(String);
+
+impl fmt::Display for Refused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the tower refused the request: {}", self.0)
+    }
+}
+
+impl std::error::Error for Refused {}
+
+/// The tower did not sign with its key.
+#[derive(Debug)]
+struct Misbehaving(String);
+
+impl fmt::Display for Misbehaving {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the tower is misbehaving: {}", self.0)
+    }
+}
+
+impl std::error::Error for Misbehaving {}
+
+fn post(url: &str, body: &json::Value) -> error::Result<json::Value> {
+    let response = minreq::post(url)
+        .with_header("Content-Type", "application/json")
+        .with_body(json::to_vec(body)?)
+        .with_timeout(REQUEST_TIMEOUT_SECS)
+        .send()?;
+    let reply: json::Value = json::from_slice(response.as_bytes()).unwrap_or_default();
+    match response.status_code {
+        200..=299 => Ok(reply),
+        400..=499 => {
+            let reason = reply["error"]
+                .as_str()
+                .map(str::to_owned)
+                .unwrap_or_else(|| format!("{} {}", response.status_code, response.reason_phrase));
+            Err(Refused(reason).into())
+        }
+        status => error::bail!("the tower replied `{status} {}`", response.reason_phrase),
+    }
+}
@@ -0,0 +1,9 @@
+//! Watchtowers of the node, the ones that back up its channels and
+//! the one served by the node to the other nodes.
+mod client;
+pub mod protocol;
+mod server;
+mod store;
+
+pub use client::{LampoTowerClient, TOWER_RETRY_INTERVAL};
+pub use server::LampoTowerServer;
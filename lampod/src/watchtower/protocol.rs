@@ -0,0 +1,194 @@
+//! The messages of the tower protocol, the one spoken by The Eye of
+//! Satoshi over HTTP.
+//!
+//! An appointment tells the tower how to punish a revoked commitment
+//! without telling which one: the locator is the first half of the
+//! txid of the commitment, and the justice transaction is encrypted
+//! with ChaCha20-Poly1305, a zero nonce and the SHA256 of the txid as
+//! the key. The tower can read the justice transaction only when the
+//! commitment is on the chain.
+//!
+//! The user is identified by its node id, and signs every appointment
+//! with the lightning message signature of
+//! `locator || encrypted_blob || to_self_delay`, the same signature
+//! returned by the tower when it accepts the appointment.
+use std::fmt;
+
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+use lampo_common::bitcoin::blockdata::opcodes::all::OP_ELSE;
+use lampo_common::bitcoin::blockdata::script::{read_scriptint, Instruction, Script};
+use lampo_common::bitcoin::consensus::{deserialize, serialize};
+use lampo_common::bitcoin::hashes::hex::{FromHex, ToHex};
+use lampo_common::bitcoin::hashes::sha256;
+use lampo_common::bitcoin::hashes::Hash;
+use lampo_common::bitcoin::{Transaction, Txid};
+use lampo_common::error;
+use lampo_common::json;
+use lampo_common::types::NodeId;
+
+/// The port of a tower when the address does not have one.
+pub const DEFAULT_TOWER_PORT: u16 = 9814;
+
+/// The locator of the appointments of a commitment.
+pub type Locator = [u8; 16];
+
+pub fn locator(commitment_txid: &Txid) -> Locator {
+    let mut locator = [0; 16];
+    locator.copy_from_slice(&commitment_txid.to_byte_array()[..16]);
+    locator
+}
+
+fn cipher(commitment_txid: &Txid) -> ChaCha20Poly1305 {
+    let key = sha256::Hash::hash(&commitment_txid.to_byte_array());
+    ChaCha20Poly1305::new(Key::from_slice(&key.to_byte_array()))
+}
+
+/// Encrypt the `justice_tx` of the commitment `commitment_txid`.
+pub fn encrypt(justice_tx: &Transaction, commitment_txid: &Txid) -> error::Result<Vec<u8>> {
+    cipher(commitment_txid)
+        .encrypt(
+            Nonce::from_slice(&[0; 12]),
+            serialize(justice_tx).as_slice(),
+        )
+        .map_err(|_| error::anyhow!("impossible encrypt the justice transaction"))
+}
+
+/// Decrypt the justice transaction of the commitment `commitment_txid`.
+pub fn decrypt(blob: &[u8], commitment_txid: &Txid) -> error::Result<Transaction> {
+    let buf = cipher(commitment_txid)
+        .decrypt(Nonce::from_slice(&[0; 12]), blob)
+        .map_err(|_| error::anyhow!("the blob is not encrypted for `{commitment_txid}`"))?;
+    Ok(deserialize(&buf)?)
+}
+
+/// The delay of the revoked output spent by `justice_tx`, read from
+/// the witness script of its first input.
+pub fn to_self_delay(justice_tx: &Transaction) -> Option<u32> {
+    let script = Script::from_bytes(justice_tx.input.first()?.witness.last()?);
+    // `OP_IF <revocation key> OP_ELSE <delay> OP_CSV ...`
+    let mut instructions = script.instructions();
+    while let Some(Ok(instruction)) = instructions.next() {
+        if instruction.opcode() != Some(OP_ELSE) {
+            continue;
+        }
+        let Some(Ok(Instruction::PushBytes(delay))) = instructions.next() else {
+            return None;
+        };
+        return read_scriptint(delay.as_bytes())
+            .ok()
+            .and_then(|delay| u32::try_from(delay).ok());
+    }
+    None
+}
+
+/// An appointment for a tower.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Appointment {
+    pub locator: Locator,
+    pub encrypted_blob: Vec<u8>,
+    pub to_self_delay: u32,
+}
+
+impl Appointment {
+    /// The appointment to punish the commitment spent by `justice_tx`.
+    pub fn new(justice_tx: &Transaction) -> error::Result<Self> {
+        let Some(input) = justice_tx.input.first() else {
+            error::bail!(
+                "the justice transaction `{}` has no inputs",
+                justice_tx.txid()
+            );
+        };
+        let commitment_txid = input.previous_output.txid;
+        Ok(Self {
+            locator: locator(&commitment_txid),
+            encrypted_blob: encrypt(justice_tx, &commitment_txid)?,
+            to_self_delay: to_self_delay(justice_tx).unwrap_or_default(),
+        })
+    }
+
+    /// The bytes signed by the user and by the tower.
+    pub fn to_signed_bytes(&self) -> Vec<u8> {
+        let mut buf = self.locator.to_vec();
+        buf.extend_from_slice(&self.encrypted_blob);
+        buf.extend_from_slice(&self.to_self_delay.to_be_bytes());
+        buf
+    }
+
+    pub fn to_json(&self) -> json::Value {
+        json::json!({
+            "locator": self.locator.to_hex(),
+            "encrypted_blob": self.encrypted_blob.to_hex(),
+            "to_self_delay": self.to_self_delay,
+        })
+    }
+
+    pub fn from_json(value: &json::Value) -> error::Result<Self> {
+        let locator = value["locator"]
+            .as_str()
+            .and_then(|hex| Vec::<u8>::from_hex(hex).ok());
+        let encrypted_blob = value["encrypted_blob"]
+            .as_str()
+            .and_then(|hex| Vec::<u8>::from_hex(hex).ok());
+        let to_self_delay = value["to_self_delay"]
+            .as_u64()
+            .and_then(|delay| u32::try_from(delay).ok());
+        match (locator, encrypted_blob, to_self_delay) {
+            (Some(locator), Some(encrypted_blob), Some(to_self_delay)) if locator.len() == 16 => {
+                Ok(Self {
+                    locator: locator.try_into().expect("the length is checked"),
+                    encrypted_blob,
+                    to_self_delay,
+                })
+            }
+            _ => error::bail!("invalid appointment `{value}`"),
+        }
+    }
+}
+
+/// The address of a tower, `<tower id>@<host>[:<port>]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TowerAddress {
+    pub tower_id: String,
+    pub host: String,
+    pub port: u16,
+}
+
+impl TowerAddress {
+    pub fn parse(address: &str) -> error::Result<Self> {
+        let Some((tower_id, host)) = address.split_once('@') else {
+            error::bail!("invalid tower `{address}`, it must be `<tower id>@<host>[:<port>]`");
+        };
+        let tower_id: NodeId = tower_id
+            .parse()
+            .map_err(|_| error::anyhow!("invalid tower id `{tower_id}`"))?;
+        let (host, port) = match host.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port
+                    .parse()
+                    .map_err(|_| error::anyhow!("invalid port `{port}` of the tower"))?;
+                (host, port)
+            }
+            None => (host, DEFAULT_TOWER_PORT),
+        };
+        if host.is_empty() {
+            error::bail!("invalid tower `{address}`, the host is empty");
+        }
+        Ok(Self {
+            tower_id: tower_id.to_string(),
+            host: host.to_owned(),
+            port,
+        })
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}:{}", self.host, self.port)
+    }
+}
+
+impl fmt::Display for TowerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}:{}", self.tower_id, self.host, self.port)
+    }
+}
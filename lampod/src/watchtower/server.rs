@@ -0,0 +1,333 @@
+//! The tower served by the node to the other nodes.
+//!
+//! The clients register and send their appointments over HTTP, see
+//! `protocol`, and the appointments are kept inside sqlite. Every
+//! block of the backend is looked for the transactions with the
+//! locator of an appointment: the justice transaction is decrypted
+//! with the txid of the revoked commitment and broadcast, and the
+//! broadcasts that fail are retried at every block with the other
+//! transactions of the node.
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use lampo_common::backend::BlockData;
+use lampo_common::bitcoin::Block;
+use lampo_common::chan;
+use lampo_common::conf::LampoConf;
+use lampo_common::error;
+use lampo_common::event::onchain::OnChainEvent;
+use lampo_common::event::Event;
+use lampo_common::json;
+use lampo_common::keys::{recover_message_signer, LampoKeys};
+use lampo_common::model::response;
+use lampo_common::types::NodeId;
+
+use super::protocol::{self, Appointment};
+use super::store::{Stored, TowerStore};
+use crate::chain::LampoChainManager;
+use crate::http::{read_request, HttpResponse};
+
+/// The file of the appointments inside the datadir.
+const TOWER_DB: &str = "tower.sqlite";
+/// Maximum size in bytes of a request, an appointment is less than a
+/// kilobyte.
+const MAX_BODY_BYTES: usize = 64 * 1024;
+/// A client that does not send its request in time is disconnected.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often the listener checks if the node is stopping.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+/// How often the chain is looked for the breaches without a new block.
+const SCAN_INTERVAL: Duration = Duration::from_secs(10);
+
+pub struct LampoTowerServer {
+    listener: TcpListener,
+    store: TowerStore,
+    keys: Arc<LampoKeys>,
+    onchain: Arc<LampoChainManager>,
+    max_appointments: u32,
+    subscription_blocks: u32,
+}
+
+impl LampoTowerServer {
+    pub fn new(
+        conf: &LampoConf,
+        keys: Arc<LampoKeys>,
+        onchain: Arc<LampoChainManager>,
+    ) -> error::Result<Self> {
+        let Some(bind) = conf.tower_bind else {
+            error::bail!("the tower is disabled, set `tower-bind` to enable it");
+        };
+        if onchain.is_lightway() {
+            error::bail!("the tower needs the full blocks, it does not work with a light backend");
+        }
+        std::fs::create_dir_all(conf.path())?;
+        let store = TowerStore::open(&Path::new(&conf.path()).join(TOWER_DB))?;
+        let listener = TcpListener::bind(bind)
+            .map_err(|err| error::anyhow!("impossible bind the tower on `{bind}`: {err}"))?;
+        Ok(Self {
+            listener,
+            store,
+            keys,
+            onchain,
+            max_appointments: conf.tower_max_appointments,
+            subscription_blocks: conf.tower_subscription_blocks,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serve the clients and watch the chain until the node stops.
+    pub fn start(
+        self: Arc<Self>,
+        events: chan::Receiver<Event>,
+        stopped: Arc<AtomicBool>,
+    ) -> error::Result<JoinHandle<()>> {
+        self.listener.set_nonblocking(true)?;
+        log::info!(target: "watchtower", "tower listening on `{}`", self.local_addr()?);
+        let server = self.clone();
+        let server_stopped = stopped.clone();
+        std::thread::spawn(move || server.listen(server_stopped));
+        Ok(std::thread::spawn(move || self.watch(events, stopped)))
+    }
+
+    fn listen(self: Arc<Self>, stopped: Arc<AtomicBool>) {
+        while !stopped.load(Ordering::SeqCst) {
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    let server = self.clone();
+                    std::thread::spawn(move || {
+                        if let Err(err) = server.serve(stream) {
+                            log::debug!(target: "watchtower", "connection with `{addr}` failed: {err}");
+                        }
+                    });
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(ACCEPT_INTERVAL)
+                }
+                Err(err) => {
+                    log::warn!(target: "watchtower", "impossible accept a connection: {err}");
+                    std::thread::sleep(ACCEPT_INTERVAL);
+                }
+            }
+        }
+        log::info!(target: "watchtower", "stopping the tower");
+    }
+
+    fn serve(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let response = match read_request(&mut stream, MAX_BODY_BYTES)? {
+            Ok(request) if request.method != "POST" => HttpResponse::method_not_allowed("POST"),
+            Ok(request) => {
+                let body = json::from_slice::<json::Value>(&request.body).unwrap_or_default();
+                let reply = match request.path.as_str() {
+                    "/register" => self.register(&body),
+                    "/add_appointment" => self.add_appointment(&body),
+                    _ => Err(Refused(404, "not found".to_owned())),
+                };
+                match reply {
+                    Ok(reply) => HttpResponse::json(200, &reply),
+                    Err(Refused(status, reason)) => {
+                        HttpResponse::json(status, &json::json!({ "error": reason }))
+                    }
+                }
+            }
+            Err(response) => response,
+        };
+        response.write(&mut stream)
+    }
+
+    fn register(&self, body: &json::Value) -> Result<json::Value, Refused> {
+        let Some(user_id) = body["user_id"].as_str() else {
+            return Err(Refused::bad_request("missing `user_id`"));
+        };
+        let user_id: NodeId = user_id
+            .parse()
+            .map_err(|_| Refused::bad_request(&format!("invalid user id `{user_id}`")))?;
+        let height = self.height().map_err(Refused::internal)?;
+        let (available_slots, subscription_expiry) = self
+            .store
+            .register(
+                &user_id.to_string(),
+                self.max_appointments,
+                height + self.subscription_blocks,
+            )
+            .map_err(Refused::internal)?;
+        log::info!(target: "watchtower", "registered the user `{user_id}` until the height {subscription_expiry}");
+        Ok(json::json!({
+            "user_id": user_id.to_string(),
+            "available_slots": available_slots,
+            "subscription_expiry": subscription_expiry,
+        }))
+    }
+
+    fn add_appointment(&self, body: &json::Value) -> Result<json::Value, Refused> {
+        let appointment = Appointment::from_json(&body["appointment"])
+            .map_err(|err| Refused::bad_request(&err.to_string()))?;
+        let Some(signature) = body["signature"].as_str() else {
+            return Err(Refused::bad_request("missing `signature`"));
+        };
+        let user_id = recover_message_signer(&appointment.to_signed_bytes(), signature)
+            .map_err(|err| Refused::bad_request(&err.to_string()))?
+            .to_string();
+        let height = self.height().map_err(Refused::internal)?;
+        let stored = self
+            .store
+            .add_appointment(&user_id, &appointment, height)
+            .map_err(Refused::internal)?;
+        let (available_slots, subscription_expiry) = match stored {
+            Stored::Accepted {
+                available_slots,
+                subscription_expiry,
+            } => (available_slots, subscription_expiry),
+            Stored::Refused(reason) => return Err(Refused::bad_request(&reason)),
+        };
+        let signature = self
+            .keys
+            .inner()
+            .sign_message(&appointment.to_signed_bytes())
+            .map_err(Refused::internal)?;
+        log::debug!(target: "watchtower", "stored an appointment of the user `{user_id}`");
+        Ok(json::json!({
+            "locator": appointment.to_json()["locator"],
+            "start_block": height,
+            "signature": signature,
+            "available_slots": available_slots,
+            "subscription_expiry": subscription_expiry,
+        }))
+    }
+
+    /// The height of the tip of the backend.
+    fn height(&self) -> error::Result<u32> {
+        let (_, height) = self.onchain.backend.get_best_block()?;
+        let Some(height) = height else {
+            error::bail!("the backend does not know the height of the tip");
+        };
+        Ok(height)
+    }
+
+    /// Look for the breaches inside the new blocks, until the node
+    /// stops.
+    fn watch(&self, events: chan::Receiver<Event>, stopped: Arc<AtomicBool>) {
+        while !stopped.load(Ordering::SeqCst) {
+            if let Err(err) = self.scan() {
+                log::error!(target: "watchtower", "impossible look for the breaches: {err}");
+            }
+            match events.recv_timeout(SCAN_INTERVAL) {
+                Ok(Event::OnChain(OnChainEvent::NewBestBlock(_)))
+                | Err(chan::RecvTimeoutError::Timeout) => {
+                    // the blocks notified together are scanned together
+                    while events.try_recv().is_ok() {}
+                }
+                Ok(_) => continue,
+                Err(chan::RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    }
+
+    /// Scan the blocks after the last one scanned, a new tower starts
+    /// from the tip.
+    fn scan(&self) -> error::Result<()> {
+        let tip = self.height()?;
+        let Some(scanned) = self.store.scanned_height()? else {
+            return self.store.set_scanned_height(tip);
+        };
+        for height in scanned + 1..=tip {
+            let hash = self.onchain.backend.get_block_hash_at(height)?;
+            let BlockData::FullBlock(block) = self.onchain.backend.get_block(&hash)? else {
+                error::bail!("the backend does not return the transactions of the block `{hash}`");
+            };
+            self.block_connected(&block, height)?;
+            self.store.set_scanned_height(height)?;
+        }
+        let expired = self.store.expire(tip)?;
+        if expired > 0 {
+            log::info!(target: "watchtower", "deleted {expired} appointments of the expired subscriptions");
+        }
+        Ok(())
+    }
+
+    /// Broadcast the justice transactions of the revoked commitments
+    /// inside `block`.
+    fn block_connected(&self, block: &Block, height: u32) -> error::Result<()> {
+        for tx in &block.txdata {
+            let txid = tx.txid();
+            let locator = protocol::locator(&txid);
+            for (user_id, appointment) in self.store.appointments(&locator)? {
+                // the locator is only half of the txid
+                let justice_tx = match protocol::decrypt(&appointment.encrypted_blob, &txid) {
+                    Ok(justice_tx) => justice_tx,
+                    Err(err) => {
+                        log::warn!(target: "watchtower", "appointment of the user `{user_id}` for `{txid}` not valid: {err}");
+                        continue;
+                    }
+                };
+                if !justice_tx
+                    .input
+                    .iter()
+                    .any(|input| input.previous_output.txid == txid)
+                {
+                    log::warn!(target: "watchtower", "the justice transaction of the user `{user_id}` does not spend `{txid}`");
+                    continue;
+                }
+                log::info!(target: "watchtower", "breach `{txid}` of the user `{user_id}` at the height {height}, broadcasting the justice transaction `{}`", justice_tx.txid());
+                // the broadcast that fails is retried at the next block
+                if let Err(err) = self.onchain.broadcast(&justice_tx) {
+                    log::warn!(target: "watchtower", "impossible broadcast the justice transaction `{}`: {err}", justice_tx.txid());
+                }
+                self.store.breach(
+                    &user_id,
+                    &locator,
+                    &txid.to_string(),
+                    &justice_tx.txid().to_string(),
+                    height,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn info(&self) -> error::Result<response::TowerInfo> {
+        Ok(response::TowerInfo {
+            tower_id: self.keys.node_id().to_string(),
+            bind: self.local_addr()?.to_string(),
+            clients: self.store.clients()?.len() as u64,
+            appointments: self.store.count_appointments()?,
+            max_appointments: self.max_appointments,
+            subscription_blocks: self.subscription_blocks,
+            scanned_height: self.store.scanned_height()?,
+            breaches: self.store.breaches()?,
+        })
+    }
+
+    pub fn clients(&self) -> error::Result<Vec<response::TowerClient>> {
+        self.store.clients()
+    }
+
+    /// Forget the client `user_id` and its appointments, returning
+    /// false when the client is unknown.
+    pub fn del_client(&self, user_id: &str) -> error::Result<bool> {
+        self.store.del_client(user_id)
+    }
+}
+
+/// The status and the reason of a request refused.
+struct Refused(u16, String);
+
+impl Refused {
+    fn bad_request(reason: &str) -> Self {
+        Self(400, reason.to_owned())
+    }
+
+    fn internal(err: error::Error) -> Self {
+        log::error!(target: "watchtower", "{err}");
+        Self(500, err.to_string())
+    }
+}
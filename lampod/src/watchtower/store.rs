@@ -0,0 +1,316 @@
+//! The appointments stored by the tower for its clients, inside
+//! sqlite.
+//!
+//! A client registers and takes `max_appointments` slots until the
+//! subscription expires; an appointment with a locator already stored
+//! for the client replaces the old one without taking a slot. When the
+//! revoked commitment of an appointment is on the chain the appointment
+//! becomes a breach, with the justice transaction broadcast.
+use std::path::Path;
+use std::sync::Mutex;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use lampo_common::bitcoin::hashes::hex::ToHex;
+use lampo_common::error;
+use lampo_common::model::response;
+use lampo_common::sync::MutexExt;
+
+use super::protocol::{Appointment, Locator};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS users (
+    user_id TEXT PRIMARY KEY,
+    available_slots INTEGER NOT NULL,
+    subscription_expiry INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS appointments (
+    locator BLOB NOT NULL,
+    user_id TEXT NOT NULL,
+    encrypted_blob BLOB NOT NULL,
+    to_self_delay INTEGER NOT NULL,
+    PRIMARY KEY (locator, user_id)
+);
+CREATE TABLE IF NOT EXISTS breaches (
+    locator BLOB NOT NULL,
+    user_id TEXT NOT NULL,
+    commitment_txid TEXT NOT NULL,
+    justice_txid TEXT NOT NULL,
+    height INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS chain (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    scanned_height INTEGER NOT NULL
+);
+";
+
+/// The answer of the tower to an appointment.
+pub enum Stored {
+    Accepted {
+        available_slots: u32,
+        subscription_expiry: u32,
+    },
+    /// The appointment is refused, e.g. because the subscription is
+    /// expired or there are no slots.
+    Refused(String),
+}
+
+pub struct TowerStore {
+    conn: Mutex<Connection>,
+}
+
+impl TowerStore {
+    pub fn open(path: &Path) -> error::Result<Self> {
+        let conn = Connection::open(path).map_err(|err| {
+            error::anyhow!(
+                "impossible open the tower database `{}`: {err}",
+                path.display()
+            )
+        })?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Register `user_id` until `subscription_expiry`, a client already
+    /// registered takes back the slots up to `max_appointments` minus
+    /// the appointments stored.
+    pub fn register(
+        &self,
+        user_id: &str,
+        max_appointments: u32,
+        subscription_expiry: u32,
+    ) -> error::Result<(u32, u32)> {
+        let conn = self.conn.lock_or_recover();
+        let stored = user_appointments(&conn, user_id)?;
+        let available_slots = max_appointments.saturating_sub(stored as u32);
+        conn.execute(
+            "INSERT INTO users (user_id, available_slots, subscription_expiry) VALUES (?1, ?2, ?3)
+             ON CONFLICT (user_id) DO UPDATE SET
+                available_slots = excluded.available_slots,
+                subscription_expiry = excluded.subscription_expiry",
+            params![user_id, available_slots, subscription_expiry],
+        )?;
+        Ok((available_slots, subscription_expiry))
+    }
+
+    /// Store the `appointment` of `user_id` when the chain is at `height`.
+    pub fn add_appointment(
+        &self,
+        user_id: &str,
+        appointment: &Appointment,
+        height: u32,
+    ) -> error::Result<Stored> {
+        let mut conn = self.conn.lock_or_recover();
+        let tx = conn.transaction()?;
+        let user = tx
+            .query_row(
+                "SELECT available_slots, subscription_expiry FROM users WHERE user_id = ?1",
+                params![user_id],
+                |row| Ok((row.get::<_, u32>(0)?, row.get::<_, u32>(1)?)),
+            )
+            .optional()?;
+        let Some((mut available_slots, subscription_expiry)) = user else {
+            return Ok(Stored::Refused(format!(
+                "the user `{user_id}` is not registered"
+            )));
+        };
+        if subscription_expiry <= height {
+            return Ok(Stored::Refused(format!(
+                "the subscription expired at the height {subscription_expiry}"
+            )));
+        }
+        let replaced = tx
+            .query_row(
+                "SELECT 1 FROM appointments WHERE locator = ?1 AND user_id = ?2",
+                params![appointment.locator.as_slice(), user_id],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        if !replaced {
+            if available_slots == 0 {
+                return Ok(Stored::Refused("no slots available".to_owned()));
+            }
+            available_slots -= 1;
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO appointments (locator, user_id, encrypted_blob, to_self_delay)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                appointment.locator.as_slice(),
+                user_id,
+                appointment.encrypted_blob,
+                appointment.to_self_delay
+            ],
+        )?;
+        tx.execute(
+            "UPDATE users SET available_slots = ?1 WHERE user_id = ?2",
+            params![available_slots, user_id],
+        )?;
+        tx.commit()?;
+        Ok(Stored::Accepted {
+            available_slots,
+            subscription_expiry,
+        })
+    }
+
+    /// The appointments of every user with the `locator`.
+    pub fn appointments(&self, locator: &Locator) -> error::Result<Vec<(String, Appointment)>> {
+        let conn = self.conn.lock_or_recover();
+        let mut stmt = conn.prepare(
+            "SELECT user_id, encrypted_blob, to_self_delay FROM appointments WHERE locator = ?1",
+        )?;
+        let appointments = stmt
+            .query_map(params![locator.as_slice()], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    Appointment {
+                        locator: *locator,
+                        encrypted_blob: row.get(1)?,
+                        to_self_delay: row.get(2)?,
+                    },
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(appointments)
+    }
+
+    /// Record the breach of the appointment of `user_id`, the slot of
+    /// the appointment is given back.
+    pub fn breach(
+        &self,
+        user_id: &str,
+        locator: &Locator,
+        commitment_txid: &str,
+        justice_txid: &str,
+        height: u32,
+    ) -> error::Result<()> {
+        let mut conn = self.conn.lock_or_recover();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO breaches (locator, user_id, commitment_txid, justice_txid, height)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                locator.as_slice(),
+                user_id,
+                commitment_txid,
+                justice_txid,
+                height
+            ],
+        )?;
+        let deleted = tx.execute(
+            "DELETE FROM appointments WHERE locator = ?1 AND user_id = ?2",
+            params![locator.as_slice(), user_id],
+        )?;
+        if deleted > 0 {
+            tx.execute(
+                "UPDATE users SET available_slots = available_slots + 1 WHERE user_id = ?1",
+                params![user_id],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Delete the appointments of the subscriptions expired at `height`.
+    pub fn expire(&self, height: u32) -> error::Result<usize> {
+        let conn = self.conn.lock_or_recover();
+        let deleted = conn.execute(
+            "DELETE FROM appointments WHERE user_id IN
+                (SELECT user_id FROM users WHERE subscription_expiry <= ?1)",
+            params![height],
+        )?;
+        Ok(deleted)
+    }
+
+    /// Delete the client `user_id` with its appointments, returning
+    /// false when the client is unknown.
+    pub fn del_client(&self, user_id: &str) -> error::Result<bool> {
+        let mut conn = self.conn.lock_or_recover();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "DELETE FROM appointments WHERE user_id = ?1",
+            params![user_id],
+        )?;
+        let deleted = tx.execute("DELETE FROM users WHERE user_id = ?1", params![user_id])?;
+        tx.commit()?;
+        Ok(deleted > 0)
+    }
+
+    pub fn clients(&self) -> error::Result<Vec<response::TowerClient>> {
+        let conn = self.conn.lock_or_recover();
+        let mut stmt = conn.prepare(
+            "SELECT users.user_id, available_slots, subscription_expiry, COUNT(locator)
+             FROM users LEFT JOIN appointments ON users.user_id = appointments.user_id
+             GROUP BY users.user_id ORDER BY users.user_id",
+        )?;
+        let clients = stmt
+            .query_map([], |row| {
+                Ok(response::TowerClient {
+                    user_id: row.get(0)?,
+                    available_slots: row.get(1)?,
+                    subscription_expiry: row.get(2)?,
+                    appointments: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(clients)
+    }
+
+    /// The number of appointments stored for every client.
+    pub fn count_appointments(&self) -> error::Result<u64> {
+        let conn = self.conn.lock_or_recover();
+        let count = conn.query_row("SELECT COUNT(*) FROM appointments", [], |row| row.get(0))?;
+        Ok(count)
+    }
+
+    pub fn breaches(&self) -> error::Result<Vec<response::TowerBreach>> {
+        let conn = self.conn.lock_or_recover();
+        let mut stmt = conn.prepare(
+            "SELECT locator, user_id, commitment_txid, justice_txid, height
+             FROM breaches ORDER BY height, rowid",
+        )?;
+        let breaches = stmt
+            .query_map([], |row| {
+                Ok(response::TowerBreach {
+                    locator: row.get::<_, Vec<u8>>(0)?.to_hex(),
+                    user_id: row.get(1)?,
+                    commitment_txid: row.get(2)?,
+                    justice_txid: row.get(3)?,
+                    height: row.get(4)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(breaches)
+    }
+
+    /// The last block looked for the breaches.
+    pub fn scanned_height(&self) -> error::Result<Option<u32>> {
+        let conn = self.conn.lock_or_recover();
+        let height = conn
+            .query_row("SELECT scanned_height FROM chain WHERE id = 0", [], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(height)
+    }
+
+    pub fn set_scanned_height(&self, height: u32) -> error::Result<()> {
+        let conn = self.conn.lock_or_recover();
+        conn.execute(
+            "INSERT OR REPLACE INTO chain (id, scanned_height) VALUES (0, ?1)",
+            params![height],
+        )?;
+        Ok(())
+    }
+}
+
+fn user_appointments(conn: &Connection, user_id: &str) -> rusqlite::Result<u64> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM appointments WHERE user_id = ?1",
+        params![user_id],
+        |row| row.get(0),
+    )
+}
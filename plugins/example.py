@@ -0,0 +1,93 @@
+#!/usr/bin/env python3
+"""An example plugin of lampo, used by the integration tests.
+
+It adds the methods `hello`, `veto` and `crash`, counts the payments
+received and rejects the payments of the invoices while the veto is on.
+"""
+import json
+import sys
+
+state = {"veto": False, "payments_received": 0}
+
+
+def send(message):
+    sys.stdout.write(json.dumps(message) + "\n")
+    sys.stdout.flush()
+
+
+def log(message):
+    send({"jsonrpc": "2.0", "method": "log", "params": {"message": message}})
+
+
+def getmanifest(params):
+    return {
+        "rpcmethods": [
+            {"name": "hello", "description": "Greet `name`, with the payments received"},
+            {"name": "veto", "description": "Reject the invoice payments while `veto` is true"},
+            {"name": "crash", "description": "Exit, as a plugin that crashes"},
+        ],
+        "subscriptions": ["payment_received"],
+        "hooks": [{"name": "invoice_payment"}],
+    }
+
+
+def init(params):
+    log("example plugin running on {}".format(params["configuration"]["network"]))
+    return {}
+
+
+def hello(params):
+    return {
+        "greeting": "hello {}".format(params.get("name", "world")),
+        "payments_received": state["payments_received"],
+    }
+
+
+def veto(params):
+    state["veto"] = bool(params.get("veto", True))
+    return {"veto": state["veto"]}
+
+
+def crash(params):
+    sys.exit(1)
+
+
+def invoice_payment(params):
+    if state["veto"]:
+        return {"result": "reject", "error_message": "vetoed by the example plugin"}
+    return {"result": "continue"}
+
+
+def payment_received(params):
+    state["payments_received"] += 1
+
+
+METHODS = {
+    "getmanifest": getmanifest,
+    "init": init,
+    "hello": hello,
+    "veto": veto,
+    "crash": crash,
+    "invoice_payment": invoice_payment,
+}
+
+NOTIFICATIONS = {
+    "payment_received": payment_received,
+}
+
+
+for line in sys.stdin:
+    if not line.strip():
+        continue
+    request = json.loads(line)
+    method = request["method"]
+    params = request.get("params") or {}
+    if "id" not in request:
+        if method in NOTIFICATIONS:
+            NOTIFICATIONS[method](params)
+        continue
+    if method not in METHODS:
+        error = {"code": -32601, "message": "method `{}` not found".format(method)}
+        send({"jsonrpc": "2.0", "id": request["id"], "error": error})
+        continue
+    send({"jsonrpc": "2.0", "id": request["id"], "result": METHODS[method](params)})
@@ -0,0 +1,55 @@
+//! Integration tests of the compact block filters backend, they need
+//! a `bitcoind` that serves the filters and the `bip157` feature.
+use std::str::FromStr;
+use std::sync::Arc;
+
+use lampo_common::error;
+use lampo_common::json;
+use lampo_common::model::response;
+
+use lampo_testing::bip157::{FilterBtcNode, LampoBip157Testing};
+use lampo_testing::prelude::bitcoincore_rpc;
+use lampo_testing::prelude::bitcoincore_rpc::RpcApi;
+use lampo_testing::prelude::lampod::chain::WalletManager;
+use lampo_testing::wait;
+
+use crate::init;
+
+#[test]
+pub fn bip157_follows_the_chain_and_funds_the_wallet() -> error::Result<()> {
+    init();
+    let btc = Arc::new(FilterBtcNode::tmp()?);
+    let node = LampoBip157Testing::new(btc.clone())?;
+    let address: response::NewAddress = node.lampod().call("newaddr", json::json!({}))?;
+    let address = bitcoincore_rpc::bitcoin::Address::from_str(&address.address)?.assume_checked();
+    // the coinbase of the first block is mature after 100 blocks
+    let _ = btc.rpc().generate_to_address(101, &address)?;
+    let best_height = btc.rpc().get_block_count()? as u32;
+
+    let chain_info = || -> response::ChainInfo {
+        node.lampod().call("getchaininfo", json::json!({})).unwrap()
+    };
+    wait!(|| {
+        if chain_info().best_block_height == Some(best_height) {
+            return Ok(());
+        }
+        Err(())
+    });
+    let info = chain_info();
+    assert_eq!(info.backend, "bip157");
+    assert_eq!(
+        info.best_block_hash,
+        Some(btc.rpc().get_best_block_hash()?.to_string())
+    );
+
+    // the wallet sees the coinbase outputs in the blocks matched
+    wait!(|| {
+        let funds: response::Utxos = node.lampod().call("funds", json::json!({})).unwrap();
+        if !funds.transactions.is_empty() {
+            return Ok(());
+        }
+        Err(())
+    });
+    assert!(node.wallet.synced_height()?.is_some());
+    Ok(())
+}
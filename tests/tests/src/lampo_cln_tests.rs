@@ -12,6 +12,7 @@ use lampo_common::model::response;
 use lampo_common::model::response::InvoiceInfo;
 use lampo_common::model::response::Offer;
 use lampo_common::model::Connect;
+use lampo_common::model::Msat;
 use lampo_common::secp256k1::PublicKey;
 use lampo_testing::prelude::bitcoincore_rpc::RpcApi;
 use lampo_testing::prelude::*;
@@ -867,7 +868,8 @@ fn be_able_to_kesend_payments() {
         "keysend",
         request::KeySend {
             destination: PublicKey::from_str(info_cln.id.as_str()).unwrap(),
-            amount_msat: 100_00_000,
+            amount_msat: Msat::from_msat(100_00_000),
+            max_total_cltv_expiry_delta: None,
         },
     );
     assert!(result.is_ok(), "{:?}", result);
@@ -975,6 +977,8 @@ fn test_closing_two_channels_without_channelid_fails() {
         request::CloseChannel {
             node_id: info_cln.id.to_string(),
             channel_id: None,
+            destination: None,
+            feerate_sat_per_1000_weight: None,
         },
     );
 
@@ -1070,6 +1074,8 @@ fn test_lampo_to_cln_close_channel_with_channel_id_success() {
         request::CloseChannel {
             node_id: info_cln.id.to_string(),
             channel_id: Some(channels.channels.first().unwrap().channel_id.to_string()),
+            destination: None,
+            feerate_sat_per_1000_weight: None,
         },
     );
     assert!(result.is_ok(), "{:?}", result);
@@ -1172,6 +1178,8 @@ fn test_lampo_to_cln_close_channel_without_channel_id_success() {
         request::CloseChannel {
             node_id: info_cln.id.to_string(),
             channel_id: None,
+            destination: None,
+            feerate_sat_per_1000_weight: None,
         },
     );
     assert!(result.is_ok(), "{:?}", result);
@@ -1219,6 +1227,8 @@ fn test_close_channel_without_opening_a_channel_fails() {
         request::CloseChannel {
             node_id: info_cln.id.to_string(),
             channel_id: None,
+            destination: None,
+            feerate_sat_per_1000_weight: None,
         },
     );
     assert!(result.is_err(), "{:?}", result);
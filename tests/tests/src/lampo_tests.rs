@@ -1,22 +1,41 @@
 //! Integration tests between lampo nodes.
 //!
 //! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use lampo_common::backend::BroadcastError;
+use lampo_common::bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use lampo_common::bitcoin::hashes::sha256::Hash as Sha256;
+use lampo_common::bitcoin::hashes::{Hash, HashEngine};
+use lampo_common::bitcoin::{Address, Network, OutPoint, Txid};
+use lampo_common::conf::{SocketAddress, WebhookConf};
 use lampo_common::error;
+use lampo_common::error::LampoError;
 use lampo_common::event::ln::LightningEvent;
 use lampo_common::event::onchain::OnChainEvent;
 use lampo_common::event::Event;
 use lampo_common::handler::Handler;
 use lampo_common::json;
-use lampo_common::model::{request, response};
+use lampo_common::model::{request, response, Msat, Sat};
+use lampo_common::types::NodeId;
+use lampo_common::wallet::WalletError;
 
 use lampo_testing::prelude::*;
+use lampo_testing::prelude::lampod::ln::LampoGraphPersister;
+use lampo_testing::prelude::lampod::persistence::LampoPersistence;
+use lampo_testing::prelude::lampod::utils::logger::LampoLogger;
 use lampo_testing::wait;
-use lampo_testing::LampoTesting;
+use lampo_testing::{open_channel, LampoTesting};
 
 use crate::init;
+use crate::utils::{
+    client_call, fund_wallet, http_call, rgs_graph_fixture, rgs_snapshot_fixture, rpc_error,
+    scrape_metrics, MockHttp, MockLnurl, MockSocks5, MockTower, MockVss, MockWebhook, WsEvents,
+};
 
 #[test]
 pub fn init_connection_test_between_lampo() -> error::Result<()> {
@@ -41,373 +60,5129 @@ pub fn init_connection_test_between_lampo() -> error::Result<()> {
 }
 
 #[test]
-pub fn fund_a_simple_channel_from() -> error::Result<()> {
+pub fn connect_peer_from_offchain_manager_lampo() -> error::Result<()> {
     init();
     let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
     let btc = Arc::new(btc);
-    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
-    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
-    let response: response::Connect = node2
-        .lampod()
-        .call(
-            "connect",
-            request::Connect {
-                node_id: node1.info.node_id.clone(),
-                addr: "127.0.0.1".to_owned(),
-                port: node1.port,
-            },
-        )
-        .unwrap();
-    log::debug!("node 1 -> connected with node 2 {:?}", response);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
 
-    let events = node1.lampod().events();
-    let _ = node1.fund_wallet(101).unwrap();
-    wait!(|| {
-        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
-            events.recv_timeout(Duration::from_millis(100))
-        else {
-            return Err(());
-        };
-        if height.to_consensus_u32() == 101 {
-            return Ok(());
-        }
-        Err(())
-    });
+    let offchain = node2.daemon().offchain_manager();
+    let node_id = node1.daemon().offchain_manager().node_id();
+    // the listener is dropped, so nobody listens on its port
+    let closed = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let err = offchain.connect_peer(node_id, closed).unwrap_err();
+    assert_eq!(LampoError::find(&err).map(|err| err.code()), Some(499));
 
-    let response: json::Value = node1
-        .lampod()
-        .call(
-            "fundchannel",
-            request::OpenChannel {
-                node_id: node2.info.node_id.clone(),
-                amount: 100000,
-                public: true,
-                port: None,
-                addr: None,
-            },
-        )
-        .unwrap();
-    assert!(response.get("tx").is_some());
+    let addr: SocketAddr = format!("127.0.0.1:{}", node1.port).parse()?;
+    offchain.connect_peer(node_id, addr)?;
+    // connecting again to a connected peer does nothing
+    offchain.connect_peer(node_id, addr)?;
 
-    let events = node2.lampod().events();
-    wait!(|| {
-        while let Ok(event) = events.recv_timeout(Duration::from_millis(10)) {
-            node2.fund_wallet(1).unwrap();
-            if let Event::Lightning(LightningEvent::ChannelReady {
-                counterparty_node_id,
-                ..
-            }) = event
-            {
-                if counterparty_node_id.to_string() == node1.info.node_id {
-                    return Err(());
-                }
-                return Ok(());
-            };
-            // check if lampo see the channel
-            let channels: response::Channels =
-                node2.lampod().call("channels", json::json!({})).unwrap();
-            if !channels.channels.is_empty() {
-                return Ok(());
-            }
+    let peers: response::Peers = node1.lampod().call("listpeers", json::json!({}))?;
+    assert_eq!(peers.peers.len(), 1);
+    assert_eq!(peers.peers[0].node_id, node2.info.node_id);
+    assert!(peers.peers[0].inbound);
+    Ok(())
+}
 
-            if channels.channels.first().unwrap().ready {
-                return Ok(());
-            }
-        }
-        node2.fund_wallet(6).unwrap();
-        Err(())
-    });
+#[test]
+pub fn offchain_manager_node_id_matches_getinfo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+    let node_id = node.daemon().offchain_manager().node_id();
+    assert_eq!(node_id.to_string(), node.info.node_id);
     Ok(())
 }
 
 #[test]
-pub fn pay_invoice_simple_case_lampo() -> error::Result<()> {
+pub fn getinfo_reports_the_balances_and_sync_state_lampo() -> error::Result<()> {
     init();
     let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
     let btc = Arc::new(btc);
-    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
-    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node = LampoTesting::new(btc.clone())?;
+    let _ = node.fund_wallet(101)?;
 
-    let events = node1.lampod().events();
-    let _ = node1.fund_wallet(101)?;
     wait!(|| {
-        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
-            events.recv_timeout(Duration::from_millis(100))
-        else {
-            return Err(());
-        };
-        if height.to_consensus_u32() == 101 {
+        let info: response::GetInfo = node.lampod().call("getinfo", json::json!({})).unwrap();
+        let funded = info
+            .onchain
+            .as_ref()
+            .is_some_and(|onchain| onchain.confirmed_sat > Sat::ZERO);
+        if funded && info.synced_to_chain {
             return Ok(());
         }
         Err(())
     });
+    let info: response::GetInfo = node.lampod().call("getinfo", json::json!({}))?;
+    assert_eq!(info.channels_state, response::ChannelsState::default());
+    assert_eq!(info.outbound_capacity_msat, 0);
+    assert_eq!(info.inbound_capacity_msat, 0);
+    assert!(!info.version.is_empty());
+    Ok(())
+}
 
-    let response: json::Value = node1
-        .lampod()
-        .call(
-            "fundchannel",
-            request::OpenChannel {
-                node_id: node2.info.node_id.clone(),
-                amount: 1_000_000,
-                public: true,
-                addr: Some("127.0.0.1".to_owned()),
-                port: Some(node2.port),
-            },
-        )
-        .unwrap();
-    assert!(response.get("tx").is_some());
+#[test]
+pub fn new_block_syncs_the_wallet_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    // the interval is never reached inside the test, so only
+    // the new block can trigger the sync
+    let node = LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.event_driven_sync = true;
+        conf.wallet_sync_interval_secs = 3600;
+    })?;
+    let wallet_sync = node.daemon().wallet_sync();
+    wait!(|| wallet_sync.synced_since().map(|_| ()).ok_or(()));
 
+    let events = node.lampod().events();
+    let _ = node.fund_wallet(1)?;
+    let height = btc.rpc().get_block_count()? as u32;
     wait!(|| {
-        while let Ok(event) = events.recv_timeout(Duration::from_millis(10)) {
-            node2.fund_wallet(6).unwrap();
-            if let Event::Lightning(LightningEvent::ChannelReady {
-                counterparty_node_id,
-                ..
-            }) = event
-            {
-                if counterparty_node_id.to_string() == node1.info.node_id {
-                    return Err(());
+        while let Ok(event) = events.recv_timeout(Duration::from_millis(100)) {
+            if let Event::OnChain(OnChainEvent::WalletSynced(synced)) = event {
+                if synced.to_consensus_u32() >= height {
+                    return Ok(());
                 }
-                return Ok(());
-            };
-            // check if lampo see the channel
-            let channels: response::Channels =
-                node2.lampod().call("channels", json::json!({})).unwrap();
-            if channels.channels.is_empty() {
-                return Err(());
-            }
-
-            if !channels.channels.first().unwrap().ready {
-                return Err(());
-            }
-
-            let channels: response::Channels =
-                node1.lampod().call("channels", json::json!({})).unwrap();
-
-            if channels.channels.is_empty() {
-                return Err(());
-            }
-
-            if channels.channels.first().unwrap().ready {
-                return Ok(());
             }
         }
-        node2.fund_wallet(6).unwrap();
         Err(())
     });
-
-    let invoice: response::Invoice = node2.lampod().call(
-        "invoice",
-        request::GenerateInvoice {
-            description: "making sure that we can work betwen lampo version".to_owned(),
-            amount_msat: Some(100_000_000),
-            expiring_in: None,
-        },
-    )?;
-
-    log::info!(target: &node2.info.node_id, "invoice generated `{:?}`", invoice);
-
-    let pay: response::PayResult = node1.lampod().call(
-        "pay",
-        request::Pay {
-            invoice_str: invoice.bolt11,
-            amount: None,
-        },
-    )?;
-    log::info!(target: &node1.info.node_id, "payment made `{:?}`", pay);
     Ok(())
 }
 
 #[test]
-pub fn pay_offer_simple_case_lampo() -> error::Result<()> {
+pub fn connect_over_multiple_bindings_lampo() -> error::Result<()> {
     init();
     let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
     let btc = Arc::new(btc);
-    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    // the port 0 let the os choose a free port for each binding
+    let node1 = Arc::new(LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.bind_addr = vec![
+            "127.0.0.1:0".parse().unwrap(),
+            "127.0.0.1:0".parse().unwrap(),
+        ];
+    })?);
+    assert_eq!(node1.info.binding.len(), 2);
+    assert_ne!(node1.info.binding[0].port, node1.info.binding[1].port);
+
     let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node3 = Arc::new(LampoTesting::new(btc.clone())?);
+    let connections = [node2, node3]
+        .into_iter()
+        .zip(node1.info.binding.iter())
+        .map(|(node, binding)| {
+            let node_id = format!("{}@{}:{}", node1.info.node_id, binding.address, binding.port);
+            std::thread::spawn(move || -> error::Result<response::Connect> {
+                node.lampod().call("connect", json::json!({ "node_id": node_id }))
+            })
+        })
+        .collect::<Vec<_>>();
+    for connection in connections {
+        // SAFETY: the thread does not panic.
+        let response = connection.join().unwrap()?;
+        assert_eq!(response.node_id, node1.info.node_id);
+    }
 
-    let events = node1.lampod().events();
-    let _ = node1.fund_wallet(101)?;
     wait!(|| {
-        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
-            events.recv_timeout(Duration::from_millis(100))
-        else {
+        let peers: error::Result<response::Peers> =
+            node1.lampod().call("listpeers", json::json!({}));
+        let Ok(peers) = peers else {
             return Err(());
         };
-        if height.to_consensus_u32() == 101 {
+        if peers.peers.len() == 2 && peers.peers.iter().all(|peer| peer.inbound) {
             return Ok(());
         }
         Err(())
     });
+    Ok(())
+}
 
-    let response: json::Value = node1
-        .lampod()
-        .call(
-            "fundchannel",
-            request::OpenChannel {
-                node_id: node2.info.node_id.clone(),
-                amount: 1_000_000,
-                public: true,
-                addr: Some("127.0.0.1".to_owned()),
-                port: Some(node2.port),
-            },
-        )
-        .unwrap();
-    assert!(response.get("tx").is_some());
-
-    wait!(|| {
-        while let Ok(event) = events.recv_timeout(Duration::from_millis(10)) {
-            node2.fund_wallet(6).unwrap();
-            if let Event::Lightning(LightningEvent::ChannelReady {
-                counterparty_node_id,
-                ..
-            }) = event
-            {
-                if counterparty_node_id.to_string() == node1.info.node_id {
-                    return Err(());
-                }
-                return Ok(());
-            };
-            // check if lampo see the channel
-            let channels: response::Channels =
-                node2.lampod().call("channels", json::json!({})).unwrap();
-            if channels.channels.is_empty() {
-                return Err(());
-            }
-
-            if !channels.channels.first().unwrap().ready {
-                return Err(());
-            }
+#[test]
+pub fn connect_through_tor_proxy_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let binding = node1.info.binding.first().expect("node 1 should listen");
+    let node1_addr = SocketAddr::new(binding.address.parse()?, binding.port as u16);
+    let proxy = MockSocks5::start(node1_addr)?;
 
-            let channels: response::Channels =
-                node1.lampod().call("channels", json::json!({})).unwrap();
+    let node2 = Arc::new(LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.proxy = Some(proxy.addr);
+    })?);
+    let onion = "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion";
+    let response: response::Connect = node2.lampod().call(
+        "connect",
+        json::json!({
+            "node_id": format!("{}@{onion}:9735", node1.info.node_id),
+        }),
+    )?;
+    assert_eq!(response.node_id, node1.info.node_id);
+    // the onion address is sent to the proxy as a name, so it is
+    // never resolved by lampo
+    assert_eq!(proxy.targets(), vec![(3, onion.to_owned(), 9735)]);
 
-            if channels.channels.is_empty() {
-                return Err(());
-            }
+    // with `tor-only` also the clearnet addresses go through the proxy
+    let node3 = Arc::new(LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.proxy = Some(proxy.addr);
+        conf.tor_only = true;
+    })?);
+    let response: response::Connect = node3.lampod().call(
+        "connect",
+        json::json!({
+            "node_id": format!("{}@{node1_addr}", node1.info.node_id),
+        }),
+    )?;
+    assert_eq!(response.node_id, node1.info.node_id);
+    assert_eq!(proxy.targets().len(), 2);
+    assert_eq!(
+        proxy.targets()[1],
+        (3, node1_addr.ip().to_string(), node1_addr.port())
+    );
+    Ok(())
+}
 
-            if channels.channels.first().unwrap().ready {
-                return Ok(());
-            }
-        }
-        node2.fund_wallet(6).unwrap();
-        Err(())
-    });
+#[test]
+pub fn ping_peer_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        json::json!({
+            "node_id": format!("{}@127.0.0.1:{}", node2.info.node_id, node2.port),
+        }),
+    )?;
 
-    let offer: response::Offer = node2.lampod().call(
-        "offer",
-        request::GenerateOffer {
-            description: Some("making sure that we can work betwen lampo version".to_owned()),
-            amount_msat: Some(100_000_000),
-        },
+    let ping: response::Ping = node1.lampod().call(
+        "ping",
+        json::json!({
+            "node_id": node2.info.node_id,
+            "count": 5,
+        }),
     )?;
+    assert_eq!(ping.sent, 5);
+    assert_eq!(ping.received, 5);
+    assert_eq!(ping.loss_percent, 0.0);
+    // SAFETY: all the pongs are received.
+    assert!(ping.max_ms.unwrap() < 1000.0);
+    assert!(ping.min_ms.unwrap() <= ping.avg_ms.unwrap());
 
-    log::info!(target: &node2.info.node_id, "offer generated `{:?}`", offer);
+    let peers: response::Peers = node1.lampod().call("listpeers", json::json!({}))?;
+    let peer = peers
+        .peers
+        .iter()
+        .find(|peer| peer.node_id == node2.info.node_id)
+        .ok_or(error::anyhow!("peer not found"))?;
+    assert!(peer.last_pong.is_some());
 
-    let pay: response::PayResult = node1.lampod().call(
-        "pay",
-        request::Pay {
-            invoice_str: offer.bolt12,
-            amount: None,
-        },
-    )?;
-    log::info!(target: &node1.info.node_id, "payment made `{:?}`", pay);
+    let ping: error::Result<response::Ping> = node1.lampod().call(
+        "ping",
+        json::json!({
+            "node_id": node2.info.node_id,
+            "count": 0,
+        }),
+    );
+    assert!(ping.is_err());
     Ok(())
 }
 
 #[test]
-pub fn pay_offer_minimal_offer() -> error::Result<()> {
+pub fn deny_inbound_peer_lampo() -> error::Result<()> {
     init();
     let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
     let btc = Arc::new(btc);
     let node1 = Arc::new(LampoTesting::new(btc.clone())?);
     let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let lists: response::PeerLists = node2.lampod().call(
+        "setpeerlist",
+        json::json!({
+            "list": "deny",
+            "node_id": node1.info.node_id,
+        }),
+    )?;
+    assert_eq!(lists.denylist, vec![node1.info.node_id.clone()]);
 
-    let events = node1.lampod().events();
-    let _ = node1.fund_wallet(101)?;
+    // the handshake can complete before the peer is disconnected
+    let _: error::Result<response::Connect> = node1.lampod().call(
+        "connect",
+        json::json!({
+            "node_id": format!("{}@127.0.0.1:{}", node2.info.node_id, node2.port),
+        }),
+    );
     wait!(|| {
-        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
-            events.recv_timeout(Duration::from_millis(100))
-        else {
+        let peers: response::Peers = node2.lampod().call("listpeers", json::json!({})).unwrap();
+        if peers
+            .peers
+            .iter()
+            .any(|peer| peer.node_id == node1.info.node_id)
+        {
             return Err(());
-        };
-        if height.to_consensus_u32() == 101 {
+        }
+        Ok(())
+    });
+
+    // node2 does not connect to a denied peer
+    let connect: error::Result<response::Connect> = node2.lampod().call(
+        "connect",
+        json::json!({
+            "node_id": format!("{}@127.0.0.1:{}", node1.info.node_id, node1.port),
+        }),
+    );
+    assert!(connect.is_err());
+    Ok(())
+}
+
+#[test]
+pub fn max_inbound_peers_evict_oldest_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.max_inbound_peers = Some(1);
+    })?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node3 = Arc::new(LampoTesting::new(btc.clone())?);
+    let _: response::Connect = node2.lampod().call(
+        "connect",
+        json::json!({
+            "node_id": format!("{}@127.0.0.1:{}", node1.info.node_id, node1.port),
+        }),
+    )?;
+    wait!(|| {
+        let peers: response::Peers = node1.lampod().call("listpeers", json::json!({})).unwrap();
+        if peers.peers.len() == 1 {
             return Ok(());
         }
         Err(())
     });
 
-    let response: json::Value = node1
+    let _: response::Connect = node3.lampod().call(
+        "connect",
+        json::json!({
+            "node_id": format!("{}@127.0.0.1:{}", node1.info.node_id, node1.port),
+        }),
+    )?;
+    wait!(|| {
+        let peers: response::Peers = node1.lampod().call("listpeers", json::json!({})).unwrap();
+        let node_ids = peers
+            .peers
+            .iter()
+            .map(|peer| peer.node_id.clone())
+            .collect::<Vec<_>>();
+        if node_ids == vec![node3.info.node_id.clone()] {
+            return Ok(());
+        }
+        Err(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn onion_message_round_trip_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        json::json!({
+            "node_id": format!("{}@127.0.0.1:{}", node2.info.node_id, node2.port),
+        }),
+    )?;
+
+    let _: response::SendOnionMessage = node1.lampod().call(
+        "sendonionmessage",
+        json::json!({
+            "destination": node2.info.node_id,
+            "tlv_type": 65537,
+            "payload": "cafe",
+            "reply_path": true,
+        }),
+    )?;
+    let message: response::OnionMessage = node2
         .lampod()
-        .call(
-            "fundchannel",
-            request::OpenChannel {
-                node_id: node2.info.node_id.clone(),
-                amount: 1_000_000,
-                public: true,
-                addr: Some("127.0.0.1".to_owned()),
-                port: Some(node2.port),
-            },
-        )
-        .unwrap();
-    assert!(response.get("tx").is_some());
+        .call("waitonionmessage", json::json!({ "timeout_secs": 10 }))?;
+    assert_eq!(message.tlv_type, 65537);
+    assert_eq!(message.payload, "cafe");
+    assert!(message.reply_path);
+
+    // answer through the blinded reply path of node 1
+    let _: response::SendOnionMessage = node2.lampod().call(
+        "sendonionmessage",
+        json::json!({
+            "reply_to": message.id,
+            "tlv_type": 65539,
+            "payload": "beef",
+        }),
+    )?;
+    let reply: response::OnionMessage = node1
+        .lampod()
+        .call("waitonionmessage", json::json!({ "timeout_secs": 10 }))?;
+    assert_eq!(reply.tlv_type, 65539);
+    assert_eq!(reply.payload, "beef");
+    assert!(!reply.reply_path);
+
+    // the reply path can be used only once
+    let result: error::Result<response::SendOnionMessage> = node2.lampod().call(
+        "sendonionmessage",
+        json::json!({
+            "reply_to": message.id,
+            "tlv_type": 65539,
+            "payload": "beef",
+        }),
+    );
+    assert!(result.is_err());
+    Ok(())
+}
+
+#[test]
+pub fn sign_and_check_message_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+
+    let message = "prove that I control the node".to_owned();
+    let signature: response::SignMessage = node1.lampod().call(
+        "signmessage",
+        request::SignMessage {
+            message: message.clone(),
+        },
+    )?;
+    assert_eq!(signature.pubkey, node1.info.node_id);
+
+    let checked: response::CheckMessage = node2.lampod().call(
+        "checkmessage",
+        request::CheckMessage {
+            message: message.clone(),
+            zbase: signature.zbase.clone(),
+            pubkey: Some(node1.info.node_id.clone()),
+        },
+    )?;
+    assert!(checked.verified);
+    assert_eq!(checked.pubkey, node1.info.node_id);
+
+    // node1 is not inside the network graph of node2
+    let checked: response::CheckMessage = node2.lampod().call(
+        "checkmessage",
+        request::CheckMessage {
+            message: message.clone(),
+            zbase: signature.zbase.clone(),
+            pubkey: None,
+        },
+    )?;
+    assert!(!checked.verified);
+    assert_eq!(checked.alias, None);
+
+    let checked: response::CheckMessage = node2.lampod().call(
+        "checkmessage",
+        request::CheckMessage {
+            message: "another message".to_owned(),
+            zbase: signature.zbase,
+            pubkey: Some(node1.info.node_id.clone()),
+        },
+    )?;
+    assert!(!checked.verified);
+    Ok(())
+}
+
+#[test]
+pub fn connect_and_disconnect_peers_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.auto_reconnect = false;
+    })?);
+    let node2 = Arc::new(LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.auto_reconnect = false;
+    })?);
+
+    // connect by DNS name, twice
+    for _ in 0..2 {
+        let response: response::Connect = node2.lampod().call(
+            "connect",
+            json::json!({
+                "node_id": format!("{}@localhost:{}", node1.info.node_id, node1.port),
+            }),
+        )?;
+        assert_eq!(response.node_id, node1.info.node_id);
+    }
+
+    let peers: response::Peers = node2.lampod().call("listpeers", json::json!({}))?;
+    assert_eq!(peers.peers.len(), 1);
+    let peer = peers.peers.first().unwrap();
+    assert_eq!(peer.node_id, node1.info.node_id);
+    assert!(!peer.inbound);
+    assert!(!peer.features.is_empty());
+
+    let peers: response::Peers = node1.lampod().call("listpeers", json::json!({}))?;
+    assert_eq!(peers.peers.len(), 1);
+    assert!(peers.peers.first().unwrap().inbound);
+
+    open_channel(&node1, &node2, 100_000)?;
+
+    let peers: response::Peers = node1.lampod().call("listpeers", json::json!({}))?;
+    assert_eq!(peers.peers.first().unwrap().channels, 1);
+
+    // no htlcs in flight, so the disconnect is allowed without force
+    let _: json::Value = node1.lampod().call(
+        "disconnect",
+        request::Disconnect {
+            node_id: node2.info.node_id.clone(),
+            force: false,
+        },
+    )?;
+    // the channel peer is still listed, but offline
+    let peers: response::Peers = node1.lampod().call("listpeers", json::json!({}))?;
+    assert_eq!(peers.peers.len(), 1);
+    assert!(!peers.peers.first().unwrap().connected);
+    let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+    assert_eq!(channels.channels.len(), 1);
+    Ok(())
+}
+
+#[test]
+pub fn reconnect_channel_peer_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    open_channel(&node1, &node2, 100_000)?;
+
+    // drop the connection, lampo should dial the peer again
+    let _: json::Value = node1.lampod().call(
+        "disconnect",
+        request::Disconnect {
+            node_id: node2.info.node_id.clone(),
+            force: true,
+        },
+    )?;
 
     wait!(|| {
-        while let Ok(event) = events.recv_timeout(Duration::from_millis(10)) {
-            node2.fund_wallet(6).unwrap();
-            if let Event::Lightning(LightningEvent::ChannelReady {
-                counterparty_node_id,
-                ..
-            }) = event
-            {
-                if counterparty_node_id.to_string() == node1.info.node_id {
-                    return Err(());
-                }
+        let channels: response::Channels = node1
+            .lampod()
+            .call("channels", json::json!({}))
+            .unwrap();
+        let peers: response::Peers = node1
+            .lampod()
+            .call("listpeers", json::json!({}))
+            .unwrap();
+        let connected = peers.peers.iter().any(|peer| peer.connected);
+        if connected && channels.channels.iter().all(|channel| channel.usable) {
+            return Ok(());
+        }
+        Err(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn queue_close_until_peer_is_back_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = LampoTesting::new(btc.clone())?;
+
+    open_channel(&node1, &node2, 100_000)?;
+
+    let node2_id = node2.info.node_id.clone();
+    // the close is queued while the peer is offline
+    let (_node2, _) = node2.restart_after(|| {
+        wait!(|| {
+            let channels: response::Channels = node1
+                .lampod()
+                .call("channels", json::json!({}))
+                .unwrap();
+            if channels.channels.iter().all(|channel| !channel.usable) {
                 return Ok(());
-            };
-            // check if lampo see the channel
-            let channels: response::Channels =
-                node2.lampod().call("channels", json::json!({})).unwrap();
-            if channels.channels.is_empty() {
-                return Err(());
             }
+            Err(())
+        });
+        let close: json::Value = node1.lampod().call(
+            "close",
+            request::CloseChannel {
+                node_id: node2_id.clone(),
+                channel_id: None,
+                destination: None,
+                feerate_sat_per_1000_weight: None,
+            },
+        )?;
+        assert_eq!(close["state"], "pending_peer", "{close}");
+        let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+        let pending = channels.channels[0]
+            .pending_close
+            .as_ref()
+            .expect("the close is not queued");
+        assert_eq!(pending.peer_id, node2_id);
+        assert!(!pending.expired);
+        Ok(())
+    })?;
 
-            if !channels.channels.first().unwrap().ready {
-                return Err(());
+    // the close starts as soon as the peer reconnects
+    wait!(|| {
+        let channels: response::Channels = node1
+            .lampod()
+            .call("channels", json::json!({}))
+            .unwrap();
+        if channels.channels.is_empty() {
+            return Ok(());
+        }
+        Err(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn queued_close_expires_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.pending_close_deadline_secs = 1;
+    })?);
+    let node2 = LampoTesting::new(btc.clone())?;
+
+    open_channel(&node1, &node2, 100_000)?;
+
+    let node2_id = node2.info.node_id.clone();
+    node2.stop()?;
+    wait!(|| {
+        let channels: response::Channels = node1
+            .lampod()
+            .call("channels", json::json!({}))
+            .unwrap();
+        if channels.channels.iter().all(|channel| !channel.usable) {
+            return Ok(());
+        }
+        Err(())
+    });
+    let close: json::Value = node1.lampod().call(
+        "close",
+        request::CloseChannel {
+            node_id: node2_id,
+            channel_id: None,
+            destination: None,
+            feerate_sat_per_1000_weight: None,
+        },
+    )?;
+    assert_eq!(close["state"], "pending_peer", "{close}");
+
+    // the peer does not come back before the deadline
+    wait!(
+        || {
+            let channels: response::Channels = node1
+                .lampod()
+                .call("channels", json::json!({}))
+                .unwrap();
+            match &channels.channels[0].pending_close {
+                Some(pending) if pending.expired => Ok(()),
+                _ => Err(()),
             }
+        },
+        10
+    );
+    Ok(())
+}
 
-            let channels: response::Channels =
-                node1.lampod().call("channels", json::json!({})).unwrap();
+#[test]
+pub fn force_close_bumps_and_sweeps_lampo() -> error::Result<()> {
+    use lampo_testing::prelude::bitcoincore_rpc::RpcApi;
 
-            if channels.channels.is_empty() {
-                return Err(());
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.developer = true;
+        conf.anchor_channels = true;
+        conf.force_close_after_deadline = true;
+        conf.pending_close_deadline_secs = 1;
+    })?);
+    let node2 = LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.developer = true;
+        conf.anchor_channels = true;
+    })?;
+
+    open_channel(&node1, &node2, 1_000_000)?;
+
+    // node2 never claims, so the HTLC stays in the commitment
+    let _: response::DevIgnoreHtlcs = node2.lampod().call(
+        "dev-ignore-htlcs",
+        json::json!({ "node_id": node1.info.node_id }),
+    )?;
+    let invoice: response::Invoice = node2.lampod().call(
+        "invoice",
+        json::json!({ "amount_msat": 100_000_000, "description": "never claimed" }),
+    )?;
+    let payer = node1.clone();
+    let _pay = std::thread::spawn(move || -> error::Result<response::PayResult> {
+        payer.lampod().call(
+            "pay",
+            request::Pay {
+                invoice_str: invoice.bolt11,
+                amount: None,
+                custom_tlvs: Vec::new(),
+                timeout_secs: None,
+                max_total_cltv_expiry_delta: None,
+            },
+        )
+    });
+    wait!(|| {
+        let htlcs: response::Htlcs = node1
+            .lampod()
+            .call("listhtlcs", request::ListHtlcs { channel: None })
+            .unwrap();
+        if htlcs.htlcs.is_empty() {
+            return Err(());
+        }
+        Ok(())
+    });
+
+    let funds: response::ListFunds = node1.lampod().call("listfunds", json::json!({}))?;
+    let onchain_before = funds.total_onchain_sat.sat();
+    let _: response::FeeRates = node1.lampod().call(
+        "dev-set-feerate",
+        json::json!({ "target": "on_chain_sweep", "sat_per_kw": 10_000 }),
+    )?;
+
+    // the peer does not come back before the deadline, so the
+    // commitment goes on chain with the anchor bumped by node1
+    let node2_id = node2.info.node_id.clone();
+    node2.stop()?;
+    let close: json::Value = node1.lampod().call(
+        "close",
+        request::CloseChannel {
+            node_id: node2_id,
+            channel_id: None,
+            destination: None,
+            feerate_sat_per_1000_weight: None,
+        },
+    )?;
+    assert_eq!(close["state"], "pending_peer", "{close}");
+    wait!(
+        || {
+            let bumps: response::BumpPackages = node1
+                .lampod()
+                .call("pendingbumps", json::json!({}))
+                .unwrap();
+            let anchor = bumps.packages.iter().find(|bump| bump.kind == "anchor");
+            match anchor {
+                Some(anchor) if anchor.target_feerate_sat_per_1000_weight >= 10_000 => Ok(()),
+                _ => Err(()),
             }
+        },
+        10
+    );
 
-            if channels.channels.first().unwrap().ready {
+    // the CPFP child pays for the commitment at the sweep fee rate,
+    // 10_000 sat/kW are 40 sat/vB
+    wait!(|| {
+        let mempool = btc.rpc().get_raw_mempool().unwrap();
+        let child = mempool.iter().find_map(|txid| {
+            let entry = btc.rpc().get_mempool_entry(txid).ok()?;
+            (entry.ancestor_count == 2).then_some(entry)
+        });
+        match child {
+            Some(child) if child.fees.ancestor.to_sat() / child.ancestor_size >= 38 => Ok(()),
+            _ => Err(()),
+        }
+    });
+    let package = btc.rpc().get_raw_mempool()?;
+    let _ = node1.fund_wallet(1)?;
+    let mempool = btc.rpc().get_raw_mempool()?;
+    assert!(
+        package.iter().all(|txid| !mempool.contains(txid)),
+        "the package is not confirmed: {package:?}"
+    );
+
+    // after the `to_self_delay` the outputs are swept into the wallet
+    wait!(
+        || {
+            let _ = node1.fund_wallet(50).unwrap();
+            let funds: response::ListFunds = node1
+                .lampod()
+                .call("listfunds", json::json!({}))
+                .unwrap();
+            let bumps: response::BumpPackages = node1
+                .lampod()
+                .call("pendingbumps", json::json!({}))
+                .unwrap();
+            if bumps.packages.is_empty()
+                && funds.total_onchain_sat.sat() > onchain_before + 500_000
+            {
                 return Ok(());
             }
+            Err(())
+        },
+        10
+    );
+    Ok(())
+}
+
+#[test]
+pub fn node_announcement_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.alias = Some("lampo-announce".to_owned());
+        conf.rgb_color = [0xff, 0x00, 0x80];
+        conf.announce_addr = vec![SocketAddress::TcpIpV4 {
+            addr: [127, 0, 0, 1],
+            port: conf.port as u16,
+        }];
+    })?);
+
+    let info: response::GetInfo = node2.lampod().call("getinfo", json::json!({}))?;
+    assert_eq!(info.alias, "lampo-announce");
+    assert_eq!(info.color, "ff0080");
+    assert_eq!(info.address.len(), 1);
+    assert_eq!(info.address[0].address, "127.0.0.1");
+    assert_eq!(info.address[0].port, node2.port);
+    // there are no public channels yet
+    assert!(info.last_announcement.is_none());
+
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101)?;
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
         }
-        node2.fund_wallet(6).unwrap();
         Err(())
     });
 
-    let offer: response::Offer = node2.lampod().call(
-        "offer",
-        request::GenerateOffer {
-            description: None,
-            amount_msat: None,
+    let response: json::Value = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 100_000,
+            public: true,
+            addr: Some("127.0.0.1".to_owned()),
+            port: Some(node2.port),
         },
     )?;
+    assert!(response.get("tx").is_some());
 
-    log::info!(target: &node2.info.node_id, "offer generated `{:?}`", offer);
+    // the channel is announced after 6 confirmations, and then
+    // the node announcement of node2 reaches node1.
+    wait!(|| {
+        let nodes: response::GossipNodes = node1
+            .lampod()
+            .call(
+                "listnodes",
+                request::ListNodes {
+                    node_id: Some(node2.info.node_id.clone()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let Some(node) = nodes.nodes.first() else {
+            node2.fund_wallet(1).unwrap();
+            return Err(());
+        };
+        if node.alias.is_none() {
+            node2.fund_wallet(1).unwrap();
+            return Err(());
+        }
+        assert_eq!(node.alias.as_deref(), Some("lampo-announce"));
+        assert_eq!(node.color.as_deref(), Some("ff0080"));
+        assert_eq!(node.addresses, vec![format!("127.0.0.1:{}", node2.port)]);
+        Ok(())
+    });
 
-    let pay: response::PayResult = node1.lampod().call(
-        "pay",
-        request::Pay {
-            invoice_str: offer.bolt12,
-            amount: Some(100_000_000),
-        },
-    )?;
-    log::info!(target: &node1.info.node_id, "payment made `{:?}`", pay);
+    let info: response::GetInfo = node2.lampod().call("getinfo", json::json!({}))?;
+    assert!(info.last_announcement.is_some());
+    Ok(())
+}
+
+#[test]
+pub fn rapid_gossip_snapshot_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new_with_conf(btc.clone(), |conf| {
+        // nothing is listening here, the snapshot is applied by the test
+        conf.rgs_url = Some("http://127.0.0.1:1/snapshot".to_owned());
+    })?;
+
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+    let rapid_gossip = node
+        .daemon()
+        .rapid_gossip()
+        .expect("rapid gossip sync should be enabled");
+    assert_eq!(rapid_gossip.apply(&rgs_snapshot_fixture(timestamp))?, timestamp);
+
+    let graph = node.daemon().channel_manager().graph();
+    {
+        let graph = graph.read_only();
+        assert_eq!(graph.nodes().len(), 3);
+        assert_eq!(graph.channels().len(), 2);
+    }
+
+    let info: response::GetInfo = node.lampod().call("getinfo", json::json!({}))?;
+    assert_eq!(info.rgs_last_sync_timestamp, Some(timestamp));
+    Ok(())
+}
+
+#[test]
+pub fn list_network_graph_paginated_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new_with_conf(btc.clone(), |conf| {
+        // nothing is listening here, the snapshot is applied by the test
+        conf.rgs_url = Some("http://127.0.0.1:1/snapshot".to_owned());
+    })?;
+
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+    let rapid_gossip = node
+        .daemon()
+        .rapid_gossip()
+        .expect("rapid gossip sync should be enabled");
+    rapid_gossip.apply(&rgs_graph_fixture(timestamp, 10_001))?;
+
+    let mut channels = Vec::new();
+    let mut start: Option<String> = None;
+    loop {
+        let page: response::GossipChannels = node.lampod().call(
+            "listgossipchannels",
+            request::ListGossipChannels {
+                start: start.clone(),
+                limit: Some(1000),
+                ..Default::default()
+            },
+        )?;
+        assert!(page.channels.len() <= 1000);
+        channels.extend(page.channels);
+        start = page.next;
+        if start.is_none() {
+            break;
+        }
+    }
+    assert_eq!(channels.len(), 10_000);
+    assert!(channels
+        .windows(2)
+        .all(|pair| pair[0].short_channel_id < pair[1].short_channel_id));
+
+    let mut nodes = 0;
+    let mut start: Option<String> = None;
+    loop {
+        let page: response::GossipNodes = node.lampod().call(
+            "listnodes",
+            request::ListNodes {
+                start: start.clone(),
+                limit: Some(1000),
+                ..Default::default()
+            },
+        )?;
+        assert!(page.nodes.len() <= 1000);
+        nodes += page.nodes.len();
+        start = page.next;
+        if start.is_none() {
+            break;
+        }
+    }
+    assert_eq!(nodes, 10_001);
+
+    // a node in the middle of the line has two channels
+    let channel: response::GossipChannels = node.lampod().call(
+        "listgossipchannels",
+        request::ListGossipChannels {
+            short_channel_id: Some(1),
+            ..Default::default()
+        },
+    )?;
+    assert_eq!(channel.channels.len(), 1);
+    let node_id = channel.channels[0].node_two.clone();
+    let node_channels: response::GossipChannels = node.lampod().call(
+        "listgossipchannels",
+        request::ListGossipChannels {
+            node_id: Some(node_id.clone()),
+            ..Default::default()
+        },
+    )?;
+    assert_eq!(node_channels.channels.len(), 2);
+    assert!(node_channels.next.is_none());
+
+    let info: response::GossipNodes = node.lampod().call(
+        "listnodes",
+        request::ListNodes {
+            node_id: Some(node_id.clone()),
+            ..Default::default()
+        },
+    )?;
+    assert_eq!(info.nodes.len(), 1);
+    assert_eq!(info.nodes[0].node_id, node_id);
+    // the snapshot does not contain the node announcements
+    assert!(info.nodes[0].alias.is_none());
+
+    let invalid: error::Result<response::GossipNodes> = node.lampod().call(
+        "listnodes",
+        request::ListNodes {
+            limit: Some(0),
+            ..Default::default()
+        },
+    );
+    assert!(invalid.is_err());
+    Ok(())
+}
+
+#[test]
+pub fn persist_network_graph_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new_with_conf(btc.clone(), |conf| {
+        // nothing is listening here, the snapshot is applied by the test
+        conf.rgs_url = Some("http://127.0.0.1:1/snapshot".to_owned());
+    })?;
+
+    // the graph is not written while the one on the disk is loading
+    node.daemon()
+        .channel_manager()
+        .graph_load()
+        .wait(Duration::from_secs(10))?;
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+    let rapid_gossip = node
+        .daemon()
+        .rapid_gossip()
+        .expect("rapid gossip sync should be enabled");
+    rapid_gossip.apply(&rgs_snapshot_fixture(timestamp))?;
+    node.daemon().graph_persister().persist_now()?;
+
+    // load the graph as the node does at the restart
+    let persister = LampoPersistence::new(node.daemon().root_path().into());
+    let graph =
+        LampoGraphPersister::load_graph(&persister, Network::Regtest, Arc::new(LampoLogger));
+    {
+        let graph = graph.read_only();
+        assert_eq!(graph.nodes().len(), 3);
+        assert_eq!(graph.channels().len(), 2);
+    }
+    assert_eq!(graph.get_last_rapid_gossip_sync_timestamp(), Some(timestamp));
+
+    // a corrupted graph gives an empty one
+    let graph_path = format!("{}/network_graph", node.daemon().root_path());
+    std::fs::write(graph_path, b"not a network graph")?;
+    let graph =
+        LampoGraphPersister::load_graph(&persister, Network::Regtest, Arc::new(LampoLogger));
+    assert_eq!(graph.read_only().nodes().len(), 0);
+    assert_eq!(graph.read_only().channels().len(), 0);
+    Ok(())
+}
+
+#[test]
+pub fn create_transaction_from_pinned_utxos_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+    let _ = node.fund_wallet(101)?;
+
+    let utxos = node.wallet.list_unspent()?;
+    let utxo = utxos
+        .iter()
+        .find(|utxo| !utxo.reserved && utxo.confirmed > 0)
+        .expect("the wallet should have a confirmed utxo");
+    let outpoint = OutPoint::new(Txid::from_str(&utxo.txid)?, utxo.vout);
+    let address = node.wallet.get_onchain_address()?;
+    let script = Address::from_str(&address.address)?
+        .require_network(Network::Regtest)?
+        .script_pubkey();
+
+    let amount_sat = Sat::from_sat(utxo.amount_msat.to_sat_floor().sat() / 2);
+    let tx = node.wallet.create_transaction_from_utxos(
+        vec![outpoint],
+        script.clone(),
+        amount_sat,
+        253,
+    )?;
+    assert_eq!(tx.input.len(), 1);
+    assert_eq!(tx.input[0].previous_output, outpoint);
+
+    // the pinned utxo is not enough to pay the amount and the fee
+    let result = node.wallet.create_transaction_from_utxos(
+        vec![outpoint],
+        script.clone(),
+        utxo.amount_msat.to_sat_floor(),
+        253,
+    );
+    assert!(result.is_err(), "{:?}", result);
+
+    // an utxo that is not owned by the wallet
+    let unknown = OutPoint::new(Txid::all_zeros(), 0);
+    let result = node
+        .wallet
+        .create_transaction_from_utxos(vec![unknown], script, amount_sat, 253);
+    assert!(result.is_err(), "{:?}", result);
+    Ok(())
+}
+
+#[test]
+pub fn create_transaction_below_min_fee_rate_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.min_fee_rate_sat_vb = 2.0;
+    })?;
+    let _ = node.fund_wallet(101)?;
+
+    let address = node.wallet.get_onchain_address()?;
+    let script = Address::from_str(&address.address)?
+        .require_network(Network::Regtest)?
+        .script_pubkey();
+    let err = node
+        .wallet
+        .create_transaction(script.clone(), Sat::from_sat(10_000), 253)
+        .expect_err("the fee rate is below the minimum");
+    assert_eq!(
+        err.downcast_ref::<WalletError>(),
+        Some(&WalletError::FeeTooLow {
+            fee_rate: 253,
+            min_fee_rate: 500,
+        })
+    );
+
+    let tx = node
+        .wallet
+        .create_transaction(script, Sat::from_sat(10_000), 500)?;
+    assert!(!tx.input.is_empty());
+    Ok(())
+}
+
+#[test]
+pub fn broadcast_below_the_mempool_min_fee_lampo() -> error::Result<()> {
+    use lampo_testing::prelude::bitcoincore_rpc::RpcApi;
+
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+    let _ = node.fund_wallet(101)?;
+
+    let address = node.wallet.get_onchain_address()?;
+    let script = Address::from_str(&address.address)?
+        .require_network(Network::Regtest)?
+        .script_pubkey();
+    let tx = node
+        .wallet
+        .create_transaction(script, Sat::from_sat(10_000), 500)?;
+    // bitcoind checks the modified fee, so lowering it is like
+    // raising the floor of the mempool for this transaction.
+    let txid = tx.txid().to_string();
+    let _: bool = btc
+        .rpc()
+        .call("prioritisetransaction", &[txid.clone().into(), 0.into(), (-100_000).into()])?;
+
+    let mempool: json::Value = btc.rpc().call("getmempoolinfo", &[])?;
+    // SAFETY: bitcoind always returns the mempool min fee.
+    let min_fee_rate = (mempool["mempoolminfee"].as_f64().unwrap() * 25_000_000.0).round() as u32;
+    let err = node
+        .daemon()
+        .onchain_manager()
+        .broadcast(&tx)
+        .expect_err("the fee is below the mempool minimum");
+    assert_eq!(
+        err,
+        BroadcastError::FeeTooLow {
+            fee_rate: None,
+            min_fee_rate,
+        }
+    );
+    let pending: response::PendingBroadcasts =
+        node.lampod().call("pendingbroadcasts", json::json!({}))?;
+    assert_eq!(pending.transactions.len(), 1);
+    assert_eq!(pending.transactions[0].txid, txid);
+    assert_eq!(pending.transactions[0].error, err);
+
+    // with the fee back the transaction is broadcasted at the next block
+    let _: bool = btc
+        .rpc()
+        .call("prioritisetransaction", &[txid.clone().into(), 0.into(), 100_000.into()])?;
+    let _ = fund_wallet(btc.clone(), &address.address, 1)?;
+    wait!(|| {
+        let pending: response::PendingBroadcasts = node
+            .lampod()
+            .call("pendingbroadcasts", json::json!({}))
+            .unwrap();
+        if pending.transactions.is_empty() {
+            return Ok(());
+        }
+        Err(())
+    });
+    let mempool = btc.rpc().get_raw_mempool()?;
+    assert!(mempool.iter().any(|mempool_txid| mempool_txid.to_string() == txid));
+    Ok(())
+}
+
+#[test]
+pub fn pending_incoming_balance_lampo() -> error::Result<()> {
+    use lampo_testing::prelude::bitcoincore_rpc::RpcApi;
+
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let _ = node1.fund_wallet(101)?;
+
+    let address = node2.wallet.get_onchain_address()?;
+    let script = Address::from_str(&address.address)?
+        .require_network(Network::Regtest)?
+        .script_pubkey();
+    let tx = node1
+        .wallet
+        .create_transaction(script, Sat::from_sat(100_000), 500)?;
+    let _ = btc
+        .rpc()
+        .send_raw_transaction(lampo_common::bitcoin::consensus::encode::serialize_hex(&tx))?;
+
+    // the deposit is in the mempool, so it is only pending
+    let funds: response::Utxos = node2.lampod().call("funds", json::json!({}))?;
+    assert_eq!(funds.pending_incoming_sat, Sat::from_sat(100_000));
+    assert_eq!(
+        node2.wallet.pending_incoming_balance()?,
+        Sat::from_sat(100_000)
+    );
+
+    let _ = fund_wallet(btc.clone(), &address.address, 1)?;
+    wait!(|| {
+        if node2.wallet.pending_incoming_balance().unwrap() == Sat::ZERO {
+            return Ok(());
+        }
+        Err(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn transaction_history_lampo() -> error::Result<()> {
+    use lampo_testing::prelude::bitcoincore_rpc::RpcApi;
+
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let _ = node1.fund_wallet(101)?;
+
+    let history: response::TxHistory = node1.lampod().call("listtransactions", json::json!({}))?;
+    assert!(!history.transactions.is_empty());
+    assert!(history
+        .transactions
+        .iter()
+        .all(|tx| tx.net_amount_sat > 0 && tx.confirmation_height.is_some()));
+
+    let address = node2.wallet.get_onchain_address()?;
+    let script = Address::from_str(&address.address)?
+        .require_network(Network::Regtest)?
+        .script_pubkey();
+    let tx = node1
+        .wallet
+        .create_transaction(script, Sat::from_sat(100_000), 500)?;
+    let _ = btc
+        .rpc()
+        .send_raw_transaction(lampo_common::bitcoin::consensus::encode::serialize_hex(&tx))?;
+
+    // the payment is unconfirmed, so it is the first one
+    let history: response::TxHistory = node1.lampod().call("listtransactions", json::json!({}))?;
+    let sent = &history.transactions[0];
+    assert_eq!(sent.txid, tx.txid().to_string());
+    assert!(sent.confirmation_height.is_none());
+    // SAFETY: we own all the inputs, so the fee is known.
+    let fee = sent.fee_sat.unwrap();
+    assert!(fee > 0);
+    assert_eq!(sent.net_amount_sat, -(100_000 + fee as i64));
+
+    let history: response::TxHistory = node2.lampod().call("listtransactions", json::json!({}))?;
+    assert_eq!(history.transactions.len(), 1);
+    assert_eq!(history.transactions[0].net_amount_sat, 100_000);
+    Ok(())
+}
+
+#[test]
+pub fn reorg_funding_transaction_lampo() -> error::Result<()> {
+    use lampo_testing::prelude::bitcoincore_rpc::RpcApi;
+
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        json::json!({
+            "node_id": format!("{}@127.0.0.1:{}", node2.info.node_id, node2.port),
+        }),
+    )?;
+    let _ = node1.fund_wallet(101)?;
+    let _: json::Value = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 100_000,
+            public: true,
+            port: None,
+            addr: None,
+        },
+    )?;
+    let address = node2.wallet.get_onchain_address()?;
+    let confirmations = || -> Option<u32> {
+        let channels: response::Channels =
+            node1.lampod().call("channels", json::json!({})).unwrap();
+        channels.channels.first().and_then(|channel| channel.confirmations)
+    };
+
+    let _ = fund_wallet(btc.clone(), &address.address, 1)?;
+    let funding_block = btc.rpc().get_best_block_hash()?;
+    wait!(|| {
+        if confirmations() == Some(1) {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    // the block with the funding transaction is reorged out
+    btc.rpc().invalidate_block(&funding_block)?;
+    wait!(|| {
+        if confirmations().is_none() {
+            return Ok(());
+        }
+        Err(())
+    });
+    let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+    assert!(!channels.channels[0].ready);
+
+    // the funding transaction is back in the mempool, so it is mined again
+    let _ = fund_wallet(btc.clone(), &address.address, 1)?;
+    wait!(|| {
+        if confirmations() == Some(1) {
+            return Ok(());
+        }
+        Err(())
+    });
+    let _ = fund_wallet(btc.clone(), &address.address, 6)?;
+    wait!(|| {
+        let channels: response::Channels =
+            node1.lampod().call("channels", json::json!({})).unwrap();
+        if channels.channels.first().is_some_and(|channel| channel.ready) {
+            return Ok(());
+        }
+        Err(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn gossip_announcements_are_validated_lampo() -> error::Result<()> {
+    use lampo_common::bitcoin::blockdata::constants::ChainHash;
+    use lampo_common::bitcoin::hashes::sha256d;
+    use lampo_common::ldk::ln::chan_utils::make_funding_redeemscript;
+    use lampo_common::ldk::ln::features::ChannelFeatures;
+    use lampo_common::ldk::ln::msgs::{
+        ChannelAnnouncement, RoutingMessageHandler, UnsignedChannelAnnouncement,
+    };
+    use lampo_common::ldk::routing::gossip::{NodeId as GossipNodeId, P2PGossipSync};
+    use lampo_common::ldk::util::ser::Writeable;
+    use lampo_common::secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+    use lampo_testing::prelude::bitcoincore_rpc::RpcApi;
+
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+    let _ = node.fund_wallet(101)?;
+    let graph = node.daemon().channel_manager().graph();
+    let gossip = P2PGossipSync::new(
+        graph.clone(),
+        Some(node.daemon().onchain_manager()),
+        Arc::new(LampoLogger),
+    );
+
+    let secp = Secp256k1::new();
+    // SAFETY: the keys are valid secret keys.
+    let keys = [1u8, 2, 3, 4].map(|byte| SecretKey::from_slice(&[byte; 32]).unwrap());
+    let pubkeys = keys.map(|key| PublicKey::from_secret_key(&secp, &key));
+    let announce = |scid: u64| -> ChannelAnnouncement {
+        let contents = UnsignedChannelAnnouncement {
+            features: ChannelFeatures::empty(),
+            chain_hash: ChainHash::using_genesis_block(Network::Regtest),
+            short_channel_id: scid,
+            node_id_1: GossipNodeId::from_pubkey(&pubkeys[0]),
+            node_id_2: GossipNodeId::from_pubkey(&pubkeys[1]),
+            bitcoin_key_1: GossipNodeId::from_pubkey(&pubkeys[2]),
+            bitcoin_key_2: GossipNodeId::from_pubkey(&pubkeys[3]),
+            excess_data: Vec::new(),
+        };
+        let hash = sha256d::Hash::hash(&contents.encode());
+        // SAFETY: the hash is 32 bytes long.
+        let msg = Message::from_slice(hash.as_byte_array()).unwrap();
+        let [node_1, node_2, bitcoin_1, bitcoin_2] = keys.map(|key| secp.sign_ecdsa(&msg, &key));
+        ChannelAnnouncement {
+            node_signature_1: node_1,
+            node_signature_2: node_2,
+            bitcoin_signature_1: bitcoin_1,
+            bitcoin_signature_2: bitcoin_2,
+            contents,
+        }
+    };
+    let scid = |height: u64, tx_index: u64, vout: u64| (height << 40) | (tx_index << 16) | vout;
+
+    // there is no such transaction inside the first block
+    let forged = scid(1, 5, 0);
+    let _ = gossip.handle_channel_announcement(&announce(forged));
+
+    // fund the 2-of-2 of the bitcoin keys, like a real channel
+    let script = make_funding_redeemscript(&pubkeys[2], &pubkeys[3]).to_v0_p2wsh();
+    let tx = node
+        .wallet
+        .create_transaction(script.clone(), Sat::from_sat(100_000), 500)?;
+    let _ = btc
+        .rpc()
+        .send_raw_transaction(lampo_common::bitcoin::consensus::encode::serialize_hex(&tx))?;
+    let address = node.wallet.get_onchain_address()?;
+    let _ = fund_wallet(btc.clone(), &address.address, 1)?;
+    let height = btc.rpc().get_block_count()?;
+    let block = btc.rpc().get_block(&btc.rpc().get_best_block_hash()?)?;
+    // SAFETY: the transaction is mined inside the last block.
+    let tx_index = block
+        .txdata
+        .iter()
+        .position(|mined| mined.txid().to_string() == tx.txid().to_string())
+        .unwrap();
+    // SAFETY: the transaction pays the funding script.
+    let vout = tx
+        .output
+        .iter()
+        .position(|output| output.script_pubkey == script)
+        .unwrap();
+    let genuine = scid(height, tx_index as u64, vout as u64);
+    let _ = gossip.handle_channel_announcement(&announce(genuine));
+
+    wait!(|| {
+        if graph.read_only().channel(genuine).is_some() {
+            return Ok(());
+        }
+        Err(())
+    });
+    assert!(graph.read_only().channel(forged).is_none());
+    Ok(())
+}
+
+#[test]
+pub fn estimate_open_cost_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+    let _ = node.fund_wallet(101)?;
+
+    let cost: response::OpenCost = node.lampod().call(
+        "estimateopencost",
+        json::json!({
+            "amount": 100_000,
+        }),
+    )?;
+    assert_eq!(cost.conf_target, 6);
+    assert!(cost.fee_sat > 0);
+    assert_eq!(cost.total_sat, cost.amount_sat + cost.fee_sat);
+
+    // the wallet does not have these funds
+    let cost: error::Result<response::OpenCost> = node.lampod().call(
+        "estimateopencost",
+        json::json!({
+            "amount": 21_000_000 * 100_000_000u64,
+            "conf_target": 1,
+        }),
+    );
+    assert!(cost.is_err());
+    Ok(())
+}
+
+#[test]
+pub fn funding_confirmation_events_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.ldk_conf.channel_handshake_config.minimum_depth = 3;
+    })?);
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        json::json!({
+            "node_id": format!("{}@127.0.0.1:{}", node2.info.node_id, node2.port),
+        }),
+    )?;
+    let _ = node1.fund_wallet(101)?;
+    let events = node1.lampod().events();
+    let _: json::Value = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 100_000,
+            public: true,
+            port: None,
+            addr: None,
+        },
+    )?;
+    let address = node2.wallet.get_onchain_address()?;
+
+    // one block at a time, so each confirmation is reported
+    for expected in 1..=3 {
+        let _ = fund_wallet(btc.clone(), &address.address, 1)?;
+        wait!(|| {
+            while let Ok(event) = events.recv_timeout(Duration::from_millis(10)) {
+                if let Event::Lightning(LightningEvent::ChannelConfirmation {
+                    confirmations,
+                    required,
+                    ..
+                }) = event
+                {
+                    assert_eq!(required, 3);
+                    if confirmations == expected {
+                        return Ok(());
+                    }
+                }
+            }
+            Err(())
+        });
+        let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+        assert_eq!(channels.channels[0].confirmations_required, Some(3));
+    }
+
+    wait!(|| {
+        while let Ok(event) = events.recv_timeout(Duration::from_millis(10)) {
+            if let Event::Lightning(LightningEvent::ChannelReady { .. }) = event {
+                return Ok(());
+            }
+        }
+        Err(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn fee_rates_from_esplora_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let esplora = MockHttp::start(
+        json::json!({
+            "1": 20.0,
+            "2": 15.0,
+            "6": 10.0,
+            "12": 5.0,
+            "144": 2.0,
+            "1008": 0.5,
+        })
+        .to_string(),
+    )?;
+    let url = esplora.url();
+    let node = LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.fee_estimates_url = Some(url);
+        conf.max_fee_rate_sat_kw = Some(4000);
+    })?;
+    assert!(esplora.requests() > 0);
+
+    let fee_rates: response::FeeRates = node.lampod().call("feerates", json::json!({}))?;
+    assert_eq!(fee_rates.source, "esplora");
+    assert!(fee_rates.updated_at.is_some());
+    let fee_rate = |target: &str| {
+        fee_rates
+            .fee_rates
+            .iter()
+            .find(|fee_rate| fee_rate.target == target)
+            .map(|fee_rate| (fee_rate.blocks, fee_rate.sat_per_kw))
+    };
+    // 20 sat/vB are 5000 sat/kW, over the ceiling
+    assert_eq!(fee_rate("on_chain_sweep"), Some((1, 4000)));
+    assert_eq!(fee_rate("anchor_channel"), Some((6, 2500)));
+    assert_eq!(fee_rate("non_anchor_channel"), Some((6, 2500)));
+    assert_eq!(fee_rate("output_spending"), Some((12, 1250)));
+    assert_eq!(fee_rate("channel_close_minimum"), Some((144, 500)));
+    // 0.5 sat/vB are below the floor of ldk
+    assert_eq!(fee_rate("min_allowed_anchor_channel_remote"), Some((1008, 253)));
+    Ok(())
+}
+
+#[test]
+pub fn chain_info_follows_the_tip_lampo() -> error::Result<()> {
+    use lampo_testing::prelude::bitcoincore_rpc::RpcApi;
+
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+    let address = node.wallet.get_onchain_address()?;
+    let _ = fund_wallet(btc.clone(), &address.address, 1)?;
+    let chain_info = || -> response::ChainInfo {
+        node.lampod().call("getchaininfo", json::json!({})).unwrap()
+    };
+    let best_height = btc.rpc().get_block_count()? as u32;
+    wait!(|| {
+        if chain_info().best_block_height == Some(best_height) {
+            return Ok(());
+        }
+        Err(())
+    });
+    let info = chain_info();
+    assert_eq!(info.network, "regtest");
+    assert_eq!(info.backend, "core");
+    assert!(info.backend_endpoint.is_some());
+    let best_hash = btc.rpc().get_best_block_hash()?.to_string();
+    assert_eq!(info.best_block_hash, Some(best_hash));
+    assert!(info.wallet_synced_height.is_some_and(|height| height >= best_height));
+
+    let _ = fund_wallet(btc.clone(), &address.address, 3)?;
+    wait!(|| {
+        let info = chain_info();
+        if info.best_block_height == Some(best_height + 3)
+            && info.wallet_synced_height == Some(best_height + 3)
+        {
+            return Ok(());
+        }
+        Err(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn fund_a_simple_channel_from() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let response: response::Connect = node2
+        .lampod()
+        .call(
+            "connect",
+            request::Connect {
+                node_id: node1.info.node_id.clone(),
+                addr: "127.0.0.1".to_owned(),
+                port: node1.port,
+            },
+        )
+        .unwrap();
+    log::debug!("node 1 -> connected with node 2 {:?}", response);
+
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101).unwrap();
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let response: json::Value = node1
+        .lampod()
+        .call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: node2.info.node_id.clone(),
+                amount: 100000,
+                public: true,
+                port: None,
+                addr: None,
+            },
+        )
+        .unwrap();
+    assert!(response.get("tx").is_some());
+
+    let events = node2.lampod().events();
+    wait!(|| {
+        while let Ok(event) = events.recv_timeout(Duration::from_millis(10)) {
+            node2.fund_wallet(1).unwrap();
+            if let Event::Lightning(LightningEvent::ChannelReady {
+                counterparty_node_id,
+                ..
+            }) = event
+            {
+                if counterparty_node_id.to_string() == node1.info.node_id {
+                    return Err(());
+                }
+                return Ok(());
+            };
+            // check if lampo see the channel
+            let channels: response::Channels =
+                node2.lampod().call("channels", json::json!({})).unwrap();
+            if !channels.channels.is_empty() {
+                return Ok(());
+            }
+
+            if channels.channels.first().unwrap().ready {
+                return Ok(());
+            }
+        }
+        node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+    let channels = channels.channels;
+    assert_eq!(channels.len(), 1);
+    assert_eq!(channels[0].peer_id, node2.info.node_id);
+    assert_eq!(channels[0].amount_satoshis, 100000);
+    Ok(())
+}
+
+#[test]
+pub fn fund_channel_with_unconfirmed_funds_lampo() -> error::Result<()> {
+    use lampo_testing::prelude::bitcoincore_rpc::RpcApi;
+
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let _ = node1.fund_wallet(101)?;
+
+    let address = node2.wallet.get_onchain_address()?;
+    let script = Address::from_str(&address.address)?
+        .require_network(Network::Regtest)?
+        .script_pubkey();
+    let tx = node1
+        .wallet
+        .create_transaction(script, Sat::from_sat(500_000), 500)?;
+    let _ = btc
+        .rpc()
+        .send_raw_transaction(lampo_common::bitcoin::consensus::encode::serialize_hex(&tx))?;
+
+    // the deposit is in the mempool, so it can not fund a channel
+    let result: error::Result<json::Value> = node2.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node1.info.node_id.clone(),
+            amount: 100_000,
+            public: true,
+            addr: Some("127.0.0.1".to_owned()),
+            port: Some(node1.port),
+        },
+    );
+    let err = result.expect_err("the channel is funded with unconfirmed outputs");
+    assert!(
+        err.to_string().contains("confirmations"),
+        "unexpected error: {err}"
+    );
+
+    let _ = fund_wallet(btc.clone(), &address.address, 1)?;
+    wait!(|| {
+        if node2.wallet.pending_incoming_balance().unwrap() == Sat::ZERO {
+            return Ok(());
+        }
+        Err(())
+    });
+    let response: json::Value = node2.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node1.info.node_id.clone(),
+            amount: 100_000,
+            public: true,
+            addr: Some("127.0.0.1".to_owned()),
+            port: Some(node1.port),
+        },
+    )?;
+    assert!(response.get("tx").is_some());
+    Ok(())
+}
+
+#[test]
+pub fn list_funds_after_fundchannel_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101)?;
+    wait!(|| {
+        let funds: response::ListFunds =
+            node1.lampod().call("listfunds", json::json!({})).unwrap();
+        if funds.total_onchain_sat == Sat::ZERO {
+            return Err(());
+        }
+        Ok(())
+    });
+    let funds: response::ListFunds = node1.lampod().call("listfunds", json::json!({}))?;
+    assert!(funds.channels.is_empty());
+    assert_eq!(funds.total_channel_sat, Sat::ZERO);
+    assert!(funds.outputs.iter().all(|utxo| utxo.address.is_some()));
+
+    let _: json::Value = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 1_000_000,
+            public: true,
+            addr: Some("127.0.0.1".to_owned()),
+            port: Some(node2.port),
+        },
+    )?;
+    let mut funding = None;
+    wait!(|| {
+        while let Ok(event) = events.recv_timeout(Duration::from_millis(100)) {
+            if let Event::Lightning(LightningEvent::FundingChannelEnd {
+                funding_transaction,
+                ..
+            }) = event
+            {
+                funding = Some(funding_transaction);
+                return Ok(());
+            }
+        }
+        Err(())
+    });
+    // SAFETY: the wait fails if the funding transaction is not created.
+    let funding = funding.unwrap();
+    let spent = funding
+        .input
+        .iter()
+        .map(|input| (input.previous_output.txid.to_string(), input.previous_output.vout))
+        .collect::<Vec<_>>();
+    assert!(funds
+        .outputs
+        .iter()
+        .any(|utxo| spent.contains(&(utxo.txid.clone(), utxo.vout))));
+
+    wait!(|| {
+        node2.fund_wallet(6).unwrap();
+        let funds: response::ListFunds =
+            node1.lampod().call("listfunds", json::json!({})).unwrap();
+        if funds.channels.first().map(|channel| channel.state.as_str()) != Some("active") {
+            return Err(());
+        }
+        Ok(())
+    });
+    let funds: response::ListFunds = node1.lampod().call("listfunds", json::json!({}))?;
+    assert_eq!(funds.channels.len(), 1);
+    assert_eq!(funds.channels[0].peer_id, node2.info.node_id);
+    assert_eq!(
+        funds.channels[0].amount_msat,
+        Msat::from_msat(1_000_000_000)
+    );
+    assert!(funds.channels[0].short_channel_id.is_some());
+    assert!(funds.total_channel_sat > Sat::ZERO);
+    assert!(!funds
+        .outputs
+        .iter()
+        .any(|utxo| spent.contains(&(utxo.txid.clone(), utxo.vout))));
+    Ok(())
+}
+
+#[test]
+pub fn pay_invoice_simple_case_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101)?;
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let response: json::Value = node1
+        .lampod()
+        .call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: node2.info.node_id.clone(),
+                amount: 1_000_000,
+                public: true,
+                addr: Some("127.0.0.1".to_owned()),
+                port: Some(node2.port),
+            },
+        )
+        .unwrap();
+    assert!(response.get("tx").is_some());
+
+    wait!(|| {
+        while let Ok(event) = events.recv_timeout(Duration::from_millis(10)) {
+            node2.fund_wallet(6).unwrap();
+            if let Event::Lightning(LightningEvent::ChannelReady {
+                counterparty_node_id,
+                ..
+            }) = event
+            {
+                if counterparty_node_id.to_string() == node1.info.node_id {
+                    return Err(());
+                }
+                return Ok(());
+            };
+            // check if lampo see the channel
+            let channels: response::Channels =
+                node2.lampod().call("channels", json::json!({})).unwrap();
+            if channels.channels.is_empty() {
+                return Err(());
+            }
+
+            if !channels.channels.first().unwrap().ready {
+                return Err(());
+            }
+
+            let channels: response::Channels =
+                node1.lampod().call("channels", json::json!({})).unwrap();
+
+            if channels.channels.is_empty() {
+                return Err(());
+            }
+
+            if channels.channels.first().unwrap().ready {
+                return Ok(());
+            }
+        }
+        node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    let invoice: response::Invoice = node2.lampod().call(
+        "invoice",
+        request::GenerateInvoice {
+            description: "making sure that we can work betwen lampo version".to_owned(),
+            amount_msat: Some(100_000_000),
+            expiring_in: None,
+            expiry_unix: None,
+            no_expiry: false,
+            label: None,
+            fallback: None,
+            metadata: None,
+        },
+    )?;
+
+    log::info!(target: &node2.info.node_id, "invoice generated `{:?}`", invoice);
+    assert_eq!(invoice.payment_secret.len(), 64);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    assert!(invoice.expiry_unix > now);
+
+    let pay: response::PayResult = node1.lampod().call(
+        "pay",
+        request::Pay {
+            invoice_str: invoice.bolt11.clone(),
+            amount: None,
+            custom_tlvs: Vec::new(),
+            timeout_secs: None,
+            max_total_cltv_expiry_delta: None,
+        },
+    )?;
+    log::info!(target: &node1.info.node_id, "payment made `{:?}`", pay);
+    assert_eq!(pay.payment_hash.as_ref(), Some(&invoice.payment_hash));
+
+    wait!(|| {
+        let status: response::PaymentStatus = node2
+            .lampod()
+            .call(
+                "isinvoicepaid",
+                request::IsInvoicePaid {
+                    invoice_str: invoice.bolt11.clone(),
+                },
+            )
+            .unwrap();
+        if status.state != response::InboundPaymentState::Paid {
+            return Err(());
+        }
+        assert_eq!(
+            status.amount_received_msat,
+            Some(Msat::from_msat(100_000_000))
+        );
+        Ok(())
+    });
+
+    let route: response::PaymentRoute = node1.lampod().call(
+        "getpaymentroute",
+        request::GetPaymentRoute {
+            payment_hash: pay.payment_hash.unwrap(),
+        },
+    )?;
+    assert_eq!(route.paths.len(), 1);
+    assert_eq!(route.paths[0].amount_msat, 100_000_000);
+    Ok(())
+}
+
+#[test]
+pub fn pay_with_cltv_limit_below_the_final_cltv_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+
+    let invoice: response::Invoice = node2.lampod().call(
+        "invoice",
+        request::GenerateInvoice {
+            description: "the route can not be shorter than the final cltv".to_owned(),
+            amount_msat: Some(100_000),
+            expiring_in: None,
+            expiry_unix: None,
+            no_expiry: false,
+            label: None,
+            fallback: None,
+            metadata: None,
+        },
+    )?;
+    let pay: error::Result<response::PayResult> = node1.lampod().call(
+        "pay",
+        request::Pay {
+            invoice_str: invoice.bolt11,
+            amount: None,
+            custom_tlvs: Vec::new(),
+            timeout_secs: None,
+            max_total_cltv_expiry_delta: Some(1),
+        },
+    );
+    let err = pay.expect_err("the cltv limit is below the final cltv");
+    assert!(err.to_string().contains("final CLTV expiry delta"), "{err}");
+
+    let keysend: error::Result<json::Value> = node1.lampod().call(
+        "keysend",
+        request::KeySend {
+            destination: node2.info.node_id.parse()?,
+            amount_msat: Msat::from_msat(100_000),
+            max_total_cltv_expiry_delta: Some(39),
+        },
+    );
+    let err = keysend.expect_err("the cltv limit is below the keysend cltv");
+    assert!(err.to_string().contains("final CLTV expiry delta"), "{err}");
+    Ok(())
+}
+
+#[test]
+pub fn pay_without_channels_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+
+    let invoice: response::Invoice = node2.lampod().call(
+        "invoice",
+        request::GenerateInvoice {
+            description: "there is no channel to pay it".to_owned(),
+            amount_msat: Some(100_000),
+            expiring_in: None,
+            expiry_unix: None,
+            no_expiry: false,
+            label: None,
+            fallback: None,
+            metadata: None,
+        },
+    )?;
+    let pay: error::Result<response::PayResult> = node1.lampod().call(
+        "pay",
+        request::Pay {
+            invoice_str: invoice.bolt11,
+            amount: None,
+            custom_tlvs: Vec::new(),
+            timeout_secs: None,
+            max_total_cltv_expiry_delta: None,
+        },
+    );
+    let err = pay.expect_err("node1 has no channels");
+    assert!(err.to_string().contains("exceeds the outbound capacity"), "{err}");
+    Ok(())
+}
+
+#[test]
+pub fn rpc_error_codes_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+
+    // the standard JSON RPC 2.0 codes
+    let result: error::Result<json::Value> = node1.lampod().call("notamethod", json::json!({}));
+    let err = rpc_error(result.expect_err("the method does not exist")).unwrap();
+    assert_eq!(err.code, -32601, "{err:?}");
+    let result: error::Result<json::Value> = node1
+        .lampod()
+        .call("getchannel", json::json!({ "channel": 1 }));
+    let err = rpc_error(result.expect_err("the `id` is missing")).unwrap();
+    assert_eq!(err.code, -32602, "{err:?}");
+
+    let result: error::Result<json::Value> = node1.lampod().call(
+        "getchannel",
+        request::GetChannel {
+            id: "103x1x1".to_owned(),
+        },
+    );
+    let err = rpc_error(result.expect_err("the channel never existed")).unwrap();
+    assert_eq!(err.code, 300, "{err:?}");
+    assert_eq!(err.data.unwrap()["category"], "channel");
+
+    let invoice: response::Invoice = node2.lampod().call(
+        "invoice",
+        request::GenerateInvoice {
+            description: "there is no channel to pay it".to_owned(),
+            amount_msat: Some(100_000),
+            expiring_in: None,
+            expiry_unix: None,
+            no_expiry: false,
+            label: None,
+            fallback: None,
+            metadata: None,
+        },
+    )?;
+    let result: error::Result<response::PayResult> = node1.lampod().call(
+        "pay",
+        request::Pay {
+            invoice_str: invoice.bolt11,
+            amount: None,
+            custom_tlvs: Vec::new(),
+            timeout_secs: None,
+            max_total_cltv_expiry_delta: None,
+        },
+    );
+    let err = rpc_error(result.expect_err("node1 has no channels")).unwrap();
+    assert_eq!(err.code, 203, "{err:?}");
+    assert_eq!(err.data.unwrap()["amount_msat"], 100_000);
+
+    // node1 has no funds to open the channel
+    let result: error::Result<json::Value> = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 100_000,
+            public: true,
+            addr: Some("127.0.0.1".to_owned()),
+            port: Some(node2.port),
+        },
+    );
+    let err = rpc_error(result.expect_err("the wallet is empty")).unwrap();
+    assert_eq!(err.code, 101, "{err:?}");
+    let data = err.data.unwrap();
+    assert_eq!(data["category"], "wallet");
+    assert_eq!(data["needed_sat"], 100_000);
+    Ok(())
+}
+
+#[test]
+pub fn read_only_token_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+
+    let err = client_call(&node, "getinfo", None, json::json!({})).unwrap_err();
+    assert_eq!(err.code, -32001, "{err:?}");
+
+    let token: response::MintToken = node.lampod().call(
+        "minttoken",
+        request::MintToken {
+            scope: "readonly".to_owned(),
+        },
+    )?;
+    let read_only = Some(token.token.as_str());
+    let info = client_call(&node, "getinfo", read_only, json::json!({})).unwrap();
+    assert_eq!(info["node_id"], node.info.node_id);
+    client_call(&node, "channels", read_only, json::json!({})).unwrap();
+
+    for method in ["pay", "withdraw", "minttoken"] {
+        let err = client_call(&node, method, read_only, json::json!({})).unwrap_err();
+        assert_eq!(err.code, -32002, "{err:?}");
+        assert_eq!(err.data.unwrap()["scope"], "readonly");
+    }
+
+    // a token with a different scope does not match the mac
+    let forged = token.token.replace("readonly", "admin");
+    let err = client_call(&node, "pay", Some(&forged), json::json!({})).unwrap_err();
+    assert_eq!(err.code, -32001, "{err:?}");
+
+    let master = node.daemon().rpc_auth().master_token();
+    client_call(
+        &node,
+        "revoketoken",
+        Some(&master),
+        json::json!({ "id": token.id }),
+    )
+    .unwrap();
+    let err = client_call(&node, "getinfo", read_only, json::json!({})).unwrap_err();
+    assert_eq!(err.code, -32001, "{err:?}");
+    Ok(())
+}
+
+#[test]
+pub fn pay_invoice_over_http_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    // the gateways listen on a free port
+    let node1 = LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.http_bind = Some("127.0.0.1:0".parse().unwrap());
+    })?;
+    let node2 = LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.http_bind = Some("127.0.0.1:0".parse().unwrap());
+    })?;
+    let token1 = node1.daemon().rpc_auth().master_token();
+    let token2 = node2.daemon().rpc_auth().master_token();
+
+    let (status, openapi) = http_call(&node1, "GET", "/v1/openapi.json", None, None)?;
+    assert_eq!(status, 200);
+    let pay = &openapi["paths"]["/v1/pay"];
+    assert!(pay.get("post").is_some() && pay.get("get").is_none());
+    assert!(
+        pay["post"]["requestBody"]["content"]["application/json"]["schema"]["properties"]
+            .get("invoice_str")
+            .is_some()
+    );
+    assert!(openapi["paths"]["/v1/getinfo"].get("get").is_some());
+
+    let (status, err) = http_call(&node1, "GET", "/v1/getinfo", None, None)?;
+    assert_eq!(status, 401, "{err}");
+    let (status, info) = http_call(&node1, "GET", "/v1/getinfo", Some(&token1), None)?;
+    assert_eq!(status, 200);
+    assert_eq!(info["node_id"], node1.info.node_id);
+    let (status, _) = http_call(&node1, "GET", "/v1/pay", Some(&token1), None)?;
+    assert_eq!(status, 405);
+
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101)?;
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+    let open = json::to_value(request::OpenChannel {
+        node_id: node2.info.node_id.clone(),
+        amount: 1_000_000,
+        public: true,
+        addr: Some("127.0.0.1".to_owned()),
+        port: Some(node2.port),
+    })?;
+    let (status, response) = http_call(
+        &node1,
+        "POST",
+        "/v1/fundchannel",
+        Some(&token1),
+        Some(&open),
+    )?;
+    assert_eq!(status, 200, "{response}");
+    assert!(response.get("tx").is_some());
+
+    wait!(|| {
+        node2.fund_wallet(6).unwrap();
+        for (node, token) in [(&node1, &token1), (&node2, &token2)] {
+            let (_, channels) = http_call(node, "GET", "/v1/channels", Some(token), None).unwrap();
+            if channels["channels"][0]["ready"] != true {
+                return Err(());
+            }
+        }
+        Ok(())
+    });
+
+    let generate = json::to_value(request::GenerateInvoice {
+        description: "paid over http".to_owned(),
+        amount_msat: Some(100_000_000),
+        expiring_in: None,
+        expiry_unix: None,
+        no_expiry: false,
+        label: None,
+        fallback: None,
+        metadata: None,
+    })?;
+    let (status, invoice) = http_call(
+        &node2,
+        "POST",
+        "/v1/invoice",
+        Some(&token2),
+        Some(&generate),
+    )?;
+    assert_eq!(status, 200, "{invoice}");
+    let invoice: response::Invoice = json::from_value(invoice)?;
+
+    // the payment goes through the raw JSON RPC 2.0 endpoint
+    let pay = json::json!({
+        "jsonrpc": "2.0",
+        "id": "pay/1",
+        "method": "pay",
+        "params": { "invoice_str": invoice.bolt11 },
+    });
+    let (status, response) = http_call(&node1, "POST", "/rpc", Some(&token1), Some(&pay))?;
+    assert_eq!(status, 200);
+    assert_eq!(response["id"], "pay/1");
+    assert!(response["error"].is_null(), "{response}");
+    assert_eq!(response["result"]["payment_hash"], invoice.payment_hash);
+
+    wait!(|| {
+        let query = format!("/v1/isinvoicepaid?invoice_str={}", invoice.bolt11);
+        let (_, status) = http_call(&node2, "GET", &query, Some(&token2), None).unwrap();
+        if status["state"] != json::json!(response::InboundPaymentState::Paid) {
+            return Err(());
+        }
+        assert_eq!(status["amount_received_msat"], 100_000_000);
+        Ok(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn stream_events_over_websocket_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.http_bind = Some("127.0.0.1:0".parse().unwrap());
+    })?;
+    let token: response::MintToken = node2.lampod().call(
+        "minttoken",
+        request::MintToken {
+            scope: "readonly".to_owned(),
+        },
+    )?;
+    let token = token.token;
+    assert!(WsEvents::connect(&node2, "1:readonly:00", None).is_err());
+    let mut stream = WsEvents::connect(&node2, &token, None)?;
+
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101)?;
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+    let _: json::Value = node1.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: node2.info.node_id.clone(),
+            amount: 1_000_000,
+            public: true,
+            addr: Some("127.0.0.1".to_owned()),
+            port: Some(node2.port),
+        },
+    )?;
+    let _ = node1.fund_wallet(6)?;
+    let ready = stream.wait_for("channel_ready")?;
+    assert_eq!(ready["payload"]["node_id"], node1.info.node_id);
+
+    wait!(|| {
+        let channels: response::Channels =
+            node1.lampod().call("channels", json::json!({})).unwrap();
+        if channels.channels.iter().any(|channel| channel.ready) {
+            return Ok(());
+        }
+        node1.fund_wallet(1).unwrap();
+        Err(())
+    });
+    let invoice: response::Invoice = node2.lampod().call(
+        "invoice",
+        request::GenerateInvoice {
+            description: "streamed over websocket".to_owned(),
+            amount_msat: Some(100_000_000),
+            expiring_in: None,
+            expiry_unix: None,
+            no_expiry: false,
+            label: None,
+            fallback: None,
+            metadata: None,
+        },
+    )?;
+    let _: response::PayResult = node1.lampod().call(
+        "pay",
+        request::Pay {
+            invoice_str: invoice.bolt11.clone(),
+            amount: None,
+            custom_tlvs: Vec::new(),
+            timeout_secs: None,
+            max_total_cltv_expiry_delta: None,
+        },
+    )?;
+    let received = stream.wait_for("payment_received")?;
+    assert_eq!(received["payload"]["payment_hash"], invoice.payment_hash);
+    assert_eq!(received["payload"]["amount_msat"], 100_000_000);
+
+    // a client that reconnects replays the events that it missed
+    let mut replay = WsEvents::connect(&node2, &token, Some(0))?;
+    let replayed = replay.wait_for("channel_ready")?;
+    assert_eq!(replayed["id"], ready["id"]);
+    Ok(())
+}
+
+#[test]
+pub fn deliver_events_to_webhooks_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    // the first attempt fails, so the delivery is retried
+    let webhook = MockWebhook::start(vec![500])?;
+    let filtered = MockWebhook::start(Vec::new())?;
+    let node1 = LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.webhooks = vec![
+            WebhookConf {
+                url: webhook.url(),
+                secret: "s3cret".to_owned(),
+                events: vec!["peer_connected".to_owned()],
+            },
+            WebhookConf {
+                url: filtered.url(),
+                secret: "s3cret".to_owned(),
+                events: vec!["payment_received".to_owned()],
+            },
+        ];
+    })?;
+    let node2 = LampoTesting::new(btc.clone())?;
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
+
+    wait!(|| {
+        if webhook.requests().len() < 2 {
+            return Err(());
+        }
+        Ok(())
+    });
+    let requests = webhook.requests();
+    assert_eq!(requests.len(), 2);
+    // the retry is the same delivery
+    assert_eq!(
+        requests[0].header("X-Lampo-Delivery"),
+        requests[1].header("X-Lampo-Delivery")
+    );
+    let request = &requests[1];
+    let event: json::Value = json::from_str(&request.body)?;
+    assert_eq!(event["type"], "peer_connected");
+    assert_eq!(event["payload"]["node_id"], node2.info.node_id);
+
+    let mut engine = HmacEngine::<Sha256>::new(b"s3cret");
+    engine.input(request.body.as_bytes());
+    let expected = format!("sha256={}", Hmac::<Sha256>::from_engine(engine));
+    assert_eq!(request.header("X-Lampo-Signature"), Some(expected.as_str()));
+
+    let deliveries: response::WebhookDeliveries = node1.lampod().call(
+        "listwebhookdeliveries",
+        request::ListWebhookDeliveries {
+            url: Some(webhook.url()),
+        },
+    )?;
+    assert_eq!(deliveries.deliveries.len(), 1);
+    let delivery = &deliveries.deliveries[0];
+    assert_eq!(delivery.status, response::DeliveryStatus::Delivered);
+    assert_eq!(delivery.attempts, 2);
+    assert_eq!(delivery.status_code, Some(200));
+    assert_eq!(delivery.event_id, event["id"]);
+
+    // the events that do not match the filter are not sent
+    assert!(filtered.requests().is_empty());
+    let deliveries: response::WebhookDeliveries = node1.lampod().call(
+        "listwebhookdeliveries",
+        request::ListWebhookDeliveries {
+            url: Some(filtered.url()),
+        },
+    )?;
+    assert!(deliveries.deliveries.is_empty());
+    Ok(())
+}
+
+#[test]
+pub fn pay_lnurl_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let lnurl = MockLnurl::start(node2.clone())?;
+
+    open_channel(&node1, &node2, 1_000_000)?;
+
+    // the amount and the comment are checked before asking an invoice
+    let result: error::Result<json::Value> = node1.lampod().call(
+        "paylnurl",
+        request::PayLnurl {
+            lnurl: lnurl.address(),
+            amount_msat: Msat::from_msat(MockLnurl::MAX_SENDABLE_MSAT + 1),
+            comment: None,
+            timeout_secs: None,
+        },
+    );
+    let err = rpc_error(result.expect_err("the amount is too big")).unwrap();
+    assert_eq!(err.code, -32602, "{err:?}");
+    let result: error::Result<json::Value> = node1.lampod().call(
+        "paylnurl",
+        request::PayLnurl {
+            lnurl: lnurl.address(),
+            amount_msat: Msat::from_msat(100_000_000),
+            comment: Some("x".repeat(MockLnurl::COMMENT_ALLOWED + 1)),
+            timeout_secs: None,
+        },
+    );
+    let err = rpc_error(result.expect_err("the comment is too long")).unwrap();
+    assert_eq!(err.code, -32602, "{err:?}");
+
+    let pay: response::PayResult = node1.lampod().call(
+        "paylnurl",
+        request::PayLnurl {
+            lnurl: format!("lightning:{}", lnurl.lnurl()),
+            amount_msat: Msat::from_msat(100_000_000),
+            comment: Some("thanks for lampo!".to_owned()),
+            timeout_secs: None,
+        },
+    )?;
+    assert_eq!(lnurl.comments(), vec!["thanks for lampo!".to_owned()]);
+
+    let pay_address: response::PayResult = node1.lampod().call(
+        "paylnurl",
+        request::PayLnurl {
+            lnurl: lnurl.address(),
+            amount_msat: Msat::from_msat(50_000_000),
+            comment: None,
+            timeout_secs: None,
+        },
+    )?;
+    assert_ne!(pay.payment_hash, pay_address.payment_hash);
+
+    let invoices = lnurl.invoices();
+    assert_eq!(invoices.len(), 2);
+    for (invoice, amount_msat) in invoices.into_iter().zip([100_000_000, 50_000_000]) {
+        wait!(|| {
+            let status: response::PaymentStatus = node2
+                .lampod()
+                .call(
+                    "isinvoicepaid",
+                    request::IsInvoicePaid {
+                        invoice_str: invoice.clone(),
+                    },
+                )
+                .unwrap();
+            if status.state != response::InboundPaymentState::Paid {
+                return Err(());
+            }
+            assert_eq!(
+                status.amount_received_msat,
+                Some(Msat::from_msat(amount_msat))
+            );
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+#[test]
+pub fn scrape_metrics_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.http_bind = Some("127.0.0.1:0".parse().unwrap());
+    })?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    open_channel(&node1, &node2, 1_000_000)?;
+
+    let invoice: response::Invoice = node2.lampod().call(
+        "invoice",
+        request::GenerateInvoice {
+            description: "scrape me".to_owned(),
+            amount_msat: Some(100_000_000),
+            expiring_in: None,
+            expiry_unix: None,
+            no_expiry: false,
+            label: None,
+            fallback: None,
+            metadata: None,
+        },
+    )?;
+    let _: response::PayResult = node1.lampod().call(
+        "pay",
+        request::Pay {
+            invoice_str: invoice.bolt11.clone(),
+            amount: None,
+            custom_tlvs: Vec::new(),
+            timeout_secs: None,
+            max_total_cltv_expiry_delta: None,
+        },
+    )?;
+
+    // the metrics are served with the readonly tokens
+    let minted: response::MintToken = node1.lampod().call(
+        "minttoken",
+        request::MintToken {
+            scope: "readonly".to_owned(),
+        },
+    )?;
+    let (status, _) = http_call(&node1, "GET", "/metrics", None, None)?;
+    assert_eq!(status, 401);
+    let mut metrics = HashMap::new();
+    wait!(|| {
+        metrics = scrape_metrics(&node1, &minted.token).unwrap();
+        if metrics.get("lampo_payments_sent_total") != Some(&1.0) {
+            return Err(());
+        }
+        Ok(())
+    });
+    assert_eq!(metrics["lampo_payments_failed_total"], 0.0);
+    assert_eq!(metrics["lampo_forwards_total"], 0.0);
+    assert_eq!(metrics["lampo_channels{state=\"active\"}"], 1.0);
+    assert_eq!(metrics["lampo_channels{state=\"pending\"}"], 0.0);
+    let local_msat = metrics["lampo_channel_balance_msat{side=\"local\"}"];
+    let remote_msat = metrics["lampo_channel_balance_msat{side=\"remote\"}"];
+    assert!(local_msat > 0.0 && local_msat < 900_000_000.0);
+    assert!(remote_msat >= 100_000_000.0, "{remote_msat}");
+    assert_eq!(metrics["lampo_peers"], 1.0);
+    assert!(metrics["lampo_chain_height"] >= 101.0);
+    assert!(metrics.contains_key("lampo_wallet_balance_sat{status=\"confirmed\"}"));
+    assert!(metrics.contains_key("lampo_wallet_balance_sat{status=\"unconfirmed\"}"));
+    assert!(metrics.contains_key("lampo_event_queue_depth"));
+    assert_eq!(
+        metrics["lampo_rpc_duration_seconds_count{method=\"pay\"}"],
+        1.0
+    );
+    assert!(metrics["lampo_rpc_duration_seconds_sum{method=\"pay\"}"] > 0.0);
+    assert_eq!(
+        metrics["lampo_rpc_duration_seconds_bucket{method=\"pay\",le=\"+Inf\"}"],
+        1.0
+    );
+
+    wait!(|| {
+        let metrics: response::Metrics =
+            node2.lampod().call("getmetrics", json::json!({})).unwrap();
+        if !metrics.text.contains("\nlampo_payments_received_total 1\n") {
+            return Err(());
+        }
+        Ok(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn pay_invoice_twice_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    open_channel(&node1, &node2, 1_000_000)?;
+
+    let invoice: response::Invoice = node2.lampod().call(
+        "invoice",
+        request::GenerateInvoice {
+            description: "pay me once".to_owned(),
+            amount_msat: Some(100_000_000),
+            expiring_in: None,
+            expiry_unix: None,
+            no_expiry: false,
+            label: None,
+            fallback: None,
+            metadata: None,
+        },
+    )?;
+    let pay = json::to_value(request::Pay {
+        invoice_str: invoice.bolt11.clone(),
+        amount: None,
+        custom_tlvs: Vec::new(),
+        timeout_secs: None,
+        max_total_cltv_expiry_delta: None,
+    })?;
+    let first: response::PayResult = node1.lampod().call("pay", pay.clone())?;
+    assert!(matches!(first.state, response::PaymentState::Success));
+
+    // the second pay returns the payment that succeeded
+    let second: response::PayResult = node1.lampod().call("pay", pay)?;
+    assert!(matches!(second.state, response::PaymentState::Success));
+    assert_eq!(second.payment_hash, first.payment_hash);
+    assert!(!second.path.is_empty());
+
+    let status: response::PaymentStatus = node2.lampod().call(
+        "isinvoicepaid",
+        request::IsInvoicePaid {
+            invoice_str: invoice.bolt11.clone(),
+        },
+    )?;
+    assert_eq!(
+        status.amount_received_msat,
+        Some(Msat::from_msat(100_000_000))
+    );
+    let metrics: response::Metrics = node1.lampod().call("getmetrics", json::json!({}))?;
+    assert!(metrics.text.contains("\nlampo_payments_sent_total 1\n"));
+    Ok(())
+}
+
+#[test]
+pub fn plugin_methods_and_hooks_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.plugin_dir = Some(format!("{}/../../plugins", env!("CARGO_MANIFEST_DIR")));
+    })?);
+
+    let plugins: response::Plugins = node2.lampod().call("listplugins", json::json!({}))?;
+    let plugin = plugins
+        .plugins
+        .iter()
+        .find(|plugin| plugin.name == "example.py")
+        .unwrap();
+    assert_eq!(plugin.status, response::PluginStatus::Running);
+    assert!(plugin.methods.contains(&"hello".to_owned()));
+    assert_eq!(plugin.hooks, vec!["invoice_payment".to_owned()]);
+    let hello: json::Value = node2
+        .lampod()
+        .call("hello", json::json!({ "name": "lampo" }))?;
+    assert_eq!(hello["greeting"], "hello lampo");
+
+    open_channel(&node1, &node2, 1_000_000)?;
+
+    // the plugin rejects the payment of the invoice
+    let _: json::Value = node2.lampod().call("veto", json::json!({ "veto": true }))?;
+    let invoice: response::Invoice = node2.lampod().call(
+        "invoice",
+        request::GenerateInvoice {
+            description: "vetoed".to_owned(),
+            amount_msat: Some(100_000_000),
+            expiring_in: None,
+            expiry_unix: None,
+            no_expiry: false,
+            label: None,
+            fallback: None,
+            metadata: None,
+        },
+    )?;
+    let pay: response::PayResult = node1.lampod().call(
+        "pay",
+        request::Pay {
+            invoice_str: invoice.bolt11.clone(),
+            amount: None,
+            custom_tlvs: Vec::new(),
+            timeout_secs: None,
+            max_total_cltv_expiry_delta: None,
+        },
+    )?;
+    assert!(matches!(pay.state, response::PaymentState::Faulure));
+    let status: response::PaymentStatus = node2.lampod().call(
+        "isinvoicepaid",
+        request::IsInvoicePaid {
+            invoice_str: invoice.bolt11.clone(),
+        },
+    )?;
+    assert_eq!(status.state, response::InboundPaymentState::Unpaid);
+
+    let _: json::Value = node2
+        .lampod()
+        .call("veto", json::json!({ "veto": false }))?;
+    let invoice: response::Invoice = node2.lampod().call(
+        "invoice",
+        request::GenerateInvoice {
+            description: "accepted".to_owned(),
+            amount_msat: Some(100_000_000),
+            expiring_in: None,
+            expiry_unix: None,
+            no_expiry: false,
+            label: None,
+            fallback: None,
+            metadata: None,
+        },
+    )?;
+    let pay: response::PayResult = node1.lampod().call(
+        "pay",
+        request::Pay {
+            invoice_str: invoice.bolt11.clone(),
+            amount: None,
+            custom_tlvs: Vec::new(),
+            timeout_secs: None,
+            max_total_cltv_expiry_delta: None,
+        },
+    )?;
+    assert!(matches!(pay.state, response::PaymentState::Success));
+    // the plugin follows the `payment_received` events
+    wait!(|| {
+        let hello: json::Value = node2.lampod().call("hello", json::json!({})).unwrap();
+        if hello["payments_received"] == 1 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    // a plugin that crashes does not take the node down
+    let crash: error::Result<json::Value> = node2.lampod().call("crash", json::json!({}));
+    assert!(crash.is_err());
+    wait!(|| {
+        let plugins: response::Plugins =
+            node2.lampod().call("listplugins", json::json!({})).unwrap();
+        match plugins.plugins.first() {
+            Some(plugin) if plugin.status == response::PluginStatus::Crashed => Ok(()),
+            _ => Err(()),
+        }
+    });
+    let _: response::GetInfo = node2.lampod().call("getinfo", json::json!({}))?;
+    Ok(())
+}
+
+#[test]
+pub fn bip21_uri_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    let uri: response::Bip21Uri = node2.lampod().call(
+        "getbip21uri",
+        request::Bip21Uri {
+            amount_sat: None,
+            label: None,
+        },
+    )?;
+    assert_eq!(uri.uri, format!("bitcoin:{}", uri.address));
+    // without channels there is no invoice
+    let uri: response::Bip21Uri = node2.lampod().call(
+        "getbip21uri",
+        request::Bip21Uri {
+            amount_sat: Some(100_000),
+            label: Some("coffee & cake".to_owned()),
+        },
+    )?;
+    let address = &uri.address;
+    assert_eq!(
+        uri.uri,
+        format!("bitcoin:{address}?amount=0.001&label=coffee%20%26%20cake")
+    );
+    assert!(uri.bolt11.is_none());
+
+    open_channel(&node1, &node2, 1_000_000)?;
+
+    let uri: response::Bip21Uri = node2.lampod().call(
+        "getbip21uri",
+        request::Bip21Uri {
+            amount_sat: Some(100_000),
+            label: Some("coffee".to_owned()),
+        },
+    )?;
+    let bolt11 = uri.bolt11.clone().unwrap();
+    let address = &uri.address;
+    assert_eq!(
+        uri.uri,
+        format!("bitcoin:{address}?amount=0.001&label=coffee&lightning={bolt11}")
+    );
+    let pay: response::PayResult = node1.lampod().call(
+        "pay",
+        request::Pay {
+            invoice_str: bolt11.clone(),
+            amount: None,
+            custom_tlvs: Vec::new(),
+            timeout_secs: None,
+            max_total_cltv_expiry_delta: None,
+        },
+    )?;
+    assert!(matches!(pay.state, response::PaymentState::Success));
+    let status: response::PaymentStatus = node2.lampod().call(
+        "isinvoicepaid",
+        request::IsInvoicePaid {
+            invoice_str: bolt11,
+        },
+    )?;
+    assert_eq!(
+        status.amount_received_msat,
+        Some(Msat::from_msat(100_000_000))
+    );
+    Ok(())
+}
+
+#[test]
+pub fn runtime_log_level_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+
+    // the records of a call to `feerates` with the `marker`
+    let records_of_call = |marker: &str| -> error::Result<usize> {
+        let _: json::Value = node
+            .lampod()
+            .call("feerates", json::json!({ "marker": marker }))?;
+        let log: response::Log = node.lampod().call(
+            "getlog",
+            request::GetLog {
+                level: Some("info".to_owned()),
+                limit: None,
+            },
+        )?;
+        Ok(log
+            .records
+            .iter()
+            .filter(|record| record.message.contains(marker))
+            .count())
+    };
+    let set_level = |level: &str| -> error::Result<response::LogLevels> {
+        node.lampod().call(
+            "setloglevel",
+            request::SetLogLevel {
+                target: Some("lampod::jsonrpc::onchain".to_owned()),
+                level: level.to_owned(),
+            },
+        )
+    };
+
+    let levels = set_level("info")?;
+    assert_eq!(
+        levels.targets.get("lampod::jsonrpc::onchain"),
+        Some(&"info".to_owned())
+    );
+    assert_eq!(records_of_call("log-enabled")?, 1);
+    let _ = set_level("off")?;
+    assert_eq!(records_of_call("log-disabled")?, 0);
+    assert!(set_level("verbose").is_err());
+    Ok(())
+}
+
+#[test]
+pub fn unified_receive_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+
+    let receive: response::UnifiedReceive = node.lampod().call(
+        "unifiedreceive",
+        request::UnifiedReceive {
+            amount_msat: 1_500,
+            description: "one QR to pay me".to_owned(),
+            expiring_in: Some(600),
+        },
+    )?;
+    // the on chain amount is rounded up to the satoshi
+    let address = &receive.address;
+    let bolt11 = &receive.bolt11;
+    assert_eq!(
+        receive.uri,
+        format!("bitcoin:{address}?amount=0.00000002&lightning={bolt11}")
+    );
+    let invoice = lampo_common::ldk::invoice::Bolt11Invoice::from_str(bolt11).unwrap();
+    assert_eq!(invoice.amount_milli_satoshis(), Some(1_500));
+    assert_eq!(invoice.payment_hash().to_string(), receive.payment_hash);
+    assert_eq!(invoice.expiry_time(), Duration::from_secs(600));
+    let status: response::PaymentStatus = node.lampod().call(
+        "isinvoicepaid",
+        request::IsInvoicePaid {
+            invoice_str: bolt11.clone(),
+        },
+    )?;
+    assert_eq!(status.state, response::InboundPaymentState::Unpaid);
+
+    let zero: error::Result<response::UnifiedReceive> = node.lampod().call(
+        "unifiedreceive",
+        request::UnifiedReceive {
+            amount_msat: 0,
+            description: "nothing".to_owned(),
+            expiring_in: None,
+        },
+    );
+    assert!(zero.is_err());
+    Ok(())
+}
+
+#[test]
+pub fn generate_invoice_without_expiry_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let invoice: response::Invoice = node.lampod().call(
+        "invoice",
+        request::GenerateInvoice {
+            description: "static tip code".to_owned(),
+            amount_msat: None,
+            expiring_in: None,
+            expiry_unix: None,
+            no_expiry: true,
+            label: None,
+            fallback: None,
+            metadata: None,
+        },
+    )?;
+    assert!(invoice.expiry_unix >= now + u32::MAX as u64);
+
+    let invoice: error::Result<response::Invoice> = node.lampod().call(
+        "invoice",
+        request::GenerateInvoice {
+            description: "static tip code".to_owned(),
+            amount_msat: None,
+            expiring_in: Some(60),
+            expiry_unix: None,
+            no_expiry: true,
+            label: None,
+            fallback: None,
+            metadata: None,
+        },
+    );
+    assert!(invoice.is_err());
+    Ok(())
+}
+
+#[test]
+pub fn wait_events_of_a_payment_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    open_channel(&node1, &node2, 1_000_000)?;
+
+    // the events before the payment are already notified
+    let seen: response::NotifiedEvents = node2.lampod().call(
+        "waitevent",
+        request::WaitEvent {
+            last_id: None,
+            kinds: Vec::new(),
+            timeout_secs: Some(0),
+        },
+    )?;
+    assert!(seen
+        .events
+        .iter()
+        .any(|event| event.kind == "peer_connected"));
+
+    let invoice: response::Invoice = node2.lampod().call(
+        "invoice",
+        request::GenerateInvoice {
+            description: "notify the payment to the frontends".to_owned(),
+            amount_msat: Some(100_000_000),
+            expiring_in: None,
+            expiry_unix: None,
+            no_expiry: false,
+            label: None,
+            fallback: None,
+            metadata: None,
+        },
+    )?;
+    let _: response::PayResult = node1.lampod().call(
+        "pay",
+        request::Pay {
+            invoice_str: invoice.bolt11,
+            amount: None,
+            custom_tlvs: Vec::new(),
+            timeout_secs: None,
+            max_total_cltv_expiry_delta: None,
+        },
+    )?;
+
+    let mut last_id = seen.last_id;
+    let mut received = Vec::new();
+    wait!(|| {
+        let result: response::NotifiedEvents = node2
+            .lampod()
+            .call(
+                "waitevent",
+                request::WaitEvent {
+                    last_id: Some(last_id),
+                    kinds: Vec::new(),
+                    timeout_secs: Some(5),
+                },
+            )
+            .unwrap();
+        last_id = result.last_id;
+        received.extend(result.events);
+        if received
+            .iter()
+            .any(|event| event.kind == "payment_received")
+        {
+            return Ok(());
+        }
+        Err(())
+    });
+    for (idx, event) in received.iter().enumerate() {
+        assert_eq!(event.id, seen.last_id + 1 + idx as u64, "{:?}", received);
+    }
+    let payment = received
+        .iter()
+        .find(|event| event.kind == "payment_received")
+        .unwrap();
+    assert_eq!(payment.data["payment_hash"], invoice.payment_hash);
+    assert_eq!(payment.data["amount_msat"], 100_000_000);
+
+    let sent: response::NotifiedEvents = node1.lampod().call(
+        "waitevent",
+        request::WaitEvent {
+            last_id: None,
+            kinds: vec!["payment_sent".to_owned()],
+            timeout_secs: Some(5),
+        },
+    )?;
+    assert_eq!(sent.events.len(), 1);
+    assert_eq!(sent.events[0].data["payment_hash"], invoice.payment_hash);
+    Ok(())
+}
+
+#[test]
+pub fn pay_hold_invoice_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    open_channel(&node1, &node2, 1_000_000)?;
+
+    let preimage = [1u8; 32];
+    let payment_hash = Sha256::hash(&preimage).to_string();
+    let invoice: response::HoldInvoice = node2.lampod().call(
+        "holdinvoice",
+        request::GenerateHoldInvoice {
+            payment_hash: payment_hash.clone(),
+            description: "hold invoice between lampo nodes".to_owned(),
+            amount_msat: Some(100_000_000),
+            expiring_in: None,
+        },
+    )?;
+    assert_eq!(invoice.state, response::HoldInvoiceState::Open);
+
+    let node2_events = node2.lampod().events();
+    let payer = node1.clone();
+    let bolt11 = invoice.bolt11.clone();
+    let pay = std::thread::spawn(move || -> error::Result<response::PayResult> {
+        payer.lampod().call(
+            "pay",
+            request::Pay {
+                invoice_str: bolt11,
+                amount: None,
+                custom_tlvs: Vec::new(),
+                timeout_secs: None,
+                max_total_cltv_expiry_delta: None,
+            },
+        )
+    });
+
+    wait!(|| {
+        while let Ok(event) = node2_events.recv_timeout(Duration::from_millis(10)) {
+            if let Event::Lightning(LightningEvent::HoldInvoiceAccepted {
+                payment_hash: hash,
+                ..
+            }) = event
+            {
+                if hash == payment_hash {
+                    return Ok(());
+                }
+            }
+        }
+        Err(())
+    });
+
+    // the held HTLC is in flight until the invoice is settled
+    let htlcs: response::Htlcs = node2
+        .lampod()
+        .call("listhtlcs", request::ListHtlcs { channel: None })?;
+    let htlc = htlcs
+        .htlcs
+        .iter()
+        .find(|htlc| htlc.payment_hash == payment_hash)
+        .expect("the held htlc is not listed");
+    assert_eq!(htlc.direction, response::HtlcDirection::Inbound);
+    assert_eq!(htlc.amount_msat, 100_000_000);
+    assert!(htlc.deadline_blocks > 0);
+
+    let settled: response::HoldInvoice = node2.lampod().call(
+        "settleinvoice",
+        request::SettleHoldInvoice {
+            preimage: "01".repeat(32),
+        },
+    )?;
+    assert_eq!(settled.state, response::HoldInvoiceState::Settled);
+    assert_eq!(
+        settled.amount_received_msat,
+        Some(Msat::from_msat(100_000_000))
+    );
+
+    let pay = pay.join().unwrap()?;
+    log::info!(target: &node1.info.node_id, "hold invoice paid `{:?}`", pay);
+
+    let pays: response::Pays = node1.lampod().call(
+        "listpays",
+        request::ListPays {
+            payment_hash: Some(payment_hash.clone()),
+        },
+    )?;
+    assert_eq!(pays.pays.len(), 1, "{pays:?}");
+    assert_eq!(pays.pays[0].status, response::PayStatus::Complete);
+    assert_eq!(pays.pays[0].amount_msat, Some(100_000_000));
+
+    wait!(|| {
+        let htlcs: response::Htlcs = node2
+            .lampod()
+            .call("listhtlcs", request::ListHtlcs { channel: None })
+            .unwrap();
+        if htlcs.htlcs.is_empty() {
+            return Ok(());
+        }
+        Err(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn cancel_hold_invoice_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    open_channel(&node1, &node2, 1_000_000)?;
+
+    let preimage = [2u8; 32];
+    let payment_hash = Sha256::hash(&preimage).to_string();
+    let invoice: response::HoldInvoice = node2.lampod().call(
+        "holdinvoice",
+        request::GenerateHoldInvoice {
+            payment_hash: payment_hash.clone(),
+            description: "hold invoice canceled by the payee".to_owned(),
+            amount_msat: Some(100_000_000),
+            expiring_in: None,
+        },
+    )?;
+
+    let node2_events = node2.lampod().events();
+    let payer = node1.clone();
+    let bolt11 = invoice.bolt11.clone();
+    let pay = std::thread::spawn(move || -> error::Result<response::PayResult> {
+        payer.lampod().call(
+            "pay",
+            request::Pay {
+                invoice_str: bolt11,
+                amount: None,
+                custom_tlvs: Vec::new(),
+                timeout_secs: None,
+                max_total_cltv_expiry_delta: None,
+            },
+        )
+    });
+
+    wait!(|| {
+        while let Ok(event) = node2_events.recv_timeout(Duration::from_millis(10)) {
+            if let Event::Lightning(LightningEvent::HoldInvoiceAccepted {
+                payment_hash: hash,
+                ..
+            }) = event
+            {
+                if hash == payment_hash {
+                    return Ok(());
+                }
+            }
+        }
+        Err(())
+    });
+
+    // the held HTLC is listed on both sides of the channel
+    for (node, direction) in [
+        (&node2, response::HtlcDirection::Inbound),
+        (&node1, response::HtlcDirection::Outbound),
+    ] {
+        let htlcs: response::Htlcs = node
+            .lampod()
+            .call("listhtlcs", request::ListHtlcs { channel: None })?;
+        let htlc = htlcs
+            .htlcs
+            .iter()
+            .find(|htlc| htlc.payment_hash == payment_hash)
+            .expect("the held htlc is not listed");
+        assert_eq!(htlc.direction, direction);
+        assert_eq!(htlc.amount_msat, 100_000_000);
+    }
+
+    let canceled: response::HoldInvoice = node2.lampod().call(
+        "cancelinvoice",
+        request::CancelHoldInvoice {
+            payment_hash: payment_hash.clone(),
+        },
+    )?;
+    assert_eq!(canceled.state, response::HoldInvoiceState::Canceled);
+    assert!(pay.join().unwrap().is_err());
+
+    // once failed back the HTLC is removed from both sides
+    for node in [&node1, &node2] {
+        wait!(|| {
+            let htlcs: response::Htlcs = node
+                .lampod()
+                .call("listhtlcs", request::ListHtlcs { channel: None })
+                .unwrap();
+            if htlcs.htlcs.is_empty() {
+                return Ok(());
+            }
+            Err(())
+        });
+    }
+    Ok(())
+}
+
+#[test]
+pub fn stop_and_restart_mid_payment_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    open_channel(&node1, &node2, 1_000_000)?;
+    let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+    assert_eq!(channels.channels.len(), 1);
+    let channel_id = channels.channels[0].channel_id.clone();
+
+    let preimage = [2u8; 32];
+    let payment_hash = Sha256::hash(&preimage).to_string();
+    let invoice: response::HoldInvoice = node2.lampod().call(
+        "holdinvoice",
+        request::GenerateHoldInvoice {
+            payment_hash: payment_hash.clone(),
+            description: "payment across a restart".to_owned(),
+            amount_msat: Some(100_000_000),
+            expiring_in: None,
+        },
+    )?;
+
+    let node2_events = node2.lampod().events();
+    let payer = node1.lampod();
+    let bolt11 = invoice.bolt11.clone();
+    // the payer is stopped before the payment is completed, so the
+    // result of the call is not checked.
+    let _ = std::thread::spawn(move || -> error::Result<response::PayResult> {
+        payer.call(
+            "pay",
+            request::Pay {
+                invoice_str: bolt11,
+                amount: None,
+                custom_tlvs: Vec::new(),
+                timeout_secs: None,
+                max_total_cltv_expiry_delta: None,
+            },
+        )
+    });
+
+    wait!(|| {
+        while let Ok(event) = node2_events.recv_timeout(Duration::from_millis(10)) {
+            if let Event::Lightning(LightningEvent::HoldInvoiceAccepted {
+                payment_hash: hash,
+                ..
+            }) = event
+            {
+                if hash == payment_hash {
+                    return Ok(());
+                }
+            }
+        }
+        Err(())
+    });
+
+    // stop the payer while the htlc is held by the payee
+    let node1 = node1.restart()?;
+    let events = node1.lampod().events();
+
+    wait!(|| {
+        let channels: response::Channels = node1
+            .lampod()
+            .call("channels", json::json!({}))
+            .unwrap();
+        let restored = channels
+            .channels
+            .iter()
+            .any(|channel| channel.channel_id == channel_id && channel.usable);
+        if restored {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let settled: response::HoldInvoice = node2.lampod().call(
+        "settleinvoice",
+        request::SettleHoldInvoice {
+            preimage: "02".repeat(32),
+        },
+    )?;
+    assert_eq!(settled.state, response::HoldInvoiceState::Settled);
+
+    // the restarted node learns the preimage of the payment sent before the stop
+    wait!(|| {
+        while let Ok(event) = events.recv_timeout(Duration::from_millis(10)) {
+            if let Event::Lightning(LightningEvent::PaymentEvent {
+                state: response::PaymentState::Success,
+                payment_hash: Some(hash),
+                ..
+            }) = event
+            {
+                if hash == payment_hash {
+                    return Ok(());
+                }
+            }
+        }
+        Err(())
+    });
+    Ok(())
+}
+
+#[test]
+pub fn cancel_and_timeout_payment_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    open_channel(&node1, &node2, 1_000_000)?;
+
+    let node2_events = node2.lampod().events();
+    // a payment for an hold invoice is in flight until the invoice is
+    // settled, so it can be canceled or it can go over the timeout.
+    for (preimage, timeout_secs) in [([2u8; 32], None), ([3u8; 32], Some(2))] {
+        let payment_hash = Sha256::hash(&preimage).to_string();
+        let invoice: response::HoldInvoice = node2.lampod().call(
+            "holdinvoice",
+            request::GenerateHoldInvoice {
+                payment_hash: payment_hash.clone(),
+                description: "payment that is never settled".to_owned(),
+                amount_msat: Some(100_000_000),
+                expiring_in: None,
+            },
+        )?;
+
+        let payer = node1.clone();
+        let bolt11 = invoice.bolt11.clone();
+        let pay = std::thread::spawn(move || -> error::Result<response::PayResult> {
+            payer.lampod().call(
+                "pay",
+                request::Pay {
+                    invoice_str: bolt11,
+                    amount: None,
+                    custom_tlvs: Vec::new(),
+                    timeout_secs,
+                    max_total_cltv_expiry_delta: None,
+                },
+            )
+        });
+
+        wait!(|| {
+            while let Ok(event) = node2_events.recv_timeout(Duration::from_millis(10)) {
+                if let Event::Lightning(LightningEvent::HoldInvoiceAccepted {
+                    payment_hash: hash,
+                    ..
+                }) = event
+                {
+                    if hash == payment_hash {
+                        return Ok(());
+                    }
+                }
+            }
+            Err(())
+        });
+
+        if timeout_secs.is_none() {
+            let _: json::Value = node1.lampod().call(
+                "cancelpayment",
+                request::CancelPayment {
+                    invoice_str: invoice.bolt11.clone(),
+                },
+            )?;
+        }
+        // SAFETY: the thread does not panic.
+        let pay = pay.join().unwrap();
+        assert!(pay.is_err(), "the payment should be abandoned");
+
+        // the payment is not in flight anymore
+        let cancel: error::Result<json::Value> = node1.lampod().call(
+            "cancelpayment",
+            request::CancelPayment {
+                invoice_str: invoice.bolt11,
+            },
+        );
+        assert!(cancel.is_err());
+    }
+    Ok(())
+}
+
+#[test]
+pub fn pay_offer_simple_case_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101)?;
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let response: json::Value = node1
+        .lampod()
+        .call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: node2.info.node_id.clone(),
+                amount: 1_000_000,
+                public: true,
+                addr: Some("127.0.0.1".to_owned()),
+                port: Some(node2.port),
+            },
+        )
+        .unwrap();
+    assert!(response.get("tx").is_some());
+
+    wait!(|| {
+        while let Ok(event) = events.recv_timeout(Duration::from_millis(10)) {
+            node2.fund_wallet(6).unwrap();
+            if let Event::Lightning(LightningEvent::ChannelReady {
+                counterparty_node_id,
+                ..
+            }) = event
+            {
+                if counterparty_node_id.to_string() == node1.info.node_id {
+                    return Err(());
+                }
+                return Ok(());
+            };
+            // check if lampo see the channel
+            let channels: response::Channels =
+                node2.lampod().call("channels", json::json!({})).unwrap();
+            if channels.channels.is_empty() {
+                return Err(());
+            }
+
+            if !channels.channels.first().unwrap().ready {
+                return Err(());
+            }
+
+            let channels: response::Channels =
+                node1.lampod().call("channels", json::json!({})).unwrap();
+
+            if channels.channels.is_empty() {
+                return Err(());
+            }
+
+            if channels.channels.first().unwrap().ready {
+                return Ok(());
+            }
+        }
+        node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    let offer: response::Offer = node2.lampod().call(
+        "offer",
+        request::GenerateOffer {
+            description: Some("making sure that we can work betwen lampo version".to_owned()),
+            amount_msat: Some(100_000_000),
+        },
+    )?;
+
+    log::info!(target: &node2.info.node_id, "offer generated `{:?}`", offer);
+
+    let pay: response::PayResult = node1.lampod().call(
+        "pay",
+        request::Pay {
+            invoice_str: offer.bolt12,
+            amount: None,
+            custom_tlvs: Vec::new(),
+            timeout_secs: None,
+            max_total_cltv_expiry_delta: None,
+        },
+    )?;
+    log::info!(target: &node1.info.node_id, "payment made `{:?}`", pay);
+    Ok(())
+}
+
+#[test]
+pub fn pay_offer_minimal_offer() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    let events = node1.lampod().events();
+    let _ = node1.fund_wallet(101)?;
+    wait!(|| {
+        let Ok(Event::OnChain(OnChainEvent::NewBestBlock((_, height)))) =
+            events.recv_timeout(Duration::from_millis(100))
+        else {
+            return Err(());
+        };
+        if height.to_consensus_u32() == 101 {
+            return Ok(());
+        }
+        Err(())
+    });
+
+    let response: json::Value = node1
+        .lampod()
+        .call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: node2.info.node_id.clone(),
+                amount: 1_000_000,
+                public: true,
+                addr: Some("127.0.0.1".to_owned()),
+                port: Some(node2.port),
+            },
+        )
+        .unwrap();
+    assert!(response.get("tx").is_some());
+
+    wait!(|| {
+        while let Ok(event) = events.recv_timeout(Duration::from_millis(10)) {
+            node2.fund_wallet(6).unwrap();
+            if let Event::Lightning(LightningEvent::ChannelReady {
+                counterparty_node_id,
+                ..
+            }) = event
+            {
+                if counterparty_node_id.to_string() == node1.info.node_id {
+                    return Err(());
+                }
+                return Ok(());
+            };
+            // check if lampo see the channel
+            let channels: response::Channels =
+                node2.lampod().call("channels", json::json!({})).unwrap();
+            if channels.channels.is_empty() {
+                return Err(());
+            }
+
+            if !channels.channels.first().unwrap().ready {
+                return Err(());
+            }
+
+            let channels: response::Channels =
+                node1.lampod().call("channels", json::json!({})).unwrap();
+
+            if channels.channels.is_empty() {
+                return Err(());
+            }
+
+            if channels.channels.first().unwrap().ready {
+                return Ok(());
+            }
+        }
+        node2.fund_wallet(6).unwrap();
+        Err(())
+    });
+
+    let offer: response::Offer = node2.lampod().call(
+        "offer",
+        request::GenerateOffer {
+            description: None,
+            amount_msat: None,
+        },
+    )?;
+
+    log::info!(target: &node2.info.node_id, "offer generated `{:?}`", offer);
+
+    let pay: response::PayResult = node1.lampod().call(
+        "pay",
+        request::Pay {
+            invoice_str: offer.bolt12,
+            amount: Some(Msat::from_msat(100_000_000)),
+            custom_tlvs: Vec::new(),
+            timeout_secs: None,
+            max_total_cltv_expiry_delta: None,
+        },
+    )?;
+    log::info!(target: &node1.info.node_id, "payment made `{:?}`", pay);
+    Ok(())
+}
+
+#[test]
+pub fn forward_through_disabled_channel_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node3 = Arc::new(LampoTesting::new(btc.clone())?);
+
+    let _ = node1.fund_wallet(101)?;
+    let _ = node2.fund_wallet(101)?;
+
+    // node1 -> node2 -> node3 where the last channel is private, so
+    // node3 will add the route hint inside the invoice.
+    for (from, to, public) in [(&node1, &node2, true), (&node2, &node3, false)] {
+        let response: json::Value = from
+            .lampod()
+            .call(
+                "fundchannel",
+                request::OpenChannel {
+                    node_id: to.info.node_id.clone(),
+                    amount: 1_000_000,
+                    public,
+                    addr: Some("127.0.0.1".to_owned()),
+                    port: Some(to.port),
+                },
+            )
+            .unwrap();
+        assert!(response.get("tx").is_some());
+    }
+
+    wait!(|| {
+        node3.fund_wallet(6).unwrap();
+        for node in [&node1, &node3] {
+            let channels: response::Channels =
+                node.lampod().call("channels", json::json!({})).unwrap();
+            if channels.channels.is_empty() || !channels.channels.iter().all(|c| c.ready) {
+                return Err(());
+            }
+        }
+        Ok(())
+    });
+
+    let channels: response::Channels = node3.lampod().call("channels", json::json!({}))?;
+    let channel_id = channels.channels.first().unwrap().channel_id.clone();
+    let channel: response::Channel = node2.lampod().call(
+        "setchannel",
+        request::SetChannel {
+            id: channel_id.clone(),
+            enabled: false,
+        },
+    )?;
+    assert!(!channel.enabled);
+
+    let channels: response::Channels = node2.lampod().call("channels", json::json!({}))?;
+    let channel = channels
+        .channels
+        .iter()
+        .find(|channel| channel.channel_id == channel_id)
+        .unwrap();
+    assert!(!channel.enabled);
+
+    // the forward from node1 must be rejected by node2
+    let invoice: response::Invoice = node3.lampod().call(
+        "invoice",
+        request::GenerateInvoice {
+            description: "forward through a disabled channel".to_owned(),
+            amount_msat: Some(10_000_000),
+            expiring_in: None,
+            expiry_unix: None,
+            no_expiry: false,
+            label: None,
+            fallback: None,
+            metadata: None,
+        },
+    )?;
+    let pay: error::Result<response::PayResult> = node1.lampod().call(
+        "pay",
+        request::Pay {
+            invoice_str: invoice.bolt11,
+            amount: None,
+            custom_tlvs: Vec::new(),
+            timeout_secs: None,
+            max_total_cltv_expiry_delta: None,
+        },
+    );
+    assert!(pay.is_err(), "the payment is forwarded: {:?}", pay);
+
+    // but node2 can still pay node3 directly
+    let invoice: response::Invoice = node3.lampod().call(
+        "invoice",
+        request::GenerateInvoice {
+            description: "direct payment on a disabled channel".to_owned(),
+            amount_msat: Some(10_000_000),
+            expiring_in: None,
+            expiry_unix: None,
+            no_expiry: false,
+            label: None,
+            fallback: None,
+            metadata: None,
+        },
+    )?;
+    let pay: response::PayResult = node2.lampod().call(
+        "pay",
+        request::Pay {
+            invoice_str: invoice.bolt11,
+            amount: None,
+            custom_tlvs: Vec::new(),
+            timeout_secs: None,
+            max_total_cltv_expiry_delta: None,
+        },
+    )?;
+    log::info!(target: &node2.info.node_id, "payment made `{:?}`", pay);
+    Ok(())
+}
+
+#[test]
+pub fn pay_invoice_with_scid_alias_zero_conf() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node1_id: NodeId = node1.info.node_id.parse()?;
+    let node2 = Arc::new(LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.zero_conf_peers.push(node1_id);
+    })?);
+    let _ = node1.fund_wallet(101)?;
+
+    let response: json::Value = node1
+        .lampod()
+        .call(
+            "fundchannel",
+            request::OpenChannel {
+                node_id: node2.info.node_id.clone(),
+                amount: 1_000_000,
+                public: false,
+                addr: Some("127.0.0.1".to_owned()),
+                port: Some(node2.port),
+            },
+        )
+        .unwrap();
+    assert!(response.get("tx").is_some());
+
+    // the channel is ready without mining any block
+    wait!(|| {
+        let channels: response::Channels =
+            node2.lampod().call("channels", json::json!({})).unwrap();
+        match channels.channels.first() {
+            Some(channel) if channel.ready => Ok(()),
+            _ => Err(()),
+        }
+    });
+    let channels: response::Channels = node2.lampod().call("channels", json::json!({}))?;
+    let channel = channels.channels.first().unwrap();
+    assert!(channel.short_channel_id.is_none());
+    let alias = channel.inbound_scid_alias.unwrap();
+
+    let invoice: response::Invoice = node2.lampod().call(
+        "invoice",
+        request::GenerateInvoice {
+            description: "pay a zero conf channel".to_owned(),
+            amount_msat: Some(10_000_000),
+            expiring_in: None,
+            expiry_unix: None,
+            no_expiry: false,
+            label: None,
+            fallback: None,
+            metadata: None,
+        },
+    )?;
+    let decoded: response::InvoiceInfo = node2.lampod().call(
+        "decode_invoice",
+        request::DecodeInvoice {
+            invoice_str: invoice.bolt11.clone(),
+            amount: None,
+        },
+    )?;
+    assert_eq!(
+        decoded.hints,
+        vec![format!("{}:{alias}", node1.info.node_id)]
+    );
+
+    let pay: response::PayResult = node1.lampod().call(
+        "pay",
+        request::Pay {
+            invoice_str: invoice.bolt11,
+            amount: None,
+            custom_tlvs: Vec::new(),
+            timeout_secs: None,
+            max_total_cltv_expiry_delta: None,
+        },
+    )?;
+    log::info!(target: &node1.info.node_id, "payment made `{:?}`", pay);
+    Ok(())
+}
+
+#[test]
+pub fn reload_fee_defaults_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+    let conf_path = format!("{}/lampo.conf", node.daemon().conf().path());
+    let write_conf = |options: &str| {
+        std::fs::write(
+            &conf_path,
+            format!("network=regtest\nport={}\n{options}\n", node.port),
+        )
+    };
+
+    // a typo is rejected with its line, and nothing is applied
+    write_conf("min-fee-rate-sat-kv=1000")?;
+    let reload: error::Result<response::Reload> = node.lampod().call("reload", json::json!({}));
+    let err = rpc_error(reload.expect_err("the option is unknown")).unwrap();
+    assert!(
+        err.message
+            .contains("lampo.conf:3: unknown option `min-fee-rate-sat-kv`"),
+        "{err:?}"
+    );
+    let fee_rates: response::FeeRates = node.lampod().call("feerates", json::json!({}))?;
+    assert_eq!(fee_rates.min_sat_per_kw, 253);
+
+    write_conf("min-fee-rate-sat-kw=1000\nmax-fee-rate-sat-kw=2000")?;
+    let reload: response::Reload = node.lampod().call("reload", json::json!({}))?;
+    assert_eq!(
+        reload.applied,
+        vec!["min-fee-rate-sat-kw".to_owned(), "max-fee-rate-sat-kw".to_owned()]
+    );
+    assert!(!reload
+        .restart_required
+        .iter()
+        .any(|option| option.contains("fee-rate")));
+    let fee_rates: response::FeeRates = node.lampod().call("feerates", json::json!({}))?;
+    assert_eq!(fee_rates.min_sat_per_kw, 1000);
+    assert_eq!(fee_rates.max_sat_per_kw, Some(2000));
+    assert!(fee_rates
+        .fee_rates
+        .iter()
+        .all(|fee_rate| (1000..=2000).contains(&fee_rate.sat_per_kw)));
+
+    // the options already applied are not reported again
+    let reload: response::Reload = node.lampod().call("reload", json::json!({}))?;
+    assert!(reload.applied.is_empty());
+    Ok(())
+}
+
+#[test]
+pub fn datastore_generations_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+    let key = |segments: &[&str]| segments.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+    let entry: response::DatastoreEntry = node.lampod().call(
+        "datastore",
+        json::json!({ "key": ["tips", "widget", "count"], "value": "1" }),
+    )?;
+    assert_eq!(entry.generation, 0);
+    let _: response::DatastoreEntry = node.lampod().call(
+        "datastore",
+        json::json!({ "key": ["tips", "widget", "owner"], "value": "alice" }),
+    )?;
+    let _: response::DatastoreEntry = node
+        .lampod()
+        .call("datastore", json::json!({ "key": ["other"], "value": "x" }))?;
+
+    // the key exists, and the default mode does not replace it
+    let err: error::Result<response::DatastoreEntry> = node.lampod().call(
+        "datastore",
+        json::json!({ "key": ["tips", "widget", "count"], "value": "2" }),
+    );
+    let err = rpc_error(err.expect_err("the key exists")).unwrap();
+    assert_eq!(err.code, 600, "{err:?}");
+
+    let entry: response::DatastoreEntry = node.lampod().call(
+        "datastore",
+        json::json!({
+            "key": ["tips", "widget", "count"],
+            "value": "2",
+            "mode": "must-replace",
+            "generation": 0,
+        }),
+    )?;
+    assert_eq!(entry.generation, 1);
+
+    // a client that read the generation 0 can not overwrite the change
+    let err: error::Result<response::DatastoreEntry> = node.lampod().call(
+        "datastore",
+        json::json!({
+            "key": ["tips", "widget", "count"],
+            "value": "3",
+            "mode": "must-replace",
+            "generation": 0,
+        }),
+    );
+    let err = rpc_error(err.expect_err("the generation is old")).unwrap();
+    assert_eq!(err.code, 602, "{err:?}");
+    assert_eq!(err.data.as_ref().unwrap()["current"], 1, "{err:?}");
+    let err: error::Result<response::DatastoreEntry> = node.lampod().call(
+        "deldatastore",
+        json::json!({ "key": ["tips", "widget", "count"], "generation": 0 }),
+    );
+    assert_eq!(rpc_error(err.unwrap_err()).unwrap().code, 602);
+
+    let entry: response::DatastoreEntry = node.lampod().call(
+        "datastore",
+        json::json!({ "key": ["tips", "widget", "owner"], "value": ",bob", "mode": "append" }),
+    )?;
+    assert_eq!(entry.value, "alice,bob");
+
+    let value = "x".repeat(64 * 1024 + 1);
+    let err: error::Result<response::DatastoreEntry> = node
+        .lampod()
+        .call("datastore", json::json!({ "key": ["big"], "value": value }));
+    assert_eq!(rpc_error(err.unwrap_err()).unwrap().code, 603);
+
+    // the entries are still there after a restart
+    let node = node.restart()?;
+    let list: response::ListDatastore = node
+        .lampod()
+        .call("listdatastore", json::json!({ "prefix": ["tips"] }))?;
+    assert_eq!(
+        list.datastore,
+        vec![
+            response::DatastoreEntry {
+                key: key(&["tips", "widget", "count"]),
+                value: "2".to_owned(),
+                generation: 1,
+            },
+            response::DatastoreEntry {
+                key: key(&["tips", "widget", "owner"]),
+                value: "alice,bob".to_owned(),
+                generation: 1,
+            },
+        ]
+    );
+    let list: response::ListDatastore = node.lampod().call("listdatastore", json::json!({}))?;
+    assert_eq!(list.datastore.len(), 3);
+
+    let entry: response::DatastoreEntry = node.lampod().call(
+        "deldatastore",
+        json::json!({ "key": ["tips", "widget", "count"], "generation": 1 }),
+    )?;
+    assert_eq!(entry.value, "2");
+    let list: response::ListDatastore = node
+        .lampod()
+        .call("listdatastore", json::json!({ "prefix": ["tips"] }))?;
+    assert_eq!(list.datastore.len(), 1);
+    Ok(())
+}
+
+#[test]
+pub fn http_batch_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.http_bind = Some("127.0.0.1:0".parse().unwrap());
+    })?;
+    let token = node.daemon().rpc_auth().master_token();
+
+    let calls = [
+        ("getinfo", json::json!({})),
+        ("channels", json::json!({})),
+        ("listpeers", json::json!({})),
+        ("datastore", json::json!({ "key": ["batch"], "value": "1" })),
+        ("listdatastore", json::json!({ "prefix": ["batch"] })),
+        ("datastore", json::json!({ "key": ["batch"], "value": "2" })),
+        ("listfunds", json::json!({})),
+        ("feerates", json::json!({})),
+        ("notamethod", json::json!({})),
+        ("getchaininfo", json::json!({})),
+    ];
+    let batch = calls
+        .iter()
+        .enumerate()
+        .map(|(id, (method, params))| {
+            json::json!({ "jsonrpc": "2.0", "id": id.to_string(), "method": method, "params": params })
+        })
+        .collect::<Vec<_>>();
+    let (status, responses) = http_call(
+        &node,
+        "POST",
+        "/rpc",
+        Some(&token),
+        Some(&json::json!(batch)),
+    )?;
+    assert_eq!(status, 200, "{responses}");
+    let responses = responses.as_array().cloned().unwrap_or_default();
+    assert_eq!(responses.len(), calls.len(), "{responses:?}");
+
+    // the responses are in the order of the requests
+    for (id, response) in responses.iter().enumerate() {
+        assert_eq!(response["id"], id.to_string(), "{response}");
+    }
+    assert_eq!(responses[0]["result"]["node_id"], node.info.node_id);
+    // the read after the mutation sees it
+    assert_eq!(
+        responses[4]["result"]["datastore"][0]["value"], "1",
+        "{}",
+        responses[4]
+    );
+    // the failures do not stop the other calls
+    assert_eq!(responses[5]["error"]["code"], 600, "{}", responses[5]);
+    assert_eq!(
+        responses[8]["error"]["code"],
+        error::METHOD_NOT_FOUND,
+        "{}",
+        responses[8]
+    );
+    for index in [1, 2, 6, 7, 9] {
+        assert!(responses[index]["error"].is_null(), "{}", responses[index]);
+    }
+    Ok(())
+}
+
+#[test]
+pub fn health_readiness_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let esplora = MockHttp::start(
+        json::json!({ "1": 20.0, "6": 10.0, "144": 2.0, "1008": 1.0 }).to_string(),
+    )?;
+    let url = esplora.url();
+    let node = LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.http_bind = Some("127.0.0.1:0".parse().unwrap());
+        conf.fee_estimates_url = Some(url);
+        conf.fee_refresh_interval_secs = 1;
+    })?;
+
+    let probe = |path: &str| -> (u16, response::Health) {
+        // the probes do not need a token
+        let (status, body) = http_call(&node, "GET", path, None, None).unwrap();
+        (status, json::from_value(body).unwrap())
+    };
+    wait!(|| {
+        if probe("/readyz").0 == 200 {
+            return Ok(());
+        }
+        Err(())
+    });
+    let health: response::Health = node.lampod().call("health", json::json!({}))?;
+    assert!(health.alive && health.ready, "{health:?}");
+    for name in [
+        "event_loop",
+        "persistence",
+        "channel_manager",
+        "chain",
+        "wallet",
+        "fees",
+    ] {
+        assert!(
+            health
+                .checks
+                .iter()
+                .any(|check| check.name == name && check.pass),
+            "{health:?}"
+        );
+    }
+
+    // without the fee estimations the node is alive but not ready
+    esplora.pause();
+    wait!(|| {
+        if probe("/readyz").0 == 503 {
+            return Ok(());
+        }
+        Err(())
+    });
+    let (_, health) = probe("/readyz");
+    assert!(health.alive && !health.ready, "{health:?}");
+    let failing = health
+        .failing()
+        .map(|check| check.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(failing, vec!["fees"], "{health:?}");
+    let (status, health) = probe("/healthz");
+    assert_eq!(status, 200, "{health:?}");
+
+    esplora.resume();
+    wait!(|| {
+        if probe("/readyz").0 == 200 {
+            return Ok(());
+        }
+        Err(())
+    });
+    Ok(())
+}
+#[test]
+pub fn maintenance_finds_corrupted_monitors_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+
+    let maintenance: response::Maintenance = node.lampod().call("maintenance", json::json!({}))?;
+    assert!(maintenance.corrupted.is_empty(), "{maintenance:?}");
+    assert!(
+        maintenance
+            .stores
+            .iter()
+            .any(|store| store.name == "channel_monitors"),
+        "{maintenance:?}"
+    );
+
+    // a monitor that ldk would refuse at the next start
+    let monitors = format!("{}/monitors", node.daemon().root_path());
+    std::fs::create_dir_all(&monitors)?;
+    let key = format!("{}_0", "aa".repeat(32));
+    std::fs::write(format!("{monitors}/{key}"), b"not a monitor")?;
+
+    let maintenance: response::Maintenance = node.lampod().call("maintenance", json::json!({}))?;
+    assert_eq!(maintenance.corrupted.len(), 1, "{maintenance:?}");
+    assert_eq!(maintenance.corrupted[0].name, "channel_monitor");
+    assert!(
+        maintenance.corrupted[0].path.ends_with(&key),
+        "{maintenance:?}"
+    );
+    Ok(())
+}
+
+#[test]
+pub fn cln_compat_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.compat_cln = true;
+    })?;
+
+    let info: json::Value = node.lampod().call("getinfo", json::json!({}))?;
+    assert_eq!(info["id"], node.info.node_id, "{info}");
+    assert_eq!(info["network"], "regtest", "{info}");
+    assert!(info["num_active_channels"].is_u64(), "{info}");
+
+    let addr: json::Value = node
+        .lampod()
+        .call("newaddr", json::json!({ "addresstype": "bech32" }))?;
+    assert!(
+        addr["bech32"]
+            .as_str()
+            .is_some_and(|addr| addr.starts_with("bcrt1")),
+        "{addr}"
+    );
+
+    let invoice: json::Value = node.lampod().call(
+        "invoice",
+        json::json!({
+            "msatoshi": "10000msat",
+            "label": "coffee",
+            "description": "a coffee",
+            "expiry": 3600,
+        }),
+    )?;
+    assert!(invoice["bolt11"].is_string(), "{invoice}");
+    assert!(invoice["expires_at"].is_u64(), "{invoice}");
+    let decode: response::InvoiceInfo = node
+        .lampod()
+        .call("decode", json::json!({ "invoice_str": invoice["bolt11"] }))?;
+    assert_eq!(decode.amount_msa, Some(10_000));
+    let invoices: response::ListInvoices = node
+        .lampod()
+        .call("listinvoices", json::json!({ "label": "coffee" }))?;
+    assert_eq!(invoices.invoices.len(), 1);
+    assert_eq!(invoices.invoices[0].bolt11, invoice["bolt11"]);
+
+    let funds: json::Value = node.lampod().call("listfunds", json::json!({}))?;
+    assert!(
+        funds["outputs"].is_array() && funds["channels"].is_array(),
+        "{funds}"
+    );
+
+    // a method of Core Lightning without a native one with the same name
+    let result: error::Result<json::Value> = node.lampod().call("delinvoice", json::json!({}));
+    let err = rpc_error(result.expect_err("the compat layer has not `delinvoice`")).unwrap();
+    assert_eq!(err.code, -32003, "{err:?}");
+    assert_eq!(err.data.unwrap()["alternative"], "cancelinvoice");
+    Ok(())
+}
+
+#[test]
+pub fn dev_methods_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new_with_conf(btc.clone(), |conf| conf.developer = true)?;
+    let node2 = LampoTesting::new(btc.clone())?;
+
+    // served only with `developer`
+    let result: error::Result<json::Value> = node2.lampod().call(
+        "dev-set-feerate",
+        json::json!({ "target": "on_chain_sweep", "sat_per_kw": 5000 }),
+    );
+    let err = rpc_error(result.expect_err("node2 is not a developer node")).unwrap();
+    assert_eq!(err.code, -32601, "{err:?}");
+
+    let fee_rates: response::FeeRates = node1.lampod().call(
+        "dev-set-feerate",
+        json::json!({ "target": "on_chain_sweep", "sat_per_kw": 5000 }),
+    )?;
+    let sweep = |fee_rates: &response::FeeRates| {
+        fee_rates
+            .fee_rates
+            .iter()
+            .find(|fee_rate| fee_rate.target == "on_chain_sweep")
+            .map(|fee_rate| fee_rate.sat_per_kw)
+    };
+    assert_eq!(sweep(&fee_rates), Some(5000));
+    let fee_rates: response::FeeRates = node1.lampod().call("feerates", json::json!({}))?;
+    assert_eq!(sweep(&fee_rates), Some(5000));
+    let fee_rates: response::FeeRates = node1.lampod().call(
+        "dev-set-feerate",
+        json::json!({ "target": "on_chain_sweep" }),
+    )?;
+    assert_ne!(sweep(&fee_rates), Some(5000));
+    let result: error::Result<json::Value> = node1.lampod().call(
+        "dev-set-feerate",
+        json::json!({ "target": "fast", "sat_per_kw": 5000 }),
+    );
+    let err = rpc_error(result.expect_err("`fast` is not a target")).unwrap();
+    assert_eq!(err.code, -32602, "{err:?}");
+
+    let ignore: response::DevIgnoreHtlcs = node1.lampod().call(
+        "dev-ignore-htlcs",
+        json::json!({ "node_id": node2.info.node_id }),
+    )?;
+    assert!(ignore.ignore);
+
+    let _: response::Connect = node1.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: node2.info.node_id.clone(),
+            addr: "127.0.0.1".to_owned(),
+            port: node2.port,
+        },
+    )?;
+    open_channel(&node1, &node2, 100_000)?;
+
+    let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+    let channel_id = channels.channels[0].channel_id.clone();
+
+    #[cfg(debug_assertions)]
+    {
+        let signed: response::DevSignLastTx = node1
+            .lampod()
+            .call("dev-sign-last-tx", json::json!({ "id": channel_id }))?;
+        assert_eq!(signed.channel_id, channel_id);
+        assert!(!signed.txs.is_empty());
+    }
+
+    // the channel is forgotten without broadcasting the commitment
+    let failed: response::DevFail = node1
+        .lampod()
+        .call("dev-fail", json::json!({ "id": channel_id }))?;
+    assert_eq!(failed.channel_id, channel_id);
+    let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+    assert!(channels.channels.is_empty());
+    let result: error::Result<json::Value> = node1
+        .lampod()
+        .call("dev-fail", json::json!({ "id": channel_id }));
+    let err = rpc_error(result.expect_err("the channel is closed")).unwrap();
+    assert_eq!(err.code, 300, "{err:?}");
+    Ok(())
+}
+
+#[test]
+pub fn invoice_with_label_fallback_and_metadata_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+    let address: response::NewAddress = node.lampod().call("newaddr", json::json!({}))?;
+
+    let generate = request::GenerateInvoice {
+        description: "order of a coffee".to_owned(),
+        amount_msat: Some(100_000),
+        expiring_in: None,
+        expiry_unix: None,
+        no_expiry: false,
+        label: Some("order-42".to_owned()),
+        fallback: Some(address.address.clone()),
+        metadata: Some("merchant-order-42".to_owned()),
+    };
+    let invoice: response::Invoice = node.lampod().call("invoice", &generate)?;
+    let decode: response::InvoiceInfo = node
+        .lampod()
+        .call("decode", json::json!({ "invoice_str": invoice.bolt11 }))?;
+    assert_eq!(decode.fallbacks, vec![address.address.clone()]);
+
+    // the metadata is stored with the invoice, not inside it
+    assert!(!invoice.bolt11.contains("merchant"));
+    let invoices: response::ListInvoices = node
+        .lampod()
+        .call("listinvoices", json::json!({ "label": "order-42" }))?;
+    assert_eq!(invoices.invoices.len(), 1);
+    let record = &invoices.invoices[0];
+    assert_eq!(record.bolt11, invoice.bolt11);
+    assert_eq!(record.payment_hash, invoice.payment_hash);
+    assert_eq!(record.amount_msat, Some(100_000));
+    assert_eq!(record.fallback.as_deref(), Some(address.address.as_str()));
+    assert_eq!(record.metadata.as_deref(), Some("merchant-order-42"));
+    assert_eq!(record.state, response::InboundPaymentState::Unpaid);
+
+    let result: error::Result<response::Invoice> = node.lampod().call("invoice", &generate);
+    let err = rpc_error(result.expect_err("the label is already used")).unwrap();
+    assert_eq!(err.code, 207, "{err:?}");
+    assert_eq!(err.data.unwrap()["label"], "order-42");
+
+    // a mainnet address can not be the fallback of a regtest invoice
+    let result: error::Result<response::Invoice> = node.lampod().call(
+        "invoice",
+        json::json!({
+            "description": "wrong network",
+            "amount_msat": 1000,
+            "label": "order-43",
+            "fallback": "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq",
+        }),
+    );
+    let err = rpc_error(result.expect_err("the fallback is for another network")).unwrap();
+    assert_eq!(err.code, -32602, "{err:?}");
+    let invoices: response::ListInvoices = node.lampod().call("listinvoices", json::json!({}))?;
+    assert_eq!(invoices.invoices.len(), 1);
+    Ok(())
+}
+
+#[test]
+pub fn recover_torn_channel_monitors_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.monitor_backup_dir = Some(format!("{}/monitor-backups", conf.path()));
+    })?;
+    let node2 = LampoTesting::new(btc.clone())?;
+
+    open_channel(&node1, &node2, 1_000_000)?;
+    let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+    assert_eq!(channels.channels.len(), 1);
+    let channel_id = channels.channels[0].channel_id.clone();
+
+    let root_path = node1.daemon().root_path();
+    let monitors = format!("{root_path}/monitors");
+    let keys = std::fs::read_dir(&monitors)?
+        .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+        .collect::<error::Result<Vec<_>>>()?;
+    // no temporary file or backup is left after a write
+    assert_eq!(keys.len(), 1, "{keys:?}");
+    let key = keys[0].clone();
+    let monitor = format!("{monitors}/{key}");
+    let mirror = std::fs::read(format!("{root_path}/monitor-backups/{key}"))?;
+    assert_eq!(mirror, std::fs::read(&monitor)?);
+
+    // killed between the write of the temporary file and the rename
+    std::fs::rename(&monitor, format!("{monitor}.tmp"))?;
+    let node1 = node1.restart()?;
+    let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+    assert!(
+        channels
+            .channels
+            .iter()
+            .any(|channel| channel.channel_id == channel_id),
+        "{channels:?}"
+    );
+    assert!(std::path::Path::new(&monitor).exists());
+    assert!(!std::path::Path::new(&format!("{monitor}.tmp")).exists());
+
+    // the monitor is torn, while the previous version is still there
+    let content = std::fs::read(&monitor)?;
+    std::fs::write(format!("{monitor}.bak"), &content)?;
+    std::fs::write(&monitor, &content[..content.len() / 2])?;
+    let node1 = node1.restart()?;
+    let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+    assert!(
+        channels
+            .channels
+            .iter()
+            .any(|channel| channel.channel_id == channel_id),
+        "{channels:?}"
+    );
+    assert_eq!(std::fs::read(&monitor)?, content);
+    assert!(!std::path::Path::new(&format!("{monitor}.bak")).exists());
+    Ok(())
+}
+
+#[test]
+pub fn remote_storage_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let vss = MockVss::start()?;
+    let node1 = LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.remote_storage_url = Some(vss.url());
+        conf.remote_storage_token = Some(MockVss::TOKEN.to_owned());
+    })?;
+    let node2 = LampoTesting::new(btc.clone())?;
+
+    open_channel(&node1, &node2, 1_000_000)?;
+    let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+    assert_eq!(channels.channels.len(), 1);
+    let channel_id = channels.channels[0].channel_id.clone();
+    let _: response::Invoice = node1.lampod().call(
+        "invoice",
+        request::GenerateInvoice {
+            amount_msat: Some(100_000),
+            description: "kept remotely".to_owned(),
+            expiring_in: None,
+            expiry_unix: None,
+            no_expiry: false,
+            label: Some("remote".to_owned()),
+            fallback: None,
+            metadata: None,
+        },
+    )?;
+
+    // the state is written on the disk and then on the storage
+    let monitors = format!("{}/monitors", node1.daemon().root_path());
+    let keys = std::fs::read_dir(&monitors)?
+        .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+        .collect::<error::Result<Vec<_>>>()?;
+    assert_eq!(keys.len(), 1, "{keys:?}");
+    let monitor = format!("monitors/{}", keys[0]);
+    let content = std::fs::read(format!("{monitors}/{}", keys[0]))?;
+    wait!(|| {
+        match vss.object(&monitor) {
+            Some((_, stored)) if stored == content => Ok(()),
+            _ => Err(()),
+        }
+    });
+    assert!(vss.object("manager").is_some(), "{:?}", vss.keys());
+    assert!(vss.object("lampo/invoices").is_some(), "{:?}", vss.keys());
+
+    // a node that lost its datadir is restored from the storage
+    let node1 = node1.restore_with_conf(|conf| {
+        conf.remote_storage_url = Some(vss.url());
+        conf.remote_storage_token = Some(MockVss::TOKEN.to_owned());
+        conf.remote_storage_restore = true;
+    })?;
+    let channels: response::Channels = node1.lampod().call("channels", json::json!({}))?;
+    assert!(
+        channels
+            .channels
+            .iter()
+            .any(|channel| channel.channel_id == channel_id),
+        "{channels:?}"
+    );
+    let invoices: response::ListInvoices = node1.lampod().call(
+        "listinvoices",
+        request::ListInvoices {
+            label: Some("remote".to_owned()),
+            ..Default::default()
+        },
+    )?;
+    assert_eq!(invoices.invoices.len(), 1);
+
+    // the storage has a monitor newer than the one on the disk
+    vss.bump(&monitor);
+    let restarted = node1.restart();
+    assert!(restarted.is_err());
+    Ok(())
+}
+
+#[test]
+pub fn keys_node_id_matches_the_handshake_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new(btc.clone())?;
+
+    let keys1 = node1.wallet.ldk_keys();
+    let keys2 = node2.wallet.ldk_keys();
+    assert_eq!(keys1.node_id().to_string(), node1.info.node_id);
+    assert_eq!(
+        keys1.shutdown_pubkey().to_string(),
+        node1.info.shutdown_pubkey
+    );
+    assert_eq!(node1.info.keys_scheme.as_deref(), Some("hardened"));
+    assert_eq!(node1.info.fingerprints.len(), 1);
+
+    let _: response::Connect = node2.lampod().call(
+        "connect",
+        request::Connect {
+            node_id: keys1.node_id().to_string(),
+            addr: "127.0.0.1".to_owned(),
+            port: node1.port,
+        },
+    )?;
+    // the handshake proves the node key of each side to the other
+    let peers: response::Peers = node1.lampod().call("listpeers", json::json!({}))?;
+    assert_eq!(peers.peers.len(), 1);
+    assert_eq!(peers.peers[0].node_id, keys2.node_id().to_string());
+    let peers: response::Peers = node2.lampod().call("listpeers", json::json!({}))?;
+    assert_eq!(peers.peers.len(), 1);
+    assert_eq!(peers.peers[0].node_id, keys1.node_id().to_string());
+    Ok(())
+}
+
+#[test]
+pub fn signet_invoice_and_fallback_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    // the chain is a regtest, but lampo takes only the prefixes of the
+    // invoices and of the addresses from the network, so for what is
+    // checked here the node runs on signet.
+    let node = LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.network = Network::Signet;
+        conf.prepare_dirs().unwrap();
+    })?;
+    assert_eq!(node.info.chain, "signet");
+
+    let fallback = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+    let invoice: response::Invoice = node.lampod().call(
+        "invoice",
+        json::json!({
+            "description": "signet invoice",
+            "amount_msat": 1000,
+            "fallback": fallback,
+        }),
+    )?;
+    assert!(invoice.bolt11.starts_with("lntbs"), "{}", invoice.bolt11);
+    let decode: response::InvoiceInfo = node
+        .lampod()
+        .call("decode", json::json!({ "invoice_str": invoice.bolt11 }))?;
+    assert_eq!(decode.fallbacks, vec![fallback.to_owned()]);
+
+    // a mainnet address is not a signet one
+    let result: error::Result<response::Invoice> = node.lampod().call(
+        "invoice",
+        json::json!({
+            "description": "wrong network",
+            "amount_msat": 1000,
+            "fallback": "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq",
+        }),
+    );
+    let err = rpc_error(result.expect_err("the fallback is for another network")).unwrap();
+    assert_eq!(err.code, -32602, "{err:?}");
+    Ok(())
+}
+
+#[test]
+pub fn listconfigs_sources_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+    let conf_path = format!("{}/lampo.conf", node.daemon().conf().path());
+    let config = |configs: &response::ListConfigs, name: &str| {
+        configs
+            .configs
+            .iter()
+            .find(|config| config.name == name)
+            .cloned()
+            .unwrap()
+    };
+
+    let configs: response::ListConfigs = node.lampod().call("listconfigs", json::json!({}))?;
+    let network = config(&configs, "network");
+    assert_eq!(network.values, vec!["regtest".to_owned()]);
+    assert_eq!(network.source, "cli");
+    assert_eq!(network.env, "LAMPO_NETWORK");
+    assert_eq!(
+        config(&configs, "core-pass").values,
+        vec!["<redacted>".to_owned()]
+    );
+    assert_eq!(config(&configs, "min-fee-rate-sat-kw").source, "default");
+
+    std::fs::write(
+        &conf_path,
+        format!(
+            "network=regtest\nport={}\nmin-fee-rate-sat-kw=1000\n",
+            node.port
+        ),
+    )?;
+    let _: response::Reload = node.lampod().call("reload", json::json!({}))?;
+    let configs: response::ListConfigs = node.lampod().call("listconfigs", json::json!({}))?;
+    let min_fee_rate = config(&configs, "min-fee-rate-sat-kw");
+    assert_eq!(min_fee_rate.values, vec!["1000".to_owned()]);
+    assert_eq!(min_fee_rate.source, "file");
+    // the port given to the node still wins over the file
+    assert_eq!(config(&configs, "port").source, "cli");
+    Ok(())
+}
+
+#[test]
+pub fn watchtower_client_uploads_appointments_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let tower = MockTower::start()?;
+    let node1 = LampoTesting::new(btc.clone())?;
+    // node 2 backs up the revoked commitments of node 1
+    let node2 = LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.watchtowers = vec![tower.address()];
+    })?;
+
+    open_channel(&node1, &node2, 1_000_000)?;
+    let towers: response::Towers = node2.lampod().call("listtowers", json::json!({}))?;
+    assert_eq!(towers.towers.len(), 1);
+    assert_eq!(towers.towers[0].tower_id, tower.tower_id);
+    assert!(towers.towers[0].registered, "{towers:?}");
+
+    let pay = |label: &str| -> error::Result<()> {
+        let invoice: response::Invoice = node2.lampod().call(
+            "invoice",
+            request::GenerateInvoice {
+                amount_msat: Some(10_000_000),
+                description: "backed up by the tower".to_owned(),
+                expiring_in: None,
+                expiry_unix: None,
+                no_expiry: false,
+                label: Some(label.to_owned()),
+                fallback: None,
+                metadata: None,
+            },
+        )?;
+        let _: response::PayResult = node1.lampod().call(
+            "pay",
+            request::Pay {
+                invoice_str: invoice.bolt11,
+                amount: None,
+                custom_tlvs: Vec::new(),
+                timeout_secs: None,
+                max_total_cltv_expiry_delta: None,
+            },
+        )?;
+        Ok(())
+    };
+
+    // every payment revokes the commitments of node 1
+    for payment in 0..3 {
+        let before = tower.appointments().len();
+        pay(&format!("tower-{payment}"))?;
+        wait!(|| {
+            if tower.appointments().len() > before {
+                return Ok(());
+            }
+            Err(())
+        });
+    }
+    let appointments = tower.appointments();
+    let mut locators = appointments
+        .iter()
+        .map(|appointment| appointment["locator"].as_str().unwrap().to_owned())
+        .collect::<Vec<_>>();
+    locators.sort();
+    locators.dedup();
+    assert_eq!(locators.len(), appointments.len(), "{appointments:?}");
+
+    // the tower is down, the appointments wait in the backlog
+    tower.pause();
+    pay("tower-down")?;
+    wait!(|| {
+        let status: response::TowerStatus = node2.lampod().call(
+            "towerstatus",
+            request::TowerStatus {
+                tower_id: tower.tower_id.clone(),
+            },
+        )?;
+        if status.tower.status == "unreachable" && status.tower.pending_appointments > 0 {
+            return Ok(());
+        }
+        Err(error::anyhow!("{status:?}"))
+    });
+    assert_eq!(tower.appointments().len(), appointments.len());
+
+    // and they are sent when the tower is back
+    tower.resume();
+    wait!(
+        || {
+            let status: response::TowerStatus = node2.lampod().call(
+                "towerstatus",
+                request::TowerStatus {
+                    tower_id: tower.tower_id.clone(),
+                },
+            )?;
+            if status.tower.pending_appointments > 0 {
+                return Err(error::anyhow!("{status:?}"));
+            }
+            assert_eq!(status.tower.status, "active");
+            assert_eq!(status.channels.len(), 1);
+            assert_eq!(
+                status.channels[0].appointments as usize,
+                tower.appointments().len()
+            );
+            Ok(())
+        },
+        15
+    );
+    assert!(tower.appointments().len() > appointments.len());
+
+    // the address of a tower needs its id
+    let err: error::Result<response::Tower> = node2.lampod().call(
+        "addtower",
+        request::AddTower {
+            tower: "127.0.0.1:9814".to_owned(),
+        },
+    );
+    assert_eq!(rpc_error(err.unwrap_err()).unwrap().code, -32602);
+    Ok(())
+}
+
+#[test]
+#[cfg(debug_assertions)]
+pub fn watchtower_server_punishes_a_breach_lampo() -> error::Result<()> {
+    use lampo_testing::prelude::bitcoincore_rpc::RpcApi;
+
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let tower = LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.tower_bind = Some(SocketAddr::from(([127, 0, 0, 1], 0)));
+    })?;
+    let info: response::TowerInfo = tower.lampod().call("towerinfo", json::json!({}))?;
+    assert_eq!(info.tower_id, tower.info.node_id);
+    assert_eq!(info.appointments, 0);
+
+    // node 1 is backed up by the tower, node 2 cheats
+    let node1 = LampoTesting::new(btc.clone())?;
+    let node2 = LampoTesting::new_with_conf(btc.clone(), |conf| conf.developer = true)?;
+    let added: response::Tower = node1.lampod().call(
+        "addtower",
+        request::AddTower {
+            tower: format!("{}@{}", info.tower_id, info.bind),
+        },
+    )?;
+    assert!(added.registered, "{added:?}");
+    let clients: response::TowerClients =
+        tower.lampod().call("listtowerclients", json::json!({}))?;
+    assert_eq!(clients.clients.len(), 1);
+    assert_eq!(clients.clients[0].user_id, node1.info.node_id);
+    assert_eq!(clients.clients[0].available_slots, info.max_appointments);
+
+    open_channel(&node2, &node1, 1_000_000)?;
+
+    // the backup of node 2 has the first commitment of the channel
+    let (node2, snapshot) = node2.snapshot()?;
+    wait!(|| {
+        let channels: response::Channels =
+            node2.lampod().call("channels", json::json!({})).unwrap();
+        if channels
+            .channels
+            .first()
+            .is_some_and(|channel| channel.usable)
+        {
+            return Ok(());
+        }
+        Err(())
+    });
+    for payment in 0..2 {
+        let invoice: response::Invoice = node1.lampod().call(
+            "invoice",
+            request::GenerateInvoice {
+                amount_msat: Some(100_000_000),
+                description: "revoke the old commitments".to_owned(),
+                expiring_in: None,
+                expiry_unix: None,
+                no_expiry: false,
+                label: Some(format!("breach-{payment}")),
+                fallback: None,
+                metadata: None,
+            },
+        )?;
+        let _: response::PayResult = node2.lampod().call(
+            "pay",
+            request::Pay {
+                invoice_str: invoice.bolt11,
+                amount: None,
+                custom_tlvs: Vec::new(),
+                timeout_secs: None,
+                max_total_cltv_expiry_delta: None,
+            },
+        )?;
+    }
+    wait!(|| {
+        let info: response::TowerInfo = tower.lampod().call("towerinfo", json::json!({}))?;
+        let status: response::TowerStatus = node1.lampod().call(
+            "towerstatus",
+            request::TowerStatus {
+                tower_id: info.tower_id.clone(),
+            },
+        )?;
+        if info.appointments >= 2 && status.tower.pending_appointments == 0 {
+            return Ok(());
+        }
+        Err(error::anyhow!("{info:?} {status:?}"))
+    });
+
+    // node 2 publishes its old commitment while node 1 is offline
+    let node1_id = node1.info.node_id.clone();
+    node1.stop()?;
+    let old_node2 = node2.start_snapshot(snapshot, |conf| conf.developer = true)?;
+    let channels: response::Channels = old_node2.lampod().call("channels", json::json!({}))?;
+    let signed: response::DevSignLastTx = old_node2.lampod().call(
+        "dev-sign-last-tx",
+        json::json!({ "id": channels.channels[0].channel_id }),
+    )?;
+    let commitment_txid = btc.rpc().send_raw_transaction(signed.txs[0].as_str())?;
+    let address = tower.wallet.get_onchain_address()?;
+    let _ = fund_wallet(btc.clone(), &address.address, 1)?;
+
+    // and the tower punishes it
+    wait!(|| {
+        let info: response::TowerInfo = tower.lampod().call("towerinfo", json::json!({}))?;
+        let Some(breach) = info.breaches.first() else {
+            return Err(error::anyhow!("{info:?}"));
+        };
+        assert_eq!(breach.commitment_txid, commitment_txid.to_string());
+        assert_eq!(breach.user_id, node1_id);
+        let mempool = btc.rpc().get_raw_mempool()?;
+        if mempool
+            .iter()
+            .any(|txid| txid.to_string() == breach.justice_txid)
+        {
+            return Ok(());
+        }
+        Err(error::anyhow!(
+            "the justice transaction is not in the mempool"
+        ))
+    });
+    let info: response::TowerInfo = tower.lampod().call("towerinfo", json::json!({}))?;
+    let justice_txid = info.breaches[0].justice_txid.clone();
+    let _ = fund_wallet(btc.clone(), &address.address, 1)?;
+    let block = btc.rpc().get_block(&btc.rpc().get_best_block_hash()?)?;
+    let justice_tx = block
+        .txdata
+        .iter()
+        .find(|tx| tx.txid().to_string() == justice_txid)
+        .expect("the justice transaction is confirmed");
+    assert!(justice_tx
+        .input
+        .iter()
+        .all(|input| input.previous_output.txid == commitment_txid));
+
+    // the client is forgotten with its appointments
+    let clients: response::TowerClients = tower.lampod().call(
+        "deltowerclient",
+        request::DelTowerClient { user_id: node1_id },
+    )?;
+    assert!(clients.clients.is_empty());
+    let info: response::TowerInfo = tower.lampod().call("towerinfo", json::json!({}))?;
+    assert_eq!(info.appointments, 0);
+    Ok(())
+}
+
+#[test]
+pub fn claim_replayed_after_crash_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let payer = LampoTesting::new(btc.clone())?;
+    let payer_id: NodeId = payer.info.node_id.parse()?;
+    let receiver = LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.zero_conf_peers.push(payer_id);
+    })?;
+    let _ = payer.fund_wallet(101)?;
+    let _: json::Value = payer.lampod().call(
+        "fundchannel",
+        request::OpenChannel {
+            node_id: receiver.info.node_id.clone(),
+            amount: 1_000_000,
+            public: false,
+            addr: Some("127.0.0.1".to_owned()),
+            port: Some(receiver.port),
+        },
+    )?;
+    wait!(|| {
+        let channels: response::Channels =
+            payer.lampod().call("channels", json::json!({})).unwrap();
+        if channels.channels.len() == 1 && channels.channels[0].ready {
+            Ok(())
+        } else {
+            Err(())
+        }
+    });
+
+    let invoice: response::Invoice = receiver.lampod().call(
+        "invoice",
+        request::GenerateInvoice {
+            description: "claimed once, even across a crash".to_owned(),
+            amount_msat: Some(10_000_000),
+            expiring_in: None,
+            expiry_unix: None,
+            no_expiry: false,
+            label: None,
+            fallback: None,
+            metadata: None,
+        },
+    )?;
+    // the receiver claims the funds and dies before recording it
+    receiver.daemon().event_journal().halt_before_completion();
+    let pay: response::PayResult = payer.lampod().call(
+        "pay",
+        request::Pay {
+            invoice_str: invoice.bolt11.clone(),
+            amount: None,
+            custom_tlvs: Vec::new(),
+            timeout_secs: None,
+            max_total_cltv_expiry_delta: None,
+        },
+    )?;
+    assert!(matches!(pay.state, response::PaymentState::Success));
+    assert!(receiver.daemon().event_journal().depth() > 0);
+
+    let receiver = receiver.restart()?;
+    wait!(|| {
+        let status: response::PaymentStatus = receiver
+            .lampod()
+            .call(
+                "isinvoicepaid",
+                request::IsInvoicePaid {
+                    invoice_str: invoice.bolt11.clone(),
+                },
+            )
+            .unwrap();
+        if status.state == response::InboundPaymentState::Paid
+            && receiver.daemon().event_journal().depth() == 0
+        {
+            Ok(())
+        } else {
+            Err(())
+        }
+    });
+    let metrics = receiver.daemon().metrics().render();
+    assert!(metrics.contains("lampo_payments_received_total 1\n"));
+    Ok(())
+}
+
+#[test]
+pub fn load_network_graph_in_background_lampo() -> error::Result<()> {
+    use std::time::Instant;
+
+    use lampo_common::ldk::rgs::RapidGossipSync;
+    use lampo_common::ldk::routing::gossip::NetworkGraph;
+    use lampo_common::ldk::util::ser::Writeable;
+
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node = LampoTesting::new(btc.clone())?;
+
+    // a graph large enough to take a while to load, like the mainnet one
+    let nodes = 200_001;
+    let graph_path = format!("{}/network_graph", node.daemon().root_path());
+    let (node, stopped_at) = node.restart_after(|| {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+        let graph = Arc::new(NetworkGraph::new(Network::Regtest, Arc::new(LampoLogger)));
+        RapidGossipSync::new(graph.clone(), Arc::new(LampoLogger))
+            .update_network_graph(&rgs_graph_fixture(timestamp, nodes))
+            .map_err(|err| error::anyhow!("{err:?}"))?;
+        std::fs::write(&graph_path, graph.encode())?;
+        Ok(Instant::now())
+    })?;
+    // the start waits a second for the node to listen, and the rest
+    // is the wallet, not the graph
+    assert!(
+        stopped_at.elapsed() < Duration::from_secs(10),
+        "the node started in {:?}",
+        stopped_at.elapsed()
+    );
+
+    let started = Instant::now();
+    let _: response::GetInfo = node.lampod().call("getinfo", json::json!({}))?;
+    assert!(
+        started.elapsed() < Duration::from_secs(2),
+        "getinfo took {:?}",
+        started.elapsed()
+    );
+
+    wait!(
+        || {
+            let info: response::GetInfo = node
+                .lampod()
+                .call("getinfo", json::json!({}))
+                .map_err(|_| ())?;
+            if !info.graph_loaded {
+                return Err(());
+            }
+            assert_eq!(info.nodes_loaded, nodes as usize);
+            Ok(())
+        },
+        15
+    );
+    let graph = node.daemon().channel_manager().graph();
+    assert_eq!(graph.read_only().channels().len(), nodes as usize - 1);
+    Ok(())
+}
+
+#[test]
+pub fn autofees_raise_drained_channel_lampo() -> error::Result<()> {
+    init();
+    let btc = async_run!(btc::BtcNode::tmp("regtest"))?;
+    let btc = Arc::new(btc);
+    let node1 = Arc::new(LampoTesting::new(btc.clone())?);
+    let node2 = Arc::new(LampoTesting::new_with_conf(btc.clone(), |conf| {
+        conf.autofees = true;
+        conf.autofees_interval_secs = 1;
+        conf.autofees_min_ppm = 1;
+        conf.autofees_max_ppm = 500;
+        conf.autofees_max_change_ppm = 100;
+        conf.autofees_hysteresis_ppm = 10;
+    })?);
+
+    open_channel(&node1, &node2, 1_000_000)?;
+
+    let status: response::AutofeesStatus =
+        node1.lampod().call("autofees-status", json::json!({}))?;
+    assert!(!status.enabled);
+    assert!(status.channels.is_empty());
+
+    // all the capacity is on the side of node1, so node2 raises its fee
+    // up to the maximum, by at most the max change every evaluation
+    wait!(
+        || {
+            let status: response::AutofeesStatus = node2
+                .lampod()
+                .call("autofees-status", json::json!({}))
+                .map_err(|_| ())?;
+            assert!(status.enabled);
+            let Some(channel) = status.channels.first() else {
+                return Err(());
+            };
+            assert_eq!(channel.outbound_ratio, 0.0);
+            assert_eq!(channel.target_ppm, 500);
+            if let Some(new_ppm) = channel.new_ppm {
+                assert!((1..=500).contains(&new_ppm), "{channel:?}");
+                assert!(new_ppm.abs_diff(channel.current_ppm) <= 100, "{channel:?}");
+            }
+            if channel.current_ppm != 500 {
+                return Err(());
+            }
+            assert_eq!(channel.new_ppm, None);
+            assert_eq!(channel.reason, "on_target");
+            Ok(())
+        },
+        5
+    );
+
+    let channels = node2.daemon().channel_manager().manager().list_channels();
+    let config = channels.first().unwrap().config.unwrap();
+    assert_eq!(config.forwarding_fee_proportional_millionths, 500);
     Ok(())
 }
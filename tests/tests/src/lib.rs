@@ -1,3 +1,5 @@
+#[cfg(all(test, feature = "bip157"))]
+pub mod lampo_bip157_tests;
 #[cfg(test)]
 pub mod lampo_cln_tests;
 #[cfg(test)]
@@ -23,8 +25,12 @@ fn init() {
                 println!("Logger initialized with level: {}", level);
             }
         }
-        Err(e) => {
-            eprintln!("Error reading TEST_LOG_LEVEL: {}", e);
+        Err(_) => {
+            // nothing is logged, but `setloglevel` can enable
+            // the targets checked by the tests.
+            if let Err(e) = lampo_common::logger::init("off", None) {
+                eprintln!("Error initializing logger: {}", e);
+            }
         }
     });
 }
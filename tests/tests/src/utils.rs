@@ -1,12 +1,25 @@
 //! Test Utils
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use lampo_testing::prelude::bitcoincore_rpc;
 use lampo_testing::prelude::bitcoincore_rpc::RpcApi;
 use lampo_testing::prelude::btc;
 
+use lampo_common::bitcoin::hashes::hex::FromHex;
 use lampo_common::error;
+use lampo_common::json;
+use lampo_common::ldk::invoice::bech32::{self, ToBase32};
+use lampo_common::ldk::util::message_signing;
+use lampo_common::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use lampo_jsonrpc::errors::{Error, RpcError};
+use lampo_jsonrpc::json_rpc2::Request;
+use lampo_testing::LampoTesting;
 
 #[macro_export]
 macro_rules! wait_cln_sync {
@@ -72,3 +85,912 @@ pub fn fund_wallet(btc: Arc<btc::BtcNode>, addr: &str, blocks: u64) -> error::Re
 
     Ok(address.to_string())
 }
+
+/// The JSON RPC 2.0 error that a lampo call would return to a client.
+pub fn rpc_error(err: error::Error) -> Option<RpcError> {
+    err.downcast::<Error>().ok().map(RpcError::from)
+}
+
+/// Call the `method` as a client of the JSON RPC 2.0 interface does,
+/// with the `token` inside the params.
+pub fn client_call(
+    node: &LampoTesting,
+    method: &str,
+    token: Option<&str>,
+    mut params: json::Value,
+) -> Result<json::Value, RpcError> {
+    if let Some(token) = token {
+        params["token"] = json::json!(token);
+    }
+    let request = Request::new(method, params);
+    // SAFETY: the handler always answers.
+    node.rpc()
+        .run_client_callback(&request)
+        .unwrap()
+        .map_err(RpcError::from)
+}
+
+/// Call the HTTP gateway of the `node` with the bearer `token`, and
+/// return the status with the JSON body.
+pub fn http_call(
+    node: &LampoTesting,
+    method: &str,
+    path: &str,
+    token: Option<&str>,
+    body: Option<&json::Value>,
+) -> error::Result<(u16, json::Value)> {
+    let body = body.map(json::Value::to_string).unwrap_or_default();
+    let (status, body) = http_request(node, method, path, token, &body)?;
+    let body = if body.is_empty() {
+        json::Value::Null
+    } else {
+        json::from_str(&body)?
+    };
+    Ok((status, body))
+}
+
+/// Scrape the metrics of the `node` from the HTTP gateway, and return
+/// the value of each series by its name with the labels.
+pub fn scrape_metrics(node: &LampoTesting, token: &str) -> error::Result<HashMap<String, f64>> {
+    let (status, text) = http_request(node, "GET", "/metrics", Some(token), "")?;
+    if status != 200 {
+        error::bail!("the scrape failed with `{status}`: {text}");
+    }
+    let mut series = HashMap::new();
+    for line in text.lines().filter(|line| !line.starts_with('#')) {
+        let (name, value) = line
+            .rsplit_once(' ')
+            .ok_or(error::anyhow!("malformed series `{line}`"))?;
+        series.insert(name.to_owned(), value.parse::<f64>()?);
+    }
+    Ok(series)
+}
+
+/// Make a request to the HTTP gateway of the `node`, and return the
+/// status with the body.
+fn http_request(
+    node: &LampoTesting,
+    method: &str,
+    path: &str,
+    token: Option<&str>,
+    body: &str,
+) -> error::Result<(u16, String)> {
+    let addr = node
+        .http_addr()
+        .ok_or(error::anyhow!("the HTTP gateway is disabled"))?;
+    let mut request = format!(
+        "{method} {path} HTTP/1.1\r\nHost: {addr}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        body.len()
+    );
+    if let Some(token) = token {
+        request.push_str(&format!("Authorization: Bearer {token}\r\n"));
+    }
+    request.push_str("\r\n");
+    request.push_str(body);
+
+    let mut stream = TcpStream::connect(addr)?;
+    stream.write_all(request.as_bytes())?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or(error::anyhow!("malformed response `{response}`"))?;
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or(error::anyhow!("malformed status line `{head}`"))?;
+    Ok((status, body.to_owned()))
+}
+
+/// A client of the WebSocket that streams the events of a node.
+pub struct WsEvents {
+    stream: TcpStream,
+}
+
+impl WsEvents {
+    /// The key of the handshake example of RFC 6455, with its accept key.
+    const KEY: &'static str = "dGhlIHNhbXBsZSBub25jZQ==";
+    const ACCEPT: &'static str = "s3pPLMBiTxaITy9ZOMsEvlYQwOA=";
+
+    /// Open the stream with the `token`, replaying the events after
+    /// `since_id` if any.
+    pub fn connect(node: &LampoTesting, token: &str, since_id: Option<u64>) -> error::Result<Self> {
+        let addr = node
+            .http_addr()
+            .ok_or(error::anyhow!("the HTTP gateway is disabled"))?;
+        let query = since_id
+            .map(|id| format!("?since_id={id}"))
+            .unwrap_or_default();
+        let request = [
+            format!("GET /v1/ws{query} HTTP/1.1"),
+            format!("Host: {addr}"),
+            "Upgrade: websocket".to_owned(),
+            "Connection: Upgrade".to_owned(),
+            format!("Sec-WebSocket-Key: {}", Self::KEY),
+            "Sec-WebSocket-Version: 13".to_owned(),
+            format!("Authorization: Bearer {token}"),
+            "\r\n".to_owned(),
+        ]
+        .join("\r\n");
+        let mut stream = TcpStream::connect(addr)?;
+        stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+        stream.write_all(request.as_bytes())?;
+        // read the response byte by byte to not eat the first frame
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8; 1];
+            if stream.read(&mut byte)? == 0 {
+                break;
+            }
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8_lossy(&head);
+        if !head.starts_with("HTTP/1.1 101") || !head.contains(Self::ACCEPT) {
+            error::bail!("handshake refused `{head}`");
+        }
+        Ok(Self { stream })
+    }
+
+    /// Wait for the next event of the `kind`, skipping the other events.
+    pub fn wait_for(&mut self, kind: &str) -> error::Result<json::Value> {
+        loop {
+            let event = self.next_event()?;
+            if event["type"] == kind {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Read the next event, answering to the pings of the node.
+    pub fn next_event(&mut self) -> error::Result<json::Value> {
+        loop {
+            let mut head = [0u8; 2];
+            self.stream.read_exact(&mut head)?;
+            let len = match head[1] & 0x7f {
+                126 => {
+                    let mut len = [0u8; 2];
+                    self.stream.read_exact(&mut len)?;
+                    u16::from_be_bytes(len) as usize
+                }
+                127 => {
+                    let mut len = [0u8; 8];
+                    self.stream.read_exact(&mut len)?;
+                    u64::from_be_bytes(len) as usize
+                }
+                len => len as usize,
+            };
+            let mut payload = vec![0u8; len];
+            self.stream.read_exact(&mut payload)?;
+            match head[0] & 0x0f {
+                0x1 => return Ok(json::from_slice(&payload)?),
+                0x8 => error::bail!("stream closed: {}", String::from_utf8_lossy(&payload)),
+                // answer to the ping with a masked pong
+                0x9 => {
+                    let mask = [1u8, 2, 3, 4];
+                    let mut pong = vec![0x8A, 0x80 | payload.len() as u8];
+                    pong.extend_from_slice(&mask);
+                    pong.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+                    self.stream.write_all(&pong)?;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Build a rapid gossip sync snapshot for regtest with three nodes and
+/// two channels between them (node 0 <-> node 1 and node 1 <-> node 2),
+/// following the format served by the ldk rgs server.
+pub fn rgs_snapshot_fixture(timestamp: u32) -> Vec<u8> {
+    rgs_graph_fixture(timestamp, 3)
+}
+
+/// Build a rapid gossip sync snapshot for regtest with `nodes` nodes
+/// connected in a line, so the graph has `nodes - 1` channels with
+/// short channel id from 1 to `nodes - 1`.
+pub fn rgs_graph_fixture(timestamp: u32, nodes: u32) -> Vec<u8> {
+    use lampo_common::bitcoin::blockdata::constants::genesis_block;
+    use lampo_common::bitcoin::hashes::Hash;
+    use lampo_common::bitcoin::Network;
+    use lampo_common::secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+    // the big size encoding used by the lightning spec
+    fn big_size(value: u32, snapshot: &mut Vec<u8>) {
+        if value < 0xfd {
+            snapshot.push(value as u8);
+        } else if value <= 0xffff {
+            snapshot.push(0xfd);
+            snapshot.extend((value as u16).to_be_bytes());
+        } else {
+            snapshot.push(0xfe);
+            snapshot.extend(value.to_be_bytes());
+        }
+    }
+
+    let secp = Secp256k1::new();
+    // "LDK" and the version of the format
+    let mut snapshot = vec![76, 68, 75, 1];
+    snapshot.extend(genesis_block(Network::Regtest).block_hash().to_byte_array());
+    snapshot.extend(timestamp.to_be_bytes());
+    snapshot.extend(nodes.to_be_bytes());
+    for index in 1..=nodes {
+        let mut key = [0u8; 32];
+        key[28..].copy_from_slice(&index.to_be_bytes());
+        // SAFETY: the key is a valid secret key.
+        let key = SecretKey::from_slice(&key).unwrap();
+        snapshot.extend(PublicKey::from_secret_key(&secp, &key).serialize());
+    }
+    let channels = nodes.saturating_sub(1);
+    snapshot.extend(channels.to_be_bytes());
+    for node_1 in 0..channels {
+        // empty channel features
+        snapshot.extend([0, 0]);
+        // short channel id delta
+        big_size(1, &mut snapshot);
+        // index of the nodes
+        big_size(node_1, &mut snapshot);
+        big_size(node_1 + 1, &mut snapshot);
+    }
+    // no channel updates
+    snapshot.extend(0u32.to_be_bytes());
+    snapshot
+}
+
+/// A SOCKS5 proxy that records the targets requested by the
+/// clients, and forwards every connection to the same address.
+pub struct MockSocks5 {
+    pub addr: SocketAddr,
+    targets: Arc<Mutex<Vec<(u8, String, u16)>>>,
+}
+
+impl MockSocks5 {
+    pub fn start(forward_to: SocketAddr) -> error::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let targets = Arc::new(Mutex::new(Vec::new()));
+        let proxy_targets = targets.clone();
+        std::thread::spawn(move || {
+            for client in listener.incoming().flatten() {
+                let targets = proxy_targets.clone();
+                std::thread::spawn(move || {
+                    if let Err(err) = Self::serve(client, forward_to, targets) {
+                        log::warn!("mock socks5 proxy error: {err}");
+                    }
+                });
+            }
+        });
+        Ok(Self { addr, targets })
+    }
+
+    /// The targets requested to the proxy, as `(address type, host, port)`.
+    pub fn targets(&self) -> Vec<(u8, String, u16)> {
+        self.targets.lock().unwrap().clone()
+    }
+
+    fn serve(
+        mut client: TcpStream,
+        forward_to: SocketAddr,
+        targets: Arc<Mutex<Vec<(u8, String, u16)>>>,
+    ) -> io::Result<()> {
+        let mut greeting = [0u8; 2];
+        client.read_exact(&mut greeting)?;
+        let mut methods = vec![0u8; greeting[1] as usize];
+        client.read_exact(&mut methods)?;
+        client.write_all(&[5, 0])?;
+
+        let mut request = [0u8; 4];
+        client.read_exact(&mut request)?;
+        let host = match request[3] {
+            1 => {
+                let mut ip = [0u8; 4];
+                client.read_exact(&mut ip)?;
+                Ipv4Addr::from(ip).to_string()
+            }
+            3 => {
+                let mut len = [0u8; 1];
+                client.read_exact(&mut len)?;
+                let mut host = vec![0u8; len[0] as usize];
+                client.read_exact(&mut host)?;
+                String::from_utf8_lossy(&host).to_string()
+            }
+            4 => {
+                let mut ip = [0u8; 16];
+                client.read_exact(&mut ip)?;
+                Ipv6Addr::from(ip).to_string()
+            }
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid atyp")),
+        };
+        let mut port = [0u8; 2];
+        client.read_exact(&mut port)?;
+        targets
+            .lock()
+            .unwrap()
+            .push((request[3], host, u16::from_be_bytes(port)));
+
+        let upstream = TcpStream::connect(forward_to)?;
+        client.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])?;
+        let mut client_read = client.try_clone()?;
+        let mut upstream_write = upstream.try_clone()?;
+        std::thread::spawn(move || io::copy(&mut client_read, &mut upstream_write));
+        let mut upstream_read = upstream;
+        io::copy(&mut upstream_read, &mut client)?;
+        Ok(())
+    }
+}
+
+/// An http server that replies to every request with the same
+/// json body, and counts the requests received.
+pub struct MockHttp {
+    pub addr: SocketAddr,
+    requests: Arc<Mutex<usize>>,
+    /// When set the server answers `503`, like an api that is down.
+    paused: Arc<AtomicBool>,
+}
+
+impl MockHttp {
+    pub fn start(body: String) -> error::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let requests = Arc::new(Mutex::new(0));
+        let paused = Arc::new(AtomicBool::new(false));
+        let server_requests = requests.clone();
+        let server_paused = paused.clone();
+        std::thread::spawn(move || {
+            for client in listener.incoming().flatten() {
+                *server_requests.lock().unwrap() += 1;
+                let paused = server_paused.load(Ordering::SeqCst);
+                if let Err(err) = Self::serve(client, &body, paused) {
+                    log::warn!("mock http server error: {err}");
+                }
+            }
+        });
+        Ok(Self {
+            addr,
+            requests,
+            paused,
+        })
+    }
+
+    /// Answer `503` to the next requests, until `resume`.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// The number of requests received.
+    pub fn requests(&self) -> usize {
+        *self.requests.lock().unwrap()
+    }
+
+    fn serve(mut client: TcpStream, body: &str, paused: bool) -> io::Result<()> {
+        // read the request until the end of the headers
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+            let read = client.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..read]);
+        }
+        let (status, body) = if paused {
+            ("503 Service Unavailable", "")
+        } else {
+            ("200 OK", body)
+        };
+        let response = format!(
+            "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        client.write_all(response.as_bytes())
+    }
+}
+
+/// A request received by `MockWebhook`.
+#[derive(Clone, Debug)]
+pub struct WebhookRequest {
+    /// The headers, with the names in lowercase.
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+impl WebhookRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(String::as_str)
+    }
+}
+
+/// A webhook that records the requests received, and answers with
+/// the `statuses` in order, then with `200`.
+pub struct MockWebhook {
+    pub addr: SocketAddr,
+    requests: Arc<Mutex<Vec<WebhookRequest>>>,
+}
+
+impl MockWebhook {
+    pub fn start(statuses: Vec<u16>) -> error::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let server_requests = requests.clone();
+        std::thread::spawn(move || {
+            let mut statuses = statuses.into_iter();
+            for client in listener.incoming().flatten() {
+                let status = statuses.next().unwrap_or(200);
+                match Self::serve(client, status) {
+                    Ok(request) => server_requests.lock().unwrap().push(request),
+                    Err(err) => log::warn!("mock webhook error: {err}"),
+                }
+            }
+        });
+        Ok(Self { addr, requests })
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}/hook", self.addr)
+    }
+
+    pub fn requests(&self) -> Vec<WebhookRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn serve(mut client: TcpStream, status: u16) -> io::Result<WebhookRequest> {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        let head_end = loop {
+            if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+                break end + 4;
+            }
+            let read = client.read(&mut buf)?;
+            if read == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            request.extend_from_slice(&buf[..read]);
+        };
+        let head = String::from_utf8_lossy(&request[..head_end]).to_string();
+        let headers = head
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_owned()))
+            .collect::<HashMap<_, _>>();
+        let len = headers
+            .get("content-length")
+            .and_then(|len| len.parse::<usize>().ok())
+            .unwrap_or_default();
+        let mut body = request[head_end..].to_vec();
+        while body.len() < len {
+            let read = client.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            body.extend_from_slice(&buf[..read]);
+        }
+        let response =
+            format!("HTTP/1.1 {status} Mock\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+        client.write_all(response.as_bytes())?;
+        Ok(WebhookRequest {
+            headers,
+            body: String::from_utf8_lossy(&body).to_string(),
+        })
+    }
+}
+
+/// The invoices given and the comments received by `MockLnurl`.
+#[derive(Default)]
+struct LnurlRecords {
+    invoices: Vec<String>,
+    comments: Vec<String>,
+}
+
+/// A LNURL-pay service that gives the invoices of the `node`.
+pub struct MockLnurl {
+    pub addr: SocketAddr,
+    records: Arc<Mutex<LnurlRecords>>,
+}
+
+impl MockLnurl {
+    pub const METADATA: &'static str = "[[\"text/plain\",\"a tip for lampo\"]]";
+    pub const MIN_SENDABLE_MSAT: u64 = 1_000;
+    pub const MAX_SENDABLE_MSAT: u64 = 500_000_000;
+    pub const COMMENT_ALLOWED: usize = 32;
+
+    pub fn start(node: Arc<LampoTesting>) -> error::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let records = Arc::new(Mutex::new(LnurlRecords::default()));
+        let server_records = records.clone();
+        std::thread::spawn(move || {
+            for client in listener.incoming().flatten() {
+                if let Err(err) = Self::serve(client, addr, &node, &server_records) {
+                    log::warn!("mock lnurl error: {err}");
+                }
+            }
+        });
+        Ok(Self { addr, records })
+    }
+
+    /// The lightning address of the service, on regtest it is
+    /// reached with http.
+    pub fn address(&self) -> String {
+        format!("tips@{}", self.addr)
+    }
+
+    /// The bech32 LNURL of the service.
+    pub fn lnurl(&self) -> String {
+        let url = format!("http://{}/.well-known/lnurlp/tips", self.addr);
+        // SAFETY: the `lnurl` prefix is a valid human readable part.
+        bech32::encode("lnurl", url.as_bytes().to_base32(), bech32::Variant::Bech32).unwrap()
+    }
+
+    pub fn invoices(&self) -> Vec<String> {
+        self.records.lock().unwrap().invoices.clone()
+    }
+
+    pub fn comments(&self) -> Vec<String> {
+        self.records.lock().unwrap().comments.clone()
+    }
+
+    fn serve(
+        mut client: TcpStream,
+        addr: SocketAddr,
+        node: &LampoTesting,
+        records: &Mutex<LnurlRecords>,
+    ) -> io::Result<()> {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+            let read = client.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..read]);
+        }
+        let request = String::from_utf8_lossy(&request).to_string();
+        let path = request.split(' ').nth(1).unwrap_or_default();
+        let body = match path.split_once("/callback?") {
+            Some((_, query)) => {
+                let params = query
+                    .split('&')
+                    .filter_map(|param| param.split_once('='))
+                    .collect::<HashMap<_, _>>();
+                let mut records = records.lock().unwrap();
+                if let Some(comment) = params.get("comment") {
+                    records.comments.push(percent_decode(comment));
+                }
+                let amount_msat = params.get("amount").and_then(|amount| amount.parse().ok());
+                let invoice = amount_msat.ok_or(()).and_then(|amount_msat| {
+                    node.daemon()
+                        .offchain_manager()
+                        .generate_invoice_for_metadata(amount_msat, Self::METADATA, None)
+                        .map_err(|_| ())
+                });
+                match invoice {
+                    Ok(invoice) => {
+                        records.invoices.push(invoice.to_string());
+                        json::json!({ "pr": invoice.to_string(), "routes": [] })
+                    }
+                    Err(_) => json::json!({ "status": "ERROR", "reason": "invalid amount" }),
+                }
+            }
+            None => json::json!({
+                "tag": "payRequest",
+                "callback": format!("http://{addr}/callback"),
+                "minSendable": Self::MIN_SENDABLE_MSAT,
+                "maxSendable": Self::MAX_SENDABLE_MSAT,
+                "metadata": Self::METADATA,
+                "commentAllowed": Self::COMMENT_ALLOWED,
+            }),
+        };
+        let body = body.to_string();
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        client.write_all(response.as_bytes())
+    }
+}
+
+/// The objects stored by `MockVss`, by key with their version.
+type VssObjects = HashMap<String, (u64, Vec<u8>)>;
+
+/// A versioned storage for `remote-storage-url`, that keeps the
+/// objects in memory.
+pub struct MockVss {
+    pub addr: SocketAddr,
+    objects: Arc<Mutex<VssObjects>>,
+}
+
+impl MockVss {
+    pub const TOKEN: &'static str = "vss-s3cret";
+
+    pub fn start() -> error::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let objects = Arc::new(Mutex::new(HashMap::new()));
+        let server_objects = objects.clone();
+        std::thread::spawn(move || {
+            for client in listener.incoming().flatten() {
+                if let Err(err) = Self::serve(client, &server_objects) {
+                    log::warn!("mock vss error: {err}");
+                }
+            }
+        });
+        Ok(Self { addr, objects })
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// The version and the content of `key`.
+    pub fn object(&self, key: &str) -> Option<(u64, Vec<u8>)> {
+        self.objects.lock().unwrap().get(key).cloned()
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.objects.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Store a newer version of `key`, like another node with the
+    /// same channels would do.
+    pub fn bump(&self, key: &str) {
+        if let Some((version, _)) = self.objects.lock().unwrap().get_mut(key) {
+            *version += 1;
+        }
+    }
+
+    fn serve(mut client: TcpStream, objects: &Mutex<VssObjects>) -> io::Result<()> {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        let head_end = loop {
+            if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+                break end + 4;
+            }
+            let read = client.read(&mut buf)?;
+            if read == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            request.extend_from_slice(&buf[..read]);
+        };
+        let head = String::from_utf8_lossy(&request[..head_end]).to_string();
+        let mut line = head.lines().next().unwrap_or_default().split(' ');
+        let method = line.next().unwrap_or_default().to_owned();
+        let path = line.next().unwrap_or_default().to_owned();
+        let headers = head
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_owned()))
+            .collect::<HashMap<_, _>>();
+        let len = headers
+            .get("content-length")
+            .and_then(|len| len.parse::<usize>().ok())
+            .unwrap_or_default();
+        let mut body = request[head_end..].to_vec();
+        while body.len() < len {
+            let read = client.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            body.extend_from_slice(&buf[..read]);
+        }
+
+        let authorized = headers.get("authorization") == Some(&format!("Bearer {}", Self::TOKEN));
+        let key = path.strip_prefix("/objects/");
+        let mut objects = objects.lock().unwrap();
+        let (status, version, body) = match (method.as_str(), key) {
+            _ if !authorized => ("401 Unauthorized", None, Vec::new()),
+            ("GET", None) if path == "/objects" => {
+                let list = objects
+                    .iter()
+                    .map(|(key, (version, _))| json::json!({ "key": key, "version": version }))
+                    .collect::<Vec<_>>();
+                ("200 OK", None, json::to_vec(&list)?)
+            }
+            ("GET", Some(key)) => match objects.get(key) {
+                Some((version, content)) => ("200 OK", Some(*version), content.clone()),
+                None => ("404 Not Found", None, Vec::new()),
+            },
+            ("PUT", Some(key)) => {
+                let version = headers
+                    .get("x-lampo-version")
+                    .and_then(|version| version.parse::<u64>().ok())
+                    .unwrap_or_default();
+                match objects.get(key) {
+                    Some((stored, _)) if *stored >= version => {
+                        ("409 Conflict", Some(*stored), Vec::new())
+                    }
+                    _ => {
+                        objects.insert(key.to_owned(), (version, body));
+                        ("200 OK", Some(version), Vec::new())
+                    }
+                }
+            }
+            _ => ("404 Not Found", None, Vec::new()),
+        };
+        let version = version
+            .map(|version| format!("X-Lampo-Version: {version}\r\n"))
+            .unwrap_or_default();
+        let head = format!(
+            "HTTP/1.1 {status}\r\n{version}Content-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        );
+        client.write_all(head.as_bytes())?;
+        client.write_all(&body)
+    }
+}
+
+/// A watchtower that speaks the http api of The Eye of Satoshi, and
+/// keeps the appointments in memory.
+pub struct MockTower {
+    pub addr: SocketAddr,
+    pub tower_id: String,
+    appointments: Arc<Mutex<Vec<json::Value>>>,
+    /// When set the tower answers `503`, like a tower that is down.
+    paused: Arc<AtomicBool>,
+}
+
+impl MockTower {
+    pub fn start() -> error::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let secret = SecretKey::from_slice(&[0x42; 32])?;
+        let tower_id = PublicKey::from_secret_key(&Secp256k1::new(), &secret).to_string();
+        let appointments = Arc::new(Mutex::new(Vec::new()));
+        let paused = Arc::new(AtomicBool::new(false));
+        let server_appointments = appointments.clone();
+        let server_paused = paused.clone();
+        std::thread::spawn(move || {
+            for client in listener.incoming().flatten() {
+                let paused = server_paused.load(Ordering::SeqCst);
+                if let Err(err) = Self::serve(client, &secret, &server_appointments, paused) {
+                    log::warn!("mock tower error: {err}");
+                }
+            }
+        });
+        Ok(Self {
+            addr,
+            tower_id,
+            appointments,
+            paused,
+        })
+    }
+
+    /// The address of the tower for `addtower` and `watchtower`.
+    pub fn address(&self) -> String {
+        format!("{}@{}", self.tower_id, self.addr)
+    }
+
+    /// The appointments accepted, in order.
+    pub fn appointments(&self) -> Vec<json::Value> {
+        self.appointments.lock().unwrap().clone()
+    }
+
+    /// Answer `503` to the next requests, until `resume`.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+
+    fn serve(
+        mut client: TcpStream,
+        secret: &SecretKey,
+        appointments: &Mutex<Vec<json::Value>>,
+        paused: bool,
+    ) -> io::Result<()> {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        let head_end = loop {
+            if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+                break end + 4;
+            }
+            let read = client.read(&mut buf)?;
+            if read == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            request.extend_from_slice(&buf[..read]);
+        };
+        let head = String::from_utf8_lossy(&request[..head_end]).to_string();
+        let path = head
+            .lines()
+            .next()
+            .and_then(|line| line.split(' ').nth(1))
+            .unwrap_or_default()
+            .to_owned();
+        let len = head
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+            .and_then(|(_, len)| len.trim().parse::<usize>().ok())
+            .unwrap_or_default();
+        let mut body = request[head_end..].to_vec();
+        while body.len() < len {
+            let read = client.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            body.extend_from_slice(&buf[..read]);
+        }
+        let request: json::Value = json::from_slice(&body).unwrap_or_default();
+
+        let (status, reply) = match path.as_str() {
+            _ if paused => ("503 Service Unavailable", json::json!({})),
+            "/register" => (
+                "200 OK",
+                json::json!({
+                    "user_id": request["user_id"],
+                    "available_slots": 10_000,
+                    "subscription_expiry": 100_000,
+                }),
+            ),
+            "/add_appointment" => {
+                let appointment = &request["appointment"];
+                let mut signed = Vec::new();
+                for field in ["locator", "encrypted_blob"] {
+                    let hex = appointment[field].as_str().unwrap_or_default();
+                    signed.extend(Vec::<u8>::from_hex(hex).unwrap_or_default());
+                }
+                let to_self_delay = appointment["to_self_delay"].as_u64().unwrap_or_default();
+                signed.extend((to_self_delay as u32).to_be_bytes());
+                let signature = message_signing::sign(&signed, secret)
+                    .map_err(|err| io::Error::new(io::ErrorKind::Other, format!("{err:?}")))?;
+                let mut appointments = appointments.lock().unwrap();
+                appointments.push(appointment.clone());
+                (
+                    "200 OK",
+                    json::json!({
+                        "locator": appointment["locator"],
+                        "start_block": 0,
+                        "signature": signature,
+                        "available_slots": 10_000 - appointments.len(),
+                        "subscription_expiry": 100_000,
+                    }),
+                )
+            }
+            _ => ("404 Not Found", json::json!({ "error": "not found" })),
+        };
+        let reply = reply.to_string();
+        let response = format!(
+            "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{reply}",
+            reply.len()
+        );
+        client.write_all(response.as_bytes())
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+                match u8::from_str_radix(hex, 16) {
+                    Ok(byte) => {
+                        i += 2;
+                        byte
+                    }
+                    Err(_) => b'%',
+                }
+            }
+            byte => byte,
+        };
+        decoded.push(byte);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
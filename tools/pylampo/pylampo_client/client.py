@@ -1,4 +1,5 @@
 import json
+import os
 import socket
 
 
@@ -7,15 +8,23 @@ class LampoClient:
     A simple Lampo client that communicates via a Unix socket.
     """
 
-    def __init__(self, socket_path: str):
+    def __init__(self, socket_path: str, token: str = None):
         """
         Initializes the LampoClient instance.
 
         Args:
           socket_path: The path to the Lampo socket.
+          token: (Optional) The token of the requests, by default the
+            master token written by the node next to the socket.
         """
 
         self.socket_path = socket_path
+        if token is None:
+            token_path = os.path.join(os.path.dirname(socket_path), "rpc_token")
+            if os.path.exists(token_path):
+                with open(token_path) as token_file:
+                    token = token_file.read().strip()
+        self.token = token
 
     def call(self, method: str, params: dict = None) -> dict:
         """
@@ -32,9 +41,12 @@ class LampoClient:
           Exception: If there is an error communicating with the Lampo client.
         """
 
+        params = dict(params) if params else {}
+        if self.token is not None:
+            params["token"] = self.token
         request = {
             "method": method,
-            "params": params if params else {},
+            "params": params,
             "id": "pylampo-client/1",
             "jsonrpc": "2.0",
         }